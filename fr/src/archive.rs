@@ -0,0 +1,490 @@
+//! Finding and replacing inside `.zip`, `.tar` and `.tar.gz` archives with
+//! `--archives`, and inside `.gz` files with `--gzip`.
+//!
+//! Each member that looks like text is decoded, rewritten and encoded as a file
+//! would be, and the archive is repacked around it. Everything else is kept as
+//! it was: the other members byte for byte, and the names, times, permissions
+//! and extra fields of the rewritten ones. Only their sizes and checksums
+//! change. A zip member is compressed again the way it was, stored or
+//! deflated, and members in any other zip format are left alone. A `.gz` file
+//! is treated as an archive of one member, and keeps its gzip header. Deflated
+//! members and `.gz` files are recompressed with `deflate`'s own encoder, not
+//! at the level they were made with, so they can come out larger. Office
+//! documents are zip archives too, whose text is rewritten by `office`.
+
+use crate::plugin::Plugins;
+use crate::{deflate, encoding, looks_binary, office, rewrite, Options};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The archive formats fr can look inside
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Zip,
+    Tar,
+    TarGz,
+    /// A single compressed file
+    Gzip,
+    /// A `.docx`, `.xlsx` or `.pptx` document
+    Office,
+}
+
+impl Kind {
+    /// Recognizes an archive from the extension of its name.
+    pub fn of(path: &Path) -> Option<Kind> {
+        let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+        if name.ends_with(".zip") {
+            Some(Kind::Zip)
+        } else if name.ends_with(".tar") {
+            Some(Kind::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Kind::TarGz)
+        } else if name.ends_with(".gz") {
+            Some(Kind::Gzip)
+        } else if [".docx", ".xlsx", ".pptx"].iter().any(|extension| name.ends_with(extension)) {
+            Some(Kind::Office)
+        } else {
+            None
+        }
+    }
+}
+
+/// The most a compressed archive or member may decompress to, so that a small
+/// file can't fill memory
+const MAX_DECOMPRESSED: usize = 1 << 30;
+
+fn invalid(path: &Path, message: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), message))
+}
+
+/// Finds and replaces text in the members of an archive.
+///
+/// # Arguments
+///
+/// * `path` - Path to the archive, for errors and the names of its members
+/// * `kind` - The archive's format
+/// * `contents` - The archive's contents
+/// * `find_text` - Text to find, which must not be empty
+/// * `replace_text` - Text to replace the found text with
+/// * `options` - Options that control how members are read and rewritten
+/// * `plugins` - Plugins that choose replacements and post-process members
+///
+/// # Returns
+///
+/// * `io::Result<Option<(usize, Vec<u8>)>>` - The number of replacements and
+///   the repacked archive, None if no member contains the find text, or an
+///   error if the archive is malformed or in a variant fr can't repack
+pub fn rewrite_archive(path: &Path, kind: Kind, contents: &[u8], find_text: &str, replace_text: &str, options: &Options, plugins: &Plugins) -> io::Result<Option<(usize, Vec<u8>)>> {
+    let member = Member { archive: path, find_text, replace_text, options, plugins, office: kind == Kind::Office };
+    match kind {
+        Kind::Zip | Kind::Office => zip(&member, contents),
+        Kind::Tar => tar(&member, contents),
+        Kind::TarGz => {
+            let (tar_contents, header) = gunzip(contents).map_err(|e| invalid(path, e))?;
+            let Some((replacements, repacked)) = tar(&member, &tar_contents)? else {
+                return Ok(None);
+            };
+            Ok(Some((replacements, gzip(header, &repacked))))
+        }
+        Kind::Gzip => {
+            let (text, header) = gunzip(contents).map_err(|e| invalid(path, e))?;
+            let name = path.file_stem().map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+            let Some((replacements, rewritten)) = member.rewrite(&name, &text)? else {
+                return Ok(None);
+            };
+            Ok(Some((replacements, gzip(header, &rewritten))))
+        }
+    }
+}
+
+/// What every member of an archive is rewritten with
+struct Member<'a> {
+    archive: &'a Path,
+    find_text: &'a str,
+    replace_text: &'a str,
+    options: &'a Options,
+    plugins: &'a Plugins,
+    /// Whether the archive is an office document, whose members are XML
+    office: bool,
+}
+
+impl Member<'_> {
+    /// Rewrites one member, returning the number of replacements and its new
+    /// contents, or None if it is left alone for not matching or not being text.
+    fn rewrite(&self, name: &str, contents: &[u8]) -> io::Result<Option<(usize, Vec<u8>)>> {
+        if self.office {
+            let part = std::str::from_utf8(contents).ok().filter(|_| office::is_text_part(name));
+            let rewritten = part.and_then(|part| office::rewrite_part(part, self.find_text, self.replace_text));
+            return Ok(rewritten.map(|(replacements, new_part)| (replacements, new_part.into_bytes())));
+        }
+        let member_path: PathBuf = self.archive.join(name);
+        if self.options.max_filesize.is_some_and(|max| contents.len() as u64 > max) {
+            return Ok(None);
+        }
+        if !self.options.treats_as_text(&member_path) && looks_binary(contents, self.options.encoding) && !self.options.encoding.allows_nul_in(contents) {
+            return Ok(None);
+        }
+        let Ok((text, decoding)) = encoding::decode(contents.to_vec(), self.options.encoding) else {
+            return Ok(None);
+        };
+        let rewritten = rewrite(&member_path, text, decoding, self.find_text, self.replace_text, self.options, self.plugins)?;
+        Ok(rewritten.map(|(change, new_contents)| (change.replacements, new_contents)))
+    }
+}
+
+/// The size of a tar header, and the unit tar contents are padded to
+const BLOCK: usize = 512;
+
+/// Reads a numeric field of a tar header, in octal or, for large values, the
+/// GNU base-256 format.
+fn tar_number(field: &[u8]) -> Option<u64> {
+    if field[0] & 0x80 != 0 {
+        return Some(field[1..].iter().fold(u64::from(field[0] & 0x7f), |value, &byte| value << 8 | u64::from(byte)));
+    }
+    let text = std::str::from_utf8(field).ok()?.trim_matches(|c: char| c == '\0' || c == ' ');
+    if text.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(text, 8).ok()
+}
+
+/// Rewrites the regular files in a tar archive.
+fn tar(member: &Member, contents: &[u8]) -> io::Result<Option<(usize, Vec<u8>)>> {
+    let malformed = |message: &str| invalid(member.archive, message);
+    let mut repacked = Vec::with_capacity(contents.len());
+    let mut replacements = 0;
+    let mut position = 0;
+    // A pax header that gives a size applies to the next member, whose size
+    // can then only change by rewriting the pax header too
+    let mut sized_by_pax = false;
+    while position + BLOCK <= contents.len() {
+        let header = &contents[position..position + BLOCK];
+        if header.iter().all(|&byte| byte == 0) {
+            break;
+        }
+        let size = tar_number(&header[124..136]).ok_or_else(|| malformed("invalid size in a tar header"))? as usize;
+        let data_start = position + BLOCK;
+        let data_end = data_start.checked_add(size).filter(|&end| end <= contents.len()).ok_or_else(|| malformed("tar member ends early"))?;
+        let padded_end = (data_start + size.div_ceil(BLOCK) * BLOCK).min(contents.len());
+        let data = &contents[data_start..data_end];
+        let type_flag = header[156];
+
+        let name = tar_name(header);
+        let rewritten = match type_flag {
+            b'0' | 0 if !sized_by_pax => member.rewrite(&name, data)?,
+            _ => None,
+        };
+        sized_by_pax = type_flag == b'x' && String::from_utf8_lossy(data).split('\n').any(|record| record.split_once(' ').is_some_and(|(_, record)| record.starts_with("size=")));
+        match rewritten {
+            Some((count, new_data)) if new_data.len() < 0o77777777777 => {
+                replacements += count;
+                let mut new_header = header.to_vec();
+                new_header[124..136].copy_from_slice(format!("{:011o}\0", new_data.len()).as_bytes());
+                new_header[148..156].fill(b' ');
+                let checksum: u32 = new_header.iter().map(|&byte| u32::from(byte)).sum();
+                new_header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+                repacked.extend_from_slice(&new_header);
+                repacked.extend_from_slice(&new_data);
+                repacked.resize(repacked.len() + (BLOCK - new_data.len() % BLOCK) % BLOCK, 0);
+            }
+            _ => repacked.extend_from_slice(&contents[position..padded_end]),
+        }
+        position = padded_end;
+    }
+    if replacements == 0 {
+        return Ok(None);
+    }
+    // The end of archive marker and anything after it
+    repacked.extend_from_slice(&contents[position..]);
+    Ok(Some((replacements, repacked)))
+}
+
+/// The name of a tar member, with the ustar prefix in front of it.
+fn tar_name(header: &[u8]) -> String {
+    let field = |bytes: &[u8]| String::from_utf8_lossy(&bytes[..bytes.iter().position(|&byte| byte == 0).unwrap_or(bytes.len())]).into_owned();
+    let name = field(&header[0..100]);
+    let prefix = if &header[257..262] == b"ustar" { field(&header[345..500]) } else { String::new() };
+    if prefix.is_empty() { name } else { format!("{}/{}", prefix, name) }
+}
+
+/// The header of a gzip member, kept so the repacked archive has the same
+/// name, time and flags
+struct GzipHeader<'a>(&'a [u8]);
+
+/// Decompresses a gzip file, returning its contents and header.
+fn gunzip(contents: &[u8]) -> Result<(Vec<u8>, GzipHeader<'_>), String> {
+    if contents.len() < 18 || contents[..3] != [0x1f, 0x8b, 8] {
+        return Err("not a gzip file".to_string());
+    }
+    let flags = contents[3];
+    let mut position = 10;
+    let ends_early = || "gzip header ends early".to_string();
+    if flags & 0x04 != 0 {
+        let len = contents.get(position..position + 2).ok_or_else(ends_early)?;
+        position += 2 + usize::from(u16::from_le_bytes([len[0], len[1]]));
+    }
+    for flag in [0x08, 0x10] {
+        if flags & flag != 0 {
+            position += contents.get(position..).and_then(|rest| rest.iter().position(|&byte| byte == 0)).ok_or_else(ends_early)? + 1;
+        }
+    }
+    if flags & 0x02 != 0 {
+        position += 2;
+    }
+    let header = contents.get(..position).ok_or_else(ends_early)?;
+    let (decompressed, len) = deflate::inflate(&contents[position..], MAX_DECOMPRESSED)?;
+    let trailer = contents.get(position + len..position + len + 8).ok_or("gzip file ends early")?;
+    if u32::from_le_bytes(trailer[..4].try_into().unwrap()) != deflate::crc32(&decompressed) {
+        return Err("gzip checksum doesn't match".to_string());
+    }
+    if position + len + 8 != contents.len() {
+        return Err("gzip files with several members aren't supported".to_string());
+    }
+    Ok((decompressed, GzipHeader(header)))
+}
+
+/// Compresses contents into a gzip file with the given header.
+fn gzip(header: GzipHeader, contents: &[u8]) -> Vec<u8> {
+    let mut gzipped = header.0.to_vec();
+    gzipped.extend(deflate::deflate(contents));
+    gzipped.extend(deflate::crc32(contents).to_le_bytes());
+    gzipped.extend((contents.len() as u32).to_le_bytes());
+    gzipped
+}
+
+fn u16_at(bytes: &[u8], position: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(bytes.get(position..position + 2)?.try_into().ok()?))
+}
+
+fn u32_at(bytes: &[u8], position: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(position..position + 4)?.try_into().ok()?))
+}
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+const DATA_DESCRIPTOR: u32 = 0x0807_4b50;
+/// The flag of a member whose sizes and checksum follow its data
+const HAS_DATA_DESCRIPTOR: u16 = 0x08;
+
+/// Rewrites the members of a zip archive, going by its central directory.
+fn zip(member: &Member, contents: &[u8]) -> io::Result<Option<(usize, Vec<u8>)>> {
+    let malformed = |message: &str| invalid(member.archive, message);
+    let end = (0..=contents.len().saturating_sub(22)).rev()
+        .find(|&position| u32_at(contents, position) == Some(END_OF_CENTRAL_DIRECTORY))
+        .ok_or_else(|| malformed("no zip central directory"))?;
+    let entries = u16_at(contents, end + 10).ok_or_else(|| malformed("zip directory ends early"))?;
+    let directory_start = u32_at(contents, end + 16).ok_or_else(|| malformed("zip directory ends early"))?;
+    if entries == 0xffff || directory_start == 0xffff_ffff {
+        return Err(malformed("zip64 archives aren't supported"));
+    }
+
+    let mut repacked = Vec::with_capacity(contents.len());
+    let mut directory = Vec::new();
+    let mut replacements = 0;
+    let mut position = directory_start as usize;
+    for _ in 0..entries {
+        let entry = contents.get(position..position + 46).filter(|entry| u32_at(entry, 0) == Some(CENTRAL_HEADER))
+            .ok_or_else(|| malformed("invalid zip directory entry"))?;
+        let flags = u16_at(entry, 8).unwrap();
+        let method = u16_at(entry, 10).unwrap();
+        let compressed_size = u32_at(entry, 20).unwrap() as usize;
+        if compressed_size == 0xffff_ffff || u32_at(entry, 24) == Some(0xffff_ffff) {
+            return Err(malformed("zip64 archives aren't supported"));
+        }
+        let entry_len = 46 + [28, 30, 32].iter().map(|&field| usize::from(u16_at(entry, field).unwrap())).sum::<usize>();
+        let entry = contents.get(position..position + entry_len).ok_or_else(|| malformed("zip directory ends early"))?;
+        let name = String::from_utf8_lossy(&entry[46..46 + usize::from(u16_at(entry, 28).unwrap())]).into_owned();
+        let local_start = u32_at(entry, 42).unwrap() as usize;
+        position += entry_len;
+
+        let local = contents.get(local_start..local_start + 30).filter(|local| u32_at(local, 0) == Some(LOCAL_HEADER))
+            .ok_or_else(|| malformed("invalid zip member header"))?;
+        let data_start = local_start + 30 + usize::from(u16_at(local, 26).unwrap()) + usize::from(u16_at(local, 28).unwrap());
+        let data_end = data_start.checked_add(compressed_size).filter(|&end| end <= contents.len())
+            .ok_or_else(|| malformed("zip member ends early"))?;
+        let mut member_end = data_end;
+        if flags & HAS_DATA_DESCRIPTOR != 0 {
+            member_end += if u32_at(contents, data_end) == Some(DATA_DESCRIPTOR) { 16 } else { 12 };
+        }
+        let data = &contents[data_start..data_end];
+
+        // Encrypted members and other compression methods are left alone
+        let uncompressed = match method {
+            _ if flags & 0x01 != 0 || name.ends_with('/') => None,
+            0 => Some(data.to_vec()),
+            8 => Some(deflate::inflate(data, MAX_DECOMPRESSED).map_err(|e| invalid(member.archive, format!("{}: {}", name, e)))?.0),
+            _ => None,
+        };
+        let rewritten = match uncompressed {
+            Some(uncompressed) => member.rewrite(&name, &uncompressed)?,
+            None => None,
+        };
+
+        let new_start = repacked.len() as u32;
+        let mut new_entry = entry.to_vec();
+        match rewritten {
+            Some((count, new_data)) if u32::try_from(new_data.len()).is_ok() => {
+                replacements += count;
+                let compressed = if method == 8 { deflate::deflate(&new_data) } else { new_data.clone() };
+                let crc = deflate::crc32(&new_data);
+                // The sizes and checksum go in the headers, with no data
+                // descriptor after the data; the central entry has the same
+                // fields as the local header, two bytes further on
+                let mut new_local = contents[local_start..data_start].to_vec();
+                for (header, at) in [(&mut new_local, 6), (&mut new_entry, 8)] {
+                    header[at..at + 2].copy_from_slice(&(flags & !HAS_DATA_DESCRIPTOR).to_le_bytes());
+                    header[at + 8..at + 12].copy_from_slice(&crc.to_le_bytes());
+                    header[at + 12..at + 16].copy_from_slice(&(compressed.len() as u32).to_le_bytes());
+                    header[at + 16..at + 20].copy_from_slice(&(new_data.len() as u32).to_le_bytes());
+                }
+                repacked.extend_from_slice(&new_local);
+                repacked.extend_from_slice(&compressed);
+            }
+            _ => repacked.extend_from_slice(&contents[local_start..member_end]),
+        }
+        new_entry[42..46].copy_from_slice(&new_start.to_le_bytes());
+        directory.extend_from_slice(&new_entry);
+    }
+    if replacements == 0 {
+        return Ok(None);
+    }
+
+    let new_directory_start = repacked.len() as u32;
+    repacked.extend_from_slice(&directory);
+    let mut new_end = contents[end..].to_vec();
+    new_end[12..16].copy_from_slice(&(directory.len() as u32).to_le_bytes());
+    new_end[16..20].copy_from_slice(&new_directory_start.to_le_bytes());
+    repacked.extend_from_slice(&new_end);
+    Ok(Some((replacements, repacked)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a ustar header for a regular file.
+    fn tar_header(name: &str, size: usize) -> Vec<u8> {
+        let mut header = vec![0; BLOCK];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..108].copy_from_slice(b"0000644\0");
+        header[124..136].copy_from_slice(format!("{:011o}\0", size).as_bytes());
+        header[136..148].copy_from_slice(b"14000000000\0");
+        header[156] = b'0';
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[148..156].fill(b' ');
+        let checksum: u32 = header.iter().map(|&byte| u32::from(byte)).sum();
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+        header
+    }
+
+    fn tar_of(members: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut tar = Vec::new();
+        for (name, contents) in members {
+            tar.extend(tar_header(name, contents.len()));
+            tar.extend_from_slice(contents);
+            tar.resize(tar.len().div_ceil(BLOCK) * BLOCK, 0);
+        }
+        tar.resize(tar.len() + 2 * BLOCK, 0);
+        tar
+    }
+
+    #[test]
+    fn test_kind_of() {
+        assert_eq!(Kind::of(Path::new("release/Bundle.ZIP")), Some(Kind::Zip));
+        assert_eq!(Kind::of(Path::new("data.tgz")), Some(Kind::TarGz));
+        assert_eq!(Kind::of(Path::new("data.tar")), Some(Kind::Tar));
+        assert_eq!(Kind::of(Path::new("app.log.1.gz")), Some(Kind::Gzip));
+        assert_eq!(Kind::of(Path::new("Handbook.docx")), Some(Kind::Office));
+        assert_eq!(Kind::of(Path::new("notes.txt")), None);
+    }
+
+    #[test]
+    fn test_tar_and_tar_gz() {
+        let (options, plugins) = (Options::default(), Plugins::default());
+        let tar_contents = tar_of(&[("a.txt", b"version 1\n"), ("logo.png", b"\x89PNG\0version 1"), ("b.txt", b"nothing")]);
+        let (replacements, repacked) = rewrite_archive(Path::new("x.tar"), Kind::Tar, &tar_contents, "1", "10.0", &options, &plugins).unwrap().unwrap();
+        assert_eq!(replacements, 1);
+        assert_eq!(repacked, tar_of(&[("a.txt", b"version 10.0\n"), ("logo.png", b"\x89PNG\0version 1"), ("b.txt", b"nothing")]));
+        assert!(rewrite_archive(Path::new("x.tar"), Kind::Tar, &tar_contents, "missing", "", &options, &plugins).unwrap().is_none());
+
+        let header = GzipHeader(b"\x1f\x8b\x08\x08\0\0\0\0\0\x03x.tar\0");
+        let gzipped = gzip(header, &tar_contents);
+        let (_, repacked) = rewrite_archive(Path::new("x.tgz"), Kind::TarGz, &gzipped, "1", "10.0", &options, &plugins).unwrap().unwrap();
+        let (unpacked, header) = gunzip(&repacked).unwrap();
+        assert_eq!(header.0, b"\x1f\x8b\x08\x08\0\0\0\0\0\x03x.tar\0");
+        assert_eq!(unpacked, tar_of(&[("a.txt", b"version 10.0\n"), ("logo.png", b"\x89PNG\0version 1"), ("b.txt", b"nothing")]));
+        assert!(gunzip(&repacked[..repacked.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_gzip() {
+        let (options, plugins) = (Options::default(), Plugins::default());
+        // Made by `gzip -9`, which records the name and level in the header; the
+        // header is copied, though the member isn't recompressed at that level
+        let header = b"\x1f\x8b\x08\x08\x00\x09\x6e\x88\x02\x03app.log\0";
+        let gzipped = gzip(GzipHeader(header), b"GET /old 200\nGET /old 404\n");
+        let (replacements, rewritten) = rewrite_archive(Path::new("app.log.gz"), Kind::Gzip, &gzipped, "/old", "/new", &options, &plugins).unwrap().unwrap();
+        assert_eq!(replacements, 2);
+        let (text, kept) = gunzip(&rewritten).unwrap();
+        assert_eq!((text.as_slice(), kept.0), (&b"GET /new 200\nGET /new 404\n"[..], &header[..]));
+
+        let binary = gzip(GzipHeader(header), b"\0\0/old");
+        assert!(rewrite_archive(Path::new("a.gz"), Kind::Gzip, &binary, "/old", "/new", &options, &plugins).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_zip() {
+        // One deflated member with a data descriptor and one stored member
+        let text = b"name = old\n".repeat(20);
+        let compressed = deflate::deflate(&text);
+        let mut zip_contents = Vec::new();
+        let mut directory = Vec::new();
+        for (name, flags, method, data, contents) in [
+            ("a.txt", HAS_DATA_DESCRIPTOR, 8u16, compressed.as_slice(), text.as_slice()),
+            ("b.txt", 0, 0, b"old", b"old"),
+        ] {
+            let offset = zip_contents.len() as u32;
+            let crc = deflate::crc32(contents);
+            let sizes = [(data.len() as u32).to_le_bytes(), (contents.len() as u32).to_le_bytes()].concat();
+            let fields = [&flags.to_le_bytes()[..], &method.to_le_bytes(), &[0x21, 0x43, 0x65, 0x87]].concat();
+            let local_sizes = if flags == 0 { [&crc.to_le_bytes()[..], &sizes].concat() } else { vec![0; 12] };
+            zip_contents.extend([&LOCAL_HEADER.to_le_bytes()[..], &[20, 0], &fields, &local_sizes, &(name.len() as u16).to_le_bytes(), &[0, 0], name.as_bytes(), data].concat());
+            if flags != 0 {
+                zip_contents.extend([&DATA_DESCRIPTOR.to_le_bytes()[..], &crc.to_le_bytes(), &sizes].concat());
+            }
+            directory.extend([&CENTRAL_HEADER.to_le_bytes()[..], &[20, 3, 20, 0], &fields, &crc.to_le_bytes(), &sizes, &(name.len() as u16).to_le_bytes(),
+                &[0; 8], &(0o100644u32 << 16).to_le_bytes(), &offset.to_le_bytes(), name.as_bytes()].concat());
+        }
+        let directory_start = zip_contents.len() as u32;
+        zip_contents.extend_from_slice(&directory);
+        zip_contents.extend([&END_OF_CENTRAL_DIRECTORY.to_le_bytes()[..], &[0, 0, 0, 0, 2, 0, 2, 0], &(directory.len() as u32).to_le_bytes(),
+            &directory_start.to_le_bytes(), &[4, 0], b"note"].concat());
+
+        let (options, plugins) = (Options::default(), Plugins::default());
+        let (replacements, repacked) = rewrite_archive(Path::new("x.zip"), Kind::Zip, &zip_contents, "old", "new", &options, &plugins).unwrap().unwrap();
+        assert_eq!(replacements, 21);
+        assert!(repacked.ends_with(b"note"));
+
+        // Reading the repacked archive back finds the new text, with the time
+        // and permissions kept and the data descriptor gone
+        let end = repacked.len() - 26;
+        let mut position = u32_at(&repacked, end + 16).unwrap() as usize;
+        let mut members = Vec::new();
+        for _ in 0..2 {
+            let entry = &repacked[position..];
+            assert_eq!((&entry[12..16], u32_at(entry, 38)), (&[0x21, 0x43, 0x65, 0x87][..], Some(0o100644 << 16)));
+            let local = &repacked[u32_at(entry, 42).unwrap() as usize..];
+            assert_eq!(u16_at(local, 6), Some(0));
+            let data_start = 30 + usize::from(u16_at(local, 26).unwrap());
+            let data = &local[data_start..data_start + u32_at(local, 18).unwrap() as usize];
+            let contents = if u16_at(local, 8) == Some(8) { deflate::inflate(data, usize::MAX).unwrap().0 } else { data.to_vec() };
+            assert_eq!(u32_at(local, 14), Some(deflate::crc32(&contents)));
+            members.push(contents);
+            position += 46 + usize::from(u16_at(entry, 28).unwrap());
+        }
+        assert_eq!(members, [b"name = new\n".repeat(20), b"new".to_vec()]);
+
+        assert!(rewrite_archive(Path::new("x.zip"), Kind::Zip, b"not a zip", "old", "new", &options, &plugins).is_err());
+    }
+}
@@ -0,0 +1,75 @@
+//! Buffers reused from one file to the next on each thread.
+//!
+//! On a tree of hundreds of thousands of small files, allocating fresh buffers
+//! to scan and read every file keeps the allocator busy. Instead, each thread
+//! keeps a few buffers from the files it has finished with, and hands them out
+//! again with their capacity intact. Contents that move on to another thread,
+//! like those of a modified file on its way to be written, are freed there as
+//! usual.
+
+use std::cell::RefCell;
+
+/// How many buffers each thread keeps
+const POOLED: usize = 4;
+
+/// Buffers larger than this are freed rather than kept, so that one huge file
+/// doesn't pin its memory for the rest of the run
+const MAX_POOLED_CAPACITY: usize = 4 * 1024 * 1024;
+
+thread_local! {
+    static POOL: RefCell<Vec<Vec<u8>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Returns an empty buffer, reusing one this thread is done with if it can.
+pub fn take() -> Vec<u8> {
+    POOL.with(|pool| pool.borrow_mut().pop()).unwrap_or_default()
+}
+
+/// Returns an empty string, reusing a buffer this thread is done with if it can.
+pub fn take_string() -> String {
+    // An empty buffer is always valid UTF-8
+    String::from_utf8(take()).unwrap_or_default()
+}
+
+/// Keeps a buffer this thread is done with, to be handed out again by `take`.
+pub fn recycle(mut buffer: Vec<u8>) {
+    if buffer.capacity() == 0 || buffer.capacity() > MAX_POOLED_CAPACITY {
+        return;
+    }
+    buffer.clear();
+    POOL.with(|pool| {
+        let mut pool = pool.borrow_mut();
+        if pool.len() < POOLED {
+            pool.push(buffer);
+        }
+    });
+}
+
+/// Keeps a string this thread is done with, to be handed out again by `take`.
+pub fn recycle_string(text: String) {
+    recycle(text.into_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffers_are_reused() {
+        let mut buffer = take();
+        buffer.extend_from_slice(b"hello");
+        let capacity = buffer.capacity();
+        let pointer = buffer.as_ptr();
+        recycle(buffer);
+
+        let buffer = take_string();
+        assert!(buffer.is_empty());
+        assert_eq!((buffer.capacity(), buffer.as_ptr()), (capacity, pointer));
+        recycle_string(buffer);
+
+        // Huge buffers aren't kept
+        recycle(Vec::with_capacity(MAX_POOLED_CAPACITY + 1));
+        assert_eq!(take().capacity(), capacity);
+        assert_eq!(take().capacity(), 0);
+    }
+}
@@ -0,0 +1,272 @@
+//! A cache of files known not to contain the find text, for `--cache`.
+//!
+//! Migrations are often run again and again with the same find text as the
+//! last few files are fixed up. The cache remembers each file that had no
+//! match along with its size, modification time and a hash of its contents, so
+//! later runs can skip it without reading it while it stays the same.
+//!
+//! The cache is a text file with one entry per line:
+//!
+//! ```text
+//! <pattern hash> <modified seconds>.<nanoseconds> <size> <content hash> <absolute path>
+//! ```
+
+use crate::encoding::Encoding;
+use crate::write_atomically;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+/// The first line of a cache file, so that caches written in another format are
+/// ignored instead of misread
+const HEADER: &str = "fr cache 1";
+
+/// FNV-1a, which is simple and stable across Rust versions unlike `std`'s hasher
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Fnv(pub(crate) u64);
+
+impl Fnv {
+    pub(crate) fn new() -> Fnv {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+/// Identifies what was searched for, since a file without a match for one find
+/// text may well contain another.
+///
+/// # Arguments
+///
+/// * `find_text` - Text that was found
+/// * `encoding` - The encoding the file was read in
+pub fn pattern(find_text: &str, encoding: Encoding) -> u64 {
+    let mut hash = Fnv::new();
+    hash.write(format!("{:?}", encoding).as_bytes());
+    hash.write(&[0]);
+    hash.write(find_text.as_bytes());
+    hash.0
+}
+
+/// What a file looked like when it had no match
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Entry {
+    /// Seconds and nanoseconds since the Unix epoch it was last modified at
+    modified: (u64, u32),
+    size: u64,
+    /// Hash of the file's contents
+    hash: u64,
+}
+
+/// Returns a file's modification time as seconds and nanoseconds since the Unix epoch.
+fn modified(metadata: &fs::Metadata) -> io::Result<(u64, u32)> {
+    let since_epoch = metadata.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+    Ok((since_epoch.as_secs(), since_epoch.subsec_nanos()))
+}
+
+/// Hashes the contents of a file.
+fn hash_file(file_path: &Path) -> io::Result<u64> {
+    let mut file = File::open(file_path)?;
+    let mut hash = Fnv::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        match file.read(&mut buffer) {
+            Ok(0) => return Ok(hash.0),
+            Ok(read) => hash.write(&buffer[..read]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// The files known not to contain each find text
+#[derive(Debug)]
+pub struct Cache {
+    /// Where the cache is saved
+    path: PathBuf,
+    entries: Mutex<HashMap<(u64, PathBuf), Entry>>,
+}
+
+impl Cache {
+    /// Loads the cache from a file, starting out empty if the file doesn't exist
+    /// yet. Lines that can't be parsed are dropped.
+    pub fn load(path: &Path) -> io::Result<Cache> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+
+        let mut entries = HashMap::new();
+        let mut lines = contents.lines();
+        if lines.next() == Some(HEADER) {
+            for line in lines {
+                if let Some((key, entry)) = parse_line(line) {
+                    entries.insert(key, entry);
+                }
+            }
+        }
+        Ok(Cache { path: path.to_path_buf(), entries: Mutex::new(entries) })
+    }
+
+    /// Whether a file is known not to contain what is being searched for, because
+    /// it hasn't changed since the last time it was searched.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - Path to the file
+    /// * `pattern` - What is being searched for, from `pattern`
+    pub fn has_no_match(&self, file_path: &Path, pattern: u64) -> bool {
+        let Ok(key) = std::path::absolute(file_path).map(|path| (pattern, path)) else {
+            return false;
+        };
+        let Some(entry) = self.entries.lock().unwrap().get(&key).copied() else {
+            return false;
+        };
+        let Ok(metadata) = fs::metadata(file_path) else {
+            return false;
+        };
+        if metadata.len() != entry.size {
+            return false;
+        }
+        let Ok(modified) = modified(&metadata) else {
+            return false;
+        };
+        if modified == entry.modified {
+            return true;
+        }
+
+        // A file that was touched without being changed still has no match
+        let unchanged = hash_file(file_path).is_ok_and(|hash| hash == entry.hash);
+        if unchanged {
+            self.entries.lock().unwrap().insert(key, Entry { modified, ..entry });
+        }
+        unchanged
+    }
+
+    /// Remembers that a file doesn't contain what is being searched for.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - Path to the file
+    /// * `pattern` - What is being searched for, from `pattern`
+    pub fn record_no_match(&self, file_path: &Path, pattern: u64) {
+        let entry = fs::metadata(file_path).and_then(|metadata| {
+            Ok(Entry { modified: modified(&metadata)?, size: metadata.len(), hash: hash_file(file_path)? })
+        });
+        if let (Ok(path), Ok(entry)) = (std::path::absolute(file_path), entry) {
+            self.entries.lock().unwrap().insert((pattern, path), entry);
+        }
+    }
+
+    /// Saves the cache back to its file.
+    pub fn save(&self) -> io::Result<()> {
+        let entries = self.entries.lock().unwrap();
+        let mut lines: Vec<String> = entries.iter()
+            .filter_map(|((pattern, path), entry)| {
+                // Paths are stored as text, one per line
+                let path = path.to_str().filter(|path| !path.contains('\n'))?;
+                Some(format!("{:016x} {}.{:09} {} {:016x} {}",
+                    pattern, entry.modified.0, entry.modified.1, entry.size, entry.hash, path))
+            })
+            .collect();
+        lines.sort();
+
+        let mut contents = format!("{}\n", HEADER);
+        for line in lines {
+            contents.push_str(&line);
+            contents.push('\n');
+        }
+        write_atomically(&self.path, contents.as_bytes())
+    }
+}
+
+/// Parses one entry of a cache file.
+fn parse_line(line: &str) -> Option<((u64, PathBuf), Entry)> {
+    let mut fields = line.splitn(5, ' ');
+    let pattern = u64::from_str_radix(fields.next()?, 16).ok()?;
+    let (seconds, nanos) = fields.next()?.split_once('.')?;
+    let modified = (seconds.parse().ok()?, nanos.parse().ok()?);
+    let size = fields.next()?.parse().ok()?;
+    let hash = u64::from_str_radix(fields.next()?, 16).ok()?;
+    let path = PathBuf::from(fields.next()?);
+    Some(((pattern, path), Entry { modified, size, hash }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+    use tempfile::TempDir;
+
+    /// Sets a file's modification time, so tests can tell touching a file from
+    /// changing it.
+    fn set_modified(file_path: &Path, time: SystemTime) {
+        File::options().write(true).open(file_path).unwrap().set_modified(time).unwrap();
+    }
+
+    #[test]
+    fn test_pattern() {
+        assert_eq!(pattern("hello", Encoding::Utf8), pattern("hello", Encoding::Utf8));
+        assert_ne!(pattern("hello", Encoding::Utf8), pattern("hello!", Encoding::Utf8));
+        assert_ne!(pattern("hello", Encoding::Utf8), pattern("hello", Encoding::Latin1));
+    }
+
+    #[test]
+    fn test_no_match_survives_saving() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache_path = temp_dir.path().join("cache");
+        let file_path = temp_dir.path().join("file.txt");
+        fs::write(&file_path, "hello world").unwrap();
+        let pattern = pattern("goodbye", Encoding::Utf8);
+
+        let cache = Cache::load(&cache_path).unwrap();
+        assert!(!cache.has_no_match(&file_path, pattern));
+        cache.record_no_match(&file_path, pattern);
+        cache.save().unwrap();
+
+        let cache = Cache::load(&cache_path).unwrap();
+        assert!(cache.has_no_match(&file_path, pattern));
+        assert!(!cache.has_no_match(&file_path, super::pattern("hello", Encoding::Utf8)));
+    }
+
+    #[test]
+    fn test_changed_files_are_searched_again() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("file.txt");
+        fs::write(&file_path, "hello world").unwrap();
+        let pattern = pattern("goodbye", Encoding::Utf8);
+        let cache = Cache::load(&temp_dir.path().join("cache")).unwrap();
+        cache.record_no_match(&file_path, pattern);
+
+        // Touching the file doesn't change its contents
+        set_modified(&file_path, SystemTime::now() + Duration::from_secs(60));
+        assert!(cache.has_no_match(&file_path, pattern));
+
+        // Changing it does, even when the size stays the same
+        fs::write(&file_path, "hello earth").unwrap();
+        set_modified(&file_path, SystemTime::now() + Duration::from_secs(120));
+        assert!(!cache.has_no_match(&file_path, pattern));
+    }
+
+    #[test]
+    fn test_load_ignores_other_formats() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache_path = temp_dir.path().join("cache");
+        fs::write(&cache_path, "something else\n0 1.0 2 3 /file\n").unwrap();
+        assert!(Cache::load(&cache_path).unwrap().entries.lock().unwrap().is_empty());
+
+        fs::write(&cache_path, format!("{}\nnot an entry\n00000000000000ff 1.000000002 3 0000000000000004 /a file\n", HEADER)).unwrap();
+        let cache = Cache::load(&cache_path).unwrap();
+        let entries = cache.entries.lock().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[&(255, PathBuf::from("/a file"))], Entry { modified: (1, 2), size: 3, hash: 4 });
+    }
+}
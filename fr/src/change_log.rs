@@ -0,0 +1,167 @@
+//! An append-only audit trail of every replacement made during a run.
+
+use crate::encoding::unescape;
+use crate::occurrences_at;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::Write;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A log file that records every replacement, shared between walker threads
+pub struct ChangeLog {
+    file: Mutex<File>,
+}
+
+impl ChangeLog {
+    /// Opens the log at the given path for appending, creating it if needed.
+    pub fn open(path: &Path) -> io::Result<ChangeLog> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(ChangeLog { file: Mutex::new(file) })
+    }
+
+    /// Appends one record per replacement made in a file.
+    ///
+    /// # Arguments
+    ///
+    /// * `display` - Path of the file as it should appear in the log
+    /// * `before` - Contents of the file before the replacement
+    /// * `replaced` - Each match in `before` that was replaced, in order, and
+    ///   what replaced it
+    pub fn record(&self, display: &Path, before: &str, replaced: &[(Range<usize>, &str)]) -> io::Result<()> {
+        let timestamp = format_timestamp(SystemTime::now());
+        let mut records = String::new();
+        // The line as rewritten, worked out once for all the matches on it
+        let mut rewritten: Option<(usize, String)> = None;
+        for ((found, _), occurrence) in replaced.iter().zip(occurrences_at(before, replaced.iter().map(|(found, _)| found.start))) {
+            if rewritten.as_ref().is_none_or(|(line, _)| *line != occurrence.line) {
+                let line_start = found.start - (occurrence.column - 1);
+                rewritten = Some((occurrence.line, rewrite_line(before, line_start..line_start + occurrence.line_text.len(), replaced)));
+            }
+            let after = rewritten.as_ref().map_or("", |(_, after)| after.as_str());
+            // Each stand-in is three bytes here for one byte in the file
+            let stand_ins = occurrence.line_text[..occurrence.column - 1].chars().filter(|&c| unescape(c).is_some()).count();
+            records.push_str(&format!("{} {}:{}:{}: {} -> {}\n",
+                timestamp,
+                display.display(),
+                occurrence.line,
+                occurrence.column - 2 * stand_ins,
+                quote(occurrence.line_text),
+                quote(after)));
+        }
+
+        // Write each file's records in one go so parallel walkers don't interleave
+        self.file.lock().unwrap().write_all(records.as_bytes())
+    }
+}
+
+/// Makes the replacements that lie within a line of the text, leaving the rest
+/// of the line as it was.
+fn rewrite_line(before: &str, line: Range<usize>, replaced: &[(Range<usize>, &str)]) -> String {
+    let mut rewritten = String::new();
+    let mut copied = line.start;
+    let first = replaced.partition_point(|(found, _)| found.start < line.start);
+    for (found, with) in replaced[first..].iter().take_while(|(found, _)| found.end <= line.end) {
+        rewritten.push_str(&before[copied..found.start]);
+        rewritten.push_str(with);
+        copied = found.end;
+    }
+    rewritten.push_str(&before[copied..line.end]);
+    rewritten
+}
+
+/// Quotes a line for the log as `{:?}` does, except that the stand-ins for
+/// bytes that aren't valid UTF-8 are written as the bytes, such as `\xE9`.
+fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    let mut run = String::new();
+    for c in text.chars() {
+        match unescape(c) {
+            Some(byte) => {
+                let debug = format!("{:?}", std::mem::take(&mut run));
+                quoted.push_str(&debug[1..debug.len() - 1]);
+                quoted.push_str(&format!("\\x{:02X}", byte));
+            }
+            None => run.push(c),
+        }
+    }
+    let debug = format!("{:?}", run);
+    quoted.push_str(&debug[1..]);
+    quoted
+}
+
+/// Formats a time as an RFC 3339 timestamp in UTC, such as `2024-01-31T09:05:00Z`.
+pub fn format_timestamp(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (year, month, day) = civil_from_days((seconds / 86_400) as i64);
+    let seconds_of_day = seconds % 86_400;
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60)
+}
+
+/// Converts a number of days since 1970-01-01 into a (year, month, day) date,
+/// using Howard Hinnant's `civil_from_days` algorithm.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(format_timestamp(UNIX_EPOCH + Duration::from_secs(951_782_400)), "2000-02-29T00:00:00Z");
+        assert_eq!(format_timestamp(UNIX_EPOCH + Duration::from_secs(1_706_691_900)), "2024-01-31T09:05:00Z");
+    }
+
+    #[test]
+    fn test_record_appends() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("changes.log");
+        fs::write(&log_path, "earlier run\n").unwrap();
+
+        let log = ChangeLog::open(&log_path).unwrap();
+        log.record(Path::new("src/a.txt"), "one foo\nfoo two foo\n", &[(4..7, "bar"), (8..11, "bar"), (16..19, "bar")]).unwrap();
+
+        let contents = fs::read_to_string(&log_path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "earlier run");
+        assert!(lines[1].ends_with(" src/a.txt:1:5: \"one foo\" -> \"one bar\""));
+        assert!(lines[2].ends_with(" src/a.txt:2:1: \"foo two foo\" -> \"bar two bar\""));
+        assert!(lines[3].ends_with(" src/a.txt:2:9: \"foo two foo\" -> \"bar two bar\""));
+    }
+
+    #[test]
+    fn test_record_writes_invalid_utf8_as_bytes() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("changes.log");
+        let (before, _) = crate::encoding::decode(b"caf\xE9 \"foo\"\n".to_vec(), crate::Encoding::Utf8).unwrap();
+
+        let found = before.find("foo").unwrap();
+        ChangeLog::open(&log_path).unwrap().record(Path::new("a.txt"), &before, &[(found..found + 3, "bar")]).unwrap();
+        let contents = fs::read_to_string(&log_path).unwrap();
+        assert!(contents.ends_with(" a.txt:1:7: \"caf\\xE9 \\\"foo\\\"\" -> \"caf\\xE9 \\\"bar\\\"\"\n"), "{}", contents);
+    }
+}
@@ -0,0 +1,2501 @@
+//! The command line interface of the `fr` binary: parsing arguments and
+//! running the command they ask for.
+
+use crate::color::{ColorChoice, Role, Theme};
+use crate::completions::{self, Shell};
+use crate::encoding::Encoding;
+use crate::eol::EolPolicy;
+use crate::index::Index;
+use crate::journal::{self, JournalQuery};
+use crate::license::{self, License};
+use crate::remote::{Remote, SshFileSystem};
+use crate::report::Report;
+use crate::{config, doctor, everything, explain, find_replace_in, lint, manpage, modules, operation, plan, redact, find_replace_stream, ripgrep, scaffold, script, serve, parse_size, stats, throttle, trace, urls, version, walk_find_replace, write_atomically};
+use crate::{Context, FrontMatter, JsonPath, KeyPattern, Members, Options, Redaction, TomlKey, XmlScope, OutputFormat, ScriptKind, SkipReason, Summary};
+use globset::Glob;
+use std::env;
+use std::io::{self, BufWriter, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const HELP_MESSAGE: &str = r#"fr - A simple find-replace tool for the command line
+
+Usage: 
+- fr [options] <find_text> <replace_text>
+- fr [options] <find_text> <replace_text> ssh://[user@]host[:port]/path
+- fr --explain <path> [<find_text> <replace_text>]
+- fr --version
+- fr --generate-manpage
+- fr --help
+
+Options:
+    -c, --count         Print the number of matches in each file as path:count
+                        without modifying anything
+    --format <format>   Print every match instead of modifying anything.
+                        Formats: vimgrep (file:line:col:matched line)
+    --print0-modified   Print the path of every modified file followed by a
+                        NUL byte, for use with xargs -0
+    --modified-list <file>
+                        Write the path of every modified file, one per line,
+                        to <file> once the run has finished
+    --log <file>        Append a timestamped record of every replacement
+                        (file, line, before and after) to <file>
+    --journal sqlite:<file>
+                        Record the run, each file it rewrites and every edit
+                        in the SQLite database <file>, to look up and undo
+                        with fr journal
+    --report <report>   Produce a report once the run has finished. Reports:
+                        html:PATH (per-file diffs, counts and skipped files),
+                        diffstat (per-file +/- histogram, like git diff --stat),
+                        csv:PATH (one row per replacement)
+    --why-skipped       Print why each file that wasn't modified was skipped
+    --explain <path>    Explain why fr would or wouldn't modify <path>, such
+                        as it being ignored, hidden, binary, or not matching
+    --log-level <level> Log walk decisions, timings and errors to stderr.
+                        Levels: off, error, warn, info, debug, trace.
+                        Defaults to the FR_LOG environment variable
+    --trace-chrome <file>
+                        Write a Chrome trace of the time spent on each file
+                        to <file>, for chrome://tracing or Perfetto
+    --stats             Print the time spent walking, reading, matching and
+                        writing, and the 10 slowest files, to stderr
+    --max-filesize <size>
+                        Skip files larger than <size>, such as 512K or 10M
+    --max-line-length <n>
+                        Skip files with a line longer than <n> bytes, such as
+                        minified code. Files with lines over 10000 bytes are
+                        warned about when this isn't given
+    --show-skipped      List the files skipped as binary or too large once the
+                        run has finished
+    -a, --text          Search and rewrite files that look binary as well
+    --sparse            Rewrite sparse files too, which fills in their holes
+                        and can make them take up far more disk space
+    --streams           Also search and rewrite the text in each file's NTFS
+                        alternate data streams, on Windows
+    --exclude <glob>    Leave out files and directories whose names or paths
+                        match <glob>, such as target or '*.min.js'. Can be
+                        given more than once
+    --include <glob>    Only read files whose names or paths match <glob>,
+                        such as '*.rs'. Can be given more than once
+    --workspace         In a Cargo workspace, only read files in the
+                        directories of its members, as listed in Cargo.toml
+    --package <name>    In a Cargo workspace, only read files in the
+                        directory of the member named <name>, and not in the
+                        members inside it. Can be given more than once
+    --forbid <dir>      Refuse to run in <dir>, and never change anything
+                        under it. Can be given more than once
+    --unsafe-vcs-dirs   Also read and rewrite files inside .git, .hg and .svn
+                        directories, which can corrupt the repository
+    --fsync             Flush each rewritten file to disk before counting it
+                        as written, so a crash straight after can't lose it
+    --strict            Stop at the first file that can't be processed,
+                        rather than carrying on with the others
+    --retries <n>       Retry reading or writing a file up to <n> times when
+                        it is busy or the filesystem doesn't answer, before
+                        it counts as failed. Defaults to 3
+    --no-config         Ignore the defaults in ~/.config/fr/config.toml and
+                        the project's .fr.toml. Flags in the FR_OPTS
+                        environment variable still go before the others
+    --profile <name>    Also use the settings in the [profile.<name>] section
+                        of either configuration file
+    --text-ext <exts>   Always treat files with these comma-separated
+                        extensions as text, such as svg,ts,dat
+    --archives          Also replace text in the members of .zip, .tar and
+                        .tar.gz archives, repacking them
+    --gzip              Also replace text in .gz files, recompressing them
+                        with fr's own encoder, which can make them larger
+    --json-values       Only replace text inside the string values of JSON
+                        files, never in keys or structure
+    --json-path <path>  Only replace text in the JSON string values at <path>,
+                        such as '$.dependencies.*'. Implies --json-values, and
+                        can be given more than once
+    --yaml-values       Only replace text inside the scalar values of .yaml and
+                        .yml files, never in keys, comments or anchors
+    --yaml-path <path>  Only replace text in the YAML values at <path>, such as
+                        '$..image', in the syntax of --json-path. Implies
+                        --yaml-values, and can be given more than once
+    --toml-key <key>    Only replace text in the values of <key> in .toml
+                        files, such as package.version or dependencies.*.
+                        Can be given more than once
+    --xml-scope <scope> Only replace text in part of .html, .xml and .svg files.
+                        Scopes: text (between tags), attr:NAME (the values
+                        of an attribute), tag:NAME (text inside an element).
+                        Can be given more than once
+    --md-links          Only replace text in the link and image destinations
+                        of Markdown files
+    --check-links       With --md-links, leave a relative link alone, with a
+                        warning, if what it would point to doesn't exist
+    --urls              Only replace text inside http:// and https:// URLs
+    --validate          With --urls, send each rewritten URL a HEAD request
+                        through curl, and leave it alone, with a warning, if
+                        it doesn't answer with a success or a redirect
+    --validate-jobs <n> Make at most <n> --validate requests at a time.
+                        Defaults to 8
+    --includes          Only replace text in the paths of #include lines in C,
+                        C++ and Objective-C files, never in strings or comments
+    --front-matter <side>
+                        Only replace text inside the YAML front matter of
+                        Markdown files, or only outside it: inside, outside
+    --po                Only replace text in the msgstr translations of .po and
+                        .pot files, never in msgid
+    --po-fuzzy          With --po, flag each entry whose translation changed
+                        as fuzzy, for review
+    --config-values     Only replace text in the values of .env, .properties
+                        and .ini, .cfg or .conf files, never in keys, sections
+                        or comments. Searches hidden .env files too
+    --config-key <glob> Only replace text in the values of keys matching
+                        <glob>, such as '*_HOST' or database.host for a key in
+                        an INI section. Implies --config-values, and can be
+                        given more than once
+    --confusables       Also match look-alikes of the find text, such as with
+                        Cyrillic or fullwidth letters or zero width
+                        characters in it
+    --office            Also replace text in .docx, .xlsx and .pptx documents,
+                        repacking them
+    --color <when>      When to color output: auto (the default, only on a
+                        terminal and when NO_COLOR isn't set), always, never
+    --theme <theme>     Colors to use: default, colorblind, monochrome
+    --encoding <enc>    Read and write files as utf-8 (the default), latin1,
+                        utf-16le, utf-16be, shift_jis, or auto to detect each
+                        file's encoding
+    --eol <ending>      Convert the line endings of rewritten files to lf or
+                        crlf, or preserve them (the default)
+    --no-editorconfig   Ignore the charset and end_of_line settings in
+                        .editorconfig files
+    --no-gitattributes  Ignore binary, -diff, text and linguist-generated in
+                        .gitattributes files, and go by what files look like
+    --cache <file>      Remember which files have no match in <file>, and skip
+                        them on later runs while they are unchanged
+    -j, --threads <n>   Walk, read and write files with <n> threads each.
+                        Defaults to one per CPU
+    --throttle <rate>   Read and write at most <rate> megabytes per second, or
+                        a size per second such as 512K
+    --nice              Run at a lower CPU and IO priority
+    --max-memory <size> Hold at most about <size> of file contents in memory
+                        at once, such as 512M, streaming larger files
+    --hook <command>    Run <command> through the shell as each file is
+                        started, matched, written, or fails, with FR_EVENT,
+                        FR_PATH, FR_REPLACEMENTS and FR_ERROR set. Exiting
+                        unsuccessfully on start or match skips the file
+    --plugin <file>     Load a WebAssembly plugin that can veto files, rewrite
+                        matches and post-process rewritten files. Can be given
+                        more than once
+    -n, --dry-run       Show which files would change without writing anything
+    -C, --context <n>   Show <n> lines around each match in --dry-run output,
+                        with each match and its replacement highlighted, or
+                        around each change in --diff output instead of 3
+    -A, --after-context <n>
+                        Show <n> lines after each match, overriding -C
+    -B, --before-context <n>
+                        Show <n> lines before each match, overriding -C
+    --diff              Print a unified diff of every change. Combine with
+                        --dry-run to preview changes
+    --no-pager          Don't send --diff and --dry-run output through $PAGER
+                        when it doesn't fit on one screen
+    --no-secret-warning Don't warn when the replace text looks like a
+                        credential, such as an API key or token
+    --check-rust        Warn about renames in .rs files that would likely
+                        break the build: matches that are part of a longer
+                        identifier, new names already used in the same
+                        scope, and paths also imported in a group
+    --force             Run even when the find and replace text are the same,
+                        and don't warn when the replace text contains the
+                        find text
+    --export-script <sed|perl>
+                        Print a standalone sed or perl script that makes the
+                        replacements in the files that would be modified,
+                        instead of modifying them
+    --save-plan <file>  Save the replacements the run would make to a plan
+                        file instead of making them, to review and make
+                        later with fr apply <file>
+    --save-op <file>    Save the arguments of the run, with the flags from the
+                        configuration files, to a JSON file instead of
+                        running it, to replay with fr run <file>
+    --estimate          Work out how many files and replacements the run
+                        would make, and ask whether to go on before
+                        writing anything
+    --pick              Choose which of the files that would be modified to
+                        modify, from a list filtered as you type
+    --out-dir <dir>     Write the modified files under <dir>, at the same
+                        paths as in the current directory, and leave the
+                        originals untouched
+    --everything        Also replace the find text in the names of files and
+                        directories and the targets of symlinks, undoing
+                        every change if any of them fails
+    --stdin             Replace in the text read from stdin and write it to
+                        stdout instead of walking the tree, as a - after the
+                        find and replace text does
+
+Commands:
+    fr index            Index the files under the current directory, so later
+                        runs only read the files that may contain a match
+    fr serve            Answer JSON-RPC requests on stdin, one per line, to
+                        scan, plan, apply and undo changes from an editor
+    fr bench <find> <replace>
+                        Run without writing anything and report the MB/s and
+                        files/s of each stage, to compare flags and versions
+    fr lint --rules <file> [--fix | --explain-conflicts]
+                        Report each forbidden term in the rules file, lines
+                        of term = "suggestion", and exit non-zero if any are
+                        found. --fix replaces them with their suggestions,
+                        the leftmost and longest match first where terms
+                        overlap; --explain-conflicts reports where they do
+    fr doctor --rules <file>
+                        Check a rules file for rules that conflict with or
+                        overlap each other, or would change the tree again
+                        on every fix, and exit non-zero if any do
+    fr run <file> [flags]
+                        Replay an operation saved with --save-op, with any
+                        flags given added to it, such as --dry-run
+    fr apply <plan>     Make the replacements in a plan saved with
+                        --save-plan, in the files that haven't changed since,
+                        and report the ones that have
+    fr journal sqlite:<file> [--touching <path> | --run <n> | --undo <n>]
+                        List the runs in a journal kept with --journal, or
+                        the ones that rewrote <path>, the edits of run <n>,
+                        or undo run <n> in the files that haven't changed
+                        since
+    fr apply-rg <replace>
+                        Replace exactly the matches in rg --json output read
+                        from stdin, as in: rg --json 'fo+' | fr apply-rg bar
+    fr bump-version <old> <new> [--manifests]
+                        Replace a version, such as 1.2.3, only where it is
+                        the whole version, not part of 11.2.3 or 1.2.3-beta.
+                        --manifests only replaces it where projects keep
+                        their version: Cargo.toml, pyproject.toml,
+                        package.json, __version__ in Python and changelog
+                        headings
+    fr mv <old> <new>   Move a module: replace its path in Rust code, and its
+                        specifier in JavaScript and TypeScript imports, but
+                        not in strings, comments or longer names
+    fr clean --invisible
+                        Remove zero width spaces, byte order marks after the
+                        start of a file, bidi controls and other invisible
+                        characters, printing where each one was
+    fr completions <shell>
+                        Print the completion script for bash, zsh, fish or
+                        powershell, as in: source <(fr completions bash)
+    fr redact [--kinds <kinds>]
+                        Replace personal data and secrets with placeholders
+                        such as [REDACTED_EMAIL]. Kinds, all by default:
+                        email, ipv4, ipv6, aws_key, github_token, jwt,
+                        credit_card
+    fr license --old <file> --new <file>
+                        Replace the license header each source file starts
+                        with, if it is the one in the --old file whatever its
+                        years and wrapping, with the one in the --new file,
+                        written in the file's comment syntax
+    fr bump-year        Extend copyright notices such as Copyright (c)
+                        2019-2023 to end in the current year
+    fr scaffold --var <name=value>
+                        Fill in a project template: replace each {{name}}
+                        placeholder in the files, hidden ones included, and
+                        in the names of files and directories. --var can be
+                        given once for each placeholder
+
+Description:
+    fr recursively finds and replaces text in files, starting from the current
+    directory. fr uses .gitignore patterns if in a git repository. Given - after
+    the find and replace text, fr replaces the text read from stdin and writes
+    it to stdout instead, like sed.
+
+Example:
+    fr "old_text" "new_text"    # Replace all occurrences of "old_text" with "new_text"
+    cat file | fr "old" "new" - > out
+
+Note:
+    - Text matching is literal (no regular expressions)
+    - Put -- before find or replace text starting with -, or naming a
+      command: fr -- -old -new
+    - Files matching .gitignore patterns are skipped
+    - Only text files are processed
+"#;
+
+/// Represents the different possible command line argument outcomes
+#[derive(Debug)]
+enum CommandArgs<'a> {
+    /// Show help message and exit
+    Help,
+    /// Show version and exit
+    Version,
+    /// Print the man page and exit
+    Manpage,
+    /// Explain what would happen to a single path
+    Explain {
+        path: &'a str,
+        find_text: Option<&'a str>,
+        options: Options,
+    },
+    /// Measure how fast find and replace runs over the current directory
+    Bench {
+        find_text: &'a str,
+        replace_text: &'a str,
+        options: Options,
+    },
+    /// Print a shell's completion script
+    Completions {
+        shell: Shell,
+    },
+    /// Print the names of the profiles in the configuration files, for the
+    /// completion scripts
+    Profiles,
+    /// Build the trigram index of the current directory
+    Index {
+        options: Options,
+    },
+    /// Check the current directory for forbidden terms, or fix them
+    Lint {
+        rules: &'a Path,
+        fix: bool,
+        /// Report where rules compete instead of what they find
+        explain_conflicts: bool,
+        options: Options,
+    },
+    /// Check a rules file for rules that get in each other's way
+    Doctor {
+        rules: &'a Path,
+    },
+    /// Replace the matches in `rg --json` output read from stdin
+    ApplyRg {
+        replace_text: &'a str,
+        options: Options,
+    },
+    /// Make the edits saved in a plan file with `--save-plan`
+    Apply {
+        plan: &'a Path,
+        options: Options,
+    },
+    /// Look up or undo the runs in a journal kept with `--journal`
+    Journal {
+        journal: PathBuf,
+        query: JournalQuery<'a>,
+        options: Options,
+    },
+    /// Answer JSON-RPC requests on stdio until stdin closes
+    Serve {
+        options: Options,
+    },
+    /// Perform find and replace in a tree on another machine
+    Remote {
+        find_text: &'a str,
+        replace_text: &'a str,
+        remote: Remote,
+        options: Options,
+    },
+    /// Perform find and replace with the given text
+    FindReplace {
+        find_text: &'a str,
+        replace_text: &'a str,
+        options: Options,
+    },
+}
+
+/// How many of the files that failed with each error to list
+const LISTED_ERRORS: usize = 5;
+
+/// Describes the files that couldn't be processed, grouped by what went
+/// wrong, with the most common error first.
+///
+/// # Arguments
+///
+/// * `errors` - Each file that failed, and why
+/// * `display` - Formats a file's path to show
+///
+/// # Returns
+///
+/// * `Option<String>` - The description, or None if nothing failed
+fn errors_message(errors: &[(PathBuf, String)], display: impl Fn(&Path) -> String) -> Option<String> {
+    if errors.is_empty() {
+        return None;
+    }
+    let mut groups: Vec<(String, Vec<&Path>)> = Vec::new();
+    for (path, error) in errors {
+        // The same error in different files reads the same without its code
+        let error = match error.rfind(" (os error ") {
+            Some(at) if error.ends_with(')') => &error[..at],
+            _ => error,
+        };
+        let mut chars = error.chars();
+        let error: String = chars.next().map(|c| c.to_lowercase().chain(chars).collect()).unwrap_or_default();
+        match groups.iter_mut().find(|(group, _)| *group == error) {
+            Some((_, paths)) => paths.push(path),
+            None => groups.push((error, vec![path])),
+        }
+    }
+    groups.sort_by_key(|(_, paths)| std::cmp::Reverse(paths.len()));
+
+    let mut message = format!("Failed to process {} file{}:\n", errors.len(), if errors.len() == 1 { "" } else { "s" });
+    for (error, paths) in &groups {
+        message.push_str(&format!("  {}: {} file{}\n", error, paths.len(), if paths.len() == 1 { "" } else { "s" }));
+        for path in paths.iter().take(LISTED_ERRORS) {
+            message.push_str(&format!("    {}\n", display(path)));
+        }
+        if paths.len() > LISTED_ERRORS {
+            message.push_str(&format!("    and {} more\n", paths.len() - LISTED_ERRORS));
+        }
+    }
+    Some(message)
+}
+
+/// Prints the files `fr apply` or `fr journal --undo` rewrote, the ones left
+/// alone since they changed, and the ones that failed.
+///
+/// # Arguments
+///
+/// * `summary` - The files rewritten and the ones that failed
+/// * `drifted` - The files left alone
+/// * `since` - What they changed since, such as `the plan was made`
+/// * `options` - Whether this was a dry run, and how to print
+fn print_applied(summary: &Summary, drifted: &[PathBuf], since: &str, options: &Options) {
+    let painter = options.stdout_painter();
+    for file in summary.modified.iter().filter(|_| !options.quiet) {
+        println!("{} {} ({} replacement{})", if options.dry_run { "Would modify" } else { "Modified" },
+            painter.paint(Role::Path, &file.path.display().to_string()), file.replacements, if file.replacements == 1 { "" } else { "s" });
+    }
+    if !drifted.is_empty() {
+        eprintln!("{} file{} changed since {}, so {} left alone:", drifted.len(),
+            if drifted.len() == 1 { "" } else { "s" }, since, if drifted.len() == 1 { "was" } else { "were" });
+        for path in drifted {
+            eprintln!("  {}", path.display());
+        }
+    }
+    if let Some(message) = errors_message(&summary.errors, |path| path.display().to_string()) {
+        eprint!("{}", message);
+    }
+}
+
+/// The error a run ends with when some files failed, so fr exits non-zero.
+fn failed(errors: &[(PathBuf, String)]) -> Result<(), String> {
+    match errors.len() {
+        0 => Ok(()),
+        count => Err(format!("{} file{} could not be processed", count, if count == 1 { "" } else { "s" })),
+    }
+}
+
+/// Describes the files skipped as binary, too large, in use, sparse, with long
+/// lines or generated, either listing them or noting how to list them.
+fn skipped_files_message(summary: &Summary, show_skipped: bool) -> Option<String> {
+    if summary.skipped.is_empty() {
+        return None;
+    }
+    if show_skipped {
+        let mut message = String::from("Skipped files:\n");
+        for (path, reason) in &summary.skipped {
+            message.push_str(&format!("  {}: {}\n", path.display(), reason));
+        }
+        return Some(message);
+    }
+
+    let binary = summary.skipped.iter().filter(|(_, reason)| *reason == SkipReason::Binary).count();
+    let too_large = summary.skipped.iter().filter(|(_, reason)| *reason == SkipReason::TooLarge).count();
+    let in_use = summary.skipped.iter().filter(|(_, reason)| *reason == SkipReason::InUse).count();
+    let sparse = summary.skipped.iter().filter(|(_, reason)| *reason == SkipReason::Sparse).count();
+    let long_lines = summary.skipped.iter().filter(|(_, reason)| *reason == SkipReason::LongLines).count();
+    let generated = summary.skipped.iter().filter(|(_, reason)| *reason == SkipReason::Generated).count();
+    let mut parts = Vec::new();
+    if binary > 0 {
+        parts.push(format!("{} binary file{}", binary, if binary == 1 { "" } else { "s" }));
+    }
+    if too_large > 0 {
+        parts.push(format!("{} file{} over --max-filesize", too_large, if too_large == 1 { "" } else { "s" }));
+    }
+    if in_use > 0 {
+        parts.push(format!("{} file{} in use by another program", in_use, if in_use == 1 { "" } else { "s" }));
+    }
+    if sparse > 0 {
+        parts.push(format!("{} sparse file{}", sparse, if sparse == 1 { "" } else { "s" }));
+    }
+    if long_lines > 0 {
+        parts.push(format!("{} file{} with lines over --max-line-length", long_lines, if long_lines == 1 { "" } else { "s" }));
+    }
+    if generated > 0 {
+        parts.push(format!("{} generated file{}", generated, if generated == 1 { "" } else { "s" }));
+    }
+    if parts.is_empty() {
+        return None;
+    }
+    let hint = match sparse {
+        0 => "pass --show-skipped to list them",
+        _ => "pass --show-skipped to list them, or --sparse to rewrite sparse files",
+    };
+    Some(format!("Skipped {} ({})\n", parts.join(" and "), hint))
+}
+
+/// Returns the value of a flag that takes an argument, given either as
+/// `--flag value` or `--flag=value`, or None if `arg` is not that flag.
+fn flag_value<'a>(
+    flag: &str,
+    arg: &'a str,
+    remaining: &mut impl Iterator<Item = &'a String>,
+) -> Result<Option<&'a str>, String> {
+    if arg == flag {
+        return match remaining.next() {
+            Some(value) => Ok(Some(value)),
+            None => Err(format!("{} requires a value", flag)),
+        };
+    }
+    Ok(arg.strip_prefix(flag).and_then(|rest| rest.strip_prefix('=')))
+}
+
+/// Refuses `--count`, `--format`, `--log` and `--report` for a subcommand
+/// that doesn't report on the find text.
+///
+/// # Arguments
+///
+/// * `command` - The subcommand, such as `lint`
+/// * `options` - The flags given with it
+fn reject_reporting(command: &str, options: &Options) -> Result<(), String> {
+    if options.count || options.format.is_some() || options.log.is_some() || !options.reports.is_empty() {
+        return Err(format!("fr {} cannot be combined with --count, --format, --log or --report", command));
+    }
+    Ok(())
+}
+
+/// Parses the number of a run in a journal, for `--run` and `--undo`.
+fn parse_run(value: &str) -> Result<u64, String> {
+    value.parse().ok().filter(|&id| id > 0).ok_or_else(|| format!("Invalid run '{}', expected the number of a run in the journal", value))
+}
+
+/// Describes a flag fr doesn't know, suggesting the known flag closest to it
+/// and how to find text that starts with `-`.
+fn unknown_flag(flag: &str) -> String {
+    let name = flag.split('=').next().unwrap_or(flag);
+    let suggestion = completions::flag_names(HELP_MESSAGE).into_iter()
+        .map(|known| (edit_distance(name, &known), known))
+        .filter(|(distance, known)| *distance <= known.len() / 3)
+        .min_by_key(|(distance, _)| *distance);
+    match suggestion {
+        Some((_, known)) => format!("Unknown flag '{}', did you mean '{}'?", name, known),
+        None => format!("Unknown flag '{}'. To find or replace text starting with -, put -- before it, as in: fr -- {} new", name, flag),
+    }
+}
+
+/// The number of single character insertions, deletions and substitutions
+/// that turn one string into another.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, &b) in b.iter().enumerate() {
+            current.push((previous[j] + usize::from(a != b)).min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Parses command line arguments and returns the appropriate command.
+/// 
+/// # Arguments
+/// 
+/// * `args` - Vector of command line arguments
+/// 
+/// # Returns
+/// 
+/// * `Result<CommandArgs, String>` - On success, returns the parsed command.
+///   On failure, returns an error message.
+fn parse_arguments<'a>(args: &'a [String]) -> Result<CommandArgs<'a>, String> {
+    if args.len() == 2 && args[1] == "--help" {
+        return Ok(CommandArgs::Help);
+    }
+
+    if args.len() == 2 && args[1] == "--version" {
+        return Ok(CommandArgs::Version);
+    }
+
+    if args.len() == 2 && args[1] == "--generate-manpage" {
+        return Ok(CommandArgs::Manpage);
+    }
+
+    let mut options = Options::default();
+    let mut explain_path = None;
+    let mut kinds = None;
+    let mut rules = None;
+    let mut fix = false;
+    let mut explain_conflicts = false;
+    let mut invisible = false;
+    let mut manifests = false;
+    let mut vars = Vec::new();
+    let (mut old_header, mut new_header) = (None, None);
+    // Lines of context from -B, -A and -C, which the first two override
+    let (mut before_context, mut after_context, mut context) = (None, None, None);
+    let mut positionals: Vec<&'a str> = Vec::new();
+    // How many positionals came before --, if it was given
+    let mut before_dashes = None;
+    let mut journal_query = None;
+    let mut remaining = args[1..].iter();
+    while let Some(arg) = remaining.next() {
+        // Everything after -- is text to find and replace, even if it starts
+        // with -
+        if arg == "--" {
+            before_dashes = Some(positionals.len());
+            positionals.extend(remaining.by_ref().map(String::as_str));
+            break;
+        }
+        if let Some(value) = flag_value("--export-script", arg, &mut remaining)? {
+            options.export_script = Some(ScriptKind::parse(value)?);
+            continue;
+        }
+        if let Some(value) = flag_value("--save-plan", arg, &mut remaining)? {
+            options.save_plan = Some(PathBuf::from(value));
+            continue;
+        }
+        if let Some(value) = flag_value("--format", arg, &mut remaining)? {
+            options.format = Some(OutputFormat::parse(value)?);
+            continue;
+        }
+        if let Some(value) = flag_value("--out-dir", arg, &mut remaining)? {
+            options.out_dir = Some(PathBuf::from(value));
+            continue;
+        }
+        if let Some(value) = flag_value("--modified-list", arg, &mut remaining)? {
+            options.modified_list = Some(PathBuf::from(value));
+            continue;
+        }
+        if let Some(value) = flag_value("--log", arg, &mut remaining)? {
+            options.log = Some(PathBuf::from(value));
+            continue;
+        }
+        if let Some(value) = flag_value("--journal", arg, &mut remaining)? {
+            options.journal = Some(journal::parse(value)?);
+            continue;
+        }
+        if let Some(value) = flag_value("--touching", arg, &mut remaining)? {
+            journal_query = Some(JournalQuery::Touching(value));
+            continue;
+        }
+        if let Some(value) = flag_value("--run", arg, &mut remaining)? {
+            journal_query = Some(JournalQuery::Edits(parse_run(value)?));
+            continue;
+        }
+        if let Some(value) = flag_value("--undo", arg, &mut remaining)? {
+            journal_query = Some(JournalQuery::Undo(parse_run(value)?));
+            continue;
+        }
+        if let Some(value) = flag_value("--report", arg, &mut remaining)? {
+            options.reports.push(Report::parse(value)?);
+            continue;
+        }
+        if let Some(value) = flag_value("--log-level", arg, &mut remaining)? {
+            options.log_level = Some(trace::parse_level(value)?);
+            continue;
+        }
+        if let Some(value) = flag_value("--trace-chrome", arg, &mut remaining)? {
+            options.trace_chrome = Some(PathBuf::from(value));
+            continue;
+        }
+        let mut context_given = false;
+        for (short, long, lines) in [("-A", "--after-context", &mut after_context), ("-B", "--before-context", &mut before_context),
+            ("-C", "--context", &mut context)] {
+            let value = match flag_value(short, arg, &mut remaining)? {
+                Some(value) => Some(value),
+                None => flag_value(long, arg, &mut remaining)?,
+            };
+            if let Some(value) = value {
+                *lines = Some(value.parse::<usize>().map_err(|_| format!("{} expects a number of lines, got '{}'", long, value))?);
+                context_given = true;
+            }
+        }
+        if context_given {
+            continue;
+        }
+        let threads = match flag_value("-j", arg, &mut remaining)? {
+            Some(value) => Some(value),
+            None => flag_value("--threads", arg, &mut remaining)?,
+        };
+        if let Some(value) = threads {
+            options.threads = Some(value.parse().map_err(|_| {
+                format!("--threads expects a number of threads, got '{}'", value)
+            })?);
+            continue;
+        }
+        if let Some(value) = flag_value("--validate-jobs", arg, &mut remaining)? {
+            options.validate_jobs = value.parse().ok().filter(|&jobs| jobs > 0).ok_or_else(|| {
+                format!("--validate-jobs expects a number of requests at a time, got '{}'", value)
+            })?;
+            continue;
+        }
+        if let Some(value) = flag_value("--retries", arg, &mut remaining)? {
+            options.retries = value.parse().map_err(|_| {
+                format!("--retries expects a number of retries, got '{}'", value)
+            })?;
+            continue;
+        }
+        if let Some(value) = flag_value("--hook", arg, &mut remaining)? {
+            options.hook = Some(value.to_string());
+            continue;
+        }
+        if let Some(value) = flag_value("--json-path", arg, &mut remaining)? {
+            options.json_paths.push(JsonPath::parse(value)?);
+            options.json_values = true;
+            continue;
+        }
+        if let Some(value) = flag_value("--yaml-path", arg, &mut remaining)? {
+            options.yaml_paths.push(JsonPath::parse(value)?);
+            options.yaml_values = true;
+            continue;
+        }
+        if let Some(value) = flag_value("--toml-key", arg, &mut remaining)? {
+            options.toml_keys.push(TomlKey::parse(value)?);
+            continue;
+        }
+        if let Some(value) = flag_value("--front-matter", arg, &mut remaining)? {
+            options.front_matter = Some(FrontMatter::parse(value)?);
+            continue;
+        }
+        if let Some(value) = flag_value("--config-key", arg, &mut remaining)? {
+            options.config_keys.push(KeyPattern::parse(value)?);
+            options.config_values = true;
+            continue;
+        }
+        if let Some(value) = flag_value("--xml-scope", arg, &mut remaining)? {
+            options.xml_scopes.push(XmlScope::parse(value)?);
+            continue;
+        }
+        if let Some(value) = flag_value("--plugin", arg, &mut remaining)? {
+            options.plugins.push(PathBuf::from(value));
+            continue;
+        }
+        if let Some(value) = flag_value("--cache", arg, &mut remaining)? {
+            options.cache = Some(PathBuf::from(value));
+            continue;
+        }
+        if let Some(value) = flag_value("--exclude", arg, &mut remaining)? {
+            options.excludes.push(Glob::new(value).map_err(|e| format!("Invalid --exclude glob '{}': {}", value, e))?);
+            continue;
+        }
+        // Read before parsing, by with_config
+        if flag_value("--profile", arg, &mut remaining)?.is_some() {
+            continue;
+        }
+        if let Some(value) = flag_value("--include", arg, &mut remaining)? {
+            options.includes.push(Glob::new(value).map_err(|e| format!("Invalid --include glob '{}': {}", value, e))?);
+            continue;
+        }
+        if let Some(value) = flag_value("--package", arg, &mut remaining)? {
+            match &mut options.members {
+                Some(Members::All) => return Err("--package cannot be combined with --workspace, which walks every member".to_string()),
+                Some(Members::Named(names)) => names.push(value.to_string()),
+                None => options.members = Some(Members::Named(vec![value.to_string()])),
+            }
+            continue;
+        }
+        if arg == "--workspace" {
+            if matches!(options.members, Some(Members::Named(_))) {
+                return Err("--package cannot be combined with --workspace, which walks every member".to_string());
+            }
+            options.members = Some(Members::All);
+            continue;
+        }
+        if let Some(value) = flag_value("--forbid", arg, &mut remaining)? {
+            options.forbidden.push(std::fs::canonicalize(value).unwrap_or_else(|_| PathBuf::from(value)));
+            continue;
+        }
+        if let Some(value) = flag_value("--text-ext", arg, &mut remaining)? {
+            for extension in value.split(',') {
+                let extension = extension.trim().trim_start_matches('.');
+                if extension.is_empty() {
+                    return Err(format!("--text-ext expects comma-separated extensions, got '{}'", value));
+                }
+                options.text_extensions.push(extension.to_ascii_lowercase());
+            }
+            continue;
+        }
+        if let Some(value) = flag_value("--throttle", arg, &mut remaining)? {
+            options.throttle = Some(throttle::parse_rate(value)?);
+            continue;
+        }
+        if let Some(value) = flag_value("--max-memory", arg, &mut remaining)? {
+            options.max_memory = Some(parse_size(value)?);
+            continue;
+        }
+        if let Some(value) = flag_value("--max-filesize", arg, &mut remaining)? {
+            options.max_filesize = Some(parse_size(value)?);
+            continue;
+        }
+        if let Some(value) = flag_value("--max-line-length", arg, &mut remaining)? {
+            options.max_line_length = Some(usize::try_from(parse_size(value)?).unwrap_or(usize::MAX));
+            continue;
+        }
+        if let Some(value) = flag_value("--color", arg, &mut remaining)? {
+            options.color = ColorChoice::parse(value)?;
+            continue;
+        }
+        if let Some(value) = flag_value("--theme", arg, &mut remaining)? {
+            options.theme = Theme::parse(value)?;
+            continue;
+        }
+        if let Some(value) = flag_value("--encoding", arg, &mut remaining)? {
+            options.encoding = Encoding::parse(value)?;
+            continue;
+        }
+        if let Some(value) = flag_value("--eol", arg, &mut remaining)? {
+            options.eol = EolPolicy::parse(value)?;
+            continue;
+        }
+        if let Some(value) = flag_value("--rules", arg, &mut remaining)? {
+            rules = Some(value);
+            continue;
+        }
+        if let Some(value) = flag_value("--old", arg, &mut remaining)? {
+            old_header = Some(value);
+            continue;
+        }
+        if let Some(value) = flag_value("--new", arg, &mut remaining)? {
+            new_header = Some(value);
+            continue;
+        }
+        if let Some(value) = flag_value("--var", arg, &mut remaining)? {
+            vars.push(scaffold::parse_var(value)?);
+            continue;
+        }
+        if let Some(value) = flag_value("--kinds", arg, &mut remaining)? {
+            kinds = Some(Redaction::parse_list(value)?);
+            continue;
+        }
+        if let Some(value) = flag_value("--explain", arg, &mut remaining)? {
+            explain_path = Some(value);
+            continue;
+        }
+        match arg.as_str() {
+            "-c" | "--count" => options.count = true,
+            "--print0-modified" => options.print0_modified = true,
+            "--why-skipped" => options.why_skipped = true,
+            "--stats" => options.stats = true,
+            "--show-skipped" => options.show_skipped = true,
+            "-a" | "--text" => options.text = true,
+            "--archives" => options.archives = true,
+            "--gzip" => options.gzip = true,
+            "--office" => options.office = true,
+            "--json-values" => options.json_values = true,
+            "--yaml-values" => options.yaml_values = true,
+            "--md-links" => options.md_links = true,
+            "--includes" => options.include_paths = true,
+            "--urls" => options.urls = true,
+            "--manifests" => manifests = true,
+            "--validate" => {
+                options.urls = true;
+                options.validate_urls = true;
+            }
+            "--po" => options.po = true,
+            "--po-fuzzy" => {
+                options.po = true;
+                options.po_fuzzy = true;
+            }
+            "--config-values" => options.config_values = true,
+            "--check-links" => {
+                options.md_links = true;
+                options.check_links = true;
+            }
+            "--no-editorconfig" => options.editorconfig = false,
+            "--no-gitattributes" => options.gitattributes = false,
+            "-n" | "--dry-run" => options.dry_run = true,
+            "--diff" => options.diff = true,
+            "--no-pager" => options.pager = false,
+            "--fix" => fix = true,
+            "--explain-conflicts" => explain_conflicts = true,
+            "--invisible" => invisible = true,
+            "--no-secret-warning" => options.secret_warning = false,
+            "--check-rust" => options.check_rust = true,
+            "--confusables" => options.confusables = true,
+            "--nice" => options.nice = true,
+            "--unsafe-vcs-dirs" => options.unsafe_vcs_dirs = true,
+            "--strict" => options.strict = true,
+            "--fsync" => options.fsync = true,
+            "--force" => options.force = true,
+            "--pick" => options.pick = true,
+            "--everything" => options.everything = true,
+            "--stdin" => options.stdin = true,
+            "--estimate" => options.estimate = true,
+            "--sparse" => options.sparse = true,
+            "--streams" => options.streams = true,
+            // Read before parsing, by with_config
+            "--no-config" => {}
+            "--help" => return Ok(CommandArgs::Help),
+            "--version" => return Ok(CommandArgs::Version),
+            // - alone stands for stdin, or is find or replace text
+            flag if flag.starts_with('-') && flag != "-" => return Err(unknown_flag(flag)),
+            _ => positionals.push(arg),
+        }
+    }
+
+    // A - after the find and replace text, or after a command that needs
+    // neither, filters stdin, as --stdin does
+    let filters_stdin = match positionals[..] {
+        [_, _, "-"] => true,
+        [command, "-"] => before_dashes != Some(0) && matches!(command, "clean" | "redact" | "license" | "bump-year" | "scaffold"),
+        _ => false,
+    };
+    if filters_stdin {
+        positionals.pop();
+        options.stdin = true;
+    }
+    // Commands are only named before --, so fr -- index new replaces the
+    // word index
+    let commands: &[&str] = if before_dashes == Some(0) { &[] } else { &positionals };
+
+    if before_context.is_some() || after_context.is_some() || context.is_some() {
+        options.context = Some(Context {
+            before: before_context.or(context).unwrap_or(0),
+            after: after_context.or(context).unwrap_or(0),
+        });
+    }
+
+    if let Some(path) = explain_path {
+        if positionals.len() > 2 {
+            return Err(format!("--explain takes a path and optionally the find and replace text, got {} arguments",
+                positionals.len()));
+        }
+        return Ok(CommandArgs::Explain { path, find_text: positionals.first().copied(), options });
+    }
+
+    if commands == ["lint"] {
+        let Some(rules) = rules else {
+            return Err("fr lint needs a rules file, given with --rules".to_string());
+        };
+        reject_reporting("lint", &options)?;
+        if fix && explain_conflicts {
+            return Err("--explain-conflicts reports what --fix would do, so can't be combined with it".to_string());
+        }
+        return Ok(CommandArgs::Lint { rules: Path::new(rules), fix, explain_conflicts, options });
+    }
+    if commands == ["doctor"] {
+        let Some(rules) = rules else {
+            return Err("fr doctor needs a rules file, given with --rules".to_string());
+        };
+        if fix || explain_conflicts {
+            return Err("--fix and --explain-conflicts only apply to fr lint".to_string());
+        }
+        return Ok(CommandArgs::Doctor { rules: Path::new(rules) });
+    }
+    if rules.is_some() || fix || explain_conflicts {
+        return Err("--rules, --fix and --explain-conflicts only apply to fr lint and fr doctor".to_string());
+    }
+
+    // These find what to replace without a find text to rename paths by
+    if options.everything && matches!(commands, ["clean"] | ["redact"] | ["license"] | ["bump-year"] | ["scaffold"]) {
+        return Err(format!("--everything cannot be combined with fr {}", commands[0]));
+    }
+
+    if commands == ["clean"] {
+        if !invisible {
+            return Err("fr clean needs something to clean, such as --invisible".to_string());
+        }
+        reject_reporting("clean", &options)?;
+        options.invisible = true;
+        return Ok(CommandArgs::FindReplace { find_text: "", replace_text: "", options });
+    }
+    if invisible {
+        return Err("--invisible only applies to fr clean".to_string());
+    }
+
+    if commands == ["redact"] {
+        // Reports and logs go by the find text, which redacting doesn't have
+        reject_reporting("redact", &options)?;
+        options.redact = kinds.unwrap_or_else(|| redact::ALL.to_vec());
+        return Ok(CommandArgs::FindReplace { find_text: "", replace_text: "", options });
+    }
+    if kinds.is_some() {
+        return Err("--kinds only applies to fr redact".to_string());
+    }
+
+    if commands == ["license"] {
+        let (Some(old_header), Some(new_header)) = (old_header, new_header) else {
+            return Err("fr license needs the old and new headers, given with --old and --new".to_string());
+        };
+        reject_reporting("license", &options)?;
+        let read = |path: &str| std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e));
+        options.license = Some(Arc::new(License::new(&read(old_header)?, &read(new_header)?)?));
+        return Ok(CommandArgs::FindReplace { find_text: "", replace_text: "", options });
+    }
+    if old_header.is_some() || new_header.is_some() {
+        return Err("--old and --new only apply to fr license".to_string());
+    }
+
+    if commands == ["bump-year"] {
+        reject_reporting("bump-year", &options)?;
+        options.bump_year = Some(license::current_year());
+        return Ok(CommandArgs::FindReplace { find_text: "", replace_text: "", options });
+    }
+
+    if commands == ["scaffold"] {
+        if vars.is_empty() {
+            return Err("fr scaffold needs the placeholders' values, given with --var NAME=value".to_string());
+        }
+        reject_reporting("scaffold", &options)?;
+        // Files are renamed where they are, which a copy or a plan wouldn't follow
+        if options.out_dir.is_some() || options.save_plan.is_some() || options.export_script.is_some() {
+            return Err("fr scaffold cannot be combined with --out-dir, --save-plan or --export-script".to_string());
+        }
+        options.placeholders = vars;
+        return Ok(CommandArgs::FindReplace { find_text: "", replace_text: "", options });
+    }
+    if !vars.is_empty() {
+        return Err("--var only applies to fr scaffold".to_string());
+    }
+
+    match commands {
+        ["completions", "profiles"] => return Ok(CommandArgs::Profiles),
+        ["completions", shell] => return Ok(CommandArgs::Completions { shell: Shell::parse(shell)? }),
+        ["completions", ..] => return Err("fr completions expects a shell: bash, zsh, fish or powershell".to_string()),
+        _ => {}
+    }
+
+    if commands == ["index"] {
+        return Ok(CommandArgs::Index { options });
+    }
+
+    if commands == ["serve"] {
+        // Anything else on stdout would get mixed up with the responses
+        if options.count || options.format.is_some() || options.print0_modified || options.max_memory.is_some() {
+            return Err("fr serve cannot be combined with --count, --format, --print0-modified or --max-memory".to_string());
+        }
+        return Ok(CommandArgs::Serve { options });
+    }
+
+    // Replacing the text apply-rg in stdin takes a - after the same arguments
+    if let (&["apply-rg", replace_text], false) = (commands, options.stdin) {
+        if options.count || options.format.is_some() || options.diff || options.log.is_some() || !options.reports.is_empty() {
+            return Err("fr apply-rg cannot be combined with --count, --format, --diff, --log or --report".to_string());
+        }
+        return Ok(CommandArgs::ApplyRg { replace_text, options });
+    }
+
+    // Replacing the word apply in stdin takes a - after the same arguments
+    if let (&["apply", plan], false) = (commands, options.stdin) {
+        if options.count || options.format.is_some() || options.diff || options.log.is_some() || !options.reports.is_empty() {
+            return Err("fr apply cannot be combined with --count, --format, --diff, --log or --report".to_string());
+        }
+        return Ok(CommandArgs::Apply { plan: Path::new(plan), options });
+    }
+
+    // Three arguments starting with mv move a module rather than name a
+    // remote tree
+    if let ["mv", old_path, new_path] = commands[..] {
+        if old_path.is_empty() || new_path.is_empty() {
+            return Err("fr mv expects the module's old and new paths, as in: fr mv old::module new::module".to_string());
+        }
+        // A Rust path can only be moved to another, or the files won't parse
+        if (old_path.contains("::") || new_path.contains("::")) && !(modules::is_rust_path(old_path) && modules::is_rust_path(new_path)) {
+            return Err(format!("fr mv can't move {} to {}, since they aren't both Rust paths like crate::old::module", old_path, new_path));
+        }
+        options.module_paths = true;
+        return Ok(CommandArgs::FindReplace { find_text: old_path, replace_text: new_path, options });
+    }
+
+    if let ["bump-version", old_version, new_version] = commands[..] {
+        if !version::is_version(old_version) || !version::is_version(new_version) {
+            return Err(format!("fr bump-version expects the old and new versions, such as 1.2.3 and 1.3.0, got {} and {}",
+                old_version, new_version));
+        }
+        options.versions = true;
+        options.version_manifests = manifests;
+        return Ok(CommandArgs::FindReplace { find_text: old_version, replace_text: new_version, options });
+    }
+    if manifests {
+        return Err("--manifests only applies to fr bump-version".to_string());
+    }
+
+    // Any other run takes two arguments, so three starting with bench can only
+    // be a benchmark
+    if let ["bench", find_text, replace_text] = commands[..] {
+        if find_text.is_empty() {
+            return Err("Find text cannot be empty".to_string());
+        }
+        return Ok(CommandArgs::Bench { find_text, replace_text, options });
+    }
+
+    if options.count && options.format.is_some() {
+        return Err("--count cannot be combined with --format".to_string());
+    }
+
+    if options.export_script.is_some() {
+        if options.count || options.format.is_some() || options.diff || options.pick || options.log.is_some() || !options.reports.is_empty() {
+            return Err("--export-script cannot be combined with --count, --format, --diff, --pick, --log or --report".to_string());
+        }
+        if !options.is_plain() {
+            return Err("--export-script can only export replacements of the find text in UTF-8 files, \
+                not ones changing encodings, line endings, values, archives or look-alikes, or using plugins".to_string());
+        }
+    }
+
+    if options.save_plan.is_some() {
+        if options.count || options.format.is_some() || options.diff || options.pick || options.log.is_some() || !options.reports.is_empty()
+            || options.export_script.is_some() || options.out_dir.is_some() {
+            return Err("--save-plan cannot be combined with --count, --format, --diff, --pick, --log, --report, --export-script or --out-dir".to_string());
+        }
+        if !options.is_plain() {
+            return Err("--save-plan can only plan replacements of the find text in UTF-8 files, \
+                not ones changing encodings, line endings, values, archives or look-alikes, or using plugins".to_string());
+        }
+    }
+
+    if options.context.is_some() && (options.count || options.format.is_some()) {
+        return Err("-A, -B and -C show lines around matches in --dry-run and --diff output, so can't be combined with --count or --format".to_string());
+    }
+
+    if options.out_dir.is_some() && (options.count || options.format.is_some() || options.export_script.is_some()) {
+        return Err("--out-dir cannot be combined with --count, --format or --export-script, which don't write anything".to_string());
+    }
+
+    // Renames and symlinks aren't in a plan, a script or a copy of the files,
+    // and undoing needs every file it rewrites
+    if options.everything && (options.count || options.format.is_some() || options.pick || options.save_plan.is_some()
+        || options.export_script.is_some() || options.out_dir.is_some()) {
+        return Err("--everything cannot be combined with --count, --format, --pick, --save-plan, --export-script or --out-dir".to_string());
+    }
+
+    if options.pick && (options.count || options.format.is_some()) {
+        return Err("--pick cannot be combined with --count or --format".to_string());
+    }
+
+    if options.print0_modified && (options.count || options.format.is_some()) {
+        return Err("--print0-modified cannot be combined with --count or --format".to_string());
+    }
+
+    if options.modified_list.is_some() && (options.count || options.format.is_some()) {
+        return Err("--modified-list cannot be combined with --count or --format".to_string());
+    }
+
+    if options.log.is_some() && (options.count || options.format.is_some()) {
+        return Err("--log cannot be combined with --count or --format".to_string());
+    }
+
+    if !options.reports.is_empty() && (options.count || options.format.is_some()) {
+        return Err("--report cannot be combined with --count or --format".to_string());
+    }
+
+    if options.journal.is_some() && (options.count || options.format.is_some()) {
+        return Err("--journal cannot be combined with --count or --format".to_string());
+    }
+
+    if let ["journal", journal] = commands[..] {
+        let journal = journal::parse(journal)?;
+        return Ok(CommandArgs::Journal { journal, query: journal_query.unwrap_or(JournalQuery::Runs), options });
+    }
+    if journal_query.is_some() {
+        return Err("--touching, --run and --undo only apply to fr journal".to_string());
+    }
+    if options.journal.is_some() && options.out_dir.is_some() {
+        return Err("--journal cannot be combined with --out-dir, which leaves the files it would record as they were".to_string());
+    }
+
+    // Counting, listing, logging and reports go by the bytes of the find text
+    if options.confusables && (options.count || options.format.is_some() || options.log.is_some() || !options.reports.is_empty()) {
+        return Err("--confusables cannot be combined with --count, --format, --log or --report".to_string());
+    }
+    
+    // A third argument can only be a remote tree
+    let remote = match positionals[..] {
+        [_, _, url] => Remote::parse(url)?,
+        _ => None,
+    };
+    if let Some(remote) = remote {
+        if positionals[0].is_empty() {
+            return Err("Find text cannot be empty".to_string());
+        }
+        if options.count || options.format.is_some() || options.diff || options.log.is_some() || options.pick || options.estimate
+            || options.export_script.is_some() || options.save_plan.is_some() {
+            return Err("--count, --format, --diff, --log, --pick, --estimate, --export-script and --save-plan aren't supported on a remote tree".to_string());
+        }
+        if options.out_dir.is_some() {
+            return Err("--out-dir isn't supported on a remote tree".to_string());
+        }
+        if options.members.is_some() {
+            return Err("--workspace and --package aren't supported on a remote tree".to_string());
+        }
+        if options.context.is_some() {
+            return Err("-A, -B and -C aren't supported on a remote tree".to_string());
+        }
+        return Ok(CommandArgs::Remote { find_text: positionals[0], replace_text: positionals[1], remote, options });
+    }
+
+    if positionals.len() != 2 {
+        return Err(format!("Expected the find text and the replace text, got {} argument{}\nRun fr --help for usage",
+            positionals.len(), if positionals.len() == 1 { "" } else { "s" }));
+    }
+
+    if positionals[0].is_empty() {
+        return Err("Find text cannot be empty".to_string());
+    }
+
+    Ok(CommandArgs::FindReplace {
+        find_text: positionals[0],
+        replace_text: positionals[1],
+        options,
+    })
+}
+
+/// Whether text is piped or redirected to stdin, for `fr apply-rg` to check it
+/// has something to read. A terminal doesn't count, and on Unix neither does a
+/// device such as `/dev/null`, which is what stdin is when fr is run from cron,
+/// xargs or CI with nothing to read.
+fn stdin_is_input() -> bool {
+    let stdin = io::stdin();
+    if stdin.is_terminal() {
+        return false;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::fd::AsFd;
+        use std::os::unix::fs::FileTypeExt;
+        let Ok(file) = stdin.as_fd().try_clone_to_owned().map(std::fs::File::from) else {
+            return false;
+        };
+        file.metadata().is_ok_and(|metadata| {
+            let file_type = metadata.file_type();
+            file_type.is_file() || file_type.is_fifo() || file_type.is_socket()
+        })
+    }
+    #[cfg(not(unix))]
+    {
+        true
+    }
+}
+
+/// Warns before replace text that looks like a credential is written into a
+/// tree, unless `--no-secret-warning` is given or nothing is written.
+fn warn_if_secret(replace_text: &str, options: &Options) {
+    if !options.secret_warning || options.dry_run {
+        return;
+    }
+    if let Some(kind) = redact::secret_kind(replace_text) {
+        eprintln!("Warning: the replace text looks like {}, which is about to be written into every matching file. \
+            Pass --no-secret-warning if this is intended", kind);
+    }
+}
+
+/// Something about the find and replace text that is likely a mistake
+#[derive(Debug, PartialEq)]
+enum PatternProblem {
+    /// The replace text is the find text, so nothing would change
+    Unchanged,
+    /// The replace text contains the find text, so running fr again would
+    /// replace it again
+    Recursive,
+}
+
+/// Checks the find and replace text for a likely mistake, unless `--force` is
+/// given. Matching by spans rather than by the find text, as `--confusables`
+/// does, can change files even when the two are the same.
+fn pattern_problem(find_text: &str, replace_text: &str, options: &Options) -> Option<PatternProblem> {
+    if options.force || options.matches_spans() || find_text.is_empty() {
+        None
+    } else if find_text == replace_text {
+        Some(PatternProblem::Unchanged)
+    } else if replace_text.contains(find_text) {
+        Some(PatternProblem::Recursive)
+    } else {
+        None
+    }
+}
+
+/// Warns about a likely mistake in the find and replace text.
+///
+/// # Returns
+///
+/// * `bool` - Whether the run should go ahead
+fn check_pattern(find_text: &str, replace_text: &str, options: &Options) -> bool {
+    match pattern_problem(find_text, replace_text, options) {
+        Some(PatternProblem::Unchanged) => {
+            eprintln!("Warning: the find and replace text are the same, so nothing would change. Pass --force to run anyway");
+            false
+        }
+        Some(PatternProblem::Recursive) => {
+            eprintln!("Warning: the replace text contains the find text, so running fr again would replace it again. \
+                Pass --force if this is intended");
+            true
+        }
+        None => true,
+    }
+}
+
+/// Applies `--throttle`, `--nice` and `--validate-jobs`, before any threads
+/// are started.
+fn limit_resources(options: &Options) -> Result<(), String> {
+    if options.validate_urls {
+        urls::start(options.validate_jobs).map_err(|e| e.to_string())?;
+    }
+    if let Some(bytes_per_second) = options.throttle {
+        throttle::start(bytes_per_second);
+    }
+    if options.nice {
+        throttle::lower_priority().map_err(|e| format!("Failed to lower priority: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Whether the arguments are a single flag that must be given alone, such as
+/// `--help`, which defaults would stop being recognized.
+fn is_plain(args: &[String]) -> bool {
+    matches!(args.get(1..), Some([arg]) if arg == "--help" || arg == "--version" || arg == "--generate-manpage")
+}
+
+/// Puts the flags in `FR_OPTS` before those on the command line, so that
+/// those on the command line override them.
+///
+/// # Arguments
+///
+/// * `args` - The command line arguments
+///
+/// # Returns
+///
+/// * `Result<Vec<String>, String>` - The arguments, or an error if `FR_OPTS`
+///   can't be split into arguments
+fn with_opts(args: Vec<String>) -> Result<Vec<String>, String> {
+    let Some(opts) = env::var_os(config::OPTS_ENV) else {
+        return Ok(args);
+    };
+    if is_plain(&args) {
+        return Ok(args);
+    }
+    let opts = config::split_words(&opts.to_string_lossy())?;
+    let mut args = args.into_iter();
+    Ok(args.next().into_iter().chain(opts).chain(args).collect())
+}
+
+/// Works out how many files and replacements a run would make, and asks on
+/// the terminal whether to go on, for `--estimate`.
+///
+/// # Returns
+///
+/// * `Result<bool, String>` - Whether to go on with the run, which is true if
+///   nothing would be modified, as there is nothing to ask about
+fn confirm_estimate(starting_directory: &Path, find_text: &str, replace_text: &str, options: &Options) -> Result<bool, String> {
+    let summary = walk_find_replace(starting_directory, find_text, replace_text, &options.planning())
+        .map_err(|e| format!("Failed to start: {}", e))?;
+    if summary.modified.is_empty() {
+        return Ok(true);
+    }
+    let (files, replacements) = (summary.modified.len(), summary.modified.iter().map(|file| file.replacements).sum::<usize>());
+    eprint!("This will modify {} file{} with {} replacement{} \u{2014} continue? [y/N] ", thousands(files),
+        if files == 1 { "" } else { "s" }, thousands(replacements), if replacements == 1 { "" } else { "s" });
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).map_err(|e| format!("Failed to read the answer: {}", e))?;
+    Ok(matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Replaces the find text in the files, the targets of symlinks and the names
+/// of files and directories, for `--everything`, printing in a dry run each
+/// path that would be renamed and symlink that would be retargeted.
+///
+/// # Returns
+///
+/// * `Result<(), String>` - An error if anything failed, in which case every
+///   change was undone
+fn rename_everything(starting_directory: &Path, find_text: &str, replace_text: &str, options: &Options) -> Result<(), String> {
+    let outcome = everything::run(starting_directory, find_text, replace_text, options)
+        .map_err(|e| format!("Failed to start: {}", e))?;
+    if let Some(message) = skipped_files_message(&outcome.summary, options.show_skipped) {
+        eprint!("{}", message);
+    }
+    let relative = |path: &Path| path.strip_prefix(starting_directory).unwrap_or(path).display().to_string();
+    if options.dry_run && !options.quiet {
+        for (link, target, new_target) in &outcome.retargeted {
+            println!("Would point {} at {} instead of {}", relative(link), new_target.display(), target.display());
+        }
+        for (old_path, new_path) in &outcome.renamed {
+            println!("Would rename {} to {}", relative(old_path), relative(new_path));
+        }
+    }
+    for report in &options.reports {
+        report.write(&outcome.summary, find_text, replace_text, &options.stdout_painter())
+            .map_err(|e| format!("Failed to write report: {}", e))?;
+    }
+    if let Some(message) = errors_message(&outcome.summary.errors, relative) {
+        eprint!("{}", message);
+    }
+    match outcome.rolled_back {
+        true => Err("Undid every change, as not all of them could be made".to_string()),
+        false => failed(&outcome.summary.errors),
+    }
+}
+
+/// Formats a number with commas between each group of three digits.
+fn thousands(number: usize) -> String {
+    let digits = number.to_string();
+    let groups: Vec<&str> = digits.as_bytes().rchunks(3).rev()
+        .map(|group| std::str::from_utf8(group).unwrap_or_default())
+        .collect();
+    groups.join(",")
+}
+
+/// Puts the flags the user's and the project's configuration files hold, and
+/// those of the profile `--profile` names, before those on the command line,
+/// so that those on the command line override them, unless it has
+/// `--no-config`.
+///
+/// # Arguments
+///
+/// * `args` - The command line arguments
+/// * `starting_directory` - Where fr is run, which the project file is found from
+///
+/// # Returns
+///
+/// * `Result<Vec<String>, String>` - The arguments to parse, or an error if a
+///   configuration file can't be read or has no such profile
+fn with_config(args: Vec<String>, starting_directory: &Path) -> Result<Vec<String>, String> {
+    // Everything after -- is text to find and replace, not flags
+    let flags = || args.iter().skip(1).take_while(|arg| *arg != "--");
+    let mut profile = None;
+    let mut remaining = flags();
+    while let Some(arg) = remaining.next() {
+        if let Some(value) = flag_value("--profile", arg, &mut remaining)? {
+            profile = Some(value);
+        }
+    }
+    if is_plain(&args) {
+        return Ok(args);
+    }
+    if flags().any(|arg| arg == "--no-config") {
+        return match profile {
+            Some(_) => Err("--profile can't be combined with --no-config".to_string()),
+            None => Ok(args),
+        };
+    }
+    let mut flags = Vec::new();
+    let mut profile_flags = None;
+    for path in [config::path(), config::project_path(starting_directory)].into_iter().flatten() {
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+        };
+        let directory = path.parent().unwrap_or(Path::new("."));
+        let (top_level, in_profile) = config::flags(&text, directory, profile).map_err(|e| format!("{}: {}", path.display(), e))?;
+        flags.extend(top_level);
+        if let Some(in_profile) = in_profile {
+            profile_flags.get_or_insert_with(Vec::new).extend(in_profile);
+        }
+    }
+    if let Some(profile) = profile {
+        flags.extend(profile_flags.ok_or_else(|| format!("No configuration file has a [profile.{}] section", profile))?);
+    }
+    let mut args = args.into_iter();
+    Ok(args.next().into_iter().chain(flags).chain(args).collect())
+}
+
+/// Main execution function that sets up and runs the find and replace operation.
+/// 
+/// # Returns
+/// 
+/// * `Result<(), String>` - Ok(()) on success, Err with error message on failure
+pub fn run() -> Result<(), String> {
+    let starting_directory =
+        env::current_dir().map_err(|e| format!("Failed to get current directory: {}", e))?;
+
+    let args: Vec<String> = env::args().collect();
+    // A saved operation already has the flags from the configuration files
+    let mut args = match operation::replay(&args)? {
+        Some(args) => args,
+        None => with_config(with_opts(args)?, &starting_directory)?,
+    };
+    if let Some(op_path) = operation::take_save_op(&mut args)? {
+        // Only an operation that would run is saved
+        parse_arguments(&args)?;
+        operation::save(&op_path, &args[1..]).map_err(|e| format!("Failed to write {}: {}", op_path.display(), e))?;
+        eprintln!("Saved the operation to {}, to replay with fr run {}", op_path.display(), op_path.display());
+        return Ok(());
+    }
+    match parse_arguments(&args)? {
+        CommandArgs::Help => {
+            println!("{}", HELP_MESSAGE);
+            Ok(())
+        }
+        CommandArgs::Version => {
+            println!("fr {}", env!("CARGO_PKG_VERSION"));
+            Ok(())
+        }
+        CommandArgs::Manpage => {
+            print!("{}", manpage::render(HELP_MESSAGE, env!("CARGO_PKG_VERSION")));
+            Ok(())
+        }
+        CommandArgs::Explain { path, find_text, options } => {
+            println!("{}", explain::explain(&starting_directory, Path::new(path), find_text, &options));
+            Ok(())
+        }
+        CommandArgs::Bench { find_text, replace_text, mut options } => {
+            // Everything a run would do except writing
+            options.dry_run = true;
+            options.quiet = true;
+            stats::start();
+            limit_resources(&options)?;
+            walk_find_replace(&starting_directory, find_text, replace_text, &options)
+                .map_err(|e| format!("Failed to start: {}", e))?;
+            print!("{}", stats::bench_report().unwrap_or_default());
+            Ok(())
+        }
+        CommandArgs::Completions { shell } => {
+            print!("{}", completions::script(shell, HELP_MESSAGE));
+            Ok(())
+        }
+        CommandArgs::Profiles => {
+            for path in [config::path(), config::project_path(&starting_directory)].into_iter().flatten() {
+                for name in std::fs::read_to_string(&path).map(|text| config::profile_names(&text)).unwrap_or_default() {
+                    println!("{}", name);
+                }
+            }
+            Ok(())
+        }
+        CommandArgs::Index { options } => {
+            limit_resources(&options)?;
+            let index = Index::build(&starting_directory, &options)
+                .and_then(|index| index.save().map(|()| index))
+                .map_err(|e| format!("Failed to index {}: {}", starting_directory.display(), e))?;
+            println!("Indexed {} file{}", index.file_count(), if index.file_count() == 1 { "" } else { "s" });
+            Ok(())
+        }
+        CommandArgs::Lint { rules: rules_path, fix, explain_conflicts, options } => {
+            let text = std::fs::read_to_string(rules_path)
+                .map_err(|e| format!("Failed to read {}: {}", rules_path.display(), e))?;
+            let rules = lint::parse_rules(&text).map_err(|e| format!("{}: {}", rules_path.display(), e))?;
+            limit_resources(&options)?;
+            if explain_conflicts {
+                let conflicts = lint::explain_conflicts(&starting_directory, &rules, rules_path, &options)
+                    .map_err(|e| format!("Failed to start: {}", e))?;
+                for conflict in &conflicts {
+                    println!("{}", conflict.describe(&rules));
+                }
+                let files = conflicts.iter().map(|conflict| &conflict.path).collect::<std::collections::BTreeSet<_>>().len();
+                return match conflicts.len() {
+                    0 => Ok(()),
+                    count => Err(format!("Found {} conflict{} between rules in {} file{}", count, if count == 1 { "" } else { "s" },
+                        files, if files == 1 { "" } else { "s" })),
+                };
+            }
+            if fix {
+                let summary = lint::fix(&starting_directory, &rules, rules_path, &options)
+                    .map_err(|e| format!("Failed to start: {}", e))?;
+                let replacements: usize = summary.modified.iter().map(|file| file.replacements).sum();
+                eprintln!("{} {} term{} in {} file{}", if options.dry_run { "Would fix" } else { "Fixed" },
+                    replacements, if replacements == 1 { "" } else { "s" },
+                    summary.modified.len(), if summary.modified.len() == 1 { "" } else { "s" });
+                if options.dry_run {
+                    return Ok(());
+                }
+            }
+            let violations = lint::lint(&starting_directory, &rules, rules_path, &options)
+                .map_err(|e| format!("Failed to start: {}", e))?;
+            let painter = options.stdout_painter();
+            for violation in &violations {
+                let rule = &rules[violation.rule];
+                let suggestion = if rule.suggestion.is_empty() { String::new() } else { format!(", use {:?}", rule.suggestion) };
+                println!("{}:{}:{}: {:?} is forbidden{}", painter.paint(Role::Path, &violation.path.display().to_string()),
+                    violation.line, violation.column, rule.term, suggestion);
+            }
+            let files = violations.iter().map(|violation| &violation.path).collect::<std::collections::BTreeSet<_>>().len();
+            match violations.len() {
+                0 => Ok(()),
+                count => Err(format!("Found {} forbidden term{} in {} file{}", count, if count == 1 { "" } else { "s" },
+                    files, if files == 1 { "" } else { "s" })),
+            }
+        }
+        CommandArgs::Doctor { rules: rules_path } => {
+            let text = std::fs::read_to_string(rules_path)
+                .map_err(|e| format!("Failed to read {}: {}", rules_path.display(), e))?;
+            let rules = lint::parse_rules(&text).map_err(|e| format!("{}: {}", rules_path.display(), e))?;
+            let findings = doctor::check(&rules);
+            for finding in &findings {
+                println!("{}: {}", rules_path.display(), doctor::describe(finding, &rules));
+            }
+            match findings.len() {
+                0 => Ok(()),
+                count => Err(format!("Found {} problem{} in {}", count, if count == 1 { "" } else { "s" }, rules_path.display())),
+            }
+        }
+        CommandArgs::ApplyRg { replace_text, options } => {
+            if !stdin_is_input() {
+                return Err("fr apply-rg reads the output of rg --json from stdin, as in rg --json foo | fr apply-rg bar".to_string());
+            }
+            let files = ripgrep::parse(io::stdin().lock())?;
+            let summary = ripgrep::apply(&starting_directory, files, replace_text, options.dry_run, options.fsync, options.retries);
+            let painter = options.stdout_painter();
+            for file in summary.modified.iter().filter(|_| options.dry_run && !options.quiet) {
+                println!("Would modify {} ({} replacement{})", painter.paint(Role::Path, &file.path.display().to_string()),
+                    file.replacements, if file.replacements == 1 { "" } else { "s" });
+            }
+            if let Some(message) = errors_message(&summary.errors, |path| path.display().to_string()) {
+                eprint!("{}", message);
+            }
+            failed(&summary.errors)
+        }
+        CommandArgs::Apply { plan: plan_path, options } => {
+            let text = std::fs::read_to_string(plan_path).map_err(|e| format!("Failed to read {}: {}", plan_path.display(), e))?;
+            let files = plan::decode(&text).map_err(|e| format!("{} {}", plan_path.display(), e))?;
+            let (summary, drifted) = plan::apply(&starting_directory, files, options.dry_run, options.fsync, options.retries);
+            print_applied(&summary, &drifted, "the plan was made", &options);
+            match drifted.len() {
+                0 => failed(&summary.errors),
+                count => Err(format!("{} file{} changed since the plan was made; make the plan again to change {}", count,
+                    if count == 1 { "" } else { "s" }, if count == 1 { "it" } else { "them" })),
+            }
+        }
+        CommandArgs::Journal { journal: journal_path, query, options } => {
+            let read_error = |e: io::Error| format!("Failed to read {}: {}", journal_path.display(), e);
+            let run = |id: u64| {
+                journal::runs(&journal_path, None).map_err(read_error)?.into_iter().find(|run| run.id == id)
+                    .ok_or_else(|| format!("{} has no run {}", journal_path.display(), id))
+            };
+            match query {
+                JournalQuery::Runs | JournalQuery::Touching(_) => {
+                    let touching = match query {
+                        JournalQuery::Touching(path) => Some(std::path::absolute(path).map_err(|e| format!("Failed to find {}: {}", path, e))?),
+                        _ => None,
+                    };
+                    for run in journal::runs(&journal_path, touching.as_deref()).map_err(read_error)? {
+                        println!("{}", journal::describe(&run));
+                    }
+                }
+                JournalQuery::Edits(id) => {
+                    for file in journal::files(&journal_path, run(id)?.id).map_err(read_error)? {
+                        for edit in &file.edits {
+                            println!("{}:{}: {:?} -> {:?}", file.path.display(), edit.start.map_or("?".to_string(), |start| start.to_string()),
+                                edit.before, edit.after);
+                        }
+                    }
+                }
+                JournalQuery::Undo(id) => {
+                    let run = run(id)?;
+                    let files = journal::files(&journal_path, run.id).map_err(read_error)?;
+                    let (summary, drifted) = journal::undo(&run, &files, options.dry_run, options.fsync, options.retries);
+                    print_applied(&summary, &drifted, &format!("run {}", id), &options);
+                    if !drifted.is_empty() {
+                        return Err(format!("{} file{} changed since run {}; undo the runs after it first", drifted.len(),
+                            if drifted.len() == 1 { "" } else { "s" }, id));
+                    }
+                    return failed(&summary.errors);
+                }
+            }
+            Ok(())
+        }
+        CommandArgs::Serve { options } => {
+            limit_resources(&options)?;
+            serve::serve(&starting_directory, options, io::stdin().lock(), io::stdout().lock())
+                .map_err(|e| format!("Failed to serve: {}", e))
+        }
+        CommandArgs::Remote { find_text, replace_text, remote, options } => {
+            if !check_pattern(find_text, replace_text, &options) {
+                return Ok(());
+            }
+            limit_resources(&options)?;
+            warn_if_secret(replace_text, &options);
+            let mut file_system = SshFileSystem::connect(remote.clone())
+                .map_err(|e| format!("Failed to connect: {}", e))?;
+            let summary = find_replace_in(&mut file_system, find_text, replace_text, &options)
+                .map_err(|e| format!("Failed to list files: {}", e))?;
+            for file in &summary.modified {
+                if options.dry_run {
+                    println!("Would modify {} ({} replacement{})", remote.display(&file.path), file.replacements,
+                        if file.replacements == 1 { "" } else { "s" });
+                }
+            }
+            if let Some(message) = skipped_files_message(&summary, options.show_skipped) {
+                eprint!("{}", message);
+            }
+            if let Some(message) = errors_message(&summary.errors, |path| remote.display(path)) {
+                eprint!("{}", message);
+            }
+            failed(&summary.errors)
+        }
+        CommandArgs::FindReplace { find_text, replace_text, options } => {
+            let log_level = match options.log_level {
+                Some(level) => Some(level),
+                None => env::var(trace::LOG_LEVEL_ENV).ok().map(|value| trace::parse_level(&value)).transpose()?,
+            };
+            if let Some(level) = log_level {
+                trace::init_logging(level);
+            }
+            if options.trace_chrome.is_some() {
+                trace::start_profiling();
+            }
+            if options.stats {
+                stats::start();
+            }
+            limit_resources(&options)?;
+
+            if options.stdin {
+                if options.count || options.format.is_some() || options.pick || options.export_script.is_some() || options.out_dir.is_some()
+                    || options.save_plan.is_some() || options.everything {
+                    return Err("--count, --format, --pick, --export-script, --save-plan, --out-dir and --everything work on files, and can't be used with - or --stdin".to_string());
+                }
+                let stdout = io::stdout().lock();
+                return find_replace_stream(io::stdin().lock(), BufWriter::new(stdout), find_text, replace_text, &options)
+                    .map(|_| ())
+                    .map_err(|e| format!("Failed to filter stdin: {}", e));
+            }
+
+            if !options.count && options.format.is_none() {
+                if !check_pattern(find_text, replace_text, &options) {
+                    return Ok(());
+                }
+                warn_if_secret(replace_text, &options);
+            }
+            if let Some(plan_path) = &options.save_plan {
+                let dry_run = Options { gzip: false, ..options.planning() };
+                let summary = walk_find_replace(&starting_directory, find_text, replace_text, &dry_run)
+                    .map_err(|e| format!("Failed to start: {}", e))?;
+                let paths: Vec<PathBuf> = summary.modified.iter().map(|file| file.path.clone()).collect();
+                let (files, mut errors) = plan::make(&starting_directory, &paths, find_text, replace_text, options.retries);
+                plan::save(plan_path, find_text, replace_text, &files)?;
+                if !options.quiet {
+                    let replacements: usize = files.iter().map(|file| file.edits.len()).sum();
+                    println!("Saved {} replacement{} in {} file{} to {}, to make with fr apply {}", replacements,
+                        if replacements == 1 { "" } else { "s" }, files.len(), if files.len() == 1 { "" } else { "s" },
+                        plan_path.display(), plan_path.display());
+                }
+                errors.extend(summary.errors);
+                if let Some(message) = errors_message(&errors, |path| path.display().to_string()) {
+                    eprint!("{}", message);
+                }
+                return failed(&errors);
+            }
+            if let Some(kind) = options.export_script {
+                // A script would edit a compressed file as if it were text
+                let dry_run = Options { gzip: false, ..options.planning() };
+                let summary = walk_find_replace(&starting_directory, find_text, replace_text, &dry_run)
+                    .map_err(|e| format!("Failed to start: {}", e))?;
+                let files: Vec<PathBuf> = summary.modified.iter().map(|file| file.path.clone()).collect();
+                print!("{}", script::render(kind, &files, find_text, replace_text)?);
+                if let Some(message) = errors_message(&summary.errors, |path| path.display().to_string()) {
+                    eprint!("{}", message);
+                }
+                return failed(&summary.errors);
+            }
+            // Only a run that writes has anything to ask about
+            let writes = !options.dry_run && !options.count && options.format.is_none();
+            if options.estimate && writes && !confirm_estimate(&starting_directory, find_text, replace_text, &options)? {
+                eprintln!("Nothing was modified");
+                return Ok(());
+            }
+            if options.everything {
+                return rename_everything(&starting_directory, find_text, replace_text, &options);
+            }
+            let mut summary = walk_find_replace(&starting_directory, find_text, replace_text, &options)
+                .map_err(|e| format!("Failed to start: {}", e))?;
+            // Names are filled in once the files' text is, as the text is
+            // found by the names it had
+            if !options.placeholders.is_empty() {
+                let (renamed, errors) = scaffold::rename(&starting_directory, &options);
+                if options.dry_run && !options.quiet {
+                    let relative = |path: &PathBuf| path.strip_prefix(&starting_directory).unwrap_or(path).display().to_string();
+                    for (old_path, new_path) in &renamed {
+                        println!("Would rename {} to {}", relative(old_path), relative(new_path));
+                    }
+                }
+                summary.errors.extend(errors);
+            }
+            if let Some(message) = skipped_files_message(&summary, options.show_skipped) {
+                eprint!("{}", message);
+            }
+            if let Some(report) = stats::report(&starting_directory) {
+                eprint!("{}", report);
+            }
+            if let Some(report) = urls::report() {
+                eprint!("{}", report);
+            }
+            if let Some(trace_path) = &options.trace_chrome {
+                trace::write_chrome_trace(trace_path)
+                    .map_err(|e| format!("Failed to write {}: {}", trace_path.display(), e))?;
+            }
+            if let Some(list_path) = &options.modified_list {
+                let mut contents = Vec::new();
+                for file in &summary.modified {
+                    contents.extend_from_slice(file.path.as_os_str().as_encoded_bytes());
+                    contents.push(b'\n');
+                }
+                write_atomically(list_path, &contents)
+                    .map_err(|e| format!("Failed to write {}: {}", list_path.display(), e))?;
+            }
+            for report in &options.reports {
+                report.write(&summary, find_text, replace_text, &options.stdout_painter())
+                    .map_err(|e| format!("Failed to write report: {}", e))?;
+            }
+            if let Some(message) = errors_message(&summary.errors, |path| path.display().to_string()) {
+                eprint!("{}", message);
+            }
+            match options.strict && !summary.errors.is_empty() {
+                true => Err("Stopped at the first file that could not be processed, as --strict was given".to_string()),
+                false => failed(&summary.errors),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::LevelFilter;
+
+    #[test]
+    fn test_parse_arguments_help() {
+        let args = vec!["fr".to_string(), "--help".to_string()];
+        assert!(matches!(parse_arguments(&args).unwrap(), CommandArgs::Help), "Expected Help variant");
+    }
+
+    #[test]
+    fn test_parse_arguments_find_replace() {
+        let args = vec!["fr".to_string(), "find".to_string(), "replace".to_string()];
+        match parse_arguments(&args).unwrap() {
+            CommandArgs::FindReplace { find_text, replace_text, options } => {
+                assert_eq!(find_text, "find");
+                assert_eq!(replace_text, "replace");
+                assert!(!options.count);
+            }
+            _ => panic!("Expected FindReplace variant"),
+        }
+    }
+
+    #[test]
+    fn test_parse_arguments_invalid() {
+        let test_cases = vec![
+            vec!["fr".to_string()],
+            vec!["fr".to_string(), "find".to_string()],
+        ];
+
+        for args in test_cases {
+            assert!(parse_arguments(&args).is_err(), "Should fail for args: {:?}", args);
+        }
+    }
+
+    #[test]
+    fn test_parse_arguments_replace_text_empty() {
+        let args = vec!["fr".to_string(), "find".to_string(), "".to_string()];
+        assert!(parse_arguments(&args).is_ok(), "Should not fail for empty replace text");
+    }
+
+    #[test]
+    fn test_parse_arguments_count() {
+        for flag in ["-c", "--count"] {
+            let args = vec!["fr".to_string(), flag.to_string(), "find".to_string(), "replace".to_string()];
+            match parse_arguments(&args).unwrap() {
+                CommandArgs::FindReplace { find_text, options, .. } => {
+                    assert_eq!(find_text, "find");
+                    assert!(options.count);
+                }
+                _ => panic!("Expected FindReplace variant"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_arguments_context() {
+        let args: Vec<String> = ["fr", "-n", "-C", "2", "--after-context=5", "a", "b"].iter().map(|arg| arg.to_string()).collect();
+        assert!(matches!(parse_arguments(&args).unwrap(),
+            CommandArgs::FindReplace { options, .. } if options.context == Some(Context { before: 2, after: 5 })));
+        let args: Vec<String> = ["fr", "-B", "1", "a", "b"].iter().map(|arg| arg.to_string()).collect();
+        assert!(matches!(parse_arguments(&args).unwrap(),
+            CommandArgs::FindReplace { options, .. } if options.context == Some(Context { before: 1, after: 0 })));
+        for args in [["fr", "-C", "x", "a", "b"], ["fr", "-C", "1", "--count", "a"]] {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            assert!(parse_arguments(&args).is_err());
+        }
+    }
+
+    #[test]
+    fn test_parse_arguments_redact() {
+        let args: Vec<String> = ["fr", "redact", "--kinds", "email,aws_key"].iter().map(|arg| arg.to_string()).collect();
+        match parse_arguments(&args).unwrap() {
+            CommandArgs::FindReplace { find_text, options, .. } => {
+                assert_eq!(find_text, "");
+                assert_eq!(options.redact, vec![Redaction::Email, Redaction::AwsKey]);
+            }
+            _ => panic!("Expected FindReplace variant"),
+        }
+
+        let args: Vec<String> = ["fr", "redact"].iter().map(|arg| arg.to_string()).collect();
+        assert!(matches!(parse_arguments(&args).unwrap(), CommandArgs::FindReplace { options, .. } if options.redact.len() == 7));
+
+        let args: Vec<String> = ["fr", "--kinds", "email", "a", "b"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_arguments(&args).is_err());
+        let args: Vec<String> = ["fr", "redact", "--count"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_arguments(&args).is_err());
+    }
+
+    #[test]
+    fn test_parse_arguments_separator() {
+        let args: Vec<String> = ["fr", "-n", "--", "-oldflag", "-newflag"].iter().map(|arg| arg.to_string()).collect();
+        match parse_arguments(&args).unwrap() {
+            CommandArgs::FindReplace { find_text, replace_text, options } => {
+                assert_eq!((find_text, replace_text), ("-oldflag", "-newflag"));
+                assert!(options.dry_run);
+            }
+            _ => panic!("Expected FindReplace variant"),
+        }
+
+        // Flags can come after the find and replace text
+        let args: Vec<String> = ["fr", "find", "replace", "--diff"].iter().map(|arg| arg.to_string()).collect();
+        assert!(matches!(parse_arguments(&args).unwrap(), CommandArgs::FindReplace { options, .. } if options.diff));
+    }
+
+    #[test]
+    fn test_parse_arguments_unknown_flag() {
+        let args: Vec<String> = ["fr", "--dryrun", "find", "replace"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_arguments(&args).unwrap_err(), "Unknown flag '--dryrun', did you mean '--dry-run'?");
+        let args: Vec<String> = ["fr", "-oldflag", "-newflag"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_arguments(&args).unwrap_err().contains("fr -- -oldflag new"));
+        let args: Vec<String> = ["fr", "find"].iter().map(|arg| arg.to_string()).collect();
+        assert!(!parse_arguments(&args).unwrap_err().contains("Options:"));
+        let args: Vec<String> = ["fr", "find", "--help"].iter().map(|arg| arg.to_string()).collect();
+        assert!(matches!(parse_arguments(&args).unwrap(), CommandArgs::Help));
+    }
+
+    #[test]
+    fn test_errors_message() {
+        let denied = "Permission denied (os error 13)".to_string();
+        let mut errors: Vec<(PathBuf, String)> = (0..7).map(|i| (PathBuf::from(format!("locked/{}.txt", i)), denied.clone())).collect();
+        errors.push((PathBuf::from("notes.txt"), "Stream did not contain valid UTF-8".to_string()));
+        assert_eq!(errors_message(&errors, |path| path.display().to_string()).unwrap(), "Failed to process 8 files:\n\
+            \x20 permission denied: 7 files\n    locked/0.txt\n    locked/1.txt\n    locked/2.txt\n    locked/3.txt\n    locked/4.txt\n    and 2 more\n\
+            \x20 stream did not contain valid UTF-8: 1 file\n    notes.txt\n");
+        assert_eq!(errors_message(&[], |path| path.display().to_string()), None);
+        assert!(failed(&errors).is_err());
+    }
+
+    #[test]
+    fn test_parse_arguments_completions() {
+        let args: Vec<String> = ["fr", "completions", "fish"].iter().map(|arg| arg.to_string()).collect();
+        assert!(matches!(parse_arguments(&args).unwrap(), CommandArgs::Completions { shell: Shell::Fish }));
+        let args: Vec<String> = ["fr", "completions", "profiles"].iter().map(|arg| arg.to_string()).collect();
+        assert!(matches!(parse_arguments(&args).unwrap(), CommandArgs::Profiles));
+        let args: Vec<String> = ["fr", "completions", "tcsh"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_arguments(&args).is_err());
+        // After -- it is text to replace, like any other
+        let args: Vec<String> = ["fr", "--", "completions", "tcsh"].iter().map(|arg| arg.to_string()).collect();
+        assert!(matches!(parse_arguments(&args).unwrap(), CommandArgs::FindReplace { find_text: "completions", replace_text: "tcsh", .. }));
+        let args: Vec<String> = ["fr", "--", "index", "new"].iter().map(|arg| arg.to_string()).collect();
+        assert!(matches!(parse_arguments(&args).unwrap(), CommandArgs::FindReplace { find_text: "index", .. }));
+    }
+
+    #[test]
+    fn test_completions_cover_every_flag() {
+        let script = completions::script(Shell::Bash, HELP_MESSAGE);
+        for flag in ["--count", "--front-matter", "--no-secret-warning", "--profile", "--rules", "--kinds", "--invisible"] {
+            assert!(script.contains(flag), "{} is missing", flag);
+        }
+        assert!(script.contains("compgen -W \"index serve bench lint doctor run apply journal apply-rg bump-version mv clean completions redact license bump-year scaffold\""));
+    }
+
+    #[test]
+    fn test_parse_arguments_clean() {
+        let args: Vec<String> = ["fr", "clean", "--invisible", "--dry-run"].iter().map(|arg| arg.to_string()).collect();
+        match parse_arguments(&args).unwrap() {
+            CommandArgs::FindReplace { find_text, options, .. } => {
+                assert_eq!(find_text, "");
+                assert!(options.invisible && options.dry_run);
+            }
+            _ => panic!("Expected FindReplace variant"),
+        }
+
+        let args: Vec<String> = ["fr", "clean"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_arguments(&args).is_err());
+        let args: Vec<String> = ["fr", "--invisible", "a", "b"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_arguments(&args).is_err());
+    }
+
+    #[test]
+    fn test_parse_arguments_doctor() {
+        let args: Vec<String> = ["fr", "doctor", "--rules", "rules.toml"].iter().map(|arg| arg.to_string()).collect();
+        assert!(matches!(parse_arguments(&args).unwrap(), CommandArgs::Doctor { rules } if rules == Path::new("rules.toml")));
+        let args: Vec<String> = ["fr", "doctor"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_arguments(&args).is_err());
+        let args: Vec<String> = ["fr", "doctor", "--rules", "rules.toml", "--explain-conflicts"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_arguments(&args).is_err());
+    }
+
+    #[test]
+    fn test_parse_arguments_lint() {
+        let args: Vec<String> = ["fr", "lint", "--rules", "rules.toml", "--explain-conflicts"].iter().map(|arg| arg.to_string()).collect();
+        assert!(matches!(parse_arguments(&args).unwrap(), CommandArgs::Lint { explain_conflicts: true, fix: false, .. }));
+        let args: Vec<String> = ["fr", "lint", "--rules", "rules.toml", "--explain-conflicts", "--fix"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_arguments(&args).is_err());
+    }
+
+    #[test]
+    fn test_parse_arguments_apply_rg() {
+        let args: Vec<String> = ["fr", "apply-rg", "new", "--dry-run"].iter().map(|arg| arg.to_string()).collect();
+        match parse_arguments(&args).unwrap() {
+            CommandArgs::ApplyRg { replace_text, options } => {
+                assert_eq!(replace_text, "new");
+                assert!(options.dry_run);
+            }
+            _ => panic!("Expected ApplyRg variant"),
+        }
+        let args: Vec<String> = ["fr", "apply-rg", "new", "--count"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_arguments(&args).is_err());
+        let args: Vec<String> = ["fr", "--", "apply-rg", "new"].iter().map(|arg| arg.to_string()).collect();
+        assert!(matches!(parse_arguments(&args).unwrap(), CommandArgs::FindReplace { find_text: "apply-rg", replace_text: "new", .. }));
+    }
+
+    #[test]
+    fn test_parse_arguments_pick() {
+        let args: Vec<String> = ["fr", "old", "new", "--pick"].iter().map(|arg| arg.to_string()).collect();
+        assert!(matches!(parse_arguments(&args).unwrap(), CommandArgs::FindReplace { options, .. } if options.pick));
+        let args: Vec<String> = ["fr", "old", "new", "--pick", "--count"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_arguments(&args).is_err());
+    }
+
+    #[test]
+    fn test_thousands() {
+        assert_eq!(thousands(0), "0");
+        assert_eq!(thousands(999), "999");
+        assert_eq!(thousands(1243), "1,243");
+        assert_eq!(thousands(1_000_000), "1,000,000");
+    }
+
+    #[test]
+    fn test_parse_arguments_export_script() {
+        let args: Vec<String> = ["fr", "--export-script", "perl", "old", "new"].iter().map(|arg| arg.to_string()).collect();
+        assert!(matches!(parse_arguments(&args).unwrap(), CommandArgs::FindReplace { options, .. } if options.export_script == Some(ScriptKind::Perl)));
+        for args in [["fr", "--export-script", "awk", "old", "new"], ["fr", "--export-script=sed", "--json-values", "old", "new"]] {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            assert!(parse_arguments(&args).is_err());
+        }
+    }
+
+    #[test]
+    fn test_parse_arguments_mv() {
+        let args: Vec<String> = ["fr", "mv", "old::module", "new::module", "--dry-run"].iter().map(|arg| arg.to_string()).collect();
+        assert!(matches!(parse_arguments(&args).unwrap(), CommandArgs::FindReplace { find_text: "old::module", replace_text: "new::module", options }
+            if options.module_paths && options.dry_run));
+        let args: Vec<String> = ["fr", "mv", "old::module", "./new/module"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_arguments(&args).is_err());
+    }
+
+    #[test]
+    fn test_parse_arguments_workspace() {
+        let args: Vec<String> = ["fr", "--package", "core", "--package=cli", "old", "new"].iter().map(|arg| arg.to_string()).collect();
+        assert!(matches!(parse_arguments(&args).unwrap(), CommandArgs::FindReplace { options, .. }
+            if options.members == Some(Members::Named(vec!["core".to_string(), "cli".to_string()]))));
+        let args: Vec<String> = ["fr", "--workspace", "old", "new"].iter().map(|arg| arg.to_string()).collect();
+        assert!(matches!(parse_arguments(&args).unwrap(), CommandArgs::FindReplace { options, .. } if options.members == Some(Members::All)));
+        let args: Vec<String> = ["fr", "--workspace", "--package", "core", "old", "new"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_arguments(&args).is_err());
+    }
+
+    #[test]
+    fn test_parse_arguments_license() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let (old, new) = (temp_dir.path().join("old.txt"), temp_dir.path().join("new.txt"));
+        std::fs::write(&old, "Copyright 2020 Acme\n").unwrap();
+        std::fs::write(&new, "Copyright 2024 Acme\n").unwrap();
+        let args: Vec<String> = ["fr", "license", "--old", old.to_str().unwrap(), "--new", new.to_str().unwrap(), "--dry-run"]
+            .iter().map(|arg| arg.to_string()).collect();
+        assert!(matches!(parse_arguments(&args).unwrap(), CommandArgs::FindReplace { find_text: "", options, .. }
+            if options.license.is_some() && options.dry_run));
+        let args: Vec<String> = ["fr", "license", "--old", old.to_str().unwrap()].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_arguments(&args).is_err());
+        let args: Vec<String> = ["fr", "--old", old.to_str().unwrap(), "a", "b"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_arguments(&args).is_err());
+
+        let args: Vec<String> = ["fr", "bump-year", "--diff"].iter().map(|arg| arg.to_string()).collect();
+        assert!(matches!(parse_arguments(&args).unwrap(), CommandArgs::FindReplace { find_text: "", options, .. }
+            if options.bump_year.is_some_and(|year| year >= 2024) && options.diff));
+    }
+
+    #[test]
+    fn test_parse_arguments_bump_version() {
+        let args: Vec<String> = ["fr", "bump-version", "1.2.3", "1.3.0", "--manifests"].iter().map(|arg| arg.to_string()).collect();
+        assert!(matches!(parse_arguments(&args).unwrap(), CommandArgs::FindReplace { find_text: "1.2.3", replace_text: "1.3.0", options }
+            if options.versions && options.version_manifests));
+        let args: Vec<String> = ["fr", "bump-version", "1.2.3", "next"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_arguments(&args).is_err());
+        let args: Vec<String> = ["fr", "--manifests", "1.2.3", "1.3.0"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_arguments(&args).is_err());
+    }
+
+    #[test]
+    fn test_parse_arguments_stdin() {
+        let parse = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            match parse_arguments(&args) {
+                Ok(CommandArgs::FindReplace { find_text, replace_text, options }) => Some((find_text.to_string(), replace_text.to_string(), options.stdin)),
+                _ => None,
+            }
+        };
+        // Whatever stdin is, only - or --stdin filters it
+        assert_eq!(parse(&["fr", "old", "new"]), Some(("old".to_string(), "new".to_string(), false)));
+        assert_eq!(parse(&["fr", "old", "new", "-"]), Some(("old".to_string(), "new".to_string(), true)));
+        assert_eq!(parse(&["fr", "--stdin", "old", "new"]), Some(("old".to_string(), "new".to_string(), true)));
+        assert_eq!(parse(&["fr", "-", "_"]), Some(("-".to_string(), "_".to_string(), false)));
+        assert_eq!(parse(&["fr", "apply-rg", "new", "-"]), Some(("apply-rg".to_string(), "new".to_string(), true)));
+        assert_eq!(parse(&["fr", "redact", "-"]), Some((String::new(), String::new(), true)));
+    }
+
+    #[test]
+    fn test_parse_arguments_everything() {
+        let args: Vec<String> = ["fr", "--everything", "widget", "gadget"].iter().map(|arg| arg.to_string()).collect();
+        assert!(matches!(parse_arguments(&args).unwrap(), CommandArgs::FindReplace { find_text: "widget", options, .. } if options.everything));
+        for args in [&["fr", "--everything", "--pick", "a", "b"][..], &["fr", "--everything", "--out-dir", "out", "a", "b"], &["fr", "--everything", "redact"]] {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            assert!(parse_arguments(&args).is_err());
+        }
+    }
+
+    #[test]
+    fn test_parse_arguments_scaffold() {
+        let args: Vec<String> = ["fr", "scaffold", "--var", "NAME=myproj", "--var=AUTHOR=Jane"].iter().map(|arg| arg.to_string()).collect();
+        assert!(matches!(parse_arguments(&args).unwrap(), CommandArgs::FindReplace { find_text: "", options, .. }
+            if options.placeholders == [("NAME".to_string(), "myproj".to_string()), ("AUTHOR".to_string(), "Jane".to_string())]));
+        for args in [&["fr", "scaffold"][..], &["fr", "scaffold", "--var", "NAME"], &["fr", "--var", "NAME=myproj", "a", "b"]] {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            assert!(parse_arguments(&args).is_err());
+        }
+    }
+
+    #[test]
+    fn test_parse_arguments_validate() {
+        let args: Vec<String> = ["fr", "--validate", "--validate-jobs=2", "old", "new"].iter().map(|arg| arg.to_string()).collect();
+        assert!(matches!(parse_arguments(&args).unwrap(), CommandArgs::FindReplace { options, .. }
+            if options.urls && options.validate_urls && options.validate_jobs == 2));
+        let args: Vec<String> = ["fr", "--validate-jobs", "0", "old", "new"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_arguments(&args).is_err());
+    }
+
+    #[test]
+    fn test_parse_arguments_plan() {
+        let args: Vec<String> = ["fr", "--save-plan", "plan.json", "old", "new"].iter().map(|arg| arg.to_string()).collect();
+        assert!(matches!(parse_arguments(&args).unwrap(), CommandArgs::FindReplace { options, .. }
+            if options.save_plan == Some(PathBuf::from("plan.json"))));
+        let args: Vec<String> = ["fr", "--save-plan", "plan.json", "--diff", "old", "new"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_arguments(&args).is_err());
+
+        let args: Vec<String> = ["fr", "apply", "plan.json", "--dry-run"].iter().map(|arg| arg.to_string()).collect();
+        assert!(matches!(parse_arguments(&args).unwrap(), CommandArgs::Apply { plan, options } if plan == Path::new("plan.json") && options.dry_run));
+        // Replacing apply takes a -- before it
+        let args: Vec<String> = ["fr", "--", "apply", "applied"].iter().map(|arg| arg.to_string()).collect();
+        assert!(matches!(parse_arguments(&args).unwrap(), CommandArgs::FindReplace { find_text: "apply", .. }));
+    }
+
+    #[test]
+    fn test_parse_arguments_format() {
+        for args in [
+            vec!["fr", "--format", "vimgrep", "find", "replace"],
+            vec!["fr", "find", "replace", "--format=vimgrep"],
+        ] {
+            let args: Vec<String> = args.into_iter().map(String::from).collect();
+            match parse_arguments(&args).unwrap() {
+                CommandArgs::FindReplace { find_text, replace_text, options } => {
+                    assert_eq!(find_text, "find");
+                    assert_eq!(replace_text, "replace");
+                    assert_eq!(options.format, Some(OutputFormat::Vimgrep));
+                }
+                _ => panic!("Expected FindReplace variant"),
+            }
+        }
+
+        let invalid = [
+            vec!["fr", "--format", "xml", "find", "replace"],
+            vec!["fr", "find", "replace", "--format"],
+            vec!["fr", "-c", "--format", "vimgrep", "find", "replace"],
+        ];
+        for args in invalid {
+            let args: Vec<String> = args.into_iter().map(String::from).collect();
+            assert!(parse_arguments(&args).is_err(), "Should fail for args: {:?}", args);
+        }
+    }
+
+    #[test]
+    fn test_parse_arguments_print0_modified() {
+        let args: Vec<String> = ["fr", "--print0-modified", "find", "replace"].into_iter().map(String::from).collect();
+        match parse_arguments(&args).unwrap() {
+            CommandArgs::FindReplace { options, .. } => assert!(options.print0_modified),
+            _ => panic!("Expected FindReplace variant"),
+        }
+
+        let args: Vec<String> = ["fr", "--print0-modified", "-c", "find", "replace"].into_iter().map(String::from).collect();
+        assert!(parse_arguments(&args).is_err());
+    }
+
+    #[test]
+    fn test_parse_arguments_modified_list() {
+        for args in [
+            vec!["fr", "--modified-list", "changed.txt", "find", "replace"],
+            vec!["fr", "find", "--modified-list=changed.txt", "replace"],
+        ] {
+            let args: Vec<String> = args.into_iter().map(String::from).collect();
+            match parse_arguments(&args).unwrap() {
+                CommandArgs::FindReplace { find_text, replace_text, options } => {
+                    assert_eq!(find_text, "find");
+                    assert_eq!(replace_text, "replace");
+                    assert_eq!(options.modified_list, Some(PathBuf::from("changed.txt")));
+                }
+                _ => panic!("Expected FindReplace variant"),
+            }
+        }
+
+        let args: Vec<String> = ["fr", "find", "replace", "--modified-list"].into_iter().map(String::from).collect();
+        assert!(parse_arguments(&args).is_err());
+    }
+
+    #[test]
+    fn test_parse_arguments_journal() {
+        let args: Vec<String> = ["fr", "--journal", "sqlite:fr.db", "old", "new"].iter().map(|arg| arg.to_string()).collect();
+        assert!(matches!(parse_arguments(&args).unwrap(), CommandArgs::FindReplace { options, .. }
+            if options.journal == Some(PathBuf::from("fr.db"))));
+        for args in [
+            &["fr", "--journal", "fr.db", "old", "new"][..],
+            &["fr", "--undo", "1", "old", "new"],
+            &["fr", "--journal", "sqlite:j", "-c", "a", "b"],
+            &["fr", "--journal", "sqlite:j", "--out-dir", "out", "a", "b"],
+        ] {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            assert!(parse_arguments(&args).is_err());
+        }
+
+        let args: Vec<String> = ["fr", "journal", "sqlite:fr.db", "--undo", "2"].iter().map(|arg| arg.to_string()).collect();
+        assert!(matches!(parse_arguments(&args).unwrap(), CommandArgs::Journal { journal, query: JournalQuery::Undo(2), .. }
+            if journal == Path::new("fr.db")));
+        let args: Vec<String> = ["fr", "journal", "sqlite:fr.db", "--touching", "a.txt"].iter().map(|arg| arg.to_string()).collect();
+        assert!(matches!(parse_arguments(&args).unwrap(), CommandArgs::Journal { query: JournalQuery::Touching("a.txt"), .. }));
+        let args: Vec<String> = ["fr", "journal", "sqlite:fr.db", "--run", "0"].iter().map(|arg| arg.to_string()).collect();
+        assert!(parse_arguments(&args).is_err());
+        let args: Vec<String> = ["fr", "--", "journal", "sqlite:fr.db"].iter().map(|arg| arg.to_string()).collect();
+        assert!(matches!(parse_arguments(&args).unwrap(), CommandArgs::FindReplace { find_text: "journal", .. }));
+    }
+
+    #[test]
+    fn test_parse_arguments_index() {
+        let args: Vec<String> = ["fr", "index", "-j", "2"].into_iter().map(String::from).collect();
+        match parse_arguments(&args).unwrap() {
+            CommandArgs::Index { options } => assert_eq!(options.threads, Some(2)),
+            _ => panic!("Expected Index variant"),
+        }
+
+        // Replacing the word index still takes both texts
+        let args: Vec<String> = ["fr", "index", "idx"].into_iter().map(String::from).collect();
+        assert!(matches!(parse_arguments(&args).unwrap(), CommandArgs::FindReplace { find_text: "index", .. }));
+    }
+
+    #[test]
+    fn test_parse_arguments_serve() {
+        let args: Vec<String> = ["fr", "serve", "--encoding", "latin1"].into_iter().map(String::from).collect();
+        match parse_arguments(&args).unwrap() {
+            CommandArgs::Serve { options } => assert_eq!(options.encoding, Encoding::Latin1),
+            _ => panic!("Expected Serve variant"),
+        }
+
+        let args: Vec<String> = ["fr", "serve", "--count"].into_iter().map(String::from).collect();
+        assert!(parse_arguments(&args).is_err());
+    }
+
+    #[test]
+    fn test_parse_arguments_json_path() {
+        let args: Vec<String> = ["fr", "old", "new", "--json-path", "$.dependencies.*"].into_iter().map(String::from).collect();
+        match parse_arguments(&args).unwrap() {
+            CommandArgs::FindReplace { options, .. } => {
+                assert!(options.json_values);
+                assert_eq!(options.json_paths, [JsonPath::parse("$.dependencies.*").unwrap()]);
+            }
+            _ => panic!("Expected FindReplace variant"),
+        }
+
+        let args: Vec<String> = ["fr", "old", "new", "--json-path", "dependencies"].into_iter().map(String::from).collect();
+        assert!(parse_arguments(&args).is_err());
+    }
+
+    #[test]
+    fn test_parse_arguments_remote() {
+        let args: Vec<String> = ["fr", "old", "new", "sftp://web1/etc/app", "--dry-run"].into_iter().map(String::from).collect();
+        match parse_arguments(&args).unwrap() {
+            CommandArgs::Remote { find_text, replace_text, remote, options } => {
+                assert_eq!((find_text, replace_text), ("old", "new"));
+                assert_eq!(remote, Remote::parse("ssh://web1/etc/app").unwrap().unwrap());
+                assert!(options.dry_run);
+            }
+            _ => panic!("Expected Remote variant"),
+        }
+
+        for args in [&["fr", "old", "new", "src"][..], &["fr", "old", "new", "ssh://web1/etc", "--count"]] {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            assert!(parse_arguments(&args).is_err());
+        }
+    }
+
+    #[test]
+    fn test_parse_arguments_bench() {
+        let args: Vec<String> = ["fr", "bench", "old", "new", "--text"].into_iter().map(String::from).collect();
+        match parse_arguments(&args).unwrap() {
+            CommandArgs::Bench { find_text, replace_text, options } => {
+                assert_eq!((find_text, replace_text), ("old", "new"));
+                assert!(options.text);
+            }
+            _ => panic!("Expected Bench variant"),
+        }
+
+        // Replacing the word bench takes the usual two arguments
+        let args: Vec<String> = ["fr", "bench", "test"].into_iter().map(String::from).collect();
+        assert!(matches!(parse_arguments(&args).unwrap(), CommandArgs::FindReplace { find_text: "bench", .. }));
+        let args: Vec<String> = ["fr", "bench", "", "new"].into_iter().map(String::from).collect();
+        assert!(parse_arguments(&args).is_err());
+    }
+
+    #[test]
+    fn test_parse_arguments_explain() {
+        let args: Vec<String> = ["fr", "--explain", "src/a.txt"].into_iter().map(String::from).collect();
+        match parse_arguments(&args).unwrap() {
+            CommandArgs::Explain { path, find_text, .. } => {
+                assert_eq!(path, "src/a.txt");
+                assert_eq!(find_text, None);
+            }
+            _ => panic!("Expected Explain variant"),
+        }
+
+        let args: Vec<String> = ["fr", "find", "replace", "--explain", "src/a.txt"].into_iter().map(String::from).collect();
+        match parse_arguments(&args).unwrap() {
+            CommandArgs::Explain { path, find_text, .. } => {
+                assert_eq!(path, "src/a.txt");
+                assert_eq!(find_text, Some("find"));
+            }
+            _ => panic!("Expected Explain variant"),
+        }
+    }
+
+    #[test]
+    fn test_parse_arguments_logging() {
+        let args: Vec<String> = ["fr", "--log-level", "debug", "--trace-chrome=trace.json", "find", "replace"]
+            .into_iter().map(String::from).collect();
+        match parse_arguments(&args).unwrap() {
+            CommandArgs::FindReplace { options, .. } => {
+                assert_eq!(options.log_level, Some(LevelFilter::Debug));
+                assert_eq!(options.trace_chrome, Some(PathBuf::from("trace.json")));
+            }
+            _ => panic!("Expected FindReplace variant"),
+        }
+
+        let args: Vec<String> = ["fr", "--log-level", "loud", "find", "replace"].into_iter().map(String::from).collect();
+        assert!(parse_arguments(&args).is_err());
+    }
+
+    #[test]
+    fn test_parse_arguments_threads() {
+        for flag in [["-j", "4"], ["--threads", "4"]] {
+            let args: Vec<String> = ["fr", flag[0], flag[1], "find", "replace"].into_iter().map(String::from).collect();
+            match parse_arguments(&args).unwrap() {
+                CommandArgs::FindReplace { options, .. } => assert_eq!(options.threads, Some(4)),
+                _ => panic!("Expected FindReplace variant"),
+            }
+        }
+
+        let args: Vec<String> = ["fr", "--retries", "0", "find", "replace"].into_iter().map(String::from).collect();
+        match parse_arguments(&args).unwrap() {
+            CommandArgs::FindReplace { options, .. } => assert_eq!(options.retries, 0),
+            _ => panic!("Expected FindReplace variant"),
+        }
+        let args: Vec<String> = ["fr", "--retries", "-1", "find", "replace"].into_iter().map(String::from).collect();
+        assert!(parse_arguments(&args).is_err());
+
+        let args: Vec<String> = ["fr", "--threads=many", "find", "replace"].into_iter().map(String::from).collect();
+        assert!(parse_arguments(&args).is_err());
+    }
+
+    #[test]
+    fn test_parse_arguments_resource_limits() {
+        let args: Vec<String> = ["fr", "--throttle", "5", "--nice", "find", "replace"].into_iter().map(String::from).collect();
+        match parse_arguments(&args).unwrap() {
+            CommandArgs::FindReplace { options, .. } => {
+                assert_eq!(options.throttle, Some(5 << 20));
+                assert!(options.nice);
+            }
+            _ => panic!("Expected FindReplace variant"),
+        }
+
+        let args: Vec<String> = ["fr", "--max-memory", "256M", "find", "replace"].into_iter().map(String::from).collect();
+        match parse_arguments(&args).unwrap() {
+            CommandArgs::FindReplace { options, .. } => assert_eq!(options.max_memory, Some(256 << 20)),
+            _ => panic!("Expected FindReplace variant"),
+        }
+
+        let args: Vec<String> = ["fr", "--throttle=0", "find", "replace"].into_iter().map(String::from).collect();
+        assert!(parse_arguments(&args).is_err());
+    }
+
+    #[test]
+    fn test_parse_arguments_text() {
+        let args: Vec<String> = ["fr", "-a", "find", "replace"].into_iter().map(String::from).collect();
+        match parse_arguments(&args).unwrap() {
+            CommandArgs::FindReplace { options, .. } => assert!(options.text),
+            _ => panic!("Expected FindReplace variant"),
+        }
+
+        let args: Vec<String> = ["fr", "--text-ext", "svg,.TS", "--text-ext=dat", "find", "replace"].into_iter().map(String::from).collect();
+        match parse_arguments(&args).unwrap() {
+            CommandArgs::FindReplace { options, .. } => assert_eq!(options.text_extensions, ["svg", "ts", "dat"]),
+            _ => panic!("Expected FindReplace variant"),
+        }
+
+        let args: Vec<String> = ["fr", "--text-ext", "svg,", "find", "replace"].into_iter().map(String::from).collect();
+        assert!(parse_arguments(&args).is_err());
+    }
+
+    #[test]
+    fn test_parse_arguments_dry_run() {
+        let args: Vec<String> = ["fr", "-n", "--diff", "--no-pager", "find", "replace"].into_iter().map(String::from).collect();
+        match parse_arguments(&args).unwrap() {
+            CommandArgs::FindReplace { options, .. } => {
+                assert!(options.dry_run);
+                assert!(options.diff);
+                assert!(!options.pager);
+            }
+            _ => panic!("Expected FindReplace variant"),
+        }
+    }
+
+    #[test]
+    fn test_parse_arguments_color() {
+        let args: Vec<String> = ["fr", "--color=always", "--theme", "colorblind", "find", "replace"]
+            .into_iter().map(String::from).collect();
+        match parse_arguments(&args).unwrap() {
+            CommandArgs::FindReplace { options, .. } => {
+                assert_eq!(options.color, ColorChoice::Always);
+                assert_eq!(options.theme, Theme::Colorblind);
+            }
+            _ => panic!("Expected FindReplace variant"),
+        }
+
+        let args: Vec<String> = ["fr", "--color", "rainbow", "find", "replace"].into_iter().map(String::from).collect();
+        assert!(parse_arguments(&args).is_err());
+    }
+
+    #[test]
+    fn test_skipped_files_message() {
+        assert_eq!(skipped_files_message(&Summary::default(), false), None);
+
+        let summary = Summary {
+            skipped: vec![
+                (PathBuf::from("a.bin"), SkipReason::Binary),
+                (PathBuf::from("b.bin"), SkipReason::Binary),
+                (PathBuf::from("huge.log"), SkipReason::TooLarge),
+            ],
+            ..Summary::default()
+        };
+        assert_eq!(skipped_files_message(&summary, false).unwrap(),
+            "Skipped 2 binary files and 1 file over --max-filesize (pass --show-skipped to list them)\n");
+        assert_eq!(skipped_files_message(&summary, true).unwrap(),
+            "Skipped files:\n  a.bin: binary file\n  b.bin: binary file\n  huge.log: larger than --max-filesize\n");
+
+        let summary = Summary { skipped: vec![(PathBuf::from("open.docx"), SkipReason::InUse)], ..Summary::default() };
+        assert_eq!(skipped_files_message(&summary, false).unwrap(),
+            "Skipped 1 file in use by another program (pass --show-skipped to list them)\n");
+        let summary = Summary { skipped: vec![(PathBuf::from("disk.img"), SkipReason::Sparse)], ..Summary::default() };
+        assert_eq!(skipped_files_message(&summary, false).unwrap(),
+            "Skipped 1 sparse file (pass --show-skipped to list them, or --sparse to rewrite sparse files)\n");
+    }
+
+    #[test]
+    fn test_pattern_problem() {
+        let options = Options::default();
+        assert_eq!(pattern_problem("foo", "foo", &options), Some(PatternProblem::Unchanged));
+        assert_eq!(pattern_problem("foo", "foofoo", &options), Some(PatternProblem::Recursive));
+        assert_eq!(pattern_problem("foo", "bar", &options), None);
+        assert_eq!(pattern_problem("foo", "foo", &Options { force: true, ..Options::default() }), None);
+        assert_eq!(pattern_problem("paypal", "paypal", &Options { confusables: true, ..Options::default() }), None);
+    }
+
+    #[test]
+    fn test_version_flag() {
+        let args = vec!["fr".to_string(), "--version".to_string()];
+        assert!(matches!(parse_arguments(&args).unwrap(), CommandArgs::Version), "Expected Version variant");
+    }
+
+}
@@ -0,0 +1,187 @@
+//! Colored terminal output.
+//!
+//! Color is used when writing to a terminal unless the `NO_COLOR` environment
+//! variable is set (see <https://no-color.org>), and can be forced on or off
+//! with `--color`.
+
+use std::env;
+
+/// When to color output, as chosen with `--color`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Color output written to a terminal, unless `NO_COLOR` is set
+    #[default]
+    Auto,
+    /// Always color output, even when it is redirected
+    Always,
+    /// Never color output
+    Never,
+}
+
+impl ColorChoice {
+    /// Parses the value given to `--color`.
+    pub fn parse(value: &str) -> Result<ColorChoice, String> {
+        match value {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!("Unknown color choice '{}', expected one of: auto, always, never", value)),
+        }
+    }
+
+    /// Whether output to a stream should be colored.
+    ///
+    /// # Arguments
+    ///
+    /// * `is_terminal` - Whether the stream is a terminal
+    pub fn enabled(self, is_terminal: bool) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => is_terminal && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()),
+        }
+    }
+}
+
+/// What a piece of colored output represents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// A line or count of lines being added
+    Added,
+    /// A line or count of lines being removed
+    Removed,
+    /// A file path
+    Path,
+    /// A line or column number
+    LineNumber,
+    /// The matched text itself
+    Match,
+}
+
+/// A set of colors for each role, chosen with `--theme`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Theme {
+    /// Red for removals and green for additions, like git
+    #[default]
+    Default,
+    /// Orange for removals and blue for additions, which are easier to tell
+    /// apart with red-green color blindness
+    Colorblind,
+    /// Text attributes only, for terminals with unreadable palettes
+    Monochrome,
+}
+
+impl Theme {
+    /// Parses the value given to `--theme`.
+    pub fn parse(value: &str) -> Result<Theme, String> {
+        match value {
+            "default" => Ok(Theme::Default),
+            "colorblind" => Ok(Theme::Colorblind),
+            "monochrome" => Ok(Theme::Monochrome),
+            _ => Err(format!("Unknown theme '{}', expected one of: default, colorblind, monochrome", value)),
+        }
+    }
+
+    /// The SGR parameters used for a role
+    fn sgr(self, role: Role) -> &'static str {
+        match (self, role) {
+            (Theme::Default, Role::Added) => "32",
+            (Theme::Default, Role::Removed) => "31",
+            (Theme::Default, Role::Path) => "35",
+            (Theme::Default, Role::LineNumber) => "32",
+            (Theme::Default, Role::Match) => "1;31",
+            (Theme::Colorblind, Role::Added) => "38;5;33",
+            (Theme::Colorblind, Role::Removed) => "38;5;208",
+            (Theme::Colorblind, Role::Path) => "38;5;141",
+            (Theme::Colorblind, Role::LineNumber) => "38;5;33",
+            (Theme::Colorblind, Role::Match) => "1;38;5;208",
+            (Theme::Monochrome, Role::Added) => "1",
+            (Theme::Monochrome, Role::Removed) => "2",
+            (Theme::Monochrome, Role::Path) => "1",
+            (Theme::Monochrome, Role::LineNumber) => "2",
+            (Theme::Monochrome, Role::Match) => "1;4",
+        }
+    }
+}
+
+/// Colors text for one output stream, or leaves it plain
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Painter {
+    theme: Option<Theme>,
+}
+
+impl Painter {
+    /// Creates a painter for a stream.
+    ///
+    /// # Arguments
+    ///
+    /// * `choice` - When the user wants color
+    /// * `theme` - Which colors to use
+    /// * `is_terminal` - Whether the stream is a terminal
+    pub fn new(choice: ColorChoice, theme: Theme, is_terminal: bool) -> Painter {
+        Painter { theme: choice.enabled(is_terminal).then_some(theme) }
+    }
+
+    /// Wraps text in the escape codes for a role, if coloring.
+    pub fn paint(&self, role: Role, text: &str) -> String {
+        match self.theme {
+            Some(theme) if !text.is_empty() => format!("\x1b[{}m{}\x1b[0m", theme.sgr(role), text),
+            _ => text.to_string(),
+        }
+    }
+
+    /// Wraps text in the escape codes for a role, emphasized to stand out in
+    /// a line painted in the same role: struck through if it is being
+    /// removed, and in reverse video otherwise.
+    pub fn emphasize(&self, role: Role, text: &str) -> String {
+        let emphasis = match role {
+            Role::Removed => "9",
+            _ => "7",
+        };
+        match self.theme {
+            Some(theme) if !text.is_empty() => format!("\x1b[{};{}m{}\x1b[0m", theme.sgr(role), emphasis, text),
+            _ => text.to_string(),
+        }
+    }
+
+    /// Whether text is colored, rather than left plain.
+    pub fn is_coloring(&self) -> bool {
+        self.theme.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(ColorChoice::parse("always").unwrap(), ColorChoice::Always);
+        assert!(ColorChoice::parse("sometimes").is_err());
+        assert_eq!(Theme::parse("colorblind").unwrap(), Theme::Colorblind);
+        assert!(Theme::parse("solarized").is_err());
+    }
+
+    #[test]
+    fn test_enabled() {
+        assert!(ColorChoice::Always.enabled(false));
+        assert!(!ColorChoice::Never.enabled(true));
+        assert!(!ColorChoice::Auto.enabled(false));
+    }
+
+    #[test]
+    fn test_paint() {
+        let plain = Painter::new(ColorChoice::Never, Theme::Default, true);
+        assert_eq!(plain.paint(Role::Added, "+++"), "+++");
+
+        let colored = Painter::new(ColorChoice::Always, Theme::Default, false);
+        assert_eq!(colored.paint(Role::Added, "+++"), "\x1b[32m+++\x1b[0m");
+        assert_eq!(colored.paint(Role::Added, ""), "");
+
+        assert_eq!(colored.emphasize(Role::Removed, "old"), "\x1b[31;9mold\x1b[0m");
+        assert_eq!(plain.emphasize(Role::Removed, "old"), "old");
+
+        let colorblind = Painter::new(ColorChoice::Always, Theme::Colorblind, false);
+        assert_eq!(colorblind.paint(Role::Removed, "-"), "\x1b[38;5;208m-\x1b[0m");
+    }
+}
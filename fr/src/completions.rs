@@ -0,0 +1,345 @@
+//! Shell completion scripts, for `fr completions`.
+//!
+//! The flags and commands are read from the help message, so every flag it
+//! lists is completed without a second list to keep up to date. Flags with a
+//! fixed set of values complete those values, flags that take a file complete
+//! paths, and `--profile` completes the profiles of the configuration files
+//! that apply where it is run, by calling `fr completions profiles`.
+
+use crate::redact;
+
+/// A shell a completion script can be written for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Powershell,
+}
+
+impl Shell {
+    /// Parses the shell given to `fr completions`.
+    pub fn parse(value: &str) -> Result<Shell, String> {
+        match value {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            "powershell" => Ok(Shell::Powershell),
+            _ => Err(format!("Unknown shell '{}', expected one of: bash, zsh, fish, powershell", value)),
+        }
+    }
+}
+
+/// What a flag's value is completed with
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Values {
+    /// Nothing: the flag takes no value
+    None,
+    /// Nothing, as the value can be anything, such as a number
+    Any,
+    /// Paths
+    Files,
+    /// The names of the profiles
+    Profiles,
+    /// One of these
+    OneOf(Vec<&'static str>),
+}
+
+/// A flag, as the help message lists it
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Flag {
+    long: String,
+    short: Option<char>,
+    /// The first sentence of what the help message says it does
+    description: String,
+    values: Values,
+}
+
+/// The values of the flags with a fixed set of them
+const CHOICES: [(&str, &[&str]); 9] = [
+    ("--format", &["vimgrep"]),
+    ("--report", &["html:", "diffstat", "csv:"]),
+    ("--log-level", &["off", "error", "warn", "info", "debug", "trace"]),
+    ("--color", &["auto", "always", "never"]),
+    ("--theme", &["default", "colorblind", "monochrome"]),
+    ("--encoding", &["utf-8", "latin1", "utf-16le", "utf-16be", "shift_jis", "auto"]),
+    ("--eol", &["lf", "crlf", "preserve"]),
+    ("--front-matter", &["inside", "outside"]),
+    ("--xml-scope", &["text", "attr:", "tag:"]),
+];
+
+/// Writes the completion script for a shell.
+///
+/// # Arguments
+///
+/// * `shell` - The shell to write it for
+/// * `help` - The help message, which the flags and commands are read from
+///
+/// # Returns
+///
+/// * `String` - The script, to be sourced by the shell
+pub fn script(shell: Shell, help: &str) -> String {
+    let flags = flags(help);
+    let commands = commands(help);
+    match shell {
+        Shell::Bash => bash(&flags, &commands),
+        Shell::Zsh => zsh(&flags, &commands),
+        Shell::Fish => fish(&flags, &commands),
+        Shell::Powershell => powershell(&flags, &commands),
+    }
+}
+
+/// Reads the flags from the help message: each line of the options that
+/// starts with a flag, and the flags of the commands.
+fn flags(help: &str) -> Vec<Flag> {
+    let mut flags: Vec<Flag> = Vec::new();
+    for (section, usage, description) in entries(help) {
+        let mut words = usage.split([' ', ',']).map(|word| word.trim_matches(['[', ']'])).filter(|word| !word.is_empty()).peekable();
+        let mut short = None;
+        while let Some(word) = words.next() {
+            if let Some(name) = word.strip_prefix('-').filter(|name| name.len() == 1) {
+                short = name.chars().next();
+                continue;
+            }
+            if !word.starts_with("--") {
+                continue;
+            }
+            let placeholder = words.next_if(|word| word.starts_with('<'));
+            let values = match (word, placeholder) {
+                ("--profile", _) => Values::Profiles,
+                ("--kinds", _) => Values::OneOf(redact::ALL.iter().map(|redaction| redaction.name()).collect()),
+                (_, None) => Values::None,
+                (_, Some("<file>" | "<dir>" | "<path>")) => Values::Files,
+                (word, Some(_)) => CHOICES.iter().find(|&&(flag, _)| flag == word)
+                    .map_or(Values::Any, |&(_, choices)| Values::OneOf(choices.to_vec())),
+            };
+            if !flags.iter().any(|flag| flag.long == word) {
+                // A command's description is of the command, not its flags
+                let description = match section {
+                    "Options:" => first_sentence(&description).to_string(),
+                    _ => String::new(),
+                };
+                flags.push(Flag { long: word.to_string(), short: short.take(), description, values });
+            }
+        }
+    }
+    flags.push(Flag { long: "--help".to_string(), short: None, description: "Print help".to_string(), values: Values::None });
+    flags.push(Flag { long: "--version".to_string(), short: None, description: "Print the version".to_string(), values: Values::None });
+    flags
+}
+
+/// The column the help message's descriptions of flags and commands start in
+const DESCRIPTION_COLUMN: usize = 24;
+
+/// Reads the flags and commands the help message lists, as their section,
+/// their usage, and what it says about them with its lines joined.
+pub fn entries(help: &str) -> Vec<(&str, &str, String)> {
+    let mut entries: Vec<(&str, &str, String)> = Vec::new();
+    let mut section = "";
+    for line in help.lines() {
+        if !line.starts_with(' ') {
+            section = line;
+            continue;
+        }
+        let text = line.trim_start();
+        match section {
+            "Options:" | "Commands:" if line.len() - text.len() == 4 => {
+                // Descriptions start in the column after the widest usage,
+                // unless the usage is too wide to leave room, which a
+                // placeholder starting in that column is part of
+                let (usage, description) = match (line.get(..DESCRIPTION_COLUMN), line.get(DESCRIPTION_COLUMN..)) {
+                    (Some(usage), Some(description)) if usage.ends_with(' ') && !description.starts_with([' ', '<']) => (usage.trim(), description.trim()),
+                    _ => (text, ""),
+                };
+                entries.push((section, usage, description.to_string()));
+            }
+            "Options:" | "Commands:" => {
+                if let Some((_, _, description)) = entries.last_mut() {
+                    if !description.is_empty() {
+                        description.push(' ');
+                    }
+                    description.push_str(text);
+                }
+            }
+            _ => {}
+        }
+    }
+    entries
+}
+
+/// The first sentence of a description, without its full stop.
+fn first_sentence(description: &str) -> &str {
+    let end = description.find(". ").unwrap_or(description.len());
+    description[..end].trim_end_matches('.')
+}
+
+/// Returns the long names of every flag the help message lists, such as
+/// `--dry-run`.
+pub fn flag_names(help: &str) -> Vec<String> {
+    flags(help).into_iter().map(|flag| flag.long).collect()
+}
+
+/// Reads the names of the commands from the help message.
+fn commands(help: &str) -> Vec<&str> {
+    let commands = help.lines().skip_while(|line| *line != "Commands:").skip(1).take_while(|line| line.starts_with(' '));
+    let mut names: Vec<&str> = commands.filter_map(|line| line.trim_start().strip_prefix("fr ")?.split(' ').next()).collect();
+    names.dedup();
+    names
+}
+
+/// Quotes text for a shell between single quotes.
+fn single_quoted(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+fn bash(flags: &[Flag], commands: &[&str]) -> String {
+    let mut cases = String::new();
+    for flag in flags {
+        let names = flag.short.map_or(flag.long.clone(), |short| format!("-{}|{}", short, flag.long));
+        let reply = match &flag.values {
+            Values::None => continue,
+            Values::Any => "return".to_string(),
+            Values::Files => "COMPREPLY=($(compgen -f -- \"$cur\")); return".to_string(),
+            Values::Profiles => "COMPREPLY=($(compgen -W \"$(fr completions profiles 2>/dev/null)\" -- \"$cur\")); return".to_string(),
+            Values::OneOf(values) => format!("COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return", values.join(" ")),
+        };
+        cases.push_str(&format!("        {})\n            {}\n            ;;\n", names, reply));
+    }
+    let words: Vec<String> = flags.iter().flat_map(|flag| flag.short.map(|short| format!("-{}", short)).into_iter().chain([flag.long.clone()])).collect();
+    format!(r#"_fr() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}"
+    local prev="${{COMP_WORDS[COMP_CWORD-1]}}"
+    case "$prev" in
+{}    esac
+    if [[ "$cur" == -* ]]; then
+        COMPREPLY=($(compgen -W "{}" -- "$cur"))
+    elif [[ $COMP_CWORD -eq 1 ]]; then
+        COMPREPLY=($(compgen -W "{}" -- "$cur"))
+    fi
+}}
+complete -o default -F _fr fr
+"#, cases, words.join(" "), commands.join(" "))
+}
+
+fn zsh(flags: &[Flag], commands: &[&str]) -> String {
+    let mut specs = String::new();
+    for flag in flags {
+        let description = flag.description.replace(['[', ']'], "").replace('\'', "'\\''").replace(':', "\\:");
+        let action = match &flag.values {
+            Values::None => String::new(),
+            Values::Any => ":value: ".to_string(),
+            Values::Files => ":file:_files".to_string(),
+            Values::Profiles => ":profile:($(fr completions profiles 2>/dev/null))".to_string(),
+            Values::OneOf(values) => format!(":value:({})", values.join(" ")),
+        };
+        let equals = if flag.values == Values::None { "" } else { "=" };
+        match flag.short {
+            Some(short) => specs.push_str(&format!("    '(-{} {})'{{-{},{}{}}}'[{}]{}' \\\n", short, flag.long, short, flag.long, equals, description, action)),
+            None => specs.push_str(&format!("    '{}{}[{}]{}' \\\n", flag.long, equals, description, action)),
+        }
+    }
+    format!(r#"#compdef fr
+
+_fr() {{
+    _arguments -s \
+{}    '1: :({})' \
+    '*:file:_files'
+}}
+
+_fr "$@"
+"#, specs, commands.join(" "))
+}
+
+fn fish(flags: &[Flag], commands: &[&str]) -> String {
+    let mut script = String::new();
+    script.push_str(&format!("complete -c fr -n __fish_use_subcommand -a {}\n", single_quoted(&commands.join(" "))));
+    for flag in flags {
+        let mut line = format!("complete -c fr -l {}", &flag.long[2..]);
+        if let Some(short) = flag.short {
+            line.push_str(&format!(" -s {}", short));
+        }
+        if !flag.description.is_empty() {
+            line.push_str(&format!(" -d {}", single_quoted(&flag.description)));
+        }
+        match &flag.values {
+            Values::None => {}
+            Values::Any => line.push_str(" -x"),
+            Values::Files => line.push_str(" -r -F"),
+            Values::Profiles => line.push_str(" -x -a '(fr completions profiles 2>/dev/null)'"),
+            Values::OneOf(values) => line.push_str(&format!(" -x -a {}", single_quoted(&values.join(" ")))),
+        }
+        script.push_str(&line);
+        script.push('\n');
+    }
+    script
+}
+
+fn powershell(flags: &[Flag], commands: &[&str]) -> String {
+    let mut cases = String::new();
+    for flag in flags {
+        let values = match &flag.values {
+            Values::None | Values::Any | Values::Files => continue,
+            Values::Profiles => "@(fr completions profiles 2>$null)".to_string(),
+            Values::OneOf(values) => format!("@({})", values.iter().map(|value| single_quoted(value)).collect::<Vec<_>>().join(", ")),
+        };
+        for name in flag.short.map(|short| format!("-{}", short)).into_iter().chain([flag.long.clone()]) {
+            cases.push_str(&format!("        '{}' {{ {} }}\n", name, values));
+        }
+    }
+    let words: Vec<String> = flags.iter().map(|flag| single_quoted(&flag.long)).collect();
+    let commands: Vec<String> = commands.iter().map(|command| single_quoted(command)).collect();
+    format!(r#"Register-ArgumentCompleter -Native -CommandName fr -ScriptBlock {{
+    param($wordToComplete, $commandAst, $cursorPosition)
+    $words = @($commandAst.CommandElements | ForEach-Object {{ $_.ToString() }})
+    $previous = if ($wordToComplete) {{ $words[-2] }} else {{ $words[-1] }}
+    $values = switch ($previous) {{
+{}        default {{
+            if ($wordToComplete -like '-*') {{ @({}) }}
+            elseif ($words.Count -le 2) {{ @({}) }}
+            else {{ @() }}
+        }}
+    }}
+    $values | Where-Object {{ $_ -like "$wordToComplete*" }} | ForEach-Object {{
+        [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterValue', $_)
+    }}
+}}
+"#, cases, words.join(", "), commands.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HELP: &str = "fr - test\n\nOptions:\n    -c, --count         Print the number of matches\n                        without modifying anything\n    --color <when>      When to color\n    --front-matter <side>\n                        Only inside or outside\n    --log <file>        Append a record\n    -j, --threads <n>   Threads\n    --profile <name>    A profile\n\nCommands:\n    fr index            Index the files\n    fr lint --rules <file> [--fix]\n                        Report forbidden terms\n\nNote:\n    - Text matching is literal\n";
+
+    #[test]
+    fn test_flags() {
+        let flags = flags(HELP);
+        let summary: Vec<(&str, Option<char>, &Values)> = flags.iter().map(|flag| (flag.long.as_str(), flag.short, &flag.values)).collect();
+        assert_eq!(summary, vec![
+            ("--count", Some('c'), &Values::None),
+            ("--color", None, &Values::OneOf(vec!["auto", "always", "never"])),
+            ("--front-matter", None, &Values::OneOf(vec!["inside", "outside"])),
+            ("--log", None, &Values::Files),
+            ("--threads", Some('j'), &Values::Any),
+            ("--profile", None, &Values::Profiles),
+            ("--rules", None, &Values::Files),
+            ("--fix", None, &Values::None),
+            ("--help", None, &Values::None),
+            ("--version", None, &Values::None),
+        ]);
+        assert_eq!(flags[0].description, "Print the number of matches without modifying anything");
+        assert_eq!(flags[2].description, "Only inside or outside");
+        assert_eq!(commands(HELP), vec!["index", "lint"]);
+    }
+
+    #[test]
+    fn test_script() {
+        let bash = script(Shell::Bash, HELP);
+        assert!(bash.contains("        --color)\n            COMPREPLY=($(compgen -W \"auto always never\" -- \"$cur\")); return\n"));
+        assert!(bash.contains("compgen -W \"index lint\""));
+        assert!(script(Shell::Zsh, HELP).contains("    '(-j --threads)'{-j,--threads=}'[Threads]:value: ' \\\n"));
+        assert!(script(Shell::Fish, HELP).contains("complete -c fr -l threads -s j -d 'Threads' -x\n"));
+        assert!(script(Shell::Powershell, HELP).contains("        '--color' { @('auto', 'always', 'never') }\n"));
+    }
+}
@@ -0,0 +1,351 @@
+//! The configuration files, which hold default flags: the user's
+//! `~/.config/fr/config.toml` for every run, and a project's `.fr.toml` at
+//! the root of its repository for runs inside it.
+//!
+//! Each setting is the name of a flag in snake case, with the value the flag
+//! would be given:
+//!
+//! ```toml
+//! threads = 4
+//! color = "always"
+//! exclude = ["target", "*.min.js"]
+//! pager = false
+//! ```
+//!
+//! A `[profile.NAME]` section holds settings that only apply with
+//! `--profile NAME`, for a kind of run that comes up again and again:
+//!
+//! ```toml
+//! [profile.docs]
+//! include = ["*.md"]
+//! md_links = true
+//! ```
+//!
+//! Settings are read as flags given before those on the command line, the
+//! user's before the project's and both before a profile's, so a flag given
+//! later overrides a setting and adds to a list such as `exclude`.
+
+use crate::lint;
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// Environment variable naming a configuration file to read instead
+pub const CONFIG_ENV: &str = "FR_CONFIG";
+
+/// Environment variable holding flags to put before those on the command line
+pub const OPTS_ENV: &str = "FR_OPTS";
+
+/// How a setting becomes flags
+#[derive(Clone, Copy)]
+enum Kind {
+    /// A flag with a value, such as `--threads 4`
+    Value,
+    /// A flag given once for each value of a list, such as `--exclude`
+    List,
+    /// A flag given once for each path of a list, relative to the directory
+    /// the configuration file is in, such as `--forbid`
+    Paths,
+    /// A flag given when the setting is true, such as `--nice`
+    Switch,
+    /// A `--no-` flag given when the setting is false, such as `--no-pager`
+    NoSwitch,
+}
+
+/// The settings, by name
+const SETTINGS: [(&str, Kind); 48] = [
+    ("threads", Kind::Value),
+    ("color", Kind::Value),
+    ("theme", Kind::Value),
+    ("encoding", Kind::Value),
+    ("eol", Kind::Value),
+    ("cache", Kind::Value),
+    ("throttle", Kind::Value),
+    ("retries", Kind::Value),
+    ("max_memory", Kind::Value),
+    ("max_filesize", Kind::Value),
+    ("max_line_length", Kind::Value),
+    ("validate_jobs", Kind::Value),
+    ("exclude", Kind::List),
+    ("include", Kind::List),
+    ("forbid", Kind::Paths),
+    ("text_ext", Kind::List),
+    ("front_matter", Kind::Value),
+    ("json_path", Kind::List),
+    ("yaml_path", Kind::List),
+    ("toml_key", Kind::List),
+    ("xml_scope", Kind::List),
+    ("config_key", Kind::List),
+    ("json_values", Kind::Switch),
+    ("yaml_values", Kind::Switch),
+    ("md_links", Kind::Switch),
+    ("includes", Kind::Switch),
+    ("urls", Kind::Switch),
+    ("po", Kind::Switch),
+    ("config_values", Kind::Switch),
+    ("confusables", Kind::Switch),
+    ("diff", Kind::Switch),
+    ("nice", Kind::Switch),
+    ("text", Kind::Switch),
+    ("sparse", Kind::Switch),
+    ("streams", Kind::Switch),
+    ("show_skipped", Kind::Switch),
+    ("archives", Kind::Switch),
+    ("gzip", Kind::Switch),
+    ("office", Kind::Switch),
+    ("stats", Kind::Switch),
+    ("strict", Kind::Switch),
+    ("fsync", Kind::Switch),
+    ("estimate", Kind::Switch),
+    ("check_rust", Kind::Switch),
+    ("editorconfig", Kind::NoSwitch),
+    ("gitattributes", Kind::NoSwitch),
+    ("pager", Kind::NoSwitch),
+    ("secret_warning", Kind::NoSwitch),
+];
+
+/// Returns where the configuration file is: `$FR_CONFIG` if it is set, or
+/// `fr/config.toml` under `$XDG_CONFIG_HOME` or `~/.config`.
+pub fn path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os(CONFIG_ENV) {
+        return Some(PathBuf::from(path));
+    }
+    let config_home = match env::var_os("XDG_CONFIG_HOME") {
+        Some(config_home) if !config_home.is_empty() => PathBuf::from(config_home),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_home.join("fr").join("config.toml"))
+}
+
+/// Returns the project configuration file that applies to a directory: the
+/// `.fr.toml` in it or the nearest directory above it, up to the root of the
+/// repository it is in.
+pub fn project_path(starting_directory: &Path) -> Option<PathBuf> {
+    for directory in starting_directory.ancestors() {
+        let path = directory.join(".fr.toml");
+        if path.is_file() {
+            return Some(path);
+        }
+        if directory.join(".git").exists() {
+            break;
+        }
+    }
+    None
+}
+
+/// Reads the settings of a configuration file as the flags they stand for.
+///
+/// # Arguments
+///
+/// * `text` - The contents of the configuration file
+/// * `directory` - The directory the file is in, which paths are relative to
+/// * `profile` - The profile to read the section of, if any
+///
+/// # Returns
+///
+/// * `Result<(Vec<String>, Option<Vec<String>>), String>` - The flags outside
+///   any section, and those in the profile's section if the file has one, in
+///   the order the settings are listed; or an error naming the line that
+///   isn't a setting
+pub fn flags(text: &str, directory: &Path, profile: Option<&str>) -> Result<(Vec<String>, Option<Vec<String>>), String> {
+    let mut top_level = Vec::new();
+    let mut in_profile = None;
+    // The profile whose section is being read, or None outside any section
+    let mut section: Option<&str> = None;
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            section = Some(section_name(line).ok_or_else(|| format!("Invalid section on line {}, expected [profile.NAME]: {}", number + 1, line))?);
+            if section == profile {
+                in_profile.get_or_insert_with(Vec::new);
+            }
+            continue;
+        }
+        let flags = match section {
+            None => &mut top_level,
+            Some(_) if section == profile => in_profile.get_or_insert_with(Vec::new),
+            // Still checked, so a mistake shows up before the profile is used
+            Some(_) => &mut Vec::new(),
+        };
+        let invalid = || format!("Invalid setting on line {}, expected name = value: {}", number + 1, line);
+        let (name, rest) = line.split_once('=').ok_or_else(invalid)?;
+        let name = name.trim();
+        let &(_, kind) = SETTINGS.iter().find(|&&(setting, _)| setting == name)
+            .ok_or_else(|| format!("Unknown setting '{}' on line {}", name, number + 1))?;
+        let (values, rest) = values(rest.trim_start()).ok_or_else(invalid)?;
+        let rest = rest.trim_start();
+        if !(rest.is_empty() || rest.starts_with('#')) {
+            return Err(invalid());
+        }
+        let flag = format!("--{}", name.replace('_', "-"));
+        match (kind, values.as_slice()) {
+            (Kind::Value, [Value::Text(value)]) => flags.extend([flag, value.clone()]),
+            (Kind::List | Kind::Paths, values) if values.iter().all(|value| matches!(value, Value::Text(_))) => {
+                for value in values {
+                    if let Value::Text(value) = value {
+                        let value = match kind {
+                            Kind::Paths => directory.join(value).display().to_string(),
+                            _ => value.clone(),
+                        };
+                        flags.extend([flag.clone(), value]);
+                    }
+                }
+            }
+            (Kind::Switch, [Value::Bool(on)]) => flags.extend(on.then_some(flag)),
+            (Kind::NoSwitch, [Value::Bool(on)]) => flags.extend((!on).then(|| format!("--no-{}", name.replace('_', "-")))),
+            _ => return Err(format!("Invalid value for '{}' on line {}: {}", name, number + 1, line)),
+        }
+    }
+    Ok((top_level, in_profile))
+}
+
+/// Returns the names of the profiles a configuration file has sections for.
+pub fn profile_names(text: &str) -> Vec<String> {
+    text.lines().filter_map(|line| section_name(line.trim())).map(str::to_string).collect()
+}
+
+/// Reads the name of the profile in a `[profile.NAME]` section header.
+fn section_name(line: &str) -> Option<&str> {
+    line.strip_prefix("[profile.").and_then(|rest| rest.split_once(']'))
+        .filter(|(name, rest)| !name.is_empty() && (rest.trim().is_empty() || rest.trim().starts_with('#')))
+        .map(|(name, _)| name.trim_matches('"'))
+}
+
+/// Splits the value of `FR_OPTS` into arguments the way a shell would split
+/// words: at whitespace, except inside single or double quotes or after a
+/// backslash.
+///
+/// # Arguments
+///
+/// * `text` - The value to split
+///
+/// # Returns
+///
+/// * `Result<Vec<String>, String>` - The arguments, or an error if a quote is
+///   never closed
+pub fn split_words(text: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\\' => word.get_or_insert_with(String::new).extend(chars.next()),
+            '\'' | '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some(end) if end == c => break,
+                        Some('\\') if c == '"' => word.extend(chars.next()),
+                        Some(next) => word.push(next),
+                        None => return Err(format!("{} has an unclosed {} quote", OPTS_ENV, c)),
+                    }
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// A value of a setting
+enum Value {
+    /// A string or a number, as the flag would be given it
+    Text(String),
+    Bool(bool),
+}
+
+/// Reads the value at the start of some text, an array's as each of its
+/// values, returning them and the text after them.
+fn values(text: &str) -> Option<(Vec<Value>, &str)> {
+    let Some(mut rest) = text.strip_prefix('[') else {
+        let (value, rest) = value(text)?;
+        return Some((vec![value], rest));
+    };
+    let mut values = Vec::new();
+    loop {
+        rest = rest.trim_start();
+        if let Some(rest) = rest.strip_prefix(']') {
+            return Some((values, rest));
+        }
+        let (value, after) = value(rest)?;
+        values.push(value);
+        rest = after.trim_start();
+        match rest.strip_prefix(',') {
+            Some(after) => rest = after,
+            None => rest = rest.starts_with(']').then_some(rest)?,
+        }
+    }
+}
+
+/// Reads a string, number or boolean at the start of some text, returning it
+/// and the text after it.
+fn value(text: &str) -> Option<(Value, &str)> {
+    if let Some(quote @ ('"' | '\'')) = text.chars().next() {
+        let (value, rest) = lint::string(text, quote)?;
+        return Some((Value::Text(value), rest));
+    }
+    let len = text.find(|c: char| !c.is_ascii_alphanumeric() && c != '_' && c != '.').unwrap_or(text.len());
+    let value = match &text[..len] {
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        number if number.starts_with(|c: char| c.is_ascii_digit()) => Value::Text(number.replace('_', "")),
+        _ => return None,
+    };
+    Some((value, &text[len..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags() {
+        let text = "# Defaults\nthreads = 4\ncolor = \"always\" # on the CI too\nexclude = [\"target\", '*.min.js']\npager = false\nnice = true\neditorconfig = true\n";
+        assert_eq!(flags(text, Path::new("/home/me"), None).unwrap().0, vec![
+            "--threads", "4", "--color", "always", "--exclude", "target", "--exclude", "*.min.js", "--no-pager", "--nice",
+        ]);
+        assert_eq!(flags("forbid = [\"vendor\"]\n", Path::new("/repo"), None).unwrap().0, vec!["--forbid", "/repo/vendor"]);
+        assert!(flags("dry_run = true\n", Path::new("."), None).is_err());
+        assert!(flags("threads = true\n", Path::new("."), None).is_err());
+        assert!(flags("exclude = [\"target\"\n", Path::new("."), None).is_err());
+        assert!(flags("threads 4\n", Path::new("."), None).is_err());
+    }
+
+    #[test]
+    fn test_flags_profiles() {
+        let text = "threads = 2\n\n[profile.docs]\ninclude = [\"*.md\"]\nmd_links = true\n\n[profile.ci] # for CI\ncolor = \"never\"\n";
+        let (top_level, profile) = flags(text, Path::new("."), Some("docs")).unwrap();
+        assert_eq!(top_level, vec!["--threads", "2"]);
+        assert_eq!(profile.unwrap(), vec!["--include", "*.md", "--md-links"]);
+        assert_eq!(flags(text, Path::new("."), Some("ci")).unwrap().1.unwrap(), vec!["--color", "never"]);
+        assert_eq!(flags(text, Path::new("."), Some("release")).unwrap().1, None);
+        assert_eq!(flags(text, Path::new("."), None).unwrap(), (vec!["--threads".to_string(), "2".to_string()], None));
+        assert!(flags("[tool]\nthreads = 2\n", Path::new("."), None).is_err());
+        assert_eq!(profile_names(text), vec!["docs", "ci"]);
+        assert!(flags("[profile.docs]\nbogus = 1\n", Path::new("."), None).is_err());
+    }
+
+    #[test]
+    fn test_split_words() {
+        assert_eq!(split_words("  --threads 4 --exclude '*.min.js'\t--color=\"always\" a\\ b \"\"").unwrap(),
+            vec!["--threads", "4", "--exclude", "*.min.js", "--color=always", "a b", ""]);
+        assert!(split_words("--exclude 'target").is_err());
+    }
+
+    #[test]
+    fn test_project_path() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let repo = temp_dir.path().join("repo");
+        std::fs::create_dir_all(repo.join(".git")).unwrap();
+        std::fs::create_dir_all(repo.join("src/deep")).unwrap();
+        assert_eq!(project_path(&repo.join("src/deep")), None);
+        std::fs::write(temp_dir.path().join(".fr.toml"), "").unwrap();
+        assert_eq!(project_path(&repo.join("src/deep")), None);
+        std::fs::write(repo.join(".fr.toml"), "").unwrap();
+        assert_eq!(project_path(&repo.join("src/deep")), Some(repo.join(".fr.toml")));
+    }
+}
@@ -0,0 +1,105 @@
+//! Matching look-alikes of the find text, for `--confusables`.
+//!
+//! Text is compared by its skeleton: each character that looks like a Latin
+//! letter, such as Cyrillic `а` or Greek `Ο`, is read as that letter,
+//! fullwidth forms are read as ASCII, and invisible characters such as zero
+//! width joiners and soft hyphens are dropped. A match is then wherever the
+//! skeleton of the find text occurs in the skeleton of the file, so an
+//! obfuscated `pаy​pal` is found as `paypal` is, and replaced whole.
+
+use crate::invisible;
+use memchr::memmem;
+
+/// Look-alikes of Latin letters from other scripts, and the letter each is
+/// read as
+const LOOK_ALIKES: [(char, char); 54] = [
+    // Cyrillic
+    ('а', 'a'), ('в', 'b'), ('е', 'e'), ('о', 'o'), ('р', 'p'), ('с', 'c'), ('у', 'y'), ('х', 'x'), ('і', 'i'), ('ј', 'j'),
+    ('ѕ', 's'), ('һ', 'h'), ('ԁ', 'd'), ('ԛ', 'q'), ('ԝ', 'w'), ('ӏ', 'l'),
+    ('А', 'A'), ('В', 'B'), ('Е', 'E'), ('К', 'K'), ('М', 'M'), ('Н', 'H'), ('О', 'O'), ('Р', 'P'), ('С', 'C'), ('Т', 'T'),
+    ('Х', 'X'), ('У', 'Y'), ('І', 'I'), ('Ј', 'J'), ('Ѕ', 'S'), ('Ԛ', 'Q'), ('Ԝ', 'W'),
+    // Greek
+    ('ο', 'o'), ('ν', 'v'), ('ρ', 'p'), ('ι', 'i'),
+    ('Α', 'A'), ('Β', 'B'), ('Ε', 'E'), ('Ζ', 'Z'), ('Η', 'H'), ('Ι', 'I'), ('Κ', 'K'), ('Μ', 'M'), ('Ν', 'N'), ('Ο', 'O'),
+    ('Ρ', 'P'), ('Τ', 'T'), ('Υ', 'Y'), ('Χ', 'X'),
+    // Latin
+    ('ı', 'i'), ('ɑ', 'a'), ('ɡ', 'g'),
+];
+
+/// Reads a character as the one it looks like, or None if it is invisible.
+fn prototype(c: char) -> Option<char> {
+    if invisible::name(c).is_some() {
+        return None;
+    }
+    if let Some(&(_, latin)) = LOOK_ALIKES.iter().find(|&&(look_alike, _)| look_alike == c) {
+        return Some(latin);
+    }
+    // Fullwidth forms of ASCII, such as ａ
+    Some(match c {
+        '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+        c => c,
+    })
+}
+
+/// The skeleton of some text, with where in the text each of its bytes came
+/// from
+struct Skeleton {
+    text: String,
+    /// Where the character each byte of the skeleton came from starts and
+    /// ends in the original text
+    origins: Vec<(usize, usize)>,
+}
+
+impl Skeleton {
+    fn of(text: &str) -> Skeleton {
+        let mut skeleton = Skeleton { text: String::with_capacity(text.len()), origins: Vec::with_capacity(text.len()) };
+        for (start, c) in text.char_indices() {
+            if let Some(prototype) = prototype(c) {
+                skeleton.text.push(prototype);
+                let end = start + c.len_utf8();
+                skeleton.origins.extend(std::iter::repeat_n((start, end), prototype.len_utf8()));
+            }
+        }
+        skeleton
+    }
+}
+
+/// Finds where every non-overlapping look-alike of the find text starts and
+/// ends, the find text itself included.
+///
+/// # Arguments
+///
+/// * `content` - Text to search
+/// * `find_text` - Text to find, which must have something visible in it
+///
+/// # Returns
+///
+/// * `Vec<(usize, usize)>` - Where each match starts and ends in the text, in
+///   the order they appear
+pub fn spans(content: &str, find_text: &str) -> Vec<(usize, usize)> {
+    let find = Skeleton::of(find_text).text;
+    if find.is_empty() {
+        return Vec::new();
+    }
+    let skeleton = Skeleton::of(content);
+    memmem::find_iter(skeleton.text.as_bytes(), find.as_bytes())
+        .map(|offset| (skeleton.origins[offset].0, skeleton.origins[offset + find.len() - 1].1))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spans() {
+        let content = "paypal, pаypal, pay\u{200B}pal, ＰａｙＰａｌ and PayPal";
+        let found: Vec<&str> = spans(content, "paypal").into_iter().map(|(start, end)| &content[start..end]).collect();
+        assert_eq!(found, vec!["paypal", "pаypal", "pay\u{200B}pal"]);
+        let found: Vec<&str> = spans(content, "PayPal").into_iter().map(|(start, end)| &content[start..end]).collect();
+        assert_eq!(found, vec!["ＰａｙＰａｌ", "PayPal"]);
+        // The find text is read the same way
+        assert_eq!(spans("paypal", "pаypal"), vec![(0, 6)]);
+        assert!(spans("paypal", "\u{200B}").is_empty());
+    }
+}
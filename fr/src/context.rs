@@ -0,0 +1,140 @@
+//! Lines of context around each match in previews, for `-A`, `-B` and `-C`.
+//!
+//! The lines are shown as grep shows them: each one numbered, with a `:` after
+//! the number of a line with a match and a `-` after the others, and `--`
+//! between groups of lines that aren't next to each other. Each match is shown
+//! with what it would be replaced with, struck through and followed by the
+//! replacement when coloring, or as `[-old-]{+new+}` like `git diff
+//! --word-diff` when not.
+
+use crate::color::{Painter, Role};
+use std::ops::Range;
+
+/// How many lines to show before and after each match
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Context {
+    pub before: usize,
+    pub after: usize,
+}
+
+impl Context {
+    /// The lines of context a unified diff shows, which are the same before
+    /// and after each change.
+    pub fn diff_lines(&self) -> usize {
+        self.before.max(self.after)
+    }
+}
+
+/// Writes the lines around each match with the matches and their
+/// replacements highlighted.
+///
+/// # Arguments
+///
+/// * `content` - The text that was searched
+/// * `matches` - The bytes of the text that matched, in order, and what each
+///   is replaced with
+/// * `context` - How many lines to show around each match
+/// * `painter` - Colors the line numbers and matches
+///
+/// # Returns
+///
+/// * `String` - The lines, each ending with a newline
+pub fn render(content: &str, matches: &[(Range<usize>, &str)], context: Context, painter: &Painter) -> String {
+    let lines: Vec<(usize, &str)> = content.split_inclusive('\n')
+        .scan(0, |start, line| {
+            let line_start = *start;
+            *start += line.len();
+            Some((line_start, line))
+        })
+        .collect();
+    let line_of = |offset: usize| lines.partition_point(|&(start, _)| start <= offset).saturating_sub(1);
+
+    // The highlighted bytes of each line with a match, which a match over
+    // several lines has on each of them, with its replacement after the last
+    let mut highlights: Vec<Vec<(Range<usize>, Option<&str>)>> = vec![Vec::new(); lines.len()];
+    for (found, replacement) in matches {
+        let first = line_of(found.start);
+        let last = line_of(found.end.saturating_sub(1).max(found.start)).min(lines.len().saturating_sub(1));
+        for line in first..=last {
+            let (start, text) = lines[line];
+            let range = found.start.max(start) - start..found.end.min(start + text.len()) - start;
+            highlights[line].push((range, (line == last).then_some(*replacement)));
+        }
+    }
+
+    let mut output = String::new();
+    // The line after the last one shown
+    let mut shown_until = 0;
+    for line in (0..lines.len()).filter(|&line| !highlights[line].is_empty()) {
+        let first = line.saturating_sub(context.before).max(shown_until);
+        let last = (line + context.after).min(lines.len() - 1);
+        if first > last {
+            // Already shown as context of an earlier match
+            continue;
+        }
+        if first > shown_until && !output.is_empty() {
+            output.push_str("--\n");
+        }
+        for shown in first..=last {
+            output.push_str(&render_line(shown + 1, lines[shown].1, &highlights[shown], painter));
+        }
+        shown_until = last + 1;
+    }
+    output
+}
+
+/// Writes one numbered line, with its matches and their replacements
+/// highlighted.
+fn render_line(number: usize, text: &str, highlights: &[(Range<usize>, Option<&str>)], painter: &Painter) -> String {
+    let text = text.trim_end_matches('\n').trim_end_matches('\r');
+    let mut line = format!("{}{}", painter.paint(Role::LineNumber, &number.to_string()), if highlights.is_empty() { '-' } else { ':' });
+    let mut copied = 0;
+    for (range, replacement) in highlights {
+        if range.start < copied || range.start > text.len() {
+            continue;
+        }
+        let end = range.end.min(text.len());
+        line.push_str(&text[copied..range.start]);
+        let (removed, added) = (&text[range.start..end], replacement.map(|text| text.replace('\r', "").replace('\n', "\\n")));
+        match painter.is_coloring() {
+            true => {
+                line.push_str(&painter.emphasize(Role::Removed, removed));
+                line.push_str(&painter.paint(Role::Added, added.as_deref().unwrap_or("")));
+            }
+            false => {
+                line.push_str(&format!("[-{}-]", removed));
+                if let Some(added) = added {
+                    line.push_str(&format!("{{+{}+}}", added));
+                }
+            }
+        }
+        copied = end;
+    }
+    line.push_str(&text[copied..]);
+    line.push('\n');
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::{ColorChoice, Theme};
+
+    #[test]
+    fn test_render() {
+        let painter = Painter::new(ColorChoice::Never, Theme::default(), false);
+        let content = "one\nfoo two\nthree\nfour\nfive\nsix foo\r\nseven foo\r\n";
+        let matches = [(4..7, "bar"), (32..35, "bar"), (43..46, "")];
+        assert_eq!(render(content, &matches, Context { before: 1, after: 0 }, &painter),
+            "1-one\n2:[-foo-]{+bar+} two\n--\n5-five\n6:six [-foo-]{+bar+}\n7:seven [-foo-]{++}\n");
+        assert_eq!(render(content, &matches, Context { before: 0, after: 2 }, &painter),
+            "2:[-foo-]{+bar+} two\n3-three\n4-four\n--\n6:six [-foo-]{+bar+}\n7:seven [-foo-]{++}\n");
+        // A match over several lines is highlighted on each of them, with its
+        // replacement after the last
+        assert_eq!(render("a\nb\nc\n", &[(2..5, "x\ny")], Context::default(), &painter), "2:[-b-]\n3:[-c-]{+x\\ny+}\n");
+
+        let painter = Painter::new(ColorChoice::Always, Theme::Monochrome, false);
+        assert_eq!(render("a foo\n", &[(2..5, "bar")], Context::default(), &painter),
+            "\x1b[2m1\x1b[0m:a \x1b[2;9mfoo\x1b[0m\x1b[1mbar\x1b[0m\n");
+    }
+}
@@ -0,0 +1,369 @@
+//! DEFLATE (RFC 1951) decompression and compression, and the CRC-32 that zip
+//! and gzip check it with, for finding and replacing inside archives.
+//!
+//! Decompression handles every kind of block. Compression only uses the fixed
+//! Huffman codes, with matches found through hash chains, so what it writes is
+//! somewhat larger than what zlib would.
+
+use std::cmp;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+/// The order the lengths of the code length code are given in
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// How far back a match may start
+const WINDOW: usize = 32 * 1024;
+/// How many earlier positions with the same hash are tried for each match
+const MAX_CHAIN: usize = 64;
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { 0xedb8_8320 ^ (crc >> 1) } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Computes the CRC-32 of some bytes.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |crc, &byte| CRC_TABLE[((crc ^ u32::from(byte)) & 0xff) as usize] ^ (crc >> 8))
+}
+
+/// Reads bits from the bytes of a stream, lowest first
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    buffer: u64,
+    count: u32,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, count: u32) -> Result<u32, String> {
+        while self.count < count {
+            let byte = *self.bytes.get(self.position).ok_or("compressed data ends early")?;
+            self.buffer |= u64::from(byte) << self.count;
+            self.position += 1;
+            self.count += 8;
+        }
+        let bits = (self.buffer & ((1 << count) - 1)) as u32;
+        self.buffer >>= count;
+        self.count -= count;
+        Ok(bits)
+    }
+
+    /// Skips to the start of the next byte.
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+/// A canonical Huffman code, as the number of codes of each length and the
+/// symbols in code order
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for length in 1..16 {
+            offsets[length] = offsets[length - 1] + counts[length - 1];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, String> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for length in 1..16 {
+            code |= reader.bits(1)? as i32;
+            let count = i32::from(self.counts[length]);
+            if code - first < count {
+                return self.symbols.get((index + code - first) as usize).copied().ok_or_else(|| "invalid code".to_string());
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("invalid code".to_string())
+    }
+}
+
+/// The lengths of the fixed literal/length and distance codes
+fn fixed_lengths() -> ([u8; 288], [u8; 30]) {
+    let mut literals = [8; 288];
+    literals[144..256].fill(9);
+    literals[256..280].fill(7);
+    (literals, [5; 30])
+}
+
+/// Decompresses a DEFLATE stream.
+///
+/// # Arguments
+///
+/// * `bytes` - The stream, which may be followed by other data
+/// * `limit` - The most bytes to decompress, so a small archive can't fill
+///   memory
+///
+/// # Returns
+///
+/// * `Result<(Vec<u8>, usize), String>` - The decompressed bytes and how many
+///   bytes of input the stream took up, or why it couldn't be decompressed
+pub fn inflate(bytes: &[u8], limit: usize) -> Result<(Vec<u8>, usize), String> {
+    let mut reader = BitReader { bytes, position: 0, buffer: 0, count: 0 };
+    let mut output = Vec::new();
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.align();
+                let header = bytes.get(reader.position..reader.position + 4).ok_or("compressed data ends early")?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                if len != !u16::from_le_bytes([header[2], header[3]]) {
+                    return Err("invalid stored block".to_string());
+                }
+                let start = reader.position + 4;
+                let stored = bytes.get(start..start + len as usize).ok_or("compressed data ends early")?;
+                output.extend_from_slice(stored);
+                reader.position = start + len as usize;
+            }
+            1 => {
+                let (literals, distances) = fixed_lengths();
+                inflate_block(&mut reader, &mut output, &Huffman::new(&literals), &Huffman::new(&distances), limit)?;
+            }
+            2 => {
+                let literal_count = reader.bits(5)? as usize + 257;
+                let distance_count = reader.bits(5)? as usize + 1;
+                let code_length_count = reader.bits(4)? as usize + 4;
+                let mut code_lengths = [0; 19];
+                for &symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+                    code_lengths[symbol] = reader.bits(3)? as u8;
+                }
+                let code_length_code = Huffman::new(&code_lengths);
+                let mut lengths = Vec::with_capacity(literal_count + distance_count);
+                while lengths.len() < literal_count + distance_count {
+                    let (length, repeat) = match code_length_code.decode(&mut reader)? {
+                        symbol @ 0..=15 => (symbol as u8, 1),
+                        16 => (*lengths.last().ok_or("invalid code lengths")?, 3 + reader.bits(2)?),
+                        17 => (0, 3 + reader.bits(3)?),
+                        _ => (0, 11 + reader.bits(7)?),
+                    };
+                    lengths.extend(std::iter::repeat_n(length, repeat as usize));
+                }
+                if lengths.len() > literal_count + distance_count {
+                    return Err("invalid code lengths".to_string());
+                }
+                let (literals, distances) = lengths.split_at(literal_count);
+                inflate_block(&mut reader, &mut output, &Huffman::new(literals), &Huffman::new(distances), limit)?;
+            }
+            _ => return Err("invalid block type".to_string()),
+        }
+        if output.len() > limit {
+            return Err("decompresses to more than the size limit".to_string());
+        }
+        if last {
+            return Ok((output, reader.position));
+        }
+    }
+}
+
+/// Decompresses one block coded with Huffman codes, up to its end of block.
+fn inflate_block(reader: &mut BitReader, output: &mut Vec<u8>, literals: &Huffman, distances: &Huffman, limit: usize) -> Result<(), String> {
+    loop {
+        let symbol = literals.decode(reader)? as usize;
+        match symbol {
+            0..=255 => output.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let index = symbol - 257;
+                let (&base, &extra) = LENGTH_BASE.get(index).zip(LENGTH_EXTRA.get(index)).ok_or("invalid length")?;
+                let length = base as usize + reader.bits(u32::from(extra))? as usize;
+                let index = distances.decode(reader)? as usize;
+                let (&base, &extra) = DISTANCE_BASE.get(index).zip(DISTANCE_EXTRA.get(index)).ok_or("invalid distance")?;
+                let distance = base as usize + reader.bits(u32::from(extra))? as usize;
+                if distance > output.len() {
+                    return Err("distance goes back past the start".to_string());
+                }
+                let start = output.len() - distance;
+                for i in 0..length {
+                    output.push(output[start + i]);
+                }
+                if output.len() > limit {
+                    return Err("decompresses to more than the size limit".to_string());
+                }
+            }
+        }
+    }
+}
+
+/// Writes bits to a stream, lowest first
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn bits(&mut self, value: u32, count: u32) {
+        self.buffer |= u64::from(value) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Writes a Huffman code, which is sent highest bit first.
+    fn code(&mut self, code: u32, length: u32) {
+        self.bits(code.reverse_bits() >> (32 - length), length);
+    }
+
+    fn literal(&mut self, symbol: usize) {
+        let symbol = symbol as u32;
+        match symbol {
+            0..=143 => self.code(0x30 + symbol, 8),
+            144..=255 => self.code(0x190 + symbol - 144, 9),
+            256..=279 => self.code(symbol - 256, 7),
+            _ => self.code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+/// Which of the base values a length or distance falls under.
+fn base_index(bases: &[u16], value: usize) -> usize {
+    bases.partition_point(|&base| base as usize <= value) - 1
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    ((usize::from(bytes[0]) << 10) ^ (usize::from(bytes[1]) << 5) ^ usize::from(bytes[2])) & (WINDOW - 1)
+}
+
+/// Adds a position to the hash chains, so later matches can start there.
+fn insert(head: &mut [usize], previous: &mut [usize], bytes: &[u8], position: usize) {
+    if position + 3 <= bytes.len() {
+        let hash = hash(&bytes[position..]);
+        previous[position] = head[hash];
+        head[hash] = position;
+    }
+}
+
+/// Compresses bytes into a DEFLATE stream of one block.
+pub fn deflate(bytes: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter { bytes: Vec::with_capacity(bytes.len() / 2), buffer: 0, count: 0 };
+    // The last block, with fixed codes
+    writer.bits(1, 1);
+    writer.bits(1, 2);
+
+    let mut head = vec![usize::MAX; WINDOW];
+    let mut previous = vec![usize::MAX; bytes.len()];
+    let mut position = 0;
+    while position < bytes.len() {
+        let (mut best_length, mut best_distance) = (0, 0);
+        if position + 3 <= bytes.len() {
+            let max_length = cmp::min(258, bytes.len() - position);
+            let mut candidate = head[hash(&bytes[position..])];
+            for _ in 0..MAX_CHAIN {
+                if candidate == usize::MAX || position - candidate > WINDOW {
+                    break;
+                }
+                let length = bytes[candidate..].iter().zip(&bytes[position..position + max_length]).take_while(|(a, b)| a == b).count();
+                if length > best_length {
+                    (best_length, best_distance) = (length, position - candidate);
+                    if length == max_length {
+                        break;
+                    }
+                }
+                candidate = previous[candidate];
+            }
+        }
+        if best_length >= 3 {
+            let index = base_index(&LENGTH_BASE, best_length);
+            writer.literal(257 + index);
+            writer.bits((best_length - LENGTH_BASE[index] as usize) as u32, u32::from(LENGTH_EXTRA[index]));
+            let index = base_index(&DISTANCE_BASE, best_distance);
+            writer.code(index as u32, 5);
+            writer.bits((best_distance - DISTANCE_BASE[index] as usize) as u32, u32::from(DISTANCE_EXTRA[index]));
+            for skipped in position..position + best_length {
+                insert(&mut head, &mut previous, bytes, skipped);
+            }
+            position += best_length;
+        } else {
+            writer.literal(bytes[position] as usize);
+            insert(&mut head, &mut previous, bytes, position);
+            position += 1;
+        }
+    }
+    writer.literal(256);
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn test_deflate_round_trip() {
+        let mut text = "fn main() {\n    println!(\"hello\");\n}\n".repeat(200).into_bytes();
+        text.extend_from_slice(b"\xe9\0\xff");
+        for input in [&b""[..], b"a", b"abcabcabcabcabcabc", &text] {
+            let compressed = deflate(input);
+            assert_eq!(inflate(&compressed, usize::MAX).unwrap(), (input.to_vec(), compressed.len()));
+        }
+        assert!(deflate(&text).len() < text.len() / 10);
+        assert!(inflate(&deflate(&text), 100).is_err());
+    }
+
+    #[test]
+    fn test_inflate_stored_and_dynamic_blocks() {
+        // A stored block, then trailing bytes that aren't part of the stream
+        assert_eq!(inflate(b"\x01\x03\x00\xfc\xffabc!", usize::MAX).unwrap(), (b"abc".to_vec(), 8));
+        // Text compressed by zlib, which codes it with a dynamic block
+        let dynamic = b"\xb5\xcb\xd1\x01\x80\x10\x14\x46\xe1\x55\xfe\x16\x68\x96\x1e\x2c\x40\x11\x15\x37\x84\x98\xbe\xbb\x44\xcf\xe7\x3b\xc2\x6a\xc4\xe2\xd6\x13\x2a\x51\x0b\x30\xf4\xe2\x28\xfe\xce\xa0\xaa\x13\x1e\xce\x97\x1c\x1d\x1b\xed\x33\xc4\x6f\x78\x91\xec\x7c\x87\x62\xd4\xdc\x63\x61\x5c\xd5\x9c\x86\x0e\xb8\x5c\x2c\x94\xf8\xdd\xf3\xf4\x01";
+        let text = "The quick brown fox jumps over the lazy dog. ".repeat(3) + "Pack my box with five dozen liquor jugs!";
+        assert_eq!(inflate(dynamic, usize::MAX).unwrap(), (text.into_bytes(), dynamic.len()));
+        assert!(inflate(b"\x07", usize::MAX).is_err());
+    }
+}
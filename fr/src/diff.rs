@@ -0,0 +1,416 @@
+//! Line-level differences between a file before and after a replacement.
+//!
+//! Because replacements are literal, the changed lines can be worked out
+//! directly from where the matches are instead of running a general diff.
+//! Rewrites that also change other lines, such as converting line endings with
+//! `--eol`, are shown as one hunk spanning every changed line.
+
+use crate::color::{Painter, Role};
+use std::fmt::Write as _;
+use std::path::Path;
+
+/// A run of consecutive lines changed by one or more replacements
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    /// 1-based line number of the first removed line in the original file
+    pub old_start: usize,
+    /// 1-based line number of the first added line in the rewritten file
+    pub new_start: usize,
+    /// Lines of the original file, without their trailing `\n`
+    pub removed: Vec<String>,
+    /// Lines of the rewritten file, without their trailing `\n`
+    pub added: Vec<String>,
+}
+
+/// Splits text into lines, removing each `\n` but keeping any `\r` so that
+/// CRLF files can be diffed and patched faithfully.
+fn split_lines(text: &str) -> impl Iterator<Item = &str> {
+    text.split_inclusive('\n').map(|line| line.strip_suffix('\n').unwrap_or(line))
+}
+
+/// Computes the lines changed by replacing occurrences of the find text: all
+/// of them, or only some, as when only those in values are.
+///
+/// # Arguments
+///
+/// * `before` - Contents of the file before the replacement
+/// * `matches` - Where each replaced occurrence starts, in order
+/// * `find_len` - Length of the find text in bytes
+/// * `replace_text` - Text each occurrence was replaced with
+///
+/// # Returns
+///
+/// * `Vec<Hunk>` - The changed regions in the order they appear
+pub fn hunks(before: &str, matches: &[usize], find_len: usize, replace_text: &str) -> Vec<Hunk> {
+    let mut hunks = Vec::new();
+    if find_len == 0 {
+        return hunks;
+    }
+
+    let mut line_starts = vec![0];
+    line_starts.extend(before.match_indices('\n').map(|(offset, _)| offset + 1));
+    let line_of = |offset: usize| line_starts.partition_point(|&start| start <= offset) - 1;
+    let line_end = |line: usize| line_starts.get(line + 1).copied().unwrap_or(before.len());
+
+    let mut line_delta: isize = 0;
+    let mut i = 0;
+    while i < matches.len() {
+        let first_line = line_of(matches[i]);
+        let region_start = line_starts[first_line];
+        let mut region_end = line_end(line_of(matches[i] + find_len - 1));
+        let mut j = i + 1;
+        let replaced = loop {
+            // Pull in every match that starts inside the region so far
+            while j < matches.len() && matches[j] < region_end {
+                region_end = region_end.max(line_end(line_of(matches[j] + find_len - 1)));
+                j += 1;
+            }
+
+            let mut replaced = String::new();
+            let mut copied = region_start;
+            for &offset in &matches[i..j] {
+                replaced.push_str(&before[copied..offset]);
+                replaced.push_str(replace_text);
+                copied = offset + find_len;
+            }
+            replaced.push_str(&before[copied..region_end]);
+
+            // If the replacement removed the region's final newline, the next
+            // line is joined onto it and so has changed too
+            if replaced.is_empty() || replaced.ends_with('\n') || region_end == before.len() {
+                break replaced;
+            }
+            region_end = line_end(line_of(region_end));
+        };
+
+        let removed: Vec<String> = split_lines(&before[region_start..region_end]).map(String::from).collect();
+        let added: Vec<String> = split_lines(&replaced).map(String::from).collect();
+        let old_start = first_line + 1;
+        let new_start = (old_start as isize + line_delta) as usize;
+        line_delta += added.len() as isize - removed.len() as isize;
+        hunks.push(Hunk { old_start, new_start, removed, added });
+        i = j;
+    }
+    hunks
+}
+
+/// Computes one hunk spanning every line that differs between two versions of
+/// a file, from the first changed line to the last.
+///
+/// # Arguments
+///
+/// * `before` - Contents of the file before it was rewritten
+/// * `after` - Contents of the file after it was rewritten
+///
+/// # Returns
+///
+/// * `Vec<Hunk>` - The changed region, or nothing if the contents are the same
+pub fn spanning(before: &str, after: &str) -> Vec<Hunk> {
+    // Compare lines with their endings, so a change to only the ending counts
+    let old_lines: Vec<&str> = before.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = after.split_inclusive('\n').collect();
+    let prefix = old_lines.iter().zip(&new_lines).take_while(|(old, new)| old == new).count();
+    let suffix = old_lines[prefix..].iter().rev()
+        .zip(new_lines[prefix..].iter().rev())
+        .take_while(|(old, new)| old == new)
+        .count();
+    let removed = &old_lines[prefix..old_lines.len() - suffix];
+    let added = &new_lines[prefix..new_lines.len() - suffix];
+    if removed.is_empty() && added.is_empty() {
+        return Vec::new();
+    }
+
+    let strip = |line: &&str| line.strip_suffix('\n').unwrap_or(line).to_string();
+    vec![Hunk {
+        old_start: prefix + 1,
+        new_start: prefix + 1,
+        removed: removed.iter().map(strip).collect(),
+        added: added.iter().map(strip).collect(),
+    }]
+}
+
+/// Paints a removed or added line with the part that differs from the line
+/// it is paired with emphasized.
+fn highlight_line(marker: char, line: &str, other: &str, role: Role, painter: &Painter) -> String {
+    let (prefix, suffix) = shared_ends(line, other);
+    format!("{}{}{}", painter.paint(role, &format!("{}{}", marker, &line[..prefix])),
+        painter.emphasize(role, &line[prefix..line.len() - suffix]),
+        painter.paint(role, &line[line.len() - suffix..]))
+}
+
+/// Finds how many bytes at the start and at the end two lines share, without
+/// the two overlapping or splitting a character.
+fn shared_ends(line: &str, other: &str) -> (usize, usize) {
+    let mut prefix = line.bytes().zip(other.bytes()).take_while(|(a, b)| a == b).count();
+    while !line.is_char_boundary(prefix) {
+        prefix -= 1;
+    }
+    let most = line.len().min(other.len()) - prefix;
+    let mut suffix = line.bytes().rev().zip(other.bytes().rev()).take(most).take_while(|(a, b)| a == b).count();
+    while !line.is_char_boundary(line.len() - suffix) || !other.is_char_boundary(other.len() - suffix) {
+        suffix -= 1;
+    }
+    (prefix, suffix)
+}
+
+/// Formats a unified diff range, which omits the count when it is one and
+/// points at the line before when it is empty.
+fn unified_range(start: usize, count: usize) -> String {
+    match count {
+        0 => format!("{},0", start.saturating_sub(1)),
+        1 => start.to_string(),
+        _ => format!("{},{}", start, count),
+    }
+}
+
+/// Renders a file's changes as a unified diff that `patch` and `git apply` accept.
+///
+/// # Arguments
+///
+/// * `path` - Path of the file, shown in the `---`/`+++` headers
+/// * `before` - Contents of the file before the replacement
+/// * `after` - Contents of the file after the replacement
+/// * `hunks` - The changed lines, as computed by `hunks`
+/// * `context` - Number of unchanged lines to show around each change
+/// * `painter` - Colors for the diff
+pub fn unified(path: &Path, before: &str, after: &str, hunks: &[Hunk], context: usize, painter: &Painter) -> String {
+    const NO_NEWLINE: &str = "\\ No newline at end of file\n";
+    let mut out = String::new();
+    if hunks.is_empty() {
+        return out;
+    }
+
+    let old_lines: Vec<&str> = split_lines(before).collect();
+    let old_missing_newline = !before.is_empty() && !before.ends_with('\n');
+    let new_missing_newline = !after.is_empty() && !after.ends_with('\n');
+    let path = path.display();
+    let _ = writeln!(out, "{}", painter.paint(Role::Path, &format!("--- a/{}", path)));
+    let _ = writeln!(out, "{}", painter.paint(Role::Path, &format!("+++ b/{}", path)));
+
+    let mut first = 0;
+    while first < hunks.len() {
+        // Gather changes whose surrounding context would touch or overlap
+        let mut last = first;
+        while last + 1 < hunks.len() {
+            let end = hunks[last].old_start + hunks[last].removed.len();
+            if hunks[last + 1].old_start > end + 2 * context {
+                break;
+            }
+            last += 1;
+        }
+
+        let old_start = hunks[first].old_start.saturating_sub(context).max(1);
+        let old_end = (hunks[last].old_start + hunks[last].removed.len() - 1 + context).min(old_lines.len());
+        let removed: usize = hunks[first..=last].iter().map(|hunk| hunk.removed.len()).sum();
+        let added: usize = hunks[first..=last].iter().map(|hunk| hunk.added.len()).sum();
+        let old_count = old_end + 1 - old_start;
+        let new_count = old_count - removed + added;
+        let new_start = hunks[first].new_start - (hunks[first].old_start - old_start);
+        let _ = writeln!(out, "{}", painter.paint(Role::LineNumber, &format!("@@ -{} +{} @@",
+            unified_range(old_start, old_count),
+            unified_range(new_start, new_count))));
+
+        let mut line = old_start;
+        for hunk in &hunks[first..=last] {
+            for context_line in line..hunk.old_start {
+                let _ = writeln!(out, " {}", old_lines[context_line - 1]);
+            }
+            // When coloring, the part of each line that changed stands out
+            // from the rest, where lines are replaced one for one
+            let paired = painter.is_coloring() && hunk.removed.len() == hunk.added.len();
+            for (i, removed_line) in hunk.removed.iter().enumerate() {
+                let _ = match paired {
+                    true => writeln!(out, "{}", highlight_line('-', removed_line, &hunk.added[i], Role::Removed, painter)),
+                    false => writeln!(out, "{}", painter.paint(Role::Removed, &format!("-{}", removed_line))),
+                };
+            }
+            line = hunk.old_start + hunk.removed.len();
+            let reaches_end = line > old_lines.len();
+            if reaches_end && old_missing_newline {
+                out.push_str(NO_NEWLINE);
+            }
+            for (i, added_line) in hunk.added.iter().enumerate() {
+                let _ = match paired {
+                    true => writeln!(out, "{}", highlight_line('+', added_line, &hunk.removed[i], Role::Added, painter)),
+                    false => writeln!(out, "{}", painter.paint(Role::Added, &format!("+{}", added_line))),
+                };
+            }
+            if reaches_end && new_missing_newline && !hunk.added.is_empty() {
+                out.push_str(NO_NEWLINE);
+            }
+        }
+        for context_line in line..=old_end {
+            let _ = writeln!(out, " {}", old_lines[context_line - 1]);
+            if context_line == old_lines.len() && old_missing_newline {
+                out.push_str(NO_NEWLINE);
+            }
+        }
+        first = last + 1;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    /// The hunks of replacing every occurrence of the find text.
+    fn hunks(before: &str, find_text: &str, replace_text: &str) -> Vec<Hunk> {
+        let matches: Vec<usize> = before.match_indices(find_text).map(|(offset, _)| offset).collect();
+        super::hunks(before, &matches, find_text.len(), replace_text)
+    }
+
+    #[test]
+    fn test_hunks_single_lines() {
+        let before = "foo one\nkeep\nfoo two foo\n";
+        assert_eq!(hunks(before, "foo", "bar"), vec![
+            Hunk { old_start: 1, new_start: 1, removed: lines(&["foo one"]), added: lines(&["bar one"]) },
+            Hunk { old_start: 3, new_start: 3, removed: lines(&["foo two foo"]), added: lines(&["bar two bar"]) },
+        ]);
+    }
+
+    #[test]
+    fn test_hunks_some_matches() {
+        assert_eq!(super::hunks("foo one\nkeep\nfoo two foo\n", &[21], 3, "bar"), vec![
+            Hunk { old_start: 3, new_start: 3, removed: lines(&["foo two foo"]), added: lines(&["foo two bar"]) },
+        ]);
+    }
+
+    #[test]
+    fn test_hunks_changing_line_counts() {
+        let before = "a\nfoo\nb\nfoo\n";
+        assert_eq!(hunks(before, "foo", "x\ny"), vec![
+            Hunk { old_start: 2, new_start: 2, removed: lines(&["foo"]), added: lines(&["x", "y"]) },
+            Hunk { old_start: 4, new_start: 5, removed: lines(&["foo"]), added: lines(&["x", "y"]) },
+        ]);
+    }
+
+    #[test]
+    fn test_hunks_multiline_find() {
+        // Removing a whole line
+        assert_eq!(hunks("a\nfoo\nb\n", "foo\n", ""), vec![
+            Hunk { old_start: 2, new_start: 2, removed: lines(&["foo"]), added: vec![] },
+        ]);
+
+        // Removing a newline joins the following line
+        assert_eq!(hunks("a\nb\nc\n", "a\n", "x"), vec![
+            Hunk { old_start: 1, new_start: 1, removed: lines(&["a", "b"]), added: lines(&["xb"]) },
+        ]);
+    }
+
+    #[test]
+    fn test_hunks_keep_carriage_returns() {
+        assert_eq!(hunks("foo\r\nbar\r\n", "foo", "baz"), vec![
+            Hunk { old_start: 1, new_start: 1, removed: lines(&["foo\r"]), added: lines(&["baz\r"]) },
+        ]);
+    }
+
+    fn unified_diff(before: &str, find_text: &str, replace_text: &str, context: usize) -> String {
+        let after = before.replace(find_text, replace_text);
+        let hunks = hunks(before, find_text, replace_text);
+        unified(Path::new("dir/file.txt"), before, &after, &hunks, context, &Painter::default())
+    }
+
+    #[test]
+    fn test_spanning() {
+        assert_eq!(spanning("a\nb\nc\nd\n", "a\r\nb\nc\r\nd\n"), vec![Hunk {
+            old_start: 1,
+            new_start: 1,
+            removed: lines(&["a", "b", "c"]),
+            added: lines(&["a\r", "b", "c\r"]),
+        }]);
+        assert_eq!(spanning("a\nb", "a\nb\n"), vec![Hunk {
+            old_start: 2,
+            new_start: 2,
+            removed: lines(&["b"]),
+            added: lines(&["b"]),
+        }]);
+        assert_eq!(spanning("a\nb\n", "a\nb\n"), vec![]);
+    }
+
+    #[test]
+    fn test_unified() {
+        let before = "1\n2\nfoo\n4\n5\n6\n7\n8\n9\n10\nfoo\n12\n";
+        assert_eq!(unified_diff(before, "foo", "bar", 2), concat!(
+            "--- a/dir/file.txt\n",
+            "+++ b/dir/file.txt\n",
+            "@@ -1,5 +1,5 @@\n",
+            " 1\n 2\n-foo\n+bar\n 4\n 5\n",
+            "@@ -9,4 +9,4 @@\n",
+            " 9\n 10\n-foo\n+bar\n 12\n",
+        ));
+
+        // Nearby changes share a hunk
+        assert_eq!(unified_diff(before, "foo", "bar", 4), concat!(
+            "--- a/dir/file.txt\n",
+            "+++ b/dir/file.txt\n",
+            "@@ -1,12 +1,12 @@\n",
+            " 1\n 2\n-foo\n+bar\n 4\n 5\n 6\n 7\n 8\n 9\n 10\n-foo\n+bar\n 12\n",
+        ));
+    }
+
+    #[test]
+    fn test_unified_line_count_changes() {
+        assert_eq!(unified_diff("a\nfoo\nb\n", "foo\n", "", 1), concat!(
+            "--- a/dir/file.txt\n",
+            "+++ b/dir/file.txt\n",
+            "@@ -1,3 +1,2 @@\n",
+            " a\n-foo\n b\n",
+        ));
+        assert_eq!(unified_diff("foo", "foo", "", 0), concat!(
+            "--- a/dir/file.txt\n",
+            "+++ b/dir/file.txt\n",
+            "@@ -1 +0,0 @@\n",
+            "-foo\n",
+            "\\ No newline at end of file\n",
+        ));
+    }
+
+    #[test]
+    fn test_unified_no_newline_at_end() {
+        assert_eq!(unified_diff("a\nfoo", "foo", "bar", 1), concat!(
+            "--- a/dir/file.txt\n",
+            "+++ b/dir/file.txt\n",
+            "@@ -1,2 +1,2 @@\n",
+            " a\n-foo\n",
+            "\\ No newline at end of file\n",
+            "+bar\n",
+            "\\ No newline at end of file\n",
+        ));
+        assert_eq!(unified_diff("foo\nb", "foo", "bar", 1), concat!(
+            "--- a/dir/file.txt\n",
+            "+++ b/dir/file.txt\n",
+            "@@ -1,2 +1,2 @@\n",
+            "-foo\n+bar\n b\n",
+            "\\ No newline at end of file\n",
+        ));
+    }
+
+    #[test]
+    fn test_hunks_no_match() {
+        assert!(hunks("nothing here", "foo", "bar").is_empty());
+        assert!(hunks("nothing here", "", "bar").is_empty());
+    }
+
+    #[test]
+    fn test_unified_highlights_changed_part() {
+        use crate::color::{ColorChoice, Theme};
+        let painter = Painter::new(ColorChoice::Always, Theme::Monochrome, false);
+        let before = "let a = getUser();\n";
+        let after = "let a = fetchUser();\n";
+        let diff = unified(Path::new("a.js"), before, after, &hunks(before, "get", "fetch"), 0, &painter);
+        assert!(diff.contains("\x1b[2m-let a = \x1b[0m\x1b[2;9mget\x1b[0m\x1b[2mUser();\x1b[0m\n"));
+        assert!(diff.contains("\x1b[1m+let a = \x1b[0m\x1b[1;7mfetch\x1b[0m\x1b[1mUser();\x1b[0m\n"));
+    }
+
+    #[test]
+    fn test_shared_ends() {
+        assert_eq!(shared_ends("let a = get;", "let a = fetch;"), (8, 1));
+        assert_eq!(shared_ends("aaa", "aa"), (2, 0));
+        assert_eq!(shared_ends("café", "cafè"), (3, 0));
+    }
+}
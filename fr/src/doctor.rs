@@ -0,0 +1,151 @@
+//! Checking a rules file for rules that get in each other's way, for
+//! `fr doctor`.
+//!
+//! `fr lint --fix` fixes the leftmost and longest match where terms overlap,
+//! and the rule listed first where they match the same text, so a rule can
+//! lose to another without anything in the file looking wrong. The rules are
+//! compared pairwise, without reading any other file, for:
+//!
+//! * conflicts, where a term is listed twice, so only its first suggestion is
+//!   ever used
+//! * overlaps, where the end of one term is the start of another, so in text
+//!   matching both the second is left for the next fix
+//! * oscillation, where a suggestion contains a term, so fixing twice doesn't
+//!   leave the tree as fixing once does
+//!
+//! A term containing another is no problem: the longer one is fixed where
+//! both match.
+
+use crate::lint::Rule;
+
+/// A problem with a rules file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    /// The 0-based rule the problem is with
+    pub rule: usize,
+    /// The 0-based rule it is caused by
+    pub other: usize,
+    pub kind: Kind,
+}
+
+/// What is wrong with a rule
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Kind {
+    /// The rule's term is listed earlier with another suggestion
+    Conflict,
+    /// The end of the rule's term is the start of the other's, as in the text
+    /// given
+    Overlap(String),
+    /// The rule's suggestion contains the other's term, which replaces it on
+    /// the next fix
+    Rewritten,
+}
+
+/// Finds the rules that conflict with, overlap or rewrite each other.
+///
+/// # Arguments
+///
+/// * `rules` - The rules, in the order they are applied
+///
+/// # Returns
+///
+/// * `Vec<Finding>` - The problems, by rule
+pub fn check(rules: &[Rule]) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for (i, rule) in rules.iter().enumerate() {
+        // Rules for files with different extensions never meet
+        for (j, other) in rules.iter().enumerate().filter(|(_, other)| !rule.files.is_disjoint(&other.files)) {
+            let finding = |kind| Finding { rule: i, other: j, kind };
+            // Rules without a suggestion are only reported, never applied
+            if j < i && !other.suggestion.is_empty() && other.term == rule.term {
+                if other.suggestion != rule.suggestion {
+                    findings.push(finding(Kind::Conflict));
+                }
+                continue;
+            }
+            let nested = rule.term.contains(&other.term) || other.term.contains(&rule.term);
+            if let Some(text) = overlap(&rule.term, &other.term).filter(|_| !nested) {
+                findings.push(finding(Kind::Overlap(text)));
+            }
+            if !rule.suggestion.is_empty() && !other.suggestion.is_empty() && rule.suggestion.contains(&other.term) {
+                findings.push(finding(Kind::Rewritten));
+            }
+        }
+    }
+    findings
+}
+
+/// Describes a problem with a rules file.
+pub fn describe(finding: &Finding, rules: &[Rule]) -> String {
+    let (rule, other) = (&rules[finding.rule], &rules[finding.other]);
+    let name = |index: usize, rule: &Rule| format!("rule {} ({:?})", index + 1, rule.term);
+    let (this, that) = (name(finding.rule, rule), name(finding.other, other));
+    match &finding.kind {
+        Kind::Conflict => format!("{} conflicts with {}, which replaces the same term with {:?}, so its suggestion is never used",
+            this, that, other.suggestion),
+        Kind::Overlap(text) => format!("{} overlaps {} in text like {:?}, where it is fixed, since it starts first, and the other is left",
+            this, that, text),
+        Kind::Rewritten if finding.other == finding.rule => {
+            format!("{} would oscillate: its suggestion {:?} contains its own term, so every fix adds another", this, rule.suggestion)
+        }
+        Kind::Rewritten => {
+            format!("{} would oscillate: its suggestion {:?} contains the term of {}, so fixing again changes it", this, rule.suggestion, that)
+        }
+    }
+}
+
+/// Finds where the end of one term is the start of another, returning the
+/// shortest text both match in.
+fn overlap(first: &str, second: &str) -> Option<String> {
+    (1..first.len().min(second.len()))
+        .rev()
+        .filter(|&length| first.is_char_boundary(first.len() - length) && second.is_char_boundary(length))
+        .find(|&length| first[first.len() - length..] == second[..length])
+        .map(|length| format!("{}{}", first, &second[length..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lint::Scope;
+
+    fn rules(pairs: &[(&str, &str)]) -> Vec<Rule> {
+        pairs.iter().map(|&(term, suggestion)| Rule { term: term.to_string(), suggestion: suggestion.to_string(), files: Scope::default() }).collect()
+    }
+
+    #[test]
+    fn test_check() {
+        assert!(check(&rules(&[("master", "main"), ("whitelist", "allowlist")])).is_empty());
+
+        let kinds = |pairs: &[(&str, &str)]| -> Vec<(usize, usize, Kind)> {
+            check(&rules(pairs)).into_iter().map(|finding| (finding.rule, finding.other, finding.kind)).collect()
+        };
+        assert_eq!(kinds(&[("master", "main"), ("master", "primary")]), vec![(1, 0, Kind::Conflict)]);
+        // The longest match is fixed, whichever rule is listed first
+        assert!(kinds(&[("master", "main"), ("master branch", "main branch")]).is_empty());
+        assert!(kinds(&[("master branch", "main branch"), ("master", "main")]).is_empty());
+        assert_eq!(kinds(&[("foo", "foofoo")]), vec![(0, 0, Kind::Rewritten)]);
+        assert_eq!(kinds(&[("a", "b"), ("b", "a")]), vec![(0, 1, Kind::Rewritten), (1, 0, Kind::Rewritten)]);
+        assert_eq!(kinds(&[("sanity check", "quick check"), ("check in", "commit")]),
+            vec![(0, 1, Kind::Overlap("sanity check in".to_string()))]);
+        let scoped = crate::lint::parse_rules("print = { suggestion = \"log\", types = [\"py\"] }\nprint = { suggestion = \"console.log\", include = [\"*.js\"] }\n").unwrap();
+        assert!(check(&scoped).is_empty());
+    }
+
+    #[test]
+    fn test_describe() {
+        let rules = rules(&[("a", "b"), ("b", "a")]);
+        let findings = check(&rules);
+        assert_eq!(describe(&findings[0], &rules),
+            "rule 1 (\"a\") would oscillate: its suggestion \"b\" contains the term of rule 2 (\"b\"), so fixing again changes it");
+        assert_eq!(describe(&findings[1], &rules),
+            "rule 2 (\"b\") would oscillate: its suggestion \"a\" contains the term of rule 1 (\"a\"), so fixing again changes it");
+    }
+
+    #[test]
+    fn test_overlap() {
+        assert_eq!(overlap("sanity check", "check in").as_deref(), Some("sanity check in"));
+        assert_eq!(overlap("check in", "sanity check"), None);
+        assert_eq!(overlap("abc", "xyz"), None);
+    }
+}
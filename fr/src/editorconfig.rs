@@ -0,0 +1,247 @@
+//! Project conventions declared in `.editorconfig` files.
+//!
+//! Only the `charset` and `end_of_line` properties are used, to decide which
+//! encoding a file is read in and which line endings it is written with. See
+//! <https://editorconfig.org> for the file format.
+
+use crate::encoding::Encoding;
+use crate::eol::EolPolicy;
+use globset::{Glob, GlobMatcher};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// The name of the files EditorConfig settings are read from
+const FILE_NAME: &str = ".editorconfig";
+
+/// The settings that apply to one file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Settings {
+    /// The encoding from the `charset` property
+    pub charset: Option<Encoding>,
+    /// The line endings from the `end_of_line` property
+    pub end_of_line: Option<EolPolicy>,
+}
+
+/// A section of an `.editorconfig` file
+#[derive(Debug)]
+struct Section {
+    /// Matches paths relative to the directory of the `.editorconfig` file
+    matcher: GlobMatcher,
+    settings: Settings,
+}
+
+/// A parsed `.editorconfig` file
+#[derive(Debug)]
+struct ConfigFile {
+    /// Whether the search for `.editorconfig` files stops here
+    root: bool,
+    sections: Vec<Section>,
+}
+
+impl ConfigFile {
+    /// Parses the contents of an `.editorconfig` file. Lines that can't be
+    /// parsed and sections with invalid globs are ignored, as editors do.
+    fn parse(contents: &str) -> ConfigFile {
+        let mut config = ConfigFile { root: false, sections: Vec::new() };
+        let mut in_preamble = true;
+        // Whether the properties that follow belong to a section whose glob is invalid
+        let mut in_invalid_section = false;
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if let Some(glob) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                in_preamble = false;
+                match section_matcher(glob) {
+                    Some(matcher) => {
+                        config.sections.push(Section { matcher, settings: Settings::default() });
+                        in_invalid_section = false;
+                    }
+                    None => in_invalid_section = true,
+                }
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let key = key.trim().to_ascii_lowercase();
+            let value = value.trim().to_ascii_lowercase();
+            if in_preamble {
+                if key == "root" {
+                    config.root = value == "true";
+                }
+                continue;
+            }
+            let Some(section) = config.sections.last_mut().filter(|_| !in_invalid_section) else {
+                continue;
+            };
+            match key.as_str() {
+                "charset" => section.settings.charset = charset(&value),
+                "end_of_line" => section.settings.end_of_line = end_of_line(&value),
+                _ => {}
+            }
+        }
+        config
+    }
+}
+
+/// Builds a matcher for a section's glob. Globs without a `/` match files with
+/// that name in any directory; the others match from the `.editorconfig`'s
+/// directory.
+fn section_matcher(glob: &str) -> Option<GlobMatcher> {
+    let glob = if glob.contains('/') {
+        glob.trim_start_matches('/').to_string()
+    } else {
+        format!("**/{}", glob)
+    };
+    globset::GlobBuilder::new(&glob)
+        .literal_separator(true)
+        .build()
+        .ok()
+        .map(|glob: Glob| glob.compile_matcher())
+}
+
+/// Maps a `charset` value to an encoding. `unset` and unknown values leave the
+/// encoding unset.
+fn charset(value: &str) -> Option<Encoding> {
+    match value {
+        "latin1" => Some(Encoding::Latin1),
+        "utf-8" => Some(Encoding::Utf8),
+        "utf-8-bom" => Some(Encoding::Utf8Bom),
+        "utf-16le" => Some(Encoding::Utf16Le),
+        "utf-16be" => Some(Encoding::Utf16Be),
+        _ => None,
+    }
+}
+
+/// Maps an `end_of_line` value to a line ending policy. `cr` isn't supported
+/// and, like `unset`, leaves the line endings alone.
+fn end_of_line(value: &str) -> Option<EolPolicy> {
+    match value {
+        "lf" => Some(EolPolicy::Lf),
+        "crlf" => Some(EolPolicy::Crlf),
+        _ => None,
+    }
+}
+
+/// Looks up the EditorConfig settings of files, reading each `.editorconfig`
+/// file once however many files it applies to
+#[derive(Debug, Default)]
+pub struct EditorConfig {
+    /// The parsed `.editorconfig` of each directory looked in, or None if there
+    /// is no readable one
+    files: Mutex<HashMap<PathBuf, Option<Arc<ConfigFile>>>>,
+}
+
+impl EditorConfig {
+    /// Returns the parsed `.editorconfig` in a directory, reading it if it
+    /// hasn't been read yet.
+    fn config_file(&self, directory: &Path) -> Option<Arc<ConfigFile>> {
+        if let Some(config) = self.files.lock().unwrap().get(directory) {
+            return config.clone();
+        }
+        let config = fs::read_to_string(directory.join(FILE_NAME)).ok()
+            .map(|contents| Arc::new(ConfigFile::parse(&contents)));
+        self.files.lock().unwrap().insert(directory.to_path_buf(), config.clone());
+        config
+    }
+
+    /// Works out the settings that apply to a file, from the `.editorconfig`
+    /// files in its directory and the ones above it up to one marked `root`.
+    /// Closer files and later sections take precedence.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - Path to the file
+    pub fn settings(&self, file_path: &Path) -> Settings {
+        let Ok(file_path) = std::path::absolute(file_path) else {
+            return Settings::default();
+        };
+
+        let mut configs = Vec::new();
+        for directory in file_path.ancestors().skip(1) {
+            if let Some(config) = self.config_file(directory) {
+                let root = config.root;
+                configs.push((directory, config));
+                if root {
+                    break;
+                }
+            }
+        }
+
+        let mut settings = Settings::default();
+        for (directory, config) in configs.iter().rev() {
+            let Ok(relative) = file_path.strip_prefix(directory) else {
+                continue;
+            };
+            for section in config.sections.iter().filter(|section| section.matcher.is_match(relative)) {
+                settings.charset = section.settings.charset.or(settings.charset);
+                settings.end_of_line = section.settings.end_of_line.or(settings.end_of_line);
+            }
+        }
+        settings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse() {
+        let config = ConfigFile::parse("\
+root = true
+
+[*]
+end_of_line = lf
+charset = utf-8
+
+; Windows scripts
+[*.{bat,cmd}]
+end_of_line = CRLF
+
+[a[b]
+charset = latin1
+");
+        assert!(config.root);
+        assert_eq!(config.sections.len(), 2);
+        assert_eq!(config.sections[0].settings, Settings { charset: Some(Encoding::Utf8), end_of_line: Some(EolPolicy::Lf) });
+        assert_eq!(config.sections[1].settings, Settings { charset: None, end_of_line: Some(EolPolicy::Crlf) });
+    }
+
+    #[test]
+    fn test_section_matcher() {
+        let matcher = section_matcher("*.py").unwrap();
+        assert!(matcher.is_match("main.py"));
+        assert!(matcher.is_match("lib/util.py"));
+        assert!(!matcher.is_match("main.rs"));
+
+        let matcher = section_matcher("/docs/*.md").unwrap();
+        assert!(matcher.is_match("docs/index.md"));
+        assert!(!matcher.is_match("docs/api/index.md"));
+        assert!(!matcher.is_match("src/docs/index.md"));
+    }
+
+    #[test]
+    fn test_settings() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::write(root.join(FILE_NAME), "root = true\n[*]\nend_of_line = lf\n[*.txt]\ncharset = latin1\n").unwrap();
+        fs::create_dir(root.join("win")).unwrap();
+        fs::write(root.join("win").join(FILE_NAME), "[*]\nend_of_line = crlf\n").unwrap();
+
+        let editorconfig = EditorConfig::default();
+        assert_eq!(editorconfig.settings(&root.join("notes.txt")),
+            Settings { charset: Some(Encoding::Latin1), end_of_line: Some(EolPolicy::Lf) });
+        assert_eq!(editorconfig.settings(&root.join("main.rs")),
+            Settings { charset: None, end_of_line: Some(EolPolicy::Lf) });
+        assert_eq!(editorconfig.settings(&root.join("win").join("setup.txt")),
+            Settings { charset: Some(Encoding::Latin1), end_of_line: Some(EolPolicy::Crlf) });
+    }
+}
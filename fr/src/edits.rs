@@ -0,0 +1,166 @@
+//! Proposed edits, worked out one file at a time without applying them.
+//!
+//! Editors and GUI wrappers that want their own review before anything is
+//! written can walk the edits a session would make instead of running it. The
+//! tree is walked on the calling thread, and each file is only read once the
+//! edits before it have been taken.
+
+use crate::editorconfig::EditorConfig;
+use crate::eol::{self, LineEnding};
+use crate::hooks::Filter;
+use crate::{encoding, match_offsets, scan, skip_reason, walk_builder, Options};
+use std::collections::VecDeque;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// One replacement that find and replace would make
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edit {
+    /// The file to edit, starting with the root it was found under
+    pub path: PathBuf,
+    /// The bytes of the file's text to replace. The range is into the text as
+    /// the edits before it in the same file left it, so a file's edits can be
+    /// applied one after another in the order they come.
+    pub range: Range<usize>,
+    /// The text that is replaced
+    pub before: String,
+    /// The text that replaces it
+    pub after: String,
+}
+
+impl Edit {
+    /// Applies the edit to the text of its file.
+    pub fn apply(&self, text: &mut String) {
+        text.replace_range(self.range.clone(), &self.after);
+    }
+}
+
+/// An iterator over the edits a session would make, from `Session::edits`.
+///
+/// Files that would be skipped are passed over, as are the changes a run
+/// makes besides replacements, like converting line endings with `--eol`.
+/// Files that can't be read yield an error, and the walk carries on.
+pub struct Edits {
+    walk: ignore::Walk,
+    rules: Vec<(String, String)>,
+    options: Options,
+    editorconfig: EditorConfig,
+    /// The edits of the file read last that haven't been taken yet
+    pending: VecDeque<Edit>,
+}
+
+impl Edits {
+    pub(crate) fn new(roots: &[PathBuf], rules: Vec<(String, String)>, options: Options, filters: &[Filter]) -> Edits {
+        Edits {
+            walk: walk_builder(roots, filters).build(),
+            rules,
+            options,
+            editorconfig: EditorConfig::default(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Works out the edits to one file, in the order they are to be applied.
+    fn file_edits(&self, path: &Path) -> io::Result<Vec<Edit>> {
+        let options = self.options.for_file(path, &self.editorconfig);
+        if skip_reason(path, &options).is_some() {
+            return Ok(Vec::new());
+        }
+        // A later rule can only match what an earlier one wrote if some rule
+        // matches the file as it is
+        let mut may_match = false;
+        for (find_text, _) in &self.rules {
+            if scan::may_contain(path, find_text, options.encoding)? {
+                may_match = true;
+                break;
+            }
+        }
+        if !may_match {
+            return Ok(Vec::new());
+        }
+
+        let (mut text, _) = encoding::read(path, options.encoding)?;
+        let ending = LineEnding::detect(&text);
+        let mut edits = Vec::new();
+        for (find_text, replace_text) in &self.rules {
+            let find_text = eol::adapt(find_text, ending);
+            let replace_text = eol::adapt(replace_text, ending);
+            let first = edits.len();
+            // Each match moves the ones after it by the difference in length
+            let mut shift = 0isize;
+            for offset in match_offsets(&text, &find_text) {
+                let start = offset.saturating_add_signed(shift);
+                edits.push(Edit {
+                    path: path.to_path_buf(),
+                    range: start..start + find_text.len(),
+                    before: find_text.to_string(),
+                    after: replace_text.to_string(),
+                });
+                shift += replace_text.len() as isize - find_text.len() as isize;
+            }
+            for edit in &edits[first..] {
+                edit.apply(&mut text);
+            }
+        }
+        Ok(edits)
+    }
+}
+
+impl Iterator for Edits {
+    type Item = io::Result<Edit>;
+
+    fn next(&mut self) -> Option<io::Result<Edit>> {
+        loop {
+            if let Some(edit) = self.pending.pop_front() {
+                return Some(Ok(edit));
+            }
+            let path = match self.walk.next()? {
+                Ok(dent) => dent.into_path(),
+                Err(e) => return Some(Err(io::Error::other(e))),
+            };
+            match self.file_edits(&path) {
+                Ok(edits) => self.pending.extend(edits),
+                Err(e) => return Some(Err(io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Session;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_edits() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("a.txt"), "one two one").unwrap();
+        fs::write(temp_dir.path().join("b.txt"), "nothing here").unwrap();
+        let path = temp_dir.path().join("a.txt");
+
+        let edits: Vec<_> = Session::new(temp_dir.path()).rule("one", "three").rule("two", "2").edits().unwrap()
+            .collect::<Result<_, _>>().unwrap();
+        let ranges: Vec<_> = edits.iter().map(|edit| (edit.range.clone(), edit.after.as_str())).collect();
+        assert_eq!(ranges, [(0..3, "three"), (10..13, "three"), (6..9, "2")]);
+        assert!(edits.iter().all(|edit| edit.path == path));
+
+        // Nothing is written until the edits are applied
+        let mut text = fs::read_to_string(&path).unwrap();
+        assert_eq!(text, "one two one");
+        for edit in &edits {
+            edit.apply(&mut text);
+        }
+        assert_eq!(text, "three 2 three");
+    }
+
+    #[test]
+    fn test_edits_in_crlf_file() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("a.txt"), "a\r\nb\r\n").unwrap();
+
+        let edit = Session::new(temp_dir.path()).rule("a\nb", "c\nd").edits().unwrap().next().unwrap().unwrap();
+        assert_eq!((edit.range, edit.before.as_str(), edit.after.as_str()), (0..4, "a\r\nb", "c\r\nd"));
+    }
+}
@@ -0,0 +1,331 @@
+//! Reading and writing files in encodings other than UTF-8.
+//!
+//! Files are decoded into a `String` before matching and encoded back the same
+//! way when they are written, byte order mark included, so that only the
+//! replaced text changes.
+//!
+//! Bytes in a UTF-8 file that aren't valid UTF-8 are kept as stand-in characters
+//! from the private use area (see `escape_invalid_utf8`), so files with a few
+//! stray bytes can still be rewritten without changing those bytes.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// The UTF-8 byte order mark
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+/// The UTF-16 little-endian byte order mark
+const UTF16LE_BOM: &[u8] = b"\xFF\xFE";
+/// The UTF-16 big-endian byte order mark
+const UTF16BE_BOM: &[u8] = b"\xFE\xFF";
+/// The code point that stands in for an invalid byte of UTF-8 is this plus the
+/// byte, which puts them all in U+F780..=U+F7FF
+const ESCAPE_BASE: u32 = 0xF700;
+
+/// Which encoding to read files in, as chosen with `--encoding`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// UTF-8, the default
+    #[default]
+    Utf8,
+    /// UTF-8 that starts with a byte order mark, which is added if it is missing
+    Utf8Bom,
+    /// ISO-8859-1, where every byte is the code point of the same value
+    Latin1,
+    /// UTF-16, lowest byte first
+    Utf16Le,
+    /// UTF-16, highest byte first
+    Utf16Be,
+    /// Shift_JIS, with the characters of JIS X 0208
+    ShiftJis,
+    /// Detect each file's encoding from its byte order mark, falling back to
+    /// Latin-1 for files that aren't valid UTF-8
+    Auto,
+}
+
+impl Encoding {
+    /// Parses the value given to `--encoding`.
+    pub fn parse(value: &str) -> Result<Encoding, String> {
+        match value.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Ok(Encoding::Utf8),
+            "utf-8-bom" | "utf8-bom" => Ok(Encoding::Utf8Bom),
+            "latin1" | "latin-1" | "iso-8859-1" => Ok(Encoding::Latin1),
+            "utf-16le" | "utf16le" => Ok(Encoding::Utf16Le),
+            "utf-16be" | "utf16be" => Ok(Encoding::Utf16Be),
+            "shift_jis" | "shift-jis" | "sjis" => Ok(Encoding::ShiftJis),
+            "auto" => Ok(Encoding::Auto),
+            _ => Err(format!(
+                "Unknown encoding '{}', expected one of: utf-8, utf-8-bom, latin1, utf-16le, utf-16be, shift_jis, auto", value)),
+        }
+    }
+
+    /// Whether files read in this encoding may contain NUL bytes, so they
+    /// shouldn't be skipped as binary for containing them.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - Path to the file, whose byte order mark is checked with `auto`
+    pub fn allows_nul(self, file_path: &Path) -> bool {
+        let mut bom = [0; 2];
+        match self {
+            Encoding::Auto => File::open(file_path).and_then(|mut file| file.read_exact(&mut bom)).is_ok() && self.allows_nul_in(&bom),
+            _ => self.allows_nul_in(&[]),
+        }
+    }
+
+    /// Whether contents read in this encoding may contain NUL bytes, as
+    /// `allows_nul` does for a file.
+    ///
+    /// # Arguments
+    ///
+    /// * `contents` - The contents, whose byte order mark is checked with `auto`
+    pub fn allows_nul_in(self, contents: &[u8]) -> bool {
+        match self {
+            Encoding::Utf16Le | Encoding::Utf16Be => true,
+            Encoding::Utf8 | Encoding::Utf8Bom | Encoding::Latin1 | Encoding::ShiftJis => false,
+            Encoding::Auto => contents.starts_with(UTF16LE_BOM) || contents.starts_with(UTF16BE_BOM),
+        }
+    }
+}
+
+/// How a file was decoded, so it can be written back the same way
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decoding {
+    /// The encoding the file was read in, never `Auto`
+    encoding: Encoding,
+    /// The byte order mark that was removed from the start of the file
+    bom: &'static [u8],
+    /// Whether invalid UTF-8 was kept as stand-in characters
+    escaped: bool,
+}
+
+impl Decoding {
+    /// Encodes text to be written back to the file.
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<Vec<u8>>` - The encoded bytes, or an error if the text
+    ///   contains characters the encoding can't represent
+    pub fn encode(&self, text: &str) -> io::Result<Vec<u8>> {
+        let mut bytes = self.bom.to_vec();
+        match self.encoding {
+            Encoding::Utf8 | Encoding::Utf8Bom | Encoding::Auto if self.escaped => {
+                for c in text.chars() {
+                    match unescape(c) {
+                        Some(byte) => bytes.push(byte),
+                        None => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+                    }
+                }
+            }
+            Encoding::Utf8 | Encoding::Utf8Bom | Encoding::Auto => bytes.extend_from_slice(text.as_bytes()),
+            Encoding::Latin1 => {
+                for c in text.chars() {
+                    let byte = u8::try_from(u32::from(c)).map_err(|_| {
+                        io::Error::new(io::ErrorKind::InvalidData,
+                            format!("{:?} cannot be written in Latin-1", c))
+                    })?;
+                    bytes.push(byte);
+                }
+            }
+            Encoding::Utf16Le => bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes)),
+            Encoding::Utf16Be => bytes.extend(text.encode_utf16().flat_map(u16::to_be_bytes)),
+            Encoding::ShiftJis => bytes.extend(crate::shift_jis::encode(text)?),
+        }
+        Ok(bytes)
+    }
+}
+
+/// Decodes the contents of a file.
+///
+/// # Arguments
+///
+/// * `bytes` - The contents of the file
+/// * `encoding` - The encoding to decode it with
+///
+/// # Returns
+///
+/// * `io::Result<(String, Decoding)>` - The decoded text and how to encode it
+///   again, or an error if the contents aren't valid in the encoding
+pub fn decode(bytes: Vec<u8>, encoding: Encoding) -> io::Result<(String, Decoding)> {
+    let decoding = |encoding, bom| Decoding { encoding, bom, escaped: false };
+    match encoding {
+        Encoding::Utf8 => decode_utf8_escaped(bytes, decoding(Encoding::Utf8, b"")),
+        Encoding::Utf8Bom => {
+            let bytes = match bytes.strip_prefix(UTF8_BOM) {
+                Some(rest) => rest.to_vec(),
+                None => bytes,
+            };
+            decode_utf8_escaped(bytes, decoding(Encoding::Utf8Bom, UTF8_BOM))
+        }
+        Encoding::Latin1 => Ok((decode_latin1(&bytes), decoding(Encoding::Latin1, b""))),
+        Encoding::Utf16Le => Ok((decode_utf16(&bytes, u16::from_le_bytes)?, decoding(Encoding::Utf16Le, b""))),
+        Encoding::Utf16Be => Ok((decode_utf16(&bytes, u16::from_be_bytes)?, decoding(Encoding::Utf16Be, b""))),
+        Encoding::ShiftJis => Ok((crate::shift_jis::decode(&bytes)?, decoding(Encoding::ShiftJis, b""))),
+        Encoding::Auto => {
+            if let Some(rest) = bytes.strip_prefix(UTF8_BOM) {
+                Ok((decode_utf8(rest.to_vec())?, decoding(Encoding::Utf8, UTF8_BOM)))
+            } else if let Some(rest) = bytes.strip_prefix(UTF16LE_BOM) {
+                Ok((decode_utf16(rest, u16::from_le_bytes)?, decoding(Encoding::Utf16Le, UTF16LE_BOM)))
+            } else if let Some(rest) = bytes.strip_prefix(UTF16BE_BOM) {
+                Ok((decode_utf16(rest, u16::from_be_bytes)?, decoding(Encoding::Utf16Be, UTF16BE_BOM)))
+            } else {
+                match String::from_utf8(bytes) {
+                    Ok(text) => Ok((text, decoding(Encoding::Utf8, b""))),
+                    Err(e) => Ok((decode_latin1(e.as_bytes()), decoding(Encoding::Latin1, b""))),
+                }
+            }
+        }
+    }
+}
+
+/// Reads and decodes a file.
+///
+/// # Arguments
+///
+/// * `file_path` - Path to the file to read
+/// * `encoding` - The encoding to decode it with
+pub fn read(file_path: &Path, encoding: Encoding) -> io::Result<(String, Decoding)> {
+    decode(crate::read_file(file_path)?, encoding)
+}
+
+/// Decodes UTF-8, keeping any invalid bytes as stand-in characters.
+fn decode_utf8_escaped(bytes: Vec<u8>, decoding: Decoding) -> io::Result<(String, Decoding)> {
+    match String::from_utf8(bytes) {
+        Ok(text) => Ok((text, decoding)),
+        Err(e) => Ok((escape_invalid_utf8(e.as_bytes())?, Decoding { escaped: true, ..decoding })),
+    }
+}
+
+/// Decodes UTF-8 that contains invalid bytes, replacing each one with a
+/// stand-in character that `Decoding::encode` turns back into the same byte.
+///
+/// # Returns
+///
+/// * `io::Result<String>` - The decoded text, or an error if the valid text
+///   already contains stand-in characters, which would make the file come out
+///   differently when it is written back
+fn escape_invalid_utf8(bytes: &[u8]) -> io::Result<String> {
+    let mut text = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        if chunk.valid().chars().any(|c| unescape(c).is_some()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                "stream did not contain valid UTF-8, and can't be rewritten without changing it"));
+        }
+        text.push_str(chunk.valid());
+        // Invalid bytes are never ASCII, so they all have a stand-in
+        text.extend(chunk.invalid().iter().filter_map(|&byte| char::from_u32(ESCAPE_BASE + u32::from(byte))));
+    }
+    Ok(text)
+}
+
+/// The invalid byte that a stand-in character was decoded from, if it is one
+pub(crate) fn unescape(c: char) -> Option<u8> {
+    u32::from(c).checked_sub(ESCAPE_BASE)
+        .and_then(|byte| u8::try_from(byte).ok())
+        .filter(|byte| !byte.is_ascii())
+}
+
+fn decode_utf8(bytes: Vec<u8>) -> io::Result<String> {
+    String::from_utf8(bytes).map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidData, "stream did not contain valid UTF-8")
+    })
+}
+
+fn decode_latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&byte| char::from(byte)).collect()
+}
+
+fn decode_utf16(bytes: &[u8], from_bytes: fn([u8; 2]) -> u16) -> io::Result<String> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "stream did not contain valid UTF-16");
+    let pairs = bytes.chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return Err(invalid());
+    }
+    let units = pairs.map(|pair| from_bytes([pair[0], pair[1]]));
+    char::decode_utf16(units).collect::<Result<String, _>>().map_err(|_| invalid())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Encoding::parse("UTF-8").unwrap(), Encoding::Utf8);
+        assert_eq!(Encoding::parse("latin1").unwrap(), Encoding::Latin1);
+        assert_eq!(Encoding::parse("iso-8859-1").unwrap(), Encoding::Latin1);
+        assert_eq!(Encoding::parse("utf-16le").unwrap(), Encoding::Utf16Le);
+        assert_eq!(Encoding::parse("auto").unwrap(), Encoding::Auto);
+        assert_eq!(Encoding::parse("Shift_JIS").unwrap(), Encoding::ShiftJis);
+        assert_eq!(Encoding::parse("sjis").unwrap(), Encoding::ShiftJis);
+        assert!(Encoding::parse("ebcdic").is_err());
+    }
+
+    #[test]
+    fn test_latin1_round_trip() {
+        let bytes = b"caf\xE9 na\xEFve".to_vec();
+        let (text, decoding) = decode(bytes.clone(), Encoding::Latin1).unwrap();
+        assert_eq!(text, "café naïve");
+        assert_eq!(decoding.encode(&text).unwrap(), bytes);
+        assert!(decoding.encode("snowman ☃").is_err());
+    }
+
+    #[test]
+    fn test_utf8_keeps_invalid_bytes() {
+        let bytes = b"stray \xFF\xC3 bytes in caf\xC3\xA9".to_vec();
+        let (text, decoding) = decode(bytes.clone(), Encoding::Utf8).unwrap();
+        assert!(text.starts_with("stray "));
+        assert!(text.ends_with(" bytes in café"));
+        assert_eq!(decoding.encode(&text).unwrap(), bytes);
+        assert_eq!(decoding.encode(&text.replace("café", "thé")).unwrap(), b"stray \xFF\xC3 bytes in th\xC3\xA9");
+
+        // Stand-ins that were already in the file can't be told apart from escaped bytes
+        let mut bytes = "\u{F7FF}".as_bytes().to_vec();
+        bytes.push(0xFF);
+        assert!(decode(bytes, Encoding::Utf8).is_err());
+    }
+
+    #[test]
+    fn test_utf8_bom() {
+        let (text, decoding) = decode(b"\xEF\xBB\xBFhi".to_vec(), Encoding::Utf8Bom).unwrap();
+        assert_eq!(text, "hi");
+        assert_eq!(decoding.encode(&text).unwrap(), b"\xEF\xBB\xBFhi");
+
+        // A missing byte order mark is added
+        let (text, decoding) = decode(b"hi".to_vec(), Encoding::Utf8Bom).unwrap();
+        assert_eq!(decoding.encode(&text).unwrap(), b"\xEF\xBB\xBFhi");
+    }
+
+    #[test]
+    fn test_utf16_round_trip() {
+        let bytes: Vec<u8> = "héllo".encode_utf16().flat_map(u16::to_be_bytes).collect();
+        let (text, decoding) = decode(bytes.clone(), Encoding::Utf16Be).unwrap();
+        assert_eq!(text, "héllo");
+        assert_eq!(decoding.encode(&text).unwrap(), bytes);
+        assert!(decode(vec![0x00], Encoding::Utf16Be).is_err());
+    }
+
+    #[test]
+    fn test_auto_detects_bom() {
+        let mut bytes = UTF16LE_BOM.to_vec();
+        bytes.extend("hi".encode_utf16().flat_map(u16::to_le_bytes));
+        let (text, decoding) = decode(bytes.clone(), Encoding::Auto).unwrap();
+        assert_eq!(text, "hi");
+        assert_eq!(decoding.encode(&text).unwrap(), bytes);
+
+        let (text, decoding) = decode(b"\xEF\xBB\xBFhi".to_vec(), Encoding::Auto).unwrap();
+        assert_eq!(text, "hi");
+        assert_eq!(decoding.encode("bye").unwrap(), b"\xEF\xBB\xBFbye");
+    }
+
+    #[test]
+    fn test_auto_falls_back_to_latin1() {
+        let (text, decoding) = decode("café".as_bytes().to_vec(), Encoding::Auto).unwrap();
+        assert_eq!(text, "café");
+        assert_eq!(decoding.encoding, Encoding::Utf8);
+
+        let (text, decoding) = decode(b"caf\xE9".to_vec(), Encoding::Auto).unwrap();
+        assert_eq!(text, "café");
+        assert_eq!(decoding.encoding, Encoding::Latin1);
+    }
+}
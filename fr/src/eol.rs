@@ -0,0 +1,143 @@
+//! Line endings.
+//!
+//! Find and replace keeps each file's line endings: in a CRLF file, the `\n`s in
+//! the find and replace text stand for `\r\n`. `--eol` can also convert the line
+//! endings of every rewritten file.
+
+use std::borrow::Cow;
+
+/// How lines end in a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    /// `\n`, as on Unix
+    Lf,
+    /// `\r\n`, as on Windows
+    Crlf,
+}
+
+impl LineEnding {
+    /// Works out how most lines in the text end.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<LineEnding>` - The line ending used by most lines, or None if
+    ///   the text is a single line
+    pub fn detect(text: &str) -> Option<LineEnding> {
+        let newlines = text.matches('\n').count();
+        if newlines == 0 {
+            return None;
+        }
+        let crlfs = text.matches("\r\n").count();
+        Some(if crlfs * 2 > newlines { LineEnding::Crlf } else { LineEnding::Lf })
+    }
+}
+
+/// What to do with the line endings of rewritten files, as chosen with `--eol`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EolPolicy {
+    /// Keep each file's line endings, the default
+    #[default]
+    Preserve,
+    /// Convert every line ending to `\n`
+    Lf,
+    /// Convert every line ending to `\r\n`
+    Crlf,
+}
+
+impl EolPolicy {
+    /// Parses the value given to `--eol`.
+    pub fn parse(value: &str) -> Result<EolPolicy, String> {
+        match value {
+            "preserve" => Ok(EolPolicy::Preserve),
+            "lf" => Ok(EolPolicy::Lf),
+            "crlf" => Ok(EolPolicy::Crlf),
+            _ => Err(format!("Unknown line ending '{}', expected one of: lf, crlf, preserve", value)),
+        }
+    }
+
+    /// The line ending rewritten files are converted to, if any
+    pub fn target(self) -> Option<LineEnding> {
+        match self {
+            EolPolicy::Preserve => None,
+            EolPolicy::Lf => Some(LineEnding::Lf),
+            EolPolicy::Crlf => Some(LineEnding::Crlf),
+        }
+    }
+}
+
+/// Adapts find or replace text to a file's line endings, so that its `\n`s
+/// become `\r\n`s in a CRLF file.
+///
+/// # Arguments
+///
+/// * `text` - The find or replace text
+/// * `ending` - How lines end in the file, if it has more than one line
+pub fn adapt(text: &str, ending: Option<LineEnding>) -> Cow<'_, str> {
+    match ending {
+        Some(LineEnding::Crlf) => convert(text, LineEnding::Crlf),
+        Some(LineEnding::Lf) | None => Cow::Borrowed(text),
+    }
+}
+
+/// Converts every line ending in the text.
+///
+/// # Returns
+///
+/// * `Cow<str>` - The converted text, borrowed if nothing needed converting
+pub fn convert(text: &str, ending: LineEnding) -> Cow<'_, str> {
+    match ending {
+        LineEnding::Lf if text.contains("\r\n") => Cow::Owned(text.replace("\r\n", "\n")),
+        LineEnding::Crlf if text.matches('\n').count() != text.matches("\r\n").count() => {
+            let mut converted = String::with_capacity(text.len() + text.len() / 16);
+            for line in text.split_inclusive('\n') {
+                match line.strip_suffix('\n') {
+                    Some(line) => {
+                        converted.push_str(line.strip_suffix('\r').unwrap_or(line));
+                        converted.push_str("\r\n");
+                    }
+                    None => converted.push_str(line),
+                }
+            }
+            Cow::Owned(converted)
+        }
+        _ => Cow::Borrowed(text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(LineEnding::detect("one line"), None);
+        assert_eq!(LineEnding::detect("a\nb\n"), Some(LineEnding::Lf));
+        assert_eq!(LineEnding::detect("a\r\nb\r\n"), Some(LineEnding::Crlf));
+        assert_eq!(LineEnding::detect("a\r\nb\r\nc\n"), Some(LineEnding::Crlf));
+        assert_eq!(LineEnding::detect("a\r\nb\n"), Some(LineEnding::Lf));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(EolPolicy::parse("preserve").unwrap(), EolPolicy::Preserve);
+        assert_eq!(EolPolicy::parse("lf").unwrap(), EolPolicy::Lf);
+        assert_eq!(EolPolicy::parse("crlf").unwrap(), EolPolicy::Crlf);
+        assert!(EolPolicy::parse("cr").is_err());
+    }
+
+    #[test]
+    fn test_adapt() {
+        assert_eq!(adapt("a\nb", Some(LineEnding::Crlf)), "a\r\nb");
+        assert_eq!(adapt("a\r\nb", Some(LineEnding::Crlf)), "a\r\nb");
+        assert_eq!(adapt("a\nb", Some(LineEnding::Lf)), "a\nb");
+        assert_eq!(adapt("a\nb", None), "a\nb");
+    }
+
+    #[test]
+    fn test_convert() {
+        assert_eq!(convert("a\r\nb\nc", LineEnding::Lf), "a\nb\nc");
+        assert_eq!(convert("a\r\nb\nc\n", LineEnding::Crlf), "a\r\nb\r\nc\r\n");
+        assert!(matches!(convert("a\r\nb", LineEnding::Crlf), Cow::Borrowed(_)));
+        assert!(matches!(convert("a\nb", LineEnding::Lf), Cow::Borrowed(_)));
+    }
+}
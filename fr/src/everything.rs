@@ -0,0 +1,172 @@
+//! Renaming something everywhere at once, for `--everything`.
+//!
+//! The find text is replaced in the files, as in any run, which also fixes the
+//! Markdown links and imports that name it. Then it is replaced in the targets
+//! of symlinks, and last in the names of files and directories, so the links
+//! are still found where they were. A symlink to a file in the tree is left to
+//! the file, so that it is rewritten once. Each file is copied before it is
+//! rewritten, and if any step fails, every change made so far is undone, most
+//! recent first, so the tree is never left half renamed. The copies are written
+//! back over the files in place, so each keeps its permissions.
+
+use crate::paths::{self, Rename, Retarget};
+use crate::hooks::Hooks;
+use crate::{stream, walk, Options, Summary};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// What an `--everything` run did
+#[derive(Debug, Default)]
+pub struct Outcome {
+    /// The files rewritten, or that would be, and the errors of every step
+    pub summary: Summary,
+    /// Each symlink pointed elsewhere
+    pub retargeted: Vec<Retarget>,
+    /// Each path renamed, with the path it has after every rename
+    pub renamed: Vec<Rename>,
+    /// Whether a step failed and every change was undone, which leaves the
+    /// files, symlinks and renames empty
+    pub rolled_back: bool,
+}
+
+/// Replaces the find text in the files, the targets of symlinks and the names
+/// of files and directories, undoing all of it if any of it fails.
+///
+/// # Arguments
+///
+/// * `starting_directory` - The directory to rename in
+/// * `find_text` - Text to find
+/// * `replace_text` - Text to replace it with
+/// * `options` - Options controlling the run, including whether it is a dry
+///   run
+///
+/// # Returns
+///
+/// * `io::Result<Outcome>` - What was changed, or would be, and whether it was
+///   undone, or an error if the run couldn't be set up
+pub fn run(starting_directory: &Path, find_text: &str, replace_text: &str, options: &Options) -> io::Result<Outcome> {
+    // Streamed files are never held whole, so there would be no copy to put
+    // back
+    let options = Options { max_filesize: Some(options.max_filesize.unwrap_or(stream::THRESHOLD).min(stream::THRESHOLD)), ..options.clone() };
+    let snapshot = Arc::new(Mutex::new(Vec::<(PathBuf, Vec<u8>)>::new()));
+    let mut hooks = Hooks::default();
+    let files: HashSet<PathBuf> = paths::walk(starting_directory, &options, !options.config_values).into_iter()
+        .filter(|path| fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_file()))
+        .filter_map(|path| fs::canonicalize(path).ok())
+        .collect();
+    hooks.filters.push(Arc::new(move |path: &Path| {
+        !fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_symlink())
+            || !fs::canonicalize(path).is_ok_and(|target| files.contains(&target))
+    }));
+    if !options.dry_run {
+        let (root, taken) = (starting_directory.to_path_buf(), Arc::clone(&snapshot));
+        // A file that can't be read again is left alone, so that every change
+        // can be undone
+        hooks.on_match.push(Arc::new(move |display: &Path, _| match fs::read(root.join(display)) {
+            Ok(contents) => {
+                taken.lock().unwrap().push((root.join(display), contents));
+                true
+            }
+            Err(_) => false,
+        }));
+    }
+    let mut summary = walk(&[starting_directory.to_path_buf()], find_text, replace_text, &options, &hooks)?;
+    let mut snapshot = std::mem::take(&mut *snapshot.lock().unwrap());
+    let written: Vec<PathBuf> = summary.modified.iter().map(|file| starting_directory.join(&file.path)).collect();
+    snapshot.retain(|(path, _)| written.contains(path));
+
+    let mut outcome = Outcome::default();
+    if summary.errors.is_empty() {
+        let walked = paths::walk(starting_directory, &options, !options.config_values);
+        let (retargeted, errors) = paths::retarget(&walked, find_text, replace_text, options.dry_run);
+        outcome.retargeted = retargeted;
+        summary.errors.extend(errors);
+    }
+    let mut renamed = Vec::new();
+    if summary.errors.is_empty() {
+        let walked: Vec<PathBuf> = paths::walk(starting_directory, &options, !options.config_values).into_iter()
+            .filter(|path| path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.contains(find_text)))
+            .collect();
+        let (done, errors) = paths::rename(walked, |name| name.replace(find_text, replace_text), options.dry_run);
+        renamed = done;
+        summary.errors.extend(errors);
+    }
+
+    if !summary.errors.is_empty() && !options.dry_run {
+        summary.errors.extend(paths::undo(&renamed, &outcome.retargeted));
+        for (path, contents) in &snapshot {
+            if let Err(e) = fs::write(path, contents) {
+                summary.errors.push((path.clone(), format!("Failed to put back: {}", e)));
+            }
+        }
+        outcome.rolled_back = true;
+        summary.modified.clear();
+        outcome.retargeted.clear();
+        renamed.clear();
+    }
+    outcome.renamed = paths::final_paths(&renamed);
+    outcome.summary = summary;
+    Ok(outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_run() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("widget")).unwrap();
+        fs::write(root.join("widget/widget.md"), "# widget\n").unwrap();
+        fs::write(root.join("README.md"), "See [widget](widget/widget.md)\n").unwrap();
+
+        let outcome = run(root, "widget", "gadget", &Options::default()).unwrap();
+        assert!(!outcome.rolled_back && outcome.summary.errors.is_empty());
+        assert_eq!(outcome.renamed, [(root.join("widget"), root.join("gadget")), (root.join("widget/widget.md"), root.join("gadget/gadget.md"))]);
+        assert_eq!(fs::read_to_string(root.join("gadget/gadget.md")).unwrap(), "# gadget\n");
+        assert_eq!(fs::read_to_string(root.join("README.md")).unwrap(), "See [gadget](gadget/gadget.md)\n");
+    }
+
+    #[test]
+    fn test_run_rolls_back() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("widget")).unwrap();
+        fs::write(root.join("widget/widget.md"), "# widget\n").unwrap();
+        // In the way of renaming the directory
+        fs::write(root.join("gadget"), "").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(root.join("widget/widget.md"), fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        let outcome = run(root, "widget", "gadget", &Options::default()).unwrap();
+        assert!(outcome.rolled_back && outcome.renamed.is_empty());
+        assert_eq!(fs::read_to_string(root.join("widget/widget.md")).unwrap(), "# widget\n");
+        assert!(!root.join("widget/gadget.md").exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(root.join("widget/widget.md")).unwrap().permissions().mode() & 0o777, 0o755);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_rewrites_symlinked_files_once() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::write(root.join("README.md"), "# widget\n").unwrap();
+        std::os::unix::fs::symlink("README.md", root.join("LINK.md")).unwrap();
+
+        let outcome = run(root, "widget", "gadget", &Options { dry_run: true, ..Options::default() }).unwrap();
+        let modified: Vec<&Path> = outcome.summary.modified.iter().map(|file| file.path.as_path()).collect();
+        assert_eq!(modified, [Path::new("README.md")]);
+    }
+}
@@ -0,0 +1,213 @@
+//! Explains why fr would or wouldn't modify a particular file.
+
+use crate::editorconfig::EditorConfig;
+use crate::gitattributes::{self, GitAttributes};
+use crate::{display_path, encoding, skip_reason, walk_filters, walks_hidden, Options, SkipReason};
+use ignore::WalkBuilder;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// A rule the directory walk uses to leave paths out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WalkFilter {
+    Hidden,
+    GitIgnore,
+    Ignore,
+    GitExclude,
+    GitGlobal,
+}
+
+impl WalkFilter {
+    const ALL: [WalkFilter; 5] = [
+        WalkFilter::Hidden,
+        WalkFilter::GitIgnore,
+        WalkFilter::Ignore,
+        WalkFilter::GitExclude,
+        WalkFilter::GitGlobal,
+    ];
+
+    fn describe(self) -> &'static str {
+        match self {
+            WalkFilter::Hidden => "hidden, because its name or a parent directory's name starts with '.'",
+            WalkFilter::GitIgnore => "ignored by a .gitignore file",
+            WalkFilter::Ignore => "ignored by a .ignore file",
+            WalkFilter::GitExclude => "ignored by .git/info/exclude",
+            WalkFilter::GitGlobal => "ignored by the global git excludes file",
+        }
+    }
+}
+
+/// Removes `.` and `..` components from a path without touching the filesystem.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Checks whether a walk from the starting directory with only the given filter
+/// enabled (or every filter, if None) reaches the target path.
+fn walk_reaches(starting_directory: &Path, target: &Path, only: Option<WalkFilter>, options: &Options) -> bool {
+    let enabled = |filter| only.is_none_or(|only| only == filter);
+    let mut builder = WalkBuilder::new(starting_directory);
+    builder
+        .hidden(walks_hidden(options) && enabled(WalkFilter::Hidden))
+        .git_ignore(enabled(WalkFilter::GitIgnore))
+        .ignore(enabled(WalkFilter::Ignore))
+        .git_exclude(enabled(WalkFilter::GitExclude))
+        .git_global(enabled(WalkFilter::GitGlobal));
+
+    // Only descend towards the target so the check stays cheap in large trees
+    let prefix = target.to_path_buf();
+    builder.filter_entry(move |entry| prefix.starts_with(entry.path()));
+
+    builder.build().flatten().any(|entry| entry.path() == target)
+}
+
+/// Works out what fr would do with a path, and why.
+///
+/// # Arguments
+///
+/// * `starting_directory` - Directory fr searches from
+/// * `path` - Path to explain, relative to the starting directory or absolute
+/// * `find_text` - Text that would be searched for, if known
+/// * `options` - Options the run would use
+///
+/// # Returns
+///
+/// * `String` - A description of what fr would do with the file and why
+pub fn explain(starting_directory: &Path, path: &Path, find_text: Option<&str>, options: &Options) -> String {
+    let target = normalize(&starting_directory.join(path));
+    let display = display_path(starting_directory, &target).display();
+
+    if !target.starts_with(starting_directory) {
+        return format!("{}: skipped (outside of the directory fr searches, {})", display, starting_directory.display());
+    }
+    if fs::symlink_metadata(&target).is_err() {
+        return format!("{}: skipped (does not exist)", display);
+    }
+
+    if !walk_reaches(starting_directory, &target, None, options) {
+        let reasons: Vec<&str> = WalkFilter::ALL.into_iter()
+            .filter(|&filter| !walk_reaches(starting_directory, &target, Some(filter), options))
+            .map(WalkFilter::describe)
+            .collect();
+        if reasons.is_empty() {
+            return format!("{}: skipped (left out of the directory walk)", display);
+        }
+        return format!("{}: skipped ({})", display, reasons.join("; "));
+    }
+    // The run's own filters see the target and each directory leading to it
+    let filters = match walk_filters(starting_directory, options) {
+        Ok(filters) => filters,
+        Err(e) => return format!("{}: skipped (the walk can't be set up: {})", display, e),
+    };
+    let walked = target.ancestors().take_while(|path| *path != starting_directory).collect::<Vec<_>>();
+    if let Some((reason, _)) = filters.iter().find(|(_, filter)| walked.iter().any(|path| !filter(path))) {
+        return format!("{}: skipped ({})", display, reason);
+    }
+
+    let options = options.for_file(&target, &EditorConfig::default());
+    let attributes = match options.gitattributes {
+        true => GitAttributes::default().settings(&target),
+        false => gitattributes::Settings::default(),
+    };
+    if target.is_file() {
+        match attributes.skip_reason() {
+            Some(SkipReason::Binary) => return format!("{}: skipped (marked binary in .gitattributes)", display),
+            Some(SkipReason::Generated) => return format!("{}: skipped (marked linguist-generated in .gitattributes)", display),
+            _ => {}
+        }
+    }
+    let options = match attributes.binary {
+        Some(false) => Options { text: true, ..options.into_owned() },
+        _ => options.into_owned(),
+    };
+    if let Some(reason) = skip_reason(&target, &options) {
+        let reason = match reason {
+            SkipReason::Binary => "detected as binary".to_string(),
+            reason => reason.to_string(),
+        };
+        return format!("{}: skipped ({})", display, reason);
+    }
+
+    let content = match encoding::read(&target, options.encoding) {
+        Ok((content, _)) => content,
+        Err(e) => return format!("{}: skipped (could not be read: {})", display, e),
+    };
+
+    match find_text {
+        Some(find_text) => {
+            let count = if find_text.is_empty() { 0 } else { content.matches(find_text).count() };
+            if count == 0 {
+                format!("{}: skipped (does not contain {:?})", display, find_text)
+            } else {
+                format!("{}: would be modified ({} match{})", display, count, if count == 1 { "" } else { "es" })
+            }
+        }
+        None => format!("{}: would be searched", display),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use globset::Glob;
+    use std::process::Command;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize(Path::new("/a/./b/../c")), PathBuf::from("/a/c"));
+    }
+
+    #[test]
+    fn test_explain() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        Command::new("git").arg("init").current_dir(root).output().unwrap();
+        fs::write(root.join(".gitignore"), "ignored.txt\n").unwrap();
+        fs::write(root.join("ignored.txt"), "hello").unwrap();
+        fs::write(root.join(".hidden.txt"), "hello").unwrap();
+        fs::write(root.join("match.txt"), "hello hello").unwrap();
+        fs::write(root.join("other.txt"), "goodbye").unwrap();
+        fs::write(root.join("binary.bin"), [0, 1, 2, 3]).unwrap();
+
+        let explain = |path: &str| explain(root, Path::new(path), Some("hello"), &Options::default());
+        assert_eq!(explain("ignored.txt"), "ignored.txt: skipped (ignored by a .gitignore file)");
+        assert!(explain(".hidden.txt").starts_with(".hidden.txt: skipped (hidden"));
+        assert_eq!(explain("binary.bin"), "binary.bin: skipped (detected as binary)");
+        assert_eq!(explain("other.txt"), "other.txt: skipped (does not contain \"hello\")");
+        assert_eq!(explain("./match.txt"), "match.txt: would be modified (2 matches)");
+        assert_eq!(explain("missing.txt"), "missing.txt: skipped (does not exist)");
+        assert_eq!(super::explain(root, Path::new("other.txt"), None, &Options::default()), "other.txt: would be searched");
+
+        let options = Options { excludes: vec![Glob::new("*.txt").unwrap()], ..Options::default() };
+        assert_eq!(super::explain(root, Path::new("match.txt"), Some("hello"), &options), "match.txt: skipped (excluded by --exclude)");
+        let options = Options { includes: vec![Glob::new("*.md").unwrap()], ..Options::default() };
+        assert_eq!(super::explain(root, Path::new("match.txt"), Some("hello"), &options), "match.txt: skipped (not named by --include)");
+        let options = Options { forbidden: vec![fs::canonicalize(root).unwrap().join("match.txt")], ..Options::default() };
+        assert_eq!(super::explain(root, Path::new("match.txt"), Some("hello"), &options), "match.txt: skipped (forbidden by --forbid)");
+        let options = Options { out_dir: Some(root.join("out")), ..Options::default() };
+        fs::create_dir(root.join("out")).unwrap();
+        fs::write(root.join("out/match.txt"), "hello").unwrap();
+        assert_eq!(super::explain(root, Path::new("out/match.txt"), Some("hello"), &options), "out/match.txt: skipped (inside --out-dir, where the copies go)");
+        let options = Options { journal: Some(root.join("fr.db")), ..Options::default() };
+        fs::write(root.join("fr.db"), "hello").unwrap();
+        assert_eq!(super::explain(root, Path::new("fr.db"), Some("hello"), &options), "fr.db: skipped (the --journal database)");
+
+        let options = Options { max_filesize: Some(4), ..Options::default() };
+        assert_eq!(super::explain(root, Path::new("match.txt"), Some("hello"), &options),
+            "match.txt: skipped (larger than --max-filesize)");
+
+        fs::write(root.join(".gitattributes"), "match.txt linguist-generated\n").unwrap();
+        assert_eq!(explain("match.txt"), "match.txt: skipped (marked linguist-generated in .gitattributes)");
+    }
+}
@@ -0,0 +1,185 @@
+//! What a project declares about its files in `.gitattributes` files.
+//!
+//! Only the attributes that say whether a file is text or generated are used:
+//! `binary` and `-diff` mark a file as binary, and `text` or `diff` as text,
+//! either way overriding the guess made from its bytes, and
+//! `linguist-generated` marks a file as generated, which fr leaves alone
+//! since it would be overwritten the next time it is generated. See
+//! <https://git-scm.com/docs/gitattributes> for the file format.
+
+use crate::SkipReason;
+use globset::{GlobBuilder, GlobMatcher};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// The name of the files attributes are read from
+const FILE_NAME: &str = ".gitattributes";
+
+/// The attributes that apply to one file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Settings {
+    /// Whether the file is declared binary, or declared text, if either
+    pub binary: Option<bool>,
+    /// Whether the file is declared generated, or declared not generated
+    pub generated: Option<bool>,
+}
+
+impl Settings {
+    /// The reason to skip a file with these attributes, if there is one.
+    pub fn skip_reason(&self) -> Option<SkipReason> {
+        if self.generated == Some(true) {
+            Some(SkipReason::Generated)
+        } else if self.binary == Some(true) {
+            Some(SkipReason::Binary)
+        } else {
+            None
+        }
+    }
+}
+
+/// A line of a `.gitattributes` file
+#[derive(Debug)]
+struct Line {
+    /// Matches paths relative to the directory of the `.gitattributes` file
+    matcher: GlobMatcher,
+    settings: Settings,
+}
+
+/// Parses the contents of a `.gitattributes` file, keeping the lines that set
+/// an attribute fr uses. Lines with invalid patterns are ignored, as git does.
+fn parse(contents: &str) -> Vec<Line> {
+    let mut lines = Vec::new();
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        let Some(matcher) = words.next().and_then(pattern_matcher) else {
+            continue;
+        };
+        let mut settings = Settings::default();
+        for attribute in words {
+            match attribute {
+                "binary" | "-diff" => settings.binary = Some(true),
+                "text" | "diff" => settings.binary = Some(false),
+                "linguist-generated" | "linguist-generated=true" => settings.generated = Some(true),
+                "-linguist-generated" | "linguist-generated=false" => settings.generated = Some(false),
+                _ => {}
+            }
+        }
+        if settings != Settings::default() {
+            lines.push(Line { matcher, settings });
+        }
+    }
+    lines
+}
+
+/// Builds a matcher for a pattern. Patterns without a `/` match files with
+/// that name in any directory; the others match from the `.gitattributes`'s
+/// directory.
+fn pattern_matcher(pattern: &str) -> Option<GlobMatcher> {
+    let pattern = if pattern.contains('/') {
+        pattern.trim_start_matches('/').to_string()
+    } else {
+        format!("**/{}", pattern)
+    };
+    GlobBuilder::new(&pattern)
+        .literal_separator(true)
+        .build()
+        .ok()
+        .map(|glob| glob.compile_matcher())
+}
+
+/// Looks up the attributes of files, reading each `.gitattributes` file once
+/// however many files it applies to
+#[derive(Debug, Default)]
+pub struct GitAttributes {
+    /// The parsed `.gitattributes` of each directory looked in, or None if
+    /// there is no readable one
+    files: Mutex<HashMap<PathBuf, Option<Arc<Vec<Line>>>>>,
+}
+
+impl GitAttributes {
+    /// Returns the parsed `.gitattributes` in a directory, reading it if it
+    /// hasn't been read yet.
+    fn lines(&self, directory: &Path) -> Option<Arc<Vec<Line>>> {
+        if let Some(lines) = self.files.lock().unwrap().get(directory) {
+            return lines.clone();
+        }
+        let lines = fs::read_to_string(directory.join(FILE_NAME)).ok()
+            .map(|contents| Arc::new(parse(&contents)));
+        self.files.lock().unwrap().insert(directory.to_path_buf(), lines.clone());
+        lines
+    }
+
+    /// Works out the attributes that apply to a file, from the
+    /// `.gitattributes` files in its directory and the ones above it up to the
+    /// root of its repository. Closer files and later lines take precedence.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - Path to the file
+    pub fn settings(&self, file_path: &Path) -> Settings {
+        let Ok(file_path) = std::path::absolute(file_path) else {
+            return Settings::default();
+        };
+
+        let mut files = Vec::new();
+        for directory in file_path.ancestors().skip(1) {
+            if let Some(lines) = self.lines(directory) {
+                files.push((directory, lines));
+            }
+            if directory.join(".git").exists() {
+                break;
+            }
+        }
+
+        let mut settings = Settings::default();
+        for (directory, lines) in files.iter().rev() {
+            let Ok(relative) = file_path.strip_prefix(directory) else {
+                continue;
+            };
+            for line in lines.iter().filter(|line| line.matcher.is_match(relative)) {
+                settings.binary = line.settings.binary.or(settings.binary);
+                settings.generated = line.settings.generated.or(settings.generated);
+            }
+        }
+        settings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse() {
+        let lines = parse("# Assets\n*.png binary\n*.svg text eol=lf\n/dist/** linguist-generated=true\n*.rs eol=lf\n[bad -diff\n");
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].settings, Settings { binary: Some(true), generated: None });
+        assert_eq!(lines[1].settings, Settings { binary: Some(false), generated: None });
+        assert_eq!(lines[2].settings, Settings { binary: None, generated: Some(true) });
+        assert!(lines[2].matcher.is_match("dist/app.js"));
+        assert!(!lines[2].matcher.is_match("src/dist/app.js"));
+    }
+
+    #[test]
+    fn test_settings() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::write(root.join(FILE_NAME), "*.lock -diff\nschema.rs linguist-generated\n").unwrap();
+        fs::create_dir(root.join("vendor")).unwrap();
+        fs::write(root.join("vendor").join(FILE_NAME), "*.lock diff\n").unwrap();
+
+        let attributes = GitAttributes::default();
+        assert_eq!(attributes.settings(&root.join("Cargo.lock")).skip_reason(), Some(SkipReason::Binary));
+        assert_eq!(attributes.settings(&root.join("src/schema.rs")).skip_reason(), Some(SkipReason::Generated));
+        assert_eq!(attributes.settings(&root.join("vendor/yarn.lock")), Settings { binary: Some(false), generated: None });
+        assert_eq!(attributes.settings(&root.join("main.rs")), Settings::default());
+    }
+}
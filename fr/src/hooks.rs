@@ -0,0 +1,161 @@
+//! Callbacks run as files go through a find and replace run, set on a
+//! `Session` or as a shell command with `--hook`.
+//!
+//! Hooks are called from whichever thread is working on the file, with the
+//! file's path as it appears in the summary. The start and match hooks can veto
+//! a file, which leaves it unmodified and reports it as skipped.
+
+use crate::ModifiedFile;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+
+/// Decides whether the walk visits a path
+pub(crate) type Filter = Arc<dyn Fn(&Path) -> bool + Send + Sync>;
+
+/// Called before a file is read, returning whether to go on with it
+type StartHook = Arc<dyn Fn(&Path) -> bool + Send + Sync>;
+
+/// Called with the number of replacements in a file before it is written,
+/// returning whether to write it
+type MatchHook = Arc<dyn Fn(&Path, usize) -> bool + Send + Sync>;
+
+/// Called with each file once it has been rewritten
+type WrittenHook = Arc<dyn Fn(&ModifiedFile) + Send + Sync>;
+
+/// Called with each file that could not be processed
+type ErrorHook = Arc<dyn Fn(&Path, &io::Error) + Send + Sync>;
+
+/// Callbacks that customize a run beyond what `Options` can express
+#[derive(Clone, Default)]
+pub(crate) struct Hooks {
+    /// Every filter must accept a path for it to be visited
+    pub(crate) filters: Vec<Filter>,
+    pub(crate) on_file_start: Vec<StartHook>,
+    pub(crate) on_match: Vec<MatchHook>,
+    pub(crate) on_file_written: Vec<WrittenHook>,
+    pub(crate) on_error: Vec<ErrorHook>,
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("filters", &self.filters.len())
+            .field("on_file_start", &self.on_file_start.len())
+            .field("on_match", &self.on_match.len())
+            .field("on_file_written", &self.on_file_written.len())
+            .field("on_error", &self.on_error.len())
+            .finish()
+    }
+}
+
+impl Hooks {
+    /// Whether any hook wants to know when a file is started.
+    pub(crate) fn watches_start(&self) -> bool {
+        !self.on_file_start.is_empty()
+    }
+
+    /// Runs the start hooks, returning whether every one of them lets the file
+    /// be processed.
+    pub(crate) fn file_start(&self, display: &Path) -> bool {
+        self.on_file_start.iter().all(|hook| hook(display))
+    }
+
+    /// Runs the match hooks, returning whether every one of them lets the file
+    /// be written.
+    pub(crate) fn matched(&self, display: &Path, replacements: usize) -> bool {
+        self.on_match.iter().all(|hook| hook(display, replacements))
+    }
+
+    /// Runs the hooks for a file that has been rewritten.
+    pub(crate) fn file_written(&self, file: &ModifiedFile) {
+        for hook in &self.on_file_written {
+            hook(file);
+        }
+    }
+
+    /// Runs the hooks for a file that could not be processed.
+    pub(crate) fn error(&self, display: &Path, e: &io::Error) {
+        for hook in &self.on_error {
+            hook(display, e);
+        }
+    }
+
+    /// Adds hooks that run a shell command for every event, for `--hook`.
+    ///
+    /// The command runs in `directory`, so the relative paths it is given can
+    /// be opened, with the event in `FR_EVENT` (`start`, `match`, `written` or
+    /// `error`), the path in `FR_PATH`, the number of replacements in
+    /// `FR_REPLACEMENTS` and the error in `FR_ERROR`. A command that exits
+    /// unsuccessfully on `start` or `match` vetoes the file.
+    pub(crate) fn with_command(mut self, command: &str, directory: &Path) -> Hooks {
+        let hook = Arc::new(CommandHook { command: command.to_string(), directory: directory.to_path_buf() });
+        {
+            let hook = Arc::clone(&hook);
+            self.on_file_start.push(Arc::new(move |path| hook.run("start", path, &[])));
+        }
+        {
+            let hook = Arc::clone(&hook);
+            self.on_match.push(Arc::new(move |path, replacements| {
+                hook.run("match", path, &[("FR_REPLACEMENTS", replacements.to_string())])
+            }));
+        }
+        {
+            let hook = Arc::clone(&hook);
+            self.on_file_written.push(Arc::new(move |file| {
+                hook.run("written", &file.path, &[("FR_REPLACEMENTS", file.replacements.to_string())]);
+            }));
+        }
+        self.on_error.push(Arc::new(move |path, e| {
+            hook.run("error", path, &[("FR_ERROR", e.to_string())]);
+        }));
+        self
+    }
+}
+
+/// A shell command run for every event, from `--hook`
+struct CommandHook {
+    command: String,
+    directory: PathBuf,
+}
+
+impl CommandHook {
+    /// Runs the command for one event, returning whether it succeeded. A
+    /// command that can't be started counts as failing, so that a broken hook
+    /// vetoes files rather than letting them all through.
+    fn run(&self, event: &str, path: &Path, variables: &[(&str, String)]) -> bool {
+        let mut shell = if cfg!(windows) { Command::new("cmd") } else { Command::new("sh") };
+        shell.arg(if cfg!(windows) { "/C" } else { "-c" }).arg(&self.command);
+        shell.current_dir(&self.directory).stdin(Stdio::null()).env("FR_EVENT", event).env("FR_PATH", path);
+        shell.envs(variables.iter().map(|(name, value)| (name, value)));
+        match shell.status() {
+            Ok(status) => status.success(),
+            Err(e) => {
+                eprintln!("Error running hook for {}: {}", path.display(), e);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_with_command() {
+        let temp_dir = TempDir::new().unwrap();
+        let hooks = Hooks::default().with_command(r#"echo "$FR_EVENT $FR_PATH $FR_REPLACEMENTS" >> events; [ "$FR_PATH" != veto.txt ]"#, temp_dir.path());
+
+        assert!(hooks.file_start(Path::new("a.txt")));
+        assert!(hooks.matched(Path::new("a.txt"), 2));
+        assert!(!hooks.matched(Path::new("veto.txt"), 1));
+        hooks.file_written(&ModifiedFile { path: PathBuf::from("a.txt"), replacements: 2, hunks: Vec::new(), locations: Vec::new() });
+        assert_eq!(fs::read_to_string(temp_dir.path().join("events")).unwrap(), "start a.txt \nmatch a.txt 2\nmatch veto.txt 1\nwritten a.txt 2\n");
+    }
+}
@@ -0,0 +1,110 @@
+//! Replacing only in the paths of C, C++ and Objective-C `#include` lines, for
+//! `--includes`.
+//!
+//! A match is kept if it is inside the path of an `#include`, `#include_next`
+//! or `#import` directive, between its quotes or angle brackets, so moving a
+//! header never touches the same path in strings, comments or build scripts.
+
+use std::path::Path;
+
+/// Returns whether a file is C, C++ or Objective-C source or a header, going by
+/// its extension.
+pub fn is_c(file_path: &Path) -> bool {
+    file_path.extension().and_then(|extension| extension.to_str()).is_some_and(|extension| {
+        matches!(extension.to_ascii_lowercase().as_str(),
+            "c" | "h" | "cc" | "cpp" | "cxx" | "c++" | "hh" | "hpp" | "hxx" | "h++" | "inl" | "ipp" | "tcc" | "m" | "mm")
+    })
+}
+
+/// Keeps the matches that lie wholly inside the path of an include directive,
+/// where the replacement can go as it is.
+///
+/// # Arguments
+///
+/// * `content` - The file's text
+/// * `offsets` - Where each match of the find text starts
+/// * `find_len` - Length of the find text in bytes
+/// * `replace_text` - Text each match would be replaced with
+///
+/// # Returns
+///
+/// * `Vec<usize>` - The offsets of the matches to replace
+pub fn include_offsets(content: &str, offsets: &[usize], find_len: usize, replace_text: &str) -> Vec<usize> {
+    if replace_text.contains(['\n', '\r']) {
+        return Vec::new();
+    }
+    let paths = paths(content);
+    let mut paths = paths.iter().peekable();
+    offsets.iter().copied().filter(|&offset| {
+        while paths.next_if(|&&(_, end, _)| end < offset + find_len).is_some() {}
+        // A replacement with the closing delimiter would end the path early
+        paths.peek().is_some_and(|&&(start, end, close)| offset >= start && offset + find_len <= end && !replace_text.contains(close))
+    }).collect()
+}
+
+/// Finds the paths of the include directives.
+///
+/// # Returns
+///
+/// * `Vec<(usize, usize, char)>` - Where the text of each path starts and
+///   ends, without its delimiters, and the delimiter that closes it
+fn paths(content: &str) -> Vec<(usize, usize, char)> {
+    let mut paths = Vec::new();
+    let mut line_start = 0;
+    for line in content.split_inclusive('\n') {
+        if let Some((start, end, close)) = directive_path(line) {
+            paths.push((line_start + start, line_start + end, close));
+        }
+        line_start += line.len();
+    }
+    paths
+}
+
+/// Finds the path of the directive on a line, if it is an include directive.
+fn directive_path(line: &str) -> Option<(usize, usize, char)> {
+    let rest = line.trim_start().strip_prefix('#')?.trim_start();
+    let rest = ["include_next", "include", "import"].iter().find_map(|directive| rest.strip_prefix(directive))?;
+    let rest = rest.trim_start();
+    let close = match rest.chars().next()? {
+        '"' => '"',
+        '<' => '>',
+        _ => return None,
+    };
+    let start = line.len() - rest.len() + 1;
+    let end = start + line[start..].find(close)?;
+    match line[start..end].contains('\n') {
+        true => None,
+        false => Some((start, end, close)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{match_offsets, splice};
+
+    fn replace(content: &str, find_text: &str, replace_text: &str) -> String {
+        let offsets = include_offsets(content, &match_offsets(content, find_text), find_text.len(), replace_text);
+        splice(content, &offsets, find_text.len(), replace_text)
+    }
+
+    #[test]
+    fn test_include_offsets() {
+        let source = "#include \"util/log.h\"\n  # include <util/log.h>\n#import \"util/log.h\"\n\
+            #include_next <util/log.h>\n// see util/log.h\nconst char *p = \"util/log.h\";\n#define LOG \"util/log.h\"\n";
+        assert_eq!(replace(source, "util/log.h", "base/logging.h"),
+            "#include \"base/logging.h\"\n  # include <base/logging.h>\n#import \"base/logging.h\"\n\
+            #include_next <base/logging.h>\n// see util/log.h\nconst char *p = \"util/log.h\";\n#define LOG \"util/log.h\"\n");
+        // Matches over the end of the path, and replacements with its closing
+        // delimiter, are left alone
+        assert_eq!(replace("#include \"a.h\" // a.h\n", "a.h\"", "b.h\""), "#include \"a.h\" // a.h\n");
+        assert_eq!(replace("#include <a.h>\n", "a.h", "a>.h"), "#include <a.h>\n");
+        assert_eq!(replace("#include \"a.h\"\n", "a.h", "a>.h"), "#include \"a>.h\"\n");
+    }
+
+    #[test]
+    fn test_is_c() {
+        assert!(is_c(Path::new("src/main.cpp")) && is_c(Path::new("View.mm")) && is_c(Path::new("LOG.H")));
+        assert!(!is_c(Path::new("build.rs")) && !is_c(Path::new("Makefile")));
+    }
+}
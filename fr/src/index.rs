@@ -0,0 +1,430 @@
+//! A trigram index of a tree, built with `fr index`.
+//!
+//! The index records which files contain each sequence of three bytes. Before
+//! searching a tree, fr looks up the trigrams of the find text to shortlist the
+//! files that can contain it; every other indexed file that hasn't changed since
+//! is skipped without being read. Files that are new or changed since the index
+//! was built are searched as usual.
+//!
+//! The index is a binary file, `.fr-index`, at the root of the tree. It starts
+//! with `INDEX_MAGIC` and is followed by LEB128 varints: the number of files,
+//! then each file's path length, path, size, and modification time in seconds
+//! and nanoseconds; then the number of trigrams, and each trigram (as the gap
+//! from the previous one) with the number of files that contain it and their
+//! numbers (as gaps from the previous one).
+
+use crate::encoding::Encoding;
+use crate::{scan, skip_reason, Options};
+use ignore::{WalkBuilder, WalkState};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::UNIX_EPOCH;
+
+/// The name of the index file at the root of an indexed tree
+pub const INDEX_FILE: &str = ".fr-index";
+
+/// The first bytes of an index file, which change whenever its format does
+const INDEX_MAGIC: &[u8] = b"fr index 1\n";
+
+/// What an indexed file looked like when it was indexed
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct IndexedFile {
+    /// Path relative to the root of the tree, with `/` separators
+    path: String,
+    size: u64,
+    /// Seconds and nanoseconds since the Unix epoch it was last modified at
+    modified: (u64, u32),
+}
+
+impl IndexedFile {
+    /// Whether a file still looks the way it did when it was indexed.
+    fn is_unchanged(&self, metadata: &fs::Metadata) -> bool {
+        metadata.len() == self.size && modified(metadata).is_ok_and(|modified| modified == self.modified)
+    }
+}
+
+/// Returns a file's modification time as seconds and nanoseconds since the Unix epoch.
+fn modified(metadata: &fs::Metadata) -> io::Result<(u64, u32)> {
+    let since_epoch = metadata.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+    Ok((since_epoch.as_secs(), since_epoch.subsec_nanos()))
+}
+
+/// Returns every trigram in some bytes, each packed into the low 24 bits.
+fn trigrams(bytes: &[u8]) -> HashSet<u32> {
+    bytes.windows(3)
+        .map(|window| u32::from(window[0]) << 16 | u32::from(window[1]) << 8 | u32::from(window[2]))
+        .collect()
+}
+
+/// The size and modification time of an index file when it was loaded
+type Stamp = (u64, (u64, u32));
+
+/// Loaded indexes by the path of their file
+type Loaded = HashMap<PathBuf, (Stamp, Arc<Index>)>;
+
+/// The indexes kept loaded by `keep_loaded`
+static LOADED: OnceLock<Mutex<Loaded>> = OnceLock::new();
+
+/// Keeps each index once it has been loaded, for `fr serve`, so that every
+/// request after the first reuses it until `fr index` writes a new one.
+pub fn keep_loaded() {
+    let _ = LOADED.set(Mutex::default());
+}
+
+/// Returns a path relative to a root as index paths are stored.
+fn index_path(root: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+    let parts: Option<Vec<&str>> = relative.components().map(|component| component.as_os_str().to_str()).collect();
+    Some(parts?.join("/"))
+}
+
+/// A loaded trigram index
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Index {
+    /// Absolute path of the indexed tree
+    root: PathBuf,
+    files: Vec<IndexedFile>,
+    /// The number of each file in `files` by its path
+    numbers: HashMap<String, u32>,
+    /// The numbers of the files that contain each trigram
+    postings: HashMap<u32, Vec<u32>>,
+}
+
+impl Index {
+    /// Builds the index of a tree, covering the files a find and replace run
+    /// would search.
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - Root directory of the tree
+    /// * `options` - Options that limit which files are searched
+    pub fn build(root: &Path, options: &Options) -> io::Result<Index> {
+        let root = std::path::absolute(root)?;
+        let indexed = Mutex::new(Vec::new());
+        let mut builder = WalkBuilder::new(&root);
+        builder.threads(options.threads.unwrap_or(0));
+        builder.build_parallel().run(|| {
+            let (root, indexed) = (&root, &indexed);
+            Box::new(move |result| {
+                let Ok(dent) = result else {
+                    return WalkState::Continue;
+                };
+                let path = dent.path();
+                if skip_reason(path, options).is_some() || path == root.join(INDEX_FILE) {
+                    return WalkState::Continue;
+                }
+                let file = fs::metadata(path).and_then(|metadata| {
+                    let contents = crate::read_file(path)?;
+                    Ok((metadata.len(), modified(&metadata)?, trigrams(&contents)))
+                });
+                match (index_path(root, path), file) {
+                    (Some(index_path), Ok((size, modified, trigrams))) => {
+                        indexed.lock().unwrap().push((IndexedFile { path: index_path, size, modified }, trigrams));
+                    }
+                    (_, Err(e)) => eprintln!("Error indexing {}: {}", path.display(), e),
+                    // Paths that aren't valid UTF-8 are left out, and searched as usual
+                    (None, _) => {}
+                }
+                WalkState::Continue
+            })
+        });
+
+        let mut indexed = indexed.into_inner().unwrap();
+        indexed.sort_by(|a, b| a.0.path.cmp(&b.0.path));
+        let mut index = Index { root, ..Index::default() };
+        for (number, (file, trigrams)) in indexed.into_iter().enumerate() {
+            let number = number as u32;
+            for trigram in trigrams {
+                index.postings.entry(trigram).or_default().push(number);
+            }
+            index.numbers.insert(file.path.clone(), number);
+            index.files.push(file);
+        }
+        Ok(index)
+    }
+
+    /// The number of files in the index
+    pub fn file_count(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Loads the index of a tree, if it has one.
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - Root directory of the tree
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<Option<Index>>` - The index, None if the tree has no index,
+    ///   or an error if it can't be read or isn't an index
+    pub fn load(root: &Path) -> io::Result<Option<Index>> {
+        let bytes = match fs::read(root.join(INDEX_FILE)) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("{} is not a valid index, run fr index again", INDEX_FILE));
+        let mut index = decode(&bytes).ok_or_else(invalid)?;
+        index.root = std::path::absolute(root)?;
+        Ok(Some(index))
+    }
+
+    /// Loads the index at the root of a tree as `load` does, reusing the copy
+    /// loaded last time while the index file is unchanged if indexes are kept
+    /// loaded.
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<Option<Arc<Index>>>` - The index, None if the tree has no
+    ///   index, or an error if it can't be read or isn't an index
+    pub fn load_shared(root: &Path) -> io::Result<Option<Arc<Index>>> {
+        let Some(loaded) = LOADED.get() else {
+            return Ok(Index::load(root)?.map(Arc::new));
+        };
+        let path = std::path::absolute(root)?.join(INDEX_FILE);
+        let stamp = match fs::metadata(&path) {
+            Ok(metadata) => (metadata.len(), modified(&metadata)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (0, (0, 0)),
+            Err(e) => return Err(e),
+        };
+        if let Some((_, index)) = loaded.lock().unwrap().get(&path).filter(|(loaded_stamp, _)| *loaded_stamp == stamp) {
+            return Ok(Some(Arc::clone(index)));
+        }
+        let index = Index::load(root)?.map(Arc::new);
+        let mut loaded = loaded.lock().unwrap();
+        match &index {
+            Some(index) => loaded.insert(path, (stamp, Arc::clone(index))),
+            None => loaded.remove(&path),
+        };
+        Ok(index)
+    }
+
+    /// Saves the index to the root of its tree.
+    pub fn save(&self) -> io::Result<()> {
+        crate::write_atomically(&self.root.join(INDEX_FILE), &self.encode())
+    }
+
+    /// Encodes the index in the format it is saved in.
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = INDEX_MAGIC.to_vec();
+        write_varint(&mut bytes, self.files.len() as u64);
+        for file in &self.files {
+            write_varint(&mut bytes, file.path.len() as u64);
+            bytes.extend_from_slice(file.path.as_bytes());
+            write_varint(&mut bytes, file.size);
+            write_varint(&mut bytes, file.modified.0);
+            write_varint(&mut bytes, u64::from(file.modified.1));
+        }
+
+        let mut trigrams: Vec<(&u32, &Vec<u32>)> = self.postings.iter().collect();
+        trigrams.sort();
+        write_varint(&mut bytes, trigrams.len() as u64);
+        let mut previous_trigram = 0;
+        for (&trigram, numbers) in trigrams {
+            write_varint(&mut bytes, u64::from(trigram - previous_trigram));
+            previous_trigram = trigram;
+            write_varint(&mut bytes, numbers.len() as u64);
+            let mut previous_number = 0;
+            for &number in numbers {
+                write_varint(&mut bytes, u64::from(number - previous_number));
+                previous_number = number;
+            }
+        }
+        bytes
+    }
+
+    /// Finds the files that may contain the find text.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Shortlist>` - The shortlist, or None if the find text is too
+    ///   short for the index to rule any file out
+    pub fn shortlist(&self, find_text: &str) -> Option<Shortlist<'_>> {
+        // Every encoding that can be searched by bytes looks for the same text
+        let needle = scan::needle(find_text, Encoding::Utf8)?;
+        let mut candidates: Option<HashSet<u32>> = None;
+        for trigram in trigrams(needle.as_bytes()) {
+            let files: HashSet<u32> = self.postings.get(&trigram).into_iter().flatten().copied().collect();
+            candidates = Some(match candidates {
+                Some(candidates) => &candidates & &files,
+                None => files,
+            });
+        }
+        Some(Shortlist { index: self, find_text: find_text.to_string(), candidates: candidates? })
+    }
+}
+
+/// The indexed files that may contain the find text
+#[derive(Debug)]
+pub struct Shortlist<'a> {
+    index: &'a Index,
+    find_text: String,
+    candidates: HashSet<u32>,
+}
+
+impl Shortlist<'_> {
+    /// Whether a file is known not to contain the find text: it is in the index,
+    /// isn't on the shortlist, and hasn't changed since it was indexed.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - Path to the file
+    /// * `encoding` - The encoding the file is read in
+    pub fn rules_out(&self, file_path: &Path, encoding: Encoding) -> bool {
+        if scan::needle(&self.find_text, encoding).is_none() {
+            return false;
+        }
+        let Some(number) = std::path::absolute(file_path).ok()
+            .and_then(|path| index_path(&self.index.root, &path))
+            .and_then(|path| self.index.numbers.get(&path).copied())
+        else {
+            return false;
+        };
+        !self.candidates.contains(&number)
+            && fs::metadata(file_path).is_ok_and(|metadata| self.index.files[number as usize].is_unchanged(&metadata))
+    }
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = bytes.split_first()?;
+        *bytes = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Decodes a saved index, or returns None if it is malformed.
+fn decode(bytes: &[u8]) -> Option<Index> {
+    let mut bytes = bytes.strip_prefix(INDEX_MAGIC)?;
+    let mut index = Index::default();
+    let file_count = read_varint(&mut bytes)?;
+    for number in 0..file_count {
+        let length = usize::try_from(read_varint(&mut bytes)?).ok()?;
+        let path = String::from_utf8(bytes.get(..length)?.to_vec()).ok()?;
+        bytes = &bytes[length..];
+        let size = read_varint(&mut bytes)?;
+        let modified = (read_varint(&mut bytes)?, u32::try_from(read_varint(&mut bytes)?).ok()?);
+        index.numbers.insert(path.clone(), u32::try_from(number).ok()?);
+        index.files.push(IndexedFile { path, size, modified });
+    }
+
+    let trigram_count = read_varint(&mut bytes)?;
+    let mut trigram = 0u32;
+    for _ in 0..trigram_count {
+        trigram = trigram.checked_add(u32::try_from(read_varint(&mut bytes)?).ok()?)?;
+        let number_count = read_varint(&mut bytes)?;
+        let mut numbers = Vec::new();
+        let mut number = 0u32;
+        for _ in 0..number_count {
+            number = number.checked_add(u32::try_from(read_varint(&mut bytes)?).ok()?)?;
+            if number as usize >= index.files.len() {
+                return None;
+            }
+            numbers.push(number);
+        }
+        index.postings.insert(trigram, numbers);
+    }
+    bytes.is_empty().then_some(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn build_tree() -> TempDir {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("hello.txt"), "hello world").unwrap();
+        fs::write(temp_dir.path().join("goodbye.txt"), "goodbye world").unwrap();
+        fs::create_dir(temp_dir.path().join("sub")).unwrap();
+        fs::write(temp_dir.path().join("sub").join("both.txt"), "hello\r\ngoodbye").unwrap();
+        temp_dir
+    }
+
+    #[test]
+    fn test_varint_round_trip() {
+        let mut bytes = Vec::new();
+        for value in [0, 1, 127, 128, 300, u64::from(u32::MAX), u64::MAX] {
+            write_varint(&mut bytes, value);
+        }
+        let mut rest = bytes.as_slice();
+        for value in [0, 1, 127, 128, 300, u64::from(u32::MAX), u64::MAX] {
+            assert_eq!(read_varint(&mut rest), Some(value));
+        }
+        assert!(rest.is_empty());
+        assert_eq!(read_varint(&mut [0x80].as_slice()), None);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let temp_dir = build_tree();
+        let index = Index::build(temp_dir.path(), &Options::default()).unwrap();
+        assert_eq!(index.file_count(), 3);
+        index.save().unwrap();
+
+        assert_eq!(Index::load(temp_dir.path()).unwrap(), Some(index));
+        assert_eq!(Index::load(&temp_dir.path().join("sub")).unwrap(), None);
+
+        fs::write(temp_dir.path().join(INDEX_FILE), b"fr index 1\n\xFF").unwrap();
+        assert!(Index::load(temp_dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_load_shared() {
+        let temp_dir = build_tree();
+        keep_loaded();
+        assert!(Index::load_shared(temp_dir.path()).unwrap().is_none());
+        Index::build(temp_dir.path(), &Options::default()).unwrap().save().unwrap();
+
+        let index = Index::load_shared(temp_dir.path()).unwrap().unwrap();
+        assert!(Arc::ptr_eq(&index, &Index::load_shared(temp_dir.path()).unwrap().unwrap()));
+        fs::write(temp_dir.path().join("new.txt"), "new").unwrap();
+        Index::build(temp_dir.path(), &Options::default()).unwrap().save().unwrap();
+        let rebuilt = Index::load_shared(temp_dir.path()).unwrap().unwrap();
+        assert!(!Arc::ptr_eq(&index, &rebuilt) && rebuilt.file_count() == 4);
+    }
+
+    #[test]
+    fn test_shortlist() {
+        let temp_dir = build_tree();
+        let root = temp_dir.path();
+        let index = Index::build(root, &Options::default()).unwrap();
+
+        let shortlist = index.shortlist("goodbye").unwrap();
+        assert!(shortlist.rules_out(&root.join("hello.txt"), Encoding::Utf8));
+        assert!(!shortlist.rules_out(&root.join("goodbye.txt"), Encoding::Utf8));
+        assert!(!shortlist.rules_out(&root.join("sub").join("both.txt"), Encoding::Utf8));
+
+        // The lines of a multi-line find text may be separated by CRLFs
+        let shortlist = index.shortlist("hello\ngoodbye").unwrap();
+        assert!(!shortlist.rules_out(&root.join("sub").join("both.txt"), Encoding::Utf8));
+
+        // New and changed files have to be searched
+        let shortlist = index.shortlist("planet").unwrap();
+        fs::write(root.join("new.txt"), "nothing").unwrap();
+        assert!(!shortlist.rules_out(&root.join("new.txt"), Encoding::Utf8));
+        fs::write(root.join("hello.txt"), "hello planet").unwrap();
+        assert!(!shortlist.rules_out(&root.join("hello.txt"), Encoding::Utf8));
+        assert!(shortlist.rules_out(&root.join("goodbye.txt"), Encoding::Utf8));
+        assert!(!shortlist.rules_out(&root.join("goodbye.txt"), Encoding::Utf16Le));
+
+        assert!(index.shortlist("hi").is_none());
+    }
+}
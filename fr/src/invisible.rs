@@ -0,0 +1,120 @@
+//! Invisible Unicode characters, which `fr clean --invisible` removes and
+//! `--confusables` reads past.
+//!
+//! These render as nothing, or only change the direction text is shown in,
+//! so they can hide inside words and identifiers, or reorder what a reviewer
+//! sees as in the "Trojan Source" attacks. A byte order mark at the very start
+//! of a file is kept, as are zero width joiners between emoji, which join them
+//! into one.
+
+use crate::color::{Painter, Role};
+use std::path::Path;
+
+/// The invisible characters, with their Unicode names
+const INVISIBLE: [(char, &str); 24] = [
+    ('\u{00AD}', "SOFT HYPHEN"),
+    ('\u{034F}', "COMBINING GRAPHEME JOINER"),
+    ('\u{061C}', "ARABIC LETTER MARK"),
+    ('\u{180E}', "MONGOLIAN VOWEL SEPARATOR"),
+    ('\u{200B}', "ZERO WIDTH SPACE"),
+    ('\u{200C}', "ZERO WIDTH NON-JOINER"),
+    ('\u{200D}', "ZERO WIDTH JOINER"),
+    ('\u{200E}', "LEFT-TO-RIGHT MARK"),
+    ('\u{200F}', "RIGHT-TO-LEFT MARK"),
+    ('\u{202A}', "LEFT-TO-RIGHT EMBEDDING"),
+    ('\u{202B}', "RIGHT-TO-LEFT EMBEDDING"),
+    ('\u{202C}', "POP DIRECTIONAL FORMATTING"),
+    ('\u{202D}', "LEFT-TO-RIGHT OVERRIDE"),
+    ('\u{202E}', "RIGHT-TO-LEFT OVERRIDE"),
+    ('\u{2060}', "WORD JOINER"),
+    ('\u{2061}', "FUNCTION APPLICATION"),
+    ('\u{2062}', "INVISIBLE TIMES"),
+    ('\u{2063}', "INVISIBLE SEPARATOR"),
+    ('\u{2064}', "INVISIBLE PLUS"),
+    ('\u{2066}', "LEFT-TO-RIGHT ISOLATE"),
+    ('\u{2067}', "RIGHT-TO-LEFT ISOLATE"),
+    ('\u{2068}', "FIRST STRONG ISOLATE"),
+    ('\u{2069}', "POP DIRECTIONAL ISOLATE"),
+    ('\u{FEFF}', "ZERO WIDTH NO-BREAK SPACE"),
+];
+
+/// Returns the name of a character if it is invisible.
+pub fn name(c: char) -> Option<&'static str> {
+    INVISIBLE.iter().find(|&&(invisible, _)| invisible == c).map(|&(_, name)| name)
+}
+
+/// Whether a character is an emoji, which a zero width joiner can join to
+/// another.
+fn is_emoji(c: char) -> bool {
+    matches!(c, '\u{1F000}'..='\u{1FAFF}' | '\u{2600}'..='\u{27BF}' | '\u{FE0F}')
+}
+
+/// Finds where each invisible character that should be removed starts and
+/// ends, and the character.
+pub fn spans(content: &str) -> Vec<(usize, usize, char)> {
+    let mut spans = Vec::new();
+    let mut previous = None;
+    for (start, c) in content.char_indices() {
+        let kept = (c == '\u{FEFF}' && start == 0)
+            || (c == '\u{200D}' && previous.is_some_and(is_emoji) && content[start + c.len_utf8()..].chars().next().is_some_and(is_emoji));
+        if !kept && name(c).is_some() {
+            spans.push((start, start + c.len_utf8(), c));
+        }
+        previous = Some(c);
+    }
+    spans
+}
+
+/// Describes where each invisible character removed from a file was, a line
+/// per character as `path:line:column: U+XXXX NAME`.
+///
+/// # Arguments
+///
+/// * `display` - Path to show
+/// * `content` - The file before the characters were removed
+/// * `painter` - Colors the path and position
+///
+/// # Returns
+///
+/// * `String` - The report, with a trailing newline
+pub fn report(display: &Path, content: &str, painter: &Painter) -> String {
+    let path = painter.paint(Role::Path, &display.display().to_string());
+    let mut report = String::new();
+    let mut line = 1;
+    let mut line_start = 0;
+    let mut scanned = 0;
+    for (start, _, c) in spans(content) {
+        for (i, byte) in content[scanned..start].bytes().enumerate() {
+            if byte == b'\n' {
+                line += 1;
+                line_start = scanned + i + 1;
+            }
+        }
+        scanned = start;
+        report.push_str(&format!("{}:{}:{}: U+{:04X} {}\n", path,
+            painter.paint(Role::LineNumber, &line.to_string()),
+            painter.paint(Role::LineNumber, &(start - line_start + 1).to_string()),
+            c as u32, name(c).unwrap_or_default()));
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::{ColorChoice, Theme};
+
+    #[test]
+    fn test_spans() {
+        let content = "\u{FEFF}let a\u{200B}b = 1;\n// \u{202E}evil\u{2066}\n\u{1F468}\u{200D}\u{1F469} x\u{200D}y\u{FEFF}";
+        let found: Vec<char> = spans(content).into_iter().map(|(_, _, c)| c).collect();
+        assert_eq!(found, vec!['\u{200B}', '\u{202E}', '\u{2066}', '\u{200D}', '\u{FEFF}']);
+    }
+
+    #[test]
+    fn test_report() {
+        let painter = Painter::new(ColorChoice::Never, Theme::Default, false);
+        assert_eq!(report(Path::new("src/main.rs"), "fn main() {\n    let a\u{200B}b = 1;\n}\n", &painter),
+            "src/main.rs:2:10: U+200B ZERO WIDTH SPACE\n");
+    }
+}
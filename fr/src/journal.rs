@@ -0,0 +1,449 @@
+//! A SQLite journal of every run, file and replacement, for
+//! `--journal sqlite:PATH` and `fr journal`.
+//!
+//! The journal has a table for each of them, so it can be queried with any
+//! SQLite client as well as with `fr journal`:
+//!
+//! ```sql
+//! CREATE TABLE runs (id INTEGER PRIMARY KEY, time TEXT, root TEXT, find TEXT, replace TEXT);
+//! CREATE TABLE files (id INTEGER PRIMARY KEY, run INTEGER REFERENCES runs (id), path TEXT, hash TEXT);
+//! CREATE TABLE edits (file INTEGER REFERENCES files (id), start INTEGER, before TEXT, after TEXT);
+//! ```
+//!
+//! A file's `path` is relative to its run's `root`, its `hash` is an FNV-1a
+//! hash of the file as the run left it, and each edit `start`s at a byte of
+//! the file as it was before the run. fr talks to the database through the
+//! `sqlite3` shell rather than linking SQLite in: a run keeps one `sqlite3`
+//! open for its whole walk and sends it each file it rewrites in a
+//! transaction of its own, so a run cut short is recorded up to the last file
+//! it wrote. A run is only recorded once it has rewritten a file.
+//!
+//! `fr journal` lists the runs, or those that touched a file, or the edits of
+//! one run, and undoes a run by making its edits the other way round as a
+//! plan, only in the files whose hash shows they haven't changed since.
+
+use crate::change_log::format_timestamp;
+use crate::plan::{self, PlannedEdit, PlannedFile};
+use crate::Summary;
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// The tables of a journal, created the first time a run opens it
+const SCHEMA: &str = "\
+CREATE TABLE IF NOT EXISTS runs (id INTEGER PRIMARY KEY, time TEXT, root TEXT, find TEXT, replace TEXT);
+CREATE TABLE IF NOT EXISTS files (id INTEGER PRIMARY KEY, run INTEGER REFERENCES runs (id), path TEXT, hash TEXT);
+CREATE TABLE IF NOT EXISTS edits (file INTEGER REFERENCES files (id), start INTEGER, before TEXT, after TEXT);
+CREATE INDEX IF NOT EXISTS files_by_run ON files (run);
+CREATE INDEX IF NOT EXISTS edits_by_file ON edits (file);
+";
+
+/// A run in the journal
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalRun {
+    /// The number of the run, counting from 1
+    pub id: u64,
+    /// When the run started, as an RFC 3339 timestamp
+    pub time: String,
+    /// The absolute path of the directory the run was in
+    pub root: PathBuf,
+    /// Text the run found
+    pub find: String,
+    /// Text it replaced it with
+    pub replace: String,
+    /// How many files it rewrote
+    pub files: usize,
+}
+
+/// A file a run rewrote
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalFile {
+    /// Path relative to the run's root
+    pub path: PathBuf,
+    /// FNV-1a hash of the file's contents as the run wrote them
+    pub hash: u64,
+    /// The replacements made, in order
+    pub edits: Vec<JournalEdit>,
+}
+
+/// One replacement made in a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalEdit {
+    /// The byte the replaced text started at, or None if the file wasn't read
+    /// as UTF-8, so the bytes aren't known
+    pub start: Option<usize>,
+    /// The text that was replaced
+    pub before: String,
+    /// The text that replaced it
+    pub after: String,
+}
+
+/// What `fr journal` looks up in a journal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalQuery<'a> {
+    /// Every run
+    Runs,
+    /// The runs that rewrote a file
+    Touching(&'a str),
+    /// The edits of a run
+    Edits(u64),
+    /// Undo a run
+    Undo(u64),
+}
+
+/// Parses a `--journal` value, `sqlite:PATH`.
+pub fn parse(value: &str) -> Result<PathBuf, String> {
+    match value.strip_prefix("sqlite:") {
+        Some(path) if !path.is_empty() => Ok(PathBuf::from(path)),
+        _ => Err(format!("Invalid journal '{}', expected sqlite:PATH", value)),
+    }
+}
+
+/// The database file and the files `sqlite3` keeps beside it while it writes,
+/// which a run leaves out of its walk.
+pub fn database_files(path: &Path) -> Vec<PathBuf> {
+    let mut files = vec![path.to_path_buf()];
+    for suffix in ["-journal", "-wal", "-shm"] {
+        let mut file = path.as_os_str().to_owned();
+        file.push(suffix);
+        files.push(PathBuf::from(file));
+    }
+    files
+}
+
+/// A journal a run records the files it rewrites in, shared between walker
+/// threads
+pub struct Journal {
+    path: PathBuf,
+    shell: Mutex<Shell>,
+}
+
+/// The `sqlite3` a run writes its journal through
+struct Shell {
+    child: Child,
+    stdin: ChildStdin,
+    /// The statements that record the run, until they are sent with the
+    /// first file
+    run: Option<String>,
+}
+
+impl Journal {
+    /// Opens a journal for recording a run, creating it if needed.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The database file
+    /// * `root` - The directory the run is in
+    /// * `find_text` - Text the run finds
+    /// * `replace_text` - Text it replaces it with
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<Journal>` - The journal, or an error if `sqlite3` can't be
+    ///   started
+    pub fn open(path: &Path, root: &Path, find_text: &str, replace_text: &str) -> io::Result<Journal> {
+        let root = std::path::absolute(root)?;
+        let root = root.to_str()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{} isn't valid UTF-8, so can't be journaled", root.display())))?;
+        let mut child = sqlite3(path).stdin(Stdio::piped()).stdout(Stdio::null()).stderr(Stdio::piped()).spawn()?;
+        let mut stdin = child.stdin.take().expect("sqlite3 has a piped stdin");
+        // Wait for other runs writing to the same journal rather than fail
+        stdin.write_all(b".timeout 10000\n")?;
+        stdin.write_all(SCHEMA.as_bytes())?;
+        // The run is numbered by SQLite, and the files refer to it through a
+        // table of this connection's own
+        let run = format!("INSERT INTO runs (time, root, find, replace) VALUES ({}, {}, {}, {});\n\
+            CREATE TEMP TABLE current (run INTEGER, file INTEGER);\n\
+            INSERT INTO current VALUES (last_insert_rowid(), NULL);\n",
+            text(&format_timestamp(SystemTime::now())), text(root), text(find_text), text(replace_text));
+        Ok(Journal { path: path.to_path_buf(), shell: Mutex::new(Shell { child, stdin, run: Some(run) }) })
+    }
+
+    /// Records a file the run has just rewritten.
+    ///
+    /// # Arguments
+    ///
+    /// * `display` - Path of the file relative to the run's root
+    /// * `path` - Path to read the file from, to hash what was written
+    /// * `before` - The text of the file before it was rewritten
+    /// * `after` - The text it was rewritten to
+    /// * `replaced` - Each match in `before` and what replaced it, or None to
+    ///   record the whole text as one edit, when the changes weren't found
+    ///   match by match
+    pub fn record(&self, display: &Path, path: &Path, before: &str, after: &str, replaced: Option<&[(Range<usize>, &str)]>) -> io::Result<()> {
+        let written = fs::read(path)?;
+        // The text is at the same bytes as in the file if it was read as
+        // UTF-8, after any byte order mark
+        let offset = written.len().checked_sub(after.len())
+            .filter(|&offset| written[offset..] == *after.as_bytes() && matches!(&written[..offset], b"" | b"\xEF\xBB\xBF"));
+        let edits: Vec<JournalEdit> = match replaced {
+            Some(replaced) => replaced.iter().map(|(found, with)| JournalEdit {
+                start: offset.map(|offset| offset + found.start),
+                before: before[found.clone()].to_string(),
+                after: with.to_string(),
+            }).collect(),
+            None => vec![JournalEdit { start: offset, before: before.to_string(), after: after.to_string() }],
+        };
+        let display = display.to_str()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "isn't valid UTF-8, so can't be journaled"))?;
+
+        let mut shell = self.shell.lock().unwrap();
+        let mut sql = String::from("BEGIN IMMEDIATE;\n");
+        sql.push_str(&shell.run.take().unwrap_or_default());
+        sql.push_str(&format!("INSERT INTO files (run, path, hash) SELECT run, {}, '{:016x}' FROM current;\n\
+            UPDATE current SET file = last_insert_rowid();\n", text(display), plan::hash(&written)));
+        for edit in &edits {
+            let start = edit.start.map_or("NULL".to_string(), |start| start.to_string());
+            sql.push_str(&format!("INSERT INTO edits (file, start, before, after) SELECT file, {}, {}, {} FROM current;\n",
+                start, text(&edit.before), text(&edit.after)));
+        }
+        sql.push_str("COMMIT;\n");
+        // Each file is sent in one go, so parallel walkers don't interleave
+        shell.stdin.write_all(sql.as_bytes()).map_err(|_| {
+            io::Error::other(format!("sqlite3 stopped writing {}", self.path.display()))
+        })
+    }
+
+    /// Closes the journal once the run has finished, waiting for `sqlite3` to
+    /// write the last file.
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<()>` - An error with what `sqlite3` said if it failed
+    pub fn close(self) -> io::Result<()> {
+        let Shell { child, stdin, .. } = self.shell.into_inner().unwrap();
+        drop(stdin);
+        let output = child.wait_with_output()?;
+        match output.status.success() {
+            true => Ok(()),
+            false => Err(io::Error::other(format!("sqlite3 failed to write {}: {}", self.path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()))),
+        }
+    }
+}
+
+/// Lists the runs in a journal.
+///
+/// # Arguments
+///
+/// * `path` - The database file
+/// * `touching` - Only list the runs that rewrote this file, given by its
+///   absolute path
+pub fn runs(path: &Path, touching: Option<&Path>) -> io::Result<Vec<JournalRun>> {
+    let touched: Option<HashSet<u64>> = match touching {
+        Some(file) => {
+            let rows = select(path, "SELECT files.run, hex(runs.root), hex(files.path) FROM files JOIN runs ON runs.id = files.run;")?;
+            let mut touched = HashSet::new();
+            for row in rows {
+                if PathBuf::from(unhex(&row[1])?).join(unhex(&row[2])?) == file {
+                    touched.insert(number(&row[0])?);
+                }
+            }
+            Some(touched)
+        }
+        None => None,
+    };
+    let rows = select(path, "SELECT id, hex(time), hex(root), hex(find), hex(replace), \
+        (SELECT count(*) FROM files WHERE files.run = runs.id) FROM runs ORDER BY id;")?;
+    let mut runs = Vec::new();
+    for row in rows {
+        let id = number(&row[0])?;
+        if touched.as_ref().is_none_or(|touched| touched.contains(&id)) {
+            runs.push(JournalRun { id, time: unhex(&row[1])?, root: PathBuf::from(unhex(&row[2])?), find: unhex(&row[3])?,
+                replace: unhex(&row[4])?, files: number(&row[5])? as usize });
+        }
+    }
+    Ok(runs)
+}
+
+/// Reads the files a run rewrote, with their edits, in the order they were
+/// written.
+pub fn files(path: &Path, run: u64) -> io::Result<Vec<JournalFile>> {
+    let rows = select(path, &format!("SELECT files.id, hex(files.path), files.hash, ifnull(edits.start, ''), hex(edits.before), \
+        hex(edits.after) FROM files JOIN edits ON edits.file = files.id WHERE files.run = {} ORDER BY files.id, edits.rowid;", run))?;
+    let mut files: Vec<(String, JournalFile)> = Vec::new();
+    for row in rows {
+        let start = match row[3].as_str() {
+            "" => None,
+            start => Some(number(start)? as usize),
+        };
+        let edit = JournalEdit { start, before: unhex(&row[4])?, after: unhex(&row[5])? };
+        match files.last_mut().filter(|(id, _)| *id == row[0]) {
+            Some((_, file)) => file.edits.push(edit),
+            None => {
+                let hash = u64::from_str_radix(&row[2], 16).map_err(|_| invalid(format!("isn't a hash: {}", row[2])))?;
+                files.push((row[0].clone(), JournalFile { path: PathBuf::from(unhex(&row[1])?), hash, edits: vec![edit] }));
+            }
+        }
+    }
+    Ok(files.into_iter().map(|(_, file)| file).collect())
+}
+
+/// Describes a run in one line, for listing the runs of a journal.
+pub fn describe(run: &JournalRun) -> String {
+    format!("{} {} {}: {:?} -> {:?} ({} file{})", run.id, run.time, run.root.display(), run.find, run.replace,
+        run.files, if run.files == 1 { "" } else { "s" })
+}
+
+/// Undoes a run, making each of its edits the other way round in the files
+/// that haven't changed since.
+///
+/// # Arguments
+///
+/// * `run` - The run to undo
+/// * `files` - The files it rewrote
+/// * `dry_run` - Only check the files, without writing them
+/// * `sync` - Flush each rewritten file to disk, for `--fsync`
+/// * `retries` - How many times to retry reading or writing a file that is
+///   briefly unavailable
+///
+/// # Returns
+///
+/// * `(Summary, Vec<PathBuf>)` - The files put back and the ones that
+///   couldn't be, and the files left alone since they have changed
+pub fn undo(run: &JournalRun, files: &[JournalFile], dry_run: bool, sync: bool, retries: u32) -> (Summary, Vec<PathBuf>) {
+    let mut planned = Vec::new();
+    let mut errors = Vec::new();
+    for file in files {
+        // Each edit starts later in the rewritten file by how much longer the
+        // edits before it made it
+        let mut grown = 0isize;
+        let edits: Option<Vec<PlannedEdit>> = file.edits.iter().map(|edit| {
+            let start = edit.start?.checked_add_signed(grown)?;
+            grown += edit.after.len() as isize - edit.before.len() as isize;
+            Some(PlannedEdit { range: start..start + edit.after.len(), before: edit.after.clone(), after: edit.before.clone() })
+        }).collect();
+        match edits {
+            Some(edits) => planned.push(PlannedFile { path: file.path.clone(), hash: file.hash, edits }),
+            None => errors.push((file.path.clone(), "wasn't UTF-8, so the journal doesn't say which bytes to put back".to_string())),
+        }
+    }
+    let (mut summary, drifted) = plan::apply(&run.root, planned, dry_run, sync, retries);
+    summary.errors.extend(errors);
+    (summary, drifted)
+}
+
+/// A `sqlite3` command for a database, which stops at the first error.
+fn sqlite3(path: &Path) -> Command {
+    let mut command = Command::new("sqlite3");
+    command.arg("-bail").arg("-batch").arg(path);
+    command
+}
+
+/// Runs a query against a journal.
+///
+/// # Returns
+///
+/// * `io::Result<Vec<Vec<String>>>` - The fields of each row, which are
+///   numbers and hex so that they can't contain the separators, or an error
+///   with what `sqlite3` said if the query failed
+fn select(path: &Path, sql: &str) -> io::Result<Vec<Vec<String>>> {
+    if !path.is_file() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} doesn't exist", path.display())));
+    }
+    let output = sqlite3(path).args(["-readonly", "-list", "-noheader", "-separator", "|"]).arg(sql).stdin(Stdio::null()).output()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => io::Error::new(e.kind(), "sqlite3 isn't installed, and fr journal needs it"),
+            _ => e,
+        })?;
+    if !output.status.success() {
+        return Err(invalid(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+    let stdout = String::from_utf8(output.stdout).map_err(|_| invalid("sqlite3 printed invalid UTF-8".to_string()))?;
+    Ok(stdout.lines().map(|line| line.split('|').map(str::to_string).collect()).collect())
+}
+
+/// Writes text as an SQL literal, in hex so that nothing in it needs escaping.
+fn text(value: &str) -> String {
+    let hex: String = value.bytes().map(|byte| format!("{:02X}", byte)).collect();
+    format!("CAST(X'{}' AS TEXT)", hex)
+}
+
+/// Reads text a query gave as hex.
+fn unhex(field: &str) -> io::Result<String> {
+    let bytes = (0..field.len()).step_by(2)
+        .map(|index| field.get(index..index + 2).and_then(|pair| u8::from_str_radix(pair, 16).ok()))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(|| invalid(format!("isn't hex: {}", field)))?;
+    String::from_utf8(bytes).map_err(|_| invalid("has text that isn't valid UTF-8".to_string()))
+}
+
+/// Reads a number a query gave.
+fn number(field: &str) -> io::Result<u64> {
+    field.parse().map_err(|_| invalid(format!("isn't a number: {}", field)))
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("The journal {}", message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_record_and_read() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = std::path::absolute(temp_dir.path()).unwrap();
+        let journal_path = root.join("fr.db");
+        fs::write(root.join("a.txt"), "one bar two bar").unwrap();
+        fs::write(root.join("b'.txt"), "\u{feff}bar").unwrap();
+
+        let journal = Journal::open(&journal_path, &root, "foo", "bar").unwrap();
+        journal.record(Path::new("a.txt"), &root.join("a.txt"), "one foo two foo", "one bar two bar", Some(&[(4..7, "bar"), (12..15, "bar")])).unwrap();
+        journal.record(Path::new("b'.txt"), &root.join("b'.txt"), "foo", "bar", Some(&[(0..3, "bar")])).unwrap();
+        journal.close().unwrap();
+        // A run that changes nothing leaves no trace
+        Journal::open(&journal_path, &root, "x", "y").unwrap().close().unwrap();
+        let journal = Journal::open(&journal_path, &root, "two", "2").unwrap();
+        fs::write(root.join("a.txt"), "one bar 2 bar").unwrap();
+        journal.record(Path::new("a.txt"), &root.join("a.txt"), "one bar two bar", "one bar 2 bar", None).unwrap();
+        journal.close().unwrap();
+
+        let runs = runs(&journal_path, None).unwrap();
+        assert_eq!(runs.iter().map(|run| (run.id, run.find.as_str(), run.files)).collect::<Vec<_>>(), [(1, "foo", 2), (2, "two", 1)]);
+        assert_eq!(runs[0].root, root);
+        let touching: Vec<u64> = super::runs(&journal_path, Some(&root.join("b'.txt"))).unwrap().iter().map(|run| run.id).collect();
+        assert_eq!(touching, [1]);
+
+        let files = files(&journal_path, 1).unwrap();
+        assert_eq!(files.iter().map(|file| file.path.to_str().unwrap()).collect::<Vec<_>>(), ["a.txt", "b'.txt"]);
+        assert_eq!(files[0].edits[1], JournalEdit { start: Some(12), before: "foo".to_string(), after: "bar".to_string() });
+        // Past the byte order mark
+        assert_eq!(files[1].edits[0].start, Some(3));
+        assert_eq!(files[1].hash, plan::hash("\u{feff}bar".as_bytes()));
+        let edits = &super::files(&journal_path, 2).unwrap()[0].edits;
+        assert_eq!(edits[..], [JournalEdit { start: Some(0), before: "one bar two bar".to_string(), after: "one bar 2 bar".to_string() }]);
+        assert!(parse("runs.db").is_err());
+        assert!(super::runs(&root.join("missing.db"), None).is_err());
+    }
+
+    #[test]
+    fn test_undo() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = std::path::absolute(temp_dir.path()).unwrap();
+        let journal_path = root.join("fr.db");
+        fs::write(root.join("a.txt"), "a longer a longer").unwrap();
+        fs::write(root.join("b.txt"), "longer").unwrap();
+
+        let journal = Journal::open(&journal_path, &root, "x", "longer").unwrap();
+        journal.record(Path::new("a.txt"), &root.join("a.txt"), "a x a x", "a longer a longer", Some(&[(2..3, "longer"), (6..7, "longer")])).unwrap();
+        journal.record(Path::new("b.txt"), &root.join("b.txt"), "x", "longer", Some(&[(0..1, "longer")])).unwrap();
+        journal.close().unwrap();
+        fs::write(root.join("b.txt"), "edited").unwrap();
+
+        let run = &runs(&journal_path, None).unwrap()[0];
+        let (summary, drifted) = undo(run, &files(&journal_path, run.id).unwrap(), false, false, 0);
+        assert!(summary.errors.is_empty());
+        assert_eq!(summary.modified.iter().map(|file| (file.path.to_str().unwrap(), file.replacements)).collect::<Vec<_>>(), [("a.txt", 2)]);
+        assert_eq!(drifted, [PathBuf::from("b.txt")]);
+        assert_eq!(fs::read_to_string(root.join("a.txt")).unwrap(), "a x a x");
+        assert_eq!(fs::read_to_string(root.join("b.txt")).unwrap(), "edited");
+    }
+}
@@ -0,0 +1,305 @@
+//! Just enough JSON for `fr serve` and the Chrome trace: a value type, a
+//! parser, and a writer.
+
+use std::fmt::{self, Write};
+
+/// A JSON value. Objects keep their keys in order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// Builds an object from its keys and values.
+    pub fn object<const N: usize>(entries: [(&str, Value); N]) -> Value {
+        Value::Object(entries.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+    }
+
+    /// Returns the value of a key, if this is an object that has it.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(entries) => entries.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    /// Returns the text of a string.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// Parses a JSON document.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The document, which may be surrounded by whitespace
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Value, String>` - The value, or a description of where the
+    ///   text isn't valid JSON
+    pub fn parse(text: &str) -> Result<Value, String> {
+        let mut parser = Parser { text, position: 0 };
+        let value = parser.value(0)?;
+        parser.skip_whitespace();
+        if parser.position < text.len() {
+            return Err(parser.error("expected the end of the document"));
+        }
+        Ok(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(text: &str) -> Value {
+        Value::String(text.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(text: String) -> Value {
+        Value::String(text)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(number: usize) -> Value {
+        Value::Number(number as f64)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(yes) => write!(f, "{}", yes),
+            Value::Number(number) if !number.is_finite() => write!(f, "null"),
+            Value::Number(number) => write!(f, "{}", number),
+            Value::String(text) => write!(f, "{}", string(text)),
+            Value::Array(items) => {
+                f.write_char('[')?;
+                for (i, item) in items.iter().enumerate() {
+                    write!(f, "{}{}", if i == 0 { "" } else { "," }, item)?;
+                }
+                f.write_char(']')
+            }
+            Value::Object(entries) => {
+                f.write_char('{')?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    write!(f, "{}{}:{}", if i == 0 { "" } else { "," }, string(key), value)?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+/// Escapes text as a JSON string literal, including the quotes.
+pub fn string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// How deeply arrays and objects may nest, so a hostile document can't
+/// overflow the stack
+const MAX_DEPTH: usize = 128;
+
+/// Parses a value at a time from the start of what is left of the text
+struct Parser<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> String {
+        format!("{} at byte {}", message, self.position)
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.position..];
+        self.position += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len();
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.position).copied()
+    }
+
+    /// Consumes a byte if it is the next one.
+    fn eat(&mut self, byte: u8) -> bool {
+        self.skip_whitespace();
+        let found = self.peek() == Some(byte);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        if self.eat(byte) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", byte as char)))
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, String> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("nested too deeply"));
+        }
+        self.skip_whitespace();
+        let rest = &self.text[self.position..];
+        for (word, value) in [("null", Value::Null), ("true", Value::Bool(true)), ("false", Value::Bool(false))] {
+            if rest.starts_with(word) {
+                self.position += word.len();
+                return Ok(value);
+            }
+        }
+        match self.peek() {
+            Some(b'"') => self.string().map(Value::String),
+            Some(b'[') => {
+                self.position += 1;
+                let mut items = Vec::new();
+                if !self.eat(b']') {
+                    loop {
+                        items.push(self.value(depth + 1)?);
+                        if self.eat(b']') {
+                            break;
+                        }
+                        self.expect(b',')?;
+                    }
+                }
+                Ok(Value::Array(items))
+            }
+            Some(b'{') => {
+                self.position += 1;
+                let mut entries = Vec::new();
+                if !self.eat(b'}') {
+                    loop {
+                        self.skip_whitespace();
+                        if self.peek() != Some(b'"') {
+                            return Err(self.error("expected a key"));
+                        }
+                        let key = self.string()?;
+                        self.expect(b':')?;
+                        entries.push((key, self.value(depth + 1)?));
+                        if self.eat(b'}') {
+                            break;
+                        }
+                        self.expect(b',')?;
+                    }
+                }
+                Ok(Value::Object(entries))
+            }
+            Some(b'-' | b'0'..=b'9') => {
+                let len = rest.find(|c: char| !matches!(c, '-' | '+' | '.' | 'e' | 'E' | '0'..='9')).unwrap_or(rest.len());
+                let number = rest[..len].parse().map_err(|_| self.error("invalid number"))?;
+                self.position += len;
+                Ok(Value::Number(number))
+            }
+            _ => Err(self.error("expected a value")),
+        }
+    }
+
+    /// Parses a string, starting at its opening quote.
+    fn string(&mut self) -> Result<String, String> {
+        self.position += 1;
+        let mut text = String::new();
+        loop {
+            let rest = &self.text[self.position..];
+            let Some(end) = rest.find(['"', '\\']) else {
+                return Err(self.error("unterminated string"));
+            };
+            if rest[..end].chars().any(|c| (c as u32) < 0x20) {
+                return Err(self.error("control character in string"));
+            }
+            text.push_str(&rest[..end]);
+            self.position += end + 1;
+            if rest.as_bytes()[end] == b'"' {
+                return Ok(text);
+            }
+            let escaped = match self.peek() {
+                Some(b'"') => '"',
+                Some(b'\\') => '\\',
+                Some(b'/') => '/',
+                Some(b'b') => '\u{8}',
+                Some(b'f') => '\u{c}',
+                Some(b'n') => '\n',
+                Some(b'r') => '\r',
+                Some(b't') => '\t',
+                Some(b'u') => {
+                    self.position += 1;
+                    let high = self.hex()?;
+                    let code = if (0xd800..0xdc00).contains(&high) && self.text[self.position..].starts_with("\\u") {
+                        self.position += 2;
+                        let low = self.hex()?;
+                        if !(0xdc00..0xe000).contains(&low) {
+                            return Err(self.error("invalid escape"));
+                        }
+                        0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+                    } else {
+                        high
+                    };
+                    text.push(char::from_u32(code).ok_or_else(|| self.error("invalid escape"))?);
+                    continue;
+                }
+                _ => return Err(self.error("invalid escape")),
+            };
+            text.push(escaped);
+            self.position += 1;
+        }
+    }
+
+    /// Parses the four hex digits of a `\u` escape.
+    fn hex(&mut self) -> Result<u32, String> {
+        let digits = self.text.get(self.position..self.position + 4).ok_or_else(|| self.error("invalid escape"))?;
+        let code = u32::from_str_radix(digits, 16).map_err(|_| self.error("invalid escape"))?;
+        self.position += 4;
+        Ok(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_string() {
+        assert_eq!(string("a \"b\"\\c\n\u{1}"), "\"a \\\"b\\\"\\\\c\\n\\u0001\"");
+    }
+
+    #[test]
+    fn test_parse() {
+        let value = Value::parse(r#" {"id": 1, "params": {"find": "a\"\u00e9\ud83d\ude00", "list": [true, null, -2.5e1]}} "#).unwrap();
+        assert_eq!(value.get("id"), Some(&Value::Number(1.0)));
+        let params = value.get("params").unwrap();
+        assert_eq!(params.get("find").and_then(Value::as_str), Some("a\"é😀"));
+        assert_eq!(params.get("list"), Some(&Value::Array(vec![Value::Bool(true), Value::Null, Value::Number(-25.0)])));
+        assert_eq!(value.to_string(), r#"{"id":1,"params":{"find":"a\"é😀","list":[true,null,-25]}}"#);
+
+        for invalid in ["", "{", "[1,]", r#"{"a" 1}"#, r#""\x""#, "1 2", "\"a\nb\""] {
+            assert!(Value::parse(invalid).is_err(), "{:?} should not parse", invalid);
+        }
+    }
+}
@@ -0,0 +1,315 @@
+//! Replacing only inside the string values of JSON files, for `--json-values`
+//! and `--json-path`.
+//!
+//! Matches are found in the text of the file as usual and then filtered down
+//! to those wholly inside a string value, so keys, numbers and the structure
+//! are never touched and everything around the replaced text keeps its
+//! formatting. The find and replace text are escaped as JSON first, so a
+//! replacement containing a quote can't end its string early.
+
+use crate::json::{self, Value};
+use std::fmt;
+
+/// A path to values in a JSON document, such as `$.dependencies.*`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonPath(Vec<Step>);
+
+/// One step of a path
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Step {
+    /// The member with this key
+    Key(String),
+    /// The element at this index
+    Index(usize),
+    /// Every member or element
+    Any,
+    /// Any number of steps, including none
+    Descend,
+}
+
+/// One step from a value to a value inside it
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+impl JsonPath {
+    /// Parses the value given to `--json-path`: `$` followed by `.key`,
+    /// `['key']`, `[index]`, `.*` or `[*]` steps, any of which can be preceded
+    /// by `..` to look for it at any depth.
+    pub fn parse(value: &str) -> Result<JsonPath, String> {
+        let invalid = |message: &str| format!("Invalid JSON path '{}': {}", value, message);
+        let mut rest = value.strip_prefix('$').ok_or_else(|| invalid("expected it to start with $"))?;
+        let mut steps = Vec::new();
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix("..") {
+                steps.push(Step::Descend);
+                // Leave the . of a .key step to be read next
+                rest = if after.starts_with('[') { after } else { &rest[1..] };
+            } else if let Some(after) = rest.strip_prefix('.') {
+                let len = after.find(['.', '[']).unwrap_or(after.len());
+                steps.push(match &after[..len] {
+                    "" => return Err(invalid("expected a key after .")),
+                    "*" => Step::Any,
+                    key => Step::Key(key.to_string()),
+                });
+                rest = &after[len..];
+            } else if let Some(after) = rest.strip_prefix('[') {
+                let end = after.find(']').ok_or_else(|| invalid("expected ]"))?;
+                let inside = &after[..end];
+                let quoted = ['\'', '"'].iter()
+                    .find_map(|&quote| inside.strip_prefix(quote).and_then(|key| key.strip_suffix(quote)));
+                steps.push(match (inside, quoted) {
+                    (_, Some(key)) => Step::Key(key.to_string()),
+                    ("*", None) => Step::Any,
+                    _ => Step::Index(inside.parse().map_err(|_| invalid("expected an index, a quoted key or * in []"))?),
+                });
+                rest = &after[end + 1..];
+            } else {
+                return Err(invalid("expected . or ["));
+            }
+        }
+        Ok(JsonPath(steps))
+    }
+
+    /// Whether the path leads to a value at the given segments.
+    pub(crate) fn matches(&self, segments: &[Segment]) -> bool {
+        steps_match(&self.0, segments)
+    }
+}
+
+fn steps_match(steps: &[Step], segments: &[Segment]) -> bool {
+    match steps.split_first() {
+        None => segments.is_empty(),
+        Some((Step::Descend, rest)) => (0..=segments.len()).any(|skipped| steps_match(rest, &segments[skipped..])),
+        Some((step, rest)) => segments.split_first().is_some_and(|(segment, segments)| {
+            let matches = match (step, segment) {
+                (Step::Key(key), Segment::Key(name)) => key == name,
+                (Step::Index(index), Segment::Index(at)) => index == at,
+                _ => matches!(step, Step::Any),
+            };
+            matches && steps_match(rest, segments)
+        }),
+    }
+}
+
+impl fmt::Display for JsonPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("$")?;
+        for step in &self.0 {
+            match step {
+                Step::Key(key) => write!(f, "[{}]", json::string(key))?,
+                Step::Index(index) => write!(f, "[{}]", index)?,
+                Step::Any => f.write_str("[*]")?,
+                Step::Descend => f.write_str("..")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Escapes text the way it appears inside a JSON string.
+pub fn escape(text: &str) -> String {
+    let quoted = json::string(text);
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+/// Keeps the matches that lie wholly inside a string value, and at one of the
+/// paths if any are given.
+///
+/// # Arguments
+///
+/// * `content` - The JSON document
+/// * `offsets` - Where each match of the escaped find text starts
+/// * `find_len` - Length of the escaped find text in bytes
+/// * `paths` - Paths the values must be at, or empty for every string value
+///
+/// # Returns
+///
+/// * `Vec<usize>` - The offsets of the matches to replace, none if the
+///   document isn't valid JSON
+pub fn value_offsets(content: &str, offsets: &[usize], find_len: usize, paths: &[JsonPath]) -> Vec<usize> {
+    let mut scanner = Scanner { text: content, position: 0, segments: Vec::new(), paths, strings: Vec::new() };
+    if scanner.value(0).is_none() {
+        return Vec::new();
+    }
+    scanner.skip_whitespace();
+    if scanner.position < content.len() {
+        return Vec::new();
+    }
+
+    let mut strings = scanner.strings.iter().peekable();
+    offsets.iter().copied().filter(|&offset| {
+        while strings.next_if(|&&(_, end)| end < offset + find_len).is_some() {}
+        strings.peek().is_some_and(|&&(start, end)| {
+            offset >= start && offset + find_len <= end
+                && on_character(content, start, offset) && on_character(content, start, offset + find_len)
+        })
+    }).collect()
+}
+
+/// Whether an offset inside a string falls between its characters rather than
+/// within an escape sequence.
+fn on_character(content: &str, start: usize, offset: usize) -> bool {
+    let bytes = content.as_bytes();
+    let mut position = start;
+    while position < offset {
+        position += match (bytes[position], bytes.get(position + 1)) {
+            (b'\\', Some(b'u')) => 6,
+            (b'\\', _) => 2,
+            _ => 1,
+        };
+    }
+    position == offset
+}
+
+/// How deeply arrays and objects may nest, as for `json::Value::parse`
+const MAX_DEPTH: usize = 128;
+
+/// Walks a document, noting where the contents of the string values at the
+/// paths start and end
+struct Scanner<'a> {
+    text: &'a str,
+    position: usize,
+    /// Where the value being scanned is
+    segments: Vec<Segment>,
+    paths: &'a [JsonPath],
+    strings: Vec<(usize, usize)>,
+}
+
+impl Scanner<'_> {
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.position..];
+        self.position += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len();
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        self.skip_whitespace();
+        let found = self.text.as_bytes().get(self.position) == Some(&byte);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    /// Skips over a string, returning where its contents start and end.
+    fn string(&mut self) -> Option<(usize, usize)> {
+        self.skip_whitespace();
+        let bytes = self.text.as_bytes();
+        if bytes.get(self.position) != Some(&b'"') {
+            return None;
+        }
+        let start = self.position + 1;
+        let mut position = start;
+        loop {
+            match bytes.get(position)? {
+                b'"' => break,
+                b'\\' => position += 2,
+                _ => position += 1,
+            }
+        }
+        self.position = position + 1;
+        Some((start, position))
+    }
+
+    fn value(&mut self, depth: usize) -> Option<()> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+        self.skip_whitespace();
+        match self.text.as_bytes().get(self.position)? {
+            b'"' => {
+                let span = self.string()?;
+                if self.paths.is_empty() || self.paths.iter().any(|path| path.matches(&self.segments)) {
+                    self.strings.push(span);
+                }
+            }
+            b'[' => {
+                self.position += 1;
+                if !self.eat(b']') {
+                    for index in 0.. {
+                        self.segments.push(Segment::Index(index));
+                        self.value(depth + 1)?;
+                        self.segments.pop();
+                        if self.eat(b']') {
+                            break;
+                        }
+                        self.eat(b',').then_some(())?;
+                    }
+                }
+            }
+            b'{' => {
+                self.position += 1;
+                if !self.eat(b'}') {
+                    loop {
+                        let (start, end) = self.string()?;
+                        let key = Value::parse(&self.text[start - 1..end + 1]).ok()?.as_str()?.to_string();
+                        self.eat(b':').then_some(())?;
+                        self.segments.push(Segment::Key(key));
+                        self.value(depth + 1)?;
+                        self.segments.pop();
+                        if self.eat(b'}') {
+                            break;
+                        }
+                        self.eat(b',').then_some(())?;
+                    }
+                }
+            }
+            _ => {
+                let rest = &self.text[self.position..];
+                let len = rest.find(|c: char| !c.is_ascii_alphanumeric() && !matches!(c, '-' | '+' | '.')).unwrap_or(rest.len());
+                if len == 0 {
+                    return None;
+                }
+                self.position += len;
+            }
+        }
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::match_offsets;
+
+    /// Replaces the escaped find text in the string values at the paths.
+    fn replace(content: &str, find_text: &str, replace_text: &str, paths: &[&str]) -> String {
+        let paths: Vec<JsonPath> = paths.iter().map(|path| JsonPath::parse(path).unwrap()).collect();
+        let (find_text, replace_text) = (escape(find_text), escape(replace_text));
+        let offsets = value_offsets(content, &match_offsets(content, &find_text), find_text.len(), &paths);
+        crate::splice(content, &offsets, find_text.len(), &replace_text)
+    }
+
+    #[test]
+    fn test_parse() {
+        let path = JsonPath::parse("$.dependencies.*['a.b'][0][*]").unwrap();
+        assert_eq!(path, JsonPath(vec![Step::Key("dependencies".to_string()), Step::Any, Step::Key("a.b".to_string()), Step::Index(0), Step::Any]));
+        assert_eq!(path.to_string(), r#"$["dependencies"][*]["a.b"][0][*]"#);
+        let path = JsonPath::parse("$..image").unwrap();
+        assert_eq!(path.to_string(), r#"$..["image"]"#);
+        assert!(path.matches(&[Segment::Key("image".to_string())]));
+        assert!(path.matches(&[Segment::Key("spec".to_string()), Segment::Index(0), Segment::Key("image".to_string())]));
+        assert!(!path.matches(&[Segment::Key("image".to_string()), Segment::Key("tag".to_string())]));
+        assert_eq!(JsonPath::parse("$..[0]").unwrap().to_string(), "$..[0]");
+
+        for invalid in ["dependencies", "$.", "$[x]", "$[0", "$x", "$.."] {
+            assert!(JsonPath::parse(invalid).is_err(), "{:?} should not parse", invalid);
+        }
+    }
+
+    #[test]
+    fn test_value_offsets() {
+        let content = "{\n  \"name\": \"left-pad\",\n  \"left-pad\": [\"left-pad\\n\", 1],\n  \"dependencies\": {\"left-pad\": \"^1.0\", \"x\": \"left-pad\"}\n}\n";
+        assert_eq!(replace(content, "left-pad", "pad \"left\"", &[]),
+            "{\n  \"name\": \"pad \\\"left\\\"\",\n  \"left-pad\": [\"pad \\\"left\\\"\\n\", 1],\n  \"dependencies\": {\"left-pad\": \"^1.0\", \"x\": \"pad \\\"left\\\"\"}\n}\n");
+        assert_eq!(replace(content, "left-pad", "right-pad", &["$.dependencies.*", "$.name"]),
+            "{\n  \"name\": \"right-pad\",\n  \"left-pad\": [\"left-pad\\n\", 1],\n  \"dependencies\": {\"left-pad\": \"^1.0\", \"x\": \"right-pad\"}\n}\n");
+
+        // Escapes are matched whole, and invalid documents not at all
+        assert_eq!(replace(r#"["a\nb", "\u006e"]"#, "n", "m", &[]), r#"["a\nb", "\u006e"]"#);
+        assert_eq!(replace(r#"["a\nb"]"#, "\n", " ", &[]), r#"["a b"]"#);
+        assert_eq!(replace(r#"{"a": "b""#, "b", "c", &[]), r#"{"a": "b""#);
+    }
+}
@@ -0,0 +1,252 @@
+//! Replacing only in the values of `.env`, `.properties` and INI files, for
+//! `--config-values` and `--config-key`.
+//!
+//! A match is kept if it is inside the value after a key's `=` or `:`, so
+//! keys, section headers and comments are never renamed. `--config-key` narrows it to the keys that match a glob such
+//! as `*_HOST`, which in an INI file can also name the section, as in
+//! `database.host`.
+
+use globset::{Glob, GlobMatcher};
+use std::path::Path;
+
+/// A glob that keys are matched against, such as `DB_*`
+#[derive(Debug, Clone)]
+pub struct KeyPattern(GlobMatcher);
+
+impl KeyPattern {
+    /// Parses the value given to `--config-key`.
+    pub fn parse(value: &str) -> Result<KeyPattern, String> {
+        Glob::new(value).map(|glob| KeyPattern(glob.compile_matcher())).map_err(|e| format!("Invalid key pattern '{}': {}", value, e))
+    }
+}
+
+impl PartialEq for KeyPattern {
+    fn eq(&self, other: &KeyPattern) -> bool {
+        self.0.glob() == other.0.glob()
+    }
+}
+
+/// The key-value formats fr can find the values of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    /// `KEY=value` lines, as read by shells, Docker and dotenv libraries
+    Env,
+    /// Java properties, with `key=value`, `key: value` or `key value`
+    /// lines that a trailing backslash continues
+    Properties,
+    /// `[section]` headers and `key = value` or `key: value` lines
+    Ini,
+}
+
+impl Format {
+    fn of(file_path: &Path) -> Option<Format> {
+        if file_path == Path::new("-") {
+            return Some(Format::Env);
+        }
+        let name = file_path.file_name()?.to_str()?.to_ascii_lowercase();
+        if is_env_file(file_path) || name.ends_with(".env") {
+            Some(Format::Env)
+        } else if name.ends_with(".properties") {
+            Some(Format::Properties)
+        } else if [".ini", ".cfg", ".conf"].iter().any(|extension| name.ends_with(extension)) {
+            Some(Format::Ini)
+        } else {
+            None
+        }
+    }
+}
+
+/// Returns whether a file is an env file such as `.env` or `.env.production`,
+/// which are searched with `--config-values` although they are hidden.
+pub fn is_env_file(file_path: &Path) -> bool {
+    file_path.file_name().and_then(|name| name.to_str())
+        .is_some_and(|name| name == ".env" || name.starts_with(".env."))
+}
+
+/// Where a value starts and ends, and the quote it is in, if any
+type Value = (usize, usize, Option<char>);
+
+/// Keeps the matches that lie wholly inside the value of a key, and of a key
+/// that matches one of the patterns if any are given, where the replacement
+/// can go as it is.
+///
+/// # Arguments
+///
+/// * `file_path` - Path to the file, whose name decides its format
+/// * `content` - The file
+/// * `offsets` - Where each match of the find text starts
+/// * `find_len` - Length of the find text in bytes
+/// * `replace_text` - Text each match would be replaced with
+/// * `patterns` - Patterns the keys must match, or empty for every key
+///
+/// # Returns
+///
+/// * `Vec<usize>` - The offsets of the matches to replace, none if the file
+///   isn't in a key-value format
+pub fn value_offsets(file_path: &Path, content: &str, offsets: &[usize], find_len: usize, replace_text: &str, patterns: &[KeyPattern]) -> Vec<usize> {
+    let Some(format) = Format::of(file_path) else {
+        return Vec::new();
+    };
+    let values = values(content, format, patterns);
+    let mut values = values.iter().peekable();
+    offsets.iter().copied().filter(|&offset| {
+        while values.next_if(|&&(_, end, _)| end < offset + find_len).is_some() {}
+        values.peek().is_some_and(|&&(start, end, quote)| {
+            offset >= start && offset + find_len <= end && !replace_text.contains(['\n', '\r'])
+                && quote.is_none_or(|quote| !replace_text.contains(quote))
+        })
+    }).collect()
+}
+
+/// Finds the values of the keys that match the patterns.
+fn values(content: &str, format: Format, patterns: &[KeyPattern]) -> Vec<Value> {
+    let comments: &[char] = match format {
+        Format::Env => &['#'],
+        Format::Properties => &['#', '!'],
+        Format::Ini => &[';', '#'],
+    };
+    let mut found = Vec::new();
+    let mut section = String::new();
+    // Whether the line before ended in a backslash, and so does this one's
+    // value belong to a wanted key
+    let mut continued: Option<bool> = None;
+    let mut line_start = 0;
+    for line in content.split_inclusive('\n') {
+        let start = line_start;
+        line_start += line.len();
+        let line = line.trim_end_matches(['\n', '\r']);
+        let text = line.trim_start();
+        let indent = line.len() - text.len();
+
+        if let Some(wanted) = continued {
+            let (end, continues) = properties_value_end(line, indent);
+            if wanted && indent < end {
+                found.push((start + indent, start + end, None));
+            }
+            continued = continues.then_some(wanted);
+            continue;
+        }
+        if text.is_empty() || text.starts_with(comments) {
+            continue;
+        }
+        if format == Format::Ini && text.starts_with('[') {
+            section = text[1..].split(']').next().unwrap_or_default().trim().to_string();
+            continue;
+        }
+        let text = if format == Format::Env { text.strip_prefix("export ").map_or(text, str::trim_start) } else { text };
+        let key_start = line.len() - text.len();
+
+        let separator = match format {
+            Format::Env => text.find('='),
+            Format::Ini => text.find(['=', ':']),
+            Format::Properties => separator(text),
+        };
+        let Some(separator) = separator else {
+            continue;
+        };
+        let key = text[..separator].trim();
+        let wanted = patterns.is_empty() || patterns.iter().any(|pattern| {
+            pattern.0.is_match(key) || (!section.is_empty() && pattern.0.is_match(format!("{}.{}", section, key)))
+        });
+
+        let mut value_start = key_start + separator;
+        let bytes = line.as_bytes();
+        if format == Format::Properties {
+            // The separator can be whitespace with an = or : after it
+            while bytes.get(value_start).is_some_and(u8::is_ascii_whitespace) {
+                value_start += 1;
+            }
+        }
+        if matches!(bytes.get(value_start), Some(b'=' | b':')) {
+            value_start += 1;
+        }
+        while bytes.get(value_start).is_some_and(|&byte| byte == b' ' || byte == b'\t') {
+            value_start += 1;
+        }
+
+        let value = match (format, bytes.get(value_start)) {
+            (Format::Env, Some(&quote @ (b'"' | b'\''))) => line[value_start + 1..].find(quote as char)
+                .map(|len| (value_start + 1, value_start + 1 + len, Some(quote as char))),
+            (Format::Env, _) => {
+                let end = line[value_start..].find(" #").map_or(line.len(), |len| value_start + len);
+                Some((value_start, value_start + line[value_start..end].trim_end().len(), None))
+            }
+            (Format::Properties, _) => {
+                let (end, continues) = properties_value_end(line, value_start);
+                continued = continues.then_some(wanted);
+                Some((value_start, end, None))
+            }
+            (Format::Ini, _) => Some((value_start, line.trim_end().len().max(value_start), None)),
+        };
+        if let Some((value_start, end, quote)) = value.filter(|&(value_start, end, _)| wanted && value_start < end) {
+            found.push((start + value_start, start + end, quote));
+        }
+    }
+    found
+}
+
+/// Finds where a properties key ends: at the first `=`, `:` or whitespace
+/// that isn't escaped.
+fn separator(text: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let mut position = 0;
+    while position < bytes.len() {
+        match bytes[position] {
+            b'\\' => position += 2,
+            b'=' | b':' | b' ' | b'\t' => return Some(position),
+            _ => position += 1,
+        }
+    }
+    None
+}
+
+/// Finds where the value on a line of a properties file ends, before a
+/// trailing backslash, and whether that backslash continues it on the next
+/// line.
+fn properties_value_end(line: &str, start: usize) -> (usize, bool) {
+    let trimmed = line.trim_end();
+    let backslashes = trimmed.len() - trimmed.trim_end_matches('\\').len();
+    let continues = backslashes % 2 == 1;
+    ((trimmed.len() - usize::from(continues)).max(start), continues)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{match_offsets, splice};
+
+    fn replace(file_name: &str, content: &str, find_text: &str, replace_text: &str, patterns: &[&str]) -> String {
+        let patterns: Vec<KeyPattern> = patterns.iter().map(|pattern| KeyPattern::parse(pattern).unwrap()).collect();
+        let offsets = value_offsets(Path::new(file_name), content, &match_offsets(content, find_text), find_text.len(), replace_text, &patterns);
+        splice(content, &offsets, find_text.len(), replace_text)
+    }
+
+    #[test]
+    fn test_env() {
+        let env = "# db.old is retired\nexport DB_HOST=db.old # primary\ndb.old=db.old\nREPLICA_HOST=\"db.old:5432\"\n";
+        assert_eq!(replace(".env.production", env, "db.old", "db.new", &[]),
+            "# db.old is retired\nexport DB_HOST=db.new # primary\ndb.old=db.new\nREPLICA_HOST=\"db.new:5432\"\n");
+        assert_eq!(replace("prod.env", env, "db.old", "db.new", &["*_HOST"]),
+            "# db.old is retired\nexport DB_HOST=db.new # primary\ndb.old=db.old\nREPLICA_HOST=\"db.new:5432\"\n");
+        assert_eq!(replace(".env", env, "db.old", "\"db\"", &["REPLICA_HOST"]), env);
+        assert_eq!(replace("notes.txt", env, "db.old", "db.new", &[]), env);
+    }
+
+    #[test]
+    fn test_properties_and_ini() {
+        let properties = "! db.old\ndb.old.url = jdbc://db.old/app\nhosts db.old, \\\n    db.old\n";
+        assert_eq!(replace("app.properties", properties, "db.old", "db.new", &[]),
+            "! db.old\ndb.old.url = jdbc://db.new/app\nhosts db.new, \\\n    db.new\n");
+
+        let ini = "; db.old\n[database]\nhost = db.old\n[replica]\nhost: db.old\n";
+        assert_eq!(replace("setup.cfg", ini, "db.old", "db.new", &["database.host"]), "; db.old\n[database]\nhost = db.new\n[replica]\nhost: db.old\n");
+        assert_eq!(replace("app.ini", ini, "db.old", "db.new", &["host"]), "; db.old\n[database]\nhost = db.new\n[replica]\nhost: db.new\n");
+    }
+
+    #[test]
+    fn test_is_env_file() {
+        assert!(is_env_file(Path::new("services/api/.env")));
+        assert!(is_env_file(Path::new(".env.local")));
+        assert!(!is_env_file(Path::new(".envrc")));
+    }
+}
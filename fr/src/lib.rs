@@ -0,0 +1,2795 @@
+//! Recursively finds and replaces literal text in the files under a directory,
+//! the way the `fr` command line tool does.
+//!
+//! [`walk_find_replace`] runs a find and replace over a tree with a set of
+//! [`Options`], and returns a [`Summary`] of what it did.
+//!
+//! ```no_run
+//! use std::path::Path;
+//!
+//! let options = fr::Options { max_filesize: Some(1 << 20), ..fr::Options::default() };
+//! let summary = fr::walk_find_replace(Path::new("src"), "old_name", "new_name", &options)?;
+//! for file in &summary.modified {
+//!     println!("{}: {} replacements", file.path.display(), file.replacements);
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! [`Session`] builds up a run instead, with several rules, roots and filters.
+//!
+//! ```no_run
+//! let summary = fr::Session::new("src")
+//!     .rule("old_name", "new_name")
+//!     .filter(|path| path.extension().is_none_or(|extension| extension == "rs"))
+//!     .dry_run(true)
+//!     .run()?;
+//! println!("{} files would change", summary.modified.len());
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+mod archive;
+mod buffers;
+mod cache;
+mod change_log;
+pub mod cli;
+mod color;
+mod completions;
+mod config;
+mod confusables;
+mod context;
+mod deflate;
+mod diff;
+mod doctor;
+mod editorconfig;
+mod edits;
+mod encoding;
+mod eol;
+mod everything;
+mod explain;
+mod gitattributes;
+mod hooks;
+mod includes;
+mod index;
+mod invisible;
+mod journal;
+mod json;
+mod lint;
+mod longpath;
+mod manpage;
+mod jsonpath;
+mod keyvalue;
+mod license;
+mod markdown;
+mod markup;
+mod memory;
+mod modules;
+mod office;
+mod operation;
+mod pager;
+mod paths;
+mod picker;
+mod plan;
+mod plugin;
+mod po;
+mod redact;
+mod remote;
+mod report;
+mod retry;
+mod ripgrep;
+mod rust;
+mod scaffold;
+mod scan;
+mod script;
+mod serve;
+mod session;
+mod shift_jis;
+mod sparse;
+mod stats;
+mod stream;
+mod streams;
+mod throttle;
+mod toml;
+mod trace;
+mod urls;
+mod version;
+mod vfs;
+mod wasm;
+mod workspace;
+mod xattr;
+mod yaml;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
+
+use cache::Cache;
+use change_log::ChangeLog;
+pub use color::{ColorChoice, Theme};
+pub use context::Context;
+use color::{Painter, Role};
+pub use diff::Hunk;
+pub use edits::{Edit, Edits};
+use editorconfig::EditorConfig;
+use gitattributes::GitAttributes;
+pub use encoding::Encoding;
+use encoding::Decoding;
+use index::{Index, Shortlist};
+pub use eol::EolPolicy;
+pub use jsonpath::JsonPath;
+pub use keyvalue::KeyPattern;
+pub use markdown::FrontMatter;
+pub use markup::XmlScope;
+use eol::LineEnding;
+use globset::{Glob, GlobSetBuilder};
+use hooks::{Filter, Hooks};
+use journal::Journal;
+use ignore::{WalkBuilder, WalkState};
+use log::LevelFilter;
+use memory::{MemoryBudget, Reservation};
+use memchr::memmem;
+use pager::Pager;
+use plugin::Plugins;
+pub use redact::Redaction;
+pub use report::Report;
+pub use script::ScriptKind;
+pub use session::Session;
+pub use toml::TomlKey;
+pub use vfs::{find_replace_in, FileSystem, MemoryFileSystem};
+pub use workspace::Members;
+use std::borrow::Cow;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+use std::fs;
+use std::io;
+use std::io::{BufReader, IsTerminal, Read, Write};
+use std::fs::File;
+use std::ops::Range;
+
+/// Options that change how a find and replace run behaves
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Report per-file match counts instead of modifying files
+    pub count: bool,
+    /// List every match in the given format instead of modifying files
+    pub format: Option<OutputFormat>,
+    /// Print each modified path terminated by a NUL byte
+    pub print0_modified: bool,
+    /// Write the list of modified paths to this file at the end of the run
+    pub modified_list: Option<PathBuf>,
+    /// Append a record of every replacement to this file
+    pub log: Option<PathBuf>,
+    /// Record the run, the files it rewrites and their edits in this SQLite
+    /// database, for `fr journal` to look up and undo
+    pub journal: Option<PathBuf>,
+    /// Reports to produce once the run has finished
+    pub reports: Vec<Report>,
+    /// Print why each file that wasn't modified was skipped
+    pub why_skipped: bool,
+    /// Log to stderr at this level, overriding `FR_LOG`
+    pub log_level: Option<LevelFilter>,
+    /// Write a Chrome trace of per-file spans to this file
+    pub trace_chrome: Option<PathBuf>,
+    /// Print timing statistics once the run has finished
+    pub stats: bool,
+    /// Skip files larger than this many bytes
+    pub max_filesize: Option<u64>,
+    /// Skip files with a line longer than this many bytes, such as minified
+    /// code, instead of rewriting them
+    pub max_line_length: Option<usize>,
+    /// List binary and oversized files once the run has finished
+    pub show_skipped: bool,
+    /// When to color output
+    pub color: ColorChoice,
+    /// Which colors to use
+    pub theme: Theme,
+    /// Work out what would change without writing anything
+    pub dry_run: bool,
+    /// Print a unified diff of every change
+    pub diff: bool,
+    /// Show lines around each match in dry-run previews, or as many lines
+    /// around each change in diffs instead of 3
+    pub context: Option<Context>,
+    /// Send diffs and dry-run output through a pager on a terminal
+    pub pager: bool,
+    /// Which encoding files are read and written in
+    pub encoding: Encoding,
+    /// What to do with the line endings of rewritten files
+    pub eol: EolPolicy,
+    /// Treat every file as text instead of skipping files that look binary
+    pub text: bool,
+    /// Extensions of files that are always treated as text, lowercased and
+    /// without the leading dot
+    pub text_extensions: Vec<String>,
+    /// Files and directories to leave out of the walk, matched against their
+    /// names and their paths below the starting directory
+    pub excludes: Vec<Glob>,
+    /// If not empty, only files whose names or paths below the starting
+    /// directory match one of these are read
+    pub includes: Vec<Glob>,
+    /// Only walk these members of the Cargo workspace in the starting
+    /// directory, for `--workspace` and `--package`
+    pub members: Option<Members>,
+    /// Directories fr refuses to run in or change anything under
+    pub forbidden: Vec<PathBuf>,
+    /// Read and rewrite files inside `.git`, `.hg` and `.svn` directories,
+    /// which are otherwise always left alone
+    pub unsafe_vcs_dirs: bool,
+    /// Stop the run at the first file that can't be processed, once the
+    /// writes already under way have finished
+    pub strict: bool,
+    /// How many times to retry reading or writing a file after a transient
+    /// error, such as the file being busy, before it counts as failed
+    pub retries: u32,
+    /// Rewrite sparse files too, filling in their holes
+    pub sparse: bool,
+    /// Also search and rewrite the text in each file's NTFS alternate data
+    /// streams, on Windows
+    pub streams: bool,
+    /// Flush each rewritten file, and the directory it was renamed into, to
+    /// disk before counting it as written
+    pub fsync: bool,
+    /// Write modified files under this directory, at their paths relative to
+    /// the starting directory, and leave the originals as they are
+    pub out_dir: Option<PathBuf>,
+    /// Run even when the find and replace text are the same, and don't warn
+    /// when the replace text contains the find text
+    pub force: bool,
+    /// Choose which of the files that would be modified to modify, from a
+    /// list shown on the terminal
+    pub pick: bool,
+    /// Also replace the find text in the names of files and directories and
+    /// the targets of symlinks, undoing every change if any fails
+    pub everything: bool,
+    /// Filter stdin to stdout instead of walking the tree, for `-` or
+    /// `--stdin`
+    pub stdin: bool,
+    /// Work out how many files and replacements the run would make, and ask
+    /// whether to go on before writing anything
+    pub estimate: bool,
+    /// Print a sed or perl script that makes the changes instead of making
+    /// them
+    pub export_script: Option<ScriptKind>,
+    /// Save the edits the run would make to a plan file instead of making
+    /// them, to make later with `fr apply`
+    pub save_plan: Option<PathBuf>,
+    /// Use the encoding and line endings set in `.editorconfig` files
+    pub editorconfig: bool,
+    /// Skip files marked `binary`, `-diff` or `linguist-generated` in
+    /// `.gitattributes` files, and treat files marked `text` or `diff` as
+    /// text, whatever their bytes look like
+    pub gitattributes: bool,
+    /// How many threads to search and rewrite files with, or None to pick
+    /// automatically
+    pub threads: Option<usize>,
+    /// File to cache which files have no match in, to skip them on later runs
+    pub cache: Option<PathBuf>,
+    /// Limit reads and writes to this many bytes per second
+    pub throttle: Option<u64>,
+    /// Hold at most about this many bytes of file contents in memory at once
+    pub max_memory: Option<u64>,
+    /// Lower the priority of the process
+    pub nice: bool,
+    /// Print nothing to stdout about each modified file, for `fr bench` and
+    /// library callers that use the summary instead
+    pub quiet: bool,
+    /// Shell command to run as each file is started, matched, written, or
+    /// fails, which can veto a file by exiting unsuccessfully
+    pub hook: Option<String>,
+    /// Whether to find and replace in the text members of `.zip`, `.tar` and
+    /// `.tar.gz` archives, repacking them
+    pub archives: bool,
+    /// Whether to find and replace in the text of `.gz` files, decompressing
+    /// and recompressing them, which can make them larger
+    pub gzip: bool,
+    /// Whether to find and replace in the text of `.docx`, `.xlsx` and `.pptx`
+    /// documents, repacking them
+    pub office: bool,
+    /// Replace only inside the string values of JSON files
+    pub json_values: bool,
+    /// Paths the string values must be at for `json_values`, or empty for
+    /// every string value
+    pub json_paths: Vec<JsonPath>,
+    /// Replace only inside the scalar values of YAML files
+    pub yaml_values: bool,
+    /// Paths the scalar values must be at for `yaml_values`, or empty for
+    /// every scalar value
+    pub yaml_paths: Vec<JsonPath>,
+    /// Keys of TOML files whose values are the only text replaced, if any
+    pub toml_keys: Vec<TomlKey>,
+    /// Parts of HTML and XML files that are the only text replaced, if any
+    pub xml_scopes: Vec<XmlScope>,
+    /// Replace only in the link destinations of Markdown files
+    pub md_links: bool,
+    /// Replace only inside `http://` and `https://` URLs
+    pub urls: bool,
+    /// Leave a URL alone if it doesn't answer once rewritten, with `urls`
+    pub validate_urls: bool,
+    /// How many URLs to request at a time, with `validate_urls`
+    pub validate_jobs: usize,
+    /// Replace only in the paths of C, C++ and Objective-C `#include` lines
+    pub include_paths: bool,
+    /// Replace only whole versions, for `fr bump-version`
+    pub versions: bool,
+    /// Replace versions only where projects keep theirs, such as in
+    /// `Cargo.toml` and changelog headings, with `versions`
+    pub version_manifests: bool,
+    /// Replace only the paths of modules in Rust code and the specifiers of
+    /// JavaScript and TypeScript imports, for `fr mv`
+    pub module_paths: bool,
+    /// Leave a relative Markdown link alone if what it would point to once
+    /// rewritten doesn't exist
+    pub check_links: bool,
+    /// The side of Markdown files' front matter that is the only text
+    /// replaced, if any
+    pub front_matter: Option<FrontMatter>,
+    /// Replace only in the translations of gettext catalogs
+    pub po: bool,
+    /// Flag the catalog entries whose translations are replaced in as fuzzy,
+    /// with `po`
+    pub po_fuzzy: bool,
+    /// Replace only in the values of `.env`, `.properties` and INI files
+    pub config_values: bool,
+    /// Patterns the keys must match for `config_values`, or empty for every
+    /// key
+    pub config_keys: Vec<KeyPattern>,
+    /// Warn before writing replace text that looks like a credential
+    pub secret_warning: bool,
+    /// Warn about renames in Rust files that would likely break the build,
+    /// such as to a name already used in the same scope
+    pub check_rust: bool,
+    /// Also match look-alikes of the find text, such as with Cyrillic letters
+    /// or zero width characters in it
+    pub confusables: bool,
+    /// Remove invisible characters such as zero width spaces and bidi
+    /// controls instead of finding the find text, for `fr clean --invisible`
+    pub invisible: bool,
+    /// Kinds of personal data and secrets to replace with placeholders
+    /// instead of finding the find text, for `fr redact`
+    pub redact: Vec<Redaction>,
+    /// Rules whose terms are replaced with their suggestions instead of
+    /// finding the find text, for `fr lint --fix`
+    pub rules: Option<Arc<lint::RuleSet>>,
+    /// The license header to replace with a new one instead of finding the
+    /// find text, for `fr license`
+    pub license: Option<Arc<license::License>>,
+    /// The year to extend copyright notices to instead of finding the find
+    /// text, for `fr bump-year`
+    pub bump_year: Option<u32>,
+    /// The value of each `{{NAME}}` placeholder to fill in instead of finding
+    /// the find text, for `fr scaffold`
+    pub placeholders: Vec<(String, String)>,
+    /// WebAssembly plugins that can veto files, rewrite matches and
+    /// post-process rewritten files, applied in order
+    pub plugins: Vec<PathBuf>,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            count: false,
+            format: None,
+            print0_modified: false,
+            modified_list: None,
+            log: None,
+            journal: None,
+            reports: Vec::new(),
+            why_skipped: false,
+            log_level: None,
+            trace_chrome: None,
+            stats: false,
+            max_filesize: None,
+            max_line_length: None,
+            show_skipped: false,
+            color: ColorChoice::default(),
+            theme: Theme::default(),
+            dry_run: false,
+            diff: false,
+            context: None,
+            pager: true,
+            encoding: Encoding::default(),
+            eol: EolPolicy::default(),
+            text: false,
+            text_extensions: Vec::new(),
+            excludes: Vec::new(),
+            includes: Vec::new(),
+            members: None,
+            forbidden: Vec::new(),
+            unsafe_vcs_dirs: false,
+            strict: false,
+            retries: retry::DEFAULT_RETRIES,
+            sparse: false,
+            streams: false,
+            fsync: false,
+            out_dir: None,
+            force: false,
+            pick: false,
+            everything: false,
+            stdin: false,
+            estimate: false,
+            export_script: None,
+            save_plan: None,
+            editorconfig: true,
+            gitattributes: true,
+            threads: None,
+            cache: None,
+            throttle: None,
+            max_memory: None,
+            nice: false,
+            quiet: false,
+            hook: None,
+            archives: false,
+            gzip: false,
+            office: false,
+            json_values: false,
+            json_paths: Vec::new(),
+            yaml_values: false,
+            yaml_paths: Vec::new(),
+            toml_keys: Vec::new(),
+            xml_scopes: Vec::new(),
+            md_links: false,
+            urls: false,
+            validate_urls: false,
+            validate_jobs: urls::DEFAULT_JOBS,
+            include_paths: false,
+            versions: false,
+            version_manifests: false,
+            module_paths: false,
+            check_links: false,
+            front_matter: None,
+            po: false,
+            po_fuzzy: false,
+            config_values: false,
+            config_keys: Vec::new(),
+            secret_warning: true,
+            check_rust: false,
+            confusables: false,
+            invisible: false,
+            redact: Vec::new(),
+            rules: None,
+            license: None,
+            bump_year: None,
+            placeholders: Vec::new(),
+            plugins: Vec::new(),
+        }
+    }
+}
+
+impl Options {
+    /// Returns a painter for output written to stdout.
+    fn stdout_painter(&self) -> Painter {
+        Painter::new(self.color, self.theme, io::stdout().is_terminal())
+    }
+
+    /// Returns the options to process a file with, once the `charset` and
+    /// `end_of_line` set for it in `.editorconfig` files are applied. An
+    /// `--encoding` or `--eol` flag takes precedence.
+    fn for_file(&self, file_path: &Path, editorconfig: &EditorConfig) -> Cow<'_, Options> {
+        if !self.editorconfig {
+            return Cow::Borrowed(self);
+        }
+        let settings = editorconfig.settings(file_path);
+        let encoding = settings.charset.filter(|_| self.encoding == Encoding::default());
+        let eol = settings.end_of_line.filter(|_| self.eol == EolPolicy::default());
+        if encoding.is_none() && eol.is_none() {
+            return Cow::Borrowed(self);
+        }
+        Cow::Owned(Options {
+            encoding: encoding.unwrap_or(self.encoding),
+            eol: eol.unwrap_or(self.eol),
+            ..self.clone()
+        })
+    }
+
+    /// Whether huge files can be rewritten a chunk at a time, which is only
+    /// possible when nothing needs their whole contents and they are written
+    /// over themselves.
+    fn can_stream(&self) -> bool {
+        !self.diff
+            && self.log.is_none()
+            && self.journal.is_none()
+            && self.reports.is_empty()
+            && self.eol.target().is_none()
+            && matches!(self.encoding, Encoding::Utf8 | Encoding::Utf8Bom)
+            && self.plugins.is_empty()
+            && !self.targets_values()
+            && !self.matches_spans()
+            && self.out_dir.is_none()
+            && self.max_line_length.is_none()
+    }
+
+    /// The options of a dry run that works out which files this run would
+    /// modify, without showing or writing anything, for `--pick`,
+    /// `--estimate` and `--export-script`.
+    fn planning(&self) -> Options {
+        Options {
+            dry_run: true,
+            quiet: true,
+            pick: false,
+            estimate: false,
+            diff: false,
+            print0_modified: false,
+            log: None,
+            journal: None,
+            reports: Vec::new(),
+            hook: None,
+            ..self.clone()
+        }
+    }
+
+    /// Whether a run only replaces the bytes of the find text in UTF-8 text
+    /// files, which a sed or perl script can do as well.
+    fn is_plain(&self) -> bool {
+        self.eol.target().is_none()
+            && matches!(self.encoding, Encoding::Utf8 | Encoding::Utf8Bom)
+            && self.plugins.is_empty()
+            && !self.archives
+            && !self.office
+            && !self.targets_values()
+            && !self.matches_spans()
+    }
+
+    /// Whether matches are found some other way than by the bytes of the find
+    /// text, so they can be of any length: redacting, removing invisible
+    /// characters, matching look-alikes, fixing lint rules, replacing license
+    /// headers or copyright years, or filling in placeholders.
+    fn matches_spans(&self) -> bool {
+        self.confusables || self.needs_no_find_text()
+    }
+
+    /// Whether what is replaced is found without a find text, as when
+    /// redacting or filling in placeholders.
+    fn needs_no_find_text(&self) -> bool {
+        !self.redact.is_empty() || self.invisible || self.rules.is_some() || self.license.is_some() || self.bump_year.is_some()
+            || !self.placeholders.is_empty()
+    }
+
+    /// Whether only the values in JSON, YAML, TOML or key-value files, parts of
+    /// HTML and XML files, Markdown links or front matter or translations are
+    /// replaced, and not every match of the find text.
+    fn targets_values(&self) -> bool {
+        self.json_values || self.yaml_values || !self.toml_keys.is_empty() || !self.xml_scopes.is_empty() || self.md_links
+            || self.urls || self.include_paths || self.versions || self.module_paths || self.front_matter.is_some() || self.po || self.config_values
+    }
+
+    /// Whether a file of a given size is streamed instead of read into memory:
+    /// when it is huge, or too large for `--max-memory`, and can be streamed.
+    fn streams(&self, size: u64) -> bool {
+        self.can_stream() && (size > stream::THRESHOLD || self.max_memory.is_some_and(|max| memory::footprint(size) > max))
+    }
+
+    /// The kind of archive fr looks inside a file as, if any.
+    fn archive_kind(&self, file_path: &Path) -> Option<archive::Kind> {
+        archive::Kind::of(file_path).filter(|&kind| match kind {
+            archive::Kind::Gzip => self.gzip,
+            archive::Kind::Office => self.office,
+            _ => self.archives,
+        })
+    }
+
+    /// Whether a file is treated as text however binary it looks.
+    fn treats_as_text(&self, file_path: &Path) -> bool {
+        self.text || file_path.extension().and_then(|extension| extension.to_str()).is_some_and(|extension| {
+            self.text_extensions.iter().any(|text_extension| text_extension.eq_ignore_ascii_case(extension))
+        })
+    }
+}
+
+/// A file that find and replace has rewritten, or would rewrite in a dry run
+#[derive(Debug)]
+struct FileChange {
+    /// Contents of the file before it was rewritten
+    before: String,
+    /// Contents of the file after it was rewritten
+    after: String,
+    /// The find text as matched in this file, with its line endings adapted to the file's
+    find_text: String,
+    /// The replace text as written to this file, with its line endings adapted to the file's
+    replace_text: String,
+    /// The number of replacements made in the file
+    replacements: usize,
+    /// The bytes of the contents before that were replaced, in order
+    matches: Vec<Range<usize>>,
+    /// What each match was replaced with, when that differs between matches,
+    /// or empty when each was replaced with the replace text
+    replaced_with: Vec<String>,
+    /// Whether the file changed beyond each match being replaced with the
+    /// replace text, by `--eol` or a plugin, so the changed lines have to be
+    /// found by comparing the whole file
+    spanning: bool,
+}
+
+impl FileChange {
+    /// Pairs each match with what it was replaced with.
+    fn replaced(&self) -> Vec<(Range<usize>, &str)> {
+        self.matches.iter().enumerate()
+            .map(|(i, found)| (found.clone(), self.replaced_with.get(i).unwrap_or(&self.replace_text).as_str()))
+            .collect()
+    }
+
+    /// The line and column each replacement starts at, for reports.
+    fn locations(&self) -> Vec<(usize, usize)> {
+        occurrences_at(&self.before, self.matches.iter().map(|found| found.start)).iter()
+            .map(|occurrence| (occurrence.line, occurrence.column))
+            .collect()
+    }
+
+    /// Computes the lines that were changed in the file.
+    fn hunks(&self) -> Vec<Hunk> {
+        if self.spanning {
+            diff::spanning(&self.before, &self.after)
+        } else {
+            let matches: Vec<usize> = self.matches.iter().map(|found| found.start).collect();
+            diff::hunks(&self.before, &matches, self.find_text.len(), &self.replace_text)
+        }
+    }
+}
+
+/// Why a file was left unmodified
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SkipReason {
+    /// The path is not a regular file, such as a directory
+    NotAFile,
+    /// The file looks binary
+    Binary,
+    /// The file is larger than `--max-filesize`
+    TooLarge,
+    /// The file doesn't contain the find text
+    NoMatch,
+    /// A hook or plugin vetoed the file
+    Vetoed,
+    /// The file is inside a `.git`, `.hg` or `.svn` directory, where
+    /// rewriting it could corrupt the repository
+    VcsMetadata,
+    /// Another program had the file locked, even after it was retried
+    InUse,
+    /// The file has holes, which rewriting it would fill in
+    Sparse,
+    /// The file has a line longer than `--max-line-length`
+    LongLines,
+    /// The file is marked `linguist-generated` in `.gitattributes`
+    Generated,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::NotAFile => write!(f, "not a regular file"),
+            SkipReason::Binary => write!(f, "binary file"),
+            SkipReason::TooLarge => write!(f, "larger than --max-filesize"),
+            SkipReason::NoMatch => write!(f, "no match"),
+            SkipReason::Vetoed => write!(f, "vetoed by a hook or plugin"),
+            SkipReason::VcsMetadata => write!(f, "inside version control metadata"),
+            SkipReason::InUse => write!(f, "file in use by another program"),
+            SkipReason::Sparse => write!(f, "sparse file, which rewriting would fill in"),
+            SkipReason::LongLines => write!(f, "has a line longer than --max-line-length"),
+            SkipReason::Generated => write!(f, "generated file"),
+        }
+    }
+}
+
+/// What happened to a single file during find and replace
+#[derive(Debug)]
+enum FileOutcome {
+    /// The file was rewritten
+    Modified(FileChange),
+    /// The file was rewritten a chunk at a time, making this many replacements
+    Streamed(usize),
+    /// The file is an archive whose members were rewritten, making this many
+    /// replacements
+    Repacked(usize),
+    /// The file was left alone
+    Skipped(SkipReason),
+}
+
+/// The new contents of a file, waiting to be written
+#[derive(Debug)]
+struct PendingWrite {
+    path: PathBuf,
+    contents: Vec<u8>,
+    /// Whether to flush the file to disk once it is written, for `--fsync`
+    sync: bool,
+}
+
+impl PendingWrite {
+    /// Writes the new contents over the file.
+    fn write(&self) -> io::Result<()> {
+        trace::span("write", &self.path).run(|| {
+            write_file(&self.path, &self.contents)?;
+            match self.sync {
+                // The file is written in place, so its directory entry is unchanged
+                true => File::open(&self.path)?.sync_all(),
+                false => Ok(()),
+            }
+        })
+    }
+
+    /// Writes the new contents to the file's copy under `--out-dir`, creating
+    /// its directory, with the permissions of the file.
+    ///
+    /// # Arguments
+    ///
+    /// * `out_dir` - The directory the tree is mirrored in
+    /// * `display` - The file's path relative to the starting directory
+    fn write_mirrored(&self, out_dir: &Path, display: &Path) -> io::Result<()> {
+        // Only the names in the path are kept, so the copy can't land outside
+        let path: PathBuf = std::iter::once(out_dir.as_os_str())
+            .chain(display.components().filter_map(|component| match component {
+                std::path::Component::Normal(name) => Some(name),
+                _ => None,
+            }))
+            .collect();
+        trace::span("write", &path).run(|| {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            write_file(&path, &self.contents)?;
+            fs::set_permissions(&path, fs::metadata(&self.path)?.permissions())?;
+            match self.sync {
+                true => File::open(&path)?.sync_all(),
+                false => Ok(()),
+            }
+        })
+    }
+}
+
+/// Reads the whole contents of a file, through io_uring when built with the
+/// `io-uring` feature.
+fn read_file(file_path: &Path) -> io::Result<Vec<u8>> {
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    let contents = uring::read(file_path)?;
+    #[cfg(not(all(feature = "io-uring", target_os = "linux")))]
+    let contents = {
+        let mut contents = buffers::take();
+        File::open(file_path)?.read_to_end(&mut contents)?;
+        contents
+    };
+    throttle::consume(contents.len());
+    Ok(contents)
+}
+
+/// Writes the whole contents of a file, through io_uring when built with the
+/// `io-uring` feature.
+fn write_file(file_path: &Path, contents: &[u8]) -> io::Result<()> {
+    throttle::consume(contents.len());
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    return uring::write(file_path, contents);
+    #[cfg(not(all(feature = "io-uring", target_os = "linux")))]
+    fs::write(file_path, contents)
+}
+
+/// A file modified during a run
+#[derive(Debug)]
+pub struct ModifiedFile {
+    /// Path relative to the starting directory
+    pub path: PathBuf,
+    /// Number of replacements made in the file
+    pub replacements: usize,
+    /// The changed lines, only computed when a report needs them
+    pub hunks: Vec<Hunk>,
+    /// The 1-based (line, column) of each replacement, only computed when a
+    /// report needs them
+    pub locations: Vec<(usize, usize)>,
+}
+
+/// What happened during a find and replace run
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// Files that were modified, sorted by path
+    pub modified: Vec<ModifiedFile>,
+    /// Files that were passed over for a reason worth reporting, sorted by path
+    pub skipped: Vec<(PathBuf, SkipReason)>,
+    /// Files that could not be processed and why, sorted by path
+    pub errors: Vec<(PathBuf, String)>,
+    /// Number of text files that didn't contain the find text
+    pub unmatched: usize,
+}
+
+/// The formats in which individual matches can be listed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// `file:line:col:matched line`, as understood by Vim's quickfix list
+    Vimgrep,
+}
+
+impl OutputFormat {
+    /// Parses the value given to `--format`.
+    fn parse(value: &str) -> Result<OutputFormat, String> {
+        match value {
+            "vimgrep" => Ok(OutputFormat::Vimgrep),
+            _ => Err(format!("Unknown format '{}', expected one of: vimgrep", value)),
+        }
+    }
+}
+
+/// A single occurrence of the find text within a file
+#[derive(Debug, PartialEq, Eq)]
+struct Occurrence<'a> {
+    /// 1-based line number the match starts on
+    line: usize,
+    /// 1-based byte column the match starts at
+    column: usize,
+    /// The full line the match starts on, without its line terminator
+    line_text: &'a str,
+}
+
+/// Files modified with a line longer than this many bytes are warned about
+/// when `--max-line-length` isn't given, as they are most likely minified
+const LONG_LINE_WARNING: usize = 10_000;
+
+/// Returns the length in bytes of the longest line of some text, without its
+/// line terminator.
+fn longest_line(content: &str) -> usize {
+    let mut longest = 0;
+    let mut start = 0;
+    for end in memchr::memchr_iter(b'\n', content.as_bytes()).chain([content.len()]) {
+        let line = content[start..end].strip_suffix('\r').unwrap_or(&content[start..end]);
+        longest = longest.max(line.len());
+        start = end + 1;
+    }
+    longest
+}
+
+/// Checks if a file is binary by reading the first 1024 bytes and checking for null bytes
+/// and high ratio of non-printable characters
+/// 
+/// # Arguments
+/// 
+/// * `file_path` - Path to the file to check
+/// * `encoding` - The encoding the file is read in
+/// 
+/// # Returns
+/// 
+/// * `bool` - True if the file is binary, false otherwise
+fn is_binary(file_path: &Path, encoding: Encoding) -> bool {
+    let Ok(file) = File::open(file_path) else {
+        return false;
+    };
+    
+    let mut reader = BufReader::new(file);
+    let mut buffer = [0; 1024];
+    let bytes_read = reader.read(&mut buffer).unwrap_or(0);
+    looks_binary(&buffer[..bytes_read], encoding)
+}
+
+/// Checks if the start of a file's contents looks binary, as `is_binary` does.
+fn looks_binary(start: &[u8], encoding: Encoding) -> bool {
+    let start = &start[..start.len().min(1024)];
+    if start.is_empty() {
+        return false;
+    }
+
+    let mut null_bytes = 0;
+    let mut non_printable = 0;
+    
+    for &byte in start {
+        if byte == 0 {
+            null_bytes += 1;
+        }
+        // Most of a Shift_JIS file's text is bytes above ASCII
+        let printable = byte.is_ascii() || encoding == Encoding::ShiftJis;
+        if !printable || (byte < 32 && byte != 9 && byte != 10 && byte != 13) {
+            non_printable += 1;
+        }
+    }
+
+    // Consider file binary if:
+    // 1. It contains null bytes, or
+    // 2. More than 30% of bytes are non-printable
+    null_bytes > 0 || (non_printable as f32 / start.len() as f32) > 0.3
+}
+
+/// Decides whether a path should be passed over without reading it.
+/// 
+/// # Arguments
+/// 
+/// * `file_path` - Path to check
+/// * `options` - Options that limit which files are read
+/// 
+/// # Returns
+/// 
+/// * `Option<SkipReason>` - Why the path should be skipped, or None if it should be read
+fn skip_reason(file_path: &Path, options: &Options) -> Option<SkipReason> {
+    // Skip anything in a repository's own files, wherever fr is run from
+    if !options.unsafe_vcs_dirs && file_path.components().any(|component| is_vcs_dir(component.as_os_str())) {
+        return Some(SkipReason::VcsMetadata);
+    }
+
+    // Skip if not a file
+    let Ok(metadata) = fs::metadata(file_path) else {
+        return Some(SkipReason::NotAFile);
+    };
+    if !metadata.is_file() {
+        return Some(SkipReason::NotAFile);
+    }
+
+    // Skip if the file is over the size limit
+    if options.max_filesize.is_some_and(|max| metadata.len() > max) {
+        return Some(SkipReason::TooLarge);
+    }
+
+    // Skip sparse files, which would take up their whole length once written
+    if !options.sparse && sparse::is_sparse(file_path, &metadata) {
+        return Some(SkipReason::Sparse);
+    }
+
+    // Skip if the file is binary, unless it is in an encoding that uses NUL bytes
+    // or should be treated as text
+    if !options.treats_as_text(file_path) && is_binary(file_path, options.encoding) && !options.encoding.allows_nul(file_path) {
+        return Some(SkipReason::Binary);
+    }
+
+    None
+}
+
+/// Reads a file that is eligible for find and replace into memory, if it may
+/// contain the find text.
+/// 
+/// # Arguments
+/// 
+/// * `file_path` - Path to the file to read
+/// * `find_text` - Text to find in the file
+/// * `options` - Options that limit which files are read
+/// 
+/// # Returns
+/// 
+/// * `io::Result<Option<String>>` - The content of the file, or None if the path
+///   should be skipped or can't contain the find text
+fn read_text_file(file_path: &Path, find_text: &str, options: &Options) -> io::Result<Option<String>> {
+    if trace::span("sniff", file_path).run(|| skip_reason(file_path, options)).is_some() {
+        return Ok(None);
+    }
+    if stats::is_active() {
+        stats::record_searched(fs::metadata(file_path)?.len());
+    }
+    if !trace::span("match", file_path).run(|| scan::may_contain(file_path, find_text, options.encoding))? {
+        return Ok(None);
+    }
+
+    // Read the entire file into memory
+    trace::span("read", file_path).run(|| encoding::read(file_path, options.encoding)).map(|(content, _)| Some(content))
+}
+
+/// Counts the occurrences of the find text in a single file without modifying it.
+/// 
+/// # Arguments
+/// 
+/// * `file_path` - Path to the file to count matches in
+/// * `find_text` - Text to find in the file
+/// * `options` - Options that limit which files are read
+/// 
+/// # Returns
+/// 
+/// * `io::Result<usize>` - The number of non-overlapping occurrences found
+fn count_file(file_path: &Path, find_text: &str, options: &Options) -> io::Result<usize> {
+    if find_text.is_empty() {
+        return Ok(0);
+    }
+
+    let Some(content) = read_text_file(file_path, find_text, options)? else {
+        return Ok(0);
+    };
+
+    let find_text = eol::adapt(find_text, LineEnding::detect(&content));
+    let count = memmem::find_iter(content.as_bytes(), find_text.as_bytes()).count();
+    buffers::recycle_string(content);
+    Ok(count)
+}
+
+/// Finds where every non-overlapping occurrence of the find text starts.
+/// 
+/// # Arguments
+/// 
+/// * `content` - Text to search
+/// * `find_text` - Text to find, which must not be empty
+/// 
+/// # Returns
+/// 
+/// * `Vec<usize>` - The byte offset of each occurrence in the order they appear
+fn match_offsets(content: &str, find_text: &str) -> Vec<usize> {
+    memmem::find_iter(content.as_bytes(), find_text.as_bytes()).collect()
+}
+
+/// Builds the rewritten text from the offsets of the matches, copying the text
+/// between them and the replacement in place of each one.
+/// 
+/// # Arguments
+/// 
+/// * `content` - Text the matches were found in
+/// * `offsets` - Where each match starts, as found by `match_offsets`
+/// * `find_len` - Length of the find text in bytes
+/// * `replace_text` - Text to replace each match with
+fn splice(content: &str, offsets: &[usize], find_len: usize, replace_text: &str) -> String {
+    let mut spliced = String::with_capacity(content.len() + offsets.len() * replace_text.len().saturating_sub(find_len));
+    let mut copied = 0;
+    for &offset in offsets {
+        spliced.push_str(&content[copied..offset]);
+        spliced.push_str(replace_text);
+        copied = offset + find_len;
+    }
+    spliced.push_str(&content[copied..]);
+    spliced
+}
+
+/// Finds every non-overlapping occurrence of the find text along with its position.
+/// 
+/// # Arguments
+/// 
+/// * `content` - Text to search
+/// * `find_text` - Text to find
+/// 
+/// # Returns
+/// 
+/// * `Vec<Occurrence>` - The occurrences in the order they appear
+fn find_occurrences<'a>(content: &'a str, find_text: &str) -> Vec<Occurrence<'a>> {
+    if find_text.is_empty() {
+        return Vec::new();
+    }
+    occurrences_at(content, content.match_indices(find_text).map(|(offset, _)| offset))
+}
+
+/// Finds the line and column of each match, such as the ones a run replaced.
+///
+/// # Arguments
+///
+/// * `content` - Text the matches were found in
+/// * `offsets` - Where each match starts, in order
+///
+/// # Returns
+///
+/// * `Vec<Occurrence>` - An occurrence for each offset, in the same order
+fn occurrences_at<'a>(content: &'a str, offsets: impl IntoIterator<Item = usize>) -> Vec<Occurrence<'a>> {
+    let mut occurrences = Vec::new();
+    let mut line = 1;
+    let mut line_start = 0;
+    let mut scanned = 0;
+    for offset in offsets {
+        for (i, byte) in content[scanned..offset].bytes().enumerate() {
+            if byte == b'\n' {
+                line += 1;
+                line_start = scanned + i + 1;
+            }
+        }
+        scanned = offset;
+
+        let line_end = content[line_start..].find('\n').map_or(content.len(), |i| line_start + i);
+        occurrences.push(Occurrence {
+            line,
+            column: offset - line_start + 1,
+            line_text: content[line_start..line_end].trim_end_matches('\r'),
+        });
+    }
+    occurrences
+}
+
+/// Prints every occurrence of the find text in a single file without modifying it.
+/// 
+/// # Arguments
+/// 
+/// * `file_path` - Path to the file to search
+/// * `display` - Path to show in the output
+/// * `find_text` - Text to find in the file
+/// * `format` - Format to print each occurrence in
+/// * `options` - Options that limit which files are read and color the output
+fn list_file(file_path: &Path, display: &Path, find_text: &str, format: OutputFormat, options: &Options) -> io::Result<()> {
+    let Some(content) = read_text_file(file_path, find_text, options)? else {
+        return Ok(());
+    };
+
+    let find_text = eol::adapt(find_text, LineEnding::detect(&content));
+    let painter = options.stdout_painter();
+    let path = painter.paint(Role::Path, &display.display().to_string());
+    let mut output = buffers::take_string();
+    for occurrence in find_occurrences(&content, &find_text) {
+        match format {
+            OutputFormat::Vimgrep => {
+                let line = occurrence.line_text;
+                let start = (occurrence.column - 1).min(line.len());
+                let end = (start + find_text.len()).min(line.len());
+                output.push_str(&format!("{}:{}:{}:{}{}{}\n",
+                    path,
+                    painter.paint(Role::LineNumber, &occurrence.line.to_string()),
+                    painter.paint(Role::LineNumber, &occurrence.column.to_string()),
+                    &line[..start],
+                    painter.paint(Role::Match, &line[start..end]),
+                    &line[end..]));
+            }
+        }
+    }
+
+    // Print each file in one go so output from parallel walkers doesn't interleave
+    if !output.is_empty() {
+        io::stdout().lock().write_all(output.as_bytes())?;
+    }
+    buffers::recycle_string(output);
+    buffers::recycle_string(content);
+    Ok(())
+}
+
+/// Works out the find and replace for a single file, without writing it yet.
+/// 
+/// # Arguments
+/// 
+/// * `file_path` - Path to the file to perform find and replace on
+/// * `find_text` - Text to find in the file
+/// * `replace_text` - Text to replace the found text with
+/// * `options` - Options that control how the file is read and rewritten
+/// 
+/// # Returns
+/// 
+/// * `io::Result<(FileOutcome, Option<PendingWrite>)>` - The change made if the
+///   file is modified, or why it was skipped, along with the contents to write
+///   unless they have already been written or this is a dry run
+fn plan_find_replace(file_path: &Path, find_text: &str, replace_text: &str, options: &Options, plugins: &Plugins) -> io::Result<(FileOutcome, Option<PendingWrite>)> {
+    // An empty find text would match between every character
+    if find_text.is_empty() && !options.needs_no_find_text() {
+        return Ok((FileOutcome::Skipped(SkipReason::NoMatch), None));
+    }
+
+    if let Some(kind) = options.archive_kind(file_path) {
+        return plan_archive(file_path, kind, find_text, replace_text, options, plugins);
+    }
+
+    if let Some(reason) = trace::span("sniff", file_path).run(|| skip_reason(file_path, options)) {
+        return Ok((FileOutcome::Skipped(reason), None));
+    }
+    let size = fs::metadata(file_path)?.len();
+    stats::record_searched(size);
+
+    // Rule out files without a match before reading them into memory
+    if !options.matches_spans() && !trace::span("match", file_path).run(|| scan::may_contain(file_path, find_text, options.encoding))? {
+        return Ok((FileOutcome::Skipped(SkipReason::NoMatch), None));
+    }
+
+    // Stream huge files instead of reading them into memory, which writes them
+    // as they are read
+    if options.streams(size) {
+        let replacements = trace::span("replace", file_path)
+            .run(|| stream::replace_file(file_path, find_text, replace_text, options.dry_run, options.fsync))?;
+        return Ok((match replacements {
+            0 => FileOutcome::Skipped(SkipReason::NoMatch),
+            replacements => FileOutcome::Streamed(replacements),
+        }, None));
+    }
+
+    // Read the entire file into memory
+    let (content, decoding) = trace::span("read", file_path).run(|| encoding::read(file_path, options.encoding))?;
+    let Some((change, new_bytes)) = rewrite(file_path, content, decoding, find_text, replace_text, options, plugins)? else {
+        return Ok((FileOutcome::Skipped(SkipReason::NoMatch), None));
+    };
+    if options.max_line_length.is_some_and(|max| longest_line(&change.before) > max) {
+        return Ok((FileOutcome::Skipped(SkipReason::LongLines), None));
+    }
+    let pending = (!options.dry_run).then(|| PendingWrite { path: file_path.to_path_buf(), contents: new_bytes, sync: options.fsync });
+    Ok((FileOutcome::Modified(change), pending))
+}
+
+/// Works out how find and replace would repack an archive, as
+/// `plan_find_replace` does for other files.
+fn plan_archive(file_path: &Path, kind: archive::Kind, find_text: &str, replace_text: &str, options: &Options, plugins: &Plugins) -> io::Result<(FileOutcome, Option<PendingWrite>)> {
+    let metadata = fs::metadata(file_path)?;
+    if !metadata.is_file() {
+        return Ok((FileOutcome::Skipped(SkipReason::NotAFile), None));
+    }
+    if options.max_filesize.is_some_and(|max| metadata.len() > max) {
+        return Ok((FileOutcome::Skipped(SkipReason::TooLarge), None));
+    }
+    stats::record_searched(metadata.len());
+
+    let contents = trace::span("read", file_path).run(|| read_file(file_path))?;
+    let repacked = trace::span("replace", file_path)
+        .run(|| archive::rewrite_archive(file_path, kind, &contents, find_text, replace_text, options, plugins))?;
+    let Some((replacements, new_contents)) = repacked else {
+        return Ok((FileOutcome::Skipped(SkipReason::NoMatch), None));
+    };
+    let pending = (!options.dry_run).then(|| PendingWrite { path: file_path.to_path_buf(), contents: new_contents, sync: options.fsync });
+    Ok((FileOutcome::Repacked(replacements), pending))
+}
+
+/// Replaces the find text in the decoded contents of a file, the part of find
+/// and replace that doesn't touch the filesystem.
+///
+/// # Arguments
+///
+/// * `file_path` - Path to the file, for tracing
+/// * `content` - The decoded contents of the file
+/// * `decoding` - How the file was decoded, to encode the new contents the same way
+/// * `find_text` - Text to find, which must not be empty
+/// * `replace_text` - Text to replace the found text with
+/// * `options` - Options that control how the file is rewritten
+/// * `plugins` - Plugins that choose replacements and post-process the new contents
+///
+/// # Returns
+///
+/// * `io::Result<Option<(FileChange, Vec<u8>)>>` - The change and the encoded
+///   new contents, None if the find text doesn't occur, or an error if the new
+///   contents can't be encoded
+fn rewrite(file_path: &Path, content: String, decoding: Decoding, find_text: &str, replace_text: &str, options: &Options, plugins: &Plugins) -> io::Result<Option<(FileChange, Vec<u8>)>> {
+    if options.matches_spans() {
+        return rewrite_spans(file_path, content, decoding, find_text, replace_text, options);
+    }
+
+    // In a CRLF file, newlines in the find and replace text stand for CRLFs,
+    // and in JSON string values they are escaped like everything else
+    let (find_text, replace_text) = if options.json_values {
+        (jsonpath::escape(find_text), jsonpath::escape(replace_text))
+    } else {
+        let ending = LineEnding::detect(&content);
+        (eol::adapt(find_text, ending).into_owned(), eol::adapt(replace_text, ending).into_owned())
+    };
+
+    // Find every match once, and skip writing if there are none
+    let offsets = trace::span("match", file_path).run(|| {
+        let offsets = match_offsets(&content, &find_text);
+        if options.targets_values() && !offsets.is_empty() {
+            value_offsets(file_path, &content, &offsets, find_text.len(), &replace_text, options)
+        } else {
+            offsets
+        }
+    });
+    if offsets.is_empty() {
+        buffers::recycle_string(content);
+        return Ok(None);
+    }
+
+    // Perform the replacement
+    let fuzzy = options.po && options.po_fuzzy && po::is_po(file_path);
+    let mut new_content = trace::span("replace", file_path).run(|| if plugins.rewrites() {
+        plugins.splice(file_path, &content, &offsets, find_text.len(), &replace_text)
+    } else if fuzzy {
+        Ok(po::splice_fuzzy(&content, &offsets, find_text.len(), &replace_text))
+    } else {
+        Ok(splice(&content, &offsets, find_text.len(), &replace_text))
+    })?;
+    let converted = options.eol.target().and_then(|target| match eol::convert(&new_content, target) {
+        Cow::Owned(converted) => Some(converted),
+        Cow::Borrowed(_) => None,
+    });
+    let spanning = converted.is_some() || fuzzy || plugins.rewrites() || plugins.post_processes();
+    if let Some(converted) = converted {
+        new_content = converted;
+    }
+    if plugins.post_processes() {
+        new_content = trace::span("post-process", file_path).run(|| plugins.post_process(file_path, new_content))?;
+    }
+    
+    // Write back to file in the encoding it was read in
+    let new_bytes = decoding.encode(&new_content)?;
+    let matches = offsets.iter().map(|&offset| offset..offset + find_text.len()).collect();
+    
+    Ok(Some((FileChange {
+        before: content,
+        after: new_content,
+        find_text,
+        replace_text,
+        replacements: offsets.len(),
+        matches,
+        replaced_with: Vec::new(),
+        spanning,
+    }, new_bytes)))
+}
+
+/// Replaces the personal data and secrets `fr redact` looks for with
+/// placeholders, removes invisible characters for `fr clean --invisible`,
+/// replaces license headers for `fr license` and copyright years for `fr
+/// bump-year`, fills in placeholders for `fr scaffold`, or replaces the look-alikes of the find text with
+/// `--confusables`, as `rewrite` does with the find text.
+fn rewrite_spans(file_path: &Path, content: String, decoding: Decoding, find_text: &str, replace_text: &str, options: &Options) -> io::Result<Option<(FileChange, Vec<u8>)>> {
+    let spans = trace::span("match", file_path).run(|| if options.invisible {
+        invisible::spans(&content).into_iter().map(|(start, end, _)| (start, end, String::new())).collect()
+    } else if let Some(rules) = &options.rules {
+        rules.spans(file_path, &content)
+    } else if let Some(license) = &options.license {
+        license.spans(file_path, &content)
+    } else if let Some(year) = options.bump_year {
+        license::year_spans(&content, year)
+    } else if !options.placeholders.is_empty() {
+        scaffold::spans(&content, &options.placeholders)
+    } else if options.redact.is_empty() {
+        let ending = LineEnding::detect(&content);
+        let replace_text = eol::adapt(replace_text, ending);
+        confusables::spans(&content, &eol::adapt(find_text, ending)).into_iter()
+            .map(|(start, end)| (start, end, replace_text.to_string()))
+            .collect()
+    } else {
+        redact::spans(&content, &options.redact)
+    });
+    if spans.is_empty() {
+        buffers::recycle_string(content);
+        return Ok(None);
+    }
+    let new_content = trace::span("replace", file_path).run(|| redact::splice(&content, &spans));
+    let new_bytes = decoding.encode(&new_content)?;
+    Ok(Some((FileChange {
+        before: content,
+        after: new_content,
+        find_text: find_text.to_string(),
+        replace_text: replace_text.to_string(),
+        replacements: spans.len(),
+        matches: spans.iter().map(|&(start, end, _)| start..end).collect(),
+        replaced_with: spans.into_iter().map(|(_, _, text)| text).collect(),
+        spanning: true,
+    }, new_bytes)))
+}
+
+/// Keeps the matches inside the values or parts of a file that options such as
+/// `json_values` limit replacements to, and none in files of other formats.
+///
+/// The matches are found in the text of the file as usual, and each format
+/// only works out which of them fall inside the parts it scopes to, so nothing
+/// else in the file, formatting and comments included, changes.
+fn value_offsets(file_path: &Path, content: &str, offsets: &[usize], find_len: usize, replace_text: &str, options: &Options) -> Vec<usize> {
+    if options.json_values {
+        jsonpath::value_offsets(content, offsets, find_len, &options.json_paths)
+    } else if options.yaml_values {
+        if yaml::is_yaml(file_path) { yaml::value_offsets(content, offsets, find_len, replace_text, &options.yaml_paths) } else { Vec::new() }
+    } else if !options.toml_keys.is_empty() {
+        if toml::is_toml(file_path) { toml::value_offsets(content, offsets, find_len, replace_text, &options.toml_keys) } else { Vec::new() }
+    } else if options.md_links {
+        if markdown::is_markdown(file_path) {
+            markdown::link_offsets(file_path, content, offsets, find_len, replace_text, options.check_links)
+        } else {
+            Vec::new()
+        }
+    } else if let Some(side) = options.front_matter {
+        if markdown::is_markdown(file_path) { markdown::front_matter_offsets(content, offsets, find_len, side) } else { Vec::new() }
+    } else if options.po {
+        if po::is_po(file_path) { po::msgstr_offsets(content, offsets, find_len, replace_text) } else { Vec::new() }
+    } else if options.urls {
+        urls::url_offsets(file_path, content, offsets, find_len, replace_text, options.validate_urls)
+    } else if options.include_paths {
+        if includes::is_c(file_path) { includes::include_offsets(content, offsets, find_len, replace_text) } else { Vec::new() }
+    } else if options.versions {
+        version::version_offsets(file_path, content, offsets, find_len, replace_text, options.version_manifests)
+    } else if options.module_paths {
+        modules::path_offsets(file_path, content, offsets, find_len)
+    } else if options.config_values {
+        keyvalue::value_offsets(file_path, content, offsets, find_len, replace_text, &options.config_keys)
+    } else if markup::is_markup(file_path) {
+        markup::scope_offsets(content, offsets, find_len, replace_text, &options.xml_scopes)
+    } else {
+        Vec::new()
+    }
+}
+
+/// Performs find and replace operation on a single file, working it out and
+/// writing it in one go where a run does it in two stages.
+/// 
+/// # Arguments
+/// 
+/// * `file_path` - Path to the file to perform find and replace on
+/// * `find_text` - Text to find in the file
+/// * `replace_text` - Text to replace the found text with
+/// * `options` - Options that control how the file is read and rewritten
+/// 
+/// # Returns
+/// 
+/// * `io::Result<FileOutcome>` - The change made if the file was modified, or why
+///   it was skipped
+#[cfg(test)]
+fn find_replace_file(file_path: &Path, find_text: &str, replace_text: &str, options: &Options) -> io::Result<FileOutcome> {
+    let (outcome, pending) = plan_find_replace(file_path, find_text, replace_text, options, &Plugins::default())?;
+    if let Some(pending) = pending {
+        pending.write()?;
+    }
+    Ok(outcome)
+}
+
+/// Writes a path terminated by a NUL byte to stdout.
+fn print0(path: &Path) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(path.as_os_str().as_encoded_bytes())?;
+    stdout.write_all(b"\0")
+}
+
+/// Returns the path of the temporary file that a file is written to before it
+/// is renamed into place.
+fn temp_path(path: &Path) -> io::Result<PathBuf> {
+    let file_name = path.file_name().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a file path", path.display()))
+    })?;
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(format!(".fr-{}.tmp", process::id()));
+    Ok(path.with_file_name(temp_name))
+}
+
+/// Writes a file so that readers see either its old contents or all of the new ones,
+/// by writing to a temporary file next to it and renaming it into place.
+fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let temp_path = temp_path(path)?;
+
+    if let Err(e) = fs::write(&temp_path, contents).and_then(|()| fs::rename(&temp_path, path)) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Flushes the directory a file was renamed into to disk, so the rename isn't
+/// lost in a crash. Windows has no way to do this, nor needs one.
+fn sync_parent(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        let parent = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+        File::open(parent)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// Returns the path relative to the starting directory for display, falling back
+/// to the full path if it lies outside of it.
+fn display_path<'a>(starting_directory: &Path, path: &'a Path) -> &'a Path {
+    path.strip_prefix(starting_directory).unwrap_or(path)
+}
+
+/// How many files can wait between one stage of a run and the next
+const STAGE_CAPACITY: usize = 256;
+
+/// State shared by every thread during a find and replace run
+struct Run<'a> {
+    starting_directory: &'a Path,
+    find_text: &'a str,
+    replace_text: &'a str,
+    options: &'a Options,
+    change_log: Option<ChangeLog>,
+    journal: Option<Journal>,
+    preview: Pager,
+    painter: Painter,
+    editorconfig: EditorConfig,
+    gitattributes: GitAttributes,
+    cache: Option<Cache>,
+    /// The indexed files that may contain the find text, if the tree is indexed
+    shortlist: Option<Shortlist<'a>>,
+    /// Shared by the files held in memory, with `--max-memory`
+    memory: Option<Arc<MemoryBudget>>,
+    hooks: &'a Hooks,
+    plugins: Plugins,
+    needs_hunks: bool,
+    needs_locations: bool,
+    summary: Mutex<Summary>,
+    /// Set at the first error with `--strict`, after which no more files are
+    /// walked, read or written
+    stopped: AtomicBool,
+}
+
+/// A file that has been read and matched, on its way to being written
+struct Processed<'a> {
+    display: PathBuf,
+    /// The options the file is processed with
+    options: Cow<'a, Options>,
+    outcome: FileOutcome,
+    pending: Option<PendingWrite>,
+    /// The memory the file's contents take up, held until it has been written
+    reservation: Option<Reservation>,
+}
+
+impl<'a> Run<'a> {
+    /// Reads and matches one file from the walk. Files that are only counted or
+    /// listed are done with here; the others are passed on to be written.
+    fn process(&self, path: &Path) -> Option<Processed<'a>> {
+        let display = display_path(self.starting_directory, path);
+        let _span = trace::span("file", path);
+        let options = self.options.for_file(path, &self.editorconfig);
+        // What the project declares about a file goes before what its bytes
+        // look like
+        let attributes = match options.gitattributes {
+            true => self.gitattributes.settings(path),
+            false => gitattributes::Settings::default(),
+        };
+        if let Some(reason) = attributes.skip_reason().filter(|_| path.is_file()) {
+            log::debug!("{}: skipped ({}, in .gitattributes)", display.display(), reason);
+            return Some(Processed { display: display.to_path_buf(), options, outcome: FileOutcome::Skipped(reason), pending: None, reservation: None });
+        }
+        let options = match attributes.binary {
+            Some(false) if !options.text => Cow::Owned(Options { text: true, ..options.into_owned() }),
+            _ => options,
+        };
+        if (self.hooks.watches_start() || self.plugins.vetoes_files()) && path.is_file() {
+            let vetoed = match self.plugins.vetoes(path) {
+                Ok(vetoed) => vetoed || !self.hooks.file_start(display),
+                Err(e) => {
+                    self.record_error(path, display, e);
+                    return None;
+                }
+            };
+            if vetoed {
+                log::debug!("{}: skipped ({})", display.display(), SkipReason::Vetoed);
+                return Some(Processed {
+                    display: display.to_path_buf(),
+                    options,
+                    outcome: FileOutcome::Skipped(SkipReason::Vetoed),
+                    pending: None,
+                    reservation: None,
+                });
+            }
+        }
+        let cached = self.cache.as_ref().map(|cache| (cache, cache::pattern(self.find_text, options.encoding)));
+        // The index and cache go by the bytes of a file, which for a compressed
+        // archive aren't its members' text, and when only values are replaced a
+        // file without a match may have the find text outside them. Redacting
+        // and matching look-alikes don't go by the find text's bytes
+        let by_bytes = options.archive_kind(path).is_none() && !options.targets_values() && !options.matches_spans();
+        let unchanged_without_match = if !by_bytes {
+            None
+        } else if cached.is_some_and(|(cache, pattern)| cache.has_no_match(path, pattern)) {
+            Some("cached")
+        } else if self.shortlist.as_ref().is_some_and(|shortlist| shortlist.rules_out(path, options.encoding)) {
+            Some("indexed")
+        } else {
+            None
+        };
+        if let Some(since) = unchanged_without_match {
+            log::debug!("{}: skipped (no match, unchanged since it was {})", display.display(), since);
+            let searched_only = options.count || options.format.is_some();
+            return (!searched_only).then(|| Processed {
+                display: display.to_path_buf(),
+                options,
+                outcome: FileOutcome::Skipped(SkipReason::NoMatch),
+                pending: None,
+                reservation: None,
+            });
+        }
+
+        let reservation = self.memory.as_ref().map(|budget| {
+            let size = fs::metadata(path).map_or(0, |metadata| metadata.len());
+            budget.reserve(if options.streams(size) { stream::BUFFERED } else { memory::footprint(size) })
+        });
+
+        let retries = options.retries;
+        // Only reading and writing go through the long form of a path, since
+        // the cache and index know the file by the path it was walked as
+        let io_path = longpath::for_io(path);
+        let io_path = io_path.as_ref();
+        let result = if options.count {
+            retry::retrying(path, retries, || count_file(io_path, self.find_text, &options)).map(|count| {
+                if count > 0 {
+                    println!("{}:{}", display.display(), count);
+                }
+                None
+            })
+        } else if let Some(format) = options.format {
+            retry::retrying(path, retries, || list_file(io_path, display, self.find_text, format, &options)).map(|()| None)
+        } else {
+            retry::retrying(path, retries, || plan_find_replace(io_path, self.find_text, self.replace_text, &options, &self.plugins)).map(|(outcome, pending)| {
+                if let (Some((cache, pattern)), FileOutcome::Skipped(SkipReason::NoMatch)) = (cached, &outcome) {
+                    cache.record_no_match(path, pattern);
+                }
+                let vetoed = match &outcome {
+                    FileOutcome::Modified(change) => !self.hooks.matched(display, change.replacements),
+                    FileOutcome::Streamed(replacements) => {
+                        // Streamed files are written as they are matched, too late to veto
+                        self.hooks.matched(display, *replacements);
+                        false
+                    }
+                    FileOutcome::Repacked(replacements) => !self.hooks.matched(display, *replacements),
+                    FileOutcome::Skipped(_) => false,
+                };
+                let (outcome, pending) = if vetoed {
+                    log::debug!("{}: skipped ({})", display.display(), SkipReason::Vetoed);
+                    (FileOutcome::Skipped(SkipReason::Vetoed), None)
+                } else {
+                    (outcome, pending)
+                };
+                // Only modified files are still in memory
+                let reservation = reservation.filter(|_| matches!(outcome, FileOutcome::Modified(_) | FileOutcome::Repacked(_)));
+                Some(Processed { display: display.to_path_buf(), options, outcome, pending, reservation })
+            })
+        };
+        result.unwrap_or_else(|e| {
+            // A file another program has locked is left for a later run
+            let recorded = match retry::is_in_use(&e) {
+                true => self.record_outcome(display, FileOutcome::Skipped(SkipReason::InUse), self.options),
+                false => Err(e),
+            };
+            if let Err(e) = recorded {
+                self.record_error(path, display, e);
+            }
+            None
+        })
+    }
+
+    /// Writes a processed file and records what happened to it.
+    fn finish(&self, processed: Processed) {
+        let Processed { display, options, outcome, pending, reservation: _reservation } = processed;
+        let path = pending.as_ref().map(|pending| pending.path.clone());
+        let result = match (pending, &options.out_dir) {
+            (Some(pending), Some(out_dir)) => retry::retrying(&pending.path, options.retries, || pending.write_mirrored(out_dir, &display)),
+            (Some(pending), None) => retry::retrying(&pending.path, options.retries, || pending.write()),
+            (None, _) => Ok(()),
+        };
+        let result = match result {
+            Err(e) if retry::is_in_use(&e) => self.record_outcome(&display, FileOutcome::Skipped(SkipReason::InUse), &options),
+            result => result.and_then(|()| self.record_outcome(&display, outcome, &options)),
+        };
+        if let Err(e) = result {
+            self.record_error(path.as_deref().unwrap_or(&display), &display, e);
+        }
+    }
+
+    /// Records an error processing a file, to be reported with the others
+    /// once the run has finished.
+    fn record_error(&self, path: &Path, display: &Path, e: io::Error) {
+        log::debug!("{}: failed ({:?})", path.display(), e.kind());
+        self.hooks.error(display, &e);
+        self.summary.lock().unwrap().errors.push((display.to_path_buf(), e.to_string()));
+        if self.options.strict {
+            self.stopped.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Whether the run has been stopped by an error with `--strict`.
+    fn stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+
+    /// Reports and records what find and replace did with a file.
+    fn record_outcome(&self, display: &Path, outcome: FileOutcome, options: &Options) -> io::Result<()> {
+        let (replacements, change) = match outcome {
+            FileOutcome::Modified(change) => (change.replacements, Some(change)),
+            FileOutcome::Streamed(replacements) | FileOutcome::Repacked(replacements) => (replacements, None),
+            FileOutcome::Skipped(SkipReason::NotAFile) => return Ok(()),
+            FileOutcome::Skipped(reason) => {
+                log::debug!("{}: skipped ({})", display.display(), reason);
+                if options.why_skipped {
+                    eprintln!("{}: skipped ({})", display.display(), reason);
+                }
+                let mut summary = self.summary.lock().unwrap();
+                match reason {
+                    SkipReason::NoMatch => summary.unmatched += 1,
+                    reason => summary.skipped.push((display.to_path_buf(), reason)),
+                }
+                return Ok(());
+            }
+        };
+
+        log::debug!("{}: {}", display.display(), if options.dry_run { "would be modified" } else { "modified" });
+        if let Some(change) = change.as_ref().filter(|_| options.max_line_length.is_none()) {
+            let longest = longest_line(&change.before);
+            if longest > LONG_LINE_WARNING {
+                eprintln!("Warning: {} has a line of {} bytes, so it is likely minified and its changes hard to review. \
+                    Pass --max-line-length to skip files like it", display.display(), longest);
+            }
+        }
+        if let Some(change) = change.as_ref().filter(|_| options.check_rust && rust::is_rust(display)) {
+            for problem in rust::check(&change.before, &change.find_text, &change.replace_text) {
+                eprintln!("Warning: {}:{}:{}: {}", display.display(), problem.line, problem.column, problem.message);
+            }
+        }
+        // Streamed files are only rewritten when nothing below needs their
+        // contents, and the members of archives aren't logged or diffed
+        if let (Some(change_log), Some(change)) = (&self.change_log, &change) {
+            change_log.record(display, &change.before, &change.replaced())?;
+        }
+        if let (Some(journal), Some(change)) = (&self.journal, &change) {
+            let replaced = change.replaced();
+            let path = self.starting_directory.join(display);
+            journal.record(display, &path, &change.before, &change.after, Some(&replaced[..]).filter(|_| !change.spanning))?;
+        }
+        if options.print0_modified {
+            print0(display)?;
+        }
+
+        let hunks = match &change {
+            Some(change) if self.needs_hunks || options.diff => change.hunks(),
+            _ => Vec::new(),
+        };
+        if options.quiet {
+            // Nothing to print
+        } else if let (true, Some(change)) = (options.diff, &change) {
+            let lines = options.context.map_or(3, |context| context.diff_lines());
+            self.preview.write(&diff::unified(display, &change.before, &change.after, &hunks, lines, &self.painter))?;
+        } else if let (true, Some(change)) = (options.invisible, &change) {
+            // Where each character was, whether or not it was removed
+            self.preview.write(&invisible::report(display, &change.before, &self.painter))?;
+        } else if options.dry_run {
+            let mut preview = format!("Would modify {} ({} replacement{})\n",
+                self.painter.paint(Role::Path, &display.display().to_string()),
+                replacements,
+                if replacements == 1 { "" } else { "s" });
+            if let (Some(context), Some(change)) = (options.context, &change) {
+                preview.push_str(&context::render(&change.before, &change.replaced(), context, &self.painter));
+            }
+            self.preview.write(&preview)?;
+        }
+
+        let locations = match &change {
+            Some(change) if self.needs_locations => change.locations(),
+            _ => Vec::new(),
+        };
+        let modified = ModifiedFile {
+            path: display.to_path_buf(),
+            replacements,
+            hunks: if self.needs_hunks { hunks } else { Vec::new() },
+            locations,
+        };
+        self.hooks.file_written(&modified);
+        self.summary.lock().unwrap().modified.push(modified);
+        Ok(())
+    }
+}
+
+/// Receives the next item for a stage from the threads that share its channel.
+fn recv<T>(receiver: &Mutex<mpsc::Receiver<T>>) -> Result<T, mpsc::RecvError> {
+    receiver.lock().unwrap().recv()
+}
+
+/// Recursively walks through a directory and performs find and replace operations on all files.
+///
+/// With `options.pick`, the files that would be modified are first listed on
+/// the terminal, and only the ones chosen there are.
+/// 
+/// # Arguments
+/// 
+/// * `starting_directory` - Root directory to start the search from
+/// * `find_text` - Text to find in files
+/// * `replace_text` - Text to replace the found text with
+/// * `options` - Options controlling what is done with each file
+/// 
+/// # Returns
+/// 
+/// * `io::Result<Summary>` - What happened during the walk, or an error if the
+///   run could not be set up
+pub fn walk_find_replace(starting_directory: &Path, find_text: &str, replace_text: &str, options: &Options) -> io::Result<Summary> {
+    let roots = [starting_directory.to_path_buf()];
+    let mut hooks = Hooks::default();
+    if options.pick {
+        let summary = walk(&roots, find_text, replace_text, &options.planning(), &hooks)?;
+        if summary.modified.is_empty() {
+            return Ok(summary);
+        }
+        let candidates: Vec<(PathBuf, usize)> = summary.modified.iter().map(|file| (file.path.clone(), file.replacements)).collect();
+        let picked: std::collections::HashSet<PathBuf> = picker::pick(&candidates)
+            .map_err(|e| io::Error::new(e.kind(), format!("--pick needs a terminal: {}", e)))?
+            .into_iter()
+            .collect();
+        if picked.is_empty() {
+            return Ok(Summary::default());
+        }
+        hooks.on_file_start.push(Arc::new(move |path: &Path| picked.contains(path)));
+    }
+    walk(&roots, find_text, replace_text, options, &hooks)
+}
+
+/// Performs find and replace on text read from a reader, such as stdin,
+/// writing the result to a writer, the way `sed` does in a pipeline.
+///
+/// The text is streamed a chunk at a time when nothing needs it whole, as huge
+/// files are, and otherwise read in full, decoded with `options.encoding` and
+/// rewritten as a file would be, with `--eol` and plugins applied.
+///
+/// # Arguments
+///
+/// * `input` - Where to read the text from
+/// * `output` - Where to write the rewritten text to, which gets the text
+///   unchanged if nothing matches
+/// * `find_text` - Text to find, which matches nothing if it is empty
+/// * `replace_text` - Text to replace the found text with
+/// * `options` - Options controlling how the text is decoded and rewritten
+///
+/// # Returns
+///
+/// * `io::Result<usize>` - The number of replacements made
+pub fn find_replace_stream(mut input: impl Read, mut output: impl Write, find_text: &str, replace_text: &str, options: &Options) -> io::Result<usize> {
+    if find_text.is_empty() && !options.needs_no_find_text() {
+        io::copy(&mut input, &mut output)?;
+        return Ok(0);
+    }
+    if options.can_stream() {
+        return stream::replace_stream(input, output, find_text, replace_text);
+    }
+
+    let mut bytes = Vec::new();
+    input.read_to_end(&mut bytes)?;
+    throttle::consume(bytes.len());
+    let (content, decoding) = encoding::decode(bytes.clone(), options.encoding)?;
+    let plugins = Plugins::load(&options.plugins)?;
+    let (replacements, new_bytes) = match rewrite(Path::new("-"), content, decoding, find_text, replace_text, options, &plugins)? {
+        Some((change, new_bytes)) => (change.replacements, new_bytes),
+        None => (0, bytes),
+    };
+    output.write_all(&new_bytes)?;
+    output.flush()?;
+    Ok(replacements)
+}
+
+/// Walks through one or more directories and performs find and replace
+/// operations on all files, as `walk_find_replace` does for one. Paths are
+/// displayed, and the index loaded, relative to the first directory.
+fn walk(roots: &[PathBuf], find_text: &str, replace_text: &str, options: &Options, hooks: &Hooks) -> io::Result<Summary> {
+    let Some(starting_directory) = roots.first() else {
+        return Ok(Summary::default());
+    };
+    let canonical_start = fs::canonicalize(starting_directory).unwrap_or_else(|_| starting_directory.clone());
+    if options.streams && !cfg!(windows) {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "--streams is only supported on Windows"));
+    }
+    if let Some(forbidden) = options.forbidden.iter().find(|forbidden| canonical_start.starts_with(forbidden)) {
+        return Err(io::Error::other(format!("{} is forbidden, so fr won't run in it", forbidden.display())));
+    }
+    // The copies are kept out of the walk, and can't be the originals
+    let out_dir = options.out_dir.as_ref()
+        .map(|out_dir| fs::canonicalize(out_dir).or_else(|_| std::path::absolute(out_dir)))
+        .transpose()?;
+    if out_dir.as_ref().is_some_and(|out_dir| *out_dir == canonical_start) {
+        return Err(io::Error::other("--out-dir is the directory being changed, so the files would be written over themselves"));
+    }
+    let hooks = match &options.hook {
+        Some(command) => Cow::Owned(hooks.clone().with_command(command, starting_directory)),
+        None => Cow::Borrowed(hooks),
+    };
+    // Nothing is written in a dry run, so there is nothing to log
+    let change_log = match &options.log {
+        Some(path) if !options.dry_run => Some(ChangeLog::open(path)?),
+        _ => None,
+    };
+    let journal = match &options.journal {
+        Some(path) if !options.dry_run => Some(Journal::open(path, starting_directory, find_text, replace_text)?),
+        _ => None,
+    };
+    let mut filters = hooks.filters.clone();
+    filters.extend(walk_filters(starting_directory, options)?.into_iter().map(|(_, filter)| filter));
+    let index = Index::load_shared(starting_directory)?;
+    let run = Run {
+        starting_directory,
+        find_text,
+        replace_text,
+        options,
+        change_log,
+        journal,
+        preview: Pager::start(options.pager && (options.diff || options.dry_run)),
+        painter: options.stdout_painter(),
+        editorconfig: EditorConfig::default(),
+        gitattributes: GitAttributes::default(),
+        cache: options.cache.as_deref().map(Cache::load).transpose()?,
+        shortlist: index.as_deref().and_then(|index| index.shortlist(find_text)),
+        memory: options.max_memory.map(|limit| Arc::new(MemoryBudget::new(limit))),
+        hooks: &hooks,
+        plugins: Plugins::load(&options.plugins)?,
+        needs_hunks: options.reports.iter().any(Report::needs_hunks),
+        needs_locations: options.reports.iter().any(Report::needs_locations),
+        summary: Mutex::new(Summary::default()),
+        stopped: AtomicBool::new(false),
+    };
+
+    // Files go through three stages, each on its own threads, so that reading
+    // and writing overlap on slow filesystems: the directory walk, reading and
+    // matching, and writing
+    let workers = match options.threads {
+        Some(threads) if threads > 0 => threads,
+        _ => thread::available_parallelism().map_or(1, |threads| threads.get()),
+    };
+    let (path_sender, path_receiver) = mpsc::sync_channel::<PathBuf>(STAGE_CAPACITY);
+    let (processed_sender, processed_receiver) = mpsc::sync_channel::<Processed>(STAGE_CAPACITY);
+    let (path_receiver, processed_receiver) = (Mutex::new(path_receiver), Mutex::new(processed_receiver));
+    thread::scope(|scope| {
+        let run = &run;
+        for _ in 0..workers {
+            let (path_receiver, processed_sender) = (&path_receiver, processed_sender.clone());
+            scope.spawn(move || {
+                while let Ok(path) = recv(path_receiver) {
+                    // Once stopped, the paths already walked are let go unread
+                    if run.stopped() {
+                        continue;
+                    }
+                    let Some(processed) = run.process(&path) else {
+                        continue;
+                    };
+                    if processed_sender.send(processed).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(processed_sender);
+        for _ in 0..workers {
+            let processed_receiver = &processed_receiver;
+            scope.spawn(move || {
+                while let Ok(processed) = recv(processed_receiver) {
+                    if !run.stopped() {
+                        run.finish(processed);
+                    }
+                }
+            });
+        }
+
+        walk_builder(roots, &filters).hidden(walks_hidden(options)).threads(workers).build_parallel().run(|| {
+            let path_sender = path_sender.clone();
+            let mut last_entry = Instant::now();
+            Box::new(move |result| {
+                if options.stats {
+                    stats::record_walk(last_entry.elapsed());
+                }
+                if run.stopped() {
+                    return WalkState::Quit;
+                }
+                // A file's named streams follow it
+                let result = result.map(|dent| {
+                    let streams = match options.streams && dent.file_type().is_some_and(|file_type| file_type.is_file()) {
+                        true => streams::named(dent.path()).unwrap_or_default(),
+                        false => Vec::new(),
+                    };
+                    for name in streams {
+                        if path_sender.send(streams::path(dent.path(), &name)).is_err() {
+                            return Err(mpsc::SendError(dent.into_path()));
+                        }
+                    }
+                    path_sender.send(dent.into_path())
+                });
+                // Stop walking if nothing is left to process the files
+                let state = match result {
+                    Ok(Err(_)) => WalkState::Quit,
+                    _ => WalkState::Continue,
+                };
+                if options.stats {
+                    last_entry = Instant::now();
+                }
+                state
+            })
+        });
+        drop(path_sender);
+    });
+
+    run.preview.finish()?;
+    if let Some(cache) = &run.cache {
+        cache.save()?;
+    }
+    let mut summary = run.summary.into_inner().unwrap();
+    if let (Some(Err(e)), Some(path)) = (run.journal.map(Journal::close), &options.journal) {
+        summary.errors.push((path.clone(), e.to_string()));
+    }
+    summary.modified.sort_by(|a, b| a.path.cmp(&b.path));
+    summary.skipped.sort();
+    summary.errors.sort();
+    Ok(summary)
+}
+
+/// Whether a run's walk leaves hidden files out, as it does unless they can
+/// be what it is for.
+pub(crate) fn walks_hidden(options: &Options) -> bool {
+    !options.config_values && options.placeholders.is_empty()
+}
+
+/// The filters a run's walk goes by besides the ignore files and the hooks,
+/// each with why it leaves a path out, for `--explain` to say. Directories
+/// are always let through by `--include`, for the files inside them.
+///
+/// # Arguments
+///
+/// * `starting_directory` - Directory the run walks from
+/// * `options` - Options the run uses
+///
+/// # Returns
+///
+/// * `io::Result<Vec<(&'static str, Filter)>>` - The filters, or an error if
+///   the workspace `--members` names can't be read
+pub(crate) fn walk_filters(starting_directory: &Path, options: &Options) -> io::Result<Vec<(&'static str, Filter)>> {
+    let starting_directory = starting_directory.to_path_buf();
+    let canonical_start = fs::canonicalize(&starting_directory).unwrap_or_else(|_| starting_directory.clone());
+    let mut filters: Vec<(&'static str, Filter)> = Vec::new();
+    // Env files are hidden, but are what --config-values is most often for,
+    // so they are let through along with visible files. Templates have hidden
+    // files such as .gitignore to fill in too
+    if options.config_values {
+        filters.push(("hidden and not an env file", Arc::new(|path: &Path| keyvalue::is_env_file(path) || !is_hidden(path))));
+    }
+    if !options.unsafe_vcs_dirs {
+        filters.push(("inside a version control directory", Arc::new(|path: &Path| !path.file_name().is_some_and(is_vcs_dir))));
+    }
+    if let Some(excluded) = excluded(&starting_directory, &options.excludes) {
+        filters.push(("excluded by --exclude", excluded));
+    }
+    if let Some(included) = included(&starting_directory, &options.includes) {
+        filters.push(("not named by --include", included));
+    }
+    if let Some(members) = &options.members {
+        filters.push(("not in the workspace members --members names", workspace::filter(&starting_directory, members)?));
+    }
+    // The copies are kept out of the walk
+    if let Some(out_dir) = &options.out_dir {
+        let out_dir = fs::canonicalize(out_dir).or_else(|_| std::path::absolute(out_dir))?;
+        let (canonical_start, starting_directory) = (canonical_start.clone(), starting_directory.clone());
+        filters.push(("inside --out-dir, where the copies go", Arc::new(move |path: &Path| {
+            canonical_start.join(path.strip_prefix(&starting_directory).unwrap_or(path)) != out_dir
+        })));
+    }
+    // The journal is kept out of the walk, so it isn't rewritten while
+    // sqlite3 writes to it
+    if let Some(journal) = &options.journal {
+        let journal_paths = journal::database_files(&fs::canonicalize(journal).or_else(|_| std::path::absolute(journal))?);
+        let (canonical_start, starting_directory) = (canonical_start.clone(), starting_directory.clone());
+        filters.push(("the --journal database", Arc::new(move |path: &Path| {
+            !journal_paths.contains(&canonical_start.join(path.strip_prefix(&starting_directory).unwrap_or(path)))
+        })));
+    }
+    if !options.forbidden.is_empty() {
+        let forbidden = options.forbidden.clone();
+        filters.push(("forbidden by --forbid", Arc::new(move |path: &Path| {
+            let path = canonical_start.join(path.strip_prefix(&starting_directory).unwrap_or(path));
+            !forbidden.iter().any(|forbidden| path.starts_with(forbidden))
+        })));
+    }
+    Ok(filters)
+}
+
+/// Sets up a walk of every root that visits the paths every filter accepts.
+fn walk_builder(roots: &[PathBuf], filters: &[Filter]) -> WalkBuilder {
+    let mut builder = WalkBuilder::new(roots.first().map_or(Path::new("."), PathBuf::as_path));
+    for root in roots.iter().skip(1) {
+        builder.add(root);
+    }
+    if !filters.is_empty() {
+        let filters = filters.to_vec();
+        builder.filter_entry(move |dent| filters.iter().all(|filter| filter(dent.path())));
+    }
+    builder
+}
+
+/// A filter that passes over the files and directories `--exclude` names, or
+/// None if it names none.
+fn excluded(starting_directory: &Path, excludes: &[Glob]) -> Option<Filter> {
+    let matches = glob_matches(starting_directory, excludes)?;
+    Some(Arc::new(move |path: &Path| !matches(path)))
+}
+
+/// A filter that passes over the files `--include` doesn't name, or None if it
+/// names none. Directories are always walked, for the files inside them.
+fn included(starting_directory: &Path, includes: &[Glob]) -> Option<Filter> {
+    let matches = glob_matches(starting_directory, includes)?;
+    Some(Arc::new(move |path: &Path| path.is_dir() || matches(path)))
+}
+
+/// Returns whether a path's name or its path below the starting directory
+/// matches any of the globs, or None if there are none.
+fn glob_matches(starting_directory: &Path, globs: &[Glob]) -> Option<impl Fn(&Path) -> bool + Send + Sync + 'static> {
+    if globs.is_empty() {
+        return None;
+    }
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(glob.clone());
+    }
+    let globs = builder.build().ok()?;
+    let starting_directory = starting_directory.to_path_buf();
+    Some(move |path: &Path| {
+        let relative = path.strip_prefix(&starting_directory).unwrap_or(path);
+        globs.is_match(relative) || path.file_name().is_some_and(|name| globs.is_match(name))
+    })
+}
+
+/// Names of the directories version control systems keep a repository's own
+/// files in
+const VCS_DIRS: [&str; 3] = [".git", ".hg", ".svn"];
+
+/// Whether a file or directory name is that of the metadata directory of git,
+/// Mercurial or Subversion.
+fn is_vcs_dir(name: &std::ffi::OsStr) -> bool {
+    VCS_DIRS.iter().any(|vcs_dir| name == *vcs_dir)
+}
+
+/// Whether a path names a hidden file or directory, one starting with `.`.
+fn is_hidden(path: &Path) -> bool {
+    path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.starts_with('.'))
+}
+
+/// Parses a size such as `4096`, `512K`, `10M` or `1G` into a number of bytes.
+/// Suffixes are binary multiples and case-insensitive, with an optional `B`.
+fn parse_size(value: &str) -> Result<u64, String> {
+    let invalid = || format!("Invalid size '{}', expected a number of bytes such as 4096, 512K, 10M or 1G", value);
+    let upper = value.trim().to_ascii_uppercase();
+    let digits = upper.strip_suffix('B').unwrap_or(&upper);
+    let (digits, multiplier) = match digits.chars().last() {
+        Some('K') => (&digits[..digits.len() - 1], 1 << 10),
+        Some('M') => (&digits[..digits.len() - 1], 1 << 20),
+        Some('G') => (&digits[..digits.len() - 1], 1 << 30),
+        Some('T') => (&digits[..digits.len() - 1], 1 << 40),
+        _ => (digits, 1),
+    };
+    let number: u64 = digits.parse().map_err(|_| invalid())?;
+    number.checked_mul(multiplier).ok_or_else(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    use std::path::PathBuf;
+    use std::process::Command;
+
+    fn create_test_file(dir: &Path, name: &str, content: &str) -> PathBuf {
+        let file_path = dir.join(name);
+        fs::write(&file_path, content).unwrap();
+        file_path
+    }
+
+    fn init_git_repo(dir: &Path) {
+        Command::new("git")
+            .arg("init")
+            .current_dir(dir)
+            .output()
+            .expect("Failed to initialize git repository");
+    }
+
+    #[test]
+    fn test_write_atomically() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = create_test_file(temp_dir.path(), "list.txt", "old contents");
+
+        write_atomically(&file_path, b"new contents").unwrap();
+
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "new contents");
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1, "Temporary file should be gone");
+    }
+
+    #[test]
+    fn test_find_occurrences() {
+        let content = "hello world\r\nsay hello, hello\nnothing\n  hello";
+        let occurrences = find_occurrences(content, "hello");
+        assert_eq!(occurrences, vec![
+            Occurrence { line: 1, column: 1, line_text: "hello world" },
+            Occurrence { line: 2, column: 5, line_text: "say hello, hello" },
+            Occurrence { line: 2, column: 12, line_text: "say hello, hello" },
+            Occurrence { line: 4, column: 3, line_text: "  hello" },
+        ]);
+
+        assert!(find_occurrences(content, "missing").is_empty());
+        assert!(find_occurrences(content, "").is_empty());
+    }
+
+    #[test]
+    fn test_count_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = create_test_file(temp_dir.path(), "test.txt", "hello hello world hello");
+
+        assert_eq!(count_file(&file_path, "hello", &Options::default()).unwrap(), 3);
+        assert_eq!(count_file(&file_path, "nonexistent", &Options::default()).unwrap(), 0);
+        assert_eq!(count_file(&file_path, "", &Options::default()).unwrap(), 0);
+
+        // Counting never modifies the file
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "hello hello world hello");
+    }
+
+    #[test]
+    fn test_find_replace_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = create_test_file(temp_dir.path(), "test.txt", "hello world");
+        
+        // Test successful replacement
+        let FileOutcome::Modified(change) = find_replace_file(&file_path, "hello", "hi", &Options::default()).unwrap() else {
+            panic!("Expected the file to be modified");
+        };
+        assert_eq!(change.before, "hello world");
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "hi world");
+
+        // Test no match
+        assert!(matches!(find_replace_file(&file_path, "nonexistent", "new", &Options::default()).unwrap(), FileOutcome::Skipped(SkipReason::NoMatch)));
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "hi world");
+
+        // Test empty find text
+        assert!(matches!(find_replace_file(&file_path, "", "new", &Options::default()).unwrap(), FileOutcome::Skipped(SkipReason::NoMatch)));
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "hi world");
+    }
+
+    #[test]
+    fn test_splice() {
+        for (content, find, replace) in [
+            ("hello world hello", "hello", "hi"),
+            ("aaaa", "aa", "b"),
+            ("no match", "x", "y"),
+            ("café ☃ café", "café", "thé"),
+            ("abcabc", "abc", ""),
+        ] {
+            let offsets = match_offsets(content, find);
+            assert_eq!(splice(content, &offsets, find.len(), replace), content.replace(find, replace));
+        }
+        assert_eq!(match_offsets("aaaa", "aa"), [0, 2]);
+    }
+
+    #[test]
+    fn test_find_replace_file_max_filesize() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = create_test_file(temp_dir.path(), "test.txt", "hello world");
+
+        let options = Options { max_filesize: Some(5), ..Options::default() };
+        assert!(matches!(find_replace_file(&file_path, "hello", "hi", &options).unwrap(), FileOutcome::Skipped(SkipReason::TooLarge)));
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "hello world");
+
+        let options = Options { max_filesize: Some(11), ..Options::default() };
+        assert!(matches!(find_replace_file(&file_path, "hello", "hi", &options).unwrap(), FileOutcome::Modified(_)));
+    }
+
+    #[test]
+    fn test_find_replace_file_max_memory() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = create_test_file(temp_dir.path(), "test.txt", "hello world");
+
+        // Files too large for the budget are streamed
+        let options = Options { max_memory: Some(20), ..Options::default() };
+        assert!(matches!(find_replace_file(&file_path, "hello", "hi", &options).unwrap(), FileOutcome::Streamed(1)));
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "hi world");
+
+        // Unless something needs their whole contents
+        let options = Options { max_memory: Some(20), diff: true, pager: false, ..Options::default() };
+        assert!(matches!(find_replace_file(&file_path, "hi", "hello", &options).unwrap(), FileOutcome::Modified(_)));
+        let options = Options { max_memory: Some(20), max_line_length: Some(4), ..Options::default() };
+        assert!(matches!(find_replace_file(&file_path, "hello", "hi", &options).unwrap(), FileOutcome::Skipped(SkipReason::LongLines)));
+
+        let options = Options { max_memory: Some(1 << 20), ..Options::default() };
+        assert!(matches!(find_replace_file(&file_path, "hello", "hi", &options).unwrap(), FileOutcome::Modified(_)));
+    }
+
+    #[test]
+    fn test_walk_find_replace_with_max_memory() {
+        let temp_dir = TempDir::new().unwrap();
+        for i in 0..20 {
+            create_test_file(temp_dir.path(), &format!("file{}.txt", i), &"hello ".repeat(i + 1));
+        }
+
+        let options = Options { max_memory: Some(64), threads: Some(4), diff: true, pager: false, ..Options::default() };
+        let summary = walk_find_replace(temp_dir.path(), "hello", "hi", &options).unwrap();
+        assert_eq!(summary.modified.len(), 20);
+        assert_eq!(fs::read_to_string(temp_dir.path().join("file19.txt")).unwrap(), "hi ".repeat(20));
+    }
+
+    #[test]
+    fn test_find_replace_file_encoding() {
+        let temp_dir = TempDir::new().unwrap();
+        let latin1_path = temp_dir.path().join("latin1.txt");
+        fs::write(&latin1_path, b"caf\xE9 au lait").unwrap();
+        let mut utf16_bytes = vec![0xFF, 0xFE];
+        utf16_bytes.extend("café au lait".encode_utf16().flat_map(u16::to_le_bytes));
+        let utf16_path = temp_dir.path().join("utf16.txt");
+        fs::write(&utf16_path, &utf16_bytes).unwrap();
+
+        // Neither file is UTF-8, and the UTF-16 one looks binary
+        assert!(matches!(find_replace_file(&latin1_path, "café", "thé", &Options::default()).unwrap(), FileOutcome::Skipped(SkipReason::NoMatch)));
+        assert!(matches!(find_replace_file(&utf16_path, "café", "thé", &Options::default()).unwrap(), FileOutcome::Skipped(SkipReason::Binary)));
+
+        let options = Options { encoding: Encoding::Auto, ..Options::default() };
+        assert!(matches!(find_replace_file(&latin1_path, "café", "thé", &options).unwrap(), FileOutcome::Modified(_)));
+        assert_eq!(fs::read(&latin1_path).unwrap(), b"th\xE9 au lait");
+        assert!(matches!(find_replace_file(&utf16_path, "café", "thé", &options).unwrap(), FileOutcome::Modified(_)));
+        let mut expected = vec![0xFF, 0xFE];
+        expected.extend("thé au lait".encode_utf16().flat_map(u16::to_le_bytes));
+        assert_eq!(fs::read(&utf16_path).unwrap(), expected);
+
+        // Replacements that can't be written in the file's encoding leave it untouched
+        let options = Options { encoding: Encoding::Latin1, ..Options::default() };
+        assert!(find_replace_file(&latin1_path, "thé", "茶", &options).is_err());
+        assert_eq!(fs::read(&latin1_path).unwrap(), b"th\xE9 au lait");
+    }
+
+    #[test]
+    fn test_find_replace_file_text() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("data.bin");
+        fs::write(&file_path, b"\x00\x01name=old\x00\xFF").unwrap();
+
+        assert!(matches!(find_replace_file(&file_path, "old", "new", &Options::default()).unwrap(), FileOutcome::Skipped(SkipReason::Binary)));
+
+        let options = Options { text: true, ..Options::default() };
+        assert!(matches!(find_replace_file(&file_path, "old", "new", &options).unwrap(), FileOutcome::Modified(_)));
+        assert_eq!(fs::read(&file_path).unwrap(), b"\x00\x01name=new\x00\xFF");
+    }
+
+    #[test]
+    fn test_find_replace_file_json_values() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("package.json");
+        fs::write(&file_path, "{\r\n  \"lodash\": \"lodash\",\r\n  \"main\": \"old\"\r\n}\r\n").unwrap();
+
+        let options = Options { json_values: true, ..Options::default() };
+        assert!(matches!(find_replace_file(&file_path, "lodash", "lodash-es", &options).unwrap(), FileOutcome::Modified(_)));
+        assert!(matches!(find_replace_file(&file_path, "\"main\"", "x", &options).unwrap(), FileOutcome::Skipped(SkipReason::NoMatch)));
+        assert!(matches!(find_replace_file(&file_path, "old", "line 1\nline 2", &options).unwrap(), FileOutcome::Modified(_)));
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "{\r\n  \"lodash\": \"lodash-es\",\r\n  \"main\": \"line 1\\nline 2\"\r\n}\r\n");
+    }
+
+    #[test]
+    fn test_find_replace_file_module_paths() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = create_test_file(temp_dir.path(), "lib.rs", "use crate::old::module;\n// old::module\n");
+
+        let options = Options { module_paths: true, ..Options::default() };
+        let FileOutcome::Modified(change) = find_replace_file(&file_path, "old::module", "new::module", &options).unwrap() else {
+            panic!("Expected the file to be modified");
+        };
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "use crate::new::module;\n// old::module\n");
+        // Only the line with the path changes, though the comment has a match too
+        let hunks = change.hunks();
+        assert_eq!(hunks.len(), 1);
+        assert_eq!(hunks[0].removed, ["use crate::old::module;"]);
+    }
+
+    #[test]
+    fn test_find_replace_file_confusables() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = create_test_file(temp_dir.path(), "phish.html", "<a>pаypal</a> <a>pay\u{200D}pal</a> <a>paypal</a>");
+
+        let options = Options { confusables: true, ..Options::default() };
+        assert!(matches!(find_replace_file(&file_path, "paypal", "[removed]", &options).unwrap(), FileOutcome::Modified(change) if change.replacements == 3));
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "<a>[removed]</a> <a>[removed]</a> <a>[removed]</a>");
+    }
+
+    #[test]
+    fn test_find_replace_file_text_extensions() {
+        let temp_dir = TempDir::new().unwrap();
+        let dat_path = temp_dir.path().join("levels.DAT");
+        fs::write(&dat_path, b"\x00title=old").unwrap();
+        let bin_path = temp_dir.path().join("levels.bin");
+        fs::write(&bin_path, b"\x00title=old").unwrap();
+
+        let options = Options { text_extensions: vec!["svg".to_string(), "dat".to_string()], ..Options::default() };
+        assert!(matches!(find_replace_file(&dat_path, "old", "new", &options).unwrap(), FileOutcome::Modified(_)));
+        assert!(matches!(find_replace_file(&bin_path, "old", "new", &options).unwrap(), FileOutcome::Skipped(SkipReason::Binary)));
+    }
+
+    #[test]
+    fn test_walk_find_replace_with_editorconfig() {
+        let temp_dir = TempDir::new().unwrap();
+        create_test_file(temp_dir.path(), ".editorconfig", "root = true\n[*.bat]\nend_of_line = crlf\n[*.txt]\ncharset = latin1\n");
+        create_test_file(temp_dir.path(), "build.bat", "echo old\necho done\n");
+        fs::write(temp_dir.path().join("notes.txt"), b"caf\xE9 old").unwrap();
+
+        walk_find_replace(temp_dir.path(), "old", "new", &Options::default()).unwrap();
+        assert_eq!(fs::read_to_string(temp_dir.path().join("build.bat")).unwrap(), "echo new\r\necho done\r\n");
+        assert_eq!(fs::read(temp_dir.path().join("notes.txt")).unwrap(), b"caf\xE9 new");
+
+        // Flags take precedence over .editorconfig
+        let options = Options { eol: EolPolicy::Lf, ..Options::default() };
+        walk_find_replace(temp_dir.path(), "new", "old", &options).unwrap();
+        assert_eq!(fs::read_to_string(temp_dir.path().join("build.bat")).unwrap(), "echo old\necho done\n");
+
+        let options = Options { editorconfig: false, ..Options::default() };
+        assert_eq!(options.for_file(&temp_dir.path().join("build.bat"), &EditorConfig::default()).eol, EolPolicy::Preserve);
+    }
+
+    #[test]
+    fn test_find_replace_file_invalid_utf8() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("stray.txt");
+        fs::write(&file_path, b"hello \xFF\xFE world\n").unwrap();
+
+        assert!(matches!(find_replace_file(&file_path, "world", "there", &Options::default()).unwrap(), FileOutcome::Modified(_)));
+        assert_eq!(fs::read(&file_path).unwrap(), b"hello \xFF\xFE there\n");
+    }
+
+    #[test]
+    fn test_find_replace_file_line_endings() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = create_test_file(temp_dir.path(), "crlf.txt", "one\r\ntwo\r\nthree\r\n");
+
+        // Newlines in the find and replace text match and keep the file's CRLFs
+        let FileOutcome::Modified(change) = find_replace_file(&file_path, "one\ntwo", "1\n2\n2.5", &Options::default()).unwrap() else {
+            panic!("Expected the file to be modified");
+        };
+        assert_eq!(change.replacements, 1);
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "1\r\n2\r\n2.5\r\nthree\r\n");
+
+        // --eol converts the whole file
+        let options = Options { eol: EolPolicy::Lf, ..Options::default() };
+        let FileOutcome::Modified(change) = find_replace_file(&file_path, "three", "3", &options).unwrap() else {
+            panic!("Expected the file to be modified");
+        };
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "1\n2\n2.5\n3\n");
+        assert_eq!(change.hunks().len(), 1);
+        assert_eq!(change.hunks()[0].removed.len(), 4);
+    }
+
+    #[test]
+    fn test_find_replace_stream() {
+        let mut output = Vec::new();
+        let replacements = find_replace_stream(&b"old\r\nold text\r\n"[..], &mut output, "old\n", "new\n", &Options::default()).unwrap();
+        assert_eq!((replacements, output.as_slice()), (1, &b"new\r\nold text\r\n"[..]));
+
+        // Text read whole is decoded, and left as it was if nothing matches
+        let options = Options { encoding: Encoding::Latin1, eol: EolPolicy::Lf, ..Options::default() };
+        let mut output = Vec::new();
+        assert_eq!(find_replace_stream(&b"caf\xe9\r\n"[..], &mut output, "caf\u{e9}", "bar", &options).unwrap(), 1);
+        assert_eq!(output, b"bar\n");
+        let mut output = Vec::new();
+        assert_eq!(find_replace_stream(&b"caf\xe9\r\n"[..], &mut output, "tea", "bar", &options).unwrap(), 0);
+        assert_eq!(output, b"caf\xe9\r\n");
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("4096").unwrap(), 4096);
+        assert_eq!(parse_size("512K").unwrap(), 512 * 1024);
+        assert_eq!(parse_size("10mb").unwrap(), 10 * 1024 * 1024);
+        assert_eq!(parse_size("1G").unwrap(), 1 << 30);
+        assert!(parse_size("").is_err());
+        assert!(parse_size("ten").is_err());
+        assert!(parse_size("-1K").is_err());
+    }
+
+    #[test]
+    fn test_find_replace_file_errors() {
+        let temp_dir = TempDir::new().unwrap();
+        let nonexistent_path = temp_dir.path().join("nonexistent.txt");
+        
+        // Test non-existent file
+        assert!(find_replace_file(&nonexistent_path, "find", "replace", &Options::default()).is_ok());
+
+        // Test directory
+        assert!(find_replace_file(temp_dir.path(), "find", "replace", &Options::default()).is_ok());
+    }
+
+    #[test]
+    fn test_walk_find_replace() {
+        let temp_dir = TempDir::new().unwrap();
+        
+        // Create test files
+        create_test_file(temp_dir.path(), "file1.txt", "hello world");
+        create_test_file(temp_dir.path(), "file2.txt", "hello there");
+        create_test_file(temp_dir.path(), "file3.txt", "no match");
+
+        // Create a subdirectory with more files
+        let subdir = temp_dir.path().join("subdir");
+        fs::create_dir(&subdir).unwrap();
+        create_test_file(&subdir, "file4.txt", "hello again");
+
+        // Perform find and replace
+        let summary = walk_find_replace(temp_dir.path(), "hello", "hi", &Options::default()).unwrap();
+
+        // Verify results
+        let modified: Vec<&Path> = summary.modified.iter().map(|file| file.path.as_path()).collect();
+        assert_eq!(modified, vec![
+            Path::new("file1.txt"),
+            Path::new("file2.txt"),
+            &Path::new("subdir").join("file4.txt"),
+        ]);
+        assert_eq!(summary.unmatched, 1);
+        assert_eq!(fs::read_to_string(temp_dir.path().join("file1.txt")).unwrap(), "hi world");
+        assert_eq!(fs::read_to_string(temp_dir.path().join("file2.txt")).unwrap(), "hi there");
+        assert_eq!(fs::read_to_string(temp_dir.path().join("file3.txt")).unwrap(), "no match");
+        assert_eq!(fs::read_to_string(subdir.join("file4.txt")).unwrap(), "hi again");
+    }
+
+    #[test]
+    fn test_walk_find_replace_logs_only_replaced_values() {
+        let temp_dir = TempDir::new().unwrap();
+        let out_dir = TempDir::new().unwrap();
+        create_test_file(temp_dir.path(), "config.yaml", "foo: foo\n");
+        let (log_path, csv_path) = (out_dir.path().join("changes.log"), out_dir.path().join("edits.csv"));
+        let options = Options {
+            yaml_values: true,
+            log: Some(log_path.clone()),
+            reports: vec![Report::Csv(csv_path.clone())],
+            quiet: true,
+            ..Options::default()
+        };
+
+        let summary = walk_find_replace(temp_dir.path(), "foo", "bar", &options).unwrap();
+        options.reports[0].write(&summary, "foo", "bar", &options.stdout_painter()).unwrap();
+        assert_eq!(fs::read_to_string(temp_dir.path().join("config.yaml")).unwrap(), "foo: bar\n");
+        // The key was found but not replaced, so only the value is listed
+        let log = fs::read_to_string(&log_path).unwrap();
+        assert_eq!(log.lines().count(), 1);
+        assert!(log.ends_with(" config.yaml:1:6: \"foo: foo\" -> \"foo: bar\"\n"), "{}", log);
+        assert_eq!(fs::read_to_string(&csv_path).unwrap(), "path,line,column,matched,replacement\r\nconfig.yaml,1,6,foo,bar\r\n");
+    }
+
+    #[test]
+    fn test_walk_find_replace_journal() {
+        let temp_dir = TempDir::new().unwrap();
+        create_test_file(temp_dir.path(), "file.txt", "hello world, hello");
+        let journal_path = temp_dir.path().join("fr.db");
+        let options = Options { journal: Some(journal_path.clone()), ..Options::default() };
+
+        let summary = walk_find_replace(temp_dir.path(), "hello", "hi", &options).unwrap();
+        assert!(summary.errors.is_empty());
+        let runs = journal::runs(&journal_path, None).unwrap();
+        assert_eq!(runs.len(), 1);
+        let files = journal::files(&journal_path, runs[0].id).unwrap();
+        assert_eq!(files[0].path, Path::new("file.txt"));
+        assert_eq!(files[0].edits[1], journal::JournalEdit { start: Some(13), before: "hello".to_string(), after: "hi".to_string() });
+
+        // The journal, which has hi in it now, isn't rewritten by the next run
+        let options = Options { text: true, ..options };
+        let summary = walk_find_replace(temp_dir.path(), "hi", "hey", &options).unwrap();
+        assert_eq!(summary.modified.iter().map(|file| file.path.to_str().unwrap()).collect::<Vec<_>>(), ["file.txt"]);
+    }
+
+    #[test]
+    fn test_walk_count_does_not_modify() {
+        let temp_dir = TempDir::new().unwrap();
+        create_test_file(temp_dir.path(), "file1.txt", "hello world");
+
+        let options = Options { count: true, ..Options::default() };
+        walk_find_replace(temp_dir.path(), "hello", "hi", &options).unwrap();
+
+        assert_eq!(fs::read_to_string(temp_dir.path().join("file1.txt")).unwrap(), "hello world");
+    }
+
+    #[test]
+    fn test_walk_find_replace_single_thread() {
+        let temp_dir = TempDir::new().unwrap();
+        create_test_file(temp_dir.path(), "file1.txt", "hello world");
+        create_test_file(temp_dir.path(), "file2.txt", "hello there");
+
+        let options = Options { threads: Some(1), ..Options::default() };
+        let summary = walk_find_replace(temp_dir.path(), "hello", "hi", &options).unwrap();
+        assert_eq!(summary.modified.len(), 2);
+    }
+
+    #[test]
+    fn test_walk_find_replace_with_cache() {
+        let temp_dir = TempDir::new().unwrap();
+        let tree = temp_dir.path().join("tree");
+        fs::create_dir(&tree).unwrap();
+        create_test_file(&tree, "match.txt", "hello world");
+        create_test_file(&tree, "other.txt", "goodbye world");
+
+        let options = Options { cache: Some(temp_dir.path().join("cache")), ..Options::default() };
+        let summary = walk_find_replace(&tree, "hello", "hi", &options).unwrap();
+        assert_eq!(summary.modified.len(), 1);
+        assert_eq!(summary.unmatched, 1);
+
+        // other.txt is skipped without being read, and still counts as unmatched
+        let summary = walk_find_replace(&tree, "hello", "hi", &options).unwrap();
+        assert_eq!(summary.modified.len(), 0);
+        assert_eq!(summary.unmatched, 2);
+        let cache = Cache::load(&temp_dir.path().join("cache")).unwrap();
+        assert!(cache.has_no_match(&tree.join("other.txt"), cache::pattern("hello", Encoding::Utf8)));
+
+        // Changed files are searched again
+        fs::write(tree.join("other.txt"), "hello again").unwrap();
+        let summary = walk_find_replace(&tree, "hello", "hi", &options).unwrap();
+        assert_eq!(summary.modified.len(), 1);
+    }
+
+    #[test]
+    fn test_walk_find_replace_with_index() {
+        let temp_dir = TempDir::new().unwrap();
+        create_test_file(temp_dir.path(), "match.txt", "hello world");
+        let other = create_test_file(temp_dir.path(), "other.txt", "goodbye world");
+        Index::build(temp_dir.path(), &Options::default()).unwrap().save().unwrap();
+
+        // Rewrite other.txt behind the index's back, keeping its size and
+        // modification time, to show that it isn't read
+        let modified = fs::metadata(&other).unwrap().modified().unwrap();
+        fs::write(&other, "hello   world").unwrap();
+        File::options().write(true).open(&other).unwrap().set_modified(modified).unwrap();
+        create_test_file(temp_dir.path(), "new.txt", "hello there");
+
+        let summary = walk_find_replace(temp_dir.path(), "hello", "hi", &Options::default()).unwrap();
+        let paths: Vec<&Path> = summary.modified.iter().map(|file| file.path.as_path()).collect();
+        assert_eq!(paths, [Path::new("match.txt"), Path::new("new.txt")]);
+        assert_eq!(summary.unmatched, 1);
+    }
+
+    #[test]
+    fn test_walk_dry_run_does_not_modify() {
+        let temp_dir = TempDir::new().unwrap();
+        create_test_file(temp_dir.path(), "file1.txt", "hello world");
+
+        let options = Options { dry_run: true, diff: true, ..Options::default() };
+        let summary = walk_find_replace(temp_dir.path(), "hello", "hi", &options).unwrap();
+
+        assert_eq!(summary.modified.len(), 1);
+        assert_eq!(summary.modified[0].replacements, 1);
+        assert_eq!(fs::read_to_string(temp_dir.path().join("file1.txt")).unwrap(), "hello world");
+    }
+
+    #[test]
+    fn test_walk_find_replace_out_dir() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir(temp_dir.path().join("src")).unwrap();
+        create_test_file(&temp_dir.path().join("src"), "file1.txt", "hello world");
+        create_test_file(temp_dir.path(), "file2.txt", "goodbye");
+
+        // The copies are written inside the tree without being walked
+        let out_dir = temp_dir.path().join("out");
+        let options = Options { out_dir: Some(out_dir.clone()), quiet: true, ..Options::default() };
+        let summary = walk_find_replace(temp_dir.path(), "hello", "hi", &options).unwrap();
+        assert_eq!(summary.modified.len(), 1);
+        assert_eq!(fs::read_to_string(temp_dir.path().join("src/file1.txt")).unwrap(), "hello world");
+        assert_eq!(fs::read_to_string(out_dir.join("src/file1.txt")).unwrap(), "hi world");
+        assert!(!out_dir.join("file2.txt").exists());
+        let summary = walk_find_replace(temp_dir.path(), "hi", "hey", &options).unwrap();
+        assert!(summary.modified.is_empty());
+
+        let options = Options { out_dir: Some(temp_dir.path().to_path_buf()), ..Options::default() };
+        assert!(walk_find_replace(temp_dir.path(), "hello", "hi", &options).is_err());
+    }
+
+    #[test]
+    fn test_walk_find_replace_with_gitignore() {
+        let temp_dir = TempDir::new().unwrap();
+        
+        // Initialize git repository
+        init_git_repo(temp_dir.path());
+
+        // Create .gitignore
+        create_test_file(temp_dir.path(), ".gitignore", "ignored.txt\n*.log");
+        
+        // Create test files
+        create_test_file(temp_dir.path(), "file.txt", "hello world");
+        create_test_file(temp_dir.path(), "ignored.txt", "hello ignored");
+        create_test_file(temp_dir.path(), "test.log", "hello log");
+
+        // Perform find and replace
+        walk_find_replace(temp_dir.path(), "hello", "hi", &Options::default()).unwrap();
+
+        // Verify results
+        assert_eq!(fs::read_to_string(temp_dir.path().join("file.txt")).unwrap(), "hi world");
+        assert_eq!(fs::read_to_string(temp_dir.path().join("ignored.txt")).unwrap(), "hello ignored");
+        assert_eq!(fs::read_to_string(temp_dir.path().join("test.log")).unwrap(), "hello log");
+    }
+
+    #[test]
+    fn test_walk_find_replace_config_values() {
+        let temp_dir = TempDir::new().unwrap();
+        create_test_file(temp_dir.path(), ".env", "DB_HOST=db1\n");
+        fs::create_dir(temp_dir.path().join(".secrets")).unwrap();
+        create_test_file(temp_dir.path(), ".secrets/db.env", "DB_HOST=db1\n");
+        create_test_file(temp_dir.path(), "app.ini", "[db1]\nhost = db1\n");
+        create_test_file(temp_dir.path(), "notes.txt", "db1\n");
+
+        let options = Options { config_values: true, ..Options::default() };
+        walk_find_replace(temp_dir.path(), "db1", "db2", &options).unwrap();
+        assert_eq!(fs::read_to_string(temp_dir.path().join(".env")).unwrap(), "DB_HOST=db2\n");
+        assert_eq!(fs::read_to_string(temp_dir.path().join(".secrets/db.env")).unwrap(), "DB_HOST=db1\n");
+        assert_eq!(fs::read_to_string(temp_dir.path().join("app.ini")).unwrap(), "[db1]\nhost = db2\n");
+        assert_eq!(fs::read_to_string(temp_dir.path().join("notes.txt")).unwrap(), "db1\n");
+    }
+
+    #[test]
+    fn test_walk_find_replace_excludes() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir_all(temp_dir.path().join("target/debug")).unwrap();
+        create_test_file(temp_dir.path(), "target/debug/out.txt", "old\n");
+        create_test_file(temp_dir.path(), "app.min.js", "old\n");
+        create_test_file(temp_dir.path(), "app.js", "old\n");
+
+        let excludes = vec![Glob::new("target").unwrap(), Glob::new("*.min.js").unwrap()];
+        let options = Options { excludes, ..Options::default() };
+        walk_find_replace(temp_dir.path(), "old", "new", &options).unwrap();
+        assert_eq!(fs::read_to_string(temp_dir.path().join("target/debug/out.txt")).unwrap(), "old\n");
+        assert_eq!(fs::read_to_string(temp_dir.path().join("app.min.js")).unwrap(), "old\n");
+        assert_eq!(fs::read_to_string(temp_dir.path().join("app.js")).unwrap(), "new\n");
+    }
+
+    #[test]
+    fn test_walk_find_replace_includes_and_forbidden() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir_all(temp_dir.path().join("vendor/lib")).unwrap();
+        create_test_file(temp_dir.path(), "vendor/lib/dep.rs", "old\n");
+        create_test_file(temp_dir.path(), "main.rs", "old\n");
+        create_test_file(temp_dir.path(), "notes.txt", "old\n");
+
+        let forbidden = vec![fs::canonicalize(temp_dir.path().join("vendor")).unwrap()];
+        let options = Options { includes: vec![Glob::new("*.rs").unwrap()], forbidden, ..Options::default() };
+        walk_find_replace(temp_dir.path(), "old", "new", &options).unwrap();
+        assert_eq!(fs::read_to_string(temp_dir.path().join("main.rs")).unwrap(), "new\n");
+        assert_eq!(fs::read_to_string(temp_dir.path().join("notes.txt")).unwrap(), "old\n");
+        assert_eq!(fs::read_to_string(temp_dir.path().join("vendor/lib/dep.rs")).unwrap(), "old\n");
+        assert!(walk_find_replace(&temp_dir.path().join("vendor/lib"), "old", "new", &options).is_err());
+    }
+
+    #[test]
+    fn test_walk_find_replace_vcs_dirs() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir_all(temp_dir.path().join(".git/refs")).unwrap();
+        create_test_file(temp_dir.path(), ".git/config", "old\n");
+        create_test_file(temp_dir.path(), ".git/refs/main", "old\n");
+        create_test_file(temp_dir.path(), "main.rs", "old\n");
+
+        // Even run from inside it, or with hidden files walked
+        let options = Options { config_values: true, ..Options::default() };
+        walk_find_replace(&temp_dir.path().join(".git"), "old", "new", &options).unwrap();
+        walk_find_replace(temp_dir.path(), "old", "new", &options).unwrap();
+        assert_eq!(fs::read_to_string(temp_dir.path().join(".git/config")).unwrap(), "old\n");
+        assert_eq!(fs::read_to_string(temp_dir.path().join(".git/refs/main")).unwrap(), "old\n");
+
+        let options = Options { unsafe_vcs_dirs: true, ..Options::default() };
+        walk_find_replace(&temp_dir.path().join(".git"), "old", "new", &options).unwrap();
+        assert_eq!(fs::read_to_string(temp_dir.path().join(".git/refs/main")).unwrap(), "new\n");
+    }
+
+    #[test]
+    fn test_walk_find_replace_strict() {
+        let temp_dir = TempDir::new().unwrap();
+        // An odd number of bytes can't be UTF-16
+        for i in 0..5 {
+            create_test_file(temp_dir.path(), &format!("{}.txt", i), "old");
+        }
+
+        let options = Options { encoding: Encoding::Utf16Le, threads: Some(1), ..Options::default() };
+        assert_eq!(walk_find_replace(temp_dir.path(), "old", "new", &options).unwrap().errors.len(), 5);
+        let options = Options { strict: true, ..options };
+        assert_eq!(walk_find_replace(temp_dir.path(), "old", "new", &options).unwrap().errors.len(), 1);
+    }
+
+    #[test]
+    fn test_longest_line() {
+        assert_eq!(longest_line(""), 0);
+        assert_eq!(longest_line("ab\r\nabcd\nabc"), 4);
+        assert_eq!(longest_line("abc\n"), 3);
+
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = create_test_file(temp_dir.path(), "app.min.js", &format!("var a=1;{}\n", "x".repeat(100)));
+        let options = Options { max_line_length: Some(100), ..Options::default() };
+        assert!(matches!(find_replace_file(&file_path, "var", "let", &options).unwrap(), FileOutcome::Skipped(SkipReason::LongLines)));
+        let options = Options { max_line_length: Some(200), ..Options::default() };
+        assert!(matches!(find_replace_file(&file_path, "var", "let", &options).unwrap(), FileOutcome::Modified(_)));
+    }
+
+    #[test]
+    fn test_walk_find_replace_with_gitattributes() {
+        let temp_dir = TempDir::new().unwrap();
+        create_test_file(temp_dir.path(), ".gitattributes", "*.lock -diff
+schema.rs linguist-generated
+*.dat text
+");
+        create_test_file(temp_dir.path(), "Cargo.lock", "old\n");
+        create_test_file(temp_dir.path(), "schema.rs", "old\n");
+        create_test_file(temp_dir.path(), "table.dat", "old\0\n");
+
+        let summary = walk_find_replace(temp_dir.path(), "old", "new", &Options::default()).unwrap();
+        assert_eq!(summary.skipped, vec![(PathBuf::from("Cargo.lock"), SkipReason::Binary), (PathBuf::from("schema.rs"), SkipReason::Generated)]);
+        assert_eq!(fs::read_to_string(temp_dir.path().join("table.dat")).unwrap(), "new\0\n");
+
+        let options = Options { gitattributes: false, ..Options::default() };
+        walk_find_replace(temp_dir.path(), "old", "new", &options).unwrap();
+        assert_eq!(fs::read_to_string(temp_dir.path().join("schema.rs")).unwrap(), "new\n");
+    }
+
+    #[test]
+    fn test_is_binary() {
+        let temp_dir = TempDir::new().unwrap();
+        
+        // Test text file (should not be binary)
+        let text_file = create_test_file(temp_dir.path(), "text.txt", "Hello, world!\n");
+        assert!(!is_binary(&text_file, Encoding::Utf8));
+
+        // Test text file with some non-printable chars (should not be binary)
+        let text_with_chars = create_test_file(temp_dir.path(), "text_with_chars.txt", "Hello\tworld\n\r");
+        assert!(!is_binary(&text_with_chars, Encoding::Utf8));
+
+        // Test binary file (should be binary)
+        let binary_content = vec![0, 1, 2, 3, 4, 5, 0, 0, 0];
+        let binary_file = temp_dir.path().join("binary.bin");
+        fs::write(&binary_file, binary_content).unwrap();
+        assert!(is_binary(&binary_file, Encoding::Utf8));
+
+        // Test file with high ratio of non-printable chars (should be binary)
+        let high_ratio = vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31];
+        let high_ratio_file = temp_dir.path().join("high_ratio.txt");
+        fs::write(&high_ratio_file, high_ratio).unwrap();
+        assert!(is_binary(&high_ratio_file, Encoding::Utf8));
+
+        // Test Japanese in Shift_JIS (should only be binary when not read as Shift_JIS)
+        let shift_jis_file = temp_dir.path().join("shift_jis.txt");
+        fs::write(&shift_jis_file, b"\x93\xfa\x96\x7b\x8c\xea\n").unwrap();
+        assert!(is_binary(&shift_jis_file, Encoding::Utf8));
+        assert!(!is_binary(&shift_jis_file, Encoding::ShiftJis));
+
+        // Test empty file (should not be binary)
+        let empty_file = create_test_file(temp_dir.path(), "empty.txt", "");
+        assert!(!is_binary(&empty_file, Encoding::Utf8));
+
+        // Test non-existent file (should not be binary)
+        let nonexistent = temp_dir.path().join("nonexistent.txt");
+        assert!(!is_binary(&nonexistent, Encoding::Utf8));
+    }
+
+    #[test]
+    fn test_version_output() {
+        let output = Command::new("cargo")
+            .arg("run")
+            .arg("--")
+            .arg("--version")
+            .output()
+            .expect("Failed to execute command");
+
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(stdout.starts_with("fr "));
+        assert!(stdout.contains(env!("CARGO_PKG_VERSION")));
+    }
+}
@@ -0,0 +1,433 @@
+//! Replacing the license header of each source file, for `fr license`, and
+//! extending copyright notices to the current year, for `fr bump-year`.
+//!
+//! A file's header is the comment it starts with, after any `#!` line, XML
+//! declaration or encoding comment: a block comment such as `/* ... */` or
+//! `<!-- ... -->`, or a run of line comments such as `//` or `#` up to the
+//! first line that isn't one. The text of the comment, without its markers or
+//! the `*` down the side of a block comment, is compared with the old header
+//! with every run of whitespace made a single space and every year or range
+//! of years, such as `2019-2023`, treated as the same, so a header is found
+//! whatever year it was written in and however it was wrapped. A match is
+//! replaced with the new header, written in the same kind of comment with the
+//! same decoration. A `{year}` in the new header is replaced with the years
+//! of the header it replaces, or the current year if it had none.
+//!
+//! `fr bump-year` looks for notices anywhere in a file instead, and only
+//! changes their last year, so `Copyright (c) 2019-2023` and `© 2019` end in
+//! the current year.
+
+use crate::change_log;
+use crate::eol::{self, LineEnding};
+use std::ops::Range;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// What stands in for the years in a normalized header
+const YEARS: &str = "<year>";
+
+/// How comments are written in a language
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Syntax {
+    /// What starts a line comment, if the language has them
+    line: Option<&'static str>,
+    /// What starts and ends a block comment, if the language has them
+    block: Option<(&'static str, &'static str)>,
+}
+
+const C_LIKE: Syntax = Syntax { line: Some("//"), block: Some(("/*", "*/")) };
+const HASH: Syntax = Syntax { line: Some("#"), block: None };
+const DASHES: Syntax = Syntax { line: Some("--"), block: None };
+const SEMICOLONS: Syntax = Syntax { line: Some(";"), block: None };
+const PERCENT: Syntax = Syntax { line: Some("%"), block: None };
+const CSS: Syntax = Syntax { line: None, block: Some(("/*", "*/")) };
+const MARKUP: Syntax = Syntax { line: None, block: Some(("<!--", "-->")) };
+
+/// Finds how comments are written in a file, going by its extension or, for
+/// files such as `Makefile`, its name.
+fn syntax(file_path: &Path) -> Option<Syntax> {
+    let name = file_path.file_name()?.to_str()?;
+    if matches!(name, "Makefile" | "Dockerfile" | "CMakeLists.txt" | "Rakefile" | "Gemfile" | "BUILD" | "BUILD.bazel") {
+        return Some(HASH);
+    }
+    let extension = file_path.extension()?.to_str()?.to_ascii_lowercase();
+    Some(match extension.as_str() {
+        "c" | "h" | "cc" | "cpp" | "cxx" | "c++" | "hh" | "hpp" | "hxx" | "h++" | "m" | "mm" | "rs" | "go" | "java" | "kt"
+        | "kts" | "scala" | "swift" | "js" | "jsx" | "mjs" | "cjs" | "ts" | "tsx" | "mts" | "cts" | "cs" | "dart" | "php"
+        | "proto" | "groovy" | "gradle" | "scss" | "less" | "zig" => C_LIKE,
+        "py" | "sh" | "bash" | "zsh" | "rb" | "pl" | "pm" | "r" | "yaml" | "yml" | "toml" | "cmake" | "ps1" | "tf" | "nix"
+        | "ex" | "exs" | "jl" | "mk" => HASH,
+        "sql" | "lua" | "hs" | "elm" => DASHES,
+        "el" | "lisp" | "clj" | "cljs" | "scm" => SEMICOLONS,
+        "tex" | "erl" => PERCENT,
+        "css" => CSS,
+        "html" | "htm" | "xhtml" | "xml" | "svg" | "vue" => MARKUP,
+        _ => return None,
+    })
+}
+
+/// How a header comment is decorated, to write the new header the same way
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Style {
+    /// Line comments starting with this marker
+    Lines(&'static str),
+    /// A block comment, with its opening line, what each line inside it starts
+    /// with, and its closing line
+    Block { open: String, prefix: String, close: String },
+}
+
+/// The comment a file starts with
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Header {
+    /// The bytes of the file it takes up, from the start of its first line to
+    /// the end of its last, before the line ending
+    range: Range<usize>,
+    /// The text of each of its lines, without the comment markers
+    lines: Vec<String>,
+    style: Style,
+}
+
+/// Finds a file's header comment.
+fn header(content: &str, syntax: Syntax) -> Option<Header> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    for line in content.split_inclusive('\n') {
+        lines.push((start, line.trim_end_matches('\n').trim_end_matches('\r')));
+        start += line.len();
+    }
+    // Lines that have to come first, which the header comes after
+    let mut first = 0;
+    if lines.first().is_some_and(|(_, line)| line.starts_with("#!") || line.starts_with("<?xml")) {
+        first += 1;
+    }
+    while lines.get(first).is_some_and(|(_, line)| first < 2 && is_encoding_comment(line)) {
+        first += 1;
+    }
+    while lines.get(first).is_some_and(|(_, line)| line.trim().is_empty()) {
+        first += 1;
+    }
+    let &(start, line) = lines.get(first)?;
+    let trimmed = line.trim_start();
+
+    if let Some((open, close)) = syntax.block.filter(|(open, _)| trimmed.starts_with(open)) {
+        let open_at = start + line.len() - trimmed.len();
+        let close_at = open_at + open.len() + content[open_at + open.len()..].find(close)?;
+        let last = lines.partition_point(|&(line_start, _)| line_start <= close_at) - 1;
+        let (last_start, last_line) = lines[last];
+        // Code after the comment on its last line would go with it
+        if !last_line[close_at + close.len() - last_start..].trim().is_empty() {
+            return None;
+        }
+        let inside = content[open_at + open.len()..close_at].trim_start_matches(['*', '!']);
+        let decorated = open == "/*";
+        let text: Vec<String> = inside.lines()
+            .map(|line| {
+                let line = line.trim();
+                let line = if decorated { line.strip_prefix('*').unwrap_or(line) } else { line };
+                line.trim().to_string()
+            })
+            .collect();
+
+        // Doc comments open with a `/**` or `/*!` the new header keeps
+        let after_open = &trimmed[open.len()..];
+        let open_line = trimmed[..trimmed.len() - after_open.trim_start_matches(['*', '!']).len()].to_string();
+        // The lines inside show what each line starts with
+        let prefix = lines.get(first + 1..last).unwrap_or_default().iter().map(|(_, line)| *line).find(|line| !line.trim().is_empty())
+            .map(|line| {
+                let indent = &line[..line.len() - line.trim_start().len()];
+                match decorated && line.trim_start().starts_with('*') {
+                    true => format!("{}* ", indent),
+                    false => indent.to_string(),
+                }
+            })
+            .unwrap_or_else(|| if decorated { " * ".to_string() } else { "  ".to_string() });
+        let close_line = match last > first && last_line.trim() == close {
+            true => last_line.trim_end().to_string(),
+            false if decorated => format!("{}{}", prefix.trim_end_matches(['*', ' ']), if prefix.contains('*') { " */" } else { "*/" }),
+            false => close.to_string(),
+        };
+        return Some(Header {
+            range: start..last_start + last_line.len(),
+            lines: trim_blank(text),
+            style: Style::Block { open: open_line, prefix, close: close_line },
+        });
+    }
+
+    let marker = syntax.line.filter(|marker| trimmed.starts_with(marker))?;
+    let comments: Vec<&(usize, &str)> = lines[first..].iter().take_while(|(_, line)| line.trim_start().starts_with(marker)).collect();
+    let &&(last_start, last_line) = comments.last()?;
+    let text = comments.iter()
+        .map(|(_, line)| {
+            let text = &line.trim_start()[marker.len()..];
+            text.strip_prefix(' ').unwrap_or(text).trim_end().to_string()
+        })
+        .collect();
+    Some(Header { range: start..last_start + last_line.len(), lines: trim_blank(text), style: Style::Lines(marker) })
+}
+
+/// Whether a line is a comment declaring a file's encoding, such as Python's
+/// `# -*- coding: utf-8 -*-`, which has to come first.
+fn is_encoding_comment(line: &str) -> bool {
+    line.trim_start().strip_prefix('#').map(str::trim_start)
+        .is_some_and(|text| text.starts_with("-*-") || text.starts_with("coding") || text.starts_with("vim:"))
+}
+
+/// Drops the blank lines at the start and end of a header's text.
+fn trim_blank(mut lines: Vec<String>) -> Vec<String> {
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+    let blank = lines.iter().take_while(|line| line.is_empty()).count();
+    lines.split_off(blank)
+}
+
+/// Finds the runs of years in some text: years such as `2024`, and lists and
+/// ranges of them such as `2019, 2021-2023` or `2019-present`.
+fn years(text: &str) -> Vec<Range<usize>> {
+    let bytes = text.as_bytes();
+    let year_at = |position: usize| -> Option<usize> {
+        let digits = bytes.get(position..position + 4)?;
+        let bounded = (position == 0 || !bytes[position - 1].is_ascii_digit()) && !bytes.get(position + 4).is_some_and(u8::is_ascii_digit);
+        (bounded && digits.iter().all(u8::is_ascii_digit) && (digits.starts_with(b"19") || digits.starts_with(b"20"))).then_some(position + 4)
+    };
+    let mut runs = Vec::new();
+    let mut position = 0;
+    while position < bytes.len() {
+        let Some(mut end) = year_at(position) else {
+            position += 1;
+            continue;
+        };
+        loop {
+            let rest = &text[end..];
+            let after_separator = rest.trim_start().strip_prefix(['-', ',', '\u{2013}']).map(str::trim_start);
+            let Some(after_separator) = after_separator else {
+                break;
+            };
+            let next = text.len() - after_separator.len();
+            match year_at(next) {
+                Some(next_end) => end = next_end,
+                None if after_separator.starts_with("present") => end = next + "present".len(),
+                None => break,
+            }
+        }
+        runs.push(position..end);
+        position = end;
+    }
+    runs
+}
+
+/// Makes every run of whitespace a single space and every run of years the
+/// same, for comparing headers.
+fn normalize(text: &str) -> String {
+    let mut normalized = String::with_capacity(text.len());
+    let mut last = 0;
+    for run in years(text) {
+        normalized.push_str(&text[last..run.start]);
+        normalized.push_str(YEARS);
+        last = run.end;
+    }
+    normalized.push_str(&text[last..]);
+    normalized.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// The old header to look for and the new one to replace it with
+#[derive(Debug, Clone)]
+pub struct License {
+    /// The old header, normalized
+    old: String,
+    /// The lines of the new header
+    new: Vec<String>,
+}
+
+impl License {
+    /// Reads the old and new headers, as plain text without comment markers.
+    ///
+    /// # Arguments
+    ///
+    /// * `old` - The header to replace
+    /// * `new` - The header to replace it with
+    ///
+    /// # Returns
+    ///
+    /// * `Result<License, String>` - The headers, or an error if the old one
+    ///   is blank
+    pub fn new(old: &str, new: &str) -> Result<License, String> {
+        let old = normalize(old);
+        if old.is_empty() {
+            return Err("The old header is empty".to_string());
+        }
+        let new = trim_blank(new.lines().map(|line| line.trim_end().to_string()).collect());
+        Ok(License { old, new })
+    }
+
+    /// Finds the header of a file to replace, if it has the old one.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - Path of the file, whose extension decides the comment
+    ///   syntax
+    /// * `content` - The file's text
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<(usize, usize, String)>` - Where the header starts and ends and
+    ///   the new header, or nothing if the file doesn't start with the old one
+    pub fn spans(&self, file_path: &Path, content: &str) -> Vec<(usize, usize, String)> {
+        let Some(header) = syntax(file_path).and_then(|syntax| header(content, syntax)) else {
+            return Vec::new();
+        };
+        let text = header.lines.join("\n");
+        if normalize(&text) != self.old {
+            return Vec::new();
+        }
+        let years = years(&text).first().map_or_else(|| current_year().to_string(), |run| text[run.clone()].to_string());
+        let rendered = self.render(&header.style, &years);
+        let rendered = eol::adapt(&rendered, LineEnding::detect(content)).into_owned();
+        match content[header.range.clone()] == rendered {
+            true => Vec::new(),
+            false => vec![(header.range.start, header.range.end, rendered)],
+        }
+    }
+
+    /// Writes the new header as a comment in a style, with `{year}` replaced
+    /// by the years.
+    fn render(&self, style: &Style, years: &str) -> String {
+        let lines = self.new.iter().map(|line| line.replace("{year}", years));
+        match style {
+            Style::Lines(marker) => lines
+                .map(|line| if line.is_empty() { marker.to_string() } else { format!("{} {}", marker, line) })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Style::Block { open, prefix, close } => {
+                let mut rendered = format!("{}\n", open);
+                for line in lines {
+                    rendered.push_str(format!("{}{}", prefix, line).trim_end());
+                    rendered.push('\n');
+                }
+                rendered.push_str(close);
+                rendered
+            }
+        }
+    }
+}
+
+/// The current year, in UTC.
+pub fn current_year() -> u32 {
+    change_log::format_timestamp(SystemTime::now())[..4].parse().unwrap_or(1970)
+}
+
+/// Finds the copyright notices whose years stop before a year, for `fr
+/// bump-year`: the years after `Copyright` or `©`, and any `(c)` after it,
+/// anywhere in a file.
+///
+/// # Arguments
+///
+/// * `content` - The file's text
+/// * `year` - The year to extend the notices to
+///
+/// # Returns
+///
+/// * `Vec<(usize, usize, String)>` - Where the last year of each notice
+///   starts and ends, and what it becomes: the range from it to `year`, or
+///   the range it ends with ending in `year` instead
+pub fn year_spans(content: &str, year: u32) -> Vec<(usize, usize, String)> {
+    let lowercase = content.to_ascii_lowercase();
+    let mut notices: Vec<usize> = lowercase.match_indices("copyright").map(|(start, marker)| start + marker.len())
+        .chain(content.match_indices('©').map(|(start, marker)| start + marker.len()))
+        .map(|mut position| {
+            // The years come after the marker and anything else saying it is
+            // a copyright
+            loop {
+                let rest = &lowercase[position..];
+                let skipped = rest.trim_start_matches([' ', '\t', ':']);
+                let skipped = skipped.strip_prefix("(c)").or_else(|| skipped.strip_prefix('©')).unwrap_or(skipped);
+                if skipped.len() == rest.len() {
+                    return position;
+                }
+                position += rest.len() - skipped.len();
+            }
+        })
+        .collect();
+    notices.sort_unstable();
+    notices.dedup();
+
+    let mut spans = Vec::new();
+    for start in notices {
+        let Some(run) = years(&content[start..]).into_iter().next().filter(|run| run.start == 0) else {
+            continue;
+        };
+        let text = &content[start..start + run.end];
+        let Some(last) = text.get(text.len() - 4..).and_then(|last| last.parse::<u32>().ok()) else {
+            // Ending in present, it is never out of date
+            continue;
+        };
+        if last >= year {
+            continue;
+        }
+        let last_start = start + run.end - 4;
+        let in_range = text[..text.len() - 4].trim_end().ends_with(['-', '\u{2013}']);
+        spans.push(match in_range {
+            true => (last_start, last_start + 4, year.to_string()),
+            false => (last_start, last_start + 4, format!("{}-{}", last, year)),
+        });
+    }
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD: &str = "Copyright 2020 Acme Corp.\nAll rights reserved.\n";
+    const NEW: &str = "Copyright {year} Acme Corp.\nLicensed under the Apache License, Version 2.0.\n";
+
+    fn replace(file_path: &str, content: &str) -> String {
+        let spans = License::new(OLD, NEW).unwrap().spans(Path::new(file_path), content);
+        crate::redact::splice(content, &spans)
+    }
+
+    #[test]
+    fn test_block_headers() {
+        assert_eq!(replace("src/main.c", "/*\n * Copyright 2015-2019 Acme Corp.\n * All rights\n * reserved.\n */\n\nint main;\n"),
+            "/*\n * Copyright 2015-2019 Acme Corp.\n * Licensed under the Apache License, Version 2.0.\n */\n\nint main;\n");
+        assert_eq!(replace("Main.java", "/** Copyright 2021 Acme Corp. All rights reserved. */\r\nclass Main {}\r\n"),
+            "/**\r\n * Copyright 2021 Acme Corp.\r\n * Licensed under the Apache License, Version 2.0.\r\n */\r\nclass Main {}\r\n");
+        assert_eq!(replace("index.html", "<!--\n  Copyright 2020 Acme Corp.\n  All rights reserved.\n-->\n<html>\n"),
+            "<!--\n  Copyright 2020 Acme Corp.\n  Licensed under the Apache License, Version 2.0.\n-->\n<html>\n");
+        // Code after the comment on its last line is left alone, as is a
+        // different header
+        assert_eq!(replace("a.c", "/* Copyright 2020 Acme Corp. All rights reserved. */ int x;\n"),
+            "/* Copyright 2020 Acme Corp. All rights reserved. */ int x;\n");
+        assert_eq!(replace("a.c", "/* Copyright 2020 Other Corp. All rights reserved. */\n"), "/* Copyright 2020 Other Corp. All rights reserved. */\n");
+    }
+
+    #[test]
+    fn test_line_headers() {
+        assert_eq!(replace("run.sh", "#!/bin/sh\n# Copyright 2019, 2022 Acme Corp.\n# All rights reserved.\necho hi # 2020\n"),
+            "#!/bin/sh\n# Copyright 2019, 2022 Acme Corp.\n# Licensed under the Apache License, Version 2.0.\necho hi # 2020\n");
+        assert_eq!(replace("lib.rs", "// Copyright 2020 Acme Corp.\n//\n// All rights reserved.\n\nfn f() {}\n"), "// Copyright 2020 Acme Corp.\n// Licensed under the Apache License, Version 2.0.\n\nfn f() {}\n");
+        // A file with no header, or in a language without known comments, is
+        // left alone
+        assert_eq!(replace("lib.rs", "fn f() {}\n// Copyright 2020 Acme Corp. All rights reserved.\n"),
+            "fn f() {}\n// Copyright 2020 Acme Corp. All rights reserved.\n");
+        assert_eq!(replace("notes.txt", "# Copyright 2020 Acme Corp. All rights reserved.\n"), "# Copyright 2020 Acme Corp. All rights reserved.\n");
+    }
+
+    #[test]
+    fn test_year_spans() {
+        let bump = |content: &str| crate::redact::splice(content, &year_spans(content, 2026));
+        assert_eq!(bump("Copyright (c) 2019-2023 Acme\n// copyright 2020, 2022 Acme\n© 2021 Acme\n"),
+            "Copyright (c) 2019-2026 Acme\n// copyright 2020, 2022-2026 Acme\n© 2021-2026 Acme\n");
+        // Notices already up to date, or without years, and other years are
+        // left alone
+        let content = "Copyright 2019-present Acme\nCopyright 2026 Acme\nCopyright Acme 2020\nReleased in 2020\n";
+        assert_eq!(bump(content), content);
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("  (c) 2019 -\n2023 Acme,\t2024-present  "), "(c) <year> Acme, <year>");
+        assert_eq!(normalize("Version 12345 of 1850"), "Version 12345 of 1850");
+        assert!(License::new(" \n", NEW).is_err());
+    }
+}
@@ -0,0 +1,621 @@
+//! Checking a tree for forbidden terms, for `fr lint`.
+//!
+//! A rules file maps each forbidden term to the term to use instead, in the
+//! `key = "value"` lines of TOML:
+//!
+//! ```toml
+//! [terms]
+//! master = "main"
+//! "sanity check" = "quick check"
+//! ```
+//!
+//! A rule can be limited to some files by giving it as an inline table, with
+//! `include` and `exclude` globs and `types`, the extensions of the files it
+//! applies to:
+//!
+//! ```toml
+//! "DROP TABLE" = { suggestion = "", include = ["migrations/**"] }
+//! print = { suggestion = "logger.info", types = ["py"], exclude = ["scripts/**"] }
+//! ```
+//!
+//! Linting reports every occurrence of a forbidden term with its location,
+//! in a single walk whatever files each rule applies to, and `--fix` replaces
+//! them with their suggestions. A term with an empty suggestion is reported
+//! but never fixed.
+//!
+//! A fix goes through each file once with every rule, so how a big rules file
+//! behaves doesn't depend on how its rules happen to be applied:
+//!
+//! * where matches of several terms overlap, the leftmost one is fixed, then
+//!   the longest of those starting there, then the one of the rule listed
+//!   first, and the others are left
+//! * text put in by a suggestion is never matched again in the same fix, so
+//!   one rule can't undo or extend another's replacement
+//!
+//! `--explain-conflicts` reports each place a match was left because another
+//! rule's won.
+
+use crate::hooks::{Filter, Hooks};
+use crate::eol::{self, LineEnding};
+use crate::{display_path, encoding, find_occurrences, glob_matches, skip_reason, walk, walk_builder, Options, Summary};
+use globset::Glob;
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// A forbidden term and what to use instead
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub term: String,
+    /// What to use instead, or empty if there is nothing to fix it with
+    pub suggestion: String,
+    /// The files the rule applies to
+    pub files: Scope,
+}
+
+/// The files a rule applies to, which are all of them by default
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Scope {
+    /// If not empty, only files whose names or paths match one of these
+    pub include: Vec<Glob>,
+    /// Files whose names or paths match one of these are left out
+    pub exclude: Vec<Glob>,
+    /// If not empty, only files with one of these extensions, lowercased and
+    /// without the leading dot
+    pub types: Vec<String>,
+}
+
+impl Scope {
+    /// Whether the rule applies to every file.
+    pub fn is_everything(&self) -> bool {
+        *self == Scope::default()
+    }
+
+    /// Builds a filter that accepts the files under a directory the rule
+    /// applies to, given by their paths or their paths below it.
+    pub fn filter(&self, starting_directory: &Path) -> Filter {
+        let include = glob_matches(starting_directory, &self.include);
+        let exclude = glob_matches(starting_directory, &self.exclude);
+        let types = self.types.clone();
+        Arc::new(move |path: &Path| {
+            let extension = path.extension().and_then(|extension| extension.to_str()).map(str::to_ascii_lowercase);
+            include.as_ref().is_none_or(|include| include(path))
+                && !exclude.as_ref().is_some_and(|exclude| exclude(path))
+                && (types.is_empty() || extension.is_some_and(|extension| types.contains(&extension)))
+        })
+    }
+
+    /// Whether two rules can't apply to the same file, because they only
+    /// apply to files with different extensions.
+    pub fn is_disjoint(&self, other: &Scope) -> bool {
+        match (self.extensions(), other.extensions()) {
+            (Some(first), Some(second)) => first.is_disjoint(&second),
+            _ => false,
+        }
+    }
+
+    /// The extensions of the only files the rule applies to, from its types
+    /// or from includes that are all like `*.sql`, or None if it isn't
+    /// limited by extension.
+    fn extensions(&self) -> Option<HashSet<String>> {
+        if !self.types.is_empty() {
+            return Some(self.types.iter().cloned().collect());
+        }
+        if self.include.is_empty() {
+            return None;
+        }
+        self.include.iter()
+            .map(|glob| glob.glob().strip_prefix("*.")
+                .filter(|extension| !extension.contains(['*', '?', '[', '{', '/']))
+                .map(str::to_ascii_lowercase))
+            .collect()
+    }
+}
+
+/// The rules a fix applies, with the files each one applies to worked out
+/// once for the whole tree
+pub struct RuleSet {
+    rules: Vec<Rule>,
+    /// Which files each rule applies to, or None for every file
+    scopes: Vec<Option<Filter>>,
+}
+
+impl fmt::Debug for RuleSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RuleSet").field("rules", &self.rules).finish_non_exhaustive()
+    }
+}
+
+/// Where a rule's term matches, by byte offsets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Found {
+    start: usize,
+    end: usize,
+    rule: usize,
+}
+
+impl RuleSet {
+    /// Builds the rules for the tree under a directory.
+    pub fn new(starting_directory: &Path, rules: &[Rule]) -> RuleSet {
+        let scopes = rules.iter()
+            .map(|rule| (!rule.files.is_everything()).then(|| rule.files.filter(starting_directory)))
+            .collect();
+        RuleSet { rules: rules.to_vec(), scopes }
+    }
+
+    /// Whether a rule applies to a file.
+    fn applies(&self, rule: usize, path: &Path) -> bool {
+        self.scopes[rule].as_ref().is_none_or(|scope| scope(path))
+    }
+
+    /// Works out which matches of the rules with suggestions a fix replaces
+    /// in a file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file, to tell which rules apply to it
+    /// * `content` - The file's contents
+    ///
+    /// # Returns
+    ///
+    /// * `(Vec<Found>, Vec<(Found, usize)>)` - The matches that are fixed, in
+    ///   order, and the ones that are left, each with the fixed match it
+    ///   overlaps
+    fn contest(&self, path: &Path, content: &str) -> (Vec<Found>, Vec<(Found, usize)>) {
+        let ending = LineEnding::detect(content);
+        let mut candidates = Vec::new();
+        for (rule, Rule { term, .. }) in self.rules.iter().enumerate() {
+            if self.rules[rule].suggestion.is_empty() || !self.applies(rule, path) {
+                continue;
+            }
+            // Every match, even ones overlapping another of the same term,
+            // since a match that loses can leave room for one that doesn't
+            let term = eol::adapt(term, ending);
+            let mut from = 0;
+            while let Some(offset) = content[from..].find(term.as_ref()) {
+                let start = from + offset;
+                candidates.push(Found { start, end: start + term.len(), rule });
+                from = start + content[start..].chars().next().map_or(1, char::len_utf8);
+            }
+        }
+        // Leftmost, then longest, then listed first
+        candidates.sort_by_key(|found| (found.start, Reverse(found.end), found.rule));
+        let mut fixed: Vec<Found> = Vec::new();
+        let mut left = Vec::new();
+        for found in candidates {
+            match fixed.last() {
+                Some(last) if found.start < last.end => left.push((found, fixed.len() - 1)),
+                _ => fixed.push(found),
+            }
+        }
+        (fixed, left)
+    }
+
+    /// Finds the text a fix replaces in a file and what it is replaced with,
+    /// as spans of the file's contents.
+    pub fn spans(&self, path: &Path, content: &str) -> Vec<(usize, usize, String)> {
+        let ending = LineEnding::detect(content);
+        self.contest(path, content).0.into_iter()
+            .map(|found| (found.start, found.end, eol::adapt(&self.rules[found.rule].suggestion, ending).into_owned()))
+            .collect()
+    }
+}
+
+/// A place where a fix leaves a rule's match because another rule's overlaps
+/// it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    /// The file, relative to the directory that was linted
+    pub path: PathBuf,
+    /// 1-based line number of the match that is left
+    pub line: usize,
+    /// 1-based byte column of the match that is left
+    pub column: usize,
+    /// The rule whose match is fixed
+    pub winner: usize,
+    /// The rule whose match is left
+    pub loser: usize,
+    pub reason: Reason,
+}
+
+/// Why one rule's match is fixed rather than another's
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    /// It starts earlier in the text
+    StartsFirst,
+    /// They start at the same place and it is longer
+    Longer,
+    /// They match the same text and its rule is listed first
+    ListedFirst,
+}
+
+impl Conflict {
+    /// Describes where two rules compete and which one a fix applies.
+    pub fn describe(&self, rules: &[Rule]) -> String {
+        let name = |index: usize| format!("rule {} ({:?})", index + 1, rules[index].term);
+        let reason = match self.reason {
+            Reason::StartsFirst => "which starts first",
+            Reason::Longer => "which is longer",
+            Reason::ListedFirst => "which matches the same text and is listed first",
+        };
+        format!("{}:{}:{}: {} is left, since it overlaps {}, {}", self.path.display(), self.line, self.column,
+            name(self.loser), name(self.winner), reason)
+    }
+}
+
+/// An occurrence of a forbidden term
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// The file, relative to the directory that was linted
+    pub path: PathBuf,
+    /// 1-based line number
+    pub line: usize,
+    /// 1-based byte column
+    pub column: usize,
+    /// Which of the rules was broken
+    pub rule: usize,
+}
+
+/// Reads the rules from a rules file.
+///
+/// # Arguments
+///
+/// * `text` - The contents of the rules file
+///
+/// # Returns
+///
+/// * `Result<Vec<Rule>, String>` - The rules in the order they are listed, or
+///   an error naming the line that isn't a rule
+pub fn parse_rules(text: &str) -> Result<Vec<Rule>, String> {
+    let mut rules = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || (line.starts_with('[') && !line.starts_with("[[")) {
+            continue;
+        }
+        let invalid = || format!("Invalid rule on line {}, expected term = \"suggestion\": {}", number + 1, line);
+        let (term, rest) = match line.chars().next() {
+            Some(quote @ ('"' | '\'')) => string(line, quote).ok_or_else(invalid)?,
+            _ => {
+                let len = line.find(|c: char| !c.is_ascii_alphanumeric() && c != '_' && c != '-').unwrap_or(line.len());
+                (line[..len].to_string(), &line[len..])
+            }
+        };
+        let rest = rest.trim_start().strip_prefix('=').ok_or_else(invalid)?.trim_start();
+        let (suggestion, files, rest) = match rest.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let (suggestion, rest) = string(rest, quote).ok_or_else(invalid)?;
+                (suggestion, Scope::default(), rest)
+            }
+            Some('{') => inline_rule(rest).map_err(|e| format!("Invalid rule on line {}: {}", number + 1, e))?,
+            _ => return Err(invalid()),
+        };
+        let rest = rest.trim_start();
+        if term.is_empty() || !(rest.is_empty() || rest.starts_with('#')) {
+            return Err(invalid());
+        }
+        rules.push(Rule { term, suggestion, files });
+    }
+    if rules.is_empty() {
+        return Err("The rules file has no rules".to_string());
+    }
+    Ok(rules)
+}
+
+/// Reads a rule given as an inline table, such as
+/// `{ suggestion = "main", types = ["md"] }`, returning its suggestion, the
+/// files it applies to and the text after it.
+fn inline_rule(text: &str) -> Result<(String, Scope, &str), String> {
+    let mut rest = text[1..].trim_start();
+    let mut suggestion = None;
+    let mut files = Scope::default();
+    loop {
+        if let Some(after) = rest.strip_prefix('}') {
+            let suggestion = suggestion.ok_or("a rule given as a table needs a suggestion, which can be \"\"")?;
+            return Ok((suggestion, files, after));
+        }
+        let length = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+        let key = &rest[..length];
+        rest = rest[length..].trim_start().strip_prefix('=').ok_or_else(|| format!("expected = after {:?}", key))?.trim_start();
+        let (values, after) = strings(rest).ok_or_else(|| format!("expected a string or an array of strings for {}", key))?;
+        match (key, values) {
+            ("suggestion", Values::One(value)) => suggestion = Some(value),
+            ("include" | "exclude", values) => {
+                let globs = if key == "include" { &mut files.include } else { &mut files.exclude };
+                for value in values.into_vec() {
+                    globs.push(Glob::new(&value).map_err(|e| format!("invalid glob '{}': {}", value, e))?);
+                }
+            }
+            ("types", values) => {
+                files.types.extend(values.into_vec().iter().map(|value| value.trim_start_matches('.').to_ascii_lowercase()));
+            }
+            _ => return Err(format!("unknown key {:?}, expected suggestion, include, exclude or types", key)),
+        }
+        rest = after.trim_start();
+        rest = rest.strip_prefix(',').unwrap_or(rest).trim_start();
+    }
+}
+
+/// A value in an inline table: a string or an array of strings
+enum Values {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Values {
+    fn into_vec(self) -> Vec<String> {
+        match self {
+            Values::One(value) => vec![value],
+            Values::Many(values) => values,
+        }
+    }
+}
+
+/// Reads a string or an array of strings at the start of some text, returning
+/// it and the text after it.
+fn strings(text: &str) -> Option<(Values, &str)> {
+    match text.chars().next()? {
+        quote @ ('"' | '\'') => string(text, quote).map(|(value, rest)| (Values::One(value), rest)),
+        '[' => {
+            let mut values = Vec::new();
+            let mut rest = text[1..].trim_start();
+            loop {
+                if let Some(after) = rest.strip_prefix(']') {
+                    return Some((Values::Many(values), after));
+                }
+                let quote = rest.chars().next().filter(|c| matches!(c, '"' | '\''))?;
+                let (value, after) = string(rest, quote)?;
+                values.push(value);
+                rest = after.trim_start();
+                rest = rest.strip_prefix(',').unwrap_or(rest).trim_start();
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Reads a TOML string at the start of some text, returning it and the text
+/// after it. Basic strings have their escapes decoded; literal strings have
+/// none.
+pub fn string(text: &str, quote: char) -> Option<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = text.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            c if c == quote => return Some((value, &text[i + 1..])),
+            '\\' if quote == '"' => value.push(match chars.next()?.1 {
+                'n' => '\n',
+                't' => '\t',
+                'r' => '\r',
+                '"' => '"',
+                '\\' => '\\',
+                _ => return None,
+            }),
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+/// Finds every occurrence of the forbidden terms in the tree under a
+/// directory, other than in the rules file itself.
+///
+/// # Arguments
+///
+/// * `starting_directory` - Root directory to lint
+/// * `rules` - The forbidden terms
+/// * `rules_path` - The rules file, which is never linted
+/// * `options` - Options that limit which files are read
+///
+/// # Returns
+///
+/// * `io::Result<Vec<Violation>>` - The violations by file, line and column
+pub fn lint(starting_directory: &Path, rules: &[Rule], rules_path: &Path, options: &Options) -> io::Result<Vec<Violation>> {
+    let rule_set = RuleSet::new(starting_directory, rules);
+    let mut violations = Vec::new();
+    each_file(starting_directory, rules_path, options, |path, display, content| {
+        let start = violations.len();
+        for (rule, Rule { term, .. }) in rules.iter().enumerate().filter(|&(rule, _)| rule_set.applies(rule, path)) {
+            for occurrence in find_occurrences(content, term) {
+                violations.push(Violation { path: display.to_path_buf(), line: occurrence.line, column: occurrence.column, rule });
+            }
+        }
+        violations[start..].sort_by_key(|violation| (violation.line, violation.column));
+    });
+    violations.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(violations)
+}
+
+/// Finds every place in the tree under a directory where a fix would leave a
+/// rule's match because another rule's overlaps it.
+///
+/// # Arguments
+///
+/// * `starting_directory` - Root directory to check
+/// * `rules` - The forbidden terms
+/// * `rules_path` - The rules file, which is never checked
+/// * `options` - Options that limit which files are read
+///
+/// # Returns
+///
+/// * `io::Result<Vec<Conflict>>` - The conflicts by file, line and column
+pub fn explain_conflicts(starting_directory: &Path, rules: &[Rule], rules_path: &Path, options: &Options) -> io::Result<Vec<Conflict>> {
+    let rule_set = RuleSet::new(starting_directory, rules);
+    let mut conflicts = Vec::new();
+    each_file(starting_directory, rules_path, options, |path, display, content| {
+        let (fixed, left) = rule_set.contest(path, content);
+        for (found, winner) in left {
+            let winner = fixed[winner];
+            let reason = if winner.start < found.start {
+                Reason::StartsFirst
+            } else if winner.end > found.end {
+                Reason::Longer
+            } else {
+                Reason::ListedFirst
+            };
+            let line_start = content[..found.start].rfind('\n').map_or(0, |i| i + 1);
+            conflicts.push(Conflict {
+                path: display.to_path_buf(),
+                line: content[..found.start].matches('\n').count() + 1,
+                column: found.start - line_start + 1,
+                winner: winner.rule,
+                loser: found.rule,
+                reason,
+            });
+        }
+    });
+    conflicts.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(conflicts)
+}
+
+/// Reads each file under a directory that can be linted, other than the
+/// rules file, passing it to a function with its path relative to the
+/// directory. Files that can't be read are reported and passed over.
+fn each_file(starting_directory: &Path, rules_path: &Path, options: &Options, mut each: impl FnMut(&Path, &Path, &str)) {
+    for entry in walk_builder(&[starting_directory.to_path_buf()], &[not_the_rules(rules_path)]).build() {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        let path = entry.path();
+        if skip_reason(path, options).is_some() {
+            continue;
+        }
+        match encoding::read(path, options.encoding) {
+            Ok((content, _)) => each(path, display_path(starting_directory, path), &content),
+            Err(e) => eprintln!("Error processing {}: {}", path.display(), e),
+        }
+    }
+}
+
+/// Replaces the forbidden terms that have suggestions in the tree under a
+/// directory, other than in the rules file itself.
+///
+/// # Arguments
+///
+/// * `starting_directory` - Root directory to fix
+/// * `rules` - The forbidden terms
+/// * `rules_path` - The rules file, which is never fixed
+/// * `options` - Options controlling what is done with each file
+///
+/// # Returns
+///
+/// * `io::Result<Summary>` - What was fixed
+pub fn fix(starting_directory: &Path, rules: &[Rule], rules_path: &Path, options: &Options) -> io::Result<Summary> {
+    if rules.iter().all(|rule| rule.suggestion.is_empty()) {
+        return Ok(Summary::default());
+    }
+    // The rules are matched instead of a find text, all of them at once
+    let rule_set = Arc::new(RuleSet::new(starting_directory, rules));
+    let options = Options { pager: false, quiet: true, rules: Some(rule_set), ..options.clone() };
+    let hooks = Hooks { filters: vec![not_the_rules(rules_path)], ..Hooks::default() };
+    walk(&[starting_directory.to_path_buf()], "", "", &options, &hooks)
+}
+
+/// A filter that passes over the rules file.
+fn not_the_rules(rules_path: &Path) -> Filter {
+    let rules_path = fs::canonicalize(rules_path).unwrap_or_else(|_| rules_path.to_path_buf());
+    Arc::new(move |path: &Path| path.file_name() != rules_path.file_name() || fs::canonicalize(path).map_or(true, |path| path != rules_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_rules() {
+        let rules = parse_rules("# Terms\n[terms]\nmaster = \"main\"\n\"sanity check\" = 'quick check' # clearer\nblacklist = \"\"\n").unwrap();
+        assert_eq!(rules, vec![
+            Rule { term: "master".to_string(), suggestion: "main".to_string(), files: Scope::default() },
+            Rule { term: "sanity check".to_string(), suggestion: "quick check".to_string(), files: Scope::default() },
+            Rule { term: "blacklist".to_string(), suggestion: String::new(), files: Scope::default() },
+        ]);
+        assert!(parse_rules("master main\n").is_err());
+        assert!(parse_rules("master = \"main\" extra\n").is_err());
+        assert!(parse_rules("# none\n").is_err());
+
+        let rules = parse_rules("print = { suggestion = 'logger.info', types = [\".PY\"], exclude = \"scripts/**\" } # py only\n").unwrap();
+        assert_eq!(rules[0].suggestion, "logger.info");
+        assert_eq!(rules[0].files, Scope { include: Vec::new(), exclude: vec![Glob::new("scripts/**").unwrap()], types: vec!["py".to_string()] });
+        assert!(parse_rules("print = { types = [\"py\"] }\n").is_err());
+        assert!(parse_rules("print = { suggestion = \"log\", paths = [\"a\"] }\n").is_err());
+    }
+
+    #[test]
+    fn test_scope() {
+        let rules = parse_rules("a = { suggestion = \"b\", include = [\"*.sql\"], exclude = [\"old/**\"] }\nc = { suggestion = \"d\", types = [\"py\", \"pyi\"] }\n").unwrap();
+        let (sql, py) = (rules[0].files.filter(Path::new("/repo")), rules[1].files.filter(Path::new("/repo")));
+        assert!(sql(Path::new("/repo/db/schema.sql")));
+        assert!(!sql(Path::new("/repo/old/schema.sql")));
+        assert!(!sql(Path::new("schema.py")));
+        assert!(py(Path::new("tool/main.PY")));
+        assert!(rules[0].files.is_disjoint(&rules[1].files));
+        assert!(!rules[0].files.is_disjoint(&Scope::default()));
+    }
+
+    #[test]
+    fn test_lint_and_fix() {
+        let temp_dir = TempDir::new().unwrap();
+        let rules_path = temp_dir.path().join("terms.toml");
+        fs::write(&rules_path, "master = \"main\"\nblacklist = \"\"\n").unwrap();
+        fs::write(temp_dir.path().join("README.md"), "Clone master.\nNo blacklist, master.\n").unwrap();
+        let rules = parse_rules(&fs::read_to_string(&rules_path).unwrap()).unwrap();
+
+        let violations = lint(temp_dir.path(), &rules, &rules_path, &Options::default()).unwrap();
+        let found: Vec<(usize, usize, usize)> = violations.iter().map(|violation| (violation.line, violation.column, violation.rule)).collect();
+        assert_eq!(found, vec![(1, 7, 0), (2, 4, 1), (2, 15, 0)]);
+        assert!(violations.iter().all(|violation| violation.path == Path::new("README.md")));
+
+        let options = Options { quiet: true, ..Options::default() };
+        let summary = fix(temp_dir.path(), &rules, &rules_path, &options).unwrap();
+        assert_eq!(summary.modified.len(), 1);
+        assert_eq!(fs::read_to_string(temp_dir.path().join("README.md")).unwrap(), "Clone main.\nNo blacklist, main.\n");
+        assert_eq!(fs::read_to_string(&rules_path).unwrap(), "master = \"main\"\nblacklist = \"\"\n");
+
+        // Rules that only apply to some files are reported and fixed in those
+        fs::write(temp_dir.path().join("notes.txt"), "master\n").unwrap();
+        let rules = parse_rules("master = { suggestion = \"main\", types = [\"md\"] }\n").unwrap();
+        fs::write(temp_dir.path().join("README.md"), "Clone master.\n").unwrap();
+        let violations = lint(temp_dir.path(), &rules, &rules_path, &Options::default()).unwrap();
+        assert_eq!(violations.iter().map(|violation| violation.path.as_path()).collect::<Vec<_>>(), [Path::new("README.md")]);
+        fix(temp_dir.path(), &rules, &rules_path, &options).unwrap();
+        assert_eq!(fs::read_to_string(temp_dir.path().join("README.md")).unwrap(), "Clone main.\n");
+        assert_eq!(fs::read_to_string(temp_dir.path().join("notes.txt")).unwrap(), "master\n");
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let rules = parse_rules("master = \"main\"\n\"master branch\" = \"main branch\"\n\"branch name\" = \"ref\"\nbranch = \"line\"\nmain = \"master\"\nmaster = \"primary\"\n").unwrap();
+        let rule_set = RuleSet::new(Path::new("/repo"), &rules);
+        // The longest match wins over the rule listed first, the leftmost over
+        // a longer one starting later, and suggestions aren't matched again
+        let content = "master branch name, master, branch name, main";
+        let spans = rule_set.spans(Path::new("/repo/a.md"), content);
+        assert_eq!(crate::redact::splice(content, &spans), "main branch name, main, ref, master");
+
+        let temp_dir = TempDir::new().unwrap();
+        let rules_path = temp_dir.path().join("terms.toml");
+        fs::write(&rules_path, "").unwrap();
+        fs::write(temp_dir.path().join("a.md"), "x\nmaster branch name\n").unwrap();
+        let conflicts = explain_conflicts(temp_dir.path(), &rules, &rules_path, &Options::default()).unwrap();
+        let found: Vec<(usize, usize, usize, usize, Reason)> = conflicts.iter()
+            .map(|conflict| (conflict.line, conflict.column, conflict.winner, conflict.loser, conflict.reason))
+            .collect();
+        assert_eq!(found, vec![
+            (2, 1, 1, 0, Reason::Longer),
+            (2, 1, 1, 5, Reason::Longer),
+            (2, 8, 1, 2, Reason::StartsFirst),
+            (2, 8, 1, 3, Reason::StartsFirst),
+        ]);
+        assert_eq!(conflicts[2].describe(&rules),
+            "a.md:2:8: rule 3 (\"branch name\") is left, since it overlaps rule 2 (\"master branch\"), which starts first");
+
+        fs::write(temp_dir.path().join("a.md"), "master\n").unwrap();
+        let conflicts = explain_conflicts(temp_dir.path(), &rules, &rules_path, &Options::default()).unwrap();
+        assert_eq!((conflicts[0].winner, conflicts[0].loser, conflicts[0].reason), (0, 5, Reason::ListedFirst));
+    }
+}
@@ -0,0 +1,78 @@
+//! Paths longer than Windows' 260 character limit.
+//!
+//! Most Windows file APIs refuse a path of `MAX_PATH` characters or more
+//! unless it is written in extended-length form, `\\?\C:\...` or
+//! `\\?\UNC\server\share\...`, which deep trees such as `node_modules` easily
+//! need. Files are read, written and renamed through that form when their
+//! path is too long, while what is shown and matched against globs stays the
+//! path as walked.
+
+use std::borrow::Cow;
+use std::path::Path;
+#[cfg(windows)]
+use std::path::PathBuf;
+
+/// The longest path Windows accepts without the extended-length prefix,
+/// counting the NUL it ends with
+#[cfg(windows)]
+const MAX_PATH: usize = 260;
+
+/// Returns the path to read and write a file through: in extended-length form
+/// on Windows if it is too long to use otherwise, and the path itself
+/// elsewhere.
+///
+/// # Arguments
+///
+/// * `path` - The file's path, as walked
+///
+/// # Returns
+///
+/// * `Cow<Path>` - The path to open the file with
+pub fn for_io(path: &Path) -> Cow<'_, Path> {
+    #[cfg(windows)]
+    if path.as_os_str().len() >= MAX_PATH - 1 {
+        if let Ok(absolute) = std::path::absolute(path) {
+            if let Some(extended) = extended(&absolute.to_string_lossy()) {
+                return Cow::Owned(PathBuf::from(extended));
+            }
+        }
+    }
+    Cow::Borrowed(path)
+}
+
+/// Writes an absolute Windows path in extended-length form, or returns None
+/// if it already is in that form or isn't absolute. Extended-length paths are
+/// passed to the filesystem as they are, so they must have no `.` or `..`
+/// components and only backslashes.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn extended(absolute: &str) -> Option<String> {
+    let path = absolute.replace('/', "\\");
+    if path.starts_with("\\\\?\\") || path.starts_with("\\\\.\\") {
+        None
+    } else if let Some(share) = path.strip_prefix("\\\\") {
+        Some(format!("\\\\?\\UNC\\{}", share))
+    } else if path.as_bytes().get(1..3) == Some(b":\\") {
+        Some(format!("\\\\?\\{}", path))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extended() {
+        assert_eq!(extended("C:\\src\\app\\node_modules\\a.js").as_deref(), Some("\\\\?\\C:\\src\\app\\node_modules\\a.js"));
+        assert_eq!(extended("C:/src/a.js").as_deref(), Some("\\\\?\\C:\\src\\a.js"));
+        assert_eq!(extended("\\\\server\\share\\a.js").as_deref(), Some("\\\\?\\UNC\\server\\share\\a.js"));
+        assert_eq!(extended("\\\\?\\C:\\a.js"), None);
+        assert_eq!(extended("src\\a.js"), None);
+    }
+
+    #[test]
+    fn test_for_io() {
+        assert_eq!(for_io(Path::new("src/main.rs")), Path::new("src/main.rs"));
+    }
+}
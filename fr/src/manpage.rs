@@ -0,0 +1,155 @@
+//! The man page, for `fr --generate-manpage`.
+//!
+//! Like the completion scripts, the page is written from the help message, so
+//! it documents exactly the flags and commands `fr --help` does. It adds the
+//! environment variables and files fr reads, which the help message only
+//! mentions in passing.
+
+use crate::completions;
+
+/// Environment variables fr reads, and what for
+const ENVIRONMENT: [(&str, &str); 5] = [
+    ("FR_OPTS", "Flags to put before those on the command line, split into words the way a shell would"),
+    ("FR_CONFIG", "A configuration file to read instead of ~/.config/fr/config.toml"),
+    ("FR_LOG", "The level to log at when --log-level isn't given"),
+    ("NO_COLOR", "When set, output isn't colored unless --color always is given"),
+    ("PAGER", "The pager --diff and --dry-run output is sent through"),
+];
+
+/// Files fr reads, and what for
+const FILES: [(&str, &str); 3] = [
+    ("~/.config/fr/config.toml", "Default flags for every run, as settings such as threads = 4, with [profile.NAME] sections for --profile. Under $XDG_CONFIG_HOME if it is set"),
+    (".fr.toml", "Default flags for a project, at the root of its repository"),
+    (".gitignore, .ignore", "Patterns of files to skip"),
+];
+
+/// Writes the man page.
+///
+/// # Arguments
+///
+/// * `help` - The help message, which the page is written from
+/// * `version` - The version of fr, for the page's footer
+///
+/// # Returns
+///
+/// * `String` - The page, in roff
+pub fn render(help: &str, version: &str) -> String {
+    let mut page = format!(".TH FR 1 \"\" \"fr {}\" \"User Commands\"\n", version);
+    let mut lines = help.lines();
+    let (name, summary) = lines.next().and_then(|title| title.split_once(" - ")).unwrap_or(("fr", ""));
+    page.push_str(&format!(".SH NAME\n{} \\- {}\n", name, escape(summary)));
+
+    let mut section = "";
+    let mut body: Vec<&str> = Vec::new();
+    for line in lines.chain([""]) {
+        if line.trim_end().ends_with(':') && !line.starts_with(' ') {
+            page.push_str(&render_section(section, &body, help));
+            section = line.trim_end();
+            body.clear();
+        } else if !line.trim().is_empty() {
+            body.push(line);
+        }
+    }
+    page.push_str(&render_section(section, &body, help));
+
+    page.push_str(".SH ENVIRONMENT\n");
+    for (name, description) in ENVIRONMENT {
+        page.push_str(&format!(".TP\n\\fB{}\\fR\n{}.\n", name, escape(description)));
+    }
+    page.push_str(".SH FILES\n");
+    for (name, description) in FILES {
+        page.push_str(&format!(".TP\n\\fI{}\\fR\n{}.\n", escape(name), escape(description)));
+    }
+    page
+}
+
+/// Writes a section of the page from the lines of a section of the help
+/// message.
+fn render_section(section: &str, body: &[&str], help: &str) -> String {
+    let mut text = String::new();
+    match section {
+        "" => {}
+        "Usage:" => {
+            text.push_str(".SH SYNOPSIS\n.nf\n");
+            for line in body {
+                text.push_str(&usage(line.trim_start_matches(['-', ' '])));
+                text.push('\n');
+            }
+            text.push_str(".fi\n");
+        }
+        "Options:" | "Commands:" => {
+            text.push_str(&format!(".SH {}\n", section.trim_end_matches(':').to_uppercase()));
+            for (_, usage_text, description) in completions::entries(help).into_iter().filter(|&(entry_section, _, _)| entry_section == section) {
+                text.push_str(&format!(".TP\n{}\n{}\n", usage(usage_text), escape(&description)));
+            }
+        }
+        "Example:" => {
+            text.push_str(".SH EXAMPLES\n.nf\n");
+            for line in body {
+                text.push_str(&escape(line.trim_start()));
+                text.push('\n');
+            }
+            text.push_str(".fi\n");
+        }
+        "Note:" => {
+            text.push_str(".SH NOTES\n");
+            for line in body {
+                text.push_str(&format!(".IP \\(bu 2\n{}\n", escape(line.trim_start().trim_start_matches("- "))));
+            }
+        }
+        section => {
+            text.push_str(&format!(".SH {}\n", section.trim_end_matches(':').to_uppercase()));
+            let paragraph: Vec<&str> = body.iter().map(|line| line.trim()).collect();
+            text.push_str(&escape(&paragraph.join(" ")));
+            text.push('\n');
+        }
+    }
+    text
+}
+
+/// Formats a usage line with flags and commands in bold and placeholders in
+/// italics.
+fn usage(line: &str) -> String {
+    let words: Vec<String> = line.split(' ').map(|word| match word.trim_start_matches('[') {
+        bare if bare.starts_with('-') || bare == "fr" => format!("\\fB{}\\fR", escape(word)),
+        bare if bare.starts_with('<') => format!("\\fI{}\\fR", escape(word)),
+        _ => escape(word),
+    }).collect();
+    words.join(" ")
+}
+
+/// Escapes text for roff: backslashes and minus signs, and a full stop or
+/// apostrophe at the start of a line, which would start a request.
+fn escape(text: &str) -> String {
+    let escaped = text.replace('\\', "\\e").replace('-', "\\-");
+    match escaped.starts_with(['.', '\'']) {
+        true => format!("\\&{}", escaped),
+        false => escaped,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HELP: &str = "fr - A simple find-replace tool\n\nUsage: \n- fr [options] <find_text> <replace_text>\n\nOptions:\n    -c, --count         Print the number of matches\n                        without modifying anything\n\nCommands:\n    fr index            Index the files\n\nDescription:\n    fr recursively finds and\n    replaces text.\n\nExample:\n    fr \"old\" \"new\"    # Replace\n\nNote:\n    - Text matching is literal\n";
+
+    #[test]
+    fn test_render() {
+        let page = render(HELP, "1.2.3");
+        assert!(page.starts_with(".TH FR 1 \"\" \"fr 1.2.3\" \"User Commands\"\n.SH NAME\nfr \\- A simple find\\-replace tool\n"));
+        assert!(page.contains(".SH SYNOPSIS\n.nf\n\\fBfr\\fR [options] \\fI<find_text>\\fR \\fI<replace_text>\\fR\n.fi\n"));
+        assert!(page.contains(".SH OPTIONS\n.TP\n\\fB\\-c,\\fR \\fB\\-\\-count\\fR\nPrint the number of matches without modifying anything\n"));
+        assert!(page.contains(".SH COMMANDS\n.TP\n\\fBfr\\fR index\nIndex the files\n"));
+        assert!(page.contains(".SH DESCRIPTION\nfr recursively finds and replaces text.\n"));
+        assert!(page.contains(".SH EXAMPLES\n.nf\nfr \"old\" \"new\"    # Replace\n.fi\n"));
+        assert!(page.contains(".SH NOTES\n.IP \\(bu 2\nText matching is literal\n"));
+        assert!(page.contains(".TP\n\\fBFR_OPTS\\fR\n"));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape(".fr.toml"), "\\&.fr.toml");
+        assert_eq!(escape("a\\b-c"), "a\\eb\\-c");
+    }
+}
@@ -0,0 +1,353 @@
+//! Replacing only in the link destinations of Markdown files, for
+//! `--md-links` and `--check-links`.
+//!
+//! Destinations are those of inline links and images, `[text](url)` and
+//! `![alt](url)`, of reference definitions, `[label]: url`, and of autolinks,
+//! `<https://...>`. Code spans and fenced code blocks are never searched. With
+//! `--check-links`, a relative destination is only rewritten if the file it
+//! points to once rewritten exists, so moving a docs directory can't leave
+//! links that lead nowhere.
+//!
+//! `--front-matter` limits replacements to the YAML front matter at the top of
+//! a Markdown file, between `---` lines, or to the rest of the file.
+
+use std::path::Path;
+
+/// Which side of a Markdown file's front matter replacements are limited to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrontMatter {
+    /// The front matter, and nothing in files without any
+    Inside,
+    /// Everything after the front matter, or the whole file without any
+    Outside,
+}
+
+impl FrontMatter {
+    /// Parses the value given to `--front-matter`.
+    pub fn parse(value: &str) -> Result<FrontMatter, String> {
+        match value {
+            "inside" => Ok(FrontMatter::Inside),
+            "outside" => Ok(FrontMatter::Outside),
+            _ => Err(format!("Unknown front matter side '{}', expected one of: inside, outside", value)),
+        }
+    }
+}
+
+/// Returns whether a file is Markdown, going by its extension, or is stdin.
+pub fn is_markdown(file_path: &Path) -> bool {
+    file_path == Path::new("-")
+        || (file_path.extension().and_then(|extension| extension.to_str()))
+            .is_some_and(|extension| ["md", "markdown", "mdx"].iter().any(|known| extension.eq_ignore_ascii_case(known)))
+}
+
+/// Where a destination starts and ends, and whether it is in angle brackets
+type Destination = (usize, usize, bool);
+
+/// Keeps the matches that lie wholly inside a link destination, where the
+/// replacement can go as it is.
+///
+/// # Arguments
+///
+/// * `file_path` - Path to the file, which relative destinations are from
+/// * `content` - The Markdown file
+/// * `offsets` - Where each match of the find text starts
+/// * `find_len` - Length of the find text in bytes
+/// * `replace_text` - Text each match would be replaced with
+/// * `check` - Whether to leave relative destinations alone, with a warning,
+///   when what they would point to doesn't exist
+///
+/// # Returns
+///
+/// * `Vec<usize>` - The offsets of the matches to replace
+pub fn link_offsets(file_path: &Path, content: &str, offsets: &[usize], find_len: usize, replace_text: &str, check: bool) -> Vec<usize> {
+    let mut kept = Vec::new();
+    let mut offsets = offsets.iter().copied().peekable();
+    for (start, end, angled) in destinations(content) {
+        while offsets.next_if(|&offset| offset < start).is_some() {}
+        let mut inside = Vec::new();
+        while let Some(offset) = offsets.next_if(|&offset| offset + find_len <= end) {
+            inside.push(offset);
+        }
+        let forbidden: &[char] = if angled { &['<', '>', '\n'] } else { &[' ', '\t', '\n', '(', ')', '<'] };
+        if inside.is_empty() || replace_text.contains(forbidden) {
+            continue;
+        }
+        if check {
+            let destination = &content[start..end];
+            let rewritten = crate::splice(destination, &inside.iter().map(|offset| offset - start).collect::<Vec<_>>(), find_len, replace_text);
+            if !target_exists(file_path, &rewritten) {
+                eprintln!("{}: leaving the link to {} alone, as {} doesn't exist", file_path.display(), destination, rewritten);
+                continue;
+            }
+        }
+        kept.extend(inside);
+    }
+    kept
+}
+
+/// Keeps the matches on the chosen side of the front matter.
+///
+/// # Arguments
+///
+/// * `content` - The Markdown file
+/// * `offsets` - Where each match of the find text starts
+/// * `find_len` - Length of the find text in bytes
+/// * `side` - Whether to keep the matches inside or outside the front matter
+///
+/// # Returns
+///
+/// * `Vec<usize>` - The offsets of the matches to replace
+pub fn front_matter_offsets(content: &str, offsets: &[usize], find_len: usize, side: FrontMatter) -> Vec<usize> {
+    let (start, end) = front_matter(content).unwrap_or((0, 0));
+    offsets.iter().copied().filter(|&offset| match side {
+        FrontMatter::Inside => offset >= start && offset + find_len <= end,
+        FrontMatter::Outside => offset >= end,
+    }).collect()
+}
+
+/// Finds where the text of the front matter starts and ends: the lines
+/// between a `---` on the first line and the next `---` or `...` line.
+fn front_matter(content: &str) -> Option<(usize, usize)> {
+    let first = content.strip_prefix('\u{feff}').unwrap_or(content);
+    let opening = first.split_inclusive('\n').next()?;
+    if opening.trim_end() != "---" {
+        return None;
+    }
+    let start = content.len() - first.len() + opening.len();
+    let mut position = start;
+    for line in content[start..].split_inclusive('\n') {
+        if matches!(line.trim_end(), "---" | "...") {
+            return Some((start, position));
+        }
+        position += line.len();
+    }
+    None
+}
+
+/// Whether a relative destination points to a file or directory that exists.
+/// Anything else, such as a URL, an anchor in the same file or a path from
+/// the root of a site, can't be checked and counts as existing.
+fn target_exists(file_path: &Path, destination: &str) -> bool {
+    let path = destination.split(['#', '?']).next().unwrap_or_default();
+    let scheme = path.split_once(':').is_some_and(|(scheme, _)| {
+        scheme.starts_with(|c: char| c.is_ascii_alphabetic()) && scheme.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+    });
+    if path.is_empty() || path.starts_with('/') || scheme {
+        return true;
+    }
+    let directory = file_path.parent().unwrap_or(Path::new(""));
+    directory.join(percent_decode(path)).exists()
+}
+
+/// Decodes the `%XX` escapes in a destination, such as `%20` for a space.
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut position = 0;
+    while position < bytes.len() {
+        let hex = text.get(position + 1..position + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[position], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                position += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                position += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Finds the link destinations in a file, in order.
+fn destinations(content: &str) -> Vec<Destination> {
+    let mut found = Vec::new();
+    let mut fence: Option<&str> = None;
+    let mut line_start = 0;
+    for line in content.split_inclusive('\n') {
+        let start = line_start;
+        line_start += line.len();
+        let trimmed = line.trim_start_matches(' ');
+        let indent = line.len() - trimmed.len();
+        if indent < 4 {
+            let marker = ["```", "~~~"].into_iter().find(|marker| trimmed.starts_with(marker));
+            match (fence, marker) {
+                (None, Some(marker)) => {
+                    fence = Some(marker);
+                    continue;
+                }
+                (Some(open), Some(marker)) if open == marker && trimmed.trim_end().trim_start_matches(marker.as_bytes()[0] as char).is_empty() => {
+                    fence = None;
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        if fence.is_some() {
+            continue;
+        }
+        if let Some(destination) = reference_definition(trimmed) {
+            let offset = start + indent;
+            found.push((offset + destination.0, offset + destination.1, destination.2));
+            continue;
+        }
+        inline_destinations(line, start, &mut found);
+    }
+    found
+}
+
+/// Reads the destination of a reference definition such as `[label]: url`.
+fn reference_definition(line: &str) -> Option<Destination> {
+    let label_end = line.strip_prefix('[')?.find("]:")? + 1;
+    if line[1..label_end].is_empty() || line[1..label_end].contains('[') {
+        return None;
+    }
+    let after = &line[label_end + 2..];
+    let skipped = label_end + 2 + after.len() - after.trim_start_matches([' ', '\t']).len();
+    destination_at(line, skipped)
+}
+
+/// Reads a destination starting at a position in a line, in angle brackets or
+/// up to whitespace or an unbalanced `)`.
+fn destination_at(line: &str, start: usize) -> Option<Destination> {
+    let rest = &line[start..];
+    if let Some(angled) = rest.strip_prefix('<') {
+        let len = angled.find(['>', '\n'])?;
+        return (angled.as_bytes()[len] == b'>').then_some((start + 1, start + 1 + len, true));
+    }
+    let mut depth = 0usize;
+    let mut len = 0;
+    for (i, c) in rest.char_indices() {
+        match c {
+            ' ' | '\t' | '\n' | '\r' => break,
+            '(' => depth += 1,
+            ')' if depth == 0 => break,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        len = i + c.len_utf8();
+    }
+    (len > 0).then_some((start, start + len, false))
+}
+
+/// Finds the destinations of the inline links, images and autolinks in a
+/// line, outside code spans.
+fn inline_destinations(line: &str, line_start: usize, found: &mut Vec<Destination>) {
+    let bytes = line.as_bytes();
+    let mut position = 0;
+    while position < bytes.len() {
+        match bytes[position] {
+            b'`' => {
+                // A code span ends at a run of as many backticks
+                let run = line[position..].len() - line[position..].trim_start_matches('`').len();
+                let ticks = &line[position..position + run];
+                position += run;
+                if let Some(len) = line[position..].find(ticks) {
+                    position += len + run;
+                }
+            }
+            b'\\' => position += 2,
+            b']' if bytes.get(position + 1) == Some(&b'(') => {
+                let after = &line[position + 2..];
+                let skipped = position + 2 + after.len() - after.trim_start_matches([' ', '\t']).len();
+                match destination_at(line, skipped) {
+                    Some((start, end, angled)) => {
+                        found.push((line_start + start, line_start + end, angled));
+                        position = end;
+                    }
+                    None => position += 2,
+                }
+            }
+            b'<' => {
+                let rest = &line[position + 1..];
+                let scheme_len = rest.find(|c: char| !c.is_ascii_alphanumeric() && !matches!(c, '+' | '-' | '.')).unwrap_or(0);
+                let autolink = scheme_len >= 2 && rest[scheme_len..].starts_with(':')
+                    && rest.find('>').filter(|&end| !rest[..end].contains([' ', '<', '\n'])).is_some();
+                if autolink {
+                    let end = rest.find('>').unwrap();
+                    found.push((line_start + position + 1, line_start + position + 1 + end, true));
+                    position += end + 2;
+                } else {
+                    position += 1;
+                }
+            }
+            _ => position += 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{match_offsets, splice};
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn replace(file_path: &Path, content: &str, find_text: &str, replace_text: &str, check: bool) -> String {
+        let offsets = link_offsets(file_path, content, &match_offsets(content, find_text), find_text.len(), replace_text, check);
+        splice(content, &offsets, find_text.len(), replace_text)
+    }
+
+    const README: &str = "\
+# The guide/ folder
+
+See [the guide](guide/intro.md \"guide/\") and ![logo](<guide/logo 1.png>).
+`[not](guide/code.md)` and <https://example.com/guide/>.
+
+```markdown
+[fenced](guide/fenced.md)
+```
+
+[ref]: guide/ref.md#usage
+";
+
+    #[test]
+    fn test_link_offsets() {
+        let path = Path::new("README.md");
+        assert_eq!(replace(path, README, "guide/", "docs/guide/", false), README
+            .replace("(guide/intro.md", "(docs/guide/intro.md")
+            .replace("<guide/logo", "<docs/guide/logo")
+            .replace("com/guide/>", "com/docs/guide/>")
+            .replace("]: guide/ref", "]: docs/guide/ref"));
+        // A replacement with a space can only go in angle brackets
+        assert_eq!(replace(path, README, "logo 1", "logo 2", false), README.replace("logo 1", "logo 2"));
+        assert_eq!(replace(path, README, "intro", "the intro", false), README);
+    }
+
+    #[test]
+    fn test_link_offsets_check() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir_all(temp_dir.path().join("docs/guide")).unwrap();
+        fs::write(temp_dir.path().join("docs/guide/intro.md"), "").unwrap();
+        fs::write(temp_dir.path().join("docs/guide/logo 1.png"), "").unwrap();
+
+        let path = temp_dir.path().join("README.md");
+        assert_eq!(replace(&path, README, "guide/", "docs/guide/", true), README
+            .replace("(guide/intro.md", "(docs/guide/intro.md")
+            .replace("<guide/logo", "<docs/guide/logo")
+            .replace("com/guide/>", "com/docs/guide/>"));
+        assert_eq!(percent_decode("a%20b%2"), "a b%2");
+    }
+
+    #[test]
+    fn test_front_matter_offsets() {
+        let post = "---\nauthor: Ann\ntags: [Ann]\n---\nBy Ann\n";
+        let replace = |content: &str, side| {
+            let offsets = front_matter_offsets(content, &match_offsets(content, "Ann"), 3, side);
+            splice(content, &offsets, 3, "Bo")
+        };
+        assert_eq!(replace(post, FrontMatter::Inside), "---\nauthor: Bo\ntags: [Bo]\n---\nBy Ann\n");
+        assert_eq!(replace(post, FrontMatter::Outside), "---\nauthor: Ann\ntags: [Ann]\n---\nBy Bo\n");
+        // Without a closing line there is no front matter
+        assert_eq!(replace("---\nBy Ann\n", FrontMatter::Inside), "---\nBy Ann\n");
+        assert_eq!(replace("---\nBy Ann\n", FrontMatter::Outside), "---\nBy Bo\n");
+        assert!(FrontMatter::parse("above").is_err());
+    }
+
+    #[test]
+    fn test_is_markdown() {
+        assert!(is_markdown(Path::new("docs/README.MD")));
+        assert!(!is_markdown(Path::new("docs/index.html")));
+    }
+}
@@ -0,0 +1,269 @@
+//! Replacing only in parts of HTML and XML files, for `--xml-scope`.
+//!
+//! A match is kept if it is inside one of the chosen parts: text between tags,
+//! the values of an attribute, or the text inside an element, and never in a
+//! tag name or markup. The parser is
+//! forgiving in the way browsers are, so unclosed and stray tags, unquoted
+//! attributes and void elements such as `<br>` don't stop it, and the
+//! contents of `<script>` and `<style>` are read as raw text rather than tags.
+
+use std::path::Path;
+
+/// A part of an HTML or XML file that replacements are limited to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum XmlScope {
+    /// Text between tags, outside scripts, styles and comments
+    Text,
+    /// The values of attributes with this name
+    Attr(String),
+    /// Text inside elements with this name, however deeply nested
+    Tag(String),
+}
+
+impl XmlScope {
+    /// Parses the value given to `--xml-scope`.
+    pub fn parse(value: &str) -> Result<XmlScope, String> {
+        match value.split_once(':') {
+            None if value == "text" => Ok(XmlScope::Text),
+            Some(("attr", name)) if !name.is_empty() => Ok(XmlScope::Attr(name.to_string())),
+            Some(("tag", name)) if !name.is_empty() => Ok(XmlScope::Tag(name.to_string())),
+            _ => Err(format!("Unknown XML scope '{}', expected one of: text, attr:NAME, tag:NAME", value)),
+        }
+    }
+}
+
+/// Returns whether a file is HTML or XML, going by its extension, or is stdin.
+pub fn is_markup(file_path: &Path) -> bool {
+    file_path == Path::new("-")
+        || (file_path.extension().and_then(|extension| extension.to_str()))
+            .is_some_and(|extension| ["html", "htm", "xhtml", "xml", "svg"].iter().any(|known| extension.eq_ignore_ascii_case(known)))
+}
+
+/// Elements that never have content or a closing tag in HTML
+const VOID_ELEMENTS: [&str; 14] = ["area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source", "track", "wbr"];
+
+/// Elements whose content is raw text, not markup
+const RAW_TEXT_ELEMENTS: [&str; 2] = ["script", "style"];
+
+/// Where a part starts and ends, and what a replacement in it can't contain
+type Part = (usize, usize, &'static [char]);
+
+/// Keeps the matches that lie wholly inside one of the parts of the file the
+/// scopes pick out, where the replacement can go as it is.
+///
+/// # Arguments
+///
+/// * `content` - The HTML or XML file
+/// * `offsets` - Where each match of the find text starts
+/// * `find_len` - Length of the find text in bytes
+/// * `replace_text` - Text each match would be replaced with
+/// * `scopes` - Parts of the file replacements are limited to
+///
+/// # Returns
+///
+/// * `Vec<usize>` - The offsets of the matches to replace
+pub fn scope_offsets(content: &str, offsets: &[usize], find_len: usize, replace_text: &str, scopes: &[XmlScope]) -> Vec<usize> {
+    let parts = parts(content, scopes);
+    let mut parts = parts.iter().peekable();
+    offsets.iter().copied().filter(|&offset| {
+        while parts.next_if(|&&(_, end, _)| end < offset + find_len).is_some() {}
+        parts.peek().is_some_and(|&&(start, end, forbidden)| {
+            offset >= start && offset + find_len <= end && !replace_text.contains(forbidden)
+                && !in_reference(&content[start..end], offset - start) && !in_reference(&content[start..end], offset + find_len - start)
+        })
+    }).collect()
+}
+
+/// Whether an offset in a part falls inside a character reference such as
+/// `&amp;`, which a match mustn't split.
+fn in_reference(part: &str, offset: usize) -> bool {
+    let before = &part[..offset];
+    let Some(amp) = before.rfind('&').filter(|&amp| offset - amp <= 32) else {
+        return false;
+    };
+    let name = &before[amp + 1..];
+    name.chars().all(|c| c.is_ascii_alphanumeric() || c == '#') && part[offset..].split(';').next()
+        .is_some_and(|rest| rest.len() < part.len() - offset && rest.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+/// Finds the parts of the file the scopes pick out.
+fn parts(content: &str, scopes: &[XmlScope]) -> Vec<Part> {
+    let mut parts = Vec::new();
+    // The elements that are open at the current position
+    let mut open: Vec<&str> = Vec::new();
+    let in_tag = |open: &[&str]| scopes.iter().any(|scope| matches!(scope, XmlScope::Tag(name) if open.iter().any(|tag| tag.eq_ignore_ascii_case(name))));
+    let add_text = |parts: &mut Vec<Part>, open: &[&str], start: usize, end: usize, raw: bool| {
+        if start < end && ((!raw && scopes.contains(&XmlScope::Text)) || in_tag(open)) {
+            parts.push((start, end, &['<'][..]));
+        }
+    };
+    let mut position = 0;
+    while position < content.len() {
+        let Some(offset) = content[position..].find('<') else {
+            add_text(&mut parts, &open, position, content.len(), false);
+            break;
+        };
+        let tag_start = position + offset;
+        add_text(&mut parts, &open, position, tag_start, false);
+        let rest = &content[tag_start..];
+        let skip_to = |end: &str| rest.find(end).map_or(content.len(), |len| tag_start + len + end.len());
+
+        if rest.starts_with("<!--") {
+            position = skip_to("-->");
+        } else if let Some(cdata) = rest.strip_prefix("<![CDATA[") {
+            let len = cdata.find("]]>").unwrap_or(cdata.len());
+            add_text(&mut parts, &open, tag_start + 9, tag_start + 9 + len, false);
+            position = (tag_start + 9 + len + 3).min(content.len());
+        } else if rest.starts_with("<!") || rest.starts_with("<?") {
+            position = skip_to(">");
+        } else if let Some(closing) = rest.strip_prefix("</") {
+            let name = &closing[..closing.find(|c: char| c.is_whitespace() || c == '>').unwrap_or(closing.len())];
+            // A stray closing tag is ignored, and one that closes an outer
+            // element closes the ones left open inside it
+            if let Some(at) = open.iter().rposition(|tag| tag.eq_ignore_ascii_case(name)) {
+                open.truncate(at);
+            }
+            position = skip_to(">");
+        } else {
+            let name_len = rest[1..].find(|c: char| c.is_whitespace() || c == '/' || c == '>').unwrap_or(rest.len() - 1);
+            let name = &rest[1..1 + name_len];
+            if !name.starts_with(|c: char| c.is_alphabetic()) {
+                // A < that doesn't start a tag is text
+                add_text(&mut parts, &open, tag_start, tag_start + 1, false);
+                position = tag_start + 1;
+                continue;
+            }
+            let (tag_len, self_closing) = attributes(content, tag_start + 1 + name_len, scopes, &mut parts);
+            position = tag_start + tag_len;
+            if self_closing || VOID_ELEMENTS.iter().any(|void| void.eq_ignore_ascii_case(name)) {
+                continue;
+            }
+            open.push(name);
+            if RAW_TEXT_ELEMENTS.iter().any(|raw| raw.eq_ignore_ascii_case(name)) {
+                let closing = format!("</{}", name.to_ascii_lowercase());
+                let len = content[position..].to_ascii_lowercase().find(&closing).unwrap_or(content.len() - position);
+                add_text(&mut parts, &open, position, position + len, true);
+                position += len;
+            }
+        }
+    }
+    parts
+}
+
+/// Reads the attributes of a tag from just after its name, adding the values
+/// the scopes pick out, and returns the length of the tag from its `<` and
+/// whether it closes itself.
+fn attributes(content: &str, start: usize, scopes: &[XmlScope], parts: &mut Vec<Part>) -> (usize, bool) {
+    let tag_start = content[..start].rfind('<').unwrap();
+    let bytes = content.as_bytes();
+    let mut position = start;
+    loop {
+        while bytes.get(position).is_some_and(u8::is_ascii_whitespace) {
+            position += 1;
+        }
+        match bytes.get(position) {
+            None => return (content.len() - tag_start, false),
+            Some(b'>') => return (position + 1 - tag_start, false),
+            Some(b'/') if bytes.get(position + 1) == Some(&b'>') => return (position + 2 - tag_start, true),
+            _ => {}
+        }
+        let name_start = position;
+        while bytes.get(position).is_some_and(|&byte| !byte.is_ascii_whitespace() && !matches!(byte, b'=' | b'>' | b'/')) {
+            position += 1;
+        }
+        if position == name_start {
+            // A stray /
+            position += 1;
+            continue;
+        }
+        let name = &content[name_start..position];
+        while bytes.get(position).is_some_and(u8::is_ascii_whitespace) {
+            position += 1;
+        }
+        if bytes.get(position) != Some(&b'=') {
+            continue;
+        }
+        position += 1;
+        while bytes.get(position).is_some_and(u8::is_ascii_whitespace) {
+            position += 1;
+        }
+        let (value_start, value_end, forbidden): (usize, usize, &'static [char]) = match bytes.get(position) {
+            Some(b'"') => {
+                let end = content[position + 1..].find('"').map_or(content.len(), |len| position + 1 + len);
+                (position + 1, end, &['"', '<'])
+            }
+            Some(b'\'') => {
+                let end = content[position + 1..].find('\'').map_or(content.len(), |len| position + 1 + len);
+                (position + 1, end, &['\'', '<'])
+            }
+            _ => {
+                let end = content[position..].find(|c: char| c.is_whitespace() || c == '>').map_or(content.len(), |len| position + len);
+                (position, end, &[' ', '\t', '\n', '\r', '>', '"', '\'', '<', '='])
+            }
+        };
+        if scopes.iter().any(|scope| matches!(scope, XmlScope::Attr(attr) if attr.eq_ignore_ascii_case(name))) {
+            parts.push((value_start, value_end, forbidden));
+        }
+        position = if value_end < content.len() && value_start > position { value_end + 1 } else { value_end };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{match_offsets, splice};
+
+    fn replace(content: &str, find_text: &str, replace_text: &str, scopes: &[&str]) -> String {
+        let scopes: Vec<XmlScope> = scopes.iter().map(|scope| XmlScope::parse(scope).unwrap()).collect();
+        let offsets = scope_offsets(content, &match_offsets(content, find_text), find_text.len(), replace_text, &scopes);
+        splice(content, &offsets, find_text.len(), replace_text)
+    }
+
+    const PAGE: &str = r#"<!DOCTYPE html>
+<html><head><title>example.com</title>
+<script>if (a <b) location = "example.com"</script></head>
+<body class=example.com>
+<!-- example.com -->
+<p>Visit <a href="https://example.com/" data-x='example.com'>example.com<br>now</a> &amp; example.com
+<img src=https://example.com/logo.png alt="example.com">
+</div></body></html>
+"#;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(XmlScope::parse("text").unwrap(), XmlScope::Text);
+        assert_eq!(XmlScope::parse("attr:href").unwrap(), XmlScope::Attr("href".to_string()));
+        assert_eq!(XmlScope::parse("tag:title").unwrap(), XmlScope::Tag("title".to_string()));
+        assert!(XmlScope::parse("attr:").is_err());
+        assert!(XmlScope::parse("node").is_err());
+    }
+
+    #[test]
+    fn test_scope_offsets() {
+        assert_eq!(replace(PAGE, "example.com", "example.org", &["attr:href", "attr:SRC"]), PAGE
+            .replace("https://example.com/\"", "https://example.org/\"")
+            .replace("https://example.com/logo", "https://example.org/logo"));
+
+        assert_eq!(replace(PAGE, "example.com", "example.org", &["text"]), PAGE
+            .replace("<title>example.com", "<title>example.org")
+            .replace("'>example.com<br>", "'>example.org<br>")
+            .replace("&amp; example.com", "&amp; example.org"));
+
+        assert_eq!(replace(PAGE, "example.com", "example.org", &["tag:a", "tag:script"]), PAGE
+            .replace("= \"example.com\"", "= \"example.org\"")
+            .replace("'>example.com<br>", "'>example.org<br>"));
+
+        // References aren't split, and replacements that would end the part
+        // are left out
+        assert_eq!(replace(PAGE, "&amp", "&", &["text"]), PAGE);
+        assert_eq!(replace(PAGE, "example.com", "a<b", &["text"]), PAGE);
+        assert_eq!(replace(PAGE, "example.com", "a b", &["attr:class"]), PAGE);
+    }
+
+    #[test]
+    fn test_is_markup() {
+        assert!(is_markup(Path::new("site/index.HTML")));
+        assert!(is_markup(Path::new("icons/logo.svg")));
+        assert!(!is_markup(Path::new("app.js")));
+    }
+}
@@ -0,0 +1,101 @@
+//! Bounding how much file content is held in memory at once, for `--max-memory`.
+//!
+//! Every file read into memory reserves an estimate of what it will take up
+//! from a shared budget, and holds the reservation until it has been written.
+//! Threads wait for enough of the budget to be free before reading a file, and
+//! files too large for the budget are streamed when possible.
+
+use std::sync::{Arc, Condvar, Mutex};
+
+/// Estimates the memory taken up by a file of a given size while it is
+/// rewritten: its original contents, the rewritten contents, and the rewritten
+/// contents encoded to be written.
+pub fn footprint(size: u64) -> u64 {
+    size.saturating_mul(3)
+}
+
+/// A number of bytes shared by every thread
+#[derive(Debug)]
+pub struct MemoryBudget {
+    limit: u64,
+    in_use: Mutex<u64>,
+    released: Condvar,
+}
+
+impl MemoryBudget {
+    /// Creates a budget of `limit` bytes.
+    pub fn new(limit: u64) -> MemoryBudget {
+        MemoryBudget { limit, in_use: Mutex::new(0), released: Condvar::new() }
+    }
+
+    /// Reserves part of the budget, waiting until enough of it is free. A
+    /// reservation larger than the whole budget waits for all of it, so that
+    /// such a file is processed on its own.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - How many bytes to reserve
+    ///
+    /// # Returns
+    ///
+    /// * `Reservation` - The reservation, which is released when it is dropped
+    pub fn reserve(self: &Arc<Self>, bytes: u64) -> Reservation {
+        let bytes = bytes.min(self.limit);
+        let mut in_use = self.in_use.lock().unwrap();
+        while *in_use + bytes > self.limit {
+            in_use = self.released.wait(in_use).unwrap();
+        }
+        *in_use += bytes;
+        Reservation { budget: Arc::clone(self), bytes }
+    }
+}
+
+/// Part of a memory budget, held while a file is in memory
+#[derive(Debug)]
+pub struct Reservation {
+    budget: Arc<MemoryBudget>,
+    bytes: u64,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        *self.budget.in_use.lock().unwrap() -= self.bytes;
+        self.budget.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_reserve_waits_for_release() {
+        let budget = Arc::new(MemoryBudget::new(100));
+        let first = budget.reserve(60);
+        let (sender, receiver) = mpsc::channel();
+        let waiting = {
+            let budget = Arc::clone(&budget);
+            thread::spawn(move || {
+                let _second = budget.reserve(60);
+                sender.send(()).unwrap();
+            })
+        };
+        assert!(receiver.recv_timeout(Duration::from_millis(50)).is_err(), "Should wait for the first reservation");
+        drop(first);
+        receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+        waiting.join().unwrap();
+        assert_eq!(*budget.in_use.lock().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_reserve_more_than_the_budget() {
+        let budget = Arc::new(MemoryBudget::new(100));
+        let reservation = budget.reserve(1000);
+        assert_eq!(*budget.in_use.lock().unwrap(), 100);
+        drop(reservation);
+        assert_eq!(*budget.in_use.lock().unwrap(), 0);
+    }
+}
@@ -0,0 +1,179 @@
+//! Moving a module, for `fr mv`: replacing its path in Rust `use`
+//! declarations and paths, and its specifier in JavaScript and TypeScript
+//! imports.
+//!
+//! A match is kept if it is the module's path where code refers to a module,
+//! so strings, comments and longer names that happen to contain it are left
+//! alone. In Rust, the
+//! path has to start and end with whole identifiers, as `old::module` does in
+//! `crate::old::module::Thing` but not in `bold::module_two` or
+//! `other::old::module`. In JavaScript and TypeScript, it has to be the whole
+//! specifier of an `import`, `export ... from`, `require()` or `import()`, or
+//! the start of one followed by a `/`, as `lodash` is of `lodash/fp`.
+
+use crate::rust::{self, Kind};
+use std::ops::Range;
+use std::path::Path;
+
+/// Returns whether a file is JavaScript or TypeScript, going by its extension.
+pub fn is_script(file_path: &Path) -> bool {
+    file_path.extension().and_then(|extension| extension.to_str())
+        .is_some_and(|extension| matches!(extension, "js" | "jsx" | "mjs" | "cjs" | "ts" | "tsx" | "mts" | "cts"))
+}
+
+/// Whether text is a Rust path, identifiers joined by `::`.
+pub fn is_rust_path(text: &str) -> bool {
+    text.split("::").all(rust::is_ident)
+}
+
+/// Keeps the matches that are the path of the module in a file of a language
+/// `fr mv` knows, and none in other files.
+///
+/// # Arguments
+///
+/// * `file_path` - Path of the file, whose extension decides the language
+/// * `content` - The file's text
+/// * `offsets` - Where each match of the path starts
+/// * `find_len` - Length of the path in bytes
+///
+/// # Returns
+///
+/// * `Vec<usize>` - The offsets of the matches to replace
+pub fn path_offsets(file_path: &Path, content: &str, offsets: &[usize], find_len: usize) -> Vec<usize> {
+    let Some(&first) = offsets.first() else {
+        return Vec::new();
+    };
+    if rust::is_rust(file_path) && is_rust_path(&content[first..first + find_len]) {
+        rust_offsets(content, offsets, find_len)
+    } else if is_script(file_path) {
+        specifier_offsets(content, offsets, find_len)
+    } else {
+        Vec::new()
+    }
+}
+
+/// Keeps the matches that are whole paths in Rust code.
+fn rust_offsets(content: &str, offsets: &[usize], find_len: usize) -> Vec<usize> {
+    let tokens = rust::tokens(content);
+    let text = |index: usize| &content[tokens[index].range.clone()];
+    offsets.iter().copied().filter(|&offset| {
+        // The match starts a token and ends one, so it is code rather than a
+        // string or comment, and its identifiers are whole
+        let first = tokens.partition_point(|token| token.range.start < offset);
+        let last = tokens.partition_point(|token| token.range.end < offset + find_len);
+        let whole = tokens.get(first).is_some_and(|token| token.range.start == offset && token.kind == Kind::Ident)
+            && tokens.get(last).is_some_and(|token| token.range.end == offset + find_len && token.kind == Kind::Ident);
+        // Going on from another path names another module, unless that is
+        // the crate, this module or its parent
+        let qualified = first > 0 && text(first - 1) == "::";
+        whole && (!qualified || first == 1 || matches!(text(first - 2), "crate" | "self" | "super" | "use")
+            || tokens[first - 2].kind != Kind::Ident)
+    }).collect()
+}
+
+/// Keeps the matches that are a whole import specifier or the start of one
+/// followed by a `/`.
+fn specifier_offsets(content: &str, offsets: &[usize], find_len: usize) -> Vec<usize> {
+    let specifiers = specifiers(content);
+    offsets.iter().copied().filter(|&offset| {
+        specifiers.iter().any(|specifier| specifier.start == offset
+            && (specifier.end == offset + find_len || content.as_bytes().get(offset + find_len) == Some(&b'/')))
+    }).collect()
+}
+
+/// Finds the specifiers of the imports in JavaScript or TypeScript, skipping
+/// comments, template literals and other strings.
+///
+/// # Returns
+///
+/// * `Vec<Range<usize>>` - The bytes of each specifier, without its quotes
+fn specifiers(content: &str) -> Vec<Range<usize>> {
+    let bytes = content.as_bytes();
+    let mut specifiers = Vec::new();
+    // The last two words or punctuation characters before the current one
+    let (mut last, mut before_last) = ("", "");
+    let mut position = 0;
+    while position < bytes.len() {
+        let rest = &content[position..];
+        let start = position;
+        let byte = bytes[position];
+        if byte.is_ascii_whitespace() {
+            position += 1;
+            continue;
+        } else if rest.starts_with("//") {
+            position += rest.find('\n').unwrap_or(rest.len());
+            continue;
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            position += comment.find("*/").map_or(rest.len(), |end| end + 4);
+            continue;
+        } else if matches!(byte, b'\'' | b'"' | b'`') {
+            position = string_end(bytes, position + 1, byte);
+            let is_specifier = last == "from" || last == "import" || (last == "(" && matches!(before_last, "require" | "import"));
+            if byte != b'`' && is_specifier {
+                specifiers.push(start + 1..position.saturating_sub(1).max(start + 1));
+            }
+            (before_last, last) = (last, "\"");
+            continue;
+        } else if byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'$' {
+            position += rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_' && c != '$').unwrap_or(rest.len());
+        } else {
+            position += rest.chars().next().map_or(1, char::len_utf8);
+        }
+        (before_last, last) = (last, &content[start..position]);
+    }
+    specifiers
+}
+
+/// Where a string that starts at `start`, after its opening quote, ends, just
+/// past its closing quote.
+fn string_end(bytes: &[u8], start: usize, quote: u8) -> usize {
+    let mut position = start;
+    while position < bytes.len() {
+        match bytes[position] {
+            b'\\' => position += 2,
+            byte if byte == quote => return position + 1,
+            _ => position += 1,
+        }
+    }
+    bytes.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{match_offsets, splice};
+
+    fn replace(file_path: &str, content: &str, find_text: &str, replace_text: &str) -> String {
+        let offsets = path_offsets(Path::new(file_path), content, &match_offsets(content, find_text), find_text.len());
+        splice(content, &offsets, find_text.len(), replace_text)
+    }
+
+    #[test]
+    fn test_rust() {
+        let source = "use crate::old::module::{Thing, Other};\nuse bold::module;\nuse other::old::module;\n\
+            // old::module moved\nfn f() { old::module::run(\"old::module\"); old::module_two(); }\n";
+        assert_eq!(replace("src/lib.rs", source, "old::module", "new::place"),
+            "use crate::new::place::{Thing, Other};\nuse bold::module;\nuse other::old::module;\n\
+            // old::module moved\nfn f() { new::place::run(\"old::module\"); old::module_two(); }\n");
+        // Only files of the languages fr mv knows are changed
+        assert_eq!(replace("notes.txt", "use old::module;", "old::module", "new::place"), "use old::module;");
+        assert_eq!(replace("src/lib.rs", "use old::module;", "old/module", "new/place"), "use old::module;");
+    }
+
+    #[test]
+    fn test_scripts() {
+        let source = "import { a } from './util';\nimport './util/setup';\nimport x from './utility';\n\
+            const b = require(\"./util\");\nconst c = await import('./util');\nconst d = './util';\n\
+            export * from './util'; // './util'\nconst e = `${'./util'}`;\n";
+        assert_eq!(replace("src/app.ts", source, "./util", "./lib/util"),
+            "import { a } from './lib/util';\nimport './lib/util/setup';\nimport x from './utility';\n\
+            const b = require(\"./lib/util\");\nconst c = await import('./lib/util');\nconst d = './util';\n\
+            export * from './lib/util'; // './util'\nconst e = `${'./util'}`;\n");
+    }
+
+    #[test]
+    fn test_is_script() {
+        assert!(is_script(Path::new("src/App.tsx")) && is_script(Path::new("index.mjs")));
+        assert!(!is_script(Path::new("style.css")));
+    }
+}
@@ -0,0 +1,243 @@
+//! Finding and replacing in the text of `.docx`, `.xlsx` and `.pptx`
+//! documents with `--office`.
+//!
+//! These are zip archives of XML parts, repacked as `--archives` repacks a zip.
+//! Only the text of a part is searched, never its markup, and text is matched
+//! a paragraph at a time: Word and PowerPoint split a paragraph into runs
+//! wherever the formatting, spell checking or revision history changes, so a
+//! word can be spread across several text elements. A match that spans runs
+//! is replaced in the run it starts in and removed from the others, which keeps
+//! the formatting of the start of the match.
+
+use crate::match_offsets;
+
+/// Elements whose content is document text: paragraphs of Word documents
+/// and of PowerPoint and chart drawings, and shared or inline spreadsheet
+/// strings
+const TEXT_ELEMENTS: [&str; 3] = ["w:t", "a:t", "t"];
+
+/// Elements that end a stretch of text a match can span: paragraphs, string
+/// items, tabs and line breaks
+const BREAKS: [&str; 8] = ["w:p", "a:p", "si", "is", "w:tab", "w:br", "w:cr", "a:br"];
+
+/// Returns whether a member of an office document is a part that holds its
+/// text, rather than its relationships, styles or metadata.
+pub fn is_text_part(name: &str) -> bool {
+    name.ends_with(".xml") && ["word/", "xl/", "ppt/"].iter().any(|folder| name.starts_with(folder))
+}
+
+/// A text element in a part
+struct Run {
+    /// Where its opening tag starts and ends
+    tag: (usize, usize),
+    /// Where its content starts and ends
+    content: (usize, usize),
+    /// Where its unescaped text starts in the stretch it belongs to
+    start: usize,
+}
+
+/// Finds and replaces text in an XML part of an office document.
+///
+/// # Arguments
+///
+/// * `xml` - The part
+/// * `find_text` - Text to find, which must not be empty
+/// * `replace_text` - Text to replace the found text with
+///
+/// # Returns
+///
+/// * `Option<(usize, String)>` - The number of replacements and the new part,
+///   or None if the find text isn't in its text
+pub fn rewrite_part(xml: &str, find_text: &str, replace_text: &str) -> Option<(usize, String)> {
+    let mut edits = Vec::new();
+    let mut replacements = 0;
+    let mut runs = Vec::new();
+    let mut text = String::new();
+    let mut position = 0;
+    while let Some(offset) = xml[position..].find('<') {
+        let tag_start = position + offset;
+        let rest = &xml[tag_start..];
+        let Some(tag_len) = tag_len(rest) else {
+            break;
+        };
+        let tag_end = tag_start + tag_len;
+        position = tag_end;
+        if rest.starts_with("<?") || rest.starts_with("<!") {
+            continue;
+        }
+        let closing = rest.starts_with("</");
+        let name_start = if closing { 2 } else { 1 };
+        let name_len = rest[name_start..].find(|c: char| c.is_whitespace() || c == '/' || c == '>').unwrap_or(0);
+        let name = &rest[name_start..name_start + name_len];
+        let self_closing = xml[..tag_end].ends_with("/>");
+
+        if BREAKS.contains(&name) && (closing || self_closing) {
+            replacements += replace_in(xml, &runs, &text, find_text, replace_text, &mut edits);
+            runs.clear();
+            text.clear();
+        } else if TEXT_ELEMENTS.contains(&name) && !closing && !self_closing {
+            let content_end = xml[tag_end..].find('<').map_or(xml.len(), |len| tag_end + len);
+            runs.push(Run { tag: (tag_start, tag_end), content: (tag_end, content_end), start: text.len() });
+            unescape_into(&xml[tag_end..content_end], &mut text);
+            position = content_end;
+        }
+    }
+    replacements += replace_in(xml, &runs, &text, find_text, replace_text, &mut edits);
+    if replacements == 0 {
+        return None;
+    }
+
+    let mut rewritten = String::with_capacity(xml.len());
+    let mut copied = 0;
+    for (start, end, replacement) in edits {
+        rewritten.push_str(&xml[copied..start]);
+        rewritten.push_str(&replacement);
+        copied = end;
+    }
+    rewritten.push_str(&xml[copied..]);
+    Some((replacements, rewritten))
+}
+
+/// The length of the tag at the start of the text, up to and including its
+/// `>`, skipping over quoted attribute values, comments and CDATA.
+fn tag_len(rest: &str) -> Option<usize> {
+    for (start, end) in [("<!--", "-->"), ("<![CDATA[", "]]>"), ("<?", "?>")] {
+        if rest.starts_with(start) {
+            return rest.find(end).map(|len| len + end.len());
+        }
+    }
+    let mut quote = None;
+    for (i, c) in rest.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), _) if open == c => quote = None,
+            (None, '>') => return Some(i + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Replaces the find text in a stretch of runs, adding an edit for every run
+/// whose text changes.
+///
+/// # Returns
+///
+/// * `usize` - The number of replacements
+fn replace_in(xml: &str, runs: &[Run], text: &str, find_text: &str, replace_text: &str, edits: &mut Vec<(usize, usize, String)>) -> usize {
+    let offsets = match_offsets(text, find_text);
+    if offsets.is_empty() {
+        return 0;
+    }
+    for (i, run) in runs.iter().enumerate() {
+        let (start, end) = (run.start, runs.get(i + 1).map_or(text.len(), |next| next.start));
+        let mut new_text = String::new();
+        let mut copied = start;
+        for &offset in &offsets {
+            if offset + find_text.len() <= start {
+                continue;
+            }
+            if offset >= end {
+                break;
+            }
+            if offset >= start {
+                new_text.push_str(&text[copied..offset]);
+                new_text.push_str(replace_text);
+            }
+            copied = (offset + find_text.len()).min(end);
+        }
+        new_text.push_str(&text[copied..end]);
+        if new_text == text[start..end] {
+            continue;
+        }
+
+        // Word drops the spaces at either end of a run unless told otherwise
+        let tag = &xml[run.tag.0..run.tag.1];
+        let padded = new_text.starts_with(char::is_whitespace) || new_text.ends_with(char::is_whitespace);
+        if padded && !tag.contains("xml:space") {
+            let name_end = tag.find(|c: char| c.is_whitespace() || c == '>').unwrap();
+            edits.push((run.tag.0 + name_end, run.tag.0 + name_end, " xml:space=\"preserve\"".to_string()));
+        }
+        edits.push((run.content.0, run.content.1, escape(&new_text)));
+    }
+    offsets.len()
+}
+
+/// Appends the text of escaped XML content, keeping any reference it doesn't
+/// know as it is.
+fn unescape_into(content: &str, text: &mut String) {
+    let mut rest = content;
+    while let Some(amp) = rest.find('&') {
+        text.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let reference = rest.find(';').map(|semicolon| &rest[1..semicolon]);
+        let c = reference.and_then(|reference| match reference {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => match reference.strip_prefix("#x").or_else(|| reference.strip_prefix("#X")) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+                None => reference.strip_prefix('#').and_then(|decimal| decimal.parse().ok()).and_then(char::from_u32),
+            },
+        });
+        match (c, reference) {
+            (Some(c), Some(reference)) => {
+                text.push(c);
+                rest = &rest[reference.len() + 2..];
+            }
+            _ => {
+                text.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    text.push_str(rest);
+}
+
+/// Escapes text as XML content.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_text_part() {
+        assert!(is_text_part("word/document.xml"));
+        assert!(is_text_part("xl/sharedStrings.xml"));
+        assert!(is_text_part("ppt/slides/slide1.xml"));
+        assert!(!is_text_part("[Content_Types].xml"));
+        assert!(!is_text_part("word/media/image1.png"));
+    }
+
+    #[test]
+    fn test_rewrite_part() {
+        // A word split across runs by spell checking, in two paragraphs
+        let xml = concat!(
+            r#"<?xml version="1.0"?><w:document><w:body>"#,
+            r#"<w:p><w:r><w:t>Acme</w:t></w:r><w:proofErr w:type="spellStart"/><w:r><w:rPr><w:b/></w:rPr><w:t>Co</w:t></w:r></w:p>"#,
+            r#"<w:p><w:r><w:t xml:space="preserve">Acme &amp; AcmeCo</w:t></w:r></w:p>"#,
+            r#"</w:body></w:document>"#,
+        );
+        let (replacements, rewritten) = rewrite_part(xml, "AcmeCo", "Globex <Inc>").unwrap();
+        assert_eq!(replacements, 2);
+        assert_eq!(rewritten, concat!(
+            r#"<?xml version="1.0"?><w:document><w:body>"#,
+            r#"<w:p><w:r><w:t>Globex &lt;Inc&gt;</w:t></w:r><w:proofErr w:type="spellStart"/><w:r><w:rPr><w:b/></w:rPr><w:t></w:t></w:r></w:p>"#,
+            r#"<w:p><w:r><w:t xml:space="preserve">Acme &amp; Globex &lt;Inc&gt;</w:t></w:r></w:p>"#,
+            r#"</w:body></w:document>"#,
+        ));
+
+        // Paragraphs, markup and attributes are never matched
+        assert!(rewrite_part(xml, "AcmeAcme", "").is_none());
+        assert!(rewrite_part(xml, "w:t", "").is_none());
+        assert!(rewrite_part(xml, "spellStart", "").is_none());
+
+        let (_, rewritten) = rewrite_part("<si><t>Hello</t></si>", "Hello", "Hello ").unwrap();
+        assert_eq!(rewritten, r#"<si><t xml:space="preserve">Hello </t></si>"#);
+    }
+}
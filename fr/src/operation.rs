@@ -0,0 +1,155 @@
+//! Saving a run to replay later, for `--save-op` and `fr run`.
+//!
+//! An operation file holds the arguments of a run, with the flags from the
+//! configuration files and `FR_OPTS` already in them, so the run can be
+//! checked in next to the code it changes and replayed the same way on any
+//! checkout, whatever configuration the machine replaying it has:
+//!
+//! ```json
+//! {
+//!   "fr": "1.0.3",
+//!   "args": [
+//!     "--exclude",
+//!     "*.lock",
+//!     "old_name",
+//!     "new_name"
+//!   ]
+//! }
+//! ```
+
+use crate::json::{self, Value};
+use crate::write_atomically;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Takes `--save-op <file>` out of the arguments.
+///
+/// # Arguments
+///
+/// * `args` - The command line arguments, which are left without the flag
+///
+/// # Returns
+///
+/// * `Result<Option<PathBuf>, String>` - The file to save the operation to,
+///   if one was given, or an error if the flag has no value
+pub fn take_save_op(args: &mut Vec<String>) -> Result<Option<PathBuf>, String> {
+    // Everything after -- is text to find and replace, not flags
+    let end = args.iter().position(|arg| arg == "--").unwrap_or(args.len());
+    let Some(index) = args[..end].iter().position(|arg| arg == "--save-op" || arg.starts_with("--save-op=")) else {
+        return Ok(None);
+    };
+    let flag = args.remove(index);
+    let path = match flag.strip_prefix("--save-op=") {
+        Some(path) => path.to_string(),
+        None if index + 1 < end => args.remove(index),
+        None => return Err("--save-op expects a file to save the operation to".to_string()),
+    };
+    Ok(Some(PathBuf::from(path)))
+}
+
+/// Writes the arguments of a run as an operation file.
+pub fn encode(args: &[String]) -> String {
+    let mut text = format!("{{\n  \"fr\": {},\n  \"args\": [\n", json::string(env!("CARGO_PKG_VERSION")));
+    for (i, arg) in args.iter().enumerate() {
+        text.push_str(&format!("    {}{}\n", json::string(arg), if i + 1 == args.len() { "" } else { "," }));
+    }
+    text.push_str("  ]\n}\n");
+    text
+}
+
+/// Reads the arguments of a run from an operation file.
+pub fn decode(text: &str) -> Result<Vec<String>, String> {
+    let value = Value::parse(text)?;
+    let Some(Value::Array(args)) = value.get("args") else {
+        return Err("isn't an operation file, which has an \"args\" array".to_string());
+    };
+    args.iter()
+        .map(|arg| arg.as_str().map(String::from).ok_or_else(|| format!("has an argument that isn't a string: {}", arg)))
+        .collect()
+}
+
+/// Saves the arguments of a run to an operation file.
+pub fn save(path: &Path, args: &[String]) -> io::Result<()> {
+    write_atomically(path, encode(args).as_bytes())
+}
+
+/// Replaces `fr run <file>` with the arguments saved in the file, adding any
+/// flags given after it, such as `--dry-run`.
+///
+/// # Arguments
+///
+/// * `args` - The command line arguments, starting with the program name
+///
+/// # Returns
+///
+/// * `Result<Option<Vec<String>>, String>` - The arguments to run, or None if
+///   this isn't `fr run`, or an error if the file can't be read
+pub fn replay(args: &[String]) -> Result<Option<Vec<String>>, String> {
+    // Replacing the word run takes two arguments too, but not ones naming a
+    // file
+    let [program, command, path, rest @ ..] = args else {
+        return Ok(None);
+    };
+    if command != "run" || !Path::new(path).is_file() {
+        return Ok(None);
+    }
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let mut saved = decode(&text).map_err(|e| format!("{} {}", path, e))?;
+    // The flags given go before any -- in the saved arguments, so they aren't
+    // taken for text to find and replace. The operation file has the find
+    // text in it, so is left alone.
+    let end = saved.iter().position(|arg| arg == "--").unwrap_or(saved.len());
+    saved.splice(end..end, ["--forbid".to_string(), path.clone()].into_iter().chain(rest.iter().cloned()));
+    Ok(Some(std::iter::once(program.clone()).chain(saved).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_take_save_op() {
+        let mut args = strings(&["fr", "--save-op", "op.json", "--exclude", "*.lock", "old", "new"]);
+        assert_eq!(take_save_op(&mut args), Ok(Some(PathBuf::from("op.json"))));
+        assert_eq!(args, strings(&["fr", "--exclude", "*.lock", "old", "new"]));
+
+        let mut args = strings(&["fr", "--save-op=op.json", "old", "new"]);
+        assert_eq!(take_save_op(&mut args), Ok(Some(PathBuf::from("op.json"))));
+        let mut args = strings(&["fr", "--", "--save-op", "new"]);
+        assert_eq!(take_save_op(&mut args), Ok(None));
+        assert!(take_save_op(&mut strings(&["fr", "--save-op"])).is_err());
+    }
+
+    #[test]
+    fn test_encode_and_decode() {
+        let args = strings(&["--exclude", "*.lock", "say \"hi\"\n", "new"]);
+        assert_eq!(decode(&encode(&args)), Ok(args));
+        assert_eq!(decode("{\"args\": []}"), Ok(Vec::new()));
+        assert!(decode("{\"args\": [1]}").is_err());
+        assert!(decode("[]").is_err());
+    }
+
+    #[test]
+    fn test_replay() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("op.json");
+        save(&path, &strings(&["--exclude", "*.lock", "old", "new"])).unwrap();
+        let path = path.to_str().unwrap();
+        assert_eq!(replay(&strings(&["fr", "run", path, "--dry-run"])),
+            Ok(Some(strings(&["fr", "--exclude", "*.lock", "old", "new", "--forbid", path, "--dry-run"]))));
+        save(Path::new(path), &strings(&["--", "-old", "-new"])).unwrap();
+        assert_eq!(replay(&strings(&["fr", "run", path, "--dry-run"])),
+            Ok(Some(strings(&["fr", "--forbid", path, "--dry-run", "--", "-old", "-new"]))));
+        // Replacing run with text that isn't a file is a usual run
+        assert_eq!(replay(&strings(&["fr", "run", "walk"])), Ok(None));
+
+        fs::write(path, "not json").unwrap();
+        assert!(replay(&strings(&["fr", "run", path])).is_err());
+    }
+}
@@ -0,0 +1,102 @@
+//! Sends previews through a pager, the way git does, when they're shown on a terminal.
+
+use std::env;
+use std::io;
+use std::io::{IsTerminal, Write};
+use std::process::{Child, Command, Stdio};
+use std::sync::Mutex;
+
+/// Output that is written to stdout, or to a pager when stdout is a terminal
+pub struct Pager {
+    /// Where output goes, or None once the pager has quit
+    writer: Mutex<Option<Box<dyn Write + Send>>>,
+    child: Option<Child>,
+}
+
+/// Works out which pager to run from the value of `$PAGER`, returning None if
+/// output shouldn't be paged.
+fn pager_command(pager: Option<&str>) -> Option<Vec<String>> {
+    let pager = pager.unwrap_or("less");
+    let words: Vec<String> = pager.split_whitespace().map(String::from).collect();
+    match words.first().map(String::as_str) {
+        None | Some("cat") => None,
+        Some(_) => Some(words),
+    }
+}
+
+impl Pager {
+    /// Starts the pager if paging is wanted and stdout is a terminal, falling
+    /// back to stdout if the pager can't be run.
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - False if paging was turned off with `--no-pager`
+    pub fn start(enabled: bool) -> Pager {
+        let stdout = || Pager { writer: Mutex::new(Some(Box::new(io::stdout()))), child: None };
+        if !enabled || !io::stdout().is_terminal() {
+            return stdout();
+        }
+        let pager = env::var("PAGER").ok();
+        let Some(words) = pager_command(pager.as_deref()) else {
+            return stdout();
+        };
+
+        let mut command = Command::new(&words[0]);
+        command.args(&words[1..]).stdin(Stdio::piped());
+        // Like git: quit if everything fits on one screen, pass colors through,
+        // and leave the output on the screen afterwards
+        if env::var_os("LESS").is_none() {
+            command.env("LESS", "FRX");
+        }
+        match command.spawn() {
+            Ok(mut child) => {
+                let stdin = child.stdin.take().map(|stdin| Box::new(stdin) as Box<dyn Write + Send>);
+                Pager { writer: Mutex::new(stdin), child: Some(child) }
+            }
+            Err(e) => {
+                log::debug!("Failed to start pager {}: {}", words[0], e);
+                stdout()
+            }
+        }
+    }
+
+    /// Writes text in one go, so output from parallel walkers doesn't interleave.
+    pub fn write(&self, text: &str) -> io::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        let Some(output) = writer.as_mut() else {
+            return Ok(());
+        };
+        match output.write_all(text.as_bytes()) {
+            // The pager quit before reading everything, so stop sending it output
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe && self.child.is_some() => {
+                *writer = None;
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    /// Flushes the output and waits for the user to quit the pager.
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(mut output) = self.writer.lock().unwrap().take() {
+            let _ = output.flush();
+        }
+        if let Some(child) = self.child.as_mut() {
+            child.wait()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pager_command() {
+        assert_eq!(pager_command(None), Some(vec!["less".to_string()]));
+        assert_eq!(pager_command(Some("less -R")), Some(vec!["less".to_string(), "-R".to_string()]));
+        assert_eq!(pager_command(Some("cat")), None);
+        assert_eq!(pager_command(Some("")), None);
+    }
+}
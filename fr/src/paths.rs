@@ -0,0 +1,225 @@
+//! Renaming files and directories and retargeting symlinks, for `fr scaffold`
+//! and `--everything`.
+//!
+//! Paths are renamed deepest first, so each one is renamed inside a directory
+//! that hasn't been yet and the path it was found at is still good. A path is
+//! never renamed over one that exists, and each change is recorded in the
+//! order it was made, so that `undo` can put the tree back in reverse.
+
+use crate::hooks::Filter;
+use crate::{excluded, included, is_vcs_dir, walk_builder, Options};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// A path and the path it is renamed to
+pub type Rename = (PathBuf, PathBuf);
+
+/// A symlink, the path it pointed to and the path it points to now
+pub type Retarget = (PathBuf, PathBuf, PathBuf);
+
+/// Lists the files, directories and symlinks below a directory, less those
+/// `--exclude` and `--include` leave out and version control metadata.
+///
+/// # Arguments
+///
+/// * `starting_directory` - The directory to walk
+/// * `options` - Which files to leave alone
+/// * `hidden` - Whether to leave out hidden files, as the content walk does
+///
+/// # Returns
+///
+/// * `Vec<PathBuf>` - Every path visited, without the starting directory
+pub fn walk(starting_directory: &Path, options: &Options, hidden: bool) -> Vec<PathBuf> {
+    let mut filters: Vec<Filter> = vec![Arc::new(|path: &Path| !path.file_name().is_some_and(is_vcs_dir))];
+    filters.extend(excluded(starting_directory, &options.excludes));
+    filters.extend(included(starting_directory, &options.includes));
+    walk_builder(&[starting_directory.to_path_buf()], &filters).hidden(hidden).build()
+        .flatten()
+        .map(|entry| entry.into_path())
+        .filter(|path| path != starting_directory)
+        .collect()
+}
+
+/// Renames the paths whose names change, or works out the renames in a dry
+/// run.
+///
+/// # Arguments
+///
+/// * `paths` - The paths to consider, in any order
+/// * `new_name` - The name each file or directory name becomes
+/// * `dry_run` - Whether to leave the paths as they are
+///
+/// # Returns
+///
+/// * `(Vec<Rename>, Vec<(PathBuf, String)>)` - Each rename in the order it was
+///   made, and the paths that couldn't be renamed and why
+pub fn rename(mut paths: Vec<PathBuf>, new_name: impl Fn(&str) -> String, dry_run: bool) -> (Vec<Rename>, Vec<(PathBuf, String)>) {
+    // Deepest first, so a directory is renamed after what is inside it
+    paths.sort_by_key(|path| std::cmp::Reverse(path.components().count()));
+    let (mut renamed, mut errors) = (Vec::new(), Vec::new());
+    for path in paths {
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let new_name = new_name(name);
+        if new_name == name {
+            continue;
+        }
+        if new_name.is_empty() || new_name.contains(['/', '\\']) {
+            errors.push((path, format!("Can't rename to '{}', which isn't a file name", new_name)));
+            continue;
+        }
+        let new_path = path.with_file_name(&new_name);
+        if fs::symlink_metadata(&new_path).is_ok() {
+            errors.push((path, format!("Can't rename to {}, which already exists", new_name)));
+            continue;
+        }
+        let result = if dry_run { Ok(()) } else { fs::rename(&path, &new_path) };
+        if let Err(e) = result {
+            errors.push((path, format!("Failed to rename: {}", e)));
+            continue;
+        }
+        renamed.push((path, new_path));
+    }
+    (renamed, errors)
+}
+
+/// Works out where each renamed path ends up once the directories it is in
+/// have been renamed too.
+///
+/// # Returns
+///
+/// * `Vec<Rename>` - Each path as it was found, with the path it has after
+///   every rename, in order of the paths
+pub fn final_paths(renamed: &[Rename]) -> Vec<Rename> {
+    let mut moved = renamed.to_vec();
+    for (_, new_path) in moved.iter_mut() {
+        for (old_directory, new_directory) in renamed {
+            let inside = new_path.strip_prefix(old_directory).ok().filter(|rest| !rest.as_os_str().is_empty());
+            if let Some(path) = inside.map(|rest| new_directory.join(rest)) {
+                *new_path = path;
+            }
+        }
+    }
+    moved.sort();
+    moved
+}
+
+/// Points the symlinks whose targets have the find text at the targets with
+/// it replaced, or works out the new targets in a dry run.
+///
+/// # Arguments
+///
+/// * `paths` - The paths to consider, of which only symlinks are retargeted
+/// * `find_text` - Text to find in the targets
+/// * `replace_text` - Text to replace it with
+/// * `dry_run` - Whether to leave the symlinks as they are
+///
+/// # Returns
+///
+/// * `(Vec<Retarget>, Vec<(PathBuf, String)>)` - Each symlink retargeted, and
+///   the symlinks that couldn't be and why
+pub fn retarget(paths: &[PathBuf], find_text: &str, replace_text: &str, dry_run: bool) -> (Vec<Retarget>, Vec<(PathBuf, String)>) {
+    let (mut retargeted, mut errors) = (Vec::new(), Vec::new());
+    for path in paths {
+        if !fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+            continue;
+        }
+        let Some(target) = fs::read_link(path).ok().and_then(|target| target.to_str().map(str::to_string)) else {
+            continue;
+        };
+        if find_text.is_empty() || !target.contains(find_text) {
+            continue;
+        }
+        let new_target = PathBuf::from(target.replace(find_text, replace_text));
+        let result = if dry_run { Ok(()) } else { relink(path, &new_target) };
+        match result {
+            Ok(()) => retargeted.push((path.clone(), PathBuf::from(target), new_target)),
+            Err(e) => errors.push((path.clone(), format!("Failed to retarget: {}", e))),
+        }
+    }
+    (retargeted, errors)
+}
+
+/// Replaces a symlink with one to another target.
+fn relink(path: &Path, target: &Path) -> io::Result<()> {
+    // Made alongside and renamed over the old one, so the link is never missing
+    let temporary = path.with_file_name(format!(".{}.fr-link", path.file_name().and_then(|name| name.to_str()).unwrap_or("link")));
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(target, &temporary)?;
+    #[cfg(windows)]
+    match fs::metadata(path).is_ok_and(|metadata| metadata.is_dir()) {
+        true => std::os::windows::fs::symlink_dir(target, &temporary)?,
+        false => std::os::windows::fs::symlink_file(target, &temporary)?,
+    }
+    fs::rename(&temporary, path).inspect_err(|_| {
+        let _ = fs::remove_file(&temporary);
+    })
+}
+
+/// Undoes renames and retargets, most recent first.
+///
+/// # Returns
+///
+/// * `Vec<(PathBuf, String)>` - The paths that couldn't be put back and why
+pub fn undo(renamed: &[Rename], retargeted: &[Retarget]) -> Vec<(PathBuf, String)> {
+    let mut errors = Vec::new();
+    for (old_path, new_path) in renamed.iter().rev() {
+        if let Err(e) = fs::rename(new_path, old_path) {
+            errors.push((new_path.clone(), format!("Failed to rename back to {}: {}", old_path.display(), e)));
+        }
+    }
+    for (path, target, _) in retargeted.iter().rev() {
+        if let Err(e) = relink(path, target) {
+            errors.push((path.clone(), format!("Failed to point back at {}: {}", target.display(), e)));
+        }
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_rename_and_undo() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("old/src")).unwrap();
+        fs::write(root.join("old/src/old.rs"), "").unwrap();
+        fs::write(root.join("old.txt"), "").unwrap();
+        fs::write(root.join("new.txt"), "").unwrap();
+        let rename_old = |name: &str| name.replace("old", "new");
+
+        let (renamed, errors) = rename(walk(root, &Options::default(), true), rename_old, true);
+        assert_eq!(final_paths(&renamed), [(root.join("old"), root.join("new")), (root.join("old/src/old.rs"), root.join("new/src/new.rs"))]);
+        assert_eq!(errors.len(), 1);
+        assert!(root.join("old").exists());
+
+        let (renamed, _) = rename(walk(root, &Options::default(), true), rename_old, false);
+        assert!(root.join("new/src/new.rs").is_file() && root.join("old.txt").is_file());
+        assert!(undo(&renamed, &[]).is_empty());
+        assert!(root.join("old/src/old.rs").is_file() && !root.join("new").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_retarget() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        std::os::unix::fs::symlink("docs/old.md", root.join("README.md")).unwrap();
+        std::os::unix::fs::symlink("other.md", root.join("OTHER.md")).unwrap();
+
+        let (retargeted, errors) = retarget(&walk(root, &Options::default(), true), "old", "new", false);
+        assert!(errors.is_empty());
+        assert_eq!(retargeted, [(root.join("README.md"), PathBuf::from("docs/old.md"), PathBuf::from("docs/new.md"))]);
+        assert_eq!(fs::read_link(root.join("README.md")).unwrap(), Path::new("docs/new.md"));
+        assert_eq!(fs::read_link(root.join("OTHER.md")).unwrap(), Path::new("other.md"));
+
+        assert!(undo(&[], &retargeted).is_empty());
+        assert_eq!(fs::read_link(root.join("README.md")).unwrap(), Path::new("docs/old.md"));
+    }
+}
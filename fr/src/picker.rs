@@ -0,0 +1,363 @@
+//! Choosing which files to modify from a fuzzy-filtered list, for `--pick`.
+//!
+//! The files a run would modify are listed on the terminal, and typing narrows
+//! the list to the paths that contain the typed characters in order, as skim
+//! and fzf do. Tab marks a file and Enter modifies the marked ones, or the
+//! highlighted one if none are marked. The list is drawn on `/dev/tty`, so
+//! it works while stdout is redirected.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A key pressed in the picker
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Key {
+    Char(char),
+    Backspace,
+    Up,
+    Down,
+    /// Mark or unmark the highlighted file
+    Tab,
+    /// Mark or unmark every file in the list
+    ToggleAll,
+    Enter,
+    Cancel,
+}
+
+/// The state of the picker between key presses
+#[derive(Debug)]
+struct Picker<'a> {
+    /// The files to choose from, and how many replacements each would get
+    candidates: &'a [(PathBuf, usize)],
+    query: String,
+    marked: Vec<bool>,
+    /// The highlighted row of the filtered list
+    cursor: usize,
+}
+
+impl<'a> Picker<'a> {
+    fn new(candidates: &'a [(PathBuf, usize)]) -> Picker<'a> {
+        Picker { candidates, query: String::new(), marked: vec![false; candidates.len()], cursor: 0 }
+    }
+
+    /// The candidates matching the query, best match first.
+    fn filtered(&self) -> Vec<usize> {
+        let mut scored: Vec<(i64, usize)> = self.candidates.iter().enumerate()
+            .filter_map(|(index, (path, _))| score(&self.query, &path.to_string_lossy()).map(|score| (score, index)))
+            .collect();
+        // Ties keep the order the files were found in
+        scored.sort_by_key(|&(score, _)| -score);
+        scored.into_iter().map(|(_, index)| index).collect()
+    }
+
+    /// Handles a key, returning the chosen files once the picker is done.
+    fn key(&mut self, key: Key) -> Option<Vec<PathBuf>> {
+        let filtered = self.filtered();
+        match key {
+            Key::Char(c) => {
+                self.query.push(c);
+                self.cursor = 0;
+            }
+            Key::Backspace => {
+                self.query.pop();
+                self.cursor = 0;
+            }
+            Key::Up => self.cursor = self.cursor.saturating_sub(1),
+            Key::Down => self.cursor = (self.cursor + 1).min(filtered.len().saturating_sub(1)),
+            Key::Tab => {
+                if let Some(&index) = filtered.get(self.cursor) {
+                    self.marked[index] = !self.marked[index];
+                    self.cursor = (self.cursor + 1).min(filtered.len() - 1);
+                }
+            }
+            Key::ToggleAll => {
+                let mark = !filtered.iter().all(|&index| self.marked[index]);
+                for &index in &filtered {
+                    self.marked[index] = mark;
+                }
+            }
+            Key::Enter => {
+                let chosen: Vec<PathBuf> = self.candidates.iter().zip(&self.marked)
+                    .filter(|(_, marked)| **marked)
+                    .map(|((path, _), _)| path.clone())
+                    .collect();
+                return match (chosen.is_empty(), filtered.get(self.cursor)) {
+                    (true, Some(&index)) => Some(vec![self.candidates[index].0.clone()]),
+                    _ => Some(chosen),
+                };
+            }
+            Key::Cancel => return Some(Vec::new()),
+        }
+        None
+    }
+
+    /// Draws the picker in a terminal of the given number of rows.
+    fn render(&self, rows: usize) -> String {
+        let filtered = self.filtered();
+        let marked = self.marked.iter().filter(|marked| **marked).count();
+        // Clear the screen and draw from the top left
+        let mut screen = String::from("\x1b[H\x1b[2J");
+        screen.push_str(&format!("{}/{} files, {} marked (tab to mark, ctrl-a for all, enter to modify, esc to cancel)\r\n",
+            filtered.len(), self.candidates.len(), marked));
+        // Keep the highlighted row on screen
+        let shown = rows.saturating_sub(2).max(1);
+        let first = self.cursor.saturating_sub(shown - 1);
+        for (row, &index) in filtered.iter().enumerate().skip(first).take(shown) {
+            let (path, replacements) = &self.candidates[index];
+            screen.push_str(&format!("{}{} {} ({} replacement{})\r\n",
+                if row == self.cursor { ">" } else { " " },
+                if self.marked[index] { "*" } else { " " },
+                path.display(), replacements, if *replacements == 1 { "" } else { "s" }));
+        }
+        screen.push_str(&format!("> {}", self.query));
+        screen
+    }
+}
+
+/// Scores how well a path matches a query, or None if it doesn't contain the
+/// query's characters in order. Runs of consecutive characters and characters
+/// starting a file or directory name score higher. Lowercase queries match
+/// either case.
+fn score(query: &str, path: &str) -> Option<i64> {
+    let ignore_case = !query.chars().any(char::is_uppercase);
+    let same = |a: char, b: char| if ignore_case { a.to_lowercase().eq(b.to_lowercase()) } else { a == b };
+    let mut score = 0;
+    let mut chars = path.chars().peekable();
+    let mut previous: Option<char> = None;
+    let mut consecutive = false;
+    for wanted in query.chars() {
+        loop {
+            let c = chars.next()?;
+            let starts_name = previous.is_none_or(|previous| matches!(previous, '/' | '\\' | '_' | '-' | '.' | ' '));
+            previous = Some(c);
+            if same(c, wanted) {
+                score += 1 + if consecutive { 5 } else { 0 } + if starts_name { 3 } else { 0 };
+                consecutive = true;
+                break;
+            }
+            consecutive = false;
+            score -= 1;
+        }
+    }
+    Some(score)
+}
+
+/// Lets the user choose which files to modify.
+///
+/// # Arguments
+///
+/// * `candidates` - The files a run would modify, and how many replacements
+///   each would get
+///
+/// # Returns
+///
+/// * `io::Result<Vec<PathBuf>>` - The chosen files, which are none if the
+///   picker was cancelled, or an error if there is no terminal to pick on
+pub fn pick(candidates: &[(PathBuf, usize)]) -> io::Result<Vec<PathBuf>> {
+    let mut terminal = Terminal::open(Path::new("/dev/tty"))?;
+    let mut picker = Picker::new(candidates);
+    loop {
+        terminal.draw(&picker.render(terminal.rows()))?;
+        if let Some(chosen) = picker.key(terminal.key()?) {
+            return Ok(chosen);
+        }
+    }
+}
+
+/// A terminal in raw mode, which is restored when dropped
+#[cfg(unix)]
+struct Terminal {
+    tty: std::fs::File,
+    original: libc::termios,
+}
+
+#[cfg(unix)]
+impl Terminal {
+    /// Opens a terminal, switching it to raw mode and the alternate screen.
+    fn open(path: &Path) -> io::Result<Terminal> {
+        use std::io::Write;
+        use std::os::fd::AsRawFd;
+        let mut tty = std::fs::OpenOptions::new().read(true).write(true).open(path)?;
+        // SAFETY: termios is plain data, filled in by tcgetattr
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        // SAFETY: the descriptor is open and the termios is live
+        if unsafe { libc::tcgetattr(tty.as_raw_fd(), &mut original) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut raw = original;
+        // SAFETY: the termios is live
+        unsafe { libc::cfmakeraw(&mut raw) };
+        // SAFETY: the descriptor is open and the termios is live
+        if unsafe { libc::tcsetattr(tty.as_raw_fd(), libc::TCSANOW, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        tty.write_all(b"\x1b[?1049h")?;
+        Ok(Terminal { tty, original })
+    }
+
+    /// The number of rows in the terminal.
+    fn rows(&self) -> usize {
+        use std::os::fd::AsRawFd;
+        // SAFETY: winsize is plain data, filled in by the ioctl
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        // SAFETY: the descriptor is open and the winsize is live
+        match unsafe { libc::ioctl(self.tty.as_raw_fd(), libc::TIOCGWINSZ, &mut size) } {
+            0 if size.ws_row > 0 => usize::from(size.ws_row),
+            _ => 24,
+        }
+    }
+
+    fn draw(&mut self, screen: &str) -> io::Result<()> {
+        use std::io::Write;
+        self.tty.write_all(screen.as_bytes())?;
+        self.tty.flush()
+    }
+
+    /// Waits for a key press.
+    fn key(&mut self) -> io::Result<Key> {
+        loop {
+            let key = match self.byte()? {
+                b'\r' | b'\n' => Key::Enter,
+                b'\t' => Key::Tab,
+                0x7f | 0x08 => Key::Backspace,
+                // Ctrl-A, Ctrl-P and Ctrl-N
+                0x01 => Key::ToggleAll,
+                0x10 => Key::Up,
+                0x0e => Key::Down,
+                // Ctrl-C, Ctrl-D and Ctrl-G
+                0x03 | 0x04 | 0x07 => Key::Cancel,
+                0x1b => match self.escape_sequence()? {
+                    None => Key::Cancel,
+                    Some(b'A') => Key::Up,
+                    Some(b'B') => Key::Down,
+                    Some(_) => continue,
+                },
+                byte if byte < 0x20 => continue,
+                byte => match self.char(byte)? {
+                    Some(c) => Key::Char(c),
+                    None => continue,
+                },
+            };
+            return Ok(key);
+        }
+    }
+
+    /// Reads the rest of an escape sequence, returning its final byte, or None
+    /// if Escape was pressed on its own.
+    fn escape_sequence(&mut self) -> io::Result<Option<u8>> {
+        use std::os::fd::AsRawFd;
+        // A lone Escape isn't followed by anything within a few milliseconds
+        let mut poll = libc::pollfd { fd: self.tty.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        // SAFETY: the pollfd is live and refers to an open descriptor
+        if unsafe { libc::poll(&mut poll, 1, 25) } <= 0 {
+            return Ok(None);
+        }
+        if !matches!(self.byte()?, b'[' | b'O') {
+            return Ok(Some(0));
+        }
+        loop {
+            let byte = self.byte()?;
+            if (0x40..=0x7e).contains(&byte) {
+                return Ok(Some(byte));
+            }
+        }
+    }
+
+    /// Reads the rest of a UTF-8 character starting with the given byte.
+    fn char(&mut self, first: u8) -> io::Result<Option<char>> {
+        let length = match first.leading_ones() {
+            0 => 1,
+            ones @ 2..=4 => ones as usize,
+            _ => return Ok(None),
+        };
+        let mut bytes = vec![first];
+        for _ in 1..length {
+            bytes.push(self.byte()?);
+        }
+        Ok(std::str::from_utf8(&bytes).ok().and_then(|text| text.chars().next()))
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        use std::io::Read;
+        let mut byte = [0u8];
+        self.tty.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        use std::io::Write;
+        use std::os::fd::AsRawFd;
+        let _ = self.tty.write_all(b"\x1b[?1049l");
+        // SAFETY: the descriptor is open and the termios is live
+        unsafe { libc::tcsetattr(self.tty.as_raw_fd(), libc::TCSANOW, &self.original) };
+    }
+}
+
+/// A terminal, which can't be put in raw mode on this platform
+#[cfg(not(unix))]
+struct Terminal;
+
+#[cfg(not(unix))]
+impl Terminal {
+    fn open(_path: &Path) -> io::Result<Terminal> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "--pick is only supported on Unix terminals"))
+    }
+
+    fn rows(&self) -> usize {
+        24
+    }
+
+    fn draw(&mut self, _screen: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn key(&mut self) -> io::Result<Key> {
+        Ok(Key::Cancel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_score() {
+        assert!(score("", "src/lib.rs").is_some());
+        assert!(score("slr", "src/lib.rs").is_some());
+        assert_eq!(score("rsl", "src/lib.rs"), None);
+        assert!(score("lib", "src/lib.rs") > score("lib", "docs/a_little_bit.md"));
+        // Lowercase queries match either case, but not the other way round
+        assert!(score("readme", "README.md").is_some());
+        assert_eq!(score("README", "readme.md"), None);
+    }
+
+    #[test]
+    fn test_picker() {
+        let candidates = [(PathBuf::from("src/main.rs"), 2), (PathBuf::from("README.md"), 1), (PathBuf::from("src/lib.rs"), 1)];
+        let mut picker = Picker::new(&candidates);
+        // Enter with nothing marked takes the highlighted file
+        for c in "lib".chars() {
+            assert_eq!(picker.key(Key::Char(c)), None);
+        }
+        assert_eq!(picker.filtered(), vec![2]);
+        assert_eq!(picker.key(Key::Enter), Some(vec![PathBuf::from("src/lib.rs")]));
+
+        let mut picker = Picker::new(&candidates);
+        picker.key(Key::Char('s'));
+        picker.key(Key::Char('r'));
+        picker.key(Key::Char('c'));
+        assert_eq!(picker.key(Key::ToggleAll), None);
+        picker.key(Key::Backspace);
+        picker.key(Key::Backspace);
+        picker.key(Key::Backspace);
+        picker.key(Key::Down);
+        assert!(picker.render(24).contains(">  README.md (1 replacement)"));
+        picker.key(Key::Tab);
+        assert_eq!(picker.key(Key::Enter), Some(candidates.iter().map(|(path, _)| path.clone()).collect()));
+
+        assert_eq!(Picker::new(&candidates).key(Key::Cancel), Some(Vec::new()));
+    }
+}
@@ -0,0 +1,292 @@
+//! Saving the edits a run would make to apply them later, for `--save-plan`
+//! and `fr apply`.
+//!
+//! A plan file holds a hash of each file the run would modify, as it was when
+//! the plan was made, and the bytes of the file each replacement covers:
+//!
+//! ```json
+//! {
+//!   "fr": "1.0.3",
+//!   "find": "old_name",
+//!   "replace": "new_name",
+//!   "files": [
+//!     {"path":"src/main.rs","hash":"5d0b4ae321f8a2c6","edits":[{"start":12,"end":20,"before":"old_name","after":"new_name"}]}
+//!   ]
+//! }
+//! ```
+//!
+//! `fr apply` makes exactly those edits, and only in the files that still
+//! have the same hash, so a plan reviewed some time ago can't change a file
+//! that has been edited since. A file that changed is reported and left
+//! alone, and can be planned again.
+
+use crate::cache::Fnv;
+use crate::eol::{self, LineEnding};
+use crate::json::{self, Value};
+use crate::{match_offsets, retry, write_atomically, ModifiedFile, PendingWrite, Summary};
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// A file the plan modifies
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedFile {
+    /// Path relative to the directory the plan was made in
+    pub path: PathBuf,
+    /// FNV-1a hash of the file's contents when the plan was made
+    pub hash: u64,
+    /// The replacements to make, in order
+    pub edits: Vec<PlannedEdit>,
+}
+
+/// One replacement the plan makes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedEdit {
+    /// The bytes of the file to replace, as it was when the plan was made
+    pub range: Range<usize>,
+    /// The text that is replaced
+    pub before: String,
+    /// The text that replaces it
+    pub after: String,
+}
+
+/// Hashes the contents of a file as the plan records it.
+pub(crate) fn hash(contents: &[u8]) -> u64 {
+    let mut hash = Fnv::new();
+    hash.write(contents);
+    hash.0
+}
+
+/// Works out the edits to the files a run would modify.
+///
+/// # Arguments
+///
+/// * `starting_directory` - The directory the paths are relative to
+/// * `files` - The files the run would modify, from a dry run
+/// * `find_text` - Text to find
+/// * `replace_text` - Text to replace the found text with
+/// * `retries` - How many times to retry reading a file that is briefly
+///   unavailable
+///
+/// # Returns
+///
+/// * `(Vec<PlannedFile>, Vec<(PathBuf, String)>)` - Each file and its edits,
+///   and the files that couldn't be read and why
+pub fn make(starting_directory: &Path, files: &[PathBuf], find_text: &str, replace_text: &str, retries: u32)
+    -> (Vec<PlannedFile>, Vec<(PathBuf, String)>) {
+    let mut planned = Vec::new();
+    let mut errors = Vec::new();
+    for display in files {
+        let path = starting_directory.join(display);
+        match retry::retrying(&path, retries, || fs::read(&path)).and_then(|contents| plan_file(display, &contents, find_text, replace_text)) {
+            Ok(file) => planned.push(file),
+            Err(e) => errors.push((display.clone(), e.to_string())),
+        }
+    }
+    (planned, errors)
+}
+
+/// Works out the edits to one file, matching the find text as a run does,
+/// with its newlines as the file writes them.
+fn plan_file(display: &Path, contents: &[u8], find_text: &str, replace_text: &str) -> io::Result<PlannedFile> {
+    let text = std::str::from_utf8(contents).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "isn't valid UTF-8"))?;
+    let ending = LineEnding::detect(text);
+    let (find_text, replace_text) = (eol::adapt(find_text, ending), eol::adapt(replace_text, ending));
+    let edits = match_offsets(text, &find_text).into_iter()
+        .map(|offset| PlannedEdit { range: offset..offset + find_text.len(), before: find_text.to_string(), after: replace_text.to_string() })
+        .collect();
+    Ok(PlannedFile { path: display.to_path_buf(), hash: hash(contents), edits })
+}
+
+/// Writes a plan file, with one line per file so plans diff well.
+pub fn encode(find_text: &str, replace_text: &str, files: &[PlannedFile]) -> Result<String, String> {
+    let mut text = format!("{{\n  \"fr\": {},\n  \"find\": {},\n  \"replace\": {},\n  \"files\": [\n",
+        json::string(env!("CARGO_PKG_VERSION")), json::string(find_text), json::string(replace_text));
+    for (i, file) in files.iter().enumerate() {
+        let path = file.path.to_str().ok_or_else(|| format!("{} isn't valid UTF-8, so can't be written in a plan", file.path.display()))?;
+        let edits = file.edits.iter().map(|edit| Value::object([
+            ("start", Value::Number(edit.range.start as f64)),
+            ("end", Value::Number(edit.range.end as f64)),
+            ("before", Value::String(edit.before.clone())),
+            ("after", Value::String(edit.after.clone())),
+        ]));
+        let value = Value::object([
+            ("path", Value::String(path.to_string())),
+            ("hash", Value::String(format!("{:016x}", file.hash))),
+            ("edits", Value::Array(edits.collect())),
+        ]);
+        text.push_str(&format!("    {}{}\n", value, if i + 1 == files.len() { "" } else { "," }));
+    }
+    text.push_str("  ]\n}\n");
+    Ok(text)
+}
+
+/// Reads the files and edits of a plan file.
+pub fn decode(text: &str) -> Result<Vec<PlannedFile>, String> {
+    let value = Value::parse(text)?;
+    let Some(Value::Array(files)) = value.get("files") else {
+        return Err("isn't a plan file, which has a \"files\" array".to_string());
+    };
+    files.iter().map(decode_file).collect()
+}
+
+/// Reads one file of a plan, checking its edits are in order and don't
+/// overlap, so they can be made one after another.
+fn decode_file(value: &Value) -> Result<PlannedFile, String> {
+    let path = value.get("path").and_then(Value::as_str).ok_or("has a file without a path")?;
+    let hash = value.get("hash").and_then(Value::as_str).and_then(|hash| u64::from_str_radix(hash, 16).ok())
+        .ok_or_else(|| format!("has no hash for {}", path))?;
+    let Some(Value::Array(edits)) = value.get("edits") else {
+        return Err(format!("has no edits for {}", path));
+    };
+    let mut planned: Vec<PlannedEdit> = Vec::new();
+    for edit in edits {
+        let (Some(start), Some(end), Some(before), Some(after)) = (edit.get("start").and_then(as_offset), edit.get("end").and_then(as_offset),
+            edit.get("before").and_then(Value::as_str), edit.get("after").and_then(Value::as_str)) else {
+            return Err(format!("has an edit to {} without a start, end, before and after", path));
+        };
+        if end < start || end - start != before.len() || planned.last().is_some_and(|last| last.range.end > start) {
+            return Err(format!("has edits to {} that overlap or whose length isn't their text's", path));
+        }
+        planned.push(PlannedEdit { range: start..end, before: before.to_string(), after: after.to_string() });
+    }
+    Ok(PlannedFile { path: PathBuf::from(path), hash, edits: planned })
+}
+
+/// Reads a JSON number as a byte offset.
+fn as_offset(value: &Value) -> Option<usize> {
+    match value {
+        Value::Number(number) if *number >= 0.0 && number.fract() == 0.0 => Some(*number as usize),
+        _ => None,
+    }
+}
+
+/// Saves a plan to a file.
+pub fn save(path: &Path, find_text: &str, replace_text: &str, files: &[PlannedFile]) -> Result<(), String> {
+    let text = encode(find_text, replace_text, files)?;
+    write_atomically(path, text.as_bytes()).map_err(|e| format!("Failed to save the plan to {}: {}", path.display(), e))
+}
+
+/// Makes a file's edits in its contents.
+///
+/// # Returns
+///
+/// * `Result<Vec<u8>, String>` - The new contents, or an error if an edit
+///   isn't where the plan says, in which case nothing is replaced
+fn replace(contents: &[u8], edits: &[PlannedEdit]) -> Result<Vec<u8>, String> {
+    let mut replaced = Vec::with_capacity(contents.len());
+    let mut copied = 0;
+    for edit in edits {
+        if contents.get(edit.range.clone()) != Some(edit.before.as_bytes()) {
+            return Err(format!("{:?} isn't at byte {}, where the plan replaces it", edit.before, edit.range.start));
+        }
+        replaced.extend_from_slice(&contents[copied..edit.range.start]);
+        replaced.extend_from_slice(edit.after.as_bytes());
+        copied = edit.range.end;
+    }
+    replaced.extend_from_slice(&contents[copied..]);
+    Ok(replaced)
+}
+
+/// Makes the edits of a plan in each file that hasn't changed since the plan
+/// was made.
+///
+/// # Arguments
+///
+/// * `starting_directory` - The directory the plan's paths are relative to
+/// * `files` - Each file and its edits, from `decode`
+/// * `dry_run` - Only check the files, without writing them
+/// * `sync` - Flush each rewritten file to disk, for `--fsync`
+/// * `retries` - How many times to retry reading or writing a file that is
+///   briefly unavailable
+///
+/// # Returns
+///
+/// * `(Summary, Vec<PathBuf>)` - The files modified and the ones that failed,
+///   in the order of the plan, and the files left alone since they have
+///   changed
+pub fn apply(starting_directory: &Path, files: Vec<PlannedFile>, dry_run: bool, sync: bool, retries: u32) -> (Summary, Vec<PathBuf>) {
+    let mut summary = Summary::default();
+    let mut drifted = Vec::new();
+    for file in files {
+        let path = starting_directory.join(&file.path);
+        let contents = match retry::retrying(&path, retries, || fs::read(&path)) {
+            Ok(contents) => contents,
+            Err(e) => {
+                summary.errors.push((file.path, e.to_string()));
+                continue;
+            }
+        };
+        if hash(&contents) != file.hash {
+            drifted.push(file.path);
+            continue;
+        }
+        let result = replace(&contents, &file.edits).map_err(io::Error::other).and_then(|contents| match dry_run {
+            true => Ok(()),
+            false => {
+                let pending = PendingWrite { path: path.clone(), contents, sync };
+                retry::retrying(&path, retries, || pending.write())
+            }
+        });
+        match result {
+            Ok(()) => summary.modified.push(ModifiedFile {
+                path: file.path,
+                replacements: file.edits.len(),
+                hunks: Vec::new(),
+                locations: Vec::new(),
+            }),
+            Err(e) => summary.errors.push((file.path, e.to_string())),
+        }
+    }
+    (summary, drifted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_encode_and_decode() {
+        let files = vec![PlannedFile {
+            path: PathBuf::from("src/a.txt"),
+            hash: 0x0123_4567_89ab_cdef,
+            edits: vec![
+                PlannedEdit { range: 0..3, before: "foo".to_string(), after: "say \"hi\"".to_string() },
+                PlannedEdit { range: 8..11, before: "foo".to_string(), after: String::new() },
+            ],
+        }];
+        let text = encode("foo", "bar", &files).unwrap();
+        assert!(text.contains("\"hash\":\"0123456789abcdef\""));
+        assert_eq!(decode(&text), Ok(files));
+        assert!(decode("{\"files\": [{\"path\": \"a\", \"hash\": \"1\", \"edits\": [{\"start\": 0, \"end\": 2, \"before\": \"a\", \"after\": \"b\"}]}]}").is_err());
+        assert!(decode("{\"args\": []}").is_err());
+    }
+
+    #[test]
+    fn test_make_and_apply() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("a.txt"), "one foo\r\nfoo\r\n").unwrap();
+        fs::write(temp_dir.path().join("b.txt"), "foo").unwrap();
+        let paths = [PathBuf::from("a.txt"), PathBuf::from("b.txt")];
+        let (files, errors) = make(temp_dir.path(), &paths, "foo\n", "bar\n", 0);
+        assert!(errors.is_empty());
+        assert_eq!(files[0].edits, [PlannedEdit { range: 4..9, before: "foo\r\n".to_string(), after: "bar\r\n".to_string() },
+            PlannedEdit { range: 9..14, before: "foo\r\n".to_string(), after: "bar\r\n".to_string() }]);
+        assert!(files[1].edits.is_empty());
+
+        let (files, _) = make(temp_dir.path(), &paths, "foo", "bar", 0);
+        let (summary, drifted) = apply(temp_dir.path(), files.clone(), true, false, 0);
+        assert_eq!((summary.modified.len(), drifted.len()), (2, 0));
+        assert_eq!(fs::read_to_string(temp_dir.path().join("b.txt")).unwrap(), "foo");
+
+        // A file changed since the plan was made is left as it is
+        fs::write(temp_dir.path().join("b.txt"), "foo foo").unwrap();
+        let (summary, drifted) = apply(temp_dir.path(), files, false, false, 0);
+        assert!(summary.errors.is_empty());
+        assert_eq!(drifted, [PathBuf::from("b.txt")]);
+        assert_eq!(fs::read_to_string(temp_dir.path().join("a.txt")).unwrap(), "one bar\r\nbar\r\n");
+        assert_eq!(fs::read_to_string(temp_dir.path().join("b.txt")).unwrap(), "foo foo");
+    }
+}
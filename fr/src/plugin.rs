@@ -0,0 +1,265 @@
+//! Plugins loaded with `--plugin`: WebAssembly modules that can veto files,
+//! rewrite matches, and post-process rewritten files.
+//!
+//! A plugin exports `fr_alloc(len: i32) -> i32`, which returns where fr may
+//! write `len` bytes of input in its memory, and any of these:
+//!
+//! * `fr_veto(path, path_len) -> i32` - Returns nonzero to leave a file alone
+//! * `fr_rewrite(path, path_len, line, line_len, replacement, replacement_len,
+//!   column) -> i64` - Chooses the replacement for one match, given the line
+//!   it is on and its byte offset into the line
+//! * `fr_post_process(path, path_len, text, text_len) -> i64` - Changes a
+//!   file's whole text once every match has been replaced
+//!
+//! The functions that return an `i64` return the address of their output in
+//! the high 32 bits and its length in the low ones, or -1 to keep their input.
+//! If the plugin exports `fr_free(address, len)`, fr calls it with each input,
+//! and each output that doesn't start where an input did, once it is done with
+//! them. Each plugin runs in its own interpreter, one call at a time.
+
+use crate::wasm::Instance;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// One loaded plugin
+#[derive(Debug)]
+struct Plugin {
+    path: PathBuf,
+    instance: Mutex<Instance>,
+}
+
+impl Plugin {
+    /// Calls one of the plugin's exports with strings as arguments, passed as
+    /// an address and length each, along with any other arguments after them,
+    /// and reads back the text it returns if it is asked to.
+    fn call(&self, name: &str, strings: &[&[u8]], others: &[u64], returns_text: bool) -> io::Result<Output> {
+        let mut instance = self.instance.lock().unwrap();
+        let result = call(&mut instance, name, strings, others, returns_text);
+        result.map_err(|e| io::Error::other(format!("plugin {}: {}", self.path.display(), e)))
+    }
+}
+
+/// What an export returned
+enum Output {
+    Number(u64),
+    /// Text, or None if it kept its input
+    Text(Option<String>),
+}
+
+/// Copies arguments into an instance's memory, calls an export with them, and
+/// frees what the plugin allocated for them.
+fn call(instance: &mut Instance, name: &str, strings: &[&[u8]], others: &[u64], returns_text: bool) -> Result<Output, String> {
+    let mut args = Vec::new();
+    for string in strings {
+        let len = string.len() as u64;
+        let address = *instance.call("fr_alloc", &[len])?.first().ok_or("fr_alloc returns nothing")?;
+        instance.write(address as u32, string)?;
+        args.extend([address, len]);
+    }
+    let inputs = args.len();
+    args.extend_from_slice(others);
+    let result = instance.call(name, &args)?.first().copied().ok_or_else(|| format!("{} returns nothing", name))?;
+
+    let mut allocated: Vec<&[u64]> = args[..inputs].chunks(2).collect();
+    let output = if !returns_text {
+        Output::Number(result)
+    } else if result == u64::MAX {
+        Output::Text(None)
+    } else {
+        let (address, len) = (result >> 32, result & 0xffff_ffff);
+        let bytes = instance.read(address as u32, len as u32)?.to_vec();
+        let text = String::from_utf8(bytes).map_err(|_| "returned text that isn't UTF-8".to_string())?;
+        Output::Text(Some(text))
+    };
+    let output_pair = [result >> 32, result & 0xffff_ffff];
+    // Output written over an input is freed along with it
+    if matches!(output, Output::Text(Some(_))) && !allocated.iter().any(|pair| pair[0] == output_pair[0]) {
+        allocated.push(&output_pair);
+    }
+    if instance.exports("fr_free") {
+        for pair in allocated {
+            instance.call("fr_free", pair)?;
+        }
+    }
+    Ok(output)
+}
+
+/// The plugins a run was given, applied in order
+#[derive(Debug, Default)]
+pub struct Plugins {
+    plugins: Vec<Plugin>,
+}
+
+impl Plugins {
+    /// Loads plugins from WebAssembly modules.
+    ///
+    /// # Arguments
+    ///
+    /// * `paths` - The module files, in the order the plugins are applied
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<Plugins>` - The plugins, or an error if one of them can't
+    ///   be read or isn't a module fr can run
+    pub fn load(paths: &[PathBuf]) -> io::Result<Plugins> {
+        let mut plugins = Vec::new();
+        for path in paths {
+            let bytes = fs::read(path)?;
+            let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, format!("plugin {}: {}", path.display(), e));
+            let instance = Instance::new(&bytes).map_err(invalid)?;
+            if !instance.exports("fr_alloc") {
+                return Err(invalid("doesn't export fr_alloc".to_string()));
+            }
+            plugins.push(Plugin { path: path.clone(), instance: Mutex::new(instance) });
+        }
+        Ok(Plugins { plugins })
+    }
+
+    fn exporting<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Plugin> {
+        self.plugins.iter().filter(move |plugin| plugin.instance.lock().unwrap().exports(name))
+    }
+
+    /// Whether any plugin may veto files.
+    pub fn vetoes_files(&self) -> bool {
+        self.exporting("fr_veto").next().is_some()
+    }
+
+    /// Whether any plugin chooses the replacement of matches.
+    pub fn rewrites(&self) -> bool {
+        self.exporting("fr_rewrite").next().is_some()
+    }
+
+    /// Whether any plugin changes the text of rewritten files.
+    pub fn post_processes(&self) -> bool {
+        self.exporting("fr_post_process").next().is_some()
+    }
+
+    /// Asks the plugins whether to leave a file alone.
+    pub fn vetoes(&self, path: &Path) -> io::Result<bool> {
+        let path = path.to_string_lossy();
+        for plugin in self.exporting("fr_veto") {
+            if matches!(plugin.call("fr_veto", &[path.as_bytes()], &[], false)?, Output::Number(veto) if veto as u32 != 0) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Builds the rewritten text from the offsets of the matches, as `splice`
+    /// does, with each replacement chosen by the plugins in turn.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path to the file the matches are in
+    /// * `content` - Text the matches were found in
+    /// * `offsets` - Where each match starts
+    /// * `find_len` - Length of the find text in bytes
+    /// * `replace_text` - The replacement to start from for each match
+    pub fn splice(&self, path: &Path, content: &str, offsets: &[usize], find_len: usize, replace_text: &str) -> io::Result<String> {
+        let path = path.to_string_lossy();
+        let mut spliced = String::with_capacity(content.len());
+        let mut copied = 0;
+        for &offset in offsets {
+            let line_start = content[..offset].rfind('\n').map_or(0, |newline| newline + 1);
+            let line_end = content[offset..].find('\n').map_or(content.len(), |newline| offset + newline);
+            let line = &content[line_start..line_end];
+            let column = (offset - line_start) as u64;
+            let mut replacement = replace_text.to_string();
+            for plugin in self.exporting("fr_rewrite") {
+                let strings = [path.as_bytes(), line.as_bytes(), replacement.as_bytes()];
+                if let Output::Text(Some(chosen)) = plugin.call("fr_rewrite", &strings, &[column], true)? {
+                    replacement = chosen;
+                }
+            }
+            spliced.push_str(&content[copied..offset]);
+            spliced.push_str(&replacement);
+            copied = offset + find_len;
+        }
+        spliced.push_str(&content[copied..]);
+        Ok(spliced)
+    }
+
+    /// Lets the plugins change the text of a rewritten file.
+    pub fn post_process(&self, path: &Path, mut text: String) -> io::Result<String> {
+        let path = path.to_string_lossy();
+        for plugin in self.exporting("fr_post_process") {
+            if let Output::Text(Some(processed)) = plugin.call("fr_post_process", &[path.as_bytes(), text.as_bytes()], &[], true)? {
+                text = processed;
+            }
+        }
+        Ok(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wasm::tests::{module, Export};
+    use crate::{walk_find_replace, Options};
+    use tempfile::TempDir;
+
+    const I32: u8 = 0x7f;
+    const I64: u8 = 0x7e;
+
+    /// Hands out memory from a global that starts at 1024
+    const ALLOC: Export = ("fr_alloc", &[I32], &[I32], &[], &[
+        0x23, 0x00, 0x23, 0x00, 0x20, 0x00, 0x6a, 0x24, 0x00, 0x0b,
+    ]);
+
+    #[test]
+    fn test_veto_and_rewrite() {
+        let temp_dir = TempDir::new().unwrap();
+        let plugin = module(&[
+            ALLOC,
+            // Vetoes paths ending in d
+            ("fr_veto", &[I32, I32], &[I32], &[], &[
+                0x20, 0x00, 0x20, 0x01, 0x6a, 0x41, 0x01, 0x6b, 0x2d, 0x00, 0x00, 0x41, 0xe4, 0x00, 0x46, 0x0b,
+            ]),
+            // Capitalizes replacements at the start of a line
+            ("fr_rewrite", &[I32, I32, I32, I32, I32, I32, I32], &[I64], &[], &[
+                0x20, 0x06, 0x04, 0x7e, 0x42, 0x7f, 0x05,
+                0x20, 0x04, 0x20, 0x04, 0x2d, 0x00, 0x00, 0x41, 0x20, 0x6b, 0x3a, 0x00, 0x00,
+                0x20, 0x04, 0xad, 0x42, 0x20, 0x86, 0x20, 0x05, 0xad, 0x84, 0x0b, 0x0b,
+            ]),
+        ]);
+        fs::write(temp_dir.path().join("plugin.wasm"), plugin).unwrap();
+        fs::create_dir(temp_dir.path().join("tree")).unwrap();
+        fs::write(temp_dir.path().join("tree").join("a.txt"), "cat and cat\ncat\n").unwrap();
+        fs::write(temp_dir.path().join("tree").join("b.md"), "cat").unwrap();
+
+        let options = Options { plugins: vec![temp_dir.path().join("plugin.wasm")], quiet: true, ..Options::default() };
+        let summary = walk_find_replace(&temp_dir.path().join("tree"), "cat", "dog", &options).unwrap();
+        assert_eq!(summary.modified.len(), 1);
+        assert_eq!(summary.skipped, [(PathBuf::from("b.md"), crate::SkipReason::Vetoed)]);
+        assert_eq!(fs::read_to_string(temp_dir.path().join("tree").join("a.txt")).unwrap(), "Dog and dog\nDog\n");
+        assert_eq!(fs::read_to_string(temp_dir.path().join("tree").join("b.md")).unwrap(), "cat");
+    }
+
+    #[test]
+    fn test_post_process() {
+        let temp_dir = TempDir::new().unwrap();
+        let plugin = module(&[
+            ALLOC,
+            // Uppercases ASCII letters in place
+            ("fr_post_process", &[I32, I32, I32, I32], &[I64], &[I32, I32], &[
+                0x02, 0x40, 0x03, 0x40,
+                0x20, 0x04, 0x20, 0x03, 0x4f, 0x0d, 0x01,
+                0x20, 0x02, 0x20, 0x04, 0x6a, 0x2d, 0x00, 0x00, 0x21, 0x05,
+                0x20, 0x05, 0x41, 0xe1, 0x00, 0x6b, 0x41, 0x1a, 0x49, 0x04, 0x40,
+                0x20, 0x02, 0x20, 0x04, 0x6a, 0x20, 0x05, 0x41, 0x20, 0x6b, 0x3a, 0x00, 0x00, 0x0b,
+                0x20, 0x04, 0x41, 0x01, 0x6a, 0x21, 0x04, 0x0c, 0x00, 0x0b, 0x0b,
+                0x20, 0x02, 0xad, 0x42, 0x20, 0x86, 0x20, 0x03, 0xad, 0x84, 0x0b,
+            ]),
+        ]);
+        fs::write(temp_dir.path().join("plugin.wasm"), plugin).unwrap();
+
+        let plugins = Plugins::load(&[temp_dir.path().join("plugin.wasm")]).unwrap();
+        assert!(plugins.post_processes() && !plugins.rewrites() && !plugins.vetoes_files());
+        assert_eq!(plugins.post_process(Path::new("a.txt"), "fn main() {}".to_string()).unwrap(), "FN MAIN() {}");
+
+        fs::write(temp_dir.path().join("broken.wasm"), b"\0asm\x01\0\0\0").unwrap();
+        assert!(Plugins::load(&[temp_dir.path().join("broken.wasm")]).unwrap_err().to_string().contains("fr_alloc"));
+    }
+}
@@ -0,0 +1,271 @@
+//! Replacing only in the translations of gettext catalogs, for `--po` and
+//! `--po-fuzzy`.
+//!
+//! A match is kept if it is inside the quoted strings of a `msgstr`, or of a
+//! plural `msgstr[n]`, so the `msgid` a translation is looked up by and the
+//! translator comments never change. The header entry, whose
+//! `msgstr` holds the catalog's metadata, and obsolete `#~` entries are left
+//! alone. With `--po-fuzzy`, each entry whose translation changed is flagged
+//! `fuzzy`, for a translator to review.
+
+use std::path::Path;
+
+/// Returns whether a file is a gettext catalog or template, going by its
+/// extension, or is stdin.
+pub fn is_po(file_path: &Path) -> bool {
+    file_path == Path::new("-")
+        || (file_path.extension().and_then(|extension| extension.to_str()))
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("po") || extension.eq_ignore_ascii_case("pot"))
+}
+
+/// An entry of a catalog, from its comments to its last string
+#[derive(Debug, Default)]
+struct Entry {
+    /// Where the `#,` flags line starts and ends, if there is one
+    flags: Option<(usize, usize)>,
+    /// Where a flags line would go: before the `#|` previous strings, or the
+    /// first keyword
+    flags_at: Option<usize>,
+    /// Where the text of each `msgstr` string starts and ends
+    strings: Vec<(usize, usize)>,
+    /// Whether the `msgid` is empty, as only the header's is
+    header: bool,
+}
+
+/// Keeps the matches that lie wholly inside the text of a translation, where
+/// the replacement can go as it is.
+///
+/// # Arguments
+///
+/// * `content` - The catalog
+/// * `offsets` - Where each match of the find text starts
+/// * `find_len` - Length of the find text in bytes
+/// * `replace_text` - Text each match would be replaced with
+///
+/// # Returns
+///
+/// * `Vec<usize>` - The offsets of the matches to replace
+pub fn msgstr_offsets(content: &str, offsets: &[usize], find_len: usize, replace_text: &str) -> Vec<usize> {
+    if replace_text.contains(['"', '\\', '\n', '\r']) {
+        return Vec::new();
+    }
+    let strings: Vec<(usize, usize)> = entries(content).into_iter().filter(|entry| !entry.header).flat_map(|entry| entry.strings).collect();
+    let mut strings = strings.iter().peekable();
+    offsets.iter().copied().filter(|&offset| {
+        while strings.next_if(|&&(_, end)| end < offset + find_len).is_some() {}
+        strings.peek().is_some_and(|&&(start, end)| {
+            offset >= start && offset + find_len <= end && outside_escapes(content, start, offset, offset + find_len)
+        })
+    }).collect()
+}
+
+/// Replaces the matches at the offsets, as `splice` does, and flags each entry
+/// with a changed translation as fuzzy.
+///
+/// # Arguments
+///
+/// * `content` - The catalog
+/// * `offsets` - Where each match to replace starts, from `msgstr_offsets`
+/// * `find_len` - Length of the find text in bytes
+/// * `replace_text` - Text to replace each match with
+///
+/// # Returns
+///
+/// * `String` - The rewritten catalog
+pub fn splice_fuzzy(content: &str, offsets: &[usize], find_len: usize, replace_text: &str) -> String {
+    // Flags to add, as where they go and what goes there, in order
+    let mut flags: Vec<(usize, usize, String)> = Vec::new();
+    let mut offsets_left = offsets.iter().copied().peekable();
+    for entry in entries(content) {
+        let Some(&(_, last_end)) = entry.strings.last() else {
+            continue;
+        };
+        let mut changed = false;
+        while offsets_left.next_if(|&offset| offset < last_end).is_some() {
+            changed = true;
+        }
+        if !changed {
+            continue;
+        }
+        match entry.flags {
+            Some((start, end)) if content[start + 2..end].split(',').any(|flag| flag.trim() == "fuzzy") => {}
+            Some((start, end)) => {
+                let line = content[start..end].trim_end();
+                flags.push((start, start + line.len(), format!("{}, fuzzy", line)));
+            }
+            None => {
+                let at = entry.flags_at.unwrap_or_default();
+                flags.push((at, at, "#, fuzzy\n".to_string()));
+            }
+        }
+    }
+
+    let mut result = String::with_capacity(content.len() + flags.len() * 9);
+    let mut last = 0;
+    let mut flags = flags.into_iter().peekable();
+    let mut replace_at = |result: &mut String, last: &mut usize, until: usize| {
+        while let Some((start, end, text)) = flags.next_if(|&(start, _, _)| start <= until) {
+            result.push_str(&content[*last..start]);
+            result.push_str(&text);
+            *last = end;
+        }
+    };
+    for &offset in offsets {
+        replace_at(&mut result, &mut last, offset);
+        result.push_str(&content[last..offset]);
+        result.push_str(replace_text);
+        last = offset + find_len;
+    }
+    replace_at(&mut result, &mut last, content.len());
+    result.push_str(&content[last..]);
+    result
+}
+
+/// Whether a match in a string starts and ends between characters rather than
+/// within an escape sequence such as `\n`.
+fn outside_escapes(content: &str, start: usize, offset: usize, end: usize) -> bool {
+    let bytes = content.as_bytes();
+    let mut position = start;
+    let mut on_offset = false;
+    while position < end {
+        on_offset |= position == offset;
+        position += if bytes[position] == b'\\' { 2 } else { 1 };
+    }
+    on_offset && position == end
+}
+
+/// Reads the entries of a catalog.
+fn entries(content: &str) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut entry = Entry::default();
+    // The keyword the strings on the lines being read belong to
+    let mut keyword = "";
+    // Whether the entry has reached its keywords, so a comment starts
+    // another
+    let mut in_keywords = false;
+    let mut line_start = 0;
+    for line in content.split_inclusive('\n') {
+        let start = line_start;
+        line_start += line.len();
+        let text = line.trim();
+
+        let starts_entry = in_keywords && (text.starts_with('#') || text.starts_with("msgctxt") || (text.starts_with("msgid ") && keyword != "msgctxt"));
+        if starts_entry || text.is_empty() {
+            if in_keywords {
+                entries.push(std::mem::take(&mut entry));
+                in_keywords = false;
+            }
+            keyword = "";
+            if text.is_empty() {
+                continue;
+            }
+        }
+        if text.starts_with("#,") {
+            entry.flags = Some((start, start + line.trim_end_matches(['\n', '\r']).len()));
+            continue;
+        }
+        if text.starts_with('#') {
+            if text.starts_with("#|") && entry.flags_at.is_none() {
+                entry.flags_at = Some(start);
+            }
+            continue;
+        }
+        if !in_keywords {
+            in_keywords = true;
+            entry.flags_at.get_or_insert(start);
+        }
+
+        let indent = line.len() - line.trim_start().len();
+        let literal_at = match text.find('"') {
+            Some(0) => indent,
+            Some(at) => {
+                keyword = text[..at].trim_end();
+                if keyword == "msgid" {
+                    entry.header = text[at..].trim_end() == "\"\"";
+                }
+                indent + at
+            }
+            None => continue,
+        };
+        if keyword == "msgid" && literal_at == indent && text != "\"\"" {
+            // A msgid continued over several lines isn't the header's
+            entry.header = false;
+        }
+        if keyword.starts_with("msgstr") {
+            let literal = &line[literal_at..];
+            let bytes = literal.as_bytes();
+            let mut end = 1;
+            while end < bytes.len() && bytes[end] != b'"' {
+                end += if bytes[end] == b'\\' { 2 } else { 1 };
+            }
+            if end < bytes.len() && end > 1 {
+                entry.strings.push((start + literal_at + 1, start + literal_at + end));
+            }
+        }
+    }
+    if in_keywords {
+        entries.push(entry);
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::match_offsets;
+
+    const CATALOG: &str = r#"msgid ""
+msgstr ""
+"Project-Id-Version: Widget 1.0\n"
+
+#: src/main.c:10
+msgid "Widget"
+msgstr "Widget"
+
+#: src/main.c:12
+#, c-format
+msgid "Open %s Widget"
+msgid_plural "Open %s Widgets"
+msgstr[0] "Ouvrir %s Widget"
+msgstr[1] "Ouvrir "
+"%s Widgets\n"
+
+#~ msgid "Widget"
+#~ msgstr "Widget"
+"#;
+
+    #[test]
+    fn test_msgstr_offsets() {
+        let offsets = msgstr_offsets(CATALOG, &match_offsets(CATALOG, "Widget"), 6, "Gadget");
+        assert_eq!(crate::splice(CATALOG, &offsets, 6, "Gadget"), CATALOG
+            .replace("\nmsgstr \"Widget\"", "\nmsgstr \"Gadget\"")
+            .replace("Ouvrir %s Widget", "Ouvrir %s Gadget")
+            .replace("\"%s Widgets", "\"%s Gadgets"));
+        // A replacement that would need escaping is left out
+        assert!(msgstr_offsets(CATALOG, &match_offsets(CATALOG, "Widget"), 6, "\"G\"").is_empty());
+        // As is a match that splits an escape sequence
+        assert!(msgstr_offsets(CATALOG, &match_offsets(CATALOG, "n\""), 2, "x").is_empty());
+    }
+
+    #[test]
+    fn test_splice_fuzzy() {
+        let offsets = msgstr_offsets(CATALOG, &match_offsets(CATALOG, "Widget"), 6, "Gadget");
+        assert_eq!(splice_fuzzy(CATALOG, &offsets, 6, "Gadget"), CATALOG
+            .replace("#: src/main.c:10\n", "#: src/main.c:10\n#, fuzzy\n")
+            .replace("#, c-format", "#, c-format, fuzzy")
+            .replace("\nmsgstr \"Widget\"", "\nmsgstr \"Gadget\"")
+            .replace("Ouvrir %s Widget", "Ouvrir %s Gadget")
+            .replace("\"%s Widgets", "\"%s Gadgets"));
+
+        let catalog = "#| msgid \"Old\"\nmsgid \"Old\"\nmsgstr \"Old\"\n";
+        let offsets = msgstr_offsets(catalog, &match_offsets(catalog, "Old"), 3, "New");
+        assert_eq!(splice_fuzzy(catalog, &offsets, 3, "New"), "#, fuzzy\n#| msgid \"Old\"\nmsgid \"Old\"\nmsgstr \"New\"\n");
+    }
+
+    #[test]
+    fn test_is_po() {
+        assert!(is_po(Path::new("po/fr.po")));
+        assert!(is_po(Path::new("po/widget.POT")));
+        assert!(!is_po(Path::new("po/Makefile")));
+    }
+}
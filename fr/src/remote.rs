@@ -0,0 +1,177 @@
+//! Find and replace over a tree on another machine, given as
+//! `ssh://[user@]host[:port]/path` or `sftp://...`, without fr installed there.
+//!
+//! Files are listed, read and written through the `ssh` command as a
+//! `FileSystem`, so the remote machine only needs a POSIX shell with `find`,
+//! `cat`, `cp` and `mv`. Every command shares one connection through an
+//! OpenSSH control socket, so there is a single login however many files are
+//! read, and `ssh` picks up the user's keys, agent and `~/.ssh/config` as it
+//! would for any other command.
+
+use crate::vfs::FileSystem;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+
+/// A tree on another machine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Remote {
+    /// The host, and the user to log in as if one was given
+    destination: String,
+    port: Option<u16>,
+    /// The root of the tree, relative to the home directory unless absolute
+    path: String,
+}
+
+impl Remote {
+    /// Parses an `ssh://` or `sftp://` URL.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The URL, such as `sftp://deploy@web1:2222/etc/app`
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<Remote>, String>` - The tree, None if the text isn't
+    ///   an ssh or sftp URL, or an error if it is one but is malformed
+    pub fn parse(url: &str) -> Result<Option<Remote>, String> {
+        let Some(rest) = url.strip_prefix("ssh://").or_else(|| url.strip_prefix("sftp://")) else {
+            return Ok(None);
+        };
+        let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        let (destination, port) = match authority.rsplit_once(':') {
+            Some((destination, port)) => {
+                let port = port.parse().map_err(|_| format!("Invalid port in {}", url))?;
+                (destination, Some(port))
+            }
+            None => (authority, None),
+        };
+        if destination.is_empty() || destination.ends_with('@') || destination.starts_with('-') {
+            return Err(format!("Missing host in {}", url));
+        }
+        // As with scp, a path starting /~/ is in the home directory
+        let path = match path.strip_prefix("/~") {
+            _ if path.is_empty() || path == "/~" => ".".to_string(),
+            Some(home) => format!(".{}", home),
+            None => path.to_string(),
+        };
+        Ok(Some(Remote { destination: destination.to_string(), port, path }))
+    }
+
+    /// The host and path, the way a path on this machine would be displayed.
+    pub fn display(&self, path: &Path) -> String {
+        format!("{}:{}", self.destination, path.display())
+    }
+}
+
+/// The files of a remote tree, reached through `ssh`
+pub struct SshFileSystem {
+    remote: Remote,
+    control_path: PathBuf,
+}
+
+impl SshFileSystem {
+    /// Connects to a remote tree, logging in once for every later command.
+    pub fn connect(remote: Remote) -> io::Result<SshFileSystem> {
+        let control_path = std::env::temp_dir().join(format!("fr-ssh-{}", process::id()));
+        let file_system = SshFileSystem { remote, control_path };
+        let status = file_system.command(&["-o", "ControlMaster=yes", "-o", "ControlPersist=600", "-N", "-f"]).status()?;
+        if !status.success() {
+            return Err(io::Error::other(format!("ssh {} failed with {}", file_system.remote.destination, status)));
+        }
+        Ok(file_system)
+    }
+
+    /// Builds an `ssh` command over the shared connection.
+    fn command(&self, options: &[&str]) -> Command {
+        let mut command = Command::new("ssh");
+        command.arg("-o").arg(format!("ControlPath={}", self.control_path.display()));
+        command.args(options);
+        if let Some(port) = self.remote.port {
+            command.arg("-p").arg(port.to_string());
+        }
+        command.arg("--").arg(&self.remote.destination);
+        command
+    }
+
+    /// Runs a shell script on the remote machine, writing `input` to its
+    /// stdin, and returns its stdout.
+    fn run(&self, script: &str, input: Option<&[u8]>) -> io::Result<Vec<u8>> {
+        let mut child = self.command(&["-o", "ControlMaster=no", "-T"])
+            .arg(script)
+            .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+            stdin.write_all(input)?;
+        }
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            let message = String::from_utf8_lossy(&output.stderr);
+            return Err(io::Error::other(message.trim().to_string()));
+        }
+        Ok(output.stdout)
+    }
+}
+
+impl Drop for SshFileSystem {
+    fn drop(&mut self) {
+        let _ = self.command(&["-O", "exit"]).stderr(Stdio::null()).status();
+    }
+}
+
+impl FileSystem for SshFileSystem {
+    fn files(&self) -> io::Result<Vec<PathBuf>> {
+        // Hidden files and directories are skipped, as they are locally
+        let script = format!("find {} -mindepth 1 -name '.*' -prune -o -type f -print0", quote(&self.remote.path));
+        let listing = self.run(&script, None)?;
+        Ok(listing.split(|&byte| byte == 0).filter(|path| !path.is_empty())
+            .map(|path| PathBuf::from(String::from_utf8_lossy(path).into_owned()))
+            .collect())
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.run(&format!("cat -- {}", quote(&path.to_string_lossy())), None)
+    }
+
+    fn write(&mut self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        // Written next to the file and renamed into place, keeping its
+        // permissions, as a local file is
+        let path = path.to_string_lossy();
+        let (directory, name) = path.rsplit_once('/').unwrap_or((".", &path));
+        let temp = quote(&format!("{}/.{}.fr-{}.tmp", directory, name, process::id()));
+        let path = quote(&path);
+        let script = format!("cp -p -- {path} {temp} && cat > {temp} && mv -f -- {temp} {path} || {{ rm -f -- {temp}; exit 1; }}");
+        self.run(&script, Some(contents)).map(|_| ())
+    }
+}
+
+/// Quotes text for a POSIX shell.
+fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let remote = Remote::parse("sftp://deploy@web1:2222/etc/app").unwrap().unwrap();
+        assert_eq!(remote, Remote { destination: "deploy@web1".to_string(), port: Some(2222), path: "/etc/app".to_string() });
+        assert_eq!(remote.display(Path::new("/etc/app/a.conf")), "deploy@web1:/etc/app/a.conf");
+        assert_eq!(Remote::parse("ssh://web1").unwrap().unwrap().path, ".");
+        assert_eq!(Remote::parse("ssh://web1/~/src").unwrap().unwrap().path, "./src");
+
+        assert_eq!(Remote::parse("src").unwrap(), None);
+        for invalid in ["ssh://", "ssh://web1:port/etc", "ssh://user@/etc", "ssh://-oProxyCommand=x/etc"] {
+            assert!(Remote::parse(invalid).is_err(), "{:?} should not parse", invalid);
+        }
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("/srv/it's here"), r#"'/srv/it'\''s here'"#);
+    }
+}
@@ -0,0 +1,358 @@
+//! Reports describing a finished run, for attaching to reviews and tickets.
+
+use crate::color::{Painter, Role};
+use crate::{write_atomically, Summary};
+use std::fmt::Write as _;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// A report to produce once a run has finished
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Report {
+    /// A self-contained HTML page written to the given path
+    Html(PathBuf),
+    /// A per-file histogram of added and removed lines, printed to stdout
+    Diffstat,
+    /// One CSV row per replacement written to the given path
+    Csv(PathBuf),
+}
+
+impl Report {
+    /// Parses the value given to `--report`, such as `html:report.html`.
+    pub fn parse(value: &str) -> Result<Report, String> {
+        let (kind, path) = match value.split_once(':') {
+            Some((kind, path)) => (kind, Some(path)),
+            None => (value, None),
+        };
+        match (kind, path) {
+            ("html", Some(path)) if !path.is_empty() => Ok(Report::Html(PathBuf::from(path))),
+            ("html", _) => Err("The html report needs a path, as in --report html:PATH".to_string()),
+            ("diffstat", None) => Ok(Report::Diffstat),
+            ("csv", Some(path)) if !path.is_empty() => Ok(Report::Csv(PathBuf::from(path))),
+            ("csv", _) => Err("The csv report needs a path, as in --report csv:PATH".to_string()),
+            _ => Err(format!("Unknown report '{}', expected one of: html:PATH, diffstat, csv:PATH", value)),
+        }
+    }
+
+    /// Whether the report shows the changed lines of each file
+    pub fn needs_hunks(&self) -> bool {
+        match self {
+            Report::Html(_) | Report::Diffstat => true,
+            Report::Csv(_) => false,
+        }
+    }
+
+    /// Whether the report shows where each replacement was made
+    pub fn needs_locations(&self) -> bool {
+        match self {
+            Report::Csv(_) => true,
+            Report::Html(_) | Report::Diffstat => false,
+        }
+    }
+
+    /// Produces the report for a finished run.
+    ///
+    /// # Arguments
+    ///
+    /// * `summary` - What happened during the run
+    /// * `find_text` - Text that was found
+    /// * `replace_text` - Text it was replaced with
+    /// * `painter` - Colors for reports printed to stdout
+    pub fn write(&self, summary: &Summary, find_text: &str, replace_text: &str, painter: &Painter) -> io::Result<()> {
+        match self {
+            Report::Html(path) => write_atomically(path, html(summary, find_text, replace_text).as_bytes()),
+            Report::Diffstat => io::stdout().lock().write_all(diffstat(summary, painter).as_bytes()),
+            Report::Csv(path) => write_atomically(path, csv(summary, find_text, replace_text).as_bytes()),
+        }
+    }
+}
+
+/// Escapes text for inclusion in HTML element content or attribute values.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn escape_path(path: &Path) -> String {
+    escape_html(&path.display().to_string())
+}
+
+const HTML_STYLE: &str = "\
+body { font-family: -apple-system, 'Segoe UI', Helvetica, Arial, sans-serif; margin: 2em; color: #24292f; }
+code, pre, .diff { font-family: ui-monospace, Menlo, Consolas, monospace; font-size: 13px; }
+table { border-collapse: collapse; }
+th, td { text-align: left; padding: 2px 12px 2px 0; }
+details { margin: 0.5em 0; border: 1px solid #d0d7de; border-radius: 6px; }
+summary { padding: 6px 10px; background: #f6f8fa; cursor: pointer; }
+.diff { width: 100%; }
+.diff td { padding: 0 8px; white-space: pre-wrap; }
+.diff .num { color: #6e7781; text-align: right; user-select: none; width: 1%; }
+.diff .del { background: #ffebe9; }
+.diff .add { background: #e6ffec; }
+.diff .gap td { background: #ddf4ff; color: #6e7781; }
+";
+
+/// Renders the HTML report for a finished run.
+fn html(summary: &Summary, find_text: &str, replace_text: &str) -> String {
+    let replacements: usize = summary.modified.iter().map(|file| file.replacements).sum();
+    let mut out = String::new();
+    out.push_str("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n");
+    out.push_str("<title>fr report</title>\n<style>\n");
+    out.push_str(HTML_STYLE);
+    out.push_str("</style>\n</head>\n<body>\n<h1>fr report</h1>\n");
+    let _ = writeln!(out, "<p>Replaced <code>{}</code> with <code>{}</code>.</p>",
+        escape_html(find_text),
+        escape_html(replace_text));
+    let _ = writeln!(out, "<table>\n<tr><th>Files modified</th><td>{}</td></tr>\n<tr><th>Replacements</th><td>{}</td></tr>\n<tr><th>Files skipped</th><td>{}</td></tr>\n<tr><th>Files without matches</th><td>{}</td></tr>\n<tr><th>Errors</th><td>{}</td></tr>\n</table>",
+        summary.modified.len(),
+        replacements,
+        summary.skipped.len(),
+        summary.unmatched,
+        summary.errors.len());
+
+    if !summary.modified.is_empty() {
+        out.push_str("<h2>Modified files</h2>\n");
+    }
+    for file in &summary.modified {
+        let _ = writeln!(out, "<details open>\n<summary><code>{}</code> ({} replacement{})</summary>\n<table class=\"diff\">",
+            escape_path(&file.path),
+            file.replacements,
+            if file.replacements == 1 { "" } else { "s" });
+        for (i, hunk) in file.hunks.iter().enumerate() {
+            if i > 0 {
+                out.push_str("<tr class=\"gap\"><td class=\"num\"></td><td class=\"num\"></td><td>&#8943;</td></tr>\n");
+            }
+            for (offset, line) in hunk.removed.iter().enumerate() {
+                let _ = writeln!(out, "<tr class=\"del\"><td class=\"num\">{}</td><td class=\"num\"></td><td>-{}</td></tr>",
+                    hunk.old_start + offset,
+                    escape_html(line));
+            }
+            for (offset, line) in hunk.added.iter().enumerate() {
+                let _ = writeln!(out, "<tr class=\"add\"><td class=\"num\"></td><td class=\"num\">{}</td><td>+{}</td></tr>",
+                    hunk.new_start + offset,
+                    escape_html(line));
+            }
+        }
+        out.push_str("</table>\n</details>\n");
+    }
+
+    if !summary.skipped.is_empty() {
+        out.push_str("<h2>Skipped files</h2>\n<table>\n<tr><th>File</th><th>Reason</th></tr>\n");
+        for (path, reason) in &summary.skipped {
+            let _ = writeln!(out, "<tr><td><code>{}</code></td><td>{}</td></tr>", escape_path(path), escape_html(&reason.to_string()));
+        }
+        out.push_str("</table>\n");
+    }
+
+    if !summary.errors.is_empty() {
+        out.push_str("<h2>Errors</h2>\n<table>\n<tr><th>File</th><th>Error</th></tr>\n");
+        for (path, error) in &summary.errors {
+            let _ = writeln!(out, "<tr><td><code>{}</code></td><td>{}</td></tr>", escape_path(path), escape_html(error));
+        }
+        out.push_str("</table>\n");
+    }
+
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+/// The widest the +/- graph of a diffstat line gets before it is scaled down
+const DIFFSTAT_GRAPH_WIDTH: usize = 50;
+
+/// Renders a `git diff --stat` style summary of the lines each file gained and lost.
+fn diffstat(summary: &Summary, painter: &Painter) -> String {
+    let counts: Vec<(String, usize, usize)> = summary.modified.iter().map(|file| {
+        let added = file.hunks.iter().map(|hunk| hunk.added.len()).sum();
+        let removed = file.hunks.iter().map(|hunk| hunk.removed.len()).sum();
+        (file.path.display().to_string(), added, removed)
+    }).collect();
+
+    let name_width = counts.iter().map(|(name, _, _)| name.chars().count()).max().unwrap_or(0);
+    let largest = counts.iter().map(|(_, added, removed)| added + removed).max().unwrap_or(0);
+    let count_width = largest.to_string().len();
+
+    let mut out = String::new();
+    for (name, added, removed) in &counts {
+        let (mut plus, mut minus) = (*added, *removed);
+        if largest > DIFFSTAT_GRAPH_WIDTH {
+            // Scale the bars down, but never hide a non-zero count entirely
+            plus = (added * DIFFSTAT_GRAPH_WIDTH).div_ceil(largest);
+            minus = (removed * DIFFSTAT_GRAPH_WIDTH).div_ceil(largest);
+        }
+        let _ = writeln!(out, " {:<name_width$} | {:>count_width$} {}{}",
+            name,
+            added + removed,
+            painter.paint(Role::Added, &"+".repeat(plus)),
+            painter.paint(Role::Removed, &"-".repeat(minus)));
+    }
+
+    let insertions: usize = counts.iter().map(|(_, added, _)| added).sum();
+    let deletions: usize = counts.iter().map(|(_, _, removed)| removed).sum();
+    let _ = writeln!(out, " {} file{} changed, {} insertion{}(+), {} deletion{}(-)",
+        counts.len(),
+        if counts.len() == 1 { "" } else { "s" },
+        insertions,
+        if insertions == 1 { "" } else { "s" },
+        deletions,
+        if deletions == 1 { "" } else { "s" });
+    out
+}
+
+/// Quotes a CSV field if it contains a delimiter, quote, or line break.
+fn escape_csv(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Renders one CSV row per replacement, with a header row first.
+fn csv(summary: &Summary, find_text: &str, replace_text: &str) -> String {
+    let mut out = String::from("path,line,column,matched,replacement\r\n");
+    let (matched, replacement) = (escape_csv(find_text), escape_csv(replace_text));
+    for file in &summary.modified {
+        let path = escape_csv(&file.path.display().to_string());
+        for (line, column) in &file.locations {
+            let _ = write!(out, "{},{},{},{},{}\r\n", path, line, column, matched, replacement);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::{ColorChoice, Theme};
+    use crate::diff::Hunk;
+    use crate::{ModifiedFile, SkipReason};
+
+    #[test]
+    fn test_parse_report() {
+        assert_eq!(Report::parse("html:out/report.html").unwrap(), Report::Html(PathBuf::from("out/report.html")));
+        assert!(Report::parse("html").is_err());
+        assert!(Report::parse("html:").is_err());
+        assert!(Report::parse("pdf:report.pdf").is_err());
+        assert_eq!(Report::parse("diffstat").unwrap(), Report::Diffstat);
+        assert!(Report::parse("diffstat:out.txt").is_err());
+        assert_eq!(Report::parse("csv:edits.csv").unwrap(), Report::Csv(PathBuf::from("edits.csv")));
+        assert!(Report::parse("csv").is_err());
+    }
+
+    #[test]
+    fn test_escape_html() {
+        assert_eq!(escape_html("<a href=\"x\">Tom & Jerry's</a>"), "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;");
+    }
+
+    fn modified_file(path: &str, removed: usize, added: usize) -> ModifiedFile {
+        ModifiedFile {
+            path: PathBuf::from(path),
+            replacements: removed,
+            hunks: vec![Hunk {
+                old_start: 1,
+                new_start: 1,
+                removed: vec![String::new(); removed],
+                added: vec![String::new(); added],
+            }],
+            locations: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_diffstat() {
+        let summary = Summary {
+            modified: vec![modified_file("a.txt", 2, 2), modified_file("src/long_name.rs", 1, 3)],
+            ..Summary::default()
+        };
+        assert_eq!(diffstat(&summary, &Painter::default()), concat!(
+            " a.txt            | 4 ++--\n",
+            " src/long_name.rs | 4 +++-\n",
+            " 2 files changed, 5 insertions(+), 3 deletions(-)\n",
+        ));
+    }
+
+    #[test]
+    fn test_diffstat_scales_large_changes() {
+        let summary = Summary {
+            modified: vec![modified_file("big.txt", 200, 200), modified_file("small.txt", 1, 1)],
+            ..Summary::default()
+        };
+        let stat = diffstat(&summary, &Painter::default());
+        let lines: Vec<&str> = stat.lines().collect();
+        assert_eq!(lines[0], format!(" big.txt   | 400 {}{}", "+".repeat(25), "-".repeat(25)));
+        assert_eq!(lines[1], " small.txt |   2 +-");
+    }
+
+    #[test]
+    fn test_diffstat_colors() {
+        let summary = Summary { modified: vec![modified_file("a.txt", 1, 1)], ..Summary::default() };
+        let painter = Painter::new(ColorChoice::Always, Theme::Default, false);
+        assert!(diffstat(&summary, &painter).starts_with(" a.txt | 2 \x1b[32m+\x1b[0m\x1b[31m-\x1b[0m\n"));
+    }
+
+    #[test]
+    fn test_escape_csv() {
+        assert_eq!(escape_csv("plain"), "plain");
+        assert_eq!(escape_csv("a,b"), "\"a,b\"");
+        assert_eq!(escape_csv("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(escape_csv("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn test_csv_report() {
+        let summary = Summary {
+            modified: vec![ModifiedFile {
+                path: PathBuf::from("docs/a,b.md"),
+                replacements: 2,
+                hunks: Vec::new(),
+                locations: vec![(1, 5), (3, 1)],
+            }],
+            ..Summary::default()
+        };
+        assert_eq!(csv(&summary, "master", "main, primary"), concat!(
+            "path,line,column,matched,replacement\r\n",
+            "\"docs/a,b.md\",1,5,master,\"main, primary\"\r\n",
+            "\"docs/a,b.md\",3,1,master,\"main, primary\"\r\n",
+        ));
+    }
+
+    #[test]
+    fn test_html_report() {
+        let summary = Summary {
+            modified: vec![ModifiedFile {
+                path: PathBuf::from("src/<main>.rs"),
+                replacements: 2,
+                hunks: vec![Hunk {
+                    old_start: 3,
+                    new_start: 3,
+                    removed: vec!["let foo = foo;".to_string()],
+                    added: vec!["let bar = bar;".to_string()],
+                }],
+                locations: Vec::new(),
+            }],
+            skipped: vec![(PathBuf::from("logo.png"), SkipReason::Binary)],
+            errors: vec![(PathBuf::from("locked.txt"), "Permission denied".to_string())],
+            unmatched: 7,
+        };
+
+        let page = html(&summary, "foo", "bar");
+        assert!(page.starts_with("<!DOCTYPE html>"));
+        assert!(page.contains("<code>src/&lt;main&gt;.rs</code> (2 replacements)"));
+        assert!(page.contains("<td class=\"num\">3</td><td class=\"num\"></td><td>-let foo = foo;</td>"));
+        assert!(page.contains("<td class=\"num\"></td><td class=\"num\">3</td><td>+let bar = bar;</td>"));
+        assert!(page.contains("<code>logo.png</code></td><td>binary file</td>"));
+        assert!(page.contains("<code>locked.txt</code></td><td>Permission denied</td>"));
+        assert!(page.contains("<tr><th>Files without matches</th><td>7</td></tr>"));
+    }
+}
@@ -0,0 +1,117 @@
+//! Retrying reads and writes that fail for reasons that may pass.
+//!
+//! A file that is busy, locked by another program for a moment, or on a
+//! network filesystem that briefly stops answering often works a few
+//! milliseconds later, so such errors are retried with a growing pause
+//! before the file is reported as failed. Other errors, such as a missing
+//! file or a denied permission, fail straight away.
+
+use std::io;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+/// How many times a failed read or write is retried by default
+pub const DEFAULT_RETRIES: u32 = 3;
+
+/// The pause before the first retry, which doubles with each one after
+const FIRST_PAUSE: Duration = Duration::from_millis(10);
+
+/// The longest pause between retries
+const MAX_PAUSE: Duration = Duration::from_secs(1);
+
+/// Windows' ERROR_SHARING_VIOLATION and ERROR_LOCK_VIOLATION, when another
+/// program has the file open
+const SHARING_VIOLATIONS: [i32; 2] = [32, 33];
+
+/// Whether an error is because another program, such as a virus scanner or
+/// an editor, has the file open and locked. Only Windows locks files this
+/// way.
+pub fn is_in_use(e: &io::Error) -> bool {
+    cfg!(windows) && e.raw_os_error().is_some_and(|code| SHARING_VIOLATIONS.contains(&code))
+}
+
+/// Whether an error may go away if the same thing is tried again.
+pub fn is_transient(e: &io::Error) -> bool {
+    is_in_use(e) || matches!(e.kind(),
+        io::ErrorKind::Interrupted
+        | io::ErrorKind::WouldBlock
+        | io::ErrorKind::TimedOut
+        | io::ErrorKind::ResourceBusy
+        | io::ErrorKind::StaleNetworkFileHandle)
+}
+
+/// Runs an operation on a file, running it again after a pause each time it
+/// fails with a transient error, until it has been retried `retries` times.
+///
+/// # Arguments
+///
+/// * `path` - The file being read or written, for logging
+/// * `retries` - How many times to retry
+/// * `operation` - What to do with the file
+///
+/// # Returns
+///
+/// * `io::Result<T>` - What the operation returned the last time it was run
+pub fn retrying<T>(path: &Path, retries: u32, mut operation: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    let mut pause = FIRST_PAUSE;
+    for attempt in 1..=retries {
+        match operation() {
+            Err(e) if is_transient(&e) => {
+                log::debug!("{}: {}, retrying ({} of {})", path.display(), e, attempt, retries);
+                thread::sleep(pause);
+                pause = (pause * 2).min(MAX_PAUSE);
+            }
+            result => return result,
+        }
+    }
+    operation()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_transient() {
+        assert!(is_transient(&io::Error::from(io::ErrorKind::ResourceBusy)));
+        assert!(is_transient(&io::Error::from(io::ErrorKind::WouldBlock)));
+        assert!(!is_transient(&io::Error::from(io::ErrorKind::NotFound)));
+        assert!(!is_transient(&io::Error::from(io::ErrorKind::PermissionDenied)));
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn test_is_in_use() {
+        assert!(is_in_use(&io::Error::from_raw_os_error(32)));
+        assert!(is_transient(&io::Error::from_raw_os_error(33)));
+        assert!(!is_in_use(&io::Error::from_raw_os_error(5)));
+    }
+
+    #[test]
+    fn test_retrying() {
+        let mut attempts = 0;
+        let result = retrying(Path::new("a.txt"), 3, || {
+            attempts += 1;
+            match attempts {
+                1 | 2 => Err(io::Error::from(io::ErrorKind::ResourceBusy)),
+                _ => Ok(attempts),
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        // Errors that won't pass, and transient ones past the last retry, fail
+        let mut attempts = 0;
+        assert!(retrying(Path::new("a.txt"), 3, || -> io::Result<()> {
+            attempts += 1;
+            Err(io::Error::from(io::ErrorKind::NotFound))
+        }).is_err());
+        assert_eq!(attempts, 1);
+        let mut attempts = 0;
+        assert!(retrying(Path::new("a.txt"), 2, || -> io::Result<()> {
+            attempts += 1;
+            Err(io::Error::from(io::ErrorKind::TimedOut))
+        }).is_err());
+        assert_eq!(attempts, 3);
+    }
+}
@@ -0,0 +1,221 @@
+//! Replacing the matches ripgrep found, for `fr apply-rg`.
+//!
+//! ripgrep searches with regexes, file types and more that fr doesn't have,
+//! but never writes. `rg --json` gives the byte offsets of every match, so fr
+//! can replace exactly those bytes, checking first that each still holds the
+//! text ripgrep matched, in case the file has changed since it was searched.
+
+use crate::json::Value;
+use crate::{retry, ModifiedFile, PendingWrite, Summary};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// A match ripgrep found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    /// The bytes of the file that matched
+    pub range: Range<usize>,
+    /// The text that matched
+    pub text: String,
+}
+
+/// Reads the matches from `rg --json` output, by file. Lines other than
+/// matches, such as ripgrep's context lines and statistics, are passed over.
+///
+/// # Arguments
+///
+/// * `input` - The output of `rg --json`
+///
+/// # Returns
+///
+/// * `Result<Vec<(PathBuf, Vec<Match>)>, String>` - Each file with a match and
+///   its matches, in the order ripgrep listed them, or an error describing the
+///   first line that couldn't be read
+pub fn parse(input: impl BufRead) -> Result<Vec<(PathBuf, Vec<Match>)>, String> {
+    let mut files: Vec<(PathBuf, Vec<Match>)> = Vec::new();
+    let mut indexes: HashMap<PathBuf, usize> = HashMap::new();
+    for (number, line) in input.lines().enumerate() {
+        let line = line.map_err(|e| format!("Failed to read stdin: {}", e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let error = |message: &str| format!("Line {} of the ripgrep output {}", number + 1, message);
+        let value = Value::parse(&line).map_err(|e| error(&format!("isn't JSON: {}", e)))?;
+        if value.get("type").and_then(Value::as_str) != Some("match") {
+            continue;
+        }
+        let (path, matches) = match_line(&value).map_err(error)?;
+        let index = *indexes.entry(path.clone()).or_insert_with(|| {
+            files.push((path, Vec::new()));
+            files.len() - 1
+        });
+        files[index].1.extend(matches);
+    }
+    Ok(files)
+}
+
+/// Reads the path and matches of a line of type `match`.
+fn match_line(value: &Value) -> Result<(PathBuf, Vec<Match>), &'static str> {
+    let data = value.get("data").ok_or("has no data")?;
+    // Paths and text that aren't UTF-8 are given as base64 "bytes" instead
+    let path = data.get("path").and_then(|path| path.get("text")).and_then(Value::as_str)
+        .ok_or("has a path that isn't UTF-8, which fr can't apply")?;
+    let offset = data.get("absolute_offset").and_then(as_offset).ok_or("has no absolute_offset")?;
+    let Some(Value::Array(submatches)) = data.get("submatches") else {
+        return Err("has no submatches");
+    };
+    let mut matches = Vec::new();
+    for submatch in submatches {
+        let (Some(start), Some(end)) = (submatch.get("start").and_then(as_offset), submatch.get("end").and_then(as_offset)) else {
+            return Err("has a submatch without a start and end");
+        };
+        let text = submatch.get("match").and_then(|text| text.get("text")).and_then(Value::as_str)
+            .ok_or("has a match that isn't UTF-8, which fr can't apply")?;
+        if start > end || end - start != text.len() {
+            return Err("has a match whose length isn't its text's");
+        }
+        matches.push(Match { range: offset + start..offset + end, text: text.to_string() });
+    }
+    Ok((PathBuf::from(path), matches))
+}
+
+/// Reads a JSON number as a byte offset.
+fn as_offset(value: &Value) -> Option<usize> {
+    match value {
+        Value::Number(number) if *number >= 0.0 && number.fract() == 0.0 => Some(*number as usize),
+        _ => None,
+    }
+}
+
+/// Replaces ripgrep's matches in a file's contents.
+///
+/// # Arguments
+///
+/// * `contents` - The file's contents
+/// * `matches` - Where ripgrep found matches in the file
+/// * `replace_text` - Text to replace each match with
+///
+/// # Returns
+///
+/// * `Result<Vec<u8>, String>` - The new contents, or an error if a match
+///   isn't in the file as ripgrep found it, in which case nothing is replaced
+pub fn replace(contents: &[u8], matches: &[Match], replace_text: &str) -> Result<Vec<u8>, String> {
+    let mut matches: Vec<&Match> = matches.iter().collect();
+    matches.sort_by_key(|found| found.range.start);
+    let mut replaced = Vec::with_capacity(contents.len());
+    let mut copied = 0;
+    for found in matches {
+        if found.range.start < copied {
+            return Err(format!("ripgrep's matches overlap at byte {}", found.range.start));
+        }
+        if contents.get(found.range.clone()) != Some(found.text.as_bytes()) {
+            return Err(format!("{:?} is no longer at byte {}, so the file has changed since ripgrep searched it",
+                found.text, found.range.start));
+        }
+        replaced.extend_from_slice(&contents[copied..found.range.start]);
+        replaced.extend_from_slice(replace_text.as_bytes());
+        copied = found.range.end;
+    }
+    replaced.extend_from_slice(&contents[copied..]);
+    Ok(replaced)
+}
+
+/// Replaces the matches ripgrep found in each file.
+///
+/// # Arguments
+///
+/// * `starting_directory` - The directory ripgrep's paths are relative to
+/// * `files` - Each file and its matches, from `parse`
+/// * `replace_text` - Text to replace each match with
+/// * `dry_run` - Only count the replacements that would be made
+/// * `sync` - Flush each rewritten file to disk, for `--fsync`
+/// * `retries` - How many times to retry reading or writing a file that is
+///   briefly unavailable
+///
+/// # Returns
+///
+/// * `Summary` - The files modified and the ones that failed, in the order
+///   ripgrep listed them
+pub fn apply(starting_directory: &Path, files: Vec<(PathBuf, Vec<Match>)>, replace_text: &str, dry_run: bool, sync: bool, retries: u32) -> Summary {
+    let mut summary = Summary::default();
+    for (display, matches) in files {
+        let path = starting_directory.join(&display);
+        let result = retry::retrying(&path, retries, || fs::read(&path)).and_then(|contents| {
+            let contents = replace(&contents, &matches, replace_text).map_err(io::Error::other)?;
+            match dry_run {
+                true => Ok(()),
+                false => {
+                    let pending = PendingWrite { path: path.clone(), contents, sync };
+                    retry::retrying(&path, retries, || pending.write())
+                }
+            }
+        });
+        match result {
+            Ok(()) => summary.modified.push(ModifiedFile {
+                path: display,
+                replacements: matches.len(),
+                hunks: Vec::new(),
+                locations: Vec::new(),
+            }),
+            Err(e) => summary.errors.push((display, e.to_string())),
+        }
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const OUTPUT: &str = r#"{"type":"begin","data":{"path":{"text":"src/main.rs"}}}
+{"type":"match","data":{"path":{"text":"src/main.rs"},"lines":{"text":"let foo = foo();\n"},"line_number":2,"absolute_offset":12,"submatches":[{"match":{"text":"foo"},"start":4,"end":7},{"match":{"text":"foo"},"start":10,"end":13}]}}
+{"type":"end","data":{"path":{"text":"src/main.rs"},"binary_offset":null,"stats":{}}}
+{"type":"summary","data":{"elapsed_total":{"secs":0,"nanos":1}}}
+"#;
+
+    #[test]
+    fn test_parse() {
+        let files = parse(OUTPUT.as_bytes()).unwrap();
+        assert_eq!(files, vec![(PathBuf::from("src/main.rs"), vec![
+            Match { range: 16..19, text: "foo".to_string() },
+            Match { range: 22..25, text: "foo".to_string() },
+        ])]);
+        assert!(parse("not json\n".as_bytes()).unwrap_err().starts_with("Line 1 of the ripgrep output isn't JSON"));
+        let bytes = r#"{"type":"match","data":{"path":{"bytes":"/w=="},"absolute_offset":0,"submatches":[]}}"#;
+        assert!(parse(bytes.as_bytes()).unwrap_err().contains("isn't UTF-8"));
+    }
+
+    #[test]
+    fn test_replace() {
+        let matches = [Match { range: 8..11, text: "foo".to_string() }, Match { range: 0..3, text: "foo".to_string() }];
+        assert_eq!(replace(b"foo and foo, not foo", &matches, "bar").unwrap(), b"bar and bar, not foo");
+        assert!(replace(b"fob and foo", &matches, "bar").unwrap_err().contains("no longer at byte 0"));
+        assert!(replace(b"foo", &matches, "bar").is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir(temp_dir.path().join("src")).unwrap();
+        let file = temp_dir.path().join("src/main.rs");
+        fs::write(&file, "fn main() {\nlet foo = foo();\n}\n").unwrap();
+        let files = parse(OUTPUT.as_bytes()).unwrap();
+
+        let summary = apply(temp_dir.path(), files.clone(), "bar", true, false, 0);
+        assert_eq!(summary.modified[0].replacements, 2);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "fn main() {\nlet foo = foo();\n}\n");
+
+        let summary = apply(temp_dir.path(), files.clone(), "bar", false, false, 0);
+        assert!(summary.errors.is_empty());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "fn main() {\nlet bar = bar();\n}\n");
+
+        // The matches are no longer where ripgrep found them
+        let summary = apply(temp_dir.path(), files, "baz", false, false, 0);
+        assert_eq!(summary.errors.len(), 1);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "fn main() {\nlet bar = bar();\n}\n");
+    }
+}
@@ -0,0 +1,351 @@
+//! Checking renames in Rust files for mistakes that would break the build,
+//! for `--check-rust`.
+//!
+//! The files are split into tokens, enough to tell identifiers from strings,
+//! comments and punctuation, and each block delimited by braces is taken as a
+//! scope. That is short of what the compiler knows, so the checks warn, and
+//! the files are changed all the same:
+//!
+//! * A match that is only part of an identifier, as `Foo` is of `FooBar`
+//! * A new name that is already used in the scope of a match, which would
+//!   then mean two things
+//! * A path whose last part is also imported in a group the find text can't
+//!   match, as `a::b::Thing` is in `use a::b::{Thing, Other}`
+
+use memchr::memmem;
+use std::ops::Range;
+use std::path::Path;
+
+/// Returns whether a file is Rust source, going by its extension.
+pub fn is_rust(file_path: &Path) -> bool {
+    file_path.extension().is_some_and(|extension| extension == "rs")
+}
+
+/// What a token is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Kind {
+    Ident,
+    /// A string, character or number
+    Literal,
+    /// `::` or any other single character, braces included
+    Punct,
+}
+
+/// A token of Rust source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Token {
+    pub(crate) kind: Kind,
+    pub(crate) range: Range<usize>,
+}
+
+/// Whether text is a single identifier.
+pub(crate) fn is_ident(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_') && chars.all(is_ident_char) && text != "_"
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Splits Rust source into tokens, leaving out comments and whitespace.
+pub(crate) fn tokens(source: &str) -> Vec<Token> {
+    let bytes = source.as_bytes();
+    let mut tokens = Vec::new();
+    let mut position = 0;
+    while let Some(c) = source[position..].chars().next() {
+        let rest = &source[position..];
+        let start = position;
+        let (kind, len) = if c.is_whitespace() {
+            position += c.len_utf8();
+            continue;
+        } else if rest.starts_with("//") {
+            position += rest.find('\n').unwrap_or(rest.len());
+            continue;
+        } else if rest.starts_with("/*") {
+            position += block_comment_len(rest);
+            continue;
+        } else if let Some(len) = raw_string_len(rest) {
+            (Kind::Literal, len)
+        } else if rest.starts_with("r#") && rest[2..].starts_with(|c: char| c.is_alphabetic() || c == '_') {
+            // A raw identifier, which is the identifier without its r#
+            let len = rest[2..].find(|c: char| !is_ident_char(c)).unwrap_or(rest.len() - 2);
+            tokens.push(Token { kind: Kind::Ident, range: start + 2..start + 2 + len });
+            position += 2 + len;
+            continue;
+        } else if c == '"' || ((c == 'b' || c == 'c') && rest[1..].starts_with('"')) {
+            let open = rest.find('"').unwrap_or(0) + 1;
+            (Kind::Literal, quoted_len(bytes, start + open, b'"') - start)
+        } else if c == '\'' || (c == 'b' && rest[1..].starts_with('\'')) {
+            let open = rest.find('\'').unwrap_or(0) + 1;
+            match char_len(&source[start + open..]) {
+                Some(len) => (Kind::Literal, open + len),
+                // A lifetime or a label, which isn't a name being renamed
+                None => {
+                    let len = rest[open..].find(|c: char| !is_ident_char(c)).unwrap_or(rest.len() - open);
+                    (Kind::Literal, open + len)
+                }
+            }
+        } else if c.is_ascii_digit() {
+            (Kind::Literal, rest.find(|c: char| !is_ident_char(c)).unwrap_or(rest.len()))
+        } else if c.is_alphabetic() || c == '_' {
+            (Kind::Ident, rest.find(|c: char| !is_ident_char(c)).unwrap_or(rest.len()))
+        } else if rest.starts_with("::") {
+            (Kind::Punct, 2)
+        } else {
+            (Kind::Punct, c.len_utf8())
+        };
+        tokens.push(Token { kind, range: start..start + len });
+        position += len;
+    }
+    tokens
+}
+
+/// The length of a block comment, which may have others inside it.
+fn block_comment_len(text: &str) -> usize {
+    let mut depth = 0;
+    let mut position = 0;
+    while position < text.len() {
+        if text[position..].starts_with("/*") {
+            depth += 1;
+            position += 2;
+        } else if text[position..].starts_with("*/") {
+            depth -= 1;
+            position += 2;
+            if depth == 0 {
+                return position;
+            }
+        } else {
+            position += text[position..].chars().next().map_or(1, char::len_utf8);
+        }
+    }
+    text.len()
+}
+
+/// The length of a raw string such as `r#"..."#` or `br"..."` at the start of
+/// the text, or None if it doesn't start with one.
+fn raw_string_len(text: &str) -> Option<usize> {
+    let after_prefix = text.strip_prefix("br").or_else(|| text.strip_prefix("cr")).or_else(|| text.strip_prefix('r'))?;
+    let hashes = after_prefix.len() - after_prefix.trim_start_matches('#').len();
+    let body = after_prefix[hashes..].strip_prefix('"')?;
+    let closing = format!("\"{}", "#".repeat(hashes));
+    let end = body.find(&closing).map_or(body.len(), |end| end + closing.len());
+    Some(text.len() - body.len() + end)
+}
+
+/// Where a quoted literal that starts at `start`, after its opening quote,
+/// ends, just past its closing quote.
+fn quoted_len(bytes: &[u8], start: usize, quote: u8) -> usize {
+    let mut position = start;
+    while position < bytes.len() {
+        match bytes[position] {
+            b'\\' => position += 2,
+            byte if byte == quote => return position + 1,
+            _ => position += 1,
+        }
+    }
+    bytes.len()
+}
+
+/// The length of a character literal after its opening quote, such as `a'`
+/// or `\n'`, or None if the quote starts a lifetime instead.
+fn char_len(text: &str) -> Option<usize> {
+    let mut chars = text.char_indices();
+    match chars.next()? {
+        (_, '\\') => Some(quoted_len(text.as_bytes(), 0, b'\'')),
+        (_, c) => text[c.len_utf8()..].starts_with('\'').then_some(c.len_utf8() + 1),
+    }
+}
+
+/// Something about a rename that would likely break the build
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    /// 1-based line of the text being renamed
+    pub line: usize,
+    /// 1-based byte column
+    pub column: usize,
+    pub message: String,
+}
+
+/// Checks a rename in a Rust file.
+///
+/// # Arguments
+///
+/// * `source` - The file before the rename
+/// * `find_text` - The name or path being renamed
+/// * `replace_text` - What it is renamed to
+///
+/// # Returns
+///
+/// * `Vec<Problem>` - What would likely go wrong, by line and column
+pub fn check(source: &str, find_text: &str, replace_text: &str) -> Vec<Problem> {
+    let tokens = tokens(source);
+    let idents: Vec<&Token> = tokens.iter().filter(|token| token.kind == Kind::Ident).collect();
+    let text = |token: &Token| &source[token.range.clone()];
+    let mut problems: Vec<(usize, String)> = Vec::new();
+
+    // The name at the end of each path, which is what is declared or imported
+    let find_name = find_text.rsplit("::").next().unwrap_or(find_text);
+    let replace_name = replace_text.rsplit("::").next().unwrap_or(replace_text);
+    let mut renamed = Vec::new();
+    for offset in memmem::find_iter(source.as_bytes(), find_text.as_bytes()) {
+        let name_start = offset + find_text.len() - find_name.len();
+        let before = idents.partition_point(|token| token.range.start <= name_start);
+        let Some(token) = before.checked_sub(1).map(|index| idents[index]).filter(|token| name_start < token.range.end) else {
+            // In a string or a comment, where it can't break anything
+            continue;
+        };
+        let partial = is_ident(find_name) && token.range != (name_start..name_start + find_name.len());
+        if partial {
+            problems.push((offset, format!("{:?} is only part of the identifier {}, which would become {}", find_name, text(token),
+                text(token).replacen(find_name, replace_name, 1))));
+        } else {
+            renamed.push(name_start);
+        }
+    }
+
+    if is_ident(find_name) && is_ident(replace_name) && find_name != replace_name {
+        let mut reported: Vec<Range<usize>> = Vec::new();
+        for &offset in &renamed {
+            let scope = scope(&tokens, source, offset);
+            if reported.contains(&scope) {
+                continue;
+            }
+            if let Some(existing) = idents.iter().find(|token| scope.contains(&token.range.start) && text(token) == replace_name) {
+                problems.push((offset, format!("{} is already used in the same scope, at line {}, so renaming {} would collide with it",
+                    replace_name, line_of(source, existing.range.start), find_name)));
+                reported.push(scope);
+            }
+        }
+    }
+
+    if let Some((prefix, _)) = find_text.rsplit_once("::").filter(|_| is_ident(find_name)) {
+        for (group, name) in grouped_imports(&tokens, source, prefix, find_name) {
+            problems.push((group, format!("{}::{{..}} imports {} at line {} without writing {}, so it wouldn't be renamed",
+                prefix, find_name, line_of(source, name), find_text)));
+        }
+    }
+
+    problems.sort_by_key(|(offset, _)| *offset);
+    problems.into_iter().map(|(offset, message)| {
+        let line_start = source[..offset].rfind('\n').map_or(0, |newline| newline + 1);
+        Problem { line: line_of(source, offset), column: offset - line_start + 1, message }
+    }).collect()
+}
+
+fn line_of(source: &str, offset: usize) -> usize {
+    source[..offset].matches('\n').count() + 1
+}
+
+/// The bytes of the innermost block around an offset, or the whole file if
+/// it isn't in one.
+fn scope(tokens: &[Token], source: &str, offset: usize) -> Range<usize> {
+    let mut open: Vec<usize> = Vec::new();
+    for token in tokens {
+        if token.range.start >= offset {
+            break;
+        }
+        match &source[token.range.clone()] {
+            "{" => open.push(token.range.start),
+            "}" => {
+                open.pop();
+            }
+            _ => {}
+        }
+    }
+    let Some(&start) = open.last() else {
+        return 0..source.len();
+    };
+    // The block ends at the brace that closes the one it opens with
+    let mut depth = 0;
+    for token in tokens.iter().filter(|token| token.range.start >= start) {
+        match &source[token.range.clone()] {
+            "{" => depth += 1,
+            "}" => {
+                depth -= 1;
+                if depth == 0 {
+                    return start..token.range.end;
+                }
+            }
+            _ => {}
+        }
+    }
+    start..source.len()
+}
+
+/// Finds the groups like `prefix::{name, ..}` that import a name.
+///
+/// # Returns
+///
+/// * `Vec<(usize, usize)>` - Where each group's path starts, and where the
+///   name is in it
+fn grouped_imports(tokens: &[Token], source: &str, prefix: &str, name: &str) -> Vec<(usize, usize)> {
+    let wanted: Vec<String> = self::tokens(prefix).iter().map(|token| prefix[token.range.clone()].to_string()).collect();
+    let text = |index: usize| tokens.get(index).map_or("", |token: &Token| &source[token.range.clone()]);
+    let mut found = Vec::new();
+    for start in 0..tokens.len() {
+        let after = start + wanted.len();
+        if (0..wanted.len()).any(|i| text(start + i) != wanted[i]) || text(after) != "::" || text(after + 1) != "{" {
+            continue;
+        }
+        let mut depth = 0;
+        for index in after + 1..tokens.len() {
+            match text(index) {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                text_at if depth == 1 && text_at == name && matches!(text(index - 1), "{" | ",") && text(index + 1) != "::" => {
+                    found.push((tokens[start].range.start, tokens[index].range.start));
+                }
+                _ => {}
+            }
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(source: &str) -> Vec<&str> {
+        tokens(source).into_iter().map(|token| &source[token.range]).collect()
+    }
+
+    #[test]
+    fn test_tokens() {
+        assert_eq!(texts("fn r#type<'a>(x: &'a str) -> char { 'x' } // old\n/* a /* b */ c */ \"s\\\"\" r#\"q\"# b'\\''"),
+            ["fn", "type", "<", "'a", ">", "(", "x", ":", "&", "'a", "str", ")", "-", ">", "char", "{", "'x'", "}",
+             "\"s\\\"\"", "r#\"q\"#", "b'\\''"]);
+        assert_eq!(texts("a::b::{C, D}"), ["a", "::", "b", "::", "{", "C", ",", "D", "}"]);
+    }
+
+    #[test]
+    fn test_check() {
+        let source = "fn old() {}\nfn new() {}\nfn old_name() { let s = \"old\"; }\n";
+        let problems = check(source, "old", "new");
+        let messages: Vec<(usize, usize, &str)> = problems.iter().map(|problem| (problem.line, problem.column, problem.message.as_str())).collect();
+        assert_eq!(messages, [
+            (1, 4, "new is already used in the same scope, at line 2, so renaming old would collide with it"),
+            (3, 4, "\"old\" is only part of the identifier old_name, which would become new_name"),
+        ]);
+        // A name used in another block is no collision
+        assert!(check("fn a() { let old = 1; }\nfn b() { let new = 2; }\n", "old", "new").is_empty());
+
+        let source = "use crate::shapes::Circle;\nuse crate::shapes::{Circle as Round, Square};\n";
+        let problems = check(source, "shapes::Circle", "shapes::Disc");
+        assert_eq!(problems.len(), 1);
+        assert_eq!((problems[0].line, problems[0].column), (2, 12));
+        assert!(problems[0].message.starts_with("shapes::{..} imports Circle at line 2"));
+    }
+
+    #[test]
+    fn test_is_rust() {
+        assert!(is_rust(Path::new("src/main.rs")));
+        assert!(!is_rust(Path::new("README.md")));
+    }
+}
@@ -0,0 +1,126 @@
+//! Filling in a project template, for `fr scaffold`.
+//!
+//! Each `--var NAME=value` gives a placeholder's value, and every `{{NAME}}`,
+//! or `{{ NAME }}`, is replaced with it: in the text of the files, as the
+//! rest of fr replaces the find text, and then in the names of the files and
+//! directories, deepest first so each one is renamed inside a directory that
+//! hasn't been yet. Placeholders without a `--var` are left as they are, and
+//! a file or directory is never renamed over one that exists.
+
+use crate::paths::{self, Rename};
+use crate::Options;
+use std::path::{Path, PathBuf};
+
+/// Parses a `--var` value, `NAME=value`.
+///
+/// # Returns
+///
+/// * `Result<(String, String), String>` - The name and value, or an error if
+///   there is no `=` or the name isn't letters, digits, `_` and `-`
+pub fn parse_var(value: &str) -> Result<(String, String), String> {
+    let (name, text) = value.split_once('=')
+        .ok_or_else(|| format!("--var expects NAME=value, got '{}'", value))?;
+    match is_name(name) {
+        true => Ok((name.to_string(), text.to_string())),
+        false => Err(format!("Invalid placeholder name '{}': expected letters, digits, _ and -", name)),
+    }
+}
+
+/// Whether text can be the name of a placeholder.
+fn is_name(text: &str) -> bool {
+    !text.is_empty() && text.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'-')
+}
+
+/// Finds the placeholders in some text that have values.
+///
+/// # Arguments
+///
+/// * `content` - The text
+/// * `vars` - The value of each placeholder, by name
+///
+/// # Returns
+///
+/// * `Vec<(usize, usize, String)>` - Where each placeholder starts and ends,
+///   braces and all, and its value
+pub fn spans(content: &str, vars: &[(String, String)]) -> Vec<(usize, usize, String)> {
+    let mut spans = Vec::new();
+    let mut position = 0;
+    while let Some(found) = content[position..].find("{{") {
+        let start = position + found;
+        let Some(length) = content[start + 2..].find("}}") else {
+            break;
+        };
+        let name = content[start + 2..start + 2 + length].trim_matches(' ');
+        match vars.iter().find(|(var, _)| var == name) {
+            Some((_, value)) => {
+                position = start + length + 4;
+                spans.push((start, position, value.clone()));
+            }
+            // The placeholder can start at the next brace, as in {{{NAME}}}
+            None => position = start + 1,
+        }
+    }
+    spans
+}
+
+/// Renames the files and directories whose names have placeholders with
+/// values, or works out the renames in a dry run.
+///
+/// # Arguments
+///
+/// * `starting_directory` - The directory the template is in
+/// * `options` - The placeholders' values, whether this is a dry run, and
+///   which files to leave alone
+///
+/// # Returns
+///
+/// * `(Vec<Rename>, Vec<(PathBuf, String)>)` - Each path renamed, with the
+///   path it has after every rename, and the paths that couldn't be renamed
+///   and why
+pub fn rename(starting_directory: &Path, options: &Options) -> (Vec<Rename>, Vec<(PathBuf, String)>) {
+    let templated = paths::walk(starting_directory, options, false).into_iter()
+        .filter(|path| path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.contains("{{")))
+        .collect();
+    let fill_in = |name: &str| crate::redact::splice(name, &spans(name, &options.placeholders));
+    let (renamed, errors) = paths::rename(templated, fill_in, options.dry_run);
+    (paths::final_paths(&renamed), errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn vars() -> Vec<(String, String)> {
+        vec![("NAME".to_string(), "myproj".to_string()), ("AUTHOR".to_string(), "Jane".to_string())]
+    }
+
+    #[test]
+    fn test_spans() {
+        let content = "# {{NAME}}\nBy {{ AUTHOR }}, {{UNKNOWN}} and {{{NAME}}}";
+        assert_eq!(crate::redact::splice(content, &spans(content, &vars())), "# myproj\nBy Jane, {{UNKNOWN}} and {myproj}");
+        assert_eq!(parse_var("NAME=a=b").unwrap(), ("NAME".to_string(), "a=b".to_string()));
+        assert!(parse_var("NAME").is_err() && parse_var("A B=c").is_err());
+    }
+
+    #[test]
+    fn test_rename() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("{{NAME}}/src")).unwrap();
+        fs::write(root.join("{{NAME}}/src/{{NAME}}.rs"), "").unwrap();
+        fs::write(root.join("{{UNKNOWN}}.txt"), "").unwrap();
+        fs::write(root.join("taken.txt"), "").unwrap();
+        fs::write(root.join("{{AUTHOR}}.txt"), "").unwrap();
+        let options = Options { placeholders: vec![("AUTHOR".to_string(), "taken".to_string()), vars().remove(0)], ..Options::default() };
+
+        let (renamed, errors) = rename(root, &Options { dry_run: true, ..options.clone() });
+        assert_eq!(renamed, [(root.join("{{NAME}}"), root.join("myproj")), (root.join("{{NAME}}/src/{{NAME}}.rs"), root.join("myproj/src/myproj.rs"))]);
+        assert_eq!(errors.len(), 1);
+        assert!(root.join("{{NAME}}").exists());
+
+        rename(root, &options);
+        assert!(root.join("myproj/src/myproj.rs").is_file() && root.join("{{UNKNOWN}}.txt").is_file() && root.join("{{AUTHOR}}.txt").is_file());
+    }
+}
@@ -0,0 +1,126 @@
+//! Ruling out files that can't contain a match without reading them into memory.
+//!
+//! Most files searched don't contain the find text, so before a file is read
+//! and decoded, its bytes are streamed through a small buffer looking for part
+//! of the find text. Only files where it turns up are read in full.
+
+use crate::buffers;
+use crate::encoding::Encoding;
+use crate::throttle;
+use memchr::memmem;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// How much of a file is scanned at a time
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Picks the bytes that must appear in a file, as they are, for the find text
+/// to match once it is decoded. That is the longest line of the find text, as
+/// each line is left alone when the find text is adapted to the file's line
+/// endings.
+///
+/// # Returns
+///
+/// * `Option<&str>` - The text to look for, or None if the file's bytes can't be
+///   searched directly in this encoding
+pub fn needle(find_text: &str, encoding: Encoding) -> Option<&str> {
+    let needle = find_text.split('\n').max_by_key(|line| line.len())?;
+    let searchable = match encoding {
+        Encoding::Utf8 | Encoding::Utf8Bom => true,
+        // Files are read as Latin-1 here, where only ASCII is encoded the same way
+        Encoding::Latin1 | Encoding::Auto => needle.is_ascii(),
+        // ASCII is the same in Shift_JIS, though it can also be the second byte
+        // of a character, which is only a false positive
+        Encoding::ShiftJis => needle.is_ascii(),
+        Encoding::Utf16Le | Encoding::Utf16Be => false,
+    };
+    Some(needle).filter(|needle| searchable && !needle.is_empty())
+}
+
+/// Works out whether a file may contain the find text.
+///
+/// # Arguments
+///
+/// * `file_path` - Path to the file to scan
+/// * `find_text` - Text to find
+/// * `encoding` - The encoding the file will be read in
+///
+/// # Returns
+///
+/// * `io::Result<bool>` - False if the file can't contain the find text, true if
+///   it may and has to be read to find out
+pub fn may_contain(file_path: &Path, find_text: &str, encoding: Encoding) -> io::Result<bool> {
+    let Some(needle) = needle(find_text, encoding) else {
+        return Ok(true);
+    };
+    let finder = memmem::Finder::new(needle.as_bytes());
+    let file = File::open(file_path)?;
+    let mut buffer = buffers::take();
+    buffer.resize(BUFFER_SIZE.max(2 * needle.len()), 0);
+    let found = find(file, &finder, &mut buffer);
+    buffers::recycle(buffer);
+    found
+}
+
+/// Streams a reader through a buffer, looking for the needle.
+fn find(mut reader: impl Read, finder: &memmem::Finder, buffer: &mut [u8]) -> io::Result<bool> {
+    // The end of each chunk is kept so that matches spanning two chunks are found
+    let overlap = finder.needle().len() - 1;
+    let mut filled = 0;
+    loop {
+        let read = match reader.read(&mut buffer[filled..]) {
+            Ok(0) => return Ok(false),
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        throttle::consume(read);
+        filled += read;
+        if finder.find(&buffer[..filled]).is_some() {
+            return Ok(true);
+        }
+        let kept = overlap.min(filled);
+        buffer.copy_within(filled - kept..filled, 0);
+        filled = kept;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_needle() {
+        assert_eq!(needle("hello", Encoding::Utf8), Some("hello"));
+        assert_eq!(needle("a\nlonger line\nb", Encoding::Utf8), Some("longer line"));
+        assert_eq!(needle("\n", Encoding::Utf8), None);
+        assert_eq!(needle("café", Encoding::Latin1), None);
+        assert_eq!(needle("cafe", Encoding::Auto), Some("cafe"));
+        assert_eq!(needle("hello", Encoding::Utf16Le), None);
+    }
+
+    #[test]
+    fn test_may_contain() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("big.txt");
+
+        // Put the match across the boundary between the first two chunks
+        let mut contents = vec![b'x'; BUFFER_SIZE - 3];
+        contents.extend_from_slice(b"needle");
+        contents.extend(vec![b'x'; BUFFER_SIZE]);
+        fs::write(&file_path, &contents).unwrap();
+        assert!(may_contain(&file_path, "needle", Encoding::Utf8).unwrap());
+        assert!(!may_contain(&file_path, "haystack", Encoding::Utf8).unwrap());
+
+        // Lines of a multi-line find text may be separated by CRLFs
+        fs::write(&file_path, "one\r\ntwo\r\n").unwrap();
+        assert!(may_contain(&file_path, "one\ntwo", Encoding::Utf8).unwrap());
+
+        // Files that can't be scanned have to be read
+        assert!(may_contain(&file_path, "three", Encoding::Utf16Le).unwrap());
+        assert!(may_contain(&temp_dir.path().join("missing.txt"), "one", Encoding::Utf8).is_err());
+    }
+}
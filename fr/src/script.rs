@@ -0,0 +1,149 @@
+//! Writing a run out as a standalone sed or perl script, for
+//! `--export-script`.
+//!
+//! The script replaces the find text in exactly the files the run would
+//! have modified, so the change can be reviewed as a script or made on a
+//! machine without fr. It doesn't know about encodings or line endings, so
+//! only plain replacements of UTF-8 text can be exported.
+
+use std::path::{Path, PathBuf};
+
+/// The language scripts are written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptKind {
+    /// A POSIX shell script running sed on each file, for find text without
+    /// newlines
+    Sed,
+    /// A perl script editing the files in place
+    Perl,
+}
+
+impl ScriptKind {
+    /// Parses the value of `--export-script`.
+    pub fn parse(text: &str) -> Result<ScriptKind, String> {
+        match text {
+            "sed" => Ok(ScriptKind::Sed),
+            "perl" => Ok(ScriptKind::Perl),
+            _ => Err(format!("--export-script expects sed or perl, got '{}'", text)),
+        }
+    }
+}
+
+/// Writes the script that makes a run's replacements.
+///
+/// # Arguments
+///
+/// * `kind` - The language to write the script in
+/// * `files` - The files the run would modify, relative to the directory it
+///   was run in
+/// * `find_text` - Text to find
+/// * `replace_text` - Text to replace the found text with
+///
+/// # Returns
+///
+/// * `Result<String, String>` - The script, or an error if the replacement
+///   or a path can't be written in the language
+pub fn render(kind: ScriptKind, files: &[PathBuf], find_text: &str, replace_text: &str) -> Result<String, String> {
+    let paths = files.iter().map(|path| script_path(path)).collect::<Result<Vec<String>, String>>()?;
+    let summary = format!("# Replaces {:?} with {:?} in the {} file{} fr found it in.\n# Run it from the directory fr was run in.\n",
+        find_text, replace_text, files.len(), if files.len() == 1 { "" } else { "s" });
+    match kind {
+        ScriptKind::Sed => {
+            if find_text.contains('\n') {
+                return Err("sed works a line at a time, so can't replace text with a newline in it; use --export-script perl".to_string());
+            }
+            let expression = format!("s/{}/{}/g", sed_pattern(find_text), sed_replacement(replace_text));
+            let mut script = format!("#!/bin/sh\n{}set -e\n\n", summary);
+            // Writing back through cat keeps each file's permissions and links
+            script.push_str(&format!("replace() {{\n    sed -e {} \"$1\" > \"$1.fr-tmp\"\n    cat \"$1.fr-tmp\" > \"$1\"\n    rm \"$1.fr-tmp\"\n}}\n\n",
+                shell_quote(&expression)));
+            for path in &paths {
+                script.push_str(&format!("replace {}\n", shell_quote(path)));
+            }
+            Ok(script)
+        }
+        ScriptKind::Perl => {
+            let mut script = format!("#!/usr/bin/env perl\n{}use strict;\nuse warnings;\n\n", summary);
+            script.push_str(&format!("my $find = {};\nmy $replace = {};\n@ARGV = (\n", perl_quote(find_text), perl_quote(replace_text)));
+            for path in &paths {
+                script.push_str(&format!("    {},\n", perl_quote(path)));
+            }
+            // <<>> opens each name as a file, whatever characters it has
+            script.push_str(");\n\n# Edit each file in place, reading it whole so matches can span lines\n$^I = '';\nlocal $/;\nwhile (<<>>) {\n    s/\\Q$find\\E/$replace/g;\n    print;\n}\n");
+            Ok(script)
+        }
+    }
+}
+
+/// Returns a path as the script names it, starting with `./` so that it
+/// can't be taken for an option.
+fn script_path(path: &Path) -> Result<String, String> {
+    let text = path.to_str().ok_or_else(|| format!("{} isn't valid UTF-8, so can't be written in a script", path.display()))?;
+    Ok(match path.is_relative() {
+        true => format!("./{}", text),
+        false => text.to_string(),
+    })
+}
+
+/// Quotes text for a POSIX shell.
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+/// Quotes text as a perl string that interpolates nothing.
+fn perl_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+/// Escapes literal text as a sed basic regular expression.
+fn sed_pattern(text: &str) -> String {
+    let mut pattern = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '/' | '.' | '*' | '[' | ']' | '^' | '$') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern
+}
+
+/// Escapes literal text as the replacement of a sed `s` command.
+fn sed_replacement(text: &str) -> String {
+    let mut replacement = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '/' | '&' | '\n') {
+            replacement.push('\\');
+        }
+        replacement.push(c);
+    }
+    replacement
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_sed() {
+        let files = [PathBuf::from("a.txt"), PathBuf::from("it's.md")];
+        let script = render(ScriptKind::Sed, &files, "a.b/[c]", "x & y\\z").unwrap();
+        assert!(script.starts_with("#!/bin/sh\n# Replaces \"a.b/[c]\" with \"x & y\\\\z\" in the 2 files"));
+        assert!(script.contains("    sed -e 's/a\\.b\\/\\[c\\]/x \\& y\\\\z/g' \"$1\" > \"$1.fr-tmp\"\n"));
+        assert!(script.ends_with("replace './a.txt'\nreplace './it'\\''s.md'\n"));
+        assert!(render(ScriptKind::Sed, &files, "a\nb", "c").is_err());
+    }
+
+    #[test]
+    fn test_render_perl() {
+        let files = [PathBuf::from("src/main.rs")];
+        let script = render(ScriptKind::Perl, &files, "it's\n", "C:\\dir").unwrap();
+        assert!(script.contains("my $find = 'it\\'s\n';\nmy $replace = 'C:\\\\dir';\n@ARGV = (\n    './src/main.rs',\n);\n"));
+        assert!(script.contains("    s/\\Q$find\\E/$replace/g;\n"));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(ScriptKind::parse("perl"), Ok(ScriptKind::Perl));
+        assert!(ScriptKind::parse("awk").is_err());
+    }
+}
@@ -0,0 +1,317 @@
+//! `fr serve`: a long-running process that answers JSON-RPC 2.0 requests on
+//! stdio, so an editor extension can keep one fr warm instead of starting a
+//! new one for every search.
+//!
+//! Each request and response is one line of JSON. The methods are:
+//!
+//! * `scan` - `{find, root?}` to `{files: [{path, count}]}`
+//! * `plan` - `{find, replace, root?}` to `{edits: [{path, start, end, before,
+//!   after}], errors}`, the edits `Session::edits` would make
+//! * `apply` - `{find, replace, root?}` to `{modified: [{path, replacements}],
+//!   skipped, errors}`, rewriting the files
+//! * `undo` - `{}` to `{restored: [path]}`, putting back the files the last
+//!   `apply` that hasn't been undone rewrote
+//!
+//! Paths are relative to the root, which is the directory fr was started in
+//! unless a request names another, relative to it. Files over 64 MiB are
+//! skipped, since they would be streamed and `undo` needs a copy of each file
+//! from before it was rewritten. A tree's `fr index` is loaded by the first
+//! request about it and kept until the index file changes. `undo` writes the
+//! old contents over each file in place, as a run does, so the file keeps its
+//! permissions.
+
+use crate::json::Value;
+use crate::{index, stream, Options, Session, Summary};
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// The JSON-RPC error codes fr answers with
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+/// Failures of the method itself, such as a root that can't be walked
+const SERVER_ERROR: i32 = -32000;
+
+/// Why a request failed, with its JSON-RPC error code
+#[derive(Debug)]
+struct RpcError {
+    code: i32,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i32, message: impl Into<String>) -> RpcError {
+        RpcError { code, message: message.into() }
+    }
+}
+
+impl From<io::Error> for RpcError {
+    fn from(e: io::Error) -> RpcError {
+        RpcError::new(SERVER_ERROR, e.to_string())
+    }
+}
+
+/// The files one `apply` rewrote, with their contents from before
+type Snapshot = Vec<(PathBuf, Vec<u8>)>;
+
+/// What the server keeps between requests
+struct Server {
+    root: PathBuf,
+    options: Options,
+    /// One snapshot per `apply`, most recent last
+    undo: Vec<Snapshot>,
+}
+
+impl Server {
+    /// Answers one line of input, or returns None for a notification, which
+    /// gets no answer.
+    fn handle(&mut self, line: &str) -> Option<Value> {
+        let request = match Value::parse(line) {
+            Ok(request) => request,
+            Err(e) => return Some(response(Value::Null, Err(RpcError::new(PARSE_ERROR, e)))),
+        };
+        let id = request.get("id").cloned();
+        let result = match request.get("method").and_then(Value::as_str) {
+            Some(method) => self.call(method, request.get("params").unwrap_or(&Value::Null)),
+            None => Err(RpcError::new(INVALID_REQUEST, "the request has no method")),
+        };
+        id.map(|id| response(id, result))
+    }
+
+    fn call(&mut self, method: &str, params: &Value) -> Result<Value, RpcError> {
+        match method {
+            "scan" => self.scan(params),
+            "plan" => self.plan(params),
+            "apply" => self.apply(params),
+            "undo" => self.undo(),
+            _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("unknown method '{}'", method))),
+        }
+    }
+
+    /// The root a request is about.
+    fn root(&self, params: &Value) -> PathBuf {
+        match params.get("root").and_then(Value::as_str) {
+            Some(root) => self.root.join(root),
+            None => self.root.clone(),
+        }
+    }
+
+    /// Sets up a session for a request's find and replace text.
+    fn session(&self, params: &Value, needs_replace: bool) -> Result<Session, RpcError> {
+        let text = |key: &str| params.get(key).and_then(Value::as_str).map(str::to_string);
+        let find_text = text("find").ok_or_else(|| RpcError::new(INVALID_PARAMS, "expected find to be a string"))?;
+        let replace_text = match text("replace") {
+            Some(replace_text) => replace_text,
+            None if !needs_replace => String::new(),
+            None => return Err(RpcError::new(INVALID_PARAMS, "expected replace to be a string")),
+        };
+        if find_text.is_empty() {
+            return Err(RpcError::new(INVALID_PARAMS, "find text cannot be empty"));
+        }
+        let mut session = Session::new(self.root(params));
+        session.options(self.options.clone()).rule(find_text, replace_text);
+        Ok(session)
+    }
+
+    fn scan(&self, params: &Value) -> Result<Value, RpcError> {
+        let summary = self.session(params, false)?.dry_run(true).run()?;
+        let files = summary.modified.iter().map(|file| Value::object([
+            ("path", path(&file.path)),
+            ("count", file.replacements.into()),
+        ]));
+        Ok(Value::object([("files", Value::Array(files.collect()))]))
+    }
+
+    fn plan(&self, params: &Value) -> Result<Value, RpcError> {
+        let root = self.root(params);
+        let (mut edits, mut errors) = (Vec::new(), Vec::new());
+        for edit in self.session(params, true)?.edits()? {
+            match edit {
+                Ok(edit) => edits.push(Value::object([
+                    ("path", path(edit.path.strip_prefix(&root).unwrap_or(&edit.path))),
+                    ("start", edit.range.start.into()),
+                    ("end", edit.range.end.into()),
+                    ("before", edit.before.into()),
+                    ("after", edit.after.into()),
+                ])),
+                Err(e) => errors.push(Value::String(e.to_string())),
+            }
+        }
+        Ok(Value::object([("edits", Value::Array(edits)), ("errors", Value::Array(errors))]))
+    }
+
+    fn apply(&mut self, params: &Value) -> Result<Value, RpcError> {
+        let root = self.root(params);
+        let snapshot = Arc::new(Mutex::new(Snapshot::new()));
+        let mut session = self.session(params, true)?;
+        let taken = Arc::clone(&snapshot);
+        // Files are read again just before they are written; one that can't
+        // be is left alone, so that every change can be undone
+        session.on_match(move |display, _| match fs::read(root.join(display)) {
+            Ok(contents) => {
+                taken.lock().unwrap().push((root.join(display), contents));
+                true
+            }
+            Err(_) => false,
+        });
+        let summary = session.run()?;
+
+        let mut snapshot = std::mem::take(&mut *snapshot.lock().unwrap());
+        let written: Vec<PathBuf> = summary.modified.iter().map(|file| self.root(params).join(&file.path)).collect();
+        snapshot.retain(|(path, _)| written.contains(path));
+        if !snapshot.is_empty() {
+            self.undo.push(snapshot);
+        }
+        Ok(summary_value(&summary))
+    }
+
+    fn undo(&mut self) -> Result<Value, RpcError> {
+        let snapshot = self.undo.pop().ok_or_else(|| RpcError::new(SERVER_ERROR, "nothing to undo"))?;
+        let mut restored = Vec::new();
+        for (file_path, contents) in &snapshot {
+            fs::write(file_path, contents)?;
+            restored.push(path(file_path.strip_prefix(&self.root).unwrap_or(file_path)));
+        }
+        Ok(Value::object([("restored", Value::Array(restored))]))
+    }
+}
+
+fn path(path: &Path) -> Value {
+    Value::String(path.to_string_lossy().into_owned())
+}
+
+/// Describes what an `apply` did.
+fn summary_value(summary: &Summary) -> Value {
+    let modified = summary.modified.iter().map(|file| Value::object([
+        ("path", path(&file.path)),
+        ("replacements", file.replacements.into()),
+    ]));
+    let skipped = summary.skipped.iter().map(|(file_path, reason)| Value::object([
+        ("path", path(file_path)),
+        ("reason", reason.to_string().into()),
+    ]));
+    let errors = summary.errors.iter().map(|(file_path, message)| Value::object([
+        ("path", path(file_path)),
+        ("message", message.as_str().into()),
+    ]));
+    Value::object([
+        ("modified", Value::Array(modified.collect())),
+        ("skipped", Value::Array(skipped.collect())),
+        ("errors", Value::Array(errors.collect())),
+    ])
+}
+
+fn response(id: Value, result: Result<Value, RpcError>) -> Value {
+    let outcome = match result {
+        Ok(result) => ("result", result),
+        Err(e) => ("error", Value::object([("code", Value::Number(e.code.into())), ("message", e.message.into())])),
+    };
+    Value::object([("jsonrpc", "2.0".into()), ("id", id), outcome])
+}
+
+/// Answers requests until the input ends.
+///
+/// # Arguments
+///
+/// * `root` - The directory requests are about unless they name another
+/// * `options` - Options for every request, such as from flags given to `fr serve`
+/// * `input` - Where requests are read from, one per line
+/// * `output` - Where responses are written, one per line
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Ok once the input ends, or an error if reading a
+///   request or writing a response fails
+pub fn serve(root: &Path, options: Options, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    let options = Options { max_filesize: Some(options.max_filesize.unwrap_or(stream::THRESHOLD).min(stream::THRESHOLD)), ..options };
+    index::keep_loaded();
+    let mut server = Server { root: root.to_path_buf(), options, undo: Vec::new() };
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = server.handle(&line) {
+            writeln!(output, "{}", response)?;
+            output.flush()?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn requests(root: &Path, lines: &[&str]) -> Vec<String> {
+        let mut output = Vec::new();
+        serve(root, Options::default(), lines.join("\n").as_bytes(), &mut output).unwrap();
+        String::from_utf8(output).unwrap().lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_serve() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("a.txt"), "one two one").unwrap();
+        fs::write(temp_dir.path().join("b.txt"), "two").unwrap();
+
+        let responses = requests(temp_dir.path(), &[
+            r#"{"jsonrpc":"2.0","id":1,"method":"scan","params":{"find":"one"}}"#,
+            r#"{"jsonrpc":"2.0","id":2,"method":"plan","params":{"find":"one","replace":"1"}}"#,
+            r#"{"jsonrpc":"2.0","id":3,"method":"apply","params":{"find":"one","replace":"1"}}"#,
+        ]);
+        assert_eq!(responses, [
+            r#"{"jsonrpc":"2.0","id":1,"result":{"files":[{"path":"a.txt","count":2}]}}"#,
+            r#"{"jsonrpc":"2.0","id":2,"result":{"edits":[{"path":"a.txt","start":0,"end":3,"before":"one","after":"1"},{"path":"a.txt","start":6,"end":9,"before":"one","after":"1"}],"errors":[]}}"#,
+            r#"{"jsonrpc":"2.0","id":3,"result":{"modified":[{"path":"a.txt","replacements":2}],"skipped":[],"errors":[]}}"#,
+        ]);
+        assert_eq!(fs::read_to_string(temp_dir.path().join("a.txt")).unwrap(), "1 two 1");
+    }
+
+    #[test]
+    fn test_serve_undo_and_errors() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("a.txt"), "one").unwrap();
+
+        let responses = requests(temp_dir.path(), &[
+            r#"{"jsonrpc":"2.0","method":"apply","params":{"find":"one","replace":"two"}}"#,
+            r#"{"jsonrpc":"2.0","id":"a","method":"apply","params":{"find":"two","replace":"three"}}"#,
+            r#"{"jsonrpc":"2.0","id":"b","method":"undo"}"#,
+            r#"{"jsonrpc":"2.0","id":"c","method":"undo"}"#,
+            r#"{"jsonrpc":"2.0","id":"d","method":"undo"}"#,
+            r#"{"jsonrpc":"2.0","id":"e","method":"plan","params":{"find":"","replace":"x"}}"#,
+            r#"{"jsonrpc":"2.0","id":"f","method":"replace"}"#,
+            r#"{"jsonrpc":"2.0","#,
+        ]);
+        assert_eq!(responses[1..], [
+            r#"{"jsonrpc":"2.0","id":"b","result":{"restored":["a.txt"]}}"#,
+            r#"{"jsonrpc":"2.0","id":"c","result":{"restored":["a.txt"]}}"#,
+            r#"{"jsonrpc":"2.0","id":"d","error":{"code":-32000,"message":"nothing to undo"}}"#,
+            r#"{"jsonrpc":"2.0","id":"e","error":{"code":-32602,"message":"find text cannot be empty"}}"#,
+            r#"{"jsonrpc":"2.0","id":"f","error":{"code":-32601,"message":"unknown method 'replace'"}}"#,
+            r#"{"jsonrpc":"2.0","id":null,"error":{"code":-32700,"message":"expected a key at byte 17"}}"#,
+        ]);
+        assert_eq!(fs::read_to_string(temp_dir.path().join("a.txt")).unwrap(), "one");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_serve_undo_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+        let temp_dir = TempDir::new().unwrap();
+        let script = temp_dir.path().join("run.sh");
+        fs::write(&script, "echo one").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+        requests(temp_dir.path(), &[
+            r#"{"jsonrpc":"2.0","method":"apply","params":{"find":"one","replace":"two"}}"#,
+            r#"{"jsonrpc":"2.0","method":"undo"}"#,
+        ]);
+        assert_eq!(fs::read_to_string(&script).unwrap(), "echo one");
+        assert_eq!(fs::metadata(&script).unwrap().permissions().mode() & 0o777, 0o755);
+    }
+}
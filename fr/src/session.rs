@@ -0,0 +1,291 @@
+//! A builder for find and replace runs, for embedding fr in other tools.
+
+use crate::hooks::Hooks;
+use crate::{walk, Edits, Encoding, EolPolicy, ModifiedFile, Options, Summary};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Builds and runs find and replace over one or more trees.
+///
+/// Like `ignore::WalkBuilder`, each setter takes and returns `&mut Session`, so
+/// calls can be chained, and the same session can be run again.
+///
+/// Unlike the command line tool, a session prints nothing about the files it
+/// modifies; what happened is in the `Summary` that `run` returns and is passed
+/// to the hooks as it happens.
+#[derive(Debug)]
+pub struct Session {
+    roots: Vec<PathBuf>,
+    /// Find and replace text pairs, applied in order
+    rules: Vec<(String, String)>,
+    options: Options,
+    hooks: Hooks,
+}
+
+impl Session {
+    /// Creates a session that searches the tree under a directory.
+    pub fn new(root: impl AsRef<Path>) -> Session {
+        Session {
+            roots: vec![root.as_ref().to_path_buf()],
+            rules: Vec::new(),
+            options: Options { pager: false, quiet: true, ..Options::default() },
+            hooks: Hooks::default(),
+        }
+    }
+
+    /// Also searches the tree under another directory. Paths in the summary are
+    /// relative to the first root, or start with their own root if they aren't
+    /// under it.
+    pub fn root(&mut self, root: impl AsRef<Path>) -> &mut Session {
+        self.roots.push(root.as_ref().to_path_buf());
+        self
+    }
+
+    /// Adds a rule replacing every occurrence of `find_text` with
+    /// `replace_text`. Rules are applied one after another, each to the result
+    /// of the ones before it.
+    pub fn rule(&mut self, find_text: impl Into<String>, replace_text: impl Into<String>) -> &mut Session {
+        self.rules.push((find_text.into(), replace_text.into()));
+        self
+    }
+
+    /// Replaces every option at once, such as to start from options parsed
+    /// elsewhere. Output to stdout stays off, as it is for every session.
+    pub fn options(&mut self, options: Options) -> &mut Session {
+        self.options = Options { pager: false, quiet: true, ..options };
+        self
+    }
+
+    /// Works out what would change without writing anything.
+    pub fn dry_run(&mut self, yes: bool) -> &mut Session {
+        self.options.dry_run = yes;
+        self
+    }
+
+    /// Sets how many threads search and rewrite files, or None to use one per CPU.
+    pub fn threads(&mut self, threads: Option<usize>) -> &mut Session {
+        self.options.threads = threads;
+        self
+    }
+
+    /// Skips files larger than this many bytes.
+    pub fn max_filesize(&mut self, max_filesize: Option<u64>) -> &mut Session {
+        self.options.max_filesize = max_filesize;
+        self
+    }
+
+    /// Sets the encoding files are read and written in.
+    pub fn encoding(&mut self, encoding: Encoding) -> &mut Session {
+        self.options.encoding = encoding;
+        self
+    }
+
+    /// Sets what happens to the line endings of rewritten files.
+    pub fn eol(&mut self, eol: EolPolicy) -> &mut Session {
+        self.options.eol = eol;
+        self
+    }
+
+    /// Treats every file as text instead of skipping files that look binary.
+    pub fn text(&mut self, yes: bool) -> &mut Session {
+        self.options.text = yes;
+        self
+    }
+
+    /// Adds a filter deciding whether the walk visits a path. A directory that
+    /// is filtered out isn't descended into.
+    pub fn filter(&mut self, filter: impl Fn(&Path) -> bool + Send + Sync + 'static) -> &mut Session {
+        self.hooks.filters.push(Arc::new(filter));
+        self
+    }
+
+    /// Adds a callback that is called with each file before it is read. A file
+    /// the callback returns false for is left alone, and reported as skipped.
+    pub fn on_file_start(&mut self, hook: impl Fn(&Path) -> bool + Send + Sync + 'static) -> &mut Session {
+        self.hooks.on_file_start.push(Arc::new(hook));
+        self
+    }
+
+    /// Adds a callback that is called with each file that contains the find
+    /// text and how many replacements it needs, before it is written. A file the
+    /// callback returns false for is left alone, and reported as skipped, unless
+    /// it is huge enough to be rewritten as it is searched.
+    pub fn on_match(&mut self, hook: impl Fn(&Path, usize) -> bool + Send + Sync + 'static) -> &mut Session {
+        self.hooks.on_match.push(Arc::new(hook));
+        self
+    }
+
+    /// Adds a callback that is called with each file once it has been
+    /// rewritten, or would have been in a dry run.
+    pub fn on_file_written(&mut self, hook: impl Fn(&ModifiedFile) + Send + Sync + 'static) -> &mut Session {
+        self.hooks.on_file_written.push(Arc::new(hook));
+        self
+    }
+
+    /// Adds a callback that is called with each file that could not be
+    /// processed and why.
+    pub fn on_error(&mut self, hook: impl Fn(&Path, &io::Error) + Send + Sync + 'static) -> &mut Session {
+        self.hooks.on_error.push(Arc::new(hook));
+        self
+    }
+
+    /// Runs every rule over every root.
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<Summary>` - What happened, with a file that several rules
+    ///   modified listed once, or an error if there are no rules, a rule's find
+    ///   text is empty, or the run could not be set up
+    pub fn run(&self) -> io::Result<Summary> {
+        self.check_rules()?;
+        let mut summary: Option<Summary> = None;
+        for (find_text, replace_text) in &self.rules {
+            let pass = walk(&self.roots, find_text, replace_text, &self.options, &self.hooks)?;
+            summary = Some(match summary {
+                Some(summary) => merge(summary, pass),
+                None => pass,
+            });
+        }
+        Ok(summary.unwrap_or_default())
+    }
+
+    /// Works out the edits every rule would make over every root, without
+    /// applying them or running any hooks besides the filters.
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<Edits>` - An iterator that walks the roots as its edits are
+    ///   taken, or an error if there are no rules or a rule's find text is empty
+    pub fn edits(&self) -> io::Result<Edits> {
+        self.check_rules()?;
+        Ok(Edits::new(&self.roots, self.rules.clone(), self.options.clone(), &self.hooks.filters))
+    }
+
+    /// Checks that there is something to find.
+    fn check_rules(&self) -> io::Result<()> {
+        if self.rules.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "a session needs at least one rule"));
+        }
+        if self.rules.iter().any(|(find_text, _)| find_text.is_empty()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "find text cannot be empty"));
+        }
+        Ok(())
+    }
+}
+
+/// Combines the summaries of two rules run over the same files.
+pub(crate) fn merge(mut first: Summary, second: Summary) -> Summary {
+    // Every pass searches the same text files, whether or not they match
+    let searched = first.modified.len() + first.unmatched;
+    for file in second.modified {
+        match first.modified.iter_mut().find(|modified| modified.path == file.path) {
+            Some(modified) => {
+                modified.replacements += file.replacements;
+                modified.hunks.extend(file.hunks);
+                modified.locations.extend(file.locations);
+            }
+            None => first.modified.push(file),
+        }
+    }
+    first.modified.sort_by(|a, b| a.path.cmp(&b.path));
+    first.unmatched = searched.saturating_sub(first.modified.len());
+
+    // A file one rule passed over is passed over by every rule
+    first.skipped.extend(second.skipped);
+    first.skipped.sort();
+    first.skipped.dedup();
+    first.errors.extend(second.errors);
+    first.errors.sort();
+    first
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SkipReason;
+    use std::fs;
+    use std::sync::Mutex;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_run() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir(temp_dir.path().join("vendor")).unwrap();
+        fs::write(temp_dir.path().join("a.txt"), "old name, old place").unwrap();
+        fs::write(temp_dir.path().join("b.txt"), "old name").unwrap();
+        fs::write(temp_dir.path().join("c.txt"), "nothing").unwrap();
+        fs::write(temp_dir.path().join("vendor").join("d.txt"), "old name").unwrap();
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let summary = {
+            let seen = Arc::clone(&seen);
+            Session::new(temp_dir.path())
+                .rule("old name", "new name")
+                .rule("old place", "new place")
+                .threads(Some(2))
+                .filter(|path| path.file_name().is_none_or(|name| name != "vendor"))
+                .on_file_written(move |file| seen.lock().unwrap().push(file.path.clone()))
+                .run()
+                .unwrap()
+        };
+
+        let modified: Vec<(&Path, usize)> = summary.modified.iter().map(|file| (file.path.as_path(), file.replacements)).collect();
+        assert_eq!(modified, [(Path::new("a.txt"), 2), (Path::new("b.txt"), 1)]);
+        assert_eq!(summary.unmatched, 1);
+        assert_eq!(fs::read_to_string(temp_dir.path().join("a.txt")).unwrap(), "new name, new place");
+        assert_eq!(fs::read_to_string(temp_dir.path().join("vendor").join("d.txt")).unwrap(), "old name");
+        assert_eq!(seen.lock().unwrap().len(), 3, "a.txt is modified by both rules");
+    }
+
+    #[test]
+    fn test_run_dry_run_with_several_roots() {
+        let first = TempDir::new().unwrap();
+        let second = TempDir::new().unwrap();
+        fs::write(first.path().join("a.txt"), "hello").unwrap();
+        fs::write(second.path().join("b.txt"), "hello").unwrap();
+
+        let summary = Session::new(first.path()).root(second.path()).rule("hello", "hi").dry_run(true).run().unwrap();
+        let paths: Vec<&Path> = summary.modified.iter().map(|file| file.path.as_path()).collect();
+        let second_path = second.path().join("b.txt");
+        assert_eq!(paths.len(), 2);
+        assert!(paths.contains(&Path::new("a.txt")) && paths.contains(&second_path.as_path()));
+        assert_eq!(fs::read_to_string(first.path().join("a.txt")).unwrap(), "hello");
+    }
+
+    #[test]
+    fn test_run_hooks_veto() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("a.txt"), "hello").unwrap();
+        fs::write(temp_dir.path().join("b.txt"), "hello hello").unwrap();
+        fs::write(temp_dir.path().join("c.txt"), "hello").unwrap();
+
+        let started = Arc::new(Mutex::new(Vec::new()));
+        let summary = {
+            let started = Arc::clone(&started);
+            Session::new(temp_dir.path())
+                .rule("hello", "hi")
+                .on_file_start(move |path| {
+                    started.lock().unwrap().push(path.to_path_buf());
+                    path != Path::new("a.txt")
+                })
+                .on_match(|_, replacements| replacements < 2)
+                .run()
+                .unwrap()
+        };
+
+        started.lock().unwrap().sort();
+        assert_eq!(*started.lock().unwrap(), [Path::new("a.txt"), Path::new("b.txt"), Path::new("c.txt")]);
+        assert_eq!(summary.skipped, [(PathBuf::from("a.txt"), SkipReason::Vetoed), (PathBuf::from("b.txt"), SkipReason::Vetoed)]);
+        assert_eq!(summary.modified.len(), 1);
+        assert_eq!(fs::read_to_string(temp_dir.path().join("b.txt")).unwrap(), "hello hello");
+        assert_eq!(fs::read_to_string(temp_dir.path().join("c.txt")).unwrap(), "hi");
+    }
+
+    #[test]
+    fn test_run_without_rules() {
+        let temp_dir = TempDir::new().unwrap();
+        assert_eq!(Session::new(temp_dir.path()).run().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(Session::new(temp_dir.path()).rule("", "x").run().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,99 @@
+//! Spotting sparse files, whose holes take up no disk space.
+//!
+//! Rewriting a sparse file writes its holes out as zeros, so a disk image or
+//! preallocated database that takes up a few megabytes could take up
+//! gigabytes once a single string in it had been replaced. Such files are
+//! skipped unless `--sparse` is given.
+
+use std::fs::Metadata;
+use std::path::Path;
+
+/// Whether a file has holes.
+///
+/// # Arguments
+///
+/// * `path` - The file
+/// * `metadata` - The file's metadata
+///
+/// # Returns
+///
+/// * `bool` - True if part of the file isn't stored on disk
+#[cfg(unix)]
+pub fn is_sparse(path: &Path, metadata: &Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    // A file taking up fewer blocks than its length needs can still be
+    // compressed by the filesystem rather than have holes
+    if metadata.blocks() * 512 >= metadata.len() {
+        return false;
+    }
+    #[cfg(target_os = "linux")]
+    if first_hole(path).is_some_and(|hole| hole >= metadata.len()) {
+        return false;
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = path;
+    true
+}
+
+/// Whether a file has holes.
+///
+/// # Arguments
+///
+/// * `path` - The file
+/// * `metadata` - The file's metadata
+///
+/// # Returns
+///
+/// * `bool` - True if the file is marked sparse
+#[cfg(windows)]
+pub fn is_sparse(_path: &Path, metadata: &Metadata) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    const FILE_ATTRIBUTE_SPARSE_FILE: u32 = 0x200;
+    metadata.file_attributes() & FILE_ATTRIBUTE_SPARSE_FILE != 0
+}
+
+/// Whether a file has holes, which can't be told on this platform.
+#[cfg(not(any(unix, windows)))]
+pub fn is_sparse(_path: &Path, _metadata: &Metadata) -> bool {
+    false
+}
+
+/// Finds where the first hole in a file starts, which is its length if it has
+/// none, or None if the filesystem can't tell.
+#[cfg(target_os = "linux")]
+fn first_hole(path: &Path) -> Option<u64> {
+    use std::os::fd::AsRawFd;
+    let file = std::fs::File::open(path).ok()?;
+    // SAFETY: lseek only moves the offset of a file this function owns
+    let offset = unsafe { libc::lseek(file.as_raw_fd(), 0, libc::SEEK_HOLE) };
+    u64::try_from(offset).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_is_sparse() {
+        let temp_dir = TempDir::new().unwrap();
+        let dense = temp_dir.path().join("dense.txt");
+        fs::write(&dense, "old\n".repeat(1024)).unwrap();
+        assert!(!is_sparse(&dense, &fs::metadata(&dense).unwrap()));
+
+        // Not every filesystem makes holes, and then the file isn't sparse
+        let sparse = temp_dir.path().join("sparse.img");
+        let mut file = File::create(&sparse).unwrap();
+        file.write_all(b"old\n").unwrap();
+        file.set_len(64 * 1024 * 1024).unwrap();
+        drop(file);
+        let metadata = fs::metadata(&sparse).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            assert_eq!(is_sparse(&sparse, &metadata), metadata.blocks() * 512 < metadata.len());
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! Where the time went during a run, for finding the files that make a sweep slow.
+
+use crate::display_path;
+use std::cmp::Reverse;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// The spans whose time is totalled, with how each is described in the report
+const STAGES: [(&str, &str); 5] = [
+    ("sniff", "binary detection"),
+    ("read", "reading"),
+    ("match", "matching"),
+    ("replace", "replacing"),
+    ("write", "writing"),
+];
+
+/// How many of the slowest files to list
+const SLOWEST_FILES: usize = 10;
+
+/// Bytes in a megabyte, as throughput is reported
+const MEGABYTE: f64 = 1024.0 * 1024.0;
+
+/// Timings collected from every walker thread
+struct Stats {
+    started: Instant,
+    walk_nanos: AtomicU64,
+    stage_nanos: [AtomicU64; STAGES.len()],
+    files: Mutex<Vec<(Duration, PathBuf)>>,
+    /// The number and total size of the files searched, for `fr bench`
+    searched_files: AtomicU64,
+    searched_bytes: AtomicU64,
+}
+
+static STATS: OnceLock<Stats> = OnceLock::new();
+
+/// Starts collecting timings.
+pub fn start() {
+    let _ = STATS.set(Stats {
+        started: Instant::now(),
+        walk_nanos: AtomicU64::new(0),
+        stage_nanos: Default::default(),
+        files: Mutex::new(Vec::new()),
+        searched_files: AtomicU64::new(0),
+        searched_bytes: AtomicU64::new(0),
+    });
+}
+
+/// Whether timings are being collected
+pub fn is_active() -> bool {
+    STATS.get().is_some()
+}
+
+fn nanos(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+}
+
+/// Records a finished span.
+pub fn record_span(name: &str, path: &Path, duration: Duration) {
+    let Some(stats) = STATS.get() else {
+        return;
+    };
+    if name == "file" {
+        stats.files.lock().unwrap().push((duration, path.to_path_buf()));
+    } else if let Some(stage) = STAGES.iter().position(|(stage, _)| *stage == name) {
+        stats.stage_nanos[stage].fetch_add(nanos(duration), Ordering::Relaxed);
+    }
+}
+
+/// Records time a walker thread spent finding its next entry.
+pub fn record_walk(duration: Duration) {
+    if let Some(stats) = STATS.get() {
+        stats.walk_nanos.fetch_add(nanos(duration), Ordering::Relaxed);
+    }
+}
+
+/// Records a file that is about to be searched.
+///
+/// # Arguments
+///
+/// * `size` - The size of the file in bytes
+pub fn record_searched(size: u64) {
+    if let Some(stats) = STATS.get() {
+        stats.searched_files.fetch_add(1, Ordering::Relaxed);
+        stats.searched_bytes.fetch_add(size, Ordering::Relaxed);
+    }
+}
+
+/// Returns the time summed across threads for each stage, with how it is described.
+fn stage_totals(stats: &Stats) -> Vec<(&'static str, Duration)> {
+    STAGES.iter().zip(&stats.stage_nanos)
+        .map(|((_, description), total)| (*description, Duration::from_nanos(total.load(Ordering::Relaxed))))
+        .collect()
+}
+
+/// Renders the throughput of the run since `start`, for `fr bench`, if `start`
+/// was called.
+pub fn bench_report() -> Option<String> {
+    let stats = STATS.get()?;
+    Some(format_bench(
+        stats.started.elapsed(),
+        &stage_totals(stats),
+        stats.searched_files.load(Ordering::Relaxed),
+        stats.searched_bytes.load(Ordering::Relaxed),
+    ))
+}
+
+/// Describes how many megabytes and files were processed per second.
+fn throughput(bytes: u64, files: u64, duration: Duration) -> (String, String) {
+    if duration.is_zero() {
+        return ("-".to_string(), "-".to_string());
+    }
+    let seconds = duration.as_secs_f64();
+    (format!("{:.1}", bytes as f64 / MEGABYTE / seconds), format!("{:.0}", files as f64 / seconds))
+}
+
+fn format_bench(wall: Duration, stages: &[(&str, Duration)], files: u64, bytes: u64) -> String {
+    let mut out = String::new();
+    let (megabytes_per_second, files_per_second) = throughput(bytes, files, wall);
+    let _ = writeln!(out, "Searched {} file{} ({:.1} MB) in {:.3?}: {} MB/s, {} files/s",
+        files, if files == 1 { "" } else { "s" }, bytes as f64 / MEGABYTE, wall, megabytes_per_second, files_per_second);
+    let _ = writeln!(out, "Per thread:");
+    let _ = writeln!(out, "  {:<18}{:>12}{:>12}{:>12}", "stage", "time", "MB/s", "files/s");
+    for (description, total) in stages {
+        let (megabytes_per_second, files_per_second) = throughput(bytes, files, *total);
+        let _ = writeln!(out, "  {:<18}{:>12.3?}{:>12}{:>12}", description, total, megabytes_per_second, files_per_second);
+    }
+    out
+}
+
+/// Renders the timings collected since `start`, if it was called.
+pub fn report(starting_directory: &Path) -> Option<String> {
+    let stats = STATS.get()?;
+    let stages = stage_totals(stats);
+    let mut files = stats.files.lock().unwrap().clone();
+    Some(format_report(
+        stats.started.elapsed(),
+        Duration::from_nanos(stats.walk_nanos.load(Ordering::Relaxed)),
+        &stages,
+        &mut files,
+        starting_directory,
+    ))
+}
+
+fn format_report(
+    wall: Duration,
+    walk: Duration,
+    stages: &[(&str, Duration)],
+    files: &mut [(Duration, PathBuf)],
+    starting_directory: &Path,
+) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "{:<20}{:>12.3?}", "wall clock", wall);
+    let _ = writeln!(out, "Time summed across threads:");
+    let _ = writeln!(out, "  {:<18}{:>12.3?}", "walking", walk);
+    for (description, total) in stages {
+        let _ = writeln!(out, "  {:<18}{:>12.3?}", description, total);
+    }
+    let _ = writeln!(out, "{:<20}{:>12}", "entries processed", files.len());
+
+    files.sort_by_key(|(duration, _)| Reverse(*duration));
+    if !files.is_empty() {
+        let _ = writeln!(out, "Slowest files:");
+    }
+    for (duration, path) in files.iter().take(SLOWEST_FILES) {
+        let _ = writeln!(out, "  {:>12.3?}  {}", duration, display_path(starting_directory, path).display());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_bench() {
+        let stages = [("reading", Duration::from_millis(500)), ("writing", Duration::ZERO)];
+        let report = format_bench(Duration::from_secs(2), &stages, 1000, 200 << 20);
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines, [
+            "Searched 1000 files (200.0 MB) in 2.000s: 100.0 MB/s, 500 files/s",
+            "Per thread:",
+            "  stage                     time        MB/s     files/s",
+            "  reading              500.000ms       400.0        2000",
+            "  writing                0.000ns           -           -",
+        ]);
+    }
+
+    #[test]
+    fn test_format_report() {
+        let root = Path::new("/repo");
+        let mut files: Vec<(Duration, PathBuf)> = (1..=12)
+            .map(|i| (Duration::from_millis(i), root.join(format!("file{}.txt", i))))
+            .collect();
+        let stages = [("reading", Duration::from_millis(5)), ("writing", Duration::from_millis(2))];
+
+        let report = format_report(Duration::from_millis(20), Duration::from_millis(3), &stages, &mut files, root);
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines[0], "wall clock              20.000ms");
+        assert_eq!(lines[1], "Time summed across threads:");
+        assert_eq!(lines[2], "  walking                3.000ms");
+        assert_eq!(lines[3], "  reading                5.000ms");
+        assert_eq!(lines[4], "  writing                2.000ms");
+        assert_eq!(lines[5], "entries processed             12");
+        assert_eq!(lines[6], "Slowest files:");
+        assert_eq!(lines[7], "      12.000ms  file12.txt");
+        assert_eq!(lines.len(), 7 + SLOWEST_FILES);
+        assert_eq!(lines[7 + SLOWEST_FILES - 1], "       3.000ms  file3.txt");
+    }
+}
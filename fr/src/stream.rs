@@ -0,0 +1,207 @@
+//! Rewriting huge files without reading them into memory.
+//!
+//! Files larger than `THRESHOLD` are read, replaced and written a chunk at a
+//! time, so memory use stays bounded however large the file is. The whole
+//! contents are never available, so this is only done when nothing needs them,
+//! such as diffs, the change log or reports.
+
+use crate::eol::{self, LineEnding};
+use crate::{sync_parent, temp_path};
+use crate::streams;
+use crate::throttle;
+use crate::xattr;
+use memchr::memmem;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// Files larger than this many bytes are streamed when possible
+pub const THRESHOLD: u64 = 64 * 1024 * 1024;
+
+/// How much of a file is read at a time
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Roughly how many bytes streaming a file holds in memory: the reader's
+/// buffer, the chunk read from it, and the buffer being matched
+pub const BUFFERED: u64 = 3 * CHUNK_SIZE as u64;
+
+/// Replaces every occurrence of the find text while copying from a reader to
+/// a writer.
+///
+/// # Arguments
+///
+/// * `reader` - Where to read the original contents from
+/// * `writer` - Where to write the rewritten contents to
+/// * `find` - Bytes to find
+/// * `replace` - Bytes to replace them with
+/// * `chunk_size` - How many bytes to read at a time
+///
+/// # Returns
+///
+/// * `io::Result<usize>` - The number of replacements made
+fn replace(mut reader: impl Read, mut writer: impl Write, find: &[u8], replace: &[u8], chunk_size: usize) -> io::Result<usize> {
+    let finder = memmem::Finder::new(find);
+    let mut buffer = Vec::with_capacity(chunk_size + find.len());
+    let mut chunk = vec![0; chunk_size];
+    let mut replacements = 0;
+    loop {
+        let read = match reader.read(&mut chunk) {
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        throttle::consume(read);
+        buffer.extend_from_slice(&chunk[..read]);
+
+        let (mut copied, mut written) = (0, 0);
+        for offset in finder.find_iter(&buffer) {
+            writer.write_all(&buffer[copied..offset])?;
+            writer.write_all(replace)?;
+            written += offset - copied + replace.len();
+            copied = offset + find.len();
+            replacements += 1;
+        }
+
+        // Hold back the end of the buffer, which may be the start of a match
+        // that the next chunk completes
+        let held_back = if read == 0 { buffer.len() } else { buffer.len().saturating_sub(find.len() - 1).max(copied) };
+        writer.write_all(&buffer[copied..held_back])?;
+        throttle::consume(written + held_back - copied);
+        buffer.drain(..held_back);
+        if read == 0 {
+            writer.flush()?;
+            return Ok(replacements);
+        }
+    }
+}
+
+/// Replaces every occurrence of the find text while copying from a reader to
+/// a writer, a chunk at a time, as `fr` does when it reads from stdin.
+///
+/// # Arguments
+///
+/// * `reader` - Where to read the original contents from
+/// * `writer` - Where to write the rewritten contents to
+/// * `find_text` - Text to find; its newlines match CRLFs if the start of the
+///   contents uses them
+/// * `replace_text` - Text to replace the found text with
+///
+/// # Returns
+///
+/// * `io::Result<usize>` - The number of replacements made
+pub fn replace_stream(reader: impl Read, writer: impl Write, find_text: &str, replace_text: &str) -> io::Result<usize> {
+    let mut reader = BufReader::with_capacity(CHUNK_SIZE, reader);
+    let ending = LineEnding::detect(&String::from_utf8_lossy(reader.fill_buf()?));
+    let find_text = eol::adapt(find_text, ending);
+    let replace_text = eol::adapt(replace_text, ending);
+    replace(reader, writer, find_text.as_bytes(), replace_text.as_bytes(), CHUNK_SIZE)
+}
+
+/// Replaces every occurrence of the find text in a file, a chunk at a time. The
+/// file is rewritten through a temporary file next to it, which is renamed into
+/// place once it is complete, and keeps the file's permissions, ACL, security
+/// labels and alternate data streams.
+///
+/// # Arguments
+///
+/// * `file_path` - Path to the file to rewrite
+/// * `find_text` - Text to find; its newlines match CRLFs if the start of the
+///   file uses them
+/// * `replace_text` - Text to replace the found text with
+/// * `dry_run` - Only count the replacements that would be made
+/// * `sync` - Flush the rewritten file and its directory to disk before
+///   returning
+///
+/// # Returns
+///
+/// * `io::Result<usize>` - The number of replacements made, leaving the file
+///   untouched if there were none
+pub fn replace_file(file_path: &Path, find_text: &str, replace_text: &str, dry_run: bool, sync: bool) -> io::Result<usize> {
+    let file = File::open(file_path)?;
+    let permissions = file.metadata()?.permissions();
+
+    if dry_run {
+        return replace_stream(file, io::sink(), find_text, replace_text);
+    }
+
+    // Rename onto the target of a symlink, so the link stays a link
+    let file_path = &fs::canonicalize(file_path)?;
+    let temp_path = temp_path(file_path)?;
+    let result = File::create(&temp_path).and_then(|temp_file| {
+        let replacements = replace_stream(&file, BufWriter::new(&temp_file), find_text, replace_text)?;
+        if replacements > 0 {
+            temp_file.set_permissions(permissions)?;
+            // After writing, which would clear the file's capabilities
+            xattr::copy_security(&file, &temp_file)?;
+            streams::copy_named(file_path, &temp_path)?;
+            if sync {
+                temp_file.sync_all()?;
+            }
+            fs::rename(&temp_path, file_path)?;
+            if sync {
+                sync_parent(file_path)?;
+            }
+        }
+        Ok(replacements)
+    });
+    if !matches!(result, Ok(replacements) if replacements > 0) {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn replaced(input: &str, find: &str, replace_with: &str, chunk_size: usize) -> (String, usize) {
+        let mut output = Vec::new();
+        let replacements = replace(input.as_bytes(), &mut output, find.as_bytes(), replace_with.as_bytes(), chunk_size).unwrap();
+        (String::from_utf8(output).unwrap(), replacements)
+    }
+
+    #[test]
+    fn test_replace_matches_whole_file_replacement() {
+        let input = "hello world, hello there, hellohello";
+        for chunk_size in [1, 2, 3, 5, 7, 64] {
+            assert_eq!(replaced(input, "hello", "bye", chunk_size), (input.replace("hello", "bye"), 4));
+        }
+        // Matches don't overlap, so "aaa" holds one match of "aa"
+        for chunk_size in [1, 2, 4] {
+            assert_eq!(replaced("aaa", "aa", "b", chunk_size), ("ba".to_string(), 1));
+        }
+        assert_eq!(replaced("nothing here", "hello", "bye", 4), ("nothing here".to_string(), 0));
+    }
+
+    #[test]
+    fn test_replace_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("big.log");
+        fs::write(&file_path, "old\r\nline\r\nold\r\n").unwrap();
+
+        assert_eq!(replace_file(&file_path, "old\nline", "new", true, false).unwrap(), 1);
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "old\r\nline\r\nold\r\n");
+
+        assert_eq!(replace_file(&file_path, "old", "new\nline", false, true).unwrap(), 2);
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "new\r\nline\r\nline\r\nnew\r\nline\r\n");
+
+        assert_eq!(replace_file(&file_path, "missing", "new", false, false).unwrap(), 0);
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_replace_file_through_symlink() {
+        let temp_dir = TempDir::new().unwrap();
+        let target = temp_dir.path().join("target.log");
+        let link = temp_dir.path().join("link.log");
+        fs::write(&target, "old line").unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        assert_eq!(replace_file(&link, "old", "new", false, false).unwrap(), 1);
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new line");
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 2);
+    }
+}
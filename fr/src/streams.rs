@@ -0,0 +1,120 @@
+//! NTFS alternate data streams, which hold text such as the
+//! `Zone.Identifier` Windows adds to downloaded files.
+//!
+//! A file rewritten in place keeps its named streams, but one renamed into
+//! place from a temporary file has only the temporary file's, so they are
+//! copied across first. With `--streams`, the text in each named stream is
+//! also searched and rewritten, as a file named `path:stream` would be.
+//! Other platforms have no alternate streams.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Lists the names of a file's alternate data streams, such as
+/// `Zone.Identifier`, leaving out its unnamed main stream.
+#[cfg(windows)]
+pub fn named(path: &Path) -> io::Result<Vec<String>> {
+    use std::ffi::c_void;
+    use std::os::windows::ffi::OsStrExt;
+
+    /// WIN32_FIND_STREAM_DATA, with room for MAX_PATH + 36 UTF-16 units of
+    /// name
+    #[repr(C)]
+    struct FindStreamData {
+        _stream_size: i64,
+        stream_name: [u16; 296],
+    }
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        fn FindFirstStreamW(file_name: *const u16, info_level: i32, data: *mut c_void, flags: u32) -> *mut c_void;
+        fn FindNextStreamW(find: *mut c_void, data: *mut c_void) -> i32;
+        fn FindClose(find: *mut c_void) -> i32;
+    }
+    const FIND_STREAM_INFO_STANDARD: i32 = 0;
+    const ERROR_HANDLE_EOF: i32 = 38;
+
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain([0]).collect();
+    let mut data = FindStreamData { _stream_size: 0, stream_name: [0; 296] };
+    let data_ptr = (&mut data as *mut FindStreamData).cast();
+    // SAFETY: the name is NUL-terminated and the data is a live
+    // WIN32_FIND_STREAM_DATA
+    let find = unsafe { FindFirstStreamW(wide.as_ptr(), FIND_STREAM_INFO_STANDARD, data_ptr, 0) };
+    if find as isize == -1 {
+        let e = io::Error::last_os_error();
+        return match e.raw_os_error() {
+            Some(ERROR_HANDLE_EOF) => Ok(Vec::new()),
+            _ => Err(e),
+        };
+    }
+    let mut names = Vec::new();
+    loop {
+        let length = data.stream_name.iter().position(|&unit| unit == 0).unwrap_or(data.stream_name.len());
+        // Streams are listed as ":name:$DATA", the main one as "::$DATA"
+        let full = String::from_utf16_lossy(&data.stream_name[..length]);
+        if let Some(name) = full.strip_prefix(':').and_then(|rest| rest.strip_suffix(":$DATA")).filter(|name| !name.is_empty()) {
+            names.push(name.to_string());
+        }
+        // SAFETY: the handle is open and the data is a live
+        // WIN32_FIND_STREAM_DATA
+        if unsafe { FindNextStreamW(find, data_ptr) } == 0 {
+            break;
+        }
+    }
+    // SAFETY: the handle is open, and isn't used again
+    unsafe { FindClose(find) };
+    Ok(names)
+}
+
+/// Lists the names of a file's alternate data streams, which this platform
+/// doesn't have.
+#[cfg(not(windows))]
+pub fn named(_path: &Path) -> io::Result<Vec<String>> {
+    Ok(Vec::new())
+}
+
+/// Returns the path a named stream of a file is read and written through.
+pub fn path(file: &Path, name: &str) -> PathBuf {
+    let mut path = file.as_os_str().to_os_string();
+    path.push(":");
+    path.push(name);
+    PathBuf::from(path)
+}
+
+/// Copies every named stream of a file to the file that will replace it.
+///
+/// # Arguments
+///
+/// * `from` - The original file
+/// * `to` - The file that will be renamed over it
+///
+/// # Returns
+///
+/// * `io::Result<()>` - An error if a stream couldn't be copied, in which case
+///   the new file should not replace the original
+pub fn copy_named(from: &Path, to: &Path) -> io::Result<()> {
+    for name in named(from)? {
+        std::fs::write(path(to, &name), std::fs::read(path(from, &name))?)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path() {
+        assert_eq!(path(Path::new("setup.exe"), "Zone.Identifier"), Path::new("setup.exe:Zone.Identifier"));
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn test_named() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let file = temp_dir.path().join("a.txt");
+        std::fs::write(&file, "main").unwrap();
+        assert!(named(&file).unwrap().is_empty());
+        std::fs::write(path(&file, "Zone.Identifier"), "[ZoneTransfer]\r\nZoneId=3\r\n").unwrap();
+        assert_eq!(named(&file).unwrap(), vec!["Zone.Identifier".to_string()]);
+    }
+}
@@ -0,0 +1,144 @@
+//! Keeping a background sweep out of the way, for `--throttle` and `--nice`.
+//!
+//! `--throttle` caps how many bytes per second are read and written across all
+//! threads. Each read or write books the time it takes at that rate after the
+//! ones before it, and waits for its turn. `--nice` lowers the CPU priority of the
+//! process, and its IO priority on Linux, before any threads are started so that
+//! they all inherit it.
+
+use crate::parse_size;
+use std::io;
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How much `--nice` lowers the CPU priority by, as a number of nice levels
+const NICENESS: i32 = 10;
+
+/// Hands out turns to read and write at a fixed rate
+#[derive(Debug)]
+struct Throttle {
+    bytes_per_second: u64,
+    /// When the last booked read or write is done, at the throttled rate
+    booked_until: Mutex<Option<Instant>>,
+}
+
+impl Throttle {
+    /// Books a read or write, returning how long to wait before doing it.
+    fn book(&self, bytes: usize, now: Instant) -> Duration {
+        let duration = Duration::from_secs_f64(bytes as f64 / self.bytes_per_second as f64);
+        let mut booked_until = self.booked_until.lock().unwrap();
+        let start = booked_until.map_or(now, |until| until.max(now));
+        *booked_until = Some(start + duration);
+        start - now
+    }
+}
+
+static THROTTLE: OnceLock<Throttle> = OnceLock::new();
+
+/// Starts limiting reads and writes to a number of bytes per second.
+pub fn start(bytes_per_second: u64) {
+    let _ = THROTTLE.set(Throttle { bytes_per_second, booked_until: Mutex::new(None) });
+}
+
+/// Waits until some bytes may be read or written, if reads and writes are
+/// throttled.
+///
+/// # Arguments
+///
+/// * `bytes` - How many bytes are about to be, or just were, read or written
+pub fn consume(bytes: usize) {
+    let Some(throttle) = THROTTLE.get() else {
+        return;
+    };
+    let wait = throttle.book(bytes, Instant::now());
+    if !wait.is_zero() {
+        thread::sleep(wait);
+    }
+}
+
+/// Parses a `--throttle` rate into bytes per second. A plain number is a number
+/// of megabytes per second; sizes such as `512K` or `1G` may be given instead,
+/// with or without a `/s` suffix.
+pub fn parse_rate(value: &str) -> Result<u64, String> {
+    let size = value.trim();
+    let size = size.strip_suffix("/s").or_else(|| size.strip_suffix("/S")).unwrap_or(size);
+    let bytes = if !size.is_empty() && size.bytes().all(|byte| byte.is_ascii_digit()) {
+        parse_size(&format!("{}M", size))
+    } else {
+        parse_size(size)
+    };
+    match bytes {
+        Ok(0) | Err(_) => Err(format!("Invalid rate '{}', expected megabytes per second such as 10, or a size such as 512K", value)),
+        Ok(bytes) => Ok(bytes),
+    }
+}
+
+/// Lowers the priority of the process, for `--nice`.
+#[cfg(unix)]
+pub fn lower_priority() -> io::Result<()> {
+    // SAFETY: getpriority and setpriority only act on the calling process
+    let result = unsafe {
+        let current = libc::getpriority(libc::PRIO_PROCESS, 0);
+        libc::setpriority(libc::PRIO_PROCESS, 0, (current + NICENESS).min(19))
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    #[cfg(target_os = "linux")]
+    lower_io_priority()?;
+    Ok(())
+}
+
+/// Lowers the priority of the process, for `--nice`.
+#[cfg(not(unix))]
+pub fn lower_priority() -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "--nice is not supported on this platform"))
+}
+
+/// Moves the process to the lowest best-effort IO priority, so the disk serves
+/// everything else first without fr waiting forever on a busy disk.
+#[cfg(target_os = "linux")]
+fn lower_io_priority() -> io::Result<()> {
+    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+    const IOPRIO_CLASS_BE: libc::c_int = 2;
+    const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+    const LOWEST_LEVEL: libc::c_int = 7;
+    // SAFETY: ioprio_set only acts on the calling process
+    let result = unsafe {
+        libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, IOPRIO_CLASS_BE << IOPRIO_CLASS_SHIFT | LOWEST_LEVEL)
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rate() {
+        assert_eq!(parse_rate("10"), Ok(10 << 20));
+        assert_eq!(parse_rate("10/s"), Ok(10 << 20));
+        assert_eq!(parse_rate("512K"), Ok(512 << 10));
+        assert_eq!(parse_rate("1GB/s"), Ok(1 << 30));
+        assert!(parse_rate("0").is_err());
+        assert!(parse_rate("fast").is_err());
+        assert!(parse_rate("").is_err());
+    }
+
+    #[test]
+    fn test_book() {
+        let throttle = Throttle { bytes_per_second: 1000, booked_until: Mutex::new(None) };
+        let now = Instant::now();
+        assert_eq!(throttle.book(500, now), Duration::ZERO);
+        assert_eq!(throttle.book(1000, now), Duration::from_millis(500));
+        assert_eq!(throttle.book(1, now + Duration::from_millis(200)), Duration::from_millis(1300));
+
+        // Time spent idle isn't saved up for later
+        assert_eq!(throttle.book(1000, now + Duration::from_secs(10)), Duration::ZERO);
+        assert_eq!(throttle.book(1, now + Duration::from_secs(10)), Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,429 @@
+//! Replacing only in the values of chosen keys of TOML files, for
+//! `--toml-key`.
+//!
+//! A match is kept if it is inside a string value of one of the keys, and
+//! never in the key itself, a table header or a comment. A key names a
+//! value by its table and key, such as `package.version`, whether it is
+//! written under a `[package]` header, as a dotted key or in an inline table,
+//! and `*` stands for any one part, as in `dependencies.*.version`. Strings
+//! in an array, such as `features = ["a", "b"]`, belong to the array's key.
+
+use std::path::Path;
+
+/// A dotted key naming values in TOML files, such as `package.version`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TomlKey(Vec<Option<String>>);
+
+impl TomlKey {
+    /// Parses the value given to `--toml-key`: bare or quoted keys joined by
+    /// dots, where `*` matches any key.
+    pub fn parse(value: &str) -> Result<TomlKey, String> {
+        let mut scanner = Scanner { text: value, position: 0, table: Vec::new(), keys: &[], values: Vec::new() };
+        let mut parts = Vec::new();
+        loop {
+            if scanner.text[scanner.position..].starts_with('*') {
+                scanner.position += 1;
+                parts.push(None);
+            } else {
+                parts.push(Some(scanner.key().ok_or_else(|| format!("Invalid TOML key '{}'", value))?));
+            }
+            scanner.skip_spaces();
+            if scanner.position == value.len() {
+                return Ok(TomlKey(parts));
+            }
+            if !scanner.eat(b'.') {
+                return Err(format!("Invalid TOML key '{}': expected a . between keys", value));
+            }
+        }
+    }
+
+    fn matches(&self, path: &[String]) -> bool {
+        self.0.len() == path.len() && self.0.iter().zip(path).all(|(part, key)| part.as_ref().is_none_or(|part| part == key))
+    }
+}
+
+/// Returns whether a file is TOML, going by its extension, or is stdin.
+pub fn is_toml(file_path: &Path) -> bool {
+    file_path == Path::new("-")
+        || file_path.extension().and_then(|extension| extension.to_str()).is_some_and(|extension| extension.eq_ignore_ascii_case("toml"))
+}
+
+/// How a value is written, which decides what a replacement may contain
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Style {
+    Basic,
+    Literal,
+    MultiLineBasic,
+    MultiLineLiteral,
+    /// A number, date or boolean
+    Bare,
+}
+
+impl Style {
+    /// Whether text can go into a value of this style as it is.
+    fn allows(self, text: &str) -> bool {
+        match self {
+            Style::Basic => !text.contains(['"', '\\', '\n', '\r']),
+            Style::Literal => !text.contains(['\'', '\n', '\r']),
+            Style::MultiLineBasic => !text.contains(['"', '\\']),
+            Style::MultiLineLiteral => !text.contains('\''),
+            Style::Bare => text.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '+' | '.' | ':')),
+        }
+    }
+}
+
+/// Keeps the matches that lie wholly inside the value of one of the keys,
+/// where the replacement can go as it is.
+///
+/// # Arguments
+///
+/// * `content` - The TOML file
+/// * `offsets` - Where each match of the find text starts
+/// * `find_len` - Length of the find text in bytes
+/// * `replace_text` - Text each match would be replaced with
+/// * `keys` - Keys whose values may be rewritten
+///
+/// # Returns
+///
+/// * `Vec<usize>` - The offsets of the matches to replace, none if the file
+///   isn't valid TOML
+pub fn value_offsets(content: &str, offsets: &[usize], find_len: usize, replace_text: &str, keys: &[TomlKey]) -> Vec<usize> {
+    let mut scanner = Scanner { text: content, position: 0, table: Vec::new(), keys, values: Vec::new() };
+    if scanner.document().is_none() {
+        return Vec::new();
+    }
+    let mut values = scanner.values.iter().peekable();
+    offsets.iter().copied().filter(|&offset| {
+        while values.next_if(|&&(_, end, _)| end < offset + find_len).is_some() {}
+        values.peek().is_some_and(|&&(start, end, style)| {
+            offset >= start && offset + find_len <= end && style.allows(replace_text)
+                && (!matches!(style, Style::Basic | Style::MultiLineBasic) || outside_escapes(content, start, offset, offset + find_len))
+        })
+    }).collect()
+}
+
+/// Reads the strings a key has, such as the paths of `workspace.members`,
+/// whether the key has one string or an array of them.
+///
+/// # Arguments
+///
+/// * `content` - The TOML file
+/// * `key` - The key to read
+///
+/// # Returns
+///
+/// * `Option<Vec<String>>` - The strings, with basic strings unescaped, or
+///   None if the file isn't valid TOML
+pub fn strings(content: &str, key: &TomlKey) -> Option<Vec<String>> {
+    let keys = std::slice::from_ref(key);
+    let mut scanner = Scanner { text: content, position: 0, table: Vec::new(), keys, values: Vec::new() };
+    scanner.document()?;
+    Some(scanner.values.iter().filter(|&&(_, _, style)| style != Style::Bare).map(|&(start, end, style)| {
+        let text = &content[start..end];
+        match style {
+            Style::Basic | Style::MultiLineBasic => unescape(text),
+            _ => text.to_string(),
+        }
+    }).collect())
+}
+
+/// Undoes the escapes of a basic string that a path or a name may have.
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some(other) => unescaped.push(other),
+            None => {}
+        }
+    }
+    unescaped
+}
+
+/// Whether a match in a basic string starts and ends between characters
+/// rather than within an escape sequence.
+fn outside_escapes(content: &str, start: usize, offset: usize, end: usize) -> bool {
+    let bytes = content.as_bytes();
+    let mut position = start;
+    let mut on_offset = false;
+    while position < end {
+        on_offset |= position == offset;
+        position += match (bytes[position], bytes.get(position + 1)) {
+            (b'\\', Some(b'u')) => 6,
+            (b'\\', Some(b'U')) => 10,
+            (b'\\', _) => 2,
+            _ => 1,
+        };
+    }
+    on_offset && position == end
+}
+
+/// How deeply arrays and inline tables may nest
+const MAX_DEPTH: usize = 128;
+
+/// Walks a document, noting where the values of the keys start and end
+struct Scanner<'a> {
+    text: &'a str,
+    position: usize,
+    /// The table of the last header
+    table: Vec<String>,
+    keys: &'a [TomlKey],
+    values: Vec<(usize, usize, Style)>,
+}
+
+impl<'a> Scanner<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.position..]
+    }
+
+    fn skip_spaces(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start_matches([' ', '\t']).len();
+    }
+
+    /// Skips spaces, newlines and comments, as between the values of an array.
+    fn skip_blank(&mut self) {
+        loop {
+            let rest = self.rest();
+            self.position += rest.len() - rest.trim_start_matches([' ', '\t', '\r', '\n']).len();
+            if !self.rest().starts_with('#') {
+                return;
+            }
+            self.position += self.rest().find('\n').unwrap_or(self.rest().len());
+        }
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        self.skip_spaces();
+        let found = self.rest().as_bytes().first() == Some(&byte);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    /// Reads one bare or quoted key.
+    fn key(&mut self) -> Option<String> {
+        self.skip_spaces();
+        let rest = self.rest();
+        let key = match rest.as_bytes().first()? {
+            quote @ (b'"' | b'\'') => {
+                let end = rest[1..].find(*quote as char)? + 1;
+                self.position += end + 1;
+                rest[1..end].to_string()
+            }
+            _ => {
+                let len = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_' && c != '-').unwrap_or(rest.len());
+                if len == 0 {
+                    return None;
+                }
+                self.position += len;
+                rest[..len].to_string()
+            }
+        };
+        Some(key)
+    }
+
+    /// Reads a dotted key.
+    fn dotted_key(&mut self) -> Option<Vec<String>> {
+        let mut parts = vec![self.key()?];
+        while self.eat(b'.') {
+            parts.push(self.key()?);
+        }
+        Some(parts)
+    }
+
+    fn document(&mut self) -> Option<()> {
+        loop {
+            self.skip_blank();
+            if self.rest().is_empty() {
+                return Some(());
+            }
+            if self.eat(b'[') {
+                let array = self.eat(b'[');
+                self.table = self.dotted_key()?;
+                (self.eat(b']') && (!array || self.eat(b']'))).then_some(())?;
+            } else {
+                let mut path = self.table.clone();
+                path.extend(self.dotted_key()?);
+                self.eat(b'=').then_some(())?;
+                self.value(&path, 0)?;
+            }
+            // Nothing but a comment may follow on the line
+            self.skip_spaces();
+            let rest = self.rest();
+            if !(rest.is_empty() || rest.starts_with(['#', '\n']) || rest.starts_with("\r\n")) {
+                return None;
+            }
+            self.position += rest.find('\n').map_or(rest.len(), |len| len + 1);
+        }
+    }
+
+    fn value(&mut self, path: &[String], depth: usize) -> Option<()> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+        self.skip_spaces();
+        let wanted = self.keys.iter().any(|key| key.matches(path));
+        let rest = self.rest();
+        let (start, end, style) = if rest.starts_with("\"\"\"") {
+            (3, basic_end(rest, 3, true)?, Style::MultiLineBasic)
+        } else if let Some(after_quotes) = rest.strip_prefix("'''") {
+            // Up to two more quotes before the closing ones are part of the string
+            let len = after_quotes.find("'''")? + 3;
+            let after = &rest[len + 3..];
+            (3, len + (after.len() - after.trim_start_matches('\'').len()).min(2), Style::MultiLineLiteral)
+        } else {
+            match rest.as_bytes().first()? {
+                b'"' => (1, basic_end(rest, 1, false)?, Style::Basic),
+                b'\'' => (1, rest[1..].find(['\'', '\n']).filter(|&len| rest.as_bytes()[1 + len] == b'\'')? + 1, Style::Literal),
+                b'[' => {
+                    self.position += 1;
+                    loop {
+                        self.skip_blank();
+                        if self.eat(b']') {
+                            return Some(());
+                        }
+                        self.value(path, depth + 1)?;
+                        self.skip_blank();
+                        if !self.eat(b',') {
+                            self.skip_blank();
+                            return self.eat(b']').then_some(());
+                        }
+                    }
+                }
+                b'{' => {
+                    self.position += 1;
+                    if self.eat(b'}') {
+                        return Some(());
+                    }
+                    loop {
+                        let mut inner = path.to_vec();
+                        inner.extend(self.dotted_key()?);
+                        self.eat(b'=').then_some(())?;
+                        self.value(&inner, depth + 1)?;
+                        if self.eat(b'}') {
+                            return Some(());
+                        }
+                        self.eat(b',').then_some(())?;
+                    }
+                }
+                _ => {
+                    // A date may have a space between its date and time
+                    let len = rest.find([',', ']', '}', '#', '\n', '\r', '\t'])
+                        .unwrap_or(rest.len());
+                    let len = rest[..len].trim_end().len();
+                    if len == 0 {
+                        return None;
+                    }
+                    (0, len, Style::Bare)
+                }
+            }
+        };
+        let closing = match style {
+            Style::Basic | Style::Literal => 1,
+            Style::MultiLineBasic | Style::MultiLineLiteral => 3,
+            Style::Bare => 0,
+        };
+        if wanted {
+            self.values.push((self.position + start, self.position + end, style));
+        }
+        self.position += end + closing;
+        Some(())
+    }
+}
+
+/// Finds where a basic string that starts at `start` in the text ends,
+/// skipping escaped quotes.
+fn basic_end(text: &str, start: usize, multi_line: bool) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let mut position = start;
+    loop {
+        match bytes.get(position)? {
+            b'\\' => position += 2,
+            b'"' if !multi_line => return Some(position),
+            b'"' if text[position..].starts_with("\"\"\"") => {
+                let extra = text[position + 3..].len() - text[position + 3..].trim_start_matches('"').len();
+                return Some(position + extra.min(2));
+            }
+            b'\n' if !multi_line => return None,
+            _ => position += 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{match_offsets, splice};
+
+    fn replace(content: &str, find_text: &str, replace_text: &str, keys: &[&str]) -> String {
+        let keys: Vec<TomlKey> = keys.iter().map(|key| TomlKey::parse(key).unwrap()).collect();
+        let offsets = value_offsets(content, &match_offsets(content, find_text), find_text.len(), replace_text, &keys);
+        splice(content, &offsets, find_text.len(), replace_text)
+    }
+
+    const MANIFEST: &str = r#"# version = "0.1.0"
+[package]
+name = "fr"
+version = "0.1.0" # bumped by release.sh
+description = """
+Version 0.1.0 of "fr"."""
+
+[dependencies]
+serde = { version = "0.1.0", features = ["derive"] }
+log.version = '0.1.0'
+regex = "0.1.0"
+
+[[bin]]
+name = "fr"
+test = false
+"#;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(TomlKey::parse("dependencies.* . \"a.b\"").unwrap(), TomlKey(vec![Some("dependencies".to_string()), None, Some("a.b".to_string())]));
+        for invalid in ["", "package.", "package version", "a.\"b"] {
+            assert!(TomlKey::parse(invalid).is_err(), "{:?} should not parse", invalid);
+        }
+    }
+
+    #[test]
+    fn test_value_offsets() {
+        assert_eq!(replace(MANIFEST, "0.1.0", "0.2.0", &["package.version"]),
+            MANIFEST.replacen("version = \"0.1.0\" #", "version = \"0.2.0\" #", 1));
+        assert_eq!(replace(MANIFEST, "0.1.0", "0.2.0", &["dependencies.*.version", "package.description"]), MANIFEST
+            .replace("Version 0.1.0", "Version 0.2.0")
+            .replace("serde = { version = \"0.1.0\"", "serde = { version = \"0.2.0\"")
+            .replace("'0.1.0'", "'0.2.0'"));
+        assert_eq!(replace(MANIFEST, "derive", "std", &["dependencies.serde.features"]), MANIFEST.replace("\"derive\"", "\"std\""));
+        assert_eq!(replace(MANIFEST, "false", "true", &["bin.test"]), MANIFEST.replace("test = false", "test = true"));
+
+        // Replacements that would need escaping, and invalid files, are left alone
+        assert_eq!(replace(MANIFEST, "fr", "\"fr\"", &["package.name"]), MANIFEST);
+        assert_eq!(replace("a = [\"x\"\nb = \"x\"\n", "x", "y", &["b"]), "a = [\"x\"\nb = \"x\"\n");
+    }
+
+    #[test]
+    fn test_strings() {
+        let key = |key: &str| TomlKey::parse(key).unwrap();
+        assert_eq!(strings(MANIFEST, &key("package.name")), Some(vec!["fr".to_string()]));
+        assert_eq!(strings(MANIFEST, &key("dependencies.serde.features")), Some(vec!["derive".to_string()]));
+        assert_eq!(strings("[workspace]\nmembers = [\n  \"crates/*\", # all of them\n  'C:\\tools',\n]\n", &key("workspace.members")),
+            Some(vec!["crates/*".to_string(), "C:\\tools".to_string()]));
+        assert_eq!(strings(MANIFEST, &key("package.edition")), Some(Vec::new()));
+        assert_eq!(strings("[package", &key("package.name")), None);
+    }
+
+    #[test]
+    fn test_is_toml() {
+        assert!(is_toml(Path::new("crates/fr/Cargo.toml")));
+        assert!(!is_toml(Path::new("Cargo.lock")));
+    }
+}
@@ -0,0 +1,197 @@
+//! Debug logging and profiling spans.
+//!
+//! Log records (including the ignore crate's walk decisions) go to stderr
+//! through the `log` facade. Spans time a piece of work on one file; they are
+//! logged at trace level and can also be collected into a Chrome trace
+//! (viewable in `chrome://tracing` or Perfetto) to see how work was spread
+//! across the walker threads, or totalled up for `--stats`.
+
+use crate::{json, stats};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::cell::Cell;
+use std::fmt::Write as _;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Name of the environment variable that sets the log level
+pub const LOG_LEVEL_ENV: &str = "FR_LOG";
+
+/// Parses a log level such as `debug` or `off`.
+pub fn parse_level(value: &str) -> Result<LevelFilter, String> {
+    value.parse().map_err(|_| {
+        format!("Unknown log level '{}', expected one of: off, error, warn, info, debug, trace", value)
+    })
+}
+
+/// Writes log records to stderr
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("[{} {}] {}", record.level(), record.target(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+/// Sends log records at or above the given level to stderr.
+pub fn init_logging(level: LevelFilter) {
+    // Only fails if a logger is already installed, in which case keep it
+    let _ = log::set_logger(&LOGGER);
+    log::set_max_level(level);
+}
+
+/// A completed span, as recorded for a Chrome trace
+struct TraceEvent {
+    name: &'static str,
+    path: PathBuf,
+    thread: usize,
+    start: Duration,
+    duration: Duration,
+}
+
+/// Collects spans from every thread for writing out as a Chrome trace
+struct Profiler {
+    start: Instant,
+    events: Mutex<Vec<TraceEvent>>,
+}
+
+static PROFILER: OnceLock<Profiler> = OnceLock::new();
+
+/// Starts collecting spans for a Chrome trace.
+pub fn start_profiling() {
+    let _ = PROFILER.set(Profiler { start: Instant::now(), events: Mutex::new(Vec::new()) });
+}
+
+/// Returns a small, stable number identifying the current thread in traces.
+fn thread_number() -> usize {
+    static NEXT: AtomicUsize = AtomicUsize::new(1);
+    thread_local! {
+        static NUMBER: Cell<usize> = const { Cell::new(0) };
+    }
+    NUMBER.with(|number| {
+        if number.get() == 0 {
+            number.set(NEXT.fetch_add(1, Ordering::Relaxed));
+        }
+        number.get()
+    })
+}
+
+/// Times a piece of work on a file until it is dropped
+pub struct Span<'a> {
+    name: &'static str,
+    path: &'a Path,
+    start: Option<Instant>,
+}
+
+/// Starts a span, which does nothing unless trace logging or profiling is on.
+///
+/// # Arguments
+///
+/// * `name` - What is being done, such as `read` or `write`
+/// * `path` - The file it is being done to
+pub fn span<'a>(name: &'static str, path: &'a Path) -> Span<'a> {
+    let active = log::log_enabled!(Level::Trace) || PROFILER.get().is_some() || stats::is_active();
+    Span { name, path, start: active.then(Instant::now) }
+}
+
+impl Span<'_> {
+    /// Runs a closure inside the span, ending the span when it returns.
+    pub fn run<T>(self, work: impl FnOnce() -> T) -> T {
+        work()
+    }
+}
+
+impl Drop for Span<'_> {
+    fn drop(&mut self) {
+        let Some(start) = self.start else {
+            return;
+        };
+        let duration = start.elapsed();
+        log::trace!("{} {} took {:?}", self.name, self.path.display(), duration);
+        stats::record_span(self.name, self.path, duration);
+        if let Some(profiler) = PROFILER.get() {
+            profiler.events.lock().unwrap().push(TraceEvent {
+                name: self.name,
+                path: self.path.to_path_buf(),
+                thread: thread_number(),
+                start: start.saturating_duration_since(profiler.start),
+                duration,
+            });
+        }
+    }
+}
+
+/// Renders spans in the Chrome trace event format.
+fn chrome_trace(events: &[TraceEvent]) -> String {
+    let mut out = String::from("{\"traceEvents\":[");
+    for (i, event) in events.iter().enumerate() {
+        let _ = write!(out, "{}\n{{\"name\":{},\"cat\":\"fr\",\"ph\":\"X\",\"ts\":{},\"dur\":{},\"pid\":1,\"tid\":{},\"args\":{{\"path\":{}}}}}",
+            if i == 0 { "" } else { "," },
+            json::string(event.name),
+            event.start.as_micros(),
+            event.duration.as_micros(),
+            event.thread,
+            json::string(&event.path.display().to_string()));
+    }
+    out.push_str("\n],\"displayTimeUnit\":\"ms\"}\n");
+    out
+}
+
+/// Writes the spans collected since profiling started as a Chrome trace.
+pub fn write_chrome_trace(path: &Path) -> io::Result<()> {
+    let Some(profiler) = PROFILER.get() else {
+        return Ok(());
+    };
+    let events = profiler.events.lock().unwrap();
+    crate::write_atomically(path, chrome_trace(&events).as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_level() {
+        assert_eq!(parse_level("debug").unwrap(), LevelFilter::Debug);
+        assert_eq!(parse_level("OFF").unwrap(), LevelFilter::Off);
+        assert!(parse_level("loud").is_err());
+    }
+
+    #[test]
+    fn test_chrome_trace() {
+        let events = vec![
+            TraceEvent {
+                name: "read",
+                path: PathBuf::from("a.txt"),
+                thread: 1,
+                start: Duration::from_micros(10),
+                duration: Duration::from_micros(5),
+            },
+            TraceEvent {
+                name: "write",
+                path: PathBuf::from("a.txt"),
+                thread: 2,
+                start: Duration::from_micros(20),
+                duration: Duration::from_micros(7),
+            },
+        ];
+        assert_eq!(chrome_trace(&events), concat!(
+            "{\"traceEvents\":[\n",
+            "{\"name\":\"read\",\"cat\":\"fr\",\"ph\":\"X\",\"ts\":10,\"dur\":5,\"pid\":1,\"tid\":1,\"args\":{\"path\":\"a.txt\"}},\n",
+            "{\"name\":\"write\",\"cat\":\"fr\",\"ph\":\"X\",\"ts\":20,\"dur\":7,\"pid\":1,\"tid\":2,\"args\":{\"path\":\"a.txt\"}}\n",
+            "],\"displayTimeUnit\":\"ms\"}\n",
+        ));
+    }
+}
@@ -0,0 +1,281 @@
+//! Replacing only inside URLs, for `--urls`, and checking that the rewritten
+//! URLs answer, for `--validate`.
+//!
+//! As with `--md-links`, matches are found in the text of the file and
+//! filtered down to those inside an `http://` or `https://` URL, which runs
+//! until whitespace, a quote or an angle bracket, less trailing punctuation
+//! and a closing bracket it didn't open. With `--validate`, each URL is
+//! rewritten and sent a HEAD request through `curl`, and the URLs that don't
+//! answer with a success or a redirect are left alone, with a warning. Each
+//! URL is only requested once however many files have it, and at most
+//! `--validate-jobs` requests are made at a time.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::process::{Command, Output};
+use std::sync::{Condvar, Mutex, OnceLock};
+
+/// How many requests are made at a time when `--validate-jobs` isn't given
+pub const DEFAULT_JOBS: usize = 8;
+
+/// How long to wait for a URL to answer, in seconds
+const TIMEOUT_SECONDS: u32 = 10;
+
+/// Whether a URL answered
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Liveness {
+    Live,
+    /// It answered with an error, or not at all, for this reason
+    Dead(String),
+}
+
+/// What the checker knows of the URLs
+#[derive(Debug, Default)]
+struct State {
+    /// How many requests are being made
+    running: usize,
+    /// Each URL requested, with its answer once it has come
+    results: HashMap<String, Option<Liveness>>,
+}
+
+/// Requests URLs, a limited number at a time, remembering the answers
+#[derive(Debug)]
+struct Checker {
+    jobs: usize,
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+impl Checker {
+    /// Finds whether a URL is live, requesting it unless it already has been.
+    fn check(&self, url: &str) -> Liveness {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            match state.results.get(url) {
+                Some(Some(liveness)) => return liveness.clone(),
+                None if state.running < self.jobs => break,
+                // Requested by another thread, or waiting for a turn
+                _ => state = self.changed.wait(state).unwrap(),
+            }
+        }
+        state.running += 1;
+        state.results.insert(url.to_string(), None);
+        drop(state);
+
+        let liveness = request(url);
+        let mut state = self.state.lock().unwrap();
+        state.running -= 1;
+        state.results.insert(url.to_string(), Some(liveness.clone()));
+        self.changed.notify_all();
+        liveness
+    }
+}
+
+static CHECKER: OnceLock<Checker> = OnceLock::new();
+
+/// Gets the checker, started with the default number of requests at a time
+/// if `start` wasn't called.
+fn checker() -> &'static Checker {
+    CHECKER.get_or_init(|| Checker { jobs: DEFAULT_JOBS, state: Mutex::new(State::default()), changed: Condvar::new() })
+}
+
+/// Starts checking URLs, making at most `jobs` requests at a time.
+///
+/// # Returns
+///
+/// * `io::Result<()>` - An error if `curl` can't be run
+pub fn start(jobs: usize) -> io::Result<()> {
+    Command::new("curl").arg("--version").output().map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => io::Error::new(e.kind(), "--validate needs curl, which isn't installed"),
+        _ => e,
+    })?;
+    let _ = CHECKER.set(Checker { jobs: jobs.max(1), state: Mutex::new(State::default()), changed: Condvar::new() });
+    Ok(())
+}
+
+/// Sends a URL a HEAD request, or a GET request for its first byte if the
+/// server doesn't take HEAD requests.
+fn request(url: &str) -> Liveness {
+    let curl = |head: bool| {
+        let mut command = Command::new("curl");
+        command.args(["--silent", "--show-error", "--location", "--globoff", "--output", "/dev/null"])
+            .args(["--max-time", &TIMEOUT_SECONDS.to_string(), "--write-out", "%{http_code}"]);
+        match head {
+            true => command.arg("--head"),
+            false => command.args(["--range", "0-0"]),
+        };
+        command.arg("--url").arg(url).output()
+    };
+    match curl(true) {
+        Ok(output) if matches!(status(&output), Some(405 | 501)) => curl(false).map_or_else(|e| Liveness::Dead(e.to_string()), |output| liveness(&output)),
+        Ok(output) => liveness(&output),
+        Err(e) => Liveness::Dead(e.to_string()),
+    }
+}
+
+/// The status code `curl` wrote out, or None if there was no answer.
+fn status(output: &Output) -> Option<u16> {
+    String::from_utf8_lossy(&output.stdout).trim().parse().ok().filter(|&code| code != 0)
+}
+
+/// Whether a URL is live, going by what `curl` made of its answer.
+fn liveness(output: &Output) -> Liveness {
+    match status(output) {
+        Some(200..=399) => Liveness::Live,
+        Some(code) => Liveness::Dead(format!("HTTP {}", code)),
+        None => {
+            let error = String::from_utf8_lossy(&output.stderr);
+            let reason = error.trim().strip_prefix("curl: ").unwrap_or(error.trim());
+            Liveness::Dead(if reason.is_empty() { "no answer".to_string() } else { reason.to_string() })
+        }
+    }
+}
+
+/// Describes the URLs that were checked, listing the dead ones.
+///
+/// # Returns
+///
+/// * `Option<String>` - The report, or None if no URL was checked
+pub fn report() -> Option<String> {
+    let state = CHECKER.get()?.state.lock().unwrap();
+    if state.results.is_empty() {
+        return None;
+    }
+    let mut dead: Vec<(&String, &String)> = state.results.iter()
+        .filter_map(|(url, liveness)| match liveness {
+            Some(Liveness::Dead(reason)) => Some((url, reason)),
+            _ => None,
+        })
+        .collect();
+    dead.sort();
+    let checked = state.results.len();
+    let mut report = format!("Checked {} rewritten URL{}, ", checked, if checked == 1 { "" } else { "s" });
+    match dead.len() {
+        0 => report.push_str("all live\n"),
+        count => {
+            report.push_str(&format!("{} dead and left alone:\n", count));
+            for (url, reason) in dead {
+                report.push_str(&format!("  {} ({})\n", url, reason));
+            }
+        }
+    }
+    Some(report)
+}
+
+/// Keeps the matches inside URLs, leaving out the URLs whose rewrite is dead
+/// when validating.
+///
+/// # Arguments
+///
+/// * `file_path` - Path of the file, for warnings
+/// * `content` - The file's text
+/// * `offsets` - Where each match of the find text starts
+/// * `find_len` - Length of the find text in bytes
+/// * `replace_text` - Text each match would be replaced with
+/// * `validate` - Whether to request each rewritten URL and leave it alone,
+///   with a warning, if it doesn't answer
+///
+/// # Returns
+///
+/// * `Vec<usize>` - The offsets of the matches to replace
+pub fn url_offsets(file_path: &Path, content: &str, offsets: &[usize], find_len: usize, replace_text: &str, validate: bool) -> Vec<usize> {
+    if replace_text.contains(|c: char| c.is_whitespace() || is_delimiter(c)) {
+        return Vec::new();
+    }
+    let mut kept = Vec::new();
+    let mut offsets = offsets.iter().copied().peekable();
+    for (start, end) in urls(content) {
+        while offsets.next_if(|&offset| offset < start).is_some() {}
+        let mut inside = Vec::new();
+        while let Some(offset) = offsets.next_if(|&offset| offset + find_len <= end) {
+            inside.push(offset);
+        }
+        if inside.is_empty() {
+            continue;
+        }
+        if validate {
+            let url = &content[start..end];
+            let rewritten = crate::splice(url, &inside.iter().map(|offset| offset - start).collect::<Vec<_>>(), find_len, replace_text);
+            if let Liveness::Dead(reason) = checker().check(&rewritten) {
+                eprintln!("{}: leaving {} alone, as {} is dead ({})", file_path.display(), url, rewritten, reason);
+                continue;
+            }
+        }
+        kept.extend(inside);
+    }
+    kept
+}
+
+/// Whether a character ends a URL.
+fn is_delimiter(c: char) -> bool {
+    matches!(c, '"' | '\'' | '`' | '<' | '>' | '\\')
+}
+
+/// Finds where each URL in a text starts and ends.
+fn urls(content: &str) -> Vec<(usize, usize)> {
+    let bytes = content.as_bytes();
+    let mut urls = Vec::new();
+    let mut position = 0;
+    while let Some(found) = content[position..].find("http") {
+        let start = position + found;
+        let rest = &content[start..];
+        let scheme = ["https://", "http://"].iter().find(|scheme| rest.starts_with(**scheme));
+        let after_word = start == 0 || !(bytes[start - 1].is_ascii_alphanumeric() || bytes[start - 1] == b'_');
+        let Some(scheme) = scheme.filter(|_| after_word) else {
+            position = start + 4;
+            continue;
+        };
+        let length = rest.find(|c: char| c.is_whitespace() || is_delimiter(c)).unwrap_or(rest.len());
+        let mut url = &rest[..length];
+        // Punctuation after a URL, and the bracket around one, are part of the
+        // text around it
+        loop {
+            let trimmed = url.trim_end_matches(['.', ',', ';', ':', '!', '?']);
+            let trimmed = match trimmed.chars().last() {
+                Some(')') if trimmed.matches('(').count() < trimmed.matches(')').count() => &trimmed[..trimmed.len() - 1],
+                Some(']') if trimmed.matches('[').count() < trimmed.matches(']').count() => &trimmed[..trimmed.len() - 1],
+                _ => trimmed,
+            };
+            if trimmed.len() == url.len() {
+                break;
+            }
+            url = trimmed;
+        }
+        if url.len() > scheme.len() {
+            urls.push((start, start + url.len()));
+        }
+        position = start + length.max(4);
+    }
+    urls
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{match_offsets, splice};
+
+    fn replace(content: &str, find_text: &str, replace_text: &str) -> String {
+        let offsets = url_offsets(Path::new("notes.md"), content, &match_offsets(content, find_text), find_text.len(), replace_text, false);
+        splice(content, &offsets, find_text.len(), replace_text)
+    }
+
+    #[test]
+    fn test_urls() {
+        let content = "See https://a.example/x. Or (http://b.example/y_(z)), [c](https://c.example/) and <https://d.example>\n\
+            but not xhttps://e.example, http:// or https://f.example/\"quoted\"";
+        let found: Vec<&str> = urls(content).into_iter().map(|(start, end)| &content[start..end]).collect();
+        assert_eq!(found, ["https://a.example/x", "http://b.example/y_(z)", "https://c.example/", "https://d.example", "https://f.example/"]);
+    }
+
+    #[test]
+    fn test_url_offsets() {
+        let content = "Docs at https://old.example/docs, old.example for short.\nsrc = \"https://old.example/logo.png\"\n";
+        assert_eq!(replace(content, "old.example", "new.example"),
+            "Docs at https://new.example/docs, old.example for short.\nsrc = \"https://new.example/logo.png\"\n");
+        // A match running past the end of a URL, or a replacement that would
+        // end one early, is left alone
+        assert_eq!(replace(content, "docs, old", "docs,old"), content);
+        assert_eq!(replace(content, "old.example", "new example"), content);
+    }
+}
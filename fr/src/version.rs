@@ -0,0 +1,127 @@
+//! Bumping a version, for `fr bump-version`.
+//!
+//! A match is kept if it is the whole version, with no digit, dot or
+//! pre-release suffix joined on, so bumping `1.2.3` leaves `11.2.3`, `1.2.30`
+//! and `1.2.3-beta` alone. With `--manifests`, it also has to be in one of the
+//! places a project keeps its version:
+//!
+//! * `Cargo.toml` - `package.version` and `workspace.package.version`
+//! * `pyproject.toml` - `project.version` and `tool.poetry.version`
+//! * `package.json` - its top-level `version`
+//! * `__init__.py`, `version.py` or `_version.py` - `__version__ = "..."`
+//! * `CHANGELOG`, `CHANGES`, `HISTORY`, `RELEASES` or `NEWS` files - their
+//!   `#` headings
+//!
+//! so a `1.2.3` that happens to be data, such as a dependency's version or an
+//! IP address, is never rewritten.
+
+use crate::jsonpath::{self, JsonPath};
+use crate::toml::{self, TomlKey};
+use std::path::Path;
+
+/// Returns whether text is a version: numbers joined by dots, with a
+/// pre-release or build after a `-` or `+`, as in `1.2.3-rc.1`.
+pub fn is_version(text: &str) -> bool {
+    let core = text.split(['-', '+']).next().unwrap_or(text);
+    let rest = &text[core.len()..];
+    core.split('.').count() >= 2
+        && core.split('.').all(|part| !part.is_empty() && part.bytes().all(|byte| byte.is_ascii_digit()))
+        && rest.bytes().all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'.' | b'-' | b'+'))
+}
+
+/// Keeps the matches that are whole versions and, with `manifests`, in the
+/// places a project keeps its version.
+///
+/// # Arguments
+///
+/// * `file_path` - Path of the file, whose name decides where its version is
+/// * `content` - The file's text
+/// * `offsets` - Where each match of the old version starts
+/// * `find_len` - Length of the old version in bytes
+/// * `replace_text` - The new version
+/// * `manifests` - Whether to keep only the matches in manifests, version
+///   modules and changelog headings
+///
+/// # Returns
+///
+/// * `Vec<usize>` - The offsets of the matches to replace
+pub fn version_offsets(file_path: &Path, content: &str, offsets: &[usize], find_len: usize, replace_text: &str, manifests: bool) -> Vec<usize> {
+    let bytes = content.as_bytes();
+    let whole: Vec<usize> = offsets.iter().copied().filter(|&offset| {
+        let before = offset.checked_sub(1).map(|before| bytes[before]);
+        let after = bytes.get(offset + find_len).copied();
+        let after_next = bytes.get(offset + find_len + 1).copied();
+        // A dot after the version ends a sentence unless a longer version
+        // goes on after it
+        let longer = before.is_some_and(|byte| byte.is_ascii_digit() || byte == b'.')
+            || after.is_some_and(|byte| byte.is_ascii_alphanumeric() || byte == b'_')
+            || (matches!(after, Some(b'.' | b'-' | b'+')) && after_next.is_some_and(|byte| byte.is_ascii_alphanumeric()));
+        !longer
+    }).collect();
+    if !manifests {
+        return whole;
+    }
+
+    let name = file_path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+    let keys = |keys: &[&str]| -> Vec<TomlKey> { keys.iter().map(|key| TomlKey::parse(key).expect("a valid key")).collect() };
+    match name {
+        "Cargo.toml" => toml::value_offsets(content, &whole, find_len, replace_text, &keys(&["package.version", "workspace.package.version"])),
+        "pyproject.toml" => toml::value_offsets(content, &whole, find_len, replace_text, &keys(&["project.version", "tool.poetry.version"])),
+        "package.json" => jsonpath::value_offsets(content, &whole, find_len, &[JsonPath::parse("$.version").expect("a valid path")]),
+        "__init__.py" | "version.py" | "_version.py" | "__version__.py" => {
+            on_lines(content, &whole, |line| line.strip_prefix("__version__").is_some_and(|rest| rest.trim_start().starts_with(['=', ':'])))
+        }
+        _ if is_changelog(name) => on_lines(content, &whole, |line| line.starts_with('#')),
+        _ => Vec::new(),
+    }
+}
+
+/// Whether a file is a changelog, going by its name with any extension.
+fn is_changelog(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or(name).to_ascii_uppercase();
+    matches!(stem.as_str(), "CHANGELOG" | "CHANGES" | "HISTORY" | "RELEASES" | "NEWS")
+}
+
+/// Keeps the matches on the lines that, without their indentation, a test
+/// accepts.
+fn on_lines(content: &str, offsets: &[usize], accepts: impl Fn(&str) -> bool) -> Vec<usize> {
+    offsets.iter().copied().filter(|&offset| {
+        let start = content[..offset].rfind('\n').map_or(0, |newline| newline + 1);
+        let end = content[offset..].find('\n').map_or(content.len(), |newline| offset + newline);
+        accepts(content[start..end].trim_start())
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{match_offsets, splice};
+
+    fn bump(file_path: &str, content: &str, manifests: bool) -> String {
+        let offsets = version_offsets(Path::new(file_path), content, &match_offsets(content, "1.2.3"), 5, "1.3.0", manifests);
+        splice(content, &offsets, 5, "1.3.0")
+    }
+
+    #[test]
+    fn test_whole_versions() {
+        assert_eq!(bump("README.md", "Install v1.2.3. Not 11.2.3, 1.2.30, 1.2.3.4 or 1.2.3-beta (see 1.2.3)\n", false),
+            "Install v1.3.0. Not 11.2.3, 1.2.30, 1.2.3.4 or 1.2.3-beta (see 1.3.0)\n");
+        assert_eq!(bump("README.md", "Install v1.2.3.\n", true), "Install v1.2.3.\n");
+    }
+
+    #[test]
+    fn test_manifests() {
+        let cargo = "[package]\nversion = \"1.2.3\"\n\n[dependencies]\nserde = \"1.2.3\"\n";
+        assert_eq!(bump("Cargo.toml", cargo, true), "[package]\nversion = \"1.3.0\"\n\n[dependencies]\nserde = \"1.2.3\"\n");
+        let package = "{\"version\": \"1.2.3\", \"dependencies\": {\"left-pad\": \"1.2.3\"}}";
+        assert_eq!(bump("package.json", package, true), "{\"version\": \"1.3.0\", \"dependencies\": {\"left-pad\": \"1.2.3\"}}");
+        assert_eq!(bump("pkg/__init__.py", "__version__ = '1.2.3'\nMINIMUM = '1.2.3'\n", true), "__version__ = '1.3.0'\nMINIMUM = '1.2.3'\n");
+        assert_eq!(bump("CHANGELOG.md", "## [1.2.3] - 2024-05-01\n\nFixed 1.2.3 bug\n", true), "## [1.3.0] - 2024-05-01\n\nFixed 1.2.3 bug\n");
+    }
+
+    #[test]
+    fn test_is_version() {
+        assert!(is_version("1.2.3") && is_version("2.0") && is_version("1.0.0-rc.1+build.5"));
+        assert!(!is_version("1") && !is_version("v1.2") && !is_version("1..2") && !is_version("1.2 beta"));
+    }
+}
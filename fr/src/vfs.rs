@@ -0,0 +1,158 @@
+//! Find and replace over files that aren't on the local disk, such as the
+//! files open in a web IDE when fr is built for WebAssembly.
+//!
+//! The matching and rewriting are the same as for a run over a directory, but
+//! files are listed, read and written through a `FileSystem` and processed one
+//! after another on the calling thread, since `wasm32-unknown-unknown` has
+//! neither a filesystem nor threads. What only makes sense on a real tree is
+//! left out: ignore files, `.editorconfig`, the cache, the index and streaming.
+
+use crate::plugin::Plugins;
+use crate::{encoding, looks_binary, rewrite, ModifiedFile, Options, Report, SkipReason, Summary};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Where `find_replace_in` lists, reads and writes files
+pub trait FileSystem {
+    /// Lists every file to search.
+    fn files(&self) -> io::Result<Vec<PathBuf>>;
+
+    /// Reads the whole contents of a file.
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Replaces the contents of a file.
+    fn write(&mut self, path: &Path, contents: &[u8]) -> io::Result<()>;
+}
+
+/// Files held in memory, such as the buffers of an editor
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFileSystem {
+    files: BTreeMap<PathBuf, Vec<u8>>,
+}
+
+impl MemoryFileSystem {
+    /// Creates a filesystem without any files.
+    pub fn new() -> MemoryFileSystem {
+        MemoryFileSystem::default()
+    }
+
+    /// Adds a file, or replaces its contents if it is already there.
+    pub fn insert(&mut self, path: impl Into<PathBuf>, contents: impl Into<Vec<u8>>) {
+        self.files.insert(path.into(), contents.into());
+    }
+
+    /// Returns the contents of a file, if there is one at the path.
+    pub fn get(&self, path: impl AsRef<Path>) -> Option<&[u8]> {
+        self.files.get(path.as_ref()).map(Vec::as_slice)
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn files(&self) -> io::Result<Vec<PathBuf>> {
+        Ok(self.files.keys().cloned().collect())
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.get(path).map(<[u8]>::to_vec).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such file"))
+    }
+
+    fn write(&mut self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.insert(path, contents);
+        Ok(())
+    }
+}
+
+/// Finds and replaces text in every file of a `FileSystem`.
+///
+/// # Arguments
+///
+/// * `file_system` - Where the files are
+/// * `find_text` - Text to find in files
+/// * `replace_text` - Text to replace the found text with
+/// * `options` - Options controlling how files are read and rewritten
+///
+/// # Returns
+///
+/// * `io::Result<Summary>` - What happened to each file, or an error if the
+///   files could not be listed
+pub fn find_replace_in<F: FileSystem + ?Sized>(file_system: &mut F, find_text: &str, replace_text: &str, options: &Options) -> io::Result<Summary> {
+    let mut summary = Summary::default();
+    if find_text.is_empty() {
+        return Ok(summary);
+    }
+    let needs_hunks = options.reports.iter().any(Report::needs_hunks);
+    let needs_locations = options.reports.iter().any(Report::needs_locations);
+    for path in file_system.files()? {
+        match find_replace_one(file_system, &path, find_text, replace_text, options, needs_hunks, needs_locations) {
+            Ok(Ok(modified)) => summary.modified.push(modified),
+            Ok(Err(SkipReason::NoMatch)) => summary.unmatched += 1,
+            Ok(Err(reason)) => summary.skipped.push((path, reason)),
+            Err(e) => summary.errors.push((path, e.to_string())),
+        }
+    }
+    summary.modified.sort_by(|a, b| a.path.cmp(&b.path));
+    summary.skipped.sort();
+    summary.errors.sort();
+    Ok(summary)
+}
+
+/// Finds and replaces text in one file, returning what was modified or why the
+/// file was skipped.
+fn find_replace_one<F: FileSystem + ?Sized>(
+    file_system: &mut F,
+    path: &Path,
+    find_text: &str,
+    replace_text: &str,
+    options: &Options,
+    needs_hunks: bool,
+    needs_locations: bool,
+) -> io::Result<Result<ModifiedFile, SkipReason>> {
+    let bytes = file_system.read(path)?;
+    if options.max_filesize.is_some_and(|max| bytes.len() as u64 > max) {
+        return Ok(Err(SkipReason::TooLarge));
+    }
+    if !options.treats_as_text(path) && looks_binary(&bytes, options.encoding) && !options.encoding.allows_nul_in(&bytes) {
+        return Ok(Err(SkipReason::Binary));
+    }
+
+    let (content, decoding) = encoding::decode(bytes, options.encoding)?;
+    let Some((change, new_bytes)) = rewrite(path, content, decoding, find_text, replace_text, options, &Plugins::default())? else {
+        return Ok(Err(SkipReason::NoMatch));
+    };
+    if !options.dry_run {
+        file_system.write(path, &new_bytes)?;
+    }
+    Ok(Ok(ModifiedFile {
+        path: path.to_path_buf(),
+        replacements: change.replacements,
+        hunks: if needs_hunks { change.hunks() } else { Vec::new() },
+        locations: if needs_locations { change.locations() } else { Vec::new() },
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Encoding;
+
+    #[test]
+    fn test_find_replace_in() {
+        let mut file_system = MemoryFileSystem::new();
+        file_system.insert("src/a.txt", "hello world\r\nhello\r\n");
+        file_system.insert("src/b.txt", "nothing");
+        file_system.insert("logo.png", b"\x89PNG\r\n\x1a\n\0\0\0hello".to_vec());
+        file_system.insert("utf16.txt", b"\xFF\xFEh\0e\0l\0l\0o\0".to_vec());
+
+        let options = Options { encoding: Encoding::Auto, ..Options::default() };
+        let summary = find_replace_in(&mut file_system, "hello\n", "bye\n", &options).unwrap();
+        assert_eq!(summary.modified.iter().map(|file| (file.path.as_path(), file.replacements)).collect::<Vec<_>>(), [(Path::new("src/a.txt"), 1)]);
+        assert_eq!(summary.skipped, [(PathBuf::from("logo.png"), SkipReason::Binary)]);
+        assert_eq!(summary.unmatched, 2);
+        assert_eq!(file_system.get("src/a.txt").unwrap(), b"hello world\r\nbye\r\n");
+
+        let summary = find_replace_in(&mut file_system, "hello", "bye", &Options { dry_run: true, ..options }).unwrap();
+        assert_eq!(summary.modified.len(), 2);
+        assert_eq!(file_system.get("utf16.txt").unwrap(), b"\xFF\xFEh\0e\0l\0l\0o\0");
+    }
+}
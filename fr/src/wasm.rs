@@ -0,0 +1,1256 @@
+//! A small WebAssembly interpreter, for running `--plugin` modules.
+//!
+//! Plugins are small and run on short inputs, so an interpreter is fast enough
+//! and keeps fr free of a runtime dependency. It supports what compilers emit
+//! for `wasm32-unknown-unknown`: the core instruction set, multi-value blocks,
+//! sign extension, saturating float conversions and bulk memory copies and
+//! fills. Modules can't import anything, so the only way a plugin can affect the
+//! world is through what its exports return.
+//!
+//! Every value is kept as a `u64`: integers zero-extended and floats as their
+//! bits. Each call may only run so many instructions and nest calls so deep,
+//! so a broken plugin traps instead of hanging the run.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// The size of a page of linear memory
+const PAGE_SIZE: usize = 64 * 1024;
+
+/// The most pages a module's memory may grow to, 256 MiB
+const MAX_PAGES: u32 = 4096;
+
+/// How many instructions each call from the host may run
+const FUEL: u64 = 1 << 30;
+
+/// How deeply calls may nest
+const MAX_DEPTH: usize = 512;
+
+/// The most locals a function may declare
+const MAX_LOCALS: usize = 50_000;
+
+/// The type of a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ValType {
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+/// The type of a function
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FuncType {
+    params: Vec<ValType>,
+    results: Vec<ValType>,
+}
+
+/// A function defined by the module
+#[derive(Debug)]
+struct Function {
+    type_index: usize,
+    /// Types of the locals after the parameters
+    locals: Vec<ValType>,
+    code: Vec<u8>,
+    /// For the position of each `block`, `loop` and `if`, the position just
+    /// after its `else`, if it has one, and just after its `end`
+    blocks: HashMap<usize, (Option<usize>, usize)>,
+}
+
+/// A block whose end, or start for a loop, can be branched to
+#[derive(Debug)]
+struct Label {
+    /// How many values were on the stack below the block's parameters
+    height: usize,
+    /// How many values a branch to the block carries
+    arity: usize,
+    /// Where a branch to the block continues
+    target: usize,
+    /// Where execution continues once the block is done, just after its `end`
+    end: usize,
+}
+
+/// An instantiated module
+#[derive(Debug)]
+pub struct Instance {
+    types: Vec<FuncType>,
+    functions: Vec<Arc<Function>>,
+    table: Vec<Option<usize>>,
+    memory: Vec<u8>,
+    max_pages: u32,
+    globals: Vec<u64>,
+    functions_by_name: HashMap<String, usize>,
+    stack: Vec<u64>,
+    /// Where the running function's frame starts on the stack
+    floor: usize,
+    fuel: u64,
+    fuel_per_call: u64,
+}
+
+/// Reads the binary format
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes, position: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position >= self.bytes.len()
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        let byte = *self.bytes.get(self.position).ok_or("unexpected end of module")?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self.position.checked_add(len).filter(|&end| end <= self.bytes.len()).ok_or("unexpected end of module")?;
+        let taken = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(taken)
+    }
+
+    /// Reads a LEB128 number of up to `bits` bits.
+    fn leb(&mut self, bits: u32, signed: bool) -> Result<u64, String> {
+        let mut result = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = self.byte()?;
+            if shift >= bits.max(7) + 7 {
+                return Err("integer too long".to_string());
+            }
+            result |= u64::from(byte & 0x7f).checked_shl(shift).unwrap_or(0);
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if signed && shift < 64 && byte & 0x40 != 0 {
+                    result |= u64::MAX << shift;
+                }
+                return Ok(result);
+            }
+        }
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(self.leb(32, false)? as u32)
+    }
+
+    fn index(&mut self) -> Result<usize, String> {
+        Ok(self.u32()? as usize)
+    }
+
+    fn s32(&mut self) -> Result<i32, String> {
+        Ok(self.leb(32, true)? as i32)
+    }
+
+    fn s64(&mut self) -> Result<i64, String> {
+        Ok(self.leb(64, true)? as i64)
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let len = self.index()?;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| "name is not UTF-8".to_string())
+    }
+
+    fn val_type(&mut self) -> Result<ValType, String> {
+        match self.byte()? {
+            0x7f => Ok(ValType::I32),
+            0x7e => Ok(ValType::I64),
+            0x7d => Ok(ValType::F32),
+            0x7c => Ok(ValType::F64),
+            byte => Err(format!("unsupported value type 0x{:02x}", byte)),
+        }
+    }
+
+    /// Reads the minimum and maximum of a memory or table.
+    fn limits(&mut self) -> Result<(u32, Option<u32>), String> {
+        match self.byte()? {
+            0x00 => Ok((self.u32()?, None)),
+            0x01 => Ok((self.u32()?, Some(self.u32()?))),
+            _ => Err("unsupported limits".to_string()),
+        }
+    }
+
+    /// Reads a constant expression, such as the initial value of a global.
+    fn const_expr(&mut self, globals: &[u64]) -> Result<u64, String> {
+        let value = match self.byte()? {
+            0x41 => self.s32()? as u32 as u64,
+            0x42 => self.s64()? as u64,
+            0x43 => u64::from(u32::from_le_bytes(self.take(4)?.try_into().unwrap())),
+            0x44 => u64::from_le_bytes(self.take(8)?.try_into().unwrap()),
+            0x23 => *globals.get(self.index()?).ok_or("unknown global")?,
+            _ => return Err("unsupported constant expression".to_string()),
+        };
+        match self.byte()? {
+            0x0b => Ok(value),
+            _ => Err("unsupported constant expression".to_string()),
+        }
+    }
+
+    /// Reads the type of a block, as the number of parameters and results.
+    fn block_type(&mut self, types: &[FuncType]) -> Result<(usize, usize), String> {
+        match self.leb(33, true)? as i64 {
+            -64 => Ok((0, 0)),
+            -4..=-1 => Ok((0, 1)),
+            index if index >= 0 => {
+                let func_type = types.get(index as usize).ok_or("unknown block type")?;
+                Ok((func_type.params.len(), func_type.results.len()))
+            }
+            _ => Err("unsupported block type".to_string()),
+        }
+    }
+
+    /// Skips the immediates of an instruction.
+    fn skip_immediates(&mut self, opcode: u8) -> Result<(), String> {
+        match opcode {
+            0x02..=0x04 => {
+                self.leb(33, true)?;
+            }
+            0x0c | 0x0d | 0x10 | 0x20..=0x26 | 0xd2 => {
+                self.u32()?;
+            }
+            0x0e => {
+                for _ in 0..=self.u32()? {
+                    self.u32()?;
+                }
+            }
+            0x11 | 0x28..=0x3e => {
+                self.u32()?;
+                self.u32()?;
+            }
+            0x1c => {
+                let len = self.index()?;
+                self.take(len)?;
+            }
+            0x3f | 0x40 | 0xd0 => {
+                self.byte()?;
+            }
+            0x41 => {
+                self.s32()?;
+            }
+            0x42 => {
+                self.s64()?;
+            }
+            0x43 => {
+                self.take(4)?;
+            }
+            0x44 => {
+                self.take(8)?;
+            }
+            0xfc => match self.u32()? {
+                0..=7 => {}
+                8 | 12 | 14 => {
+                    self.u32()?;
+                    self.u32()?;
+                }
+                9 | 13 | 15..=17 => {
+                    self.u32()?;
+                }
+                10 => {
+                    self.take(2)?;
+                }
+                11 => {
+                    self.byte()?;
+                }
+                sub => return Err(format!("unsupported instruction 0xfc {}", sub)),
+            },
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Finds where each block of a function's code has its `else` and `end`.
+fn find_blocks(code: &[u8]) -> Result<HashMap<usize, (Option<usize>, usize)>, String> {
+    let mut blocks = HashMap::new();
+    let mut open: Vec<(usize, Option<usize>)> = Vec::new();
+    let mut reader = Reader::new(code);
+    while !reader.is_empty() {
+        let start = reader.position;
+        let opcode = reader.byte()?;
+        reader.skip_immediates(opcode)?;
+        match opcode {
+            0x02..=0x04 => open.push((start, None)),
+            0x05 => open.last_mut().ok_or("else outside of an if")?.1 = Some(reader.position),
+            0x0b => match open.pop() {
+                Some((start, else_position)) => {
+                    blocks.insert(start, (else_position, reader.position));
+                }
+                None if reader.is_empty() => return Ok(blocks),
+                None => return Err("end outside of a block".to_string()),
+            },
+            _ => {}
+        }
+    }
+    Err("function doesn't end".to_string())
+}
+
+impl Instance {
+    /// Decodes and instantiates a module, running its start function if it has
+    /// one.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The module in the binary format
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Instance, String>` - The instance, or why the module couldn't
+    ///   be loaded
+    pub fn new(bytes: &[u8]) -> Result<Instance, String> {
+        let mut reader = Reader::new(bytes);
+        if reader.take(8).ok() != Some(b"\0asm\x01\0\0\0") {
+            return Err("not a WebAssembly module".to_string());
+        }
+
+        let mut instance = Instance {
+            types: Vec::new(),
+            functions: Vec::new(),
+            table: Vec::new(),
+            memory: Vec::new(),
+            max_pages: MAX_PAGES,
+            globals: Vec::new(),
+            functions_by_name: HashMap::new(),
+            stack: Vec::new(),
+            fuel: FUEL,
+            fuel_per_call: FUEL,
+            floor: 0,
+        };
+        let mut function_types = Vec::new();
+        let mut start = None;
+        while !reader.is_empty() {
+            let id = reader.byte()?;
+            let len = reader.index()?;
+            let mut section = Reader::new(reader.take(len)?);
+            if id == 0 {
+                continue;
+            }
+            // The start and data count sections hold a single number
+            let count = match id {
+                8 => 1,
+                12 => 0,
+                _ => section.u32()?,
+            };
+            for _ in 0..count {
+                match id {
+                    1 => {
+                        if section.byte()? != 0x60 {
+                            return Err("unsupported type".to_string());
+                        }
+                        let params = (0..section.u32()?).map(|_| section.val_type()).collect::<Result<_, _>>()?;
+                        let results = (0..section.u32()?).map(|_| section.val_type()).collect::<Result<_, _>>()?;
+                        instance.types.push(FuncType { params, results });
+                    }
+                    2 => return Err("plugins can't import anything".to_string()),
+                    3 => function_types.push(section.index()?),
+                    4 => {
+                        if section.byte()? != 0x70 {
+                            return Err("unsupported table type".to_string());
+                        }
+                        let (min, _) = section.limits()?;
+                        instance.table = vec![None; min as usize];
+                    }
+                    5 => {
+                        let (min, max) = section.limits()?;
+                        instance.max_pages = max.unwrap_or(MAX_PAGES).min(MAX_PAGES);
+                        if min > instance.max_pages {
+                            return Err("memory is too large".to_string());
+                        }
+                        instance.memory = vec![0; min as usize * PAGE_SIZE];
+                    }
+                    6 => {
+                        section.val_type()?;
+                        section.byte()?;
+                        let value = section.const_expr(&instance.globals)?;
+                        instance.globals.push(value);
+                    }
+                    7 => {
+                        let name = section.name()?;
+                        let kind = section.byte()?;
+                        let index = section.index()?;
+                        if kind == 0 {
+                            instance.functions_by_name.insert(name, index);
+                        }
+                    }
+                    8 => start = Some(section.index()?),
+                    9 => {
+                        if section.u32()? != 0 {
+                            return Err("unsupported element segment".to_string());
+                        }
+                        let offset = section.const_expr(&instance.globals)? as usize;
+                        for i in 0..section.index()? {
+                            let slot = instance.table.get_mut(offset + i).ok_or("element segment out of bounds")?;
+                            *slot = Some(section.index()?);
+                        }
+                    }
+                    10 => {
+                        let len = section.index()?;
+                        let mut body = Reader::new(section.take(len)?);
+                        let mut locals = Vec::new();
+                        for _ in 0..body.u32()? {
+                            let count = body.index()?;
+                            let val_type = body.val_type()?;
+                            if locals.len() + count > MAX_LOCALS {
+                                return Err("too many locals".to_string());
+                            }
+                            locals.extend(std::iter::repeat_n(val_type, count));
+                        }
+                        let code = body.bytes[body.position..].to_vec();
+                        let type_index = *function_types.get(instance.functions.len()).ok_or("function without a type")?;
+                        if type_index >= instance.types.len() {
+                            return Err("unknown function type".to_string());
+                        }
+                        let blocks = find_blocks(&code)?;
+                        instance.functions.push(Arc::new(Function { type_index, locals, code, blocks }));
+                    }
+                    11 => {
+                        let offset = match section.u32()? {
+                            0 => section.const_expr(&instance.globals)? as u32 as usize,
+                            1 => {
+                                let len = section.index()?;
+                                section.take(len)?;
+                                continue;
+                            }
+                            2 => {
+                                section.u32()?;
+                                section.const_expr(&instance.globals)? as u32 as usize
+                            }
+                            _ => return Err("unsupported data segment".to_string()),
+                        };
+                        let len = section.index()?;
+                        let data = section.take(len)?;
+                        instance.memory.get_mut(offset..offset + len).ok_or("data segment out of bounds")?.copy_from_slice(data);
+                    }
+                    _ => return Err(format!("unsupported section {}", id)),
+                }
+            }
+        }
+        if instance.functions.len() != function_types.len() {
+            return Err("functions without code".to_string());
+        }
+        if let Some(start) = start {
+            instance.fuel = instance.fuel_per_call;
+            instance.invoke(start, 0)?;
+        }
+        Ok(instance)
+    }
+
+    /// Whether the module exports a function with this name.
+    pub fn exports(&self, name: &str) -> bool {
+        self.functions_by_name.contains_key(name)
+    }
+
+    /// Calls an exported function.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the export
+    /// * `args` - The arguments, with each integer zero-extended to 64 bits
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<u64>, String>` - The results, or why the call trapped
+    pub fn call(&mut self, name: &str, args: &[u64]) -> Result<Vec<u64>, String> {
+        let index = *self.functions_by_name.get(name).ok_or_else(|| format!("no function named {}", name))?;
+        let function = self.functions.get(index).ok_or("unknown function")?;
+        let func_type = &self.types[function.type_index];
+        if func_type.params.len() != args.len() {
+            return Err(format!("{} takes {} arguments", name, func_type.params.len()));
+        }
+        self.stack.clear();
+        self.floor = 0;
+        for (&arg, val_type) in args.iter().zip(&func_type.params) {
+            self.stack.push(if *val_type == ValType::I32 { arg & 0xffff_ffff } else { arg });
+        }
+        self.fuel = self.fuel_per_call;
+        let result = self.invoke(index, 0);
+        let results = std::mem::take(&mut self.stack);
+        result.map(|()| results)
+    }
+
+    /// Reads part of the linear memory.
+    pub fn read(&self, address: u32, len: u32) -> Result<&[u8], String> {
+        let start = address as usize;
+        self.memory.get(start..start + len as usize).ok_or_else(|| "out of bounds memory access".to_string())
+    }
+
+    /// Writes to part of the linear memory.
+    pub fn write(&mut self, address: u32, bytes: &[u8]) -> Result<(), String> {
+        let start = address as usize;
+        self.memory.get_mut(start..start + bytes.len()).ok_or("out of bounds memory access")?.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Pops a value, failing rather than take one from below the function's
+    /// frame, which only a malformed module would.
+    fn pop(&mut self) -> Result<u64, String> {
+        match self.stack.len() > self.floor {
+            true => self.stack.pop().ok_or_else(underflow),
+            false => Err(underflow()),
+        }
+    }
+
+    fn pop_u32(&mut self) -> Result<u32, String> {
+        Ok(self.pop()? as u32)
+    }
+
+    fn pop_f32(&mut self) -> Result<f32, String> {
+        Ok(f32::from_bits(self.pop_u32()?))
+    }
+
+    fn pop_f64(&mut self) -> Result<f64, String> {
+        Ok(f64::from_bits(self.pop()?))
+    }
+
+    /// How many values are on the stack below the top `count`, if that leaves
+    /// them all above `height`.
+    fn below(&self, count: usize, height: usize) -> Result<usize, String> {
+        self.stack.len().checked_sub(count).filter(|&below| below >= height).ok_or_else(underflow)
+    }
+
+    fn push_u32(&mut self, value: u32) {
+        self.stack.push(u64::from(value));
+    }
+
+    fn push_bool(&mut self, value: bool) {
+        self.push_u32(u32::from(value));
+    }
+
+    fn push_f32(&mut self, value: f32) {
+        self.push_u32(value.to_bits());
+    }
+
+    fn push_f64(&mut self, value: f64) {
+        self.stack.push(value.to_bits());
+    }
+
+    /// Works out the address of a load or store of `size` bytes.
+    fn address(&mut self, reader: &mut Reader, size: usize) -> Result<usize, String> {
+        reader.u32()?;
+        let offset = reader.u32()? as usize;
+        let address = self.pop_u32()? as usize + offset;
+        if address + size > self.memory.len() {
+            return Err("out of bounds memory access".to_string());
+        }
+        Ok(address)
+    }
+
+    fn load<const N: usize>(&mut self, reader: &mut Reader) -> Result<[u8; N], String> {
+        let address = self.address(reader, N)?;
+        Ok(self.memory[address..address + N].try_into().unwrap())
+    }
+
+    fn store(&mut self, reader: &mut Reader, bytes: &[u8]) -> Result<(), String> {
+        let address = self.address(reader, bytes.len())?;
+        self.memory[address..address + bytes.len()].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Runs a function with its arguments on top of the stack, leaving its
+    /// results there instead.
+    fn invoke(&mut self, index: usize, depth: usize) -> Result<(), String> {
+        if depth > MAX_DEPTH {
+            return Err("call stack exhausted".to_string());
+        }
+        let function = Arc::clone(self.functions.get(index).ok_or("unknown function")?);
+        let func_type = &self.types[function.type_index];
+        let (param_count, result_count) = (func_type.params.len(), func_type.results.len());
+        let base = self.below(param_count, self.floor)?;
+        let mut locals: Vec<u64> = self.stack.drain(base..).collect();
+        let floor = std::mem::replace(&mut self.floor, base);
+        locals.resize(param_count + function.locals.len(), 0);
+
+        let mut labels: Vec<Label> = Vec::new();
+        let mut reader = Reader::new(&function.code);
+        loop {
+            if self.fuel == 0 {
+                return Err("ran out of fuel".to_string());
+            }
+            self.fuel -= 1;
+            let start = reader.position;
+            let opcode = reader.byte()?;
+            // The depth of the label to branch to, if the instruction branches
+            let mut branch = None;
+            match opcode {
+                0x00 => return Err("unreachable".to_string()),
+                0x01 => {}
+                0x02..=0x04 => {
+                    let (params, results) = reader.block_type(&self.types)?;
+                    let (else_position, end) = *function.blocks.get(&start).ok_or("unknown block")?;
+                    // An if's condition is on top of its parameters
+                    let height = self.below(params + usize::from(opcode == 0x04), self.floor)?;
+                    match opcode {
+                        0x02 => labels.push(Label { height, arity: results, target: end, end }),
+                        0x03 => labels.push(Label { height, arity: params, target: reader.position, end }),
+                        _ => {
+                            if self.pop_u32()? != 0 {
+                                labels.push(Label { height, arity: results, target: end, end });
+                            } else if let Some(else_position) = else_position {
+                                labels.push(Label { height, arity: results, target: end, end });
+                                reader.position = else_position;
+                            } else {
+                                reader.position = end;
+                            }
+                        }
+                    }
+                }
+                // The end of the true branch of an if
+                0x05 => {
+                    let label = labels.pop().ok_or("else outside of an if")?;
+                    reader.position = label.end;
+                }
+                0x0b => {
+                    if labels.pop().is_none() {
+                        break;
+                    }
+                }
+                0x0c => branch = Some(reader.index()?),
+                0x0d => {
+                    let depth = reader.index()?;
+                    if self.pop_u32()? != 0 {
+                        branch = Some(depth);
+                    }
+                }
+                0x0e => {
+                    let targets = (0..reader.u32()?).map(|_| reader.index()).collect::<Result<Vec<_>, _>>()?;
+                    let default = reader.index()?;
+                    let chosen = self.pop_u32()? as usize;
+                    branch = Some(targets.get(chosen).copied().unwrap_or(default));
+                }
+                0x0f => break,
+                0x10 => {
+                    let callee = reader.index()?;
+                    self.invoke(callee, depth + 1)?;
+                }
+                0x11 => {
+                    let type_index = reader.index()?;
+                    reader.u32()?;
+                    let slot = self.pop_u32()? as usize;
+                    let callee = self.table.get(slot).copied().flatten().ok_or("undefined table element")?;
+                    let callee_type = self.functions.get(callee).map(|callee| callee.type_index).ok_or("unknown function")?;
+                    if self.types.get(type_index) != Some(&self.types[callee_type]) {
+                        return Err("indirect call type mismatch".to_string());
+                    }
+                    self.invoke(callee, depth + 1)?;
+                }
+                0x1a => {
+                    self.pop()?;
+                }
+                0x1b | 0x1c => {
+                    if opcode == 0x1c {
+                        reader.skip_immediates(opcode)?;
+                    }
+                    let condition = self.pop_u32()?;
+                    let second = self.pop()?;
+                    let first = self.pop()?;
+                    self.stack.push(if condition != 0 { first } else { second });
+                }
+                0x20 => {
+                    let value = *locals.get(reader.index()?).ok_or("unknown local")?;
+                    self.stack.push(value);
+                }
+                0x21 | 0x22 => {
+                    let value = self.pop()?;
+                    if opcode == 0x22 {
+                        self.stack.push(value);
+                    }
+                    *locals.get_mut(reader.index()?).ok_or("unknown local")? = value;
+                }
+                0x23 => {
+                    let value = *self.globals.get(reader.index()?).ok_or("unknown global")?;
+                    self.stack.push(value);
+                }
+                0x24 => {
+                    let value = self.pop()?;
+                    *self.globals.get_mut(reader.index()?).ok_or("unknown global")? = value;
+                }
+                0x28 => {
+                    let bytes = self.load::<4>(&mut reader)?;
+                    self.push_u32(u32::from_le_bytes(bytes));
+                }
+                0x29 => {
+                    let bytes = self.load::<8>(&mut reader)?;
+                    self.stack.push(u64::from_le_bytes(bytes));
+                }
+                0x2a => {
+                    let bytes = self.load::<4>(&mut reader)?;
+                    self.push_u32(u32::from_le_bytes(bytes));
+                }
+                0x2b => {
+                    let bytes = self.load::<8>(&mut reader)?;
+                    self.stack.push(u64::from_le_bytes(bytes));
+                }
+                0x2c => {
+                    let [byte] = self.load::<1>(&mut reader)?;
+                    self.push_u32(byte as i8 as i32 as u32);
+                }
+                0x2d => {
+                    let [byte] = self.load::<1>(&mut reader)?;
+                    self.push_u32(u32::from(byte));
+                }
+                0x2e => {
+                    let bytes = self.load::<2>(&mut reader)?;
+                    self.push_u32(i16::from_le_bytes(bytes) as i32 as u32);
+                }
+                0x2f => {
+                    let bytes = self.load::<2>(&mut reader)?;
+                    self.push_u32(u32::from(u16::from_le_bytes(bytes)));
+                }
+                0x30 => {
+                    let [byte] = self.load::<1>(&mut reader)?;
+                    self.stack.push(byte as i8 as i64 as u64);
+                }
+                0x31 => {
+                    let [byte] = self.load::<1>(&mut reader)?;
+                    self.stack.push(u64::from(byte));
+                }
+                0x32 => {
+                    let bytes = self.load::<2>(&mut reader)?;
+                    self.stack.push(i16::from_le_bytes(bytes) as i64 as u64);
+                }
+                0x33 => {
+                    let bytes = self.load::<2>(&mut reader)?;
+                    self.stack.push(u64::from(u16::from_le_bytes(bytes)));
+                }
+                0x34 => {
+                    let bytes = self.load::<4>(&mut reader)?;
+                    self.stack.push(i32::from_le_bytes(bytes) as i64 as u64);
+                }
+                0x35 => {
+                    let bytes = self.load::<4>(&mut reader)?;
+                    self.stack.push(u64::from(u32::from_le_bytes(bytes)));
+                }
+                0x36..=0x3e => {
+                    let value = self.pop()?;
+                    let size = match opcode {
+                        0x37 | 0x39 => 8,
+                        0x36 | 0x38 | 0x3e => 4,
+                        0x3b | 0x3d => 2,
+                        _ => 1,
+                    };
+                    self.store(&mut reader, &value.to_le_bytes()[..size])?;
+                }
+                0x3f => {
+                    reader.byte()?;
+                    self.push_u32((self.memory.len() / PAGE_SIZE) as u32);
+                }
+                0x40 => {
+                    reader.byte()?;
+                    let pages = (self.memory.len() / PAGE_SIZE) as u32;
+                    let delta = self.pop_u32()?;
+                    match pages.checked_add(delta).filter(|&total| total <= self.max_pages) {
+                        Some(total) => {
+                            self.memory.resize(total as usize * PAGE_SIZE, 0);
+                            self.push_u32(pages);
+                        }
+                        None => self.push_u32(u32::MAX),
+                    }
+                }
+                0x41 => {
+                    let value = reader.s32()?;
+                    self.push_u32(value as u32);
+                }
+                0x42 => {
+                    let value = reader.s64()?;
+                    self.stack.push(value as u64);
+                }
+                0x43 => {
+                    let bytes = reader.take(4)?;
+                    self.push_u32(u32::from_le_bytes(bytes.try_into().unwrap()));
+                }
+                0x44 => {
+                    let bytes = reader.take(8)?;
+                    self.stack.push(u64::from_le_bytes(bytes.try_into().unwrap()));
+                }
+                0x45 => {
+                    let value = self.pop_u32()?;
+                    self.push_bool(value == 0);
+                }
+                0x46..=0x4f => {
+                    let b = self.pop_u32()?;
+                    let a = self.pop_u32()?;
+                    self.push_bool(compare(opcode - 0x46, a.into(), b.into(), i64::from(a as i32), i64::from(b as i32)));
+                }
+                0x50 => {
+                    let value = self.pop()?;
+                    self.push_bool(value == 0);
+                }
+                0x51..=0x5a => {
+                    let b = self.pop()?;
+                    let a = self.pop()?;
+                    self.push_bool(compare(opcode - 0x51, a, b, a as i64, b as i64));
+                }
+                0x5b..=0x60 => {
+                    let b = self.pop_f32()?;
+                    let a = self.pop_f32()?;
+                    self.push_bool(compare_float(opcode - 0x5b, a.into(), b.into()));
+                }
+                0x61..=0x66 => {
+                    let b = self.pop_f64()?;
+                    let a = self.pop_f64()?;
+                    self.push_bool(compare_float(opcode - 0x61, a, b));
+                }
+                0x67..=0x69 => {
+                    let a = self.pop_u32()?;
+                    self.push_u32(match opcode {
+                        0x67 => a.leading_zeros(),
+                        0x68 => a.trailing_zeros(),
+                        _ => a.count_ones(),
+                    });
+                }
+                0x6a..=0x78 => {
+                    let b = self.pop_u32()?;
+                    let a = self.pop_u32()?;
+                    let result = i32_binary(opcode, a, b)?;
+                    self.push_u32(result);
+                }
+                0x79..=0x7b => {
+                    let a = self.pop()?;
+                    self.stack.push(u64::from(match opcode {
+                        0x79 => a.leading_zeros(),
+                        0x7a => a.trailing_zeros(),
+                        _ => a.count_ones(),
+                    }));
+                }
+                0x7c..=0x8a => {
+                    let b = self.pop()?;
+                    let a = self.pop()?;
+                    let result = i64_binary(opcode, a, b)?;
+                    self.stack.push(result);
+                }
+                0x8b..=0x91 => {
+                    let a = self.pop_f32()?;
+                    self.push_f32(float_unary(opcode - 0x8b, a.into()) as f32);
+                }
+                0x92..=0x98 => {
+                    let b = self.pop_f32()?;
+                    let a = self.pop_f32()?;
+                    let result = match opcode {
+                        0x92 => a + b,
+                        0x93 => a - b,
+                        0x94 => a * b,
+                        0x95 => a / b,
+                        0x98 => a.copysign(b),
+                        _ => min_max(opcode == 0x96, a.into(), b.into()) as f32,
+                    };
+                    self.push_f32(result);
+                }
+                0x99..=0x9f => {
+                    let a = self.pop_f64()?;
+                    self.push_f64(float_unary(opcode - 0x99, a));
+                }
+                0xa0..=0xa6 => {
+                    let b = self.pop_f64()?;
+                    let a = self.pop_f64()?;
+                    let result = match opcode {
+                        0xa0 => a + b,
+                        0xa1 => a - b,
+                        0xa2 => a * b,
+                        0xa3 => a / b,
+                        0xa6 => a.copysign(b),
+                        _ => min_max(opcode == 0xa4, a, b),
+                    };
+                    self.push_f64(result);
+                }
+                0xa7 => {
+                    let value = self.pop()?;
+                    self.push_u32(value as u32);
+                }
+                0xa8..=0xab | 0xae..=0xb1 => {
+                    let value = if matches!(opcode, 0xa8 | 0xa9 | 0xae | 0xaf) { f64::from(self.pop_f32()?) } else { self.pop_f64()? };
+                    let (bits, signed) = match opcode {
+                        0xa8 | 0xaa => (32, true),
+                        0xa9 | 0xab => (32, false),
+                        0xae | 0xb0 => (64, true),
+                        _ => (64, false),
+                    };
+                    let result = truncate(value, bits, signed, false)?;
+                    self.stack.push(result);
+                }
+                0xac => {
+                    let value = self.pop_u32()?;
+                    self.stack.push(value as i32 as i64 as u64);
+                }
+                0xad => {
+                    let value = self.pop_u32()?;
+                    self.stack.push(u64::from(value));
+                }
+                0xb2..=0xba => {
+                    let value = self.pop()?;
+                    let converted = match opcode {
+                        0xb2 | 0xb7 => f64::from(value as u32 as i32),
+                        0xb3 | 0xb8 => f64::from(value as u32),
+                        0xb4 => (value as i64) as f32 as f64,
+                        0xb5 => value as f32 as f64,
+                        0xb6 => f64::from_bits(value) as f32 as f64,
+                        0xb9 => (value as i64) as f64,
+                        _ => value as f64,
+                    };
+                    if opcode <= 0xb6 {
+                        self.push_f32(converted as f32);
+                    } else {
+                        self.push_f64(converted);
+                    }
+                }
+                0xbb => {
+                    let value = self.pop_f32()?;
+                    self.push_f64(value.into());
+                }
+                // Reinterpretations keep the bits as they are
+                0xbc..=0xbf => {}
+                0xc0 => {
+                    let value = self.pop_u32()?;
+                    self.push_u32(value as i8 as i32 as u32);
+                }
+                0xc1 => {
+                    let value = self.pop_u32()?;
+                    self.push_u32(value as i16 as i32 as u32);
+                }
+                0xc2 => {
+                    let value = self.pop()?;
+                    self.stack.push(value as i8 as i64 as u64);
+                }
+                0xc3 => {
+                    let value = self.pop()?;
+                    self.stack.push(value as i16 as i64 as u64);
+                }
+                0xc4 => {
+                    let value = self.pop()?;
+                    self.stack.push(value as i32 as i64 as u64);
+                }
+                0xfc => match reader.u32()? {
+                    sub @ 0..=7 => {
+                        let value = if matches!(sub, 0 | 1 | 4 | 5) { f64::from(self.pop_f32()?) } else { self.pop_f64()? };
+                        let result = truncate(value, if sub < 4 { 32 } else { 64 }, sub % 2 == 0, true)?;
+                        self.stack.push(result);
+                    }
+                    10 => {
+                        reader.take(2)?;
+                        let len = self.pop_u32()? as usize;
+                        let source = self.pop_u32()? as usize;
+                        let destination = self.pop_u32()? as usize;
+                        if source + len > self.memory.len() || destination + len > self.memory.len() {
+                            return Err("out of bounds memory access".to_string());
+                        }
+                        self.memory.copy_within(source..source + len, destination);
+                    }
+                    11 => {
+                        reader.byte()?;
+                        let len = self.pop_u32()? as usize;
+                        let value = self.pop_u32()? as u8;
+                        let destination = self.pop_u32()? as usize;
+                        self.memory.get_mut(destination..destination + len).ok_or("out of bounds memory access")?.fill(value);
+                    }
+                    sub => return Err(format!("unsupported instruction 0xfc {}", sub)),
+                },
+                _ => return Err(format!("unsupported instruction 0x{:02x}", opcode)),
+            }
+
+            if let Some(depth) = branch {
+                if depth >= labels.len() {
+                    break;
+                }
+                let label = &labels[labels.len() - 1 - depth];
+                // A branch to a loop stays in it; any other leaves the block
+                let (height, arity, target, is_loop) = (label.height, label.arity, label.target, label.target != label.end);
+                let carried = self.below(arity, height)?;
+                self.stack.drain(height..carried);
+                reader.position = target;
+                labels.truncate(labels.len() - depth - usize::from(!is_loop));
+            }
+        }
+
+        let carried = self.below(result_count, base)?;
+        self.stack.drain(base..carried);
+        self.floor = floor;
+        Ok(())
+    }
+}
+
+/// Compares two integers, for the comparison after `eqz` numbered `index`.
+fn compare(index: u8, a: u64, b: u64, signed_a: i64, signed_b: i64) -> bool {
+    match index {
+        0 => a == b,
+        1 => a != b,
+        2 => signed_a < signed_b,
+        3 => a < b,
+        4 => signed_a > signed_b,
+        5 => a > b,
+        6 => signed_a <= signed_b,
+        7 => a <= b,
+        8 => signed_a >= signed_b,
+        _ => a >= b,
+    }
+}
+
+/// Compares two floats, for the comparison numbered `index`.
+fn compare_float(index: u8, a: f64, b: f64) -> bool {
+    match index {
+        0 => a == b,
+        1 => a != b,
+        2 => a < b,
+        3 => a > b,
+        4 => a <= b,
+        _ => a >= b,
+    }
+}
+
+/// Applies a 32-bit integer operator.
+fn i32_binary(opcode: u8, a: u32, b: u32) -> Result<u32, String> {
+    let (signed_a, signed_b) = (a as i32, b as i32);
+    Ok(match opcode {
+        0x6a => a.wrapping_add(b),
+        0x6b => a.wrapping_sub(b),
+        0x6c => a.wrapping_mul(b),
+        0x6d => signed_a.checked_div(signed_b).ok_or_else(|| division_trap(b == 0))? as u32,
+        0x6e => a.checked_div(b).ok_or_else(|| division_trap(true))?,
+        0x6f if b == 0 => return Err(division_trap(true)),
+        0x6f => signed_a.wrapping_rem(signed_b) as u32,
+        0x70 => a.checked_rem(b).ok_or_else(|| division_trap(true))?,
+        0x71 => a & b,
+        0x72 => a | b,
+        0x73 => a ^ b,
+        0x74 => a.wrapping_shl(b),
+        0x75 => signed_a.wrapping_shr(b) as u32,
+        0x76 => a.wrapping_shr(b),
+        0x77 => a.rotate_left(b % 32),
+        _ => a.rotate_right(b % 32),
+    })
+}
+
+/// Applies a 64-bit integer operator.
+fn i64_binary(opcode: u8, a: u64, b: u64) -> Result<u64, String> {
+    let (signed_a, signed_b) = (a as i64, b as i64);
+    Ok(match opcode {
+        0x7c => a.wrapping_add(b),
+        0x7d => a.wrapping_sub(b),
+        0x7e => a.wrapping_mul(b),
+        0x7f => signed_a.checked_div(signed_b).ok_or_else(|| division_trap(b == 0))? as u64,
+        0x80 => a.checked_div(b).ok_or_else(|| division_trap(true))?,
+        0x81 if b == 0 => return Err(division_trap(true)),
+        0x81 => signed_a.wrapping_rem(signed_b) as u64,
+        0x82 => a.checked_rem(b).ok_or_else(|| division_trap(true))?,
+        0x83 => a & b,
+        0x84 => a | b,
+        0x85 => a ^ b,
+        0x86 => a.wrapping_shl(b as u32),
+        0x87 => signed_a.wrapping_shr(b as u32) as u64,
+        0x88 => a.wrapping_shr(b as u32),
+        0x89 => a.rotate_left((b % 64) as u32),
+        _ => a.rotate_right((b % 64) as u32),
+    })
+}
+
+/// Describes a failed division.
+fn division_trap(by_zero: bool) -> String {
+    if by_zero { "integer divide by zero".to_string() } else { "integer overflow".to_string() }
+}
+
+/// The trap of a malformed module taking more values than the stack has.
+fn underflow() -> String {
+    "stack underflow".to_string()
+}
+
+/// Applies a float operator of one operand, numbered from `abs`.
+fn float_unary(index: u8, a: f64) -> f64 {
+    match index {
+        0 => a.abs(),
+        1 => -a,
+        2 => a.ceil(),
+        3 => a.floor(),
+        4 => a.trunc(),
+        5 => a.round_ties_even(),
+        _ => a.sqrt(),
+    }
+}
+
+/// Takes the minimum or maximum of two floats the way WebAssembly does, where
+/// NaN wins and -0 is less than 0.
+fn min_max(min: bool, a: f64, b: f64) -> f64 {
+    if a.is_nan() || b.is_nan() {
+        f64::NAN
+    } else if a == b {
+        if min == a.is_sign_negative() { a } else { b }
+    } else if (a < b) == min {
+        a
+    } else {
+        b
+    }
+}
+
+/// Truncates a float to an integer, trapping if it doesn't fit unless the
+/// conversion saturates.
+fn truncate(value: f64, bits: u32, signed: bool, saturating: bool) -> Result<u64, String> {
+    let (low, high) = match (bits, signed) {
+        (32, true) => (-2147483649.0, 2147483648.0),
+        (32, false) => (-1.0, 4294967296.0),
+        (_, true) => (-9223372036854777856.0, 9223372036854775808.0),
+        (_, false) => (-1.0, 18446744073709551616.0),
+    };
+    if !saturating && (value.is_nan() || value <= low || value >= high) {
+        return Err(if value.is_nan() { "invalid conversion to integer" } else { "integer overflow" }.to_string());
+    }
+    Ok(match (bits, signed) {
+        (32, true) => value as i32 as u32 as u64,
+        (32, false) => value as u32 as u64,
+        (_, true) => value as i64 as u64,
+        (_, false) => value as u64,
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Encodes a number as unsigned LEB128.
+    fn leb(mut value: usize, bytes: &mut Vec<u8>) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                bytes.push(byte);
+                return;
+            }
+            bytes.push(byte | 0x80);
+        }
+    }
+
+    fn section(id: u8, items: &[Vec<u8>], bytes: &mut Vec<u8>) {
+        let mut contents = Vec::new();
+        leb(items.len(), &mut contents);
+        for item in items {
+            contents.extend_from_slice(item);
+        }
+        bytes.push(id);
+        leb(contents.len(), bytes);
+        bytes.extend(contents);
+    }
+
+    /// A function to assemble: its export name, params, results, locals and code
+    pub(crate) type Export<'a> = (&'a str, &'a [u8], &'a [u8], &'a [u8], &'a [u8]);
+
+    /// Assembles a module from functions, each exported under its name, with
+    /// one page of memory and one mutable i32 global that starts at 1024.
+    pub(crate) fn module(functions: &[Export]) -> Vec<u8> {
+        assemble(functions, None)
+    }
+
+    /// Assembles a module as `module` does, starting with the function at
+    /// `start` when it is instantiated.
+    fn assemble(functions: &[Export], start: Option<usize>) -> Vec<u8> {
+        let mut bytes = b"\0asm\x01\0\0\0".to_vec();
+        let types: Vec<Vec<u8>> = functions.iter().map(|(_, params, results, _, _)| {
+            let mut func_type = vec![0x60, params.len() as u8];
+            func_type.extend_from_slice(params);
+            func_type.push(results.len() as u8);
+            func_type.extend_from_slice(results);
+            func_type
+        }).collect();
+        section(1, &types, &mut bytes);
+        section(3, &(0..functions.len()).map(|i| vec![i as u8]).collect::<Vec<_>>(), &mut bytes);
+        section(5, &[vec![0x00, 0x01]], &mut bytes);
+        section(6, &[vec![0x7f, 0x01, 0x41, 0x80, 0x08, 0x0b]], &mut bytes);
+        let exports: Vec<Vec<u8>> = functions.iter().enumerate().map(|(i, (name, ..))| {
+            let mut export = vec![name.len() as u8];
+            export.extend_from_slice(name.as_bytes());
+            export.extend([0x00, i as u8]);
+            export
+        }).collect();
+        section(7, &exports, &mut bytes);
+        if let Some(start) = start {
+            bytes.extend([8, 1, start as u8]);
+        }
+        let bodies: Vec<Vec<u8>> = functions.iter().map(|(_, _, _, locals, code)| {
+            let mut body = Vec::new();
+            body.push(locals.len() as u8);
+            for local in locals.iter() {
+                body.extend([0x01, *local]);
+            }
+            body.extend_from_slice(code);
+            let mut sized = Vec::new();
+            leb(body.len(), &mut sized);
+            sized.extend(body);
+            sized
+        }).collect();
+        section(10, &bodies, &mut bytes);
+        bytes
+    }
+
+    const I32: u8 = 0x7f;
+    const I64: u8 = 0x7e;
+
+    #[test]
+    fn test_call() {
+        let module = module(&[
+            // Recursive factorial
+            ("factorial", &[I64], &[I64], &[], &[
+                0x20, 0x00, 0x50, 0x04, 0x7e, 0x42, 0x01, 0x05,
+                0x20, 0x00, 0x20, 0x00, 0x42, 0x01, 0x7d, 0x10, 0x00, 0x7e, 0x0b, 0x0b,
+            ]),
+            // Sums 0..n with a loop
+            ("sum", &[I32], &[I32], &[I32, I32], &[
+                0x02, 0x40, 0x03, 0x40,
+                0x20, 0x01, 0x20, 0x00, 0x4f, 0x0d, 0x01,
+                0x20, 0x02, 0x20, 0x01, 0x6a, 0x21, 0x02,
+                0x20, 0x01, 0x41, 0x01, 0x6a, 0x21, 0x01,
+                0x0c, 0x00, 0x0b, 0x0b,
+                0x20, 0x02, 0x0b,
+            ]),
+            // Stores a value and loads it back sign-extended
+            ("memory", &[], &[I64], &[], &[
+                0x41, 0x10, 0x41, 0x7f, 0x3a, 0x00, 0x00,
+                0x41, 0x10, 0x30, 0x00, 0x00, 0x0b,
+            ]),
+            ("divide", &[I32, I32], &[I32], &[], &[0x20, 0x00, 0x20, 0x01, 0x6d, 0x0b]),
+            ("spin", &[], &[], &[], &[0x03, 0x40, 0x0c, 0x00, 0x0b, 0x0b]),
+        ]);
+        let mut instance = Instance::new(&module).unwrap();
+        assert_eq!(instance.call("factorial", &[20]).unwrap(), [2432902008176640000]);
+        assert_eq!(instance.call("sum", &[100]).unwrap(), [4950]);
+        assert_eq!(instance.call("memory", &[]).unwrap(), [u64::MAX]);
+        assert_eq!(instance.call("divide", &[(-7i32) as u32 as u64, 2]).unwrap(), [(-3i32) as u32 as u64]);
+        assert_eq!(instance.call("divide", &[1, 0]).unwrap_err(), "integer divide by zero");
+
+        instance.fuel_per_call = 10_000;
+        assert_eq!(instance.call("spin", &[]).unwrap_err(), "ran out of fuel");
+        assert!(instance.exports("sum") && !instance.exports("memory_size"));
+    }
+
+    #[test]
+    fn test_new_rejects_bad_modules() {
+        assert!(Instance::new(b"not wasm").is_err());
+        // An import section
+        assert!(Instance::new(b"\0asm\x01\0\0\0\x02\x01\x01").unwrap_err().contains("import"));
+        // A start function that takes a parameter nothing passes it
+        let start = assemble(&[("start", &[I32], &[], &[], &[0x0b])], Some(0));
+        assert_eq!(Instance::new(&start).unwrap_err(), "stack underflow");
+    }
+
+    #[test]
+    fn test_call_rejects_stack_underflow() {
+        let mut instance = Instance::new(&module(&[
+            ("add", &[], &[I32], &[], &[0x6a, 0x0b]),
+            ("result", &[], &[I32], &[], &[0x0b]),
+            ("branch", &[], &[I32], &[], &[0x02, 0x7f, 0x0c, 0x00, 0x0b, 0x0b]),
+            // Calls add, which mustn't take its caller's values
+            ("inner", &[I32], &[I32], &[], &[0x20, 0x00, 0x20, 0x00, 0x10, 0x00, 0x0b]),
+        ])).unwrap();
+        for name in ["add", "result", "branch"] {
+            assert_eq!(instance.call(name, &[]).unwrap_err(), "stack underflow", "{}", name);
+        }
+        assert_eq!(instance.call("inner", &[1]).unwrap_err(), "stack underflow");
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate(-1.9, 32, true, false), Ok(u64::from((-1i32) as u32)));
+        assert!(truncate(f64::NAN, 32, true, false).is_err());
+        assert!(truncate(4294967296.0, 32, false, false).is_err());
+        assert_eq!(truncate(1e20, 32, false, true), Ok(u64::from(u32::MAX)));
+        assert_eq!(min_max(true, 0.0, -0.0).to_bits(), (-0.0f64).to_bits());
+    }
+}
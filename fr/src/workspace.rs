@@ -0,0 +1,200 @@
+//! Limiting a run to the members of a Cargo workspace, for `--workspace` and
+//! `--package`.
+//!
+//! The members are read from the `Cargo.toml` in the starting directory: the
+//! directories `workspace.members` lists, where `*` and the other glob
+//! characters match directory names, less those under `workspace.exclude`,
+//! and the directory itself if it has a `[package]` too. Each member takes in
+//! its directory but not the directories of the members inside it, so
+//! choosing the root package leaves the crates next to it alone.
+
+use crate::hooks::Filter;
+use crate::toml::{self, TomlKey};
+use globset::Glob;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+/// Which members of the workspace to walk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Members {
+    /// Every member, for `--workspace`
+    All,
+    /// The members with these package names, for `--package`
+    Named(Vec<String>),
+}
+
+/// A member of the workspace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Member {
+    /// The name of its package
+    pub name: String,
+    /// Its directory relative to the root of the workspace, empty for the
+    /// root package
+    pub directory: PathBuf,
+}
+
+/// Reads the strings a key of a manifest has, or None if the manifest isn't
+/// valid TOML.
+fn manifest_strings(manifest: &str, key: &str) -> Option<Vec<String>> {
+    toml::strings(manifest, &TomlKey::parse(key).expect("a valid key"))
+}
+
+/// Reads the members of the workspace whose manifest is in a directory.
+///
+/// # Arguments
+///
+/// * `root` - The directory with the workspace's `Cargo.toml`
+///
+/// # Returns
+///
+/// * `io::Result<Vec<Member>>` - The members, sorted by directory, or an
+///   error if the directory has no manifest or the manifest no members
+pub fn members(root: &Path) -> io::Result<Vec<Member>> {
+    let manifest = fs::read_to_string(root.join("Cargo.toml")).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => io::Error::new(e.kind(), "--workspace and --package need a Cargo.toml in the directory fr is run in"),
+        _ => io::Error::new(e.kind(), format!("Failed to read Cargo.toml: {}", e)),
+    })?;
+    let patterns = manifest_strings(&manifest, "workspace.members")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Cargo.toml isn't valid TOML"))?;
+
+    let mut directories = Vec::new();
+    for pattern in patterns {
+        directories.extend(expand(root, &pattern)?);
+    }
+    let excluded: Vec<PathBuf> = manifest_strings(&manifest, "workspace.exclude").unwrap_or_default().iter()
+        .map(|path| normalize(Path::new(path)))
+        .collect();
+    directories.retain(|directory| !excluded.iter().any(|excluded| directory.starts_with(excluded)));
+    directories.push(PathBuf::new());
+    directories.sort();
+    directories.dedup();
+
+    let mut members = Vec::new();
+    for directory in directories {
+        // Globs may match directories that aren't crates, and a virtual
+        // manifest has no package of its own
+        let Ok(manifest) = fs::read_to_string(root.join(&directory).join("Cargo.toml")) else {
+            continue;
+        };
+        if let Some(name) = manifest_strings(&manifest, "package.name").and_then(|names| names.into_iter().next()) {
+            members.push(Member { name, directory });
+        }
+    }
+    match members.is_empty() {
+        true => Err(io::Error::new(io::ErrorKind::InvalidData, "Cargo.toml has no workspace members or package")),
+        false => Ok(members),
+    }
+}
+
+/// Drops the `.` parts of a relative path, so paths can be compared.
+fn normalize(path: &Path) -> PathBuf {
+    path.components().filter(|component| *component != Component::CurDir).collect()
+}
+
+/// Finds the directories a path in `workspace.members` names.
+fn expand(root: &Path, pattern: &str) -> io::Result<Vec<PathBuf>> {
+    let mut directories = vec![PathBuf::new()];
+    for part in normalize(Path::new(pattern)).iter() {
+        let text = part.to_string_lossy();
+        if !text.contains(['*', '?', '[', '{']) {
+            directories.iter_mut().for_each(|directory| directory.push(part));
+            continue;
+        }
+        let matcher = Glob::new(&text)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid workspace member '{}' in Cargo.toml: {}", pattern, e)))?
+            .compile_matcher();
+        let mut matched = Vec::new();
+        for directory in &directories {
+            let Ok(entries) = fs::read_dir(root.join(directory)) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                if entry.path().is_dir() && matcher.is_match(entry.file_name()) {
+                    matched.push(directory.join(entry.file_name()));
+                }
+            }
+        }
+        directories = matched;
+    }
+    Ok(directories)
+}
+
+/// Builds the filter that passes over everything outside the chosen members.
+///
+/// # Arguments
+///
+/// * `starting_directory` - The root of the workspace, which the walked paths
+///   start with
+/// * `chosen` - Which members to walk
+///
+/// # Returns
+///
+/// * `io::Result<Filter>` - A filter passing the files of the chosen members
+///   and the directories leading to them, or an error if the workspace can't
+///   be read or has no member by one of the names
+pub fn filter(starting_directory: &Path, chosen: &Members) -> io::Result<Filter> {
+    let members = members(starting_directory)?;
+    let selected: Vec<bool> = match chosen {
+        Members::All => vec![true; members.len()],
+        Members::Named(names) => {
+            if let Some(unknown) = names.iter().find(|name| !members.iter().any(|member| member.name == **name)) {
+                let known: Vec<&str> = members.iter().map(|member| member.name.as_str()).collect();
+                return Err(io::Error::new(io::ErrorKind::NotFound,
+                    format!("No member of the workspace is named '{}'; its members are: {}", unknown, known.join(", "))));
+            }
+            members.iter().map(|member| names.contains(&member.name)).collect()
+        }
+    };
+    let starting_directory = starting_directory.to_path_buf();
+    Ok(Arc::new(move |path: &Path| {
+        let relative = path.strip_prefix(&starting_directory).unwrap_or(path);
+        // A path belongs to the innermost member it is in
+        let owner = members.iter().zip(&selected)
+            .filter(|(member, _)| relative.starts_with(&member.directory))
+            .max_by_key(|(member, _)| member.directory.components().count());
+        // Directories on the way to a chosen member are walked too
+        owner.is_some_and(|(_, selected)| *selected)
+            || members.iter().zip(&selected).any(|(member, selected)| *selected && member.directory.starts_with(relative))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write(root: &Path, path: &str, text: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, text).unwrap();
+    }
+
+    #[test]
+    fn test_members_and_filter() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        write(root, "Cargo.toml", "[workspace]\nmembers = [\"crates/*\", \"./tools/gen\"]\nexclude = [\"crates/old\"]\n\n[package]\nname = \"app\"\n");
+        write(root, "crates/core/Cargo.toml", "[package]\nname = \"core\"\n");
+        write(root, "crates/old/Cargo.toml", "[package]\nname = \"old\"\n");
+        write(root, "crates/docs/README.md", "not a crate");
+        write(root, "tools/gen/Cargo.toml", "[package]\nname = 'gen'\n");
+
+        let found = members(root).unwrap();
+        let names: Vec<(&str, &Path)> = found.iter().map(|member| (member.name.as_str(), member.directory.as_path())).collect();
+        assert_eq!(names, [("app", Path::new("")), ("core", Path::new("crates/core")), ("gen", Path::new("tools/gen"))]);
+
+        let core = filter(root, &Members::Named(vec!["core".to_string()])).unwrap();
+        assert!(core(&root.join("crates")) && core(&root.join("crates/core/src/lib.rs")));
+        assert!(!core(&root.join("src/main.rs")) && !core(&root.join("tools")) && !core(&root.join("crates/docs")));
+        // The root package takes in what no other member does
+        let app = filter(root, &Members::Named(vec!["app".to_string()])).unwrap();
+        assert!(app(&root.join("src/main.rs")) && app(&root.join("crates/old/lib.rs")) && !app(&root.join("crates/core/src/lib.rs")));
+        let all = filter(root, &Members::All).unwrap();
+        assert!(all(&root.join("tools/gen/src/main.rs")) && all(&root.join("README.md")));
+
+        assert!(filter(root, &Members::Named(vec!["cli".to_string()])).err().unwrap().to_string().ends_with("its members are: app, core, gen"));
+        assert!(members(&root.join("crates/docs")).is_err());
+    }
+}
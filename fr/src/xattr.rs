@@ -0,0 +1,108 @@
+//! Keeping a file's access control lists and security labels when it is
+//! rewritten through a temporary file.
+//!
+//! A file renamed into place has the temporary file's extended attributes,
+//! not the original's, so a config file under `/etc` would lose its POSIX
+//! ACL, SELinux context or file capabilities and stop being readable by, or
+//! stop working for, the service that uses it. Those attributes are copied
+//! across before the rename. Other platforms have none of them to keep.
+
+use std::fs::File;
+use std::io;
+
+/// The extended attributes a rewritten file keeps: its POSIX ACL, SELinux
+/// context and file capabilities
+#[cfg(target_os = "linux")]
+const SECURITY_ATTRIBUTES: [&std::ffi::CStr; 3] = [c"system.posix_acl_access", c"security.selinux", c"security.capability"];
+
+/// Copies a file's ACL and security labels to the file that will replace it.
+///
+/// # Arguments
+///
+/// * `from` - The original file
+/// * `to` - The file that will be renamed over it
+///
+/// # Returns
+///
+/// * `io::Result<()>` - An error if an attribute the original has couldn't be
+///   set on the new file, which should then not replace it
+#[cfg(target_os = "linux")]
+pub fn copy_security(from: &File, to: &File) -> io::Result<()> {
+    copy(from, to, &SECURITY_ATTRIBUTES)
+}
+
+/// Copies a file's ACL and security labels, which this platform doesn't have.
+#[cfg(not(target_os = "linux"))]
+pub fn copy_security(_from: &File, _to: &File) -> io::Result<()> {
+    Ok(())
+}
+
+/// Copies the named extended attributes that one file has to another.
+#[cfg(target_os = "linux")]
+fn copy(from: &File, to: &File, names: &[&std::ffi::CStr]) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+    for name in names {
+        let Some(value) = get(from, name)? else {
+            continue;
+        };
+        // SAFETY: the value is a live buffer of the length passed
+        let result = unsafe { libc::fsetxattr(to.as_raw_fd(), name.as_ptr(), value.as_ptr().cast(), value.len(), 0) };
+        if result != 0 {
+            let e = io::Error::last_os_error();
+            return Err(io::Error::new(e.kind(), format!("Couldn't keep {}: {}", name.to_string_lossy(), e)));
+        }
+    }
+    Ok(())
+}
+
+/// Reads an extended attribute of a file, or None if it doesn't have it.
+#[cfg(target_os = "linux")]
+fn get(file: &File, name: &std::ffi::CStr) -> io::Result<Option<Vec<u8>>> {
+    use std::os::fd::AsRawFd;
+    loop {
+        // SAFETY: a null buffer of length 0 only asks for the value's size
+        let size = unsafe { libc::fgetxattr(file.as_raw_fd(), name.as_ptr(), std::ptr::null_mut(), 0) };
+        let Ok(size) = usize::try_from(size) else {
+            let e = io::Error::last_os_error();
+            return match e.raw_os_error() {
+                Some(libc::ENODATA | libc::ENOTSUP) => Ok(None),
+                _ => Err(e),
+            };
+        };
+        let mut value = vec![0u8; size];
+        // SAFETY: the buffer is live and as long as the length passed
+        let read = unsafe { libc::fgetxattr(file.as_raw_fd(), name.as_ptr(), value.as_mut_ptr().cast(), value.len()) };
+        match usize::try_from(read) {
+            Ok(read) => {
+                value.truncate(read);
+                return Ok(Some(value));
+            }
+            // The value grew since its size was asked for
+            Err(_) if io::Error::last_os_error().raw_os_error() == Some(libc::ERANGE) => continue,
+            Err(_) => return Err(io::Error::last_os_error()),
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::os::fd::AsRawFd;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_copy() {
+        let temp_dir = TempDir::new().unwrap();
+        let from = File::create(temp_dir.path().join("from")).unwrap();
+        let to = File::create(temp_dir.path().join("to")).unwrap();
+        // SAFETY: the value is a live buffer of the length passed
+        let set = unsafe { libc::fsetxattr(from.as_raw_fd(), c"user.fr".as_ptr(), b"label".as_ptr().cast(), 5, 0) };
+        // Not every filesystem the tests run on has extended attributes
+        if set != 0 {
+            return;
+        }
+        copy(&from, &to, &[c"user.fr", c"user.missing"]).unwrap();
+        assert_eq!(get(&to, c"user.fr").unwrap(), Some(b"label".to_vec()));
+        assert_eq!(get(&to, c"user.missing").unwrap(), None);
+    }
+}
@@ -0,0 +1,311 @@
+//! Replacing only inside the scalar values of YAML files, for `--yaml-values`
+//! and `--yaml-path`.
+//!
+//! A match is kept if it is inside a scalar value, or one at `--yaml-path`, and
+//! never in a key, comment, anchor, tag or quote. Documents are read a line at a time
+//! from their indentation, which covers the block style of Kubernetes
+//! manifests, CI pipelines and Ansible playbooks. Flow collections such as
+//! `[a, b]` and scalars quoted across several lines are left alone.
+
+use crate::jsonpath::{JsonPath, Segment};
+use std::path::Path;
+
+/// Returns whether a file is YAML, going by its extension, or is stdin.
+pub fn is_yaml(file_path: &Path) -> bool {
+    file_path == Path::new("-")
+        || file_path.extension().and_then(|extension| extension.to_str())
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("yaml") || extension.eq_ignore_ascii_case("yml"))
+}
+
+/// How a scalar is written, which decides what a replacement may contain
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Style {
+    Plain,
+    SingleQuoted,
+    DoubleQuoted,
+    /// A line of a `|` or `>` block scalar
+    Block,
+}
+
+impl Style {
+    /// Whether text can go into a scalar of this style as it is, without
+    /// changing how the rest of the line is read.
+    fn allows(self, text: &str) -> bool {
+        !text.contains(['\n', '\r']) && match self {
+            Style::Plain => !text.contains(": ") && !text.contains(" #") && !text.ends_with(':'),
+            Style::SingleQuoted => !text.contains('\''),
+            Style::DoubleQuoted => !text.contains(['"', '\\']),
+            Style::Block => true,
+        }
+    }
+}
+
+/// Where a scalar's text starts and ends, and how it is written
+type Scalar = (usize, usize, Style);
+
+/// Keeps the matches that lie wholly inside a scalar value, and at one of the
+/// paths if any are given, where the replacement can go as it is.
+///
+/// # Arguments
+///
+/// * `content` - The YAML file, of one or more documents
+/// * `offsets` - Where each match of the find text starts
+/// * `find_len` - Length of the find text in bytes
+/// * `replace_text` - Text each match would be replaced with
+/// * `paths` - Paths the values must be at, or empty for every scalar value
+///
+/// # Returns
+///
+/// * `Vec<usize>` - The offsets of the matches to replace
+pub fn value_offsets(content: &str, offsets: &[usize], find_len: usize, replace_text: &str, paths: &[JsonPath]) -> Vec<usize> {
+    let scalars = scalars(content, paths);
+    let mut scalars = scalars.iter().peekable();
+    offsets.iter().copied().filter(|&offset| {
+        while scalars.next_if(|&&(_, end, _)| end < offset + find_len).is_some() {}
+        scalars.peek().is_some_and(|&&(start, end, style)| {
+            offset >= start && offset + find_len <= end && style.allows(replace_text)
+                && (style != Style::DoubleQuoted || outside_escapes(content, start, offset, offset + find_len))
+        })
+    }).collect()
+}
+
+/// Whether a match in a double-quoted scalar starts and ends between
+/// characters rather than within an escape sequence.
+fn outside_escapes(content: &str, start: usize, offset: usize, end: usize) -> bool {
+    let bytes = content.as_bytes();
+    let mut position = start;
+    let mut on_offset = false;
+    while position < end {
+        on_offset |= position == offset;
+        position += match (bytes[position], bytes.get(position + 1)) {
+            (b'\\', Some(b'x')) => 4,
+            (b'\\', Some(b'u')) => 6,
+            (b'\\', Some(b'U')) => 10,
+            (b'\\', _) => 2,
+            _ => 1,
+        };
+    }
+    on_offset && position == end
+}
+
+/// Finds the scalar values at the paths, or every one if there are none.
+fn scalars(content: &str, paths: &[JsonPath]) -> Vec<Scalar> {
+    let mut found = Vec::new();
+    // The keys and sequence items the current line is inside, with the
+    // column each starts at
+    let mut stack: Vec<(usize, Segment)> = Vec::new();
+    // The column a block scalar's lines must be indented past, while in one
+    let mut block: Option<(usize, bool)> = None;
+    let mut line_start = 0;
+    for line in content.split_inclusive('\n') {
+        let start = line_start;
+        line_start += line.len();
+        let line = line.trim_end_matches(['\n', '\r']);
+        let indent = line.len() - line.trim_start_matches(' ').len();
+        let text = &line[indent..];
+
+        if let Some((parent, wanted)) = block {
+            if text.is_empty() {
+                continue;
+            }
+            if indent > parent {
+                if wanted {
+                    found.push((start + indent, start + line.len(), Style::Block));
+                }
+                continue;
+            }
+            block = None;
+        }
+        if text.is_empty() || text.starts_with('#') || text.starts_with('%') {
+            continue;
+        }
+        if indent == 0 && (text.starts_with("---") || text.starts_with("...")) {
+            stack.clear();
+            continue;
+        }
+
+        let mut column = indent;
+        let mut rest = text;
+        // Sequence items, several to a line in a nested sequence
+        while rest == "-" || rest.starts_with("- ") {
+            // A key at the same column stays, for a sequence under it that
+            // isn't indented, and the item before this one is replaced
+            while stack.last().is_some_and(|(at, _)| *at > column) {
+                stack.pop();
+            }
+            let index = match stack.last() {
+                Some(&(at, Segment::Index(index))) if at == column => {
+                    stack.pop();
+                    index + 1
+                }
+                _ => 0,
+            };
+            stack.push((column, Segment::Index(index)));
+            let after = rest[1..].trim_start_matches(' ');
+            column += rest.len() - after.len();
+            rest = after;
+        }
+        if rest.is_empty() || rest.starts_with('#') {
+            continue;
+        }
+
+        let value_start = match key(rest) {
+            Some((key, len)) => {
+                while stack.last().is_some_and(|(at, _)| *at >= column) {
+                    stack.pop();
+                }
+                stack.push((column, Segment::Key(key)));
+                let after = &rest[len..];
+                column + len + (after.len() - after.trim_start_matches(' ').len())
+            }
+            None => column,
+        };
+        let position = start + value_start;
+        let wanted = paths.is_empty() || {
+            let segments: Vec<Segment> = stack.iter().map(|(_, segment)| segment.clone()).collect();
+            paths.iter().any(|path| path.matches(&segments))
+        };
+        let parent = stack.last().map_or(0, |(at, _)| *at);
+        match scalar(&line[value_start..]) {
+            Some(Value::Scalar(from, to, style)) if wanted => found.push((position + from, position + to, style)),
+            Some(Value::Block) => block = Some((if value_start == column { indent } else { parent }, wanted)),
+            _ => {}
+        }
+    }
+    found
+}
+
+/// What follows a key or a sequence item's dash
+enum Value {
+    /// A scalar on the line, from where to where, and how it is written
+    Scalar(usize, usize, Style),
+    /// A block scalar whose lines follow
+    Block,
+}
+
+/// Reads a `key:` at the start of the text, returning the key and the length
+/// up to and including the colon.
+fn key(text: &str) -> Option<(String, usize)> {
+    if let Some(quote) = text.chars().next().filter(|c| matches!(c, '"' | '\'')) {
+        let end = text[1..].find(quote)? + 1;
+        let after = &text[end + 1..];
+        let colon = after.len() - after.trim_start_matches(' ').len();
+        let after = &after[colon..];
+        return (after.starts_with(':') && (after.len() == 1 || after[1..].starts_with([' ', '\t'])))
+            .then(|| (text[1..end].to_string(), end + 1 + colon + 1));
+    }
+    if text.starts_with(['[', '{', '&', '*', '!', '|', '>', '@', '`', '?']) {
+        return None;
+    }
+    let end = text.find(": ").or_else(|| text.strip_suffix(':').map(str::len))?;
+    let key = text[..end].trim_end();
+    (!key.is_empty() && !key.contains(" #")).then(|| (key.to_string(), end + 1))
+}
+
+/// Reads the value after a key or dash, skipping any anchor and tag.
+fn scalar(text: &str) -> Option<Value> {
+    let mut rest = text;
+    while rest.starts_with(['&', '!']) {
+        let len = rest.find(' ')?;
+        rest = rest[len..].trim_start_matches(' ');
+    }
+    let skipped = text.len() - rest.len();
+    let first = rest.chars().next()?;
+    let (from, to, style) = match first {
+        '#' | '*' | '[' | '{' => return None,
+        '|' | '>' => {
+            let header = rest.split(" #").next().unwrap_or(rest).trim_end();
+            return header[1..].chars().all(|c| c.is_ascii_digit() || c == '-' || c == '+').then_some(Value::Block);
+        }
+        '\'' => {
+            let bytes = rest.as_bytes();
+            let mut end = 1;
+            loop {
+                match bytes.get(end)? {
+                    b'\'' if bytes.get(end + 1) == Some(&b'\'') => end += 2,
+                    b'\'' => break,
+                    _ => end += 1,
+                }
+            }
+            (1, end, Style::SingleQuoted)
+        }
+        '"' => {
+            let bytes = rest.as_bytes();
+            let mut end = 1;
+            loop {
+                match bytes.get(end)? {
+                    b'\\' => end += 2,
+                    b'"' => break,
+                    _ => end += 1,
+                }
+            }
+            (1, end, Style::DoubleQuoted)
+        }
+        _ => {
+            let end = rest.find(" #").unwrap_or(rest.len());
+            (0, rest[..end].trim_end().len(), Style::Plain)
+        }
+    };
+    Some(Value::Scalar(skipped + from, skipped + to, style))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{match_offsets, splice};
+
+    fn replace(content: &str, find_text: &str, replace_text: &str, paths: &[&str]) -> String {
+        let paths: Vec<JsonPath> = paths.iter().map(|path| JsonPath::parse(path).unwrap()).collect();
+        let offsets = value_offsets(content, &match_offsets(content, find_text), find_text.len(), replace_text, &paths);
+        splice(content, &offsets, find_text.len(), replace_text)
+    }
+
+    const MANIFEST: &str = "\
+# image: registry.old/comment
+apiVersion: apps/v1
+kind: Deployment
+metadata:
+  name: registry.old
+spec:
+  template:
+    spec:
+      containers:
+      - name: web
+        image: &web registry.old/web:1.0  # pinned
+      - name: sidecar
+        image: \"registry.old/proxy\"
+        args:
+          - --upstream='registry.old'
+        command: |
+          echo registry.old
+---
+registry.old: 'registry.old'
+image: *web
+";
+
+    #[test]
+    fn test_value_offsets() {
+        assert_eq!(replace(MANIFEST, "registry.old", "registry.new", &[]), MANIFEST.replace("registry.old", "registry.new")
+            .replace("# image: registry.new", "# image: registry.old")
+            .replace("registry.new: 'registry.new'", "registry.old: 'registry.new'"));
+
+        assert_eq!(replace(MANIFEST, "registry.old", "registry.new", &["$..image"]), MANIFEST
+            .replace("&web registry.old", "&web registry.new")
+            .replace("\"registry.old/proxy\"", "\"registry.new/proxy\""));
+
+        assert_eq!(replace(MANIFEST, "registry.old", "r", &["$.spec..containers[1].args[0]", "$.spec..command"]), MANIFEST
+            .replace("='registry.old'", "='r'")
+            .replace("echo registry.old", "echo r"));
+
+        // Replacements that would need quoting in a scalar's style are left out
+        assert_eq!(replace("a: \"x\"\nb: x\n", "x", "say \"hi\"", &[]), "a: \"x\"\nb: say \"hi\"\n");
+        assert_eq!(replace("a: x\n", "x", "y # z", &[]), "a: x\n");
+    }
+
+    #[test]
+    fn test_is_yaml() {
+        assert!(is_yaml(Path::new("k8s/deploy.YAML")));
+        assert!(is_yaml(Path::new(".github/workflows/ci.yml")));
+        assert!(!is_yaml(Path::new("Cargo.toml")));
+    }
+}